@@ -19,6 +19,7 @@ mod cuda {
 
         println!("cargo:rustc-link-lib=dylib=cuda");
         println!("cargo:rustc-link-lib=dylib=cublas");
+        println!("cargo:rustc-link-lib=dylib=nvidia-ml");
 
         let include_paths = link_cuda();
         let builder = include_paths.iter().fold(builder, |builder, path| {
@@ -46,10 +47,24 @@ mod cuda {
 
         println!("cargo:rerun-if-changed=./src/backend/kernels");
 
-        let files: Vec<String> = ["backprops", "bufops", "mpe", "select", "sparse_affine", "splat_add", "update"]
-            .iter()
-            .map(|s| format!("./src/backend/kernels/{s}.cu"))
-            .collect();
+        let files: Vec<String> = [
+            "attention",
+            "backprops",
+            "bufops",
+            "conv",
+            "mpe",
+            "norm",
+            "reduce",
+            "select",
+            "softmax",
+            "sparse_affine",
+            "sparse_format",
+            "splat_add",
+            "update",
+        ]
+        .iter()
+        .map(|s| format!("./src/backend/kernels/{s}.cu"))
+        .collect();
 
         cc::Build::new()
             .cuda(true)
@@ -93,6 +108,8 @@ mod cuda {
     fn link_cuda() -> Vec<PathBuf> {
         let path = get_var_path("CUDA_PATH");
         println!("cargo:rustc-link-search=native={}", path.join("lib64").to_str().unwrap());
+        // NVML ships with the driver, but the toolkit has a stub to link against
+        println!("cargo:rustc-link-search=native={}", path.join("lib64/stubs").to_str().unwrap());
         vec![path.join("include")]
     }
 
@@ -0,0 +1,179 @@
+//! End-to-end time-to-accuracy check, training a small reference net on a
+//! mini-dataset generated from a fixed seed, so that refactors of the backend
+//! or trainer can be checked for both speed and convergence.
+
+use std::time::Instant;
+
+use bullet::{
+    format::{BulletFormat, ChessBoard},
+    inputs, outputs, Activation, LocalSettings, Loss, LrScheduler, Trainer, TrainerBuilder, TrainingSchedule,
+    WdScheduler, WdlScheduler,
+};
+
+const POSITIONS: usize = 131_072;
+const BATCH_SIZE: usize = 4096;
+const BATCHES_PER_SUPERBATCH: usize = 32;
+const SEED: u64 = 0x5EED;
+
+/// Piece values used to score the generated positions, from pawn to queen.
+const VALUES: [i32; 5] = [100, 300, 320, 500, 900];
+
+/// Tolerances that a run must be within to pass.
+pub struct Tolerances {
+    pub max_loss: f32,
+    /// Positions per second, from the end of the first superbatch, which
+    /// is not checked if zero as it depends heavily on the device.
+    pub min_throughput: f32,
+}
+
+/// Loss and throughput at the end of a run.
+struct Outcome {
+    loss: f32,
+    throughput: f32,
+}
+
+/// Trains the reference net for `superbatches`, returning whether
+/// the final loss and throughput are within `tolerances`.
+pub fn run(superbatches: usize, threads: usize, out_dir: &str, tolerances: &Tolerances) -> bool {
+    assert!(superbatches > 1, "Need at least two superbatches to measure throughput!");
+
+    std::fs::create_dir_all(out_dir).unwrap_or_else(|_| panic!("Creating [{out_dir}] failed!"));
+    let data_path = format!("{out_dir}/convergence.data");
+    write_dataset(&data_path).unwrap_or_else(|_| panic!("Writing to [{data_path}] failed!"));
+
+    let mut trainer = TrainerBuilder::default()
+        .input(inputs::Chess768)
+        .output_buckets(outputs::Single)
+        .feature_transformer(64)
+        .activate(Activation::SCReLU)
+        .add_layer(1)
+        .build();
+
+    trainer.randomise_weights_seeded(SEED);
+
+    let schedule = TrainingSchedule {
+        net_id: "convergence".to_string(),
+        eval_scale: 400.0,
+        ft_regularisation: 0.0,
+        batch_size: BATCH_SIZE,
+        batches_per_superbatch: BATCHES_PER_SUPERBATCH,
+        start_superbatch: 1,
+        end_superbatch: superbatches,
+        wdl_scheduler: WdlScheduler::Constant { value: 0.0 },
+        lr_scheduler: LrScheduler::Step { start: 0.001, gamma: 0.3, step: superbatches / 2 + 1 },
+        wd_scheduler: WdScheduler::Constant { value: 0.01 },
+        loss_function: Loss::SigmoidMSE,
+        save_rate: superbatches,
+    };
+
+    let settings = LocalSettings {
+        threads,
+        data_file_paths: vec![data_path.as_str()],
+        output_directory: out_dir,
+        resume_from: None,
+    };
+
+    let mut losses = Vec::new();
+    let mut first_finished = None;
+    let timer = Instant::now();
+
+    let callback = |superbatch: usize, trainer: &Trainer<_, _>, schedule: &TrainingSchedule, _: &LocalSettings| {
+        losses.push(trainer.mean_error());
+
+        if superbatch == schedule.start_superbatch {
+            first_finished = Some(timer.elapsed().as_secs_f32());
+        }
+    };
+
+    trainer.run_custom(&schedule, &settings, callback);
+
+    let elapsed = timer.elapsed().as_secs_f32() - first_finished.expect("No superbatches were trained!");
+    let positions = (superbatches - 1) * schedule.positions_per_superbatch();
+    let outcome = Outcome { loss: *losses.last().unwrap(), throughput: positions as f32 / elapsed };
+
+    report(&outcome, tolerances)
+}
+
+fn report(outcome: &Outcome, tolerances: &Tolerances) -> bool {
+    let loss_ok = outcome.loss <= tolerances.max_loss;
+    let throughput_ok = outcome.throughput >= tolerances.min_throughput;
+    let status = |ok: bool| if ok { "ok" } else { "FAILED" };
+
+    println!("Final Loss             : {:.6} (max {:.6}) {}", outcome.loss, tolerances.max_loss, status(loss_ok));
+
+    if tolerances.min_throughput > 0.0 {
+        let (throughput, min) = (outcome.throughput, tolerances.min_throughput);
+        println!("Throughput             : {throughput:.0} pos/sec (min {min:.0}) {}", status(throughput_ok));
+    } else {
+        println!("Throughput             : {:.0} pos/sec", outcome.throughput);
+    }
+
+    loss_ok && throughput_ok
+}
+
+/// Writes the mini-dataset, which is the same on every run: random positions
+/// with both kings and up to twelve other pieces, scored by material with a
+/// small bonus for centralisation, so that the reference net can learn it.
+fn write_dataset(path: &str) -> std::io::Result<()> {
+    let mut rng = SplitMix(SEED);
+    let mut data = Vec::with_capacity(POSITIONS);
+
+    while data.len() < POSITIONS {
+        let mut bbs = [0u64; 8];
+        let mut score = 0;
+
+        let mut place = |rng: &mut SplitMix, colour: usize, piece: usize| loop {
+            let sq = rng.below(64) as usize;
+            let rank = sq / 8;
+
+            if (bbs[0] | bbs[1]) & (1 << sq) > 0 || (piece == 0 && (rank == 0 || rank == 7)) {
+                continue;
+            }
+
+            bbs[colour] |= 1 << sq;
+            bbs[2 + piece] |= 1 << sq;
+
+            if piece < 5 {
+                let (file, rank) = ((sq % 8) as i32, rank as i32);
+                let centre = 6 - (2 * file - 7).abs() / 2 - (2 * rank - 7).abs() / 2;
+                let value = VALUES[piece] + 4 * centre;
+                score += if colour == 0 { value } else { -value };
+            }
+
+            break;
+        };
+
+        place(&mut rng, 0, 5);
+        place(&mut rng, 1, 5);
+
+        for _ in 0..rng.below(13) {
+            let colour = rng.below(2) as usize;
+            let piece = rng.below(5) as usize;
+            place(&mut rng, colour, piece);
+        }
+
+        let score = score.clamp(-3000, 3000);
+        let result = match score {
+            ..=-100 => 0.0,
+            100.. => 1.0,
+            _ => 0.5,
+        };
+
+        let stm = rng.below(2) as usize;
+        data.push(ChessBoard::from_raw(bbs, stm, score as i16, result).expect("Invalid generated position!"));
+    }
+
+    std::fs::write(path, ChessBoard::as_bytes_slice(&data))
+}
+
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut x = self.0;
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (x ^ (x >> 31)) % n
+    }
+}
@@ -0,0 +1,186 @@
+mod convergence;
+
+use std::time::Instant;
+
+use bullet::{
+    tensor::{
+        device_name, device_synchronise, DeviceBuffer, DeviceHandles, Feat, Optimiser, OptimiserType, Shape,
+        SparseTensor, Tensor, TensorBatch,
+    },
+    Activation,
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "bullet-bench", about = "Times the core kernels on the current device.")]
+pub struct Options {
+    #[structopt(long, default_value = "4096,16384")]
+    batch_sizes: String,
+    #[structopt(long, default_value = "50")]
+    iters: usize,
+    #[structopt(long, default_value = "1")]
+    threads: usize,
+    /// Instead of timing kernels, trains a small reference net on a generated
+    /// mini-dataset and checks its final loss and throughput.
+    #[structopt(long)]
+    convergence: bool,
+    #[structopt(long, default_value = "10")]
+    superbatches: usize,
+    /// Maximum final loss, which is about 25% above that of the default 10 superbatches.
+    #[structopt(long, default_value = "0.0005")]
+    max_loss: f32,
+    /// Positions per second, not checked if zero.
+    #[structopt(long, default_value = "0")]
+    min_throughput: f32,
+    #[structopt(long, default_value = "checkpoints/convergence")]
+    out_dir: String,
+}
+
+/// Input size, max active inputs and hidden size of the feature transformer.
+const FT_INPUTS: usize = 768;
+const FT_ACTIVE: usize = 32;
+const FT_HIDDEN: usize = 768;
+
+/// Dense layer sizes from common architectures, as (inputs, outputs).
+const GEMMS: [(usize, usize); 3] = [(2 * FT_HIDDEN, 1), (2 * FT_HIDDEN, 16), (16, 32)];
+
+struct Bench {
+    handle: DeviceHandles,
+    iters: usize,
+}
+
+impl Bench {
+    /// Prints the mean time taken by `f`, after a single warmup call.
+    fn time<F: FnMut()>(&self, name: &str, batch_size: usize, mut f: F) {
+        f();
+        device_synchronise();
+
+        let timer = Instant::now();
+        for _ in 0..self.iters {
+            f();
+        }
+        device_synchronise();
+
+        let micros = timer.elapsed().as_secs_f64() * 1_000_000.0 / self.iters as f64;
+        println!("| {name:<32} | {batch_size:>10} | {micros:>12.1} |");
+    }
+
+    fn sparse_affine(&self, batch_size: usize) {
+        let weights = tensor(Shape::new(FT_HIDDEN, FT_INPUTS));
+        let biases = tensor(Shape::new(1, FT_HIDDEN));
+        let outputs = TensorBatch::new(Shape::new(1, 2 * FT_HIDDEN), batch_size);
+
+        let mut inputs = unsafe { SparseTensor::uninit(batch_size, FT_INPUTS, FT_ACTIVE) };
+        let feats: Vec<_> = (0..batch_size * FT_ACTIVE)
+            .map(|i| {
+                let feat = (i * 97 % FT_INPUTS) as i32;
+                Feat::new(feat, FT_INPUTS as i32 - 1 - feat)
+            })
+            .collect();
+        inputs.append(&feats);
+
+        self.time("sparse affine forward", batch_size, || unsafe {
+            SparseTensor::affine(self.handle, &weights, &inputs, &biases, &outputs);
+        });
+
+        self.time("sparse affine backward", batch_size, || unsafe {
+            SparseTensor::affine_backprop(self.handle, &weights, &inputs, &biases, &outputs, None, 0.0);
+        });
+    }
+
+    fn gemm(&self, batch_size: usize, inp_size: usize, out_size: usize) {
+        let weights = tensor(Shape::new(inp_size, out_size));
+        let biases = tensor(Shape::new(1, out_size));
+        let weights_grad = tensor(Shape::new(inp_size, out_size));
+        let biases_grad = tensor(Shape::new(1, out_size));
+        let inputs = TensorBatch::new(Shape::new(1, inp_size), batch_size);
+        let outputs = TensorBatch::new(Shape::new(1, out_size), batch_size);
+
+        let ones = DeviceBuffer::new(batch_size);
+        ones.load_from_host(&vec![1.0; batch_size]);
+
+        self.time(&format!("affine {inp_size}->{out_size} forward"), batch_size, || unsafe {
+            TensorBatch::affine(self.handle, batch_size, &weights, &inputs, &biases, &outputs);
+        });
+
+        self.time(&format!("affine {inp_size}->{out_size} backward"), batch_size, || unsafe {
+            TensorBatch::backprop_affine(
+                self.handle,
+                &ones,
+                batch_size,
+                &weights,
+                &outputs,
+                &inputs,
+                &weights_grad,
+                &biases_grad,
+            );
+        });
+    }
+
+    fn activations(&self, batch_size: usize) {
+        let shape = Shape::new(1, 2 * FT_HIDDEN);
+        let inputs = TensorBatch::new(shape, batch_size);
+        let outputs = TensorBatch::new(shape, batch_size);
+
+        for activation in [Activation::ReLU, Activation::CReLU, Activation::SCReLU] {
+            self.time(&format!("{activation:?} forward"), batch_size, || {
+                TensorBatch::activate(self.handle, batch_size, activation, &inputs, &outputs);
+            });
+
+            self.time(&format!("{activation:?} backward"), batch_size, || {
+                TensorBatch::backprop_activation(self.handle, batch_size, activation, &inputs, &outputs);
+            });
+        }
+    }
+
+    fn optimiser(&self) {
+        let size = (FT_INPUTS + 1) * FT_HIDDEN + GEMMS.iter().map(|(inp, out)| (inp + 1) * out).sum::<usize>();
+        let mut optimiser = Optimiser::new(size, OptimiserType::AdamW);
+
+        self.time("adamw update", size, || optimiser.update(self.handle, 0.01, 1.0, 0.001));
+    }
+}
+
+fn tensor(shape: Shape) -> Tensor {
+    let mut tensor = unsafe { Tensor::uninit(shape) };
+    tensor.calloc();
+    tensor
+}
+
+fn main() {
+    let options = Options::from_args();
+
+    if options.convergence {
+        let tolerances = convergence::Tolerances { max_loss: options.max_loss, min_throughput: options.min_throughput };
+
+        if !convergence::run(options.superbatches, options.threads, &options.out_dir, &tolerances) {
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    let batch_sizes: Vec<usize> =
+        options.batch_sizes.split(',').map(|size| size.trim().parse().expect("Invalid batch size!")).collect();
+
+    let mut handle = DeviceHandles::default();
+    handle.set_threads(options.threads);
+    let bench = Bench { handle, iters: options.iters };
+
+    println!("Device: {}", device_name());
+    println!("| {:<32} | {:>10} | {:>12} |", "Kernel", "Size", "Time (us)");
+    println!("|{:-<34}|{:->12}|{:->14}|", "", "", "");
+
+    for &batch_size in &batch_sizes {
+        bench.sparse_affine(batch_size);
+
+        for (inp_size, out_size) in GEMMS {
+            bench.gemm(batch_size, inp_size, out_size);
+        }
+
+        bench.activations(batch_size);
+    }
+
+    // sizes are batch sizes, except for the optimiser step, which is timed over the number of weights
+    bench.optimiser();
+}
@@ -0,0 +1,42 @@
+use bullet::inputs::{self, find_collisions, Chess768, InputType};
+use bulletformat::{ChessBoard, DataLoader};
+use structopt::StructOpt;
+
+use std::path::PathBuf;
+
+#[derive(StructOpt)]
+pub struct CollisionsOptions {
+    #[structopt(required = true, short, long)]
+    input: PathBuf,
+    /// Input type to check, one of `chess768` or `mirrored`.
+    #[structopt(short, long, default_value = "mirrored")]
+    features: String,
+    /// Maximum number of positions to scan.
+    #[structopt(short, long, default_value = "10000000")]
+    limit: usize,
+}
+
+impl CollisionsOptions {
+    pub fn run(&self) {
+        let report = match self.features.as_str() {
+            "chess768" => self.scan(Chess768),
+            "mirrored" => self.scan(inputs::ChessBucketsMirrored::default()),
+            other => panic!("Unknown input type [{other}]!"),
+        };
+
+        println!("{report}");
+    }
+
+    fn scan<I: InputType<RequiredDataType = ChessBoard>>(&self, input: I) -> inputs::CollisionReport {
+        let loader = DataLoader::<ChessBoard>::new(&self.input, 256).unwrap();
+
+        let mut positions = Vec::new();
+        loader.map_positions(|pos| {
+            if positions.len() < self.limit {
+                positions.push(*pos);
+            }
+        });
+
+        find_collisions(&input, &Chess768, positions)
+    }
+}
@@ -5,6 +5,7 @@ use std::{
     time::Instant,
 };
 
+use bullet::convert::{parse_text_position, BinSource, Converter, PositionSource, TextSource};
 use bulletformat::{chess::{CudADFormat, MarlinFormat}, convert_from_bin, convert_from_text, AtaxxBoard, BulletFormat, ChessBoard};
 use structopt::StructOpt;
 
@@ -18,14 +19,27 @@ pub struct ConvertOptions {
     output: PathBuf,
     #[structopt(short, long)]
     threads: usize,
+    /// Write this many shuffled shards into the output directory
+    #[structopt(long)]
+    shards: Option<usize>,
+    /// Drop positions with an absolute score above this, requires `--shards`
+    #[structopt(long)]
+    max_score: Option<i16>,
+    #[structopt(long, default_value = "0")]
+    seed: u64,
 }
 
 impl ConvertOptions {
     pub fn run(&self) {
+        if let Some(shards) = self.shards {
+            self.run_sharded(shards);
+            return;
+        }
+
+        assert!(self.max_score.is_none(), "Filtering requires `--shards`!");
+
         match self.from.as_str() {
-            "marlinformat" => {
-                convert_from_bin::<MarlinFormat, ChessBoard>(&self.input, &self.output, self.threads).unwrap()
-            }
+            "marlinformat" => convert_marlin(&self.input, &self.output),
             "cudadformat" => {
                 convert_from_bin::<CudADFormat, ChessBoard>(&self.input, &self.output, self.threads).unwrap()
             }
@@ -36,6 +50,30 @@ impl ConvertOptions {
     }
 }
 
+impl ConvertOptions {
+    fn run_sharded(&self, shards: usize) {
+        let timer = Instant::now();
+        let max_score = self.max_score.unwrap_or(i16::MAX);
+        let converter =
+            Converter::new(self.threads).shards(shards).seed(self.seed).filter(|pos| pos.score.abs() <= max_score);
+
+        let output = self.output.to_str().expect("Provide a correct path!");
+        let summary = match self.from.as_str() {
+            "marlinformat" => converter.run(&mut BinSource::<MarlinFormat>::new(&self.input).unwrap(), output),
+            "cudadformat" => converter.run(&mut BinSource::<CudADFormat>::new(&self.input).unwrap(), output),
+            "bulletformat" => converter.run(&mut BinSource::<ChessBoard>::new(&self.input).unwrap(), output),
+            "text" => converter.run(&mut TextSource::new(&self.input).unwrap(), output),
+            _ => {
+                println!("Unrecognised Source Type! Supported: 'marlinformat', 'cudadformat', 'bulletformat', 'text'.");
+                return;
+            }
+        };
+
+        println!("{}", summary.unwrap());
+        println!("> Took {:.2} seconds.", timer.elapsed().as_secs_f32());
+    }
+}
+
 fn convert_text(inp_path: impl AsRef<Path>, out_path: impl AsRef<Path>) {
     let timer = Instant::now();
 
@@ -48,7 +86,7 @@ fn convert_text(inp_path: impl AsRef<Path>, out_path: impl AsRef<Path>) {
     let mut output = BufWriter::new(File::create(&out_path).expect("Provide a correct path!"));
 
     for line in file.lines().map(Result::unwrap) {
-        match line.parse::<ChessBoard>() {
+        match parse_text_position(&line) {
             Ok(pos) => {
                 results[pos.result_idx()] += 1;
                 data.push(pos);
@@ -68,3 +106,27 @@ fn convert_text(inp_path: impl AsRef<Path>, out_path: impl AsRef<Path>) {
     println!("Summary: {} Positions in {:.2} seconds", results.iter().sum::<u64>(), timer.elapsed().as_secs_f32());
     println!("Wins: {}, Draws: {}, Losses: {}", results[2], results[1], results[0]);
 }
+
+/// Converts in order, keeping the halfmove clock and the castling rights
+/// of unmoved rooks, which `convert_from_bin` would turn into bishops.
+fn convert_marlin(inp_path: impl AsRef<Path>, out_path: impl AsRef<Path>) {
+    let timer = Instant::now();
+
+    let mut source = BinSource::<MarlinFormat>::new(&inp_path).expect("Provide a correct path!");
+    let mut output = BufWriter::new(File::create(&out_path).expect("Provide a correct path!"));
+    let mut data = Vec::new();
+    let mut total = 0;
+
+    loop {
+        data.clear();
+        let read = source.read_chunk(&mut data, 16384).unwrap();
+        if read == 0 {
+            break;
+        }
+
+        total += read;
+        BulletFormat::write_to_bin(&mut output, &data).unwrap();
+    }
+
+    println!("Summary: {total} Positions in {:.2} seconds", timer.elapsed().as_secs_f32());
+}
@@ -1,3 +1,4 @@
+mod collisions;
 mod convert;
 mod interleave;
 mod shuffle;
@@ -7,6 +8,7 @@ use structopt::StructOpt;
 
 #[derive(StructOpt)]
 pub enum Options {
+    Collisions(collisions::CollisionsOptions),
     Convert(convert::ConvertOptions),
     Interleave(interleave::InterleaveOptions),
     Shuffle(shuffle::ShuffleOptions),
@@ -15,6 +17,7 @@ pub enum Options {
 
 fn main() {
     match Options::from_args() {
+        Options::Collisions(options) => options.run(),
         Options::Convert(options) => options.run(),
         Options::Interleave(options) => options.run(),
         Options::Shuffle(options) => options.run(),
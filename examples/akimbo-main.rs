@@ -3,7 +3,7 @@ The exact training used for akimbo's current network, updated as I merge new net
 */
 use bullet_lib::{
     inputs, outputs, Activation, Engine, LocalSettings, LrScheduler, OpeningBook, TestSettings, TimeControl,
-    TrainerBuilder, TrainingSchedule, UciOption, WdlScheduler, Loss
+    TrainerBuilder, TrainingSchedule, UciOption, WdScheduler, WdlScheduler, Loss
 };
 
 macro_rules! net_id {
@@ -44,6 +44,7 @@ fn main() {
         end_superbatch: 240,
         wdl_scheduler: WdlScheduler::Constant { value: 0.0 },
         lr_scheduler: LrScheduler::Step { start: 0.001, gamma: 0.3, step: 60 },
+        wd_scheduler: WdScheduler::Constant { value: 0.01 },
         loss_function: Loss::SigmoidMSE,
         save_rate: 150,
     };
@@ -52,6 +53,7 @@ fn main() {
         threads: 4,
         data_file_paths: vec!["../../data/test80-sep2022.data"],
         output_directory: "checkpoints",
+        resume_from: None,
     };
 
     let base_engine = Engine {
@@ -1,6 +1,6 @@
 use bullet_lib::{
     format::AtaxxBoard, inputs::InputType, outputs, Activation, LocalSettings, LrScheduler, TrainerBuilder,
-    TrainingSchedule, WdlScheduler, Loss
+    TrainingSchedule, WdScheduler, WdlScheduler, Loss
 };
 
 const HIDDEN_SIZE: usize = 128;
@@ -112,6 +112,7 @@ fn main() {
         end_superbatch: 40,
         wdl_scheduler: WdlScheduler::Constant { value: 0.5 },
         lr_scheduler: LrScheduler::Step { start: 0.001, gamma: 0.1, step: 15 },
+        wd_scheduler: WdScheduler::Constant { value: 0.01 },
         loss_function: Loss::SigmoidMSE,
         save_rate: 10,
     };
@@ -120,6 +121,7 @@ fn main() {
         threads: 4,
         data_file_paths: vec!["../../data/ataxx/005.data"],
         output_directory: "checkpoints",
+        resume_from: None,
     };
 
     trainer.run(&schedule, &settings);
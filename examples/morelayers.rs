@@ -5,7 +5,8 @@ fixed-nodes, but unfortunately was too much of a slowdown to pass any
 time-controlled test.
 */
 use bullet_lib::{
-    inputs, outputs, Activation, LocalSettings, LrScheduler, TrainerBuilder, TrainingSchedule, WdlScheduler, Loss
+    inputs, outputs, Activation, LocalSettings, LrScheduler, TrainerBuilder, TrainingSchedule, WdScheduler,
+    WdlScheduler, Loss
 };
 
 fn main() {
@@ -31,6 +32,7 @@ fn main() {
         end_superbatch: 255,
         wdl_scheduler: WdlScheduler::Linear { start: 0.2, end: 0.5 },
         lr_scheduler: LrScheduler::Step { start: 0.001, gamma: 0.1, step: 120 },
+        wd_scheduler: WdScheduler::Constant { value: 0.01 },
         loss_function: Loss::SigmoidMSE,
         save_rate: 1,
     };
@@ -39,6 +41,7 @@ fn main() {
         threads: 4,
         data_file_paths: vec!["../../data/akimbo3-9.data"],
         output_directory: "checkpoints",
+        resume_from: None,
     };
 
     trainer.run(&schedule, &settings);
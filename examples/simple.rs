@@ -6,7 +6,8 @@ There's potentially a lot of elo available by adjusting the wdl
 and lr schedulers, depending on your dataset.
 */
 use bullet_lib::{
-    inputs, outputs, Activation, LocalSettings, LrScheduler, TrainerBuilder, TrainingSchedule, WdlScheduler, Loss
+    inputs, outputs, Activation, LocalSettings, LrScheduler, TrainerBuilder, TrainingSchedule, WdScheduler,
+    WdlScheduler, Loss
 };
 
 const HIDDEN_SIZE: usize = 16;
@@ -34,12 +35,17 @@ fn main() {
         end_superbatch: 10,
         wdl_scheduler: WdlScheduler::Constant { value: 0.75 },
         lr_scheduler: LrScheduler::Step { start: 0.001, gamma: 0.1, step: 4 },
+        wd_scheduler: WdScheduler::Constant { value: 0.01 },
         loss_function: Loss::SigmoidMSE,
         save_rate: 1,
     };
 
-    let settings =
-        LocalSettings { threads: 4, data_file_paths: vec!["../../data/30m.data"], output_directory: "checkpoints" };
+    let settings = LocalSettings {
+        threads: 4,
+        data_file_paths: vec!["../../data/30m.data"],
+        output_directory: "checkpoints",
+        resume_from: None,
+    };
 
     trainer.run(&schedule, &settings);
 }
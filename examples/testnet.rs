@@ -2,7 +2,8 @@
 This is used to confirm non-functional changes for bullet.
 */
 use bullet_lib::{
-    inputs, outputs, Activation, LocalSettings, LrScheduler, TrainerBuilder, TrainingSchedule, WdlScheduler, Loss
+    inputs, outputs, Activation, LocalSettings, LrScheduler, TrainerBuilder, TrainingSchedule, WdScheduler,
+    WdlScheduler, Loss
 };
 
 fn main() {
@@ -27,12 +28,17 @@ fn main() {
         end_superbatch: 5,
         wdl_scheduler: WdlScheduler::Constant { value: 0.2 },
         lr_scheduler: LrScheduler::Constant { value: 0.001 },
+        wd_scheduler: WdScheduler::Constant { value: 0.01 },
         loss_function: Loss::SigmoidMSE,
         save_rate: 10,
     };
 
-    let settings =
-        LocalSettings { threads: 4, data_file_paths: vec!["../../data/batch1.data"], output_directory: "checkpoints" };
+    let settings = LocalSettings {
+        threads: 4,
+        data_file_paths: vec!["../../data/batch1.data"],
+        output_directory: "checkpoints",
+        resume_from: None,
+    };
 
     trainer.run(&schedule, &settings);
 }
@@ -1,17 +1,31 @@
 #![allow(unused_variables, clippy::missing_safety_doc, clippy::too_many_arguments)]
+mod attention;
 mod backprops;
 mod bufops;
+mod conv;
+mod grouped;
 mod mpe;
+mod norm;
+mod reduce;
+mod softmax;
 mod sparse_affine;
+mod sparse_format;
 mod splat_add;
 mod update;
 
 use super::{util, DeviceHandles};
 
+pub use attention::*;
 pub use backprops::*;
 pub use bufops::*;
+pub use conv::*;
+pub use grouped::*;
 pub use mpe::*;
+pub use norm::*;
+pub use reduce::*;
+pub use softmax::*;
 pub use sparse_affine::*;
+pub use sparse_format::*;
 pub use splat_add::*;
 pub use update::*;
 
@@ -141,12 +155,13 @@ pub unsafe fn reduce_add(
             sum += *this_inp.add(out_size * i);
         }
 
-        *(out as *mut f32).add(idx) = sum;
+        *(out as *mut f32).add(idx) += sum;
     });
 }
 
+/// Copies chunk `buckets[i]` of size `output_size` of input `i` to output `i`.
 pub unsafe fn select(
-    _: DeviceHandles,
+    handle: DeviceHandles,
     batch_size: usize,
     input_size: usize,
     output_size: usize,
@@ -154,11 +169,21 @@ pub unsafe fn select(
     inp: *const f32,
     out: *mut f32,
 ) {
-    unimplemented!();
+    let buckets = buckets as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let bucket = usize::from(*(buckets as *const u8).add(idx));
+        let this_inp = (inp as *const f32).add(input_size * idx + output_size * bucket);
+        std::ptr::copy_nonoverlapping(this_inp, (out as *mut f32).add(output_size * idx), output_size);
+    });
 }
 
+/// Copies input `i` to chunk `buckets[i]` of size `input_size` of output `i`,
+/// leaving the rest of the output untouched.
 pub unsafe fn select_backprop(
-    _: DeviceHandles,
+    handle: DeviceHandles,
     batch_size: usize,
     input_size: usize,
     output_size: usize,
@@ -166,5 +191,55 @@ pub unsafe fn select_backprop(
     inp: *const f32,
     out: *mut f32,
 ) {
-    unimplemented!();
+    let buckets = buckets as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let bucket = usize::from(*(buckets as *const u8).add(idx));
+        let this_out = (out as *mut f32).add(output_size * idx + input_size * bucket);
+        std::ptr::copy_nonoverlapping((inp as *const f32).add(input_size * idx), this_out, input_size);
+    });
+}
+
+/// Copies row `buckets[i]` of the `size` by `buckets` matrix `weights` to output `i`.
+pub unsafe fn gather(
+    handle: DeviceHandles,
+    batch_size: usize,
+    size: usize,
+    buckets: *const u8,
+    weights: *const f32,
+    out: *mut f32,
+) {
+    let buckets = buckets as usize;
+    let weights = weights as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let bucket = usize::from(*(buckets as *const u8).add(idx));
+        let row = (weights as *const f32).add(size * bucket);
+        std::ptr::copy_nonoverlapping(row, (out as *mut f32).add(size * idx), size);
+    });
+}
+
+/// Adds the errors of each output of `gather` to the gradient of the row it was read from.
+pub unsafe fn backprop_gather(
+    handle: DeviceHandles,
+    batch_size: usize,
+    size: usize,
+    buckets: *const u8,
+    errors: *const f32,
+    weights_grad: *mut f32,
+) {
+    let buckets = buckets as usize;
+    let errors = errors as usize;
+    let weights_grad = weights_grad as usize;
+
+    // each element of a row is accumulated by a single thread, so no atomics are needed
+    handle.split_workload(size, |_, elem| {
+        for idx in 0..batch_size {
+            let bucket = usize::from(*(buckets as *const u8).add(idx));
+            *(weights_grad as *mut f32).add(size * bucket + elem) += *(errors as *const f32).add(size * idx + elem);
+        }
+    });
 }
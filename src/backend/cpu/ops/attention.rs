@@ -0,0 +1,198 @@
+use super::DeviceHandles;
+use crate::tensor::AttentionDescription;
+
+// Per tensor, `qkv` holds the queries, keys and values of each token in turn,
+// and `attn` the attention weights of each head, by query then key.
+
+pub unsafe fn attention(
+    handle: DeviceHandles,
+    desc: &AttentionDescription,
+    batch_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    inp: *const f32,
+    qkv: *mut f32,
+    attn: *mut f32,
+    out: *mut f32,
+) {
+    let AttentionDescription { len, dim, heads, head_dim } = *desc;
+    let inner = desc.inner();
+    let scale = 1.0 / (head_dim as f32).sqrt();
+    let weights = weights as usize;
+    let biases = biases as usize;
+    let inp = inp as usize;
+    let qkv = qkv as usize;
+    let attn = attn as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let weights = weights as *const f32;
+        let biases = biases as *const f32;
+        let inp = (inp as *const f32).add(idx * len * dim);
+        let qkv = (qkv as *mut f32).add(idx * len * 3 * inner);
+        let attn = (attn as *mut f32).add(idx * heads * len * len);
+        let out = (out as *mut f32).add(idx * len * inner);
+
+        for t in 0..len {
+            for row in 0..3 * inner {
+                let mut sum = *biases.add(row);
+                for f in 0..dim {
+                    sum += *weights.add(row * dim + f) * *inp.add(t * dim + f);
+                }
+
+                *qkv.add(t * 3 * inner + row) = sum;
+            }
+        }
+
+        for h in 0..heads {
+            for i in 0..len {
+                let q = qkv.add(i * 3 * inner + h * head_dim);
+                let scores = attn.add((h * len + i) * len);
+                let mut max = f32::NEG_INFINITY;
+
+                for j in 0..len {
+                    let k = qkv.add(j * 3 * inner + inner + h * head_dim);
+                    let mut score = 0.0;
+                    for d in 0..head_dim {
+                        score += *q.add(d) * *k.add(d);
+                    }
+
+                    *scores.add(j) = score * scale;
+                    max = max.max(score * scale);
+                }
+
+                let mut total = 0.0;
+                for j in 0..len {
+                    *scores.add(j) = (*scores.add(j) - max).exp();
+                    total += *scores.add(j);
+                }
+
+                for j in 0..len {
+                    *scores.add(j) /= total;
+                }
+
+                for d in 0..head_dim {
+                    let mut sum = 0.0;
+                    for j in 0..len {
+                        sum += *scores.add(j) * *qkv.add(j * 3 * inner + 2 * inner + h * head_dim + d);
+                    }
+
+                    *out.add(i * inner + h * head_dim + d) = sum;
+                }
+            }
+        }
+    });
+}
+
+/// Accumulates the gradients of the weights and biases, then overwrites
+/// `inp` with its own gradient, using `qkv_grad` and `attn_grad` as scratch.
+pub unsafe fn backprop_attention(
+    handle: DeviceHandles,
+    desc: &AttentionDescription,
+    batch_size: usize,
+    weights: *const f32,
+    weights_grad: *mut f32,
+    biases_grad: *mut f32,
+    qkv: *const f32,
+    attn: *const f32,
+    qkv_grad: *mut f32,
+    attn_grad: *mut f32,
+    errors: *const f32,
+    inp: *mut f32,
+) {
+    let AttentionDescription { len, dim, heads, head_dim } = *desc;
+    let inner = desc.inner();
+    let scale = 1.0 / (head_dim as f32).sqrt();
+    let weights = weights as usize;
+    let weights_grad = weights_grad as usize;
+    let biases_grad = biases_grad as usize;
+    let qkv = qkv as usize;
+    let attn = attn as usize;
+    let qkv_grad = qkv_grad as usize;
+    let attn_grad = attn_grad as usize;
+    let errors = errors as usize;
+    let inp = inp as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let qkv = (qkv as *const f32).add(idx * len * 3 * inner);
+        let attn = (attn as *const f32).add(idx * heads * len * len);
+        let qkv_grad = (qkv_grad as *mut f32).add(idx * len * 3 * inner);
+        let attn_grad = (attn_grad as *mut f32).add(idx * heads * len * len);
+        let errors = (errors as *const f32).add(idx * len * inner);
+
+        for i in 0..len * 3 * inner {
+            *qkv_grad.add(i) = 0.0;
+        }
+
+        for h in 0..heads {
+            for i in 0..len {
+                let err = errors.add(i * inner + h * head_dim);
+                let weights = attn.add((h * len + i) * len);
+                let grads = attn_grad.add((h * len + i) * len);
+
+                // gradient of the attention weights, then through the softmax
+                let mut dot = 0.0;
+                for j in 0..len {
+                    let v = qkv.add(j * 3 * inner + 2 * inner + h * head_dim);
+                    let mut grad = 0.0;
+                    for d in 0..head_dim {
+                        grad += *err.add(d) * *v.add(d);
+                    }
+
+                    *grads.add(j) = grad;
+                    dot += grad * *weights.add(j);
+                }
+
+                for j in 0..len {
+                    let a = *weights.add(j);
+                    let ds = a * (*grads.add(j) - dot) * scale;
+                    let (q, k) = (i * 3 * inner + h * head_dim, j * 3 * inner + inner + h * head_dim);
+                    let v = j * 3 * inner + 2 * inner + h * head_dim;
+
+                    for d in 0..head_dim {
+                        *qkv_grad.add(q + d) += ds * *qkv.add(k + d);
+                        *qkv_grad.add(k + d) += ds * *qkv.add(q + d);
+                        *qkv_grad.add(v + d) += a * *err.add(d);
+                    }
+                }
+            }
+        }
+    });
+
+    // each row of the projections is accumulated by a single thread, so no atomics are needed
+    handle.split_workload(3 * inner, |_, row| {
+        let weights_grad = (weights_grad as *mut f32).add(row * dim);
+        let bias_grad = (biases_grad as *mut f32).add(row);
+
+        for b in 0..batch_size {
+            let inp = (inp as *const f32).add(b * len * dim);
+            let qkv_grad = (qkv_grad as *const f32).add(b * len * 3 * inner);
+
+            for t in 0..len {
+                let grad = *qkv_grad.add(t * 3 * inner + row);
+                *bias_grad += grad;
+
+                for f in 0..dim {
+                    *weights_grad.add(f) += grad * *inp.add(t * dim + f);
+                }
+            }
+        }
+    });
+
+    handle.split_workload(batch_size, |_, idx| {
+        let weights = weights as *const f32;
+        let inp = (inp as *mut f32).add(idx * len * dim);
+        let qkv_grad = (qkv_grad as *const f32).add(idx * len * 3 * inner);
+
+        for t in 0..len {
+            for f in 0..dim {
+                let mut sum = 0.0;
+                for row in 0..3 * inner {
+                    sum += *weights.add(row * dim + f) * *qkv_grad.add(t * 3 * inner + row);
+                }
+
+                *inp.add(t * dim + f) = sum;
+            }
+        }
+    });
+}
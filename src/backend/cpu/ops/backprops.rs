@@ -24,3 +24,89 @@ pub unsafe fn backprop_crelu(handle: DeviceHandles, size: usize, inp: *const f32
 pub unsafe fn backprop_screlu(handle: DeviceHandles, size: usize, inp: *const f32, out: *mut f32) {
     backprop_operation::<SCReLU>(handle, size, inp, out);
 }
+
+pub unsafe fn backprop_bounded_crelu(
+    handle: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+) {
+    bounded_backprop(handle, size, inp, out, |x| if x > min && x < max { 1.0 } else { 0.0 });
+}
+
+pub unsafe fn backprop_bounded_screlu(
+    handle: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+) {
+    bounded_backprop(handle, size, inp, out, |x| if x > min && x < max { 2.0 * x } else { 0.0 });
+}
+
+pub unsafe fn backprop_leaky_relu(handle: DeviceHandles, size: usize, inp: *const f32, out: *mut f32, slope: f32) {
+    bounded_backprop(handle, size, inp, out, |x| if x > 0.0 { 1.0 } else { slope });
+}
+
+/// As `backprop_leaky_relu` with the slopes of `activate_prelu`, also
+/// accumulating the gradient of each slope into `slopes_grad`.
+pub unsafe fn backprop_prelu(
+    handle: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    slopes: *const f32,
+    slopes_grad: *mut f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    // each column is summed by a single thread, so no atomics are needed
+    let mut column_grads = vec![0.0f32; width];
+    let grads = column_grads.as_mut_ptr() as usize;
+    let slopes = slopes as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(width, |_, col| {
+        let slope = *(slopes as *const f32).add(col % channels);
+        let mut grad = 0.0;
+
+        for idx in (col..size).step_by(width) {
+            let err = *(inp as *const f32).add(idx);
+            let this_out = (out as *mut f32).add(idx);
+
+            if *this_out > 0.0 {
+                *this_out = err;
+            } else {
+                grad += err * *this_out;
+                *this_out = err * slope;
+            }
+        }
+
+        *(grads as *mut f32).add(col) = grad;
+    });
+
+    for (col, grad) in column_grads.into_iter().enumerate() {
+        *slopes_grad.add(col % channels) += grad;
+    }
+}
+
+unsafe fn bounded_backprop<F: Fn(f32) -> f32 + Sync>(
+    handle: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    prime: F,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let this_inp = (inp as *const f32).add(idx);
+        let this_out = (out as *mut f32).add(idx);
+        *this_out = *this_inp * prime(*this_out);
+    });
+}
@@ -74,6 +74,85 @@ pub unsafe fn activate_screlu(handle: DeviceHandles, size: usize, inp: *const f3
     buffer_operation::<SCReLU>(handle, size, inp, out);
 }
 
+pub unsafe fn activate_bounded_crelu(
+    handle: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+) {
+    bounded_operation(handle, size, inp, out, |x| x.clamp(min, max));
+}
+
+pub unsafe fn activate_bounded_screlu(
+    handle: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+) {
+    bounded_operation(handle, size, inp, out, |x| x.clamp(min, max).powi(2));
+}
+
+pub unsafe fn activate_leaky_relu(handle: DeviceHandles, size: usize, inp: *const f32, out: *mut f32, slope: f32) {
+    bounded_operation(handle, size, inp, out, |x| if x > 0.0 { x } else { slope * x });
+}
+
+/// `slopes` holds `channels` values, with element `i` of each
+/// tensor of `width` elements using slope `i % channels`.
+pub unsafe fn activate_prelu(
+    handle: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    slopes: *const f32,
+    out: *mut f32,
+) {
+    let inp = inp as usize;
+    let slopes = slopes as usize;
+    let out = out as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let x = *(inp as *const f32).add(idx);
+        let slope = *(slopes as *const f32).add(idx % width % channels);
+        *(out as *mut f32).add(idx) = if x > 0.0 { x } else { slope * x };
+    });
+}
+
+/// Any of the activations, as fused into the op before them: clipped
+/// to `[min, max]`, multiplied by `slope` if negative, then squared if
+/// `square`.
+pub(super) fn fused_activate(x: f32, min: f32, max: f32, slope: f32, square: bool) -> f32 {
+    let x = x.clamp(min, max);
+    let x = if x < 0.0 { slope * x } else { x };
+
+    if square {
+        x * x
+    } else {
+        x
+    }
+}
+
+unsafe fn bounded_operation<F: Fn(f32) -> f32 + Sync>(
+    handle: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    f: F,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let this_inp = (inp as *const f32).add(idx);
+        let this_out = (out as *mut f32).add(idx);
+        *this_out = f(*this_inp);
+    });
+}
+
 pub unsafe fn add_to(handle: DeviceHandles, size: usize, inp: *const f32, out: *mut f32) {
     let inp = inp as usize;
     let out = out as usize;
@@ -84,3 +163,101 @@ pub unsafe fn add_to(handle: DeviceHandles, size: usize, inp: *const f32, out: *
         *this_out += *this_inp;
     });
 }
+
+/// Copies `size` elements of each tensor in a batch of `inp`, starting at `inp_offset`,
+/// into each tensor of `out` starting at `out_offset`, for concatenating tensors.
+pub unsafe fn copy_strided(
+    handle: DeviceHandles,
+    batch_size: usize,
+    size: usize,
+    inp_stride: usize,
+    inp_offset: usize,
+    out_stride: usize,
+    out_offset: usize,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size * size, |_, idx| {
+        let (tensor, i) = (idx / size, idx % size);
+        let this_inp = (inp as *const f32).add(inp_stride * tensor + inp_offset + i);
+        let this_out = (out as *mut f32).add(out_stride * tensor + out_offset + i);
+        *this_out = *this_inp;
+    });
+}
+
+/// Multiplies `inp` elementwise by `mask`.
+pub unsafe fn masked_scale(handle: DeviceHandles, size: usize, mask: *const f32, inp: *const f32, out: *mut f32) {
+    let mask = mask as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let this_mask = (mask as *const f32).add(idx);
+        let this_inp = (inp as *const f32).add(idx);
+        let this_out = (out as *mut f32).add(idx);
+        *this_out = *this_mask * *this_inp;
+    });
+}
+
+/// Adds the sum of squares of `inp` to `out`, which holds one entry per thread.
+pub unsafe fn sum_of_squares(handle: DeviceHandles, size: usize, inp: *const f32, out: *mut f32) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(size, |thread, idx| {
+        let this_inp = *(inp as *const f32).add(idx);
+        *(out as *mut f32).add(thread) += this_inp * this_inp;
+    });
+}
+
+/// Multiplies the first `stride` elements of each consecutive run of `2 * stride`
+/// in `inp` with the rest, after clipping each to `[min, max]`, giving `size` outputs.
+pub unsafe fn pairwise_mul(
+    handle: DeviceHandles,
+    size: usize,
+    stride: usize,
+    min: f32,
+    max: f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let first = 2 * stride * (idx / stride) + idx % stride;
+        let a = *(inp as *const f32).add(first);
+        let b = *(inp as *const f32).add(first + stride);
+        *(out as *mut f32).add(idx) = a.clamp(min, max) * b.clamp(min, max);
+    });
+}
+
+/// Overwrites the inputs to `pairwise_mul` in `out` with their gradients,
+/// given the gradients `inp` of its outputs.
+pub unsafe fn backprop_pairwise_mul(
+    handle: DeviceHandles,
+    size: usize,
+    stride: usize,
+    min: f32,
+    max: f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let err = *(inp as *const f32).add(idx);
+        let first = (out as *mut f32).add(2 * stride * (idx / stride) + idx % stride);
+        let second = first.add(stride);
+
+        let (a, b) = (*first, *second);
+        let prime = |x: f32| if x > min && x < max { 1.0 } else { 0.0 };
+
+        *first = err * b.clamp(min, max) * prime(a);
+        *second = err * a.clamp(min, max) * prime(b);
+    });
+}
@@ -0,0 +1,116 @@
+use super::DeviceHandles;
+use crate::tensor::ConvolutionDescription;
+
+/// Calls `f` with the indices of each output of channel `oc`, weight, and input
+/// of the `i`th channel of its group that it is multiplied by, within a single tensor.
+fn for_each_tap(desc: &ConvolutionDescription, oc: usize, i: usize, mut f: impl FnMut(usize, usize, usize)) {
+    let (oh, ow) = desc.output_shape();
+    let (kh, kw) = desc.kernel;
+    let (ih, iw) = desc.input_shape;
+    let ic = desc.input_channel(oc, i);
+
+    for oy in 0..oh {
+        for ox in 0..ow {
+            for ky in 0..kh {
+                for kx in 0..kw {
+                    if let Some((iy, ix)) = desc.input_position((oy, ox), (ky, kx)) {
+                        let out = (oc * oh + oy) * ow + ox;
+                        let weight = ((oc * desc.group_inputs() + i) * kh + ky) * kw + kx;
+                        f(out, weight, (ic * ih + iy) * iw + ix);
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub unsafe fn convolution(
+    handle: DeviceHandles,
+    desc: &ConvolutionDescription,
+    batch_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let (inp_size, out_size) = (desc.input_size(), desc.output_size());
+    let per_channel = out_size / desc.output_channels;
+    let weights = weights as usize;
+    let biases = biases as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let weights = weights as *const f32;
+        let inp = (inp as *const f32).add(inp_size * idx);
+        let out = (out as *mut f32).add(out_size * idx);
+
+        for oc in 0..desc.output_channels {
+            let bias = *(biases as *const f32).add(oc);
+            for i in 0..per_channel {
+                *out.add(oc * per_channel + i) = bias;
+            }
+
+            for i in 0..desc.group_inputs() {
+                for_each_tap(desc, oc, i, |o, w, i| *out.add(o) += *weights.add(w) * *inp.add(i));
+            }
+        }
+    });
+}
+
+/// Accumulates the gradients of the weights and biases, then overwrites
+/// `inp` with its own gradient.
+pub unsafe fn backprop_convolution(
+    handle: DeviceHandles,
+    desc: &ConvolutionDescription,
+    batch_size: usize,
+    weights: *const f32,
+    weights_grad: *mut f32,
+    biases_grad: *mut f32,
+    errors: *const f32,
+    inp: *mut f32,
+) {
+    let (inp_size, out_size) = (desc.input_size(), desc.output_size());
+    let per_channel = out_size / desc.output_channels;
+    let weights = weights as usize;
+    let weights_grad = weights_grad as usize;
+    let errors = errors as usize;
+    let inp = inp as usize;
+
+    // each pair of channels is accumulated by a single thread, so no atomics are needed
+    handle.split_workload(desc.output_channels * desc.group_inputs(), |_, idx| {
+        let (oc, i) = (idx / desc.group_inputs(), idx % desc.group_inputs());
+
+        for b in 0..batch_size {
+            let errors = (errors as *const f32).add(out_size * b);
+            let inp = (inp as *const f32).add(inp_size * b);
+            let grad = weights_grad as *mut f32;
+            for_each_tap(desc, oc, i, |o, w, i| *grad.add(w) += *errors.add(o) * *inp.add(i));
+        }
+    });
+
+    for b in 0..batch_size {
+        let errors = (errors as *const f32).add(out_size * b);
+        for oc in 0..desc.output_channels {
+            for i in 0..per_channel {
+                *biases_grad.add(oc) += *errors.add(oc * per_channel + i);
+            }
+        }
+    }
+
+    handle.split_workload(batch_size, |_, idx| {
+        let weights = weights as *const f32;
+        let errors = (errors as *const f32).add(out_size * idx);
+        let inp = (inp as *mut f32).add(inp_size * idx);
+
+        for i in 0..inp_size {
+            *inp.add(i) = 0.0;
+        }
+
+        for oc in 0..desc.output_channels {
+            for i in 0..desc.group_inputs() {
+                for_each_tap(desc, oc, i, |o, w, i| *inp.add(i) += *weights.add(w) * *errors.add(o));
+            }
+        }
+    });
+}
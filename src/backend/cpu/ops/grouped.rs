@@ -0,0 +1,99 @@
+use super::DeviceHandles;
+
+// Each of `groups` column-major `n` by `m` matrices in `a` multiplies its own
+// chunk of `m` inputs of every tensor, giving a chunk of `n` outputs.
+
+pub unsafe fn grouped_mul_matrix_vector(
+    handle: DeviceHandles,
+    groups: usize,
+    m: usize,
+    n: usize,
+    a_ptr: *const f32,
+    x_ptr: *const f32,
+    y_ptr: *mut f32,
+    batch_size: usize,
+) {
+    let a_ptr = a_ptr as usize;
+    let x_ptr = x_ptr as usize;
+    let y_ptr = y_ptr as usize;
+
+    handle.split_workload(batch_size * groups, |_, idx| {
+        let (b, g) = (idx / groups, idx % groups);
+        let a_ptr = (a_ptr as *const f32).add(g * m * n);
+        let x_ptr = (x_ptr as *const f32).add(groups * m * b + m * g);
+        let y_ptr = (y_ptr as *mut f32).add(groups * n * b + n * g);
+
+        for j in 0..n {
+            let mut y = 0.0;
+            let a = a_ptr.add(j);
+            for i in 0..m {
+                y += *a.add(n * i) * *x_ptr.add(i);
+            }
+
+            *y_ptr.add(j) = y;
+        }
+    });
+}
+
+pub unsafe fn grouped_mul_matrixt_vector(
+    handle: DeviceHandles,
+    groups: usize,
+    m: usize,
+    n: usize,
+    a_ptr: *const f32,
+    y_ptr: *const f32,
+    x_ptr: *mut f32,
+    batch_size: usize,
+) {
+    let a_ptr = a_ptr as usize;
+    let x_ptr = x_ptr as usize;
+    let y_ptr = y_ptr as usize;
+
+    handle.split_workload(batch_size * groups, |_, idx| {
+        let (b, g) = (idx / groups, idx % groups);
+        let a_ptr = (a_ptr as *const f32).add(g * m * n);
+        let x_ptr = (x_ptr as *mut f32).add(groups * m * b + m * g);
+        let y_ptr = (y_ptr as *const f32).add(groups * n * b + n * g);
+
+        for i in 0..m {
+            let mut x = 0.0;
+            let col = a_ptr.add(i * n);
+
+            for j in 0..n {
+                x += *col.add(j) * *y_ptr.add(j);
+            }
+
+            *x_ptr.add(i) = x;
+        }
+    });
+}
+
+pub unsafe fn grouped_reduce_add_mul_vector_vectort(
+    handle: DeviceHandles,
+    groups: usize,
+    m: usize,
+    n: usize,
+    y_ptr: *const f32,
+    x_ptr: *const f32,
+    a_ptr: *mut f32,
+    batch_size: usize,
+) {
+    let a_ptr = a_ptr as usize;
+    let x_ptr = x_ptr as usize;
+    let y_ptr = y_ptr as usize;
+
+    // each column of each matrix is accumulated by a single thread, so no atomics are needed
+    handle.split_workload(groups * m, |_, idx| {
+        let (g, i) = (idx / m, idx % m);
+        let col = (a_ptr as *mut f32).add(g * m * n + i * n);
+
+        for b in 0..batch_size {
+            let xi = *(x_ptr as *const f32).add(groups * m * b + m * g + i);
+            let y = (y_ptr as *const f32).add(groups * n * b + n * g);
+
+            for j in 0..n {
+                *col.add(j) += xi * *y.add(j);
+            }
+        }
+    });
+}
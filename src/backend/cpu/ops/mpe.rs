@@ -28,3 +28,131 @@ pub unsafe fn sigmoid_mpe(
         *this_error += absd.powf(power);
     });
 }
+
+pub unsafe fn sigmoid_mpe_weighted(
+    handle: DeviceHandles,
+    buffer_size: usize,
+    tensor_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    weights: *const f32,
+    errors: *mut f32,
+    power: f32,
+) {
+    let results = results as usize;
+    let outputs = outputs as usize;
+    let weights = weights as usize;
+    let errors = errors as usize;
+
+    handle.split_workload(buffer_size, |thread, idx| {
+        let this_result = (results as *const f32).add(idx);
+        let this_output = (outputs as *mut f32).add(idx);
+        let this_weight = (weights as *const f32).add(idx / tensor_size);
+        let this_error = (errors as *mut f32).add(thread);
+
+        let result = *this_result;
+        let output = *this_output;
+        let weight = *this_weight;
+
+        let sigmoid = 1.0 / (1.0 + (-output).exp());
+        let diff = sigmoid - result;
+        let absd = diff.abs();
+
+        *this_output = weight * diff.signum() * absd.powf(power - 1.0) * sigmoid * (1.0 - sigmoid);
+        *this_error += weight * absd.powf(power);
+    });
+}
+
+pub unsafe fn sigmoid_focal(
+    handle: DeviceHandles,
+    buffer_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    errors: *mut f32,
+    power: f32,
+    gamma: f32,
+) {
+    let results = results as usize;
+    let outputs = outputs as usize;
+    let errors = errors as usize;
+
+    handle.split_workload(buffer_size, |thread, idx| {
+        let this_result = (results as *const f32).add(idx);
+        let this_output = (outputs as *mut f32).add(idx);
+        let this_error = (errors as *mut f32).add(thread);
+
+        let result = *this_result;
+        let output = *this_output;
+
+        let sigmoid = 1.0 / (1.0 + (-output).exp());
+        let diff = sigmoid - result;
+        let absd = diff.abs();
+
+        *this_output = diff.signum() * absd.powf(power + gamma - 1.0) * sigmoid * (1.0 - sigmoid);
+        *this_error += absd.powf(power);
+    });
+}
+
+pub unsafe fn sigmoid_mse_variance(
+    handle: DeviceHandles,
+    batch_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    errors: *mut f32,
+) {
+    let results = results as usize;
+    let outputs = outputs as usize;
+    let errors = errors as usize;
+
+    handle.split_workload(batch_size, |thread, idx| {
+        let this_result = (results as *const f32).add(2 * idx);
+        let this_output = (outputs as *mut f32).add(2 * idx);
+        let this_error = (errors as *mut f32).add(thread);
+
+        let result = *this_result;
+        let output = *this_output;
+        let variance = *this_output.add(1);
+
+        let sigmoid = 1.0 / (1.0 + (-output).exp());
+        let diff = sigmoid - result;
+        let sqr = diff * diff;
+
+        *this_output = diff * sigmoid * (1.0 - sigmoid);
+        *this_output.add(1) = variance - sqr;
+        *this_error += sqr;
+    });
+}
+
+pub unsafe fn sigmoid_huber(
+    handle: DeviceHandles,
+    buffer_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    errors: *mut f32,
+    delta: f32,
+) {
+    let results = results as usize;
+    let outputs = outputs as usize;
+    let errors = errors as usize;
+
+    handle.split_workload(buffer_size, |thread, idx| {
+        let this_result = (results as *const f32).add(idx);
+        let this_output = (outputs as *mut f32).add(idx);
+        let this_error = (errors as *mut f32).add(thread);
+
+        let result = *this_result;
+        let output = *this_output;
+
+        let sigmoid = 1.0 / (1.0 + (-output).exp());
+        let diff = sigmoid - result;
+        let absd = diff.abs();
+
+        if absd <= delta {
+            *this_output = diff * sigmoid * (1.0 - sigmoid);
+            *this_error += 0.5 * diff * diff;
+        } else {
+            *this_output = delta * diff.signum() * sigmoid * (1.0 - sigmoid);
+            *this_error += delta * (absd - 0.5 * delta);
+        }
+    });
+}
@@ -0,0 +1,242 @@
+use super::DeviceHandles;
+
+/// Added to the variance before normalising, to avoid dividing by zero.
+const EPSILON: f32 = 1e-5;
+
+/// Mean and reciprocal standard deviation of a tensor.
+unsafe fn stats(inp: *const f32, tensor_size: usize) -> (f32, f32) {
+    let mut mean = 0.0;
+    for i in 0..tensor_size {
+        mean += *inp.add(i);
+    }
+    mean /= tensor_size as f32;
+
+    let mut var = 0.0;
+    for i in 0..tensor_size {
+        var += (*inp.add(i) - mean).powi(2);
+    }
+    var /= tensor_size as f32;
+
+    (mean, 1.0 / (var + EPSILON).sqrt())
+}
+
+/// Normalises each tensor in the batch to zero mean and unit variance,
+/// then scales element-wise by `gamma` and shifts by `beta`.
+pub unsafe fn layer_norm(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    beta: *const f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let gamma = gamma as usize;
+    let beta = beta as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_inp = (inp as *const f32).add(tensor_size * idx);
+        let this_out = (out as *mut f32).add(tensor_size * idx);
+        let (mean, rstd) = stats(this_inp, tensor_size);
+
+        for i in 0..tensor_size {
+            let norm = (*this_inp.add(i) - mean) * rstd;
+            *this_out.add(i) = *(gamma as *const f32).add(i) * norm + *(beta as *const f32).add(i);
+        }
+    });
+}
+
+/// Overwrites the inputs to `layer_norm` in `out` with their gradients, given
+/// the gradients `inp` of its outputs, and accumulates the gradients of `gamma`
+/// and `beta`. The normalised inputs are recomputed, as the outputs have already
+/// been overwritten.
+pub unsafe fn backprop_layer_norm(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    gamma_grad: *mut f32,
+    beta_grad: *mut f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let mut batch_stats = vec![(0.0, 0.0); batch_size];
+    let stats_ptr = batch_stats.as_mut_ptr() as usize;
+    let gamma = gamma as usize;
+    let gamma_grad = gamma_grad as usize;
+    let beta_grad = beta_grad as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_out = (out as *const f32).add(tensor_size * idx);
+        *(stats_ptr as *mut (f32, f32)).add(idx) = stats(this_out, tensor_size);
+    });
+
+    // each column is summed by a single thread, so no atomics are needed
+    handle.split_workload(tensor_size, |_, col| {
+        let mut dgamma = 0.0;
+        let mut dbeta = 0.0;
+
+        for idx in 0..batch_size {
+            let (mean, rstd) = *(stats_ptr as *const (f32, f32)).add(idx);
+            let grad = *(inp as *const f32).add(tensor_size * idx + col);
+            let norm = (*(out as *const f32).add(tensor_size * idx + col) - mean) * rstd;
+
+            dgamma += grad * norm;
+            dbeta += grad;
+        }
+
+        *(gamma_grad as *mut f32).add(col) += dgamma;
+        *(beta_grad as *mut f32).add(col) += dbeta;
+    });
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_inp = (inp as *const f32).add(tensor_size * idx);
+        let this_out = (out as *mut f32).add(tensor_size * idx);
+        let (mean, rstd) = *(stats_ptr as *const (f32, f32)).add(idx);
+
+        let mut sum = 0.0;
+        let mut dot = 0.0;
+        for i in 0..tensor_size {
+            let grad = *this_inp.add(i) * *(gamma as *const f32).add(i);
+            sum += grad;
+            dot += grad * (*this_out.add(i) - mean) * rstd;
+        }
+
+        let size = tensor_size as f32;
+        for i in 0..tensor_size {
+            let grad = *this_inp.add(i) * *(gamma as *const f32).add(i);
+            let norm = (*this_out.add(i) - mean) * rstd;
+            *this_out.add(i) = rstd * (grad - sum / size - norm * dot / size);
+        }
+    });
+}
+
+/// Normalises each element of the tensors by the mean and variance of that
+/// element across the batch, storing them in `batch_mean` and `batch_rstd`
+/// for backprop and moving the running statistics towards them by `momentum`.
+pub unsafe fn batch_norm(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    beta: *const f32,
+    running_mean: *mut f32,
+    running_var: *mut f32,
+    batch_mean: *mut f32,
+    batch_rstd: *mut f32,
+    inp: *const f32,
+    out: *mut f32,
+    momentum: f32,
+) {
+    let gamma = gamma as usize;
+    let beta = beta as usize;
+    let running_mean = running_mean as usize;
+    let running_var = running_var as usize;
+    let batch_mean = batch_mean as usize;
+    let batch_rstd = batch_rstd as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(tensor_size, |_, col| {
+        let elem = |idx: usize| *(inp as *const f32).add(tensor_size * idx + col);
+        let n = batch_size as f32;
+
+        let mean = (0..batch_size).map(elem).sum::<f32>() / n;
+        let var = (0..batch_size).map(|idx| (elem(idx) - mean).powi(2)).sum::<f32>() / n;
+        let rstd = 1.0 / (var + EPSILON).sqrt();
+
+        let (gamma, beta) = (*(gamma as *const f32).add(col), *(beta as *const f32).add(col));
+        for idx in 0..batch_size {
+            *(out as *mut f32).add(tensor_size * idx + col) = gamma * (elem(idx) - mean) * rstd + beta;
+        }
+
+        *(batch_mean as *mut f32).add(col) = mean;
+        *(batch_rstd as *mut f32).add(col) = rstd;
+
+        // the running variance is unbiased, as the batch variance underestimates it
+        let unbiased = var * n / (n - 1.0).max(1.0);
+        *(running_mean as *mut f32).add(col) += momentum * (mean - *(running_mean as *const f32).add(col));
+        *(running_var as *mut f32).add(col) += momentum * (unbiased - *(running_var as *const f32).add(col));
+    });
+}
+
+/// As `batch_norm`, but normalising by the running statistics.
+pub unsafe fn batch_norm_inference(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    beta: *const f32,
+    running_mean: *const f32,
+    running_var: *const f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let gamma = gamma as usize;
+    let beta = beta as usize;
+    let running_mean = running_mean as usize;
+    let running_var = running_var as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size * tensor_size, |_, idx| {
+        let col = idx % tensor_size;
+        let mean = *(running_mean as *const f32).add(col);
+        let rstd = 1.0 / (*(running_var as *const f32).add(col) + EPSILON).sqrt();
+        let norm = (*(inp as *const f32).add(idx) - mean) * rstd;
+
+        *(out as *mut f32).add(idx) = *(gamma as *const f32).add(col) * norm + *(beta as *const f32).add(col);
+    });
+}
+
+/// Overwrites the inputs to `batch_norm` in `out` with their gradients, given
+/// the gradients `inp` of its outputs, and accumulates the gradients of `gamma`
+/// and `beta`, using the batch statistics stored by `batch_norm`.
+pub unsafe fn backprop_batch_norm(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    batch_mean: *const f32,
+    batch_rstd: *const f32,
+    gamma_grad: *mut f32,
+    beta_grad: *mut f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let gamma = gamma as usize;
+    let batch_mean = batch_mean as usize;
+    let batch_rstd = batch_rstd as usize;
+    let gamma_grad = gamma_grad as usize;
+    let beta_grad = beta_grad as usize;
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(tensor_size, |_, col| {
+        let mean = *(batch_mean as *const f32).add(col);
+        let rstd = *(batch_rstd as *const f32).add(col);
+        let grad = |idx: usize| *(inp as *const f32).add(tensor_size * idx + col);
+        let norm = |idx: usize| (*(out as *const f32).add(tensor_size * idx + col) - mean) * rstd;
+
+        let mut dgamma = 0.0;
+        let mut dbeta = 0.0;
+        for idx in 0..batch_size {
+            dgamma += grad(idx) * norm(idx);
+            dbeta += grad(idx);
+        }
+
+        let n = batch_size as f32;
+        let scale = *(gamma as *const f32).add(col) * rstd / n;
+        for idx in 0..batch_size {
+            let dx = scale * (n * grad(idx) - dbeta - norm(idx) * dgamma);
+            *(out as *mut f32).add(tensor_size * idx + col) = dx;
+        }
+
+        *(gamma_grad as *mut f32).add(col) += dgamma;
+        *(beta_grad as *mut f32).add(col) += dbeta;
+    });
+}
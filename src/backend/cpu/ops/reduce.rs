@@ -0,0 +1,84 @@
+use super::DeviceHandles;
+
+/// Reduces `len` elements of each tensor of `inp_size` elements to each
+/// of its `out_size` outputs, where output `j` reduces the elements at
+/// `j * outer + k * inner` for `k < len`, taking their maximum if `max`
+/// and otherwise their sum multiplied by `scale`.
+pub unsafe fn reduce(
+    handle: DeviceHandles,
+    batch_size: usize,
+    inp_size: usize,
+    out_size: usize,
+    len: usize,
+    outer: usize,
+    inner: usize,
+    max: bool,
+    scale: f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_inp = (inp as *const f32).add(inp_size * idx);
+        let this_out = (out as *mut f32).add(out_size * idx);
+
+        for j in 0..out_size {
+            let elem = |k: usize| *this_inp.add(j * outer + k * inner);
+
+            *this_out.add(j) = if max {
+                (1..len).fold(elem(0), |acc, k| acc.max(elem(k)))
+            } else {
+                scale * (0..len).map(elem).sum::<f32>()
+            };
+        }
+    });
+}
+
+/// Overwrites the inputs to `reduce` in `out` with their gradients,
+/// given the gradients `inp` of its outputs. The maximum is recomputed
+/// from the inputs as the outputs have already been overwritten.
+pub unsafe fn backprop_reduce(
+    handle: DeviceHandles,
+    batch_size: usize,
+    inp_size: usize,
+    out_size: usize,
+    len: usize,
+    outer: usize,
+    inner: usize,
+    max: bool,
+    scale: f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_inp = (inp as *const f32).add(out_size * idx);
+        let this_out = (out as *mut f32).add(inp_size * idx);
+
+        for j in 0..out_size {
+            let grad = *this_inp.add(j);
+            let elem = |k: usize| this_out.add(j * outer + k * inner);
+
+            let mut best = 0;
+            if max {
+                for k in 1..len {
+                    if *elem(k) > *elem(best) {
+                        best = k;
+                    }
+                }
+            }
+
+            for k in 0..len {
+                *elem(k) = match max {
+                    true if k == best => grad,
+                    true => 0.0,
+                    false => scale * grad,
+                };
+            }
+        }
+    });
+}
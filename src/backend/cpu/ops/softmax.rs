@@ -0,0 +1,120 @@
+use super::DeviceHandles;
+
+pub unsafe fn softmax_crossentropy(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    errors: *mut f32,
+) {
+    let results = results as usize;
+    let outputs = outputs as usize;
+    let errors = errors as usize;
+
+    handle.split_workload(batch_size, |thread, idx| {
+        let this_result = (results as *const f32).add(tensor_size * idx);
+        let this_output = (outputs as *mut f32).add(tensor_size * idx);
+        let this_error = (errors as *mut f32).add(thread);
+
+        let mut max = f32::NEG_INFINITY;
+        for i in 0..tensor_size {
+            max = max.max(*this_output.add(i));
+        }
+
+        let mut total = 0.0;
+        for i in 0..tensor_size {
+            let exp = (*this_output.add(i) - max).exp();
+            *this_output.add(i) = exp;
+            total += exp;
+        }
+
+        for i in 0..tensor_size {
+            let prob = *this_output.add(i) / total;
+            let target = *this_result.add(i);
+
+            *this_output.add(i) = prob - target;
+
+            if target > 0.0 {
+                *this_error -= target * prob.ln();
+            }
+        }
+    });
+}
+
+/// Softmax, or log-softmax if `log`, of each tensor in the batch.
+pub unsafe fn softmax(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    log: bool,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_inp = (inp as *const f32).add(tensor_size * idx);
+        let this_out = (out as *mut f32).add(tensor_size * idx);
+
+        let mut max = f32::NEG_INFINITY;
+        for i in 0..tensor_size {
+            max = max.max(*this_inp.add(i));
+        }
+
+        let mut total = 0.0;
+        for i in 0..tensor_size {
+            total += (*this_inp.add(i) - max).exp();
+        }
+
+        let log_total = total.ln();
+        for i in 0..tensor_size {
+            let shifted = *this_inp.add(i) - max;
+            *this_out.add(i) = if log { shifted - log_total } else { shifted.exp() / total };
+        }
+    });
+}
+
+/// Overwrites the inputs to `softmax` in `out` with their gradients,
+/// given the gradients `inp` of its outputs. The softmax is recomputed
+/// from the inputs as its outputs have already been overwritten.
+pub unsafe fn backprop_softmax(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    log: bool,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_inp = (inp as *const f32).add(tensor_size * idx);
+        let this_out = (out as *mut f32).add(tensor_size * idx);
+
+        let mut max = f32::NEG_INFINITY;
+        for i in 0..tensor_size {
+            max = max.max(*this_out.add(i));
+        }
+
+        let mut total = 0.0;
+        for i in 0..tensor_size {
+            total += (*this_out.add(i) - max).exp();
+        }
+
+        // sum of the output gradients, weighted by the softmax if not `log`
+        let mut dot = 0.0;
+        for i in 0..tensor_size {
+            let prob = (*this_out.add(i) - max).exp() / total;
+            dot += *this_inp.add(i) * if log { 1.0 } else { prob };
+        }
+
+        for i in 0..tensor_size {
+            let prob = (*this_out.add(i) - max).exp() / total;
+            let grad = *this_inp.add(i);
+            *this_out.add(i) = if log { grad - prob * dot } else { prob * (grad - dot) };
+        }
+    });
+}
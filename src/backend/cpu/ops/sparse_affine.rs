@@ -1,4 +1,4 @@
-use super::{util, DeviceHandles};
+use super::{bufops::fused_activate, util, DeviceHandles};
 use crate::loader::Feat;
 
 pub unsafe fn sparse_affine_forward(
@@ -6,10 +6,55 @@ pub unsafe fn sparse_affine_forward(
     batch_size: usize,
     max_input_size: usize,
     output_size: usize,
+    opp_size: usize,
     weights: *const f32,
     biases: *const f32,
     inputs: *const Feat,
     outputs: *mut f32,
+) {
+    let (sizes, params) = ((max_input_size, output_size, opp_size), (weights, biases, inputs, outputs));
+    sparse_affine_forward_then(handle, batch_size, sizes, params, |_, _| {});
+}
+
+/// Fuses `sparse_affine_forward` with an activation, as `splat_add_activate`,
+/// writing the activated outputs to `activated`.
+pub unsafe fn sparse_affine_activate_forward(
+    handle: DeviceHandles,
+    batch_size: usize,
+    max_input_size: usize,
+    output_size: usize,
+    opp_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    inputs: *const Feat,
+    outputs: *mut f32,
+    activated: *mut f32,
+    min: f32,
+    max: f32,
+    slope: f32,
+    square: bool,
+) {
+    let activated = activated as usize;
+    let size = output_size + opp_size;
+    let (sizes, params) = ((max_input_size, output_size, opp_size), (weights, biases, inputs, outputs));
+
+    sparse_affine_forward_then(handle, batch_size, sizes, params, |idx, out| {
+        let this_activated = (activated as *mut f32).add(size * idx);
+
+        for i in 0..size {
+            *this_activated.add(i) = fused_activate(*out.add(i), min, max, slope, square);
+        }
+    });
+}
+
+/// Computes the outputs of each sample in the batch, then calls
+/// `then` on them while they are still hot in the cache.
+unsafe fn sparse_affine_forward_then<F: Fn(usize, *const f32) + Copy + Send + Sync>(
+    handle: DeviceHandles,
+    batch_size: usize,
+    (max_input_size, output_size, opp_size): (usize, usize, usize),
+    (weights, biases, inputs, outputs): (*const f32, *const f32, *const Feat, *mut f32),
+    then: F,
 ) {
     let weights = weights as usize;
     let biases = biases as usize;
@@ -20,14 +65,14 @@ pub unsafe fn sparse_affine_forward(
         let weights = weights as *const f32;
         let biases = biases as *const f32;
         let this_inp = (inputs as *const Feat).add(max_input_size * idx);
-        let our_out = (outputs as *mut f32).add(2 * output_size * idx);
+        let our_out = (outputs as *mut f32).add((output_size + opp_size) * idx);
         let opp_out = our_out.add(output_size);
 
         for i in 0..output_size {
             *our_out.add(i) = *biases.add(i);
         }
 
-        for i in 0..output_size {
+        for i in 0..opp_size {
             *opp_out.add(i) = *biases.add(i);
         }
 
@@ -44,10 +89,12 @@ pub unsafe fn sparse_affine_forward(
             }
 
             let opp_weights = weights.add(output_size * feat.opp() as usize);
-            for j in 0..output_size {
+            for j in 0..opp_size {
                 *opp_out.add(j) += *opp_weights.add(j);
             }
         }
+
+        then(idx, our_out);
     });
 }
 
@@ -57,6 +104,7 @@ pub unsafe fn sparse_affine_backward(
     max_active_inputs: usize,
     input_size: usize,
     output_size: usize,
+    opp_size: usize,
     weights_grad: *mut f32,
     biases_grad: *mut f32,
     inputs: *const Feat,
@@ -87,21 +135,23 @@ pub unsafe fn sparse_affine_backward(
         let biases = biases_grads[thread] as *mut f32;
 
         let this_inp = inputs.add(max_active_inputs * idx);
-        let this_err = errors.add(2 * output_size * idx);
-        let this_out = output.add(2 * output_size * idx);
+        let this_err = errors.add((output_size + opp_size) * idx);
+        // the outputs are only passed when regularising
+        let this_out = output.wrapping_add((output_size + opp_size) * idx);
 
         let our_err = this_err;
         let opp_err = this_err.add(output_size);
 
         let our_out = this_out;
-        let opp_out = this_out.add(output_size);
+        let opp_out = this_out.wrapping_add(output_size);
+        let reg = |out: *const f32, i: usize| if ft_reg == 0.0 { 0.0 } else { ft_reg * f32::from(*out.add(i) > 0.0) };
 
         for i in 0..output_size {
-            *biases.add(i) += *our_err.add(i) + ft_reg * f32::from(*our_out.add(i) > 0.0);
+            *biases.add(i) += *our_err.add(i) + reg(our_out, i);
         }
 
-        for i in 0..output_size {
-            *biases.add(i) += *opp_err.add(i) + ft_reg * f32::from(*opp_out.add(i) > 0.0);
+        for i in 0..opp_size {
+            *biases.add(i) += *opp_err.add(i) + reg(opp_out, i);
         }
 
         for i in 0..max_active_inputs {
@@ -113,12 +163,12 @@ pub unsafe fn sparse_affine_backward(
 
             let our_weights = weights.add(output_size * feat.our() as usize);
             for j in 0..output_size {
-                *our_weights.add(j) += *our_err.add(j) + ft_reg * f32::from(*our_out.add(j) > 0.0);
+                *our_weights.add(j) += *our_err.add(j) + reg(our_out, j);
             }
 
             let opp_weights = weights.add(output_size * feat.opp() as usize);
-            for j in 0..output_size {
-                *opp_weights.add(j) += *opp_err.add(j) + ft_reg * f32::from(*opp_out.add(j) > 0.0);
+            for j in 0..opp_size {
+                *opp_weights.add(j) += *opp_err.add(j) + reg(opp_out, j);
             }
         }
     });
@@ -183,6 +233,39 @@ pub unsafe fn single_sparse_affine_forward(
     });
 }
 
+pub unsafe fn single_sparse_affine_activate_forward(
+    handle: DeviceHandles,
+    batch_size: usize,
+    max_active_inputs: usize,
+    output_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    inputs: *const Feat,
+    outputs: *mut f32,
+    activated: *mut f32,
+    min: f32,
+    max: f32,
+    slope: f32,
+    square: bool,
+) {
+    sparse_affine_activate_forward(
+        handle,
+        batch_size,
+        max_active_inputs,
+        output_size,
+        0,
+        weights,
+        biases,
+        inputs,
+        outputs,
+        activated,
+        min,
+        max,
+        slope,
+        square,
+    );
+}
+
 pub unsafe fn single_sparse_affine_backward(
     handle: DeviceHandles,
     batch_size: usize,
@@ -220,13 +303,15 @@ pub unsafe fn single_sparse_affine_backward(
 
         let this_inp = inputs.add(max_active_inputs * idx);
         let this_err = errors.add(output_size * idx);
-        let this_out = output.add(output_size * idx);
+        // the outputs are only passed when regularising
+        let this_out = output.wrapping_add(output_size * idx);
 
         let our_err = this_err;
         let our_out = this_out;
+        let reg = |out: *const f32, i: usize| if ft_reg == 0.0 { 0.0 } else { ft_reg * f32::from(*out.add(i) > 0.0) };
 
         for i in 0..output_size {
-            *biases.add(i) += *our_err.add(i) + ft_reg * f32::from(*our_out.add(i) > 0.0);
+            *biases.add(i) += *our_err.add(i) + reg(our_out, i);
         }
 
         for i in 0..max_active_inputs {
@@ -238,7 +323,7 @@ pub unsafe fn single_sparse_affine_backward(
 
             let our_weights = weights.add(output_size * feat.our() as usize);
             for j in 0..output_size {
-                *our_weights.add(j) += *our_err.add(j) + ft_reg * f32::from(*our_out.add(j) > 0.0);
+                *our_weights.add(j) += *our_err.add(j) + reg(our_out, j);
             }
         }
     });
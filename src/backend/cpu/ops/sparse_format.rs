@@ -0,0 +1,168 @@
+use super::DeviceHandles;
+use crate::loader::Feat;
+
+/// Turns the count of entries in each row, stored at `offsets[row + 1]`,
+/// into the offset of the first entry of each row, with the total at the end.
+unsafe fn counts_to_offsets(rows: usize, offsets: *mut i32) {
+    *offsets = 0;
+
+    for row in 0..rows {
+        *offsets.add(row + 1) += *offsets.add(row);
+    }
+}
+
+pub unsafe fn sparse_ell_to_csr(
+    handle: DeviceHandles,
+    batch_size: usize,
+    max_input_size: usize,
+    opp: bool,
+    inputs: *const Feat,
+    offsets: *mut i32,
+    cols: *mut i32,
+    values: *mut f32,
+) {
+    let inputs = inputs as usize;
+    let offsets = offsets as usize;
+    let cols = cols as usize;
+    let values = values as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_inp = (inputs as *const Feat).add(max_input_size * idx);
+        let count = (0..max_input_size).take_while(|&i| (*this_inp.add(i)).our() != -1).count();
+        *(offsets as *mut i32).add(idx + 1) = count as i32;
+    });
+
+    counts_to_offsets(batch_size, offsets as *mut i32);
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_inp = (inputs as *const Feat).add(max_input_size * idx);
+        let start = *(offsets as *const i32).add(idx) as usize;
+        let end = *(offsets as *const i32).add(idx + 1) as usize;
+
+        for (i, entry) in (start..end).enumerate() {
+            let feat = *this_inp.add(i);
+            *(cols as *mut i32).add(entry) = if opp { feat.opp() } else { feat.our() };
+            *(values as *mut f32).add(entry) = 1.0;
+        }
+    });
+}
+
+/// Counting sort of the entries by row, which keeps the
+/// entries of each row in the order they were given.
+pub unsafe fn sparse_coo_to_csr(
+    _: DeviceHandles,
+    nnz: usize,
+    rows: usize,
+    row_indices: *const i32,
+    cols: *const i32,
+    values: *const f32,
+    offsets: *mut i32,
+    cursors: *mut i32,
+    csr_cols: *mut i32,
+    csr_values: *mut f32,
+) {
+    for row in 0..=rows {
+        *offsets.add(row) = 0;
+    }
+
+    for entry in 0..nnz {
+        *offsets.add(*row_indices.add(entry) as usize + 1) += 1;
+    }
+
+    counts_to_offsets(rows, offsets);
+
+    for row in 0..rows {
+        *cursors.add(row) = *offsets.add(row);
+    }
+
+    for entry in 0..nnz {
+        let cursor = cursors.add(*row_indices.add(entry) as usize);
+        *csr_cols.add(*cursor as usize) = *cols.add(entry);
+        *csr_values.add(*cursor as usize) = *values.add(entry);
+        *cursor += 1;
+    }
+}
+
+pub unsafe fn sparse_csr_to_coo(handle: DeviceHandles, rows: usize, offsets: *const i32, row_indices: *mut i32) {
+    let offsets = offsets as usize;
+    let row_indices = row_indices as usize;
+
+    handle.split_workload(rows, |_, idx| {
+        let start = *(offsets as *const i32).add(idx);
+        let end = *(offsets as *const i32).add(idx + 1);
+
+        for entry in start..end {
+            *(row_indices as *mut i32).add(entry as usize) = idx as i32;
+        }
+    });
+}
+
+pub unsafe fn sparse_csr_affine_forward(
+    handle: DeviceHandles,
+    rows: usize,
+    output_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    offsets: *const i32,
+    cols: *const i32,
+    values: *const f32,
+    outputs: *mut f32,
+) {
+    let weights = weights as usize;
+    let biases = biases as usize;
+    let offsets = offsets as usize;
+    let cols = cols as usize;
+    let values = values as usize;
+    let outputs = outputs as usize;
+
+    handle.split_workload(rows, |_, idx| {
+        let this_out = (outputs as *mut f32).add(output_size * idx);
+        let start = *(offsets as *const i32).add(idx) as usize;
+        let end = *(offsets as *const i32).add(idx + 1) as usize;
+
+        for i in 0..output_size {
+            *this_out.add(i) = *(biases as *const f32).add(i);
+        }
+
+        for entry in start..end {
+            let col = *(cols as *const i32).add(entry) as usize;
+            let value = *(values as *const f32).add(entry);
+            let this_weights = (weights as *const f32).add(output_size * col);
+
+            for i in 0..output_size {
+                *this_out.add(i) += value * *this_weights.add(i);
+            }
+        }
+    });
+}
+
+/// Scatters the gradient of each entry into the weights of its column,
+/// so is run serially on the CPU, where each thread would race.
+pub unsafe fn sparse_coo_affine_backward(
+    _: DeviceHandles,
+    nnz: usize,
+    rows: usize,
+    output_size: usize,
+    weights_grad: *mut f32,
+    biases_grad: *mut f32,
+    row_indices: *const i32,
+    cols: *const i32,
+    values: *const f32,
+    errors: *const f32,
+) {
+    for entry in 0..nnz {
+        let this_errors = errors.add(output_size * *row_indices.add(entry) as usize);
+        let this_grad = weights_grad.add(output_size * *cols.add(entry) as usize);
+        let value = *values.add(entry);
+
+        for i in 0..output_size {
+            *this_grad.add(i) += value * *this_errors.add(i);
+        }
+    }
+
+    for row in 0..rows {
+        for i in 0..output_size {
+            *biases_grad.add(i) += *errors.add(output_size * row + i);
+        }
+    }
+}
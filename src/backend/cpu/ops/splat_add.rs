@@ -1,4 +1,4 @@
-use super::DeviceHandles;
+use super::{bufops::fused_activate, DeviceHandles};
 
 pub unsafe fn splat_add(handle: DeviceHandles, batch_size: usize, tensor_size: usize, inp: *const f32, out: *mut f32) {
     let inp = inp as usize;
@@ -12,3 +12,205 @@ pub unsafe fn splat_add(handle: DeviceHandles, batch_size: usize, tensor_size: u
         }
     });
 }
+
+/// Fuses `splat_add` with an activation, adding `inp` to each tensor of
+/// `pre` in place and writing the activation of the result to `out`.
+pub unsafe fn splat_add_activate(
+    handle: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    inp: *const f32,
+    pre: *mut f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+    slope: f32,
+    square: bool,
+) {
+    let inp = inp as usize;
+    let pre = pre as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_pre = (pre as *mut f32).add(tensor_size * idx);
+        let this_out = (out as *mut f32).add(tensor_size * idx);
+
+        for i in 0..tensor_size {
+            let x = *this_pre.add(i) + *(inp as *const f32).add(i);
+            *this_pre.add(i) = x;
+            *this_out.add(i) = fused_activate(x, min, max, slope, square);
+        }
+    });
+}
+
+/// Adds `inp[i % channels]` to each of the `size` elements of `out`,
+/// where `channels` is 1 for a scalar or the tensor `width` for a vector.
+pub unsafe fn broadcast_add(
+    handle: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    let inp = inp as usize;
+    let out = out as usize;
+
+    handle.split_workload(width, |_, col| {
+        let val = *(inp as *const f32).add(col % channels);
+
+        for idx in (col..size).step_by(width) {
+            *(out as *mut f32).add(idx) += val;
+        }
+    });
+}
+
+/// Accumulates the gradient of the broadcast input of `broadcast_add`
+/// into `grad`, by summing `inp` over everything it was broadcast across.
+pub unsafe fn backprop_broadcast_add(
+    handle: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    grad: *mut f32,
+) {
+    // each column is summed by a single thread, so no atomics are needed
+    let mut column_grads = vec![0.0f32; width];
+    let grads = column_grads.as_mut_ptr() as usize;
+    let inp = inp as usize;
+
+    handle.split_workload(width, |_, col| {
+        let sum = (col..size).step_by(width).map(|idx| *(inp as *const f32).add(idx)).sum();
+        *(grads as *mut f32).add(col) = sum;
+    });
+
+    for (col, sum) in column_grads.into_iter().enumerate() {
+        *grad.add(col % channels) += sum;
+    }
+}
+
+/// This calculates `out[i] = inp[i % channels] * x[i]`, as `broadcast_add`.
+pub unsafe fn broadcast_mul(
+    handle: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    x: *const f32,
+    out: *mut f32,
+) {
+    let inp = inp as usize;
+    let x = x as usize;
+    let out = out as usize;
+
+    handle.split_workload(width, |_, col| {
+        let val = *(inp as *const f32).add(col % channels);
+
+        for idx in (col..size).step_by(width) {
+            *(out as *mut f32).add(idx) = val * *(x as *const f32).add(idx);
+        }
+    });
+}
+
+/// Overwrites the inputs `x` to `broadcast_mul` in `out` with their gradients,
+/// given the gradients `errors` of its outputs, and accumulates the gradient of
+/// the broadcast input into `grad`.
+pub unsafe fn backprop_broadcast_mul(
+    handle: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    grad: *mut f32,
+    errors: *const f32,
+    out: *mut f32,
+) {
+    // each column is summed by a single thread, so no atomics are needed
+    let mut column_grads = vec![0.0f32; width];
+    let grads = column_grads.as_mut_ptr() as usize;
+    let inp = inp as usize;
+    let errors = errors as usize;
+    let out = out as usize;
+
+    handle.split_workload(width, |_, col| {
+        let val = *(inp as *const f32).add(col % channels);
+        let mut sum = 0.0;
+
+        for idx in (col..size).step_by(width) {
+            let err = *(errors as *const f32).add(idx);
+            let this_out = (out as *mut f32).add(idx);
+
+            sum += err * *this_out;
+            *this_out = err * val;
+        }
+
+        *(grads as *mut f32).add(col) = sum;
+    });
+
+    for (col, sum) in column_grads.into_iter().enumerate() {
+        *grad.add(col % channels) += sum;
+    }
+}
+
+/// This calculates `out[i] = a[i % channels] * x[i]` for each tensor in the
+/// batch, where each tensor of `a` has `channels` elements, dividing `width`.
+pub unsafe fn hadamard(
+    handle: DeviceHandles,
+    batch_size: usize,
+    width: usize,
+    channels: usize,
+    a: *const f32,
+    x: *const f32,
+    out: *mut f32,
+) {
+    let a = a as usize;
+    let x = x as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_a = (a as *const f32).add(channels * idx);
+        let this_x = (x as *const f32).add(width * idx);
+        let this_out = (out as *mut f32).add(width * idx);
+
+        for i in 0..width {
+            *this_out.add(i) = *this_a.add(i % channels) * *this_x.add(i);
+        }
+    });
+}
+
+/// Overwrites the inputs `x` to `hadamard` in `out` with their gradients,
+/// given the gradients `errors` of its outputs, and writes the gradients
+/// of `a` to `a_grad`.
+pub unsafe fn backprop_hadamard(
+    handle: DeviceHandles,
+    batch_size: usize,
+    width: usize,
+    channels: usize,
+    a: *const f32,
+    a_grad: *mut f32,
+    errors: *const f32,
+    out: *mut f32,
+) {
+    let a = a as usize;
+    let a_grad = a_grad as usize;
+    let errors = errors as usize;
+    let out = out as usize;
+
+    handle.split_workload(batch_size, |_, idx| {
+        let this_a = (a as *const f32).add(channels * idx);
+        let this_a_grad = (a_grad as *mut f32).add(channels * idx);
+        let this_errors = (errors as *const f32).add(width * idx);
+        let this_out = (out as *mut f32).add(width * idx);
+
+        for c in 0..channels {
+            *this_a_grad.add(c) = 0.0;
+        }
+
+        for i in 0..width {
+            let err = *this_errors.add(i);
+            *this_a_grad.add(i % channels) += err * *this_out.add(i);
+            *this_out.add(i) = err * *this_a.add(i % channels);
+        }
+    });
+}
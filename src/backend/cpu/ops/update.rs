@@ -1,11 +1,6 @@
 use super::DeviceHandles;
 
-const B1: f32 = 0.9;
-const B2: f32 = 0.999;
-const B1P: f32 = 1.0 - B1;
-const B2P: f32 = 1.0 - B2;
 const EPSILON: f32 = 0.00000001;
-const MAX: f32 = 1.98;
 
 pub unsafe fn update_weights(
     handle: DeviceHandles,
@@ -13,6 +8,9 @@ pub unsafe fn update_weights(
     decay: f32,
     adj: f32,
     rate: f32,
+    max_weight: f32,
+    beta1: f32,
+    beta2: f32,
     network: *mut f32,
     momentum: *mut f32,
     velocity: *mut f32,
@@ -31,12 +29,196 @@ pub unsafe fn update_weights(
 
         let mut param = *p * decay;
 
-        *m = B1 * *m + B1P * grad;
-        *v = B2 * *v + B2P * grad * grad;
+        *m = beta1 * *m + (1.0 - beta1) * grad;
+        *v = beta2 * *v + (1.0 - beta2) * grad * grad;
 
         param -= rate * *m / ((*v).sqrt() + EPSILON);
-        param = param.clamp(-MAX, MAX);
+        param = param.clamp(-max_weight, max_weight);
 
         *p = param;
     });
 }
+
+pub unsafe fn update_weights_sgd(
+    handle: DeviceHandles,
+    network_size: usize,
+    decay: f32,
+    adj: f32,
+    rate: f32,
+    max_weight: f32,
+    beta: f32,
+    nesterov: bool,
+    network: *mut f32,
+    momentum: *mut f32,
+    gradients: *const f32,
+) {
+    let network = network as usize;
+    let momentum = momentum as usize;
+    let gradients = gradients as usize;
+
+    handle.split_workload(network_size, |_, idx| {
+        let grad = adj * *(gradients as *const f32).add(idx);
+        let p = (network as *mut f32).add(idx);
+        let m = (momentum as *mut f32).add(idx);
+
+        let mut param = *p * decay;
+
+        *m = beta * *m + grad;
+        let step = if nesterov { grad + beta * *m } else { *m };
+
+        param -= rate * step;
+        param = param.clamp(-max_weight, max_weight);
+
+        *p = param;
+    });
+}
+
+/// Rectified Adam, where `momentum_scale` is the bias correction of the
+/// first moment and `rectification` is zero while the variance of the
+/// adaptive learning rate is intractable, during which SGD with momentum is used.
+pub unsafe fn update_weights_radam(
+    handle: DeviceHandles,
+    network_size: usize,
+    decay: f32,
+    adj: f32,
+    rate: f32,
+    max_weight: f32,
+    beta1: f32,
+    beta2: f32,
+    momentum_scale: f32,
+    rectification: f32,
+    network: *mut f32,
+    momentum: *mut f32,
+    velocity: *mut f32,
+    gradients: *const f32,
+) {
+    let network = network as usize;
+    let momentum = momentum as usize;
+    let velocity = velocity as usize;
+    let gradients = gradients as usize;
+
+    handle.split_workload(network_size, |_, idx| {
+        let grad = adj * *(gradients as *const f32).add(idx);
+        let p = (network as *mut f32).add(idx);
+        let m = (momentum as *mut f32).add(idx);
+        let v = (velocity as *mut f32).add(idx);
+
+        let mut param = *p * decay;
+
+        *m = beta1 * *m + (1.0 - beta1) * grad;
+        *v = beta2 * *v + (1.0 - beta2) * grad * grad;
+
+        let step = momentum_scale * *m;
+        if rectification > 0.0 {
+            param -= rate * rectification * step / ((*v).sqrt() + EPSILON);
+        } else {
+            param -= rate * step;
+        }
+
+        param = param.clamp(-max_weight, max_weight);
+
+        *p = param;
+    });
+}
+
+/// Updates the moments as in Adam, and overwrites each gradient with
+/// the bias corrected Adam step plus weight decay, for LAMB.
+pub unsafe fn lamb_moments(
+    handle: DeviceHandles,
+    network_size: usize,
+    decay: f32,
+    adj: f32,
+    beta1: f32,
+    beta2: f32,
+    momentum_scale: f32,
+    velocity_scale: f32,
+    network: *const f32,
+    momentum: *mut f32,
+    velocity: *mut f32,
+    gradients: *mut f32,
+) {
+    let network = network as usize;
+    let momentum = momentum as usize;
+    let velocity = velocity as usize;
+    let gradients = gradients as usize;
+
+    handle.split_workload(network_size, |_, idx| {
+        let g = (gradients as *mut f32).add(idx);
+        let grad = adj * *g;
+        let param = *(network as *const f32).add(idx);
+        let m = (momentum as *mut f32).add(idx);
+        let v = (velocity as *mut f32).add(idx);
+
+        *m = beta1 * *m + (1.0 - beta1) * grad;
+        *v = beta2 * *v + (1.0 - beta2) * grad * grad;
+
+        *g = momentum_scale * *m / ((velocity_scale * *v).sqrt() + EPSILON) + decay * param;
+    });
+}
+
+pub unsafe fn lamb_apply(
+    handle: DeviceHandles,
+    size: usize,
+    rate: f32,
+    max_weight: f32,
+    network: *mut f32,
+    steps: *const f32,
+) {
+    let network = network as usize;
+    let steps = steps as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let p = (network as *mut f32).add(idx);
+        let step = *(steps as *const f32).add(idx);
+
+        *p = (*p - rate * step).clamp(-max_weight, max_weight);
+    });
+}
+
+/// Moves the slow weights a fraction `alpha` of the way
+/// towards the weights, then resets the weights to them.
+pub unsafe fn lookahead_sync(handle: DeviceHandles, size: usize, alpha: f32, network: *mut f32, slow: *mut f32) {
+    let network = network as usize;
+    let slow = slow as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let p = (network as *mut f32).add(idx);
+        let s = (slow as *mut f32).add(idx);
+
+        *s += alpha * (*p - *s);
+        *p = *s;
+    });
+}
+
+/// Subtracts from the gradients of each output neuron of a weight matrix,
+/// stored as `fan_in` contiguous blocks of `fan_out`, their mean.
+pub unsafe fn centralise_gradients(handle: DeviceHandles, fan_in: usize, fan_out: usize, gradients: *mut f32) {
+    let gradients = gradients as usize;
+
+    handle.split_workload(fan_out, |_, idx| {
+        let g = (gradients as *mut f32).add(idx);
+
+        let mut mean = 0.0;
+        for i in 0..fan_in {
+            mean += *g.add(i * fan_out);
+        }
+        mean /= fan_in as f32;
+
+        for i in 0..fan_in {
+            *g.add(i * fan_out) -= mean;
+        }
+    });
+}
+
+/// Moves `average` a fraction `alpha` of the way towards the weights.
+pub unsafe fn moving_average(handle: DeviceHandles, size: usize, alpha: f32, network: *const f32, average: *mut f32) {
+    let network = network as usize;
+    let average = average as usize;
+
+    handle.split_workload(size, |_, idx| {
+        let p = *(network as *const f32).add(idx);
+        let a = (average as *mut f32).add(idx);
+
+        *a += alpha * (p - *a);
+    });
+}
@@ -8,6 +8,23 @@ pub fn device_name() -> String {
     "CPU".to_string()
 }
 
+pub fn device_count() -> usize {
+    1
+}
+
+pub fn current_device() -> usize {
+    0
+}
+
+pub fn set_device(device: usize) {
+    assert_eq!(device, 0, "Device {device} does not exist!");
+}
+
+/// The energy used by the CPU is not measured.
+pub fn device_energy() -> Option<f64> {
+    None
+}
+
 pub fn device_synchronise() {}
 
 pub fn panic_if_device_error(_: &str) {}
@@ -69,3 +86,37 @@ pub unsafe fn copy_from_device<T: Copy>(dest: *mut T, src: *const T, amt: usize)
 pub unsafe fn copy_on_device<T: Copy>(dest: *mut T, src: *const T, amt: usize) {
     copy_to_device(dest, src, amt);
 }
+
+/// # Safety
+/// Pointers need to be valid and `amt` need to be valid.
+pub unsafe fn copy_between_devices<T: Copy>(dest: *mut T, _: usize, src: *const T, _: usize, amt: usize) {
+    copy_to_device(dest, src, amt);
+}
+
+/// Copies on the CPU are synchronous, so there is nothing to wait for.
+#[derive(Default)]
+pub struct CopyStream;
+
+impl CopyStream {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn synchronise(&self) {}
+}
+
+pub fn malloc_pinned<T>(num: usize) -> *mut T {
+    malloc(num)
+}
+
+/// # Safety
+/// Need to make sure not to double free.
+pub unsafe fn free_pinned<T>(ptr: *mut T, num: usize) {
+    free(ptr, num);
+}
+
+/// # Safety
+/// Pointers need to be valid and `amt` need to be valid.
+pub unsafe fn copy_to_device_async<T: Copy>(dest: *mut T, src: *const T, amt: usize, _: &CopyStream) {
+    copy_to_device(dest, src, amt);
+}
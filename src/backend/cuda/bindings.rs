@@ -10,6 +10,27 @@ use crate::loader::Feat;
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+/// Mirrors `ConvDesc` in `conv.cu`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ConvDesc {
+    pub inputChannels: usize,
+    pub outputChannels: usize,
+    pub inputHeight: usize,
+    pub inputWidth: usize,
+    pub outputHeight: usize,
+    pub outputWidth: usize,
+    pub kernelHeight: usize,
+    pub kernelWidth: usize,
+    pub strideHeight: usize,
+    pub strideWidth: usize,
+    pub paddingHeight: usize,
+    pub paddingWidth: usize,
+    pub dilationHeight: usize,
+    pub dilationWidth: usize,
+    pub groups: usize,
+}
+
 #[link(name = "kernels", kind = "static")]
 extern "C" {
     pub fn updateWeights(
@@ -17,26 +38,98 @@ extern "C" {
         decay: f32,
         adj: f32,
         rate: f32,
+        maxWeight: f32,
+        beta1: f32,
+        beta2: f32,
+        network: *mut f32,
+        momentum: *mut f32,
+        velocity: *mut f32,
+        gradients: *const f32,
+    );
+
+    pub fn updateWeightsSGD(
+        networkSize: usize,
+        decay: f32,
+        adj: f32,
+        rate: f32,
+        maxWeight: f32,
+        beta: f32,
+        nesterov: bool,
+        network: *mut f32,
+        momentum: *mut f32,
+        gradients: *const f32,
+    );
+
+    pub fn updateWeightsRAdam(
+        networkSize: usize,
+        decay: f32,
+        adj: f32,
+        rate: f32,
+        maxWeight: f32,
+        beta1: f32,
+        beta2: f32,
+        momentumScale: f32,
+        rectification: f32,
         network: *mut f32,
         momentum: *mut f32,
         velocity: *mut f32,
         gradients: *const f32,
     );
 
+    pub fn lambMoments(
+        networkSize: usize,
+        decay: f32,
+        adj: f32,
+        beta1: f32,
+        beta2: f32,
+        momentumScale: f32,
+        velocityScale: f32,
+        network: *const f32,
+        momentum: *mut f32,
+        velocity: *mut f32,
+        gradients: *mut f32,
+    );
+
+    pub fn lambApply(size: usize, rate: f32, maxWeight: f32, network: *mut f32, steps: *const f32);
+
+    pub fn lookaheadSync(size: usize, alpha: f32, network: *mut f32, slow: *mut f32);
+
+    pub fn centraliseGradients(fanIn: usize, fanOut: usize, gradients: *mut f32);
+
+    pub fn movingAverage(size: usize, alpha: f32, network: *const f32, average: *mut f32);
+
     pub fn sparseAffineForward(
         batchSize: usize,
         maxInputSize: usize,
         outputSize: usize,
+        oppSize: usize,
+        weights: *const f32,
+        biases: *const f32,
+        inputs: *const Feat,
+        outputs: *mut f32,
+    );
+
+    pub fn sparseAffineActivateForward(
+        batchSize: usize,
+        maxInputSize: usize,
+        outputSize: usize,
+        oppSize: usize,
         weights: *const f32,
         biases: *const f32,
         inputs: *const Feat,
         outputs: *mut f32,
+        activated: *mut f32,
+        min: f32,
+        max: f32,
+        slope: f32,
+        square: bool,
     );
 
     pub fn sparseAffineBackward(
         batchSize: usize,
         maxInputSize: usize,
         outputSize: usize,
+        oppSize: usize,
         weightsGrad: *mut f32,
         biasesGrad: *mut f32,
         inputs: *const Feat,
@@ -55,6 +148,21 @@ extern "C" {
         outputs: *mut f32,
     );
 
+    pub fn singleSparseAffineActivateForward(
+        batchSize: usize,
+        maxInputSize: usize,
+        outputSize: usize,
+        weights: *const f32,
+        biases: *const f32,
+        inputs: *const Feat,
+        outputs: *mut f32,
+        activated: *mut f32,
+        min: f32,
+        max: f32,
+        slope: f32,
+        square: bool,
+    );
+
     pub fn singleSparseAffineBackward(
         batchSize: usize,
         maxInputSize: usize,
@@ -79,10 +187,198 @@ extern "C" {
 
     pub fn backpropSCReLU(size: usize, inp: *const f32, out: *mut f32);
 
+    pub fn activateBoundedCReLU(size: usize, inp: *const f32, out: *mut f32, min: f32, max: f32);
+
+    pub fn activateBoundedSCReLU(size: usize, inp: *const f32, out: *mut f32, min: f32, max: f32);
+
+    pub fn backpropBoundedCReLU(size: usize, inp: *const f32, out: *mut f32, min: f32, max: f32);
+
+    pub fn backpropBoundedSCReLU(size: usize, inp: *const f32, out: *mut f32, min: f32, max: f32);
+
+    pub fn activateLeakyReLU(size: usize, inp: *const f32, out: *mut f32, slope: f32);
+
+    pub fn backpropLeakyReLU(size: usize, inp: *const f32, out: *mut f32, slope: f32);
+
+    pub fn activatePReLU(
+        size: usize,
+        width: usize,
+        channels: usize,
+        inp: *const f32,
+        slopes: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn backpropPReLU(
+        size: usize,
+        width: usize,
+        channels: usize,
+        slopes: *const f32,
+        slopesGrad: *mut f32,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
     pub fn sigmoidMPE(bufferSize: usize, outputs: *mut f32, results: *const f32, error: *mut f32, power: f32);
 
+    pub fn sigmoidMSEVariance(batchSize: usize, outputs: *mut f32, results: *const f32, error: *mut f32);
+
+    pub fn sigmoidFocal(
+        bufferSize: usize,
+        outputs: *mut f32,
+        results: *const f32,
+        error: *mut f32,
+        power: f32,
+        gamma: f32,
+    );
+
+    pub fn sigmoidMPEWeighted(
+        bufferSize: usize,
+        tensorSize: usize,
+        outputs: *mut f32,
+        results: *const f32,
+        weights: *const f32,
+        error: *mut f32,
+        power: f32,
+    );
+
+    pub fn sigmoidHuber(bufferSize: usize, outputs: *mut f32, results: *const f32, error: *mut f32, delta: f32);
+
+    pub fn softmaxCrossEntropy(
+        batchSize: usize,
+        tensorSize: usize,
+        outputs: *mut f32,
+        results: *const f32,
+        error: *mut f32,
+    );
+
+    pub fn softmax(batchSize: usize, tensorSize: usize, inp: *const f32, out: *mut f32, logarithm: bool);
+
+    pub fn backpropSoftmax(batchSize: usize, tensorSize: usize, inp: *const f32, out: *mut f32, logarithm: bool);
+
+    pub fn reduce(
+        batchSize: usize,
+        inpSize: usize,
+        outSize: usize,
+        len: usize,
+        outer: usize,
+        inner: usize,
+        maximum: bool,
+        scale: f32,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn backpropReduce(
+        batchSize: usize,
+        inpSize: usize,
+        outSize: usize,
+        len: usize,
+        outer: usize,
+        inner: usize,
+        maximum: bool,
+        scale: f32,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn layerNorm(
+        batchSize: usize,
+        tensorSize: usize,
+        gamma: *const f32,
+        beta: *const f32,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn backpropLayerNorm(
+        batchSize: usize,
+        tensorSize: usize,
+        gamma: *const f32,
+        gammaGrad: *mut f32,
+        betaGrad: *mut f32,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn batchNorm(
+        batchSize: usize,
+        tensorSize: usize,
+        gamma: *const f32,
+        beta: *const f32,
+        runningMean: *mut f32,
+        runningVar: *mut f32,
+        batchMean: *mut f32,
+        batchRstd: *mut f32,
+        inp: *const f32,
+        out: *mut f32,
+        momentum: f32,
+    );
+
+    pub fn batchNormInference(
+        batchSize: usize,
+        tensorSize: usize,
+        gamma: *const f32,
+        beta: *const f32,
+        runningMean: *const f32,
+        runningVar: *const f32,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn backpropBatchNorm(
+        batchSize: usize,
+        tensorSize: usize,
+        gamma: *const f32,
+        batchMean: *const f32,
+        batchRstd: *const f32,
+        gammaGrad: *mut f32,
+        betaGrad: *mut f32,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
     pub fn splatAdd(batchSize: usize, tensorSize: usize, inp: *const f32, out: *mut f32);
 
+    pub fn splatAddActivate(
+        batchSize: usize,
+        tensorSize: usize,
+        inp: *const f32,
+        pre: *mut f32,
+        out: *mut f32,
+        min: f32,
+        max: f32,
+        slope: f32,
+        square: bool,
+    );
+
+    pub fn broadcastAdd(size: usize, width: usize, channels: usize, inp: *const f32, out: *mut f32);
+
+    pub fn backpropBroadcastAdd(size: usize, width: usize, channels: usize, inp: *const f32, grad: *mut f32);
+
+    pub fn broadcastMul(size: usize, width: usize, channels: usize, inp: *const f32, x: *const f32, out: *mut f32);
+
+    pub fn hadamard(batchSize: usize, width: usize, channels: usize, a: *const f32, x: *const f32, out: *mut f32);
+
+    pub fn backpropHadamard(
+        batchSize: usize,
+        width: usize,
+        channels: usize,
+        a: *const f32,
+        aGrad: *mut f32,
+        errors: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn backpropBroadcastMul(
+        size: usize,
+        width: usize,
+        channels: usize,
+        inp: *const f32,
+        grad: *mut f32,
+        errors: *const f32,
+        out: *mut f32,
+    );
+
     pub fn activateDual(batchSize: usize, tensorSize: usize, inp: *const f32, out: *mut f32);
 
     pub fn backpropDual(batchSize: usize, tensorSize: usize, inp: *const f32, out: *mut f32);
@@ -105,5 +401,125 @@ extern "C" {
         out: *mut f32,
     );
 
+    pub fn gather(batchSize: usize, size: usize, buckets: *const u8, weights: *const f32, out: *mut f32);
+
+    pub fn backpropGather(batchSize: usize, size: usize, buckets: *const u8, errors: *const f32, weightsGrad: *mut f32);
+
+    pub fn convolution(
+        desc: ConvDesc,
+        batchSize: usize,
+        weights: *const f32,
+        biases: *const f32,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn backpropConvolution(
+        desc: ConvDesc,
+        batchSize: usize,
+        weights: *const f32,
+        weightsGrad: *mut f32,
+        biasesGrad: *mut f32,
+        errors: *const f32,
+        inp: *mut f32,
+    );
+
+    pub fn attention(
+        batchSize: usize,
+        len: usize,
+        dim: usize,
+        heads: usize,
+        headDim: usize,
+        weights: *const f32,
+        biases: *const f32,
+        inp: *const f32,
+        qkv: *mut f32,
+        attn: *mut f32,
+        out: *mut f32,
+    );
+
+    pub fn backpropAttention(
+        batchSize: usize,
+        len: usize,
+        dim: usize,
+        heads: usize,
+        headDim: usize,
+        weights: *const f32,
+        weightsGrad: *mut f32,
+        biasesGrad: *mut f32,
+        qkv: *const f32,
+        attn: *const f32,
+        qkvGrad: *mut f32,
+        attnGrad: *mut f32,
+        errors: *const f32,
+        inp: *mut f32,
+    );
+
     pub fn addTo(size: usize, inp: *const f32, out: *mut f32);
+
+    pub fn copyStrided(
+        batchSize: usize,
+        tensorSize: usize,
+        inpStride: usize,
+        inpOffset: usize,
+        outStride: usize,
+        outOffset: usize,
+        inp: *const f32,
+        out: *mut f32,
+    );
+
+    pub fn maskedScale(size: usize, mask: *const f32, inp: *const f32, out: *mut f32);
+
+    pub fn pairwiseMul(size: usize, stride: usize, min: f32, max: f32, inp: *const f32, out: *mut f32);
+
+    pub fn backpropPairwiseMul(size: usize, stride: usize, min: f32, max: f32, inp: *const f32, out: *mut f32);
+
+    pub fn sumOfSquares(size: usize, inp: *const f32, out: *mut f32);
+
+    pub fn sparseEllToCsr(
+        batchSize: usize,
+        maxInputSize: usize,
+        opp: bool,
+        inputs: *const Feat,
+        offsets: *mut i32,
+        cols: *mut i32,
+        values: *mut f32,
+    );
+
+    pub fn sparseCooToCsr(
+        nnz: usize,
+        rows: usize,
+        rowIndices: *const i32,
+        cols: *const i32,
+        values: *const f32,
+        offsets: *mut i32,
+        cursors: *mut i32,
+        csrCols: *mut i32,
+        csrValues: *mut f32,
+    );
+
+    pub fn sparseCsrToCoo(rows: usize, offsets: *const i32, rowIndices: *mut i32);
+
+    pub fn sparseCsrAffineForward(
+        rows: usize,
+        outputSize: usize,
+        weights: *const f32,
+        biases: *const f32,
+        offsets: *const i32,
+        cols: *const i32,
+        values: *const f32,
+        outputs: *mut f32,
+    );
+
+    pub fn sparseCooAffineBackward(
+        nnz: usize,
+        rows: usize,
+        outputSize: usize,
+        weightsGrad: *mut f32,
+        biasesGrad: *mut f32,
+        rowIndices: *const i32,
+        cols: *const i32,
+        values: *const f32,
+        errors: *const f32,
+    );
 }
@@ -5,7 +5,10 @@ use super::{
     bindings::{self, cublasOperation_t},
     DeviceHandles,
 };
-use crate::loader::Feat;
+use crate::{
+    loader::Feat,
+    tensor::{AttentionDescription, ConvolutionDescription},
+};
 
 use std::ffi::c_int;
 
@@ -91,7 +94,7 @@ pub unsafe fn reduce_add_mul_vector_vectort(
     batch_size: usize,
 ) {
     let alpha = 1.0;
-    let beta = 0.0;
+    let beta = 1.0;
 
     let m = m as c_int;
     let n = n as c_int;
@@ -117,6 +120,123 @@ pub unsafe fn reduce_add_mul_vector_vectort(
     }
 }
 
+pub unsafe fn grouped_mul_matrix_vector(
+    handle: DeviceHandles,
+    groups: usize,
+    m: usize,
+    n: usize,
+    a_ptr: *const f32,
+    x_ptr: *const f32,
+    y_ptr: *mut f32,
+    batch_size: usize,
+) {
+    let alpha = 1.0;
+    let beta = 0.0;
+
+    let (m, n, groups) = (m as i64, n as i64, groups as i64);
+
+    unsafe {
+        bindings::cublasSgemmStridedBatched(
+            *handle,
+            cublasOperation_t::CUBLAS_OP_N,
+            cublasOperation_t::CUBLAS_OP_N,
+            n as c_int,
+            batch_size as c_int,
+            m as c_int,
+            &alpha,
+            a_ptr,
+            n as c_int,
+            m * n,
+            x_ptr,
+            (groups * m) as c_int,
+            m,
+            &beta,
+            y_ptr,
+            (groups * n) as c_int,
+            n,
+            groups as c_int,
+        );
+    }
+}
+
+pub unsafe fn grouped_mul_matrixt_vector(
+    handle: DeviceHandles,
+    groups: usize,
+    m: usize,
+    n: usize,
+    a_ptr: *const f32,
+    y_ptr: *const f32,
+    x_ptr: *mut f32,
+    batch_size: usize,
+) {
+    let alpha = 1.0;
+    let beta = 0.0;
+
+    let (m, n, groups) = (m as i64, n as i64, groups as i64);
+
+    unsafe {
+        bindings::cublasSgemmStridedBatched(
+            *handle,
+            cublasOperation_t::CUBLAS_OP_T,
+            cublasOperation_t::CUBLAS_OP_N,
+            m as c_int,
+            batch_size as c_int,
+            n as c_int,
+            &alpha,
+            a_ptr,
+            n as c_int,
+            m * n,
+            y_ptr,
+            (groups * n) as c_int,
+            n,
+            &beta,
+            x_ptr,
+            (groups * m) as c_int,
+            m,
+            groups as c_int,
+        );
+    }
+}
+
+pub unsafe fn grouped_reduce_add_mul_vector_vectort(
+    handle: DeviceHandles,
+    groups: usize,
+    m: usize,
+    n: usize,
+    y_ptr: *const f32,
+    x_ptr: *const f32,
+    a_ptr: *mut f32,
+    batch_size: usize,
+) {
+    let alpha = 1.0;
+    let beta = 1.0;
+
+    let (m, n, groups) = (m as i64, n as i64, groups as i64);
+
+    unsafe {
+        bindings::cublasSgemmStridedBatched(
+            *handle,
+            cublasOperation_t::CUBLAS_OP_N,
+            cublasOperation_t::CUBLAS_OP_T,
+            n as c_int,
+            m as c_int,
+            batch_size as c_int,
+            &alpha,
+            y_ptr,
+            (groups * n) as c_int,
+            n,
+            x_ptr,
+            (groups * m) as c_int,
+            m,
+            &beta,
+            a_ptr,
+            n as c_int,
+            m * n,
+            groups as c_int,
+        );
+    }
+}
+
 pub unsafe fn reduce_add(
     handle: DeviceHandles,
     ones: *const f32,
@@ -126,7 +246,7 @@ pub unsafe fn reduce_add(
     out: *mut f32,
 ) {
     let alpha = 1.0;
-    let beta = 0.0;
+    let beta = 1.0;
 
     let m = batch_size as c_int;
     let n = out_size as c_int;
@@ -158,6 +278,83 @@ pub unsafe fn backprop_screlu(_: DeviceHandles, size: usize, inp: *const f32, ou
     bindings::backpropSCReLU(size, inp, out);
 }
 
+pub unsafe fn activate_bounded_crelu(
+    _: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+) {
+    bindings::activateBoundedCReLU(size, inp, out, min, max);
+}
+
+pub unsafe fn activate_bounded_screlu(
+    _: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+) {
+    bindings::activateBoundedSCReLU(size, inp, out, min, max);
+}
+
+pub unsafe fn backprop_bounded_crelu(
+    _: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+) {
+    bindings::backpropBoundedCReLU(size, inp, out, min, max);
+}
+
+pub unsafe fn backprop_bounded_screlu(
+    _: DeviceHandles,
+    size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+) {
+    bindings::backpropBoundedSCReLU(size, inp, out, min, max);
+}
+
+pub unsafe fn activate_leaky_relu(_: DeviceHandles, size: usize, inp: *const f32, out: *mut f32, slope: f32) {
+    bindings::activateLeakyReLU(size, inp, out, slope);
+}
+
+pub unsafe fn backprop_leaky_relu(_: DeviceHandles, size: usize, inp: *const f32, out: *mut f32, slope: f32) {
+    bindings::backpropLeakyReLU(size, inp, out, slope);
+}
+
+pub unsafe fn activate_prelu(
+    _: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    slopes: *const f32,
+    out: *mut f32,
+) {
+    bindings::activatePReLU(size, width, channels, inp, slopes, out);
+}
+
+pub unsafe fn backprop_prelu(
+    _: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    slopes: *const f32,
+    slopes_grad: *mut f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::backpropPReLU(size, width, channels, slopes, slopes_grad, inp, out);
+}
+
 pub unsafe fn sigmoid_mpe(
     _: DeviceHandles,
     buffer_size: usize,
@@ -169,12 +366,217 @@ pub unsafe fn sigmoid_mpe(
     bindings::sigmoidMPE(buffer_size, outputs, results, error, power);
 }
 
+pub unsafe fn sigmoid_mse_variance(
+    _: DeviceHandles,
+    batch_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    error: *mut f32,
+) {
+    bindings::sigmoidMSEVariance(batch_size, outputs, results, error);
+}
+
+pub unsafe fn sigmoid_focal(
+    _: DeviceHandles,
+    buffer_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    error: *mut f32,
+    power: f32,
+    gamma: f32,
+) {
+    bindings::sigmoidFocal(buffer_size, outputs, results, error, power, gamma);
+}
+
+pub unsafe fn sigmoid_mpe_weighted(
+    _: DeviceHandles,
+    buffer_size: usize,
+    tensor_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    weights: *const f32,
+    error: *mut f32,
+    power: f32,
+) {
+    bindings::sigmoidMPEWeighted(buffer_size, tensor_size, outputs, results, weights, error, power);
+}
+
+pub unsafe fn sigmoid_huber(
+    _: DeviceHandles,
+    buffer_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    error: *mut f32,
+    delta: f32,
+) {
+    bindings::sigmoidHuber(buffer_size, outputs, results, error, delta);
+}
+
+pub unsafe fn softmax_crossentropy(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    outputs: *mut f32,
+    results: *const f32,
+    error: *mut f32,
+) {
+    bindings::softmaxCrossEntropy(batch_size, tensor_size, outputs, results, error);
+}
+
+pub unsafe fn softmax(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    log: bool,
+) {
+    bindings::softmax(batch_size, tensor_size, inp, out, log);
+}
+
+pub unsafe fn backprop_softmax(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    inp: *const f32,
+    out: *mut f32,
+    log: bool,
+) {
+    bindings::backpropSoftmax(batch_size, tensor_size, inp, out, log);
+}
+
+pub unsafe fn reduce(
+    _: DeviceHandles,
+    batch_size: usize,
+    inp_size: usize,
+    out_size: usize,
+    len: usize,
+    outer: usize,
+    inner: usize,
+    max: bool,
+    scale: f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::reduce(batch_size, inp_size, out_size, len, outer, inner, max, scale, inp, out);
+}
+
+pub unsafe fn backprop_reduce(
+    _: DeviceHandles,
+    batch_size: usize,
+    inp_size: usize,
+    out_size: usize,
+    len: usize,
+    outer: usize,
+    inner: usize,
+    max: bool,
+    scale: f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::backpropReduce(batch_size, inp_size, out_size, len, outer, inner, max, scale, inp, out);
+}
+
+pub unsafe fn layer_norm(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    beta: *const f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::layerNorm(batch_size, tensor_size, gamma, beta, inp, out);
+}
+
+pub unsafe fn backprop_layer_norm(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    gamma_grad: *mut f32,
+    beta_grad: *mut f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::backpropLayerNorm(batch_size, tensor_size, gamma, gamma_grad, beta_grad, inp, out);
+}
+
+pub unsafe fn batch_norm(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    beta: *const f32,
+    running_mean: *mut f32,
+    running_var: *mut f32,
+    batch_mean: *mut f32,
+    batch_rstd: *mut f32,
+    inp: *const f32,
+    out: *mut f32,
+    momentum: f32,
+) {
+    bindings::batchNorm(
+        batch_size,
+        tensor_size,
+        gamma,
+        beta,
+        running_mean,
+        running_var,
+        batch_mean,
+        batch_rstd,
+        inp,
+        out,
+        momentum,
+    );
+}
+
+pub unsafe fn batch_norm_inference(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    beta: *const f32,
+    running_mean: *const f32,
+    running_var: *const f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::batchNormInference(batch_size, tensor_size, gamma, beta, running_mean, running_var, inp, out);
+}
+
+pub unsafe fn backprop_batch_norm(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    gamma: *const f32,
+    batch_mean: *const f32,
+    batch_rstd: *const f32,
+    gamma_grad: *mut f32,
+    beta_grad: *mut f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::backpropBatchNorm(
+        batch_size,
+        tensor_size,
+        gamma,
+        batch_mean,
+        batch_rstd,
+        gamma_grad,
+        beta_grad,
+        inp,
+        out,
+    );
+}
+
 pub unsafe fn sparse_affine_backward(
     _: DeviceHandles,
     batch_size: usize,
     max_input_size: usize,
     _: usize,
     output_size: usize,
+    opp_size: usize,
     weights_grad: *mut f32,
     biases_grad: *mut f32,
     inputs: *const Feat,
@@ -186,6 +588,7 @@ pub unsafe fn sparse_affine_backward(
         batch_size,
         max_input_size,
         output_size,
+        opp_size,
         weights_grad,
         biases_grad,
         inputs,
@@ -200,12 +603,46 @@ pub unsafe fn sparse_affine_forward(
     batch_size: usize,
     max_input_size: usize,
     output_size: usize,
+    opp_size: usize,
     weights: *const f32,
     biases: *const f32,
     inputs: *const Feat,
     outputs: *mut f32,
 ) {
-    bindings::sparseAffineForward(batch_size, max_input_size, output_size, weights, biases, inputs, outputs);
+    bindings::sparseAffineForward(batch_size, max_input_size, output_size, opp_size, weights, biases, inputs, outputs);
+}
+
+pub unsafe fn sparse_affine_activate_forward(
+    _: DeviceHandles,
+    batch_size: usize,
+    max_input_size: usize,
+    output_size: usize,
+    opp_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    inputs: *const Feat,
+    outputs: *mut f32,
+    activated: *mut f32,
+    min: f32,
+    max: f32,
+    slope: f32,
+    square: bool,
+) {
+    bindings::sparseAffineActivateForward(
+        batch_size,
+        max_input_size,
+        output_size,
+        opp_size,
+        weights,
+        biases,
+        inputs,
+        outputs,
+        activated,
+        min,
+        max,
+        slope,
+        square,
+    );
 }
 
 pub unsafe fn single_sparse_affine_backward(
@@ -247,22 +684,267 @@ pub unsafe fn single_sparse_affine_forward(
     bindings::singleSparseAffineForward(batch_size, max_input_size, output_size, weights, biases, inputs, outputs);
 }
 
+pub unsafe fn single_sparse_affine_activate_forward(
+    _: DeviceHandles,
+    batch_size: usize,
+    max_input_size: usize,
+    output_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    inputs: *const Feat,
+    outputs: *mut f32,
+    activated: *mut f32,
+    min: f32,
+    max: f32,
+    slope: f32,
+    square: bool,
+) {
+    bindings::singleSparseAffineActivateForward(
+        batch_size,
+        max_input_size,
+        output_size,
+        weights,
+        biases,
+        inputs,
+        outputs,
+        activated,
+        min,
+        max,
+        slope,
+        square,
+    );
+}
+
 pub unsafe fn splat_add(_: DeviceHandles, batch_size: usize, tensor_size: usize, inp: *const f32, out: *mut f32) {
     bindings::splatAdd(batch_size, tensor_size, inp, out);
 }
 
+pub unsafe fn splat_add_activate(
+    _: DeviceHandles,
+    batch_size: usize,
+    tensor_size: usize,
+    inp: *const f32,
+    pre: *mut f32,
+    out: *mut f32,
+    min: f32,
+    max: f32,
+    slope: f32,
+    square: bool,
+) {
+    bindings::splatAddActivate(batch_size, tensor_size, inp, pre, out, min, max, slope, square);
+}
+
+pub unsafe fn broadcast_add(
+    _: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::broadcastAdd(size, width, channels, inp, out);
+}
+
+pub unsafe fn backprop_broadcast_add(
+    _: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    grad: *mut f32,
+) {
+    bindings::backpropBroadcastAdd(size, width, channels, inp, grad);
+}
+
+pub unsafe fn broadcast_mul(
+    _: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    x: *const f32,
+    out: *mut f32,
+) {
+    bindings::broadcastMul(size, width, channels, inp, x, out);
+}
+
+pub unsafe fn backprop_broadcast_mul(
+    _: DeviceHandles,
+    size: usize,
+    width: usize,
+    channels: usize,
+    inp: *const f32,
+    grad: *mut f32,
+    errors: *const f32,
+    out: *mut f32,
+) {
+    bindings::backpropBroadcastMul(size, width, channels, inp, grad, errors, out);
+}
+
+pub unsafe fn hadamard(
+    _: DeviceHandles,
+    batch_size: usize,
+    width: usize,
+    channels: usize,
+    a: *const f32,
+    x: *const f32,
+    out: *mut f32,
+) {
+    bindings::hadamard(batch_size, width, channels, a, x, out);
+}
+
+pub unsafe fn backprop_hadamard(
+    _: DeviceHandles,
+    batch_size: usize,
+    width: usize,
+    channels: usize,
+    a: *const f32,
+    a_grad: *mut f32,
+    errors: *const f32,
+    out: *mut f32,
+) {
+    bindings::backpropHadamard(batch_size, width, channels, a, a_grad, errors, out);
+}
+
 pub unsafe fn update_weights(
     _: DeviceHandles,
     network_size: usize,
     decay: f32,
     adj: f32,
     rate: f32,
+    max_weight: f32,
+    beta1: f32,
+    beta2: f32,
     network: *mut f32,
     momentum: *mut f32,
     velocity: *mut f32,
     gradients: *const f32,
 ) {
-    bindings::updateWeights(network_size, decay, adj, rate, network, momentum, velocity, gradients);
+    bindings::updateWeights(
+        network_size,
+        decay,
+        adj,
+        rate,
+        max_weight,
+        beta1,
+        beta2,
+        network,
+        momentum,
+        velocity,
+        gradients,
+    );
+}
+
+pub unsafe fn update_weights_sgd(
+    _: DeviceHandles,
+    network_size: usize,
+    decay: f32,
+    adj: f32,
+    rate: f32,
+    max_weight: f32,
+    beta: f32,
+    nesterov: bool,
+    network: *mut f32,
+    momentum: *mut f32,
+    gradients: *const f32,
+) {
+    bindings::updateWeightsSGD(
+        network_size,
+        decay,
+        adj,
+        rate,
+        max_weight,
+        beta,
+        nesterov,
+        network,
+        momentum,
+        gradients,
+    );
+}
+
+pub unsafe fn update_weights_radam(
+    _: DeviceHandles,
+    network_size: usize,
+    decay: f32,
+    adj: f32,
+    rate: f32,
+    max_weight: f32,
+    beta1: f32,
+    beta2: f32,
+    momentum_scale: f32,
+    rectification: f32,
+    network: *mut f32,
+    momentum: *mut f32,
+    velocity: *mut f32,
+    gradients: *const f32,
+) {
+    bindings::updateWeightsRAdam(
+        network_size,
+        decay,
+        adj,
+        rate,
+        max_weight,
+        beta1,
+        beta2,
+        momentum_scale,
+        rectification,
+        network,
+        momentum,
+        velocity,
+        gradients,
+    );
+}
+
+pub unsafe fn lamb_moments(
+    _: DeviceHandles,
+    network_size: usize,
+    decay: f32,
+    adj: f32,
+    beta1: f32,
+    beta2: f32,
+    momentum_scale: f32,
+    velocity_scale: f32,
+    network: *const f32,
+    momentum: *mut f32,
+    velocity: *mut f32,
+    gradients: *mut f32,
+) {
+    bindings::lambMoments(
+        network_size,
+        decay,
+        adj,
+        beta1,
+        beta2,
+        momentum_scale,
+        velocity_scale,
+        network,
+        momentum,
+        velocity,
+        gradients,
+    );
+}
+
+pub unsafe fn lamb_apply(
+    _: DeviceHandles,
+    size: usize,
+    rate: f32,
+    max_weight: f32,
+    network: *mut f32,
+    steps: *const f32,
+) {
+    bindings::lambApply(size, rate, max_weight, network, steps);
+}
+
+pub unsafe fn lookahead_sync(_: DeviceHandles, size: usize, alpha: f32, network: *mut f32, slow: *mut f32) {
+    bindings::lookaheadSync(size, alpha, network, slow);
+}
+
+pub unsafe fn centralise_gradients(_: DeviceHandles, fan_in: usize, fan_out: usize, gradients: *mut f32) {
+    bindings::centraliseGradients(fan_in, fan_out, gradients);
+}
+
+pub unsafe fn moving_average(_: DeviceHandles, size: usize, alpha: f32, network: *const f32, average: *mut f32) {
+    bindings::movingAverage(size, alpha, network, average);
 }
 
 pub unsafe fn select(
@@ -289,6 +971,241 @@ pub unsafe fn select_backprop(
     bindings::selectBackprop(batch_size, input_size, output_size, buckets, inp, out);
 }
 
+pub unsafe fn gather(
+    _: DeviceHandles,
+    batch_size: usize,
+    size: usize,
+    buckets: *const u8,
+    weights: *const f32,
+    out: *mut f32,
+) {
+    bindings::gather(batch_size, size, buckets, weights, out);
+}
+
+pub unsafe fn backprop_gather(
+    _: DeviceHandles,
+    batch_size: usize,
+    size: usize,
+    buckets: *const u8,
+    errors: *const f32,
+    weights_grad: *mut f32,
+) {
+    bindings::backpropGather(batch_size, size, buckets, errors, weights_grad);
+}
+
+fn conv_desc(desc: &ConvolutionDescription) -> bindings::ConvDesc {
+    let (output_height, output_width) = desc.output_shape();
+
+    bindings::ConvDesc {
+        inputChannels: desc.input_channels,
+        outputChannels: desc.output_channels,
+        inputHeight: desc.input_shape.0,
+        inputWidth: desc.input_shape.1,
+        outputHeight: output_height,
+        outputWidth: output_width,
+        kernelHeight: desc.kernel.0,
+        kernelWidth: desc.kernel.1,
+        strideHeight: desc.stride.0,
+        strideWidth: desc.stride.1,
+        paddingHeight: desc.padding.0,
+        paddingWidth: desc.padding.1,
+        dilationHeight: desc.dilation.0,
+        dilationWidth: desc.dilation.1,
+        groups: desc.groups,
+    }
+}
+
+pub unsafe fn convolution(
+    _: DeviceHandles,
+    desc: &ConvolutionDescription,
+    batch_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::convolution(conv_desc(desc), batch_size, weights, biases, inp, out);
+}
+
+pub unsafe fn backprop_convolution(
+    _: DeviceHandles,
+    desc: &ConvolutionDescription,
+    batch_size: usize,
+    weights: *const f32,
+    weights_grad: *mut f32,
+    biases_grad: *mut f32,
+    errors: *const f32,
+    inp: *mut f32,
+) {
+    bindings::backpropConvolution(conv_desc(desc), batch_size, weights, weights_grad, biases_grad, errors, inp);
+}
+
+pub unsafe fn attention(
+    _: DeviceHandles,
+    desc: &AttentionDescription,
+    batch_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    inp: *const f32,
+    qkv: *mut f32,
+    attn: *mut f32,
+    out: *mut f32,
+) {
+    let AttentionDescription { len, dim, heads, head_dim } = *desc;
+    bindings::attention(batch_size, len, dim, heads, head_dim, weights, biases, inp, qkv, attn, out);
+}
+
+pub unsafe fn backprop_attention(
+    _: DeviceHandles,
+    desc: &AttentionDescription,
+    batch_size: usize,
+    weights: *const f32,
+    weights_grad: *mut f32,
+    biases_grad: *mut f32,
+    qkv: *const f32,
+    attn: *const f32,
+    qkv_grad: *mut f32,
+    attn_grad: *mut f32,
+    errors: *const f32,
+    inp: *mut f32,
+) {
+    let AttentionDescription { len, dim, heads, head_dim } = *desc;
+    bindings::backpropAttention(
+        batch_size,
+        len,
+        dim,
+        heads,
+        head_dim,
+        weights,
+        weights_grad,
+        biases_grad,
+        qkv,
+        attn,
+        qkv_grad,
+        attn_grad,
+        errors,
+        inp,
+    );
+}
+
 pub unsafe fn add_to(_: DeviceHandles, size: usize, inp: *const f32, out: *mut f32) {
     bindings::addTo(size, inp, out);
 }
+
+pub unsafe fn copy_strided(
+    _: DeviceHandles,
+    batch_size: usize,
+    size: usize,
+    inp_stride: usize,
+    inp_offset: usize,
+    out_stride: usize,
+    out_offset: usize,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::copyStrided(batch_size, size, inp_stride, inp_offset, out_stride, out_offset, inp, out);
+}
+
+pub unsafe fn masked_scale(_: DeviceHandles, size: usize, mask: *const f32, inp: *const f32, out: *mut f32) {
+    bindings::maskedScale(size, mask, inp, out);
+}
+
+pub unsafe fn pairwise_mul(
+    _: DeviceHandles,
+    size: usize,
+    stride: usize,
+    min: f32,
+    max: f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::pairwiseMul(size, stride, min, max, inp, out);
+}
+
+pub unsafe fn backprop_pairwise_mul(
+    _: DeviceHandles,
+    size: usize,
+    stride: usize,
+    min: f32,
+    max: f32,
+    inp: *const f32,
+    out: *mut f32,
+) {
+    bindings::backpropPairwiseMul(size, stride, min, max, inp, out);
+}
+
+pub unsafe fn sum_of_squares(_: DeviceHandles, size: usize, inp: *const f32, out: *mut f32) {
+    bindings::sumOfSquares(size, inp, out);
+}
+
+pub unsafe fn sparse_ell_to_csr(
+    _: DeviceHandles,
+    batch_size: usize,
+    max_input_size: usize,
+    opp: bool,
+    inputs: *const Feat,
+    offsets: *mut i32,
+    cols: *mut i32,
+    values: *mut f32,
+) {
+    bindings::sparseEllToCsr(batch_size, max_input_size, opp, inputs, offsets, cols, values);
+}
+
+/// The order of the entries within each row is unspecified.
+pub unsafe fn sparse_coo_to_csr(
+    _: DeviceHandles,
+    nnz: usize,
+    rows: usize,
+    row_indices: *const i32,
+    cols: *const i32,
+    values: *const f32,
+    offsets: *mut i32,
+    cursors: *mut i32,
+    csr_cols: *mut i32,
+    csr_values: *mut f32,
+) {
+    bindings::sparseCooToCsr(nnz, rows, row_indices, cols, values, offsets, cursors, csr_cols, csr_values);
+}
+
+pub unsafe fn sparse_csr_to_coo(_: DeviceHandles, rows: usize, offsets: *const i32, row_indices: *mut i32) {
+    bindings::sparseCsrToCoo(rows, offsets, row_indices);
+}
+
+pub unsafe fn sparse_csr_affine_forward(
+    _: DeviceHandles,
+    rows: usize,
+    output_size: usize,
+    weights: *const f32,
+    biases: *const f32,
+    offsets: *const i32,
+    cols: *const i32,
+    values: *const f32,
+    outputs: *mut f32,
+) {
+    bindings::sparseCsrAffineForward(rows, output_size, weights, biases, offsets, cols, values, outputs);
+}
+
+pub unsafe fn sparse_coo_affine_backward(
+    _: DeviceHandles,
+    nnz: usize,
+    rows: usize,
+    output_size: usize,
+    weights_grad: *mut f32,
+    biases_grad: *mut f32,
+    row_indices: *const i32,
+    cols: *const i32,
+    values: *const f32,
+    errors: *const f32,
+) {
+    bindings::sparseCooAffineBackward(
+        nnz,
+        rows,
+        output_size,
+        weights_grad,
+        biases_grad,
+        row_indices,
+        cols,
+        values,
+        errors,
+    );
+}
@@ -1,6 +1,9 @@
 use super::bindings::{
-    cudaDeviceSynchronize, cudaError, cudaFree, cudaGetDeviceCount, cudaGetDeviceProperties_v2, cudaGetLastError,
-    cudaMalloc, cudaMemcpy, cudaMemcpyKind, cudaMemset,
+    cudaDeviceGetPCIBusId, cudaDeviceSynchronize, cudaError, cudaFree, cudaFreeHost, cudaGetDevice, cudaGetDeviceCount,
+    cudaGetDeviceProperties_v2, cudaGetLastError, cudaMalloc, cudaMallocHost, cudaMemcpy, cudaMemcpyAsync,
+    cudaMemcpyKind, cudaMemcpyPeer, cudaMemset, cudaSetDevice, cudaStreamCreateWithFlags, cudaStreamDestroy,
+    cudaStreamNonBlocking, cudaStreamSynchronize, cudaStream_t, nvmlDeviceGetHandleByPciBusId_v2,
+    nvmlDeviceGetTotalEnergyConsumption, nvmlInit_v2, nvmlReturn_t, nvmlShutdown,
 };
 use crate::util;
 use std::ffi::c_void;
@@ -24,7 +27,7 @@ pub fn device_name() -> String {
     catch!(cudaGetDeviceCount(&mut num));
     assert!(num >= 1);
     let mut props = util::boxed_and_zeroed();
-    catch!(cudaGetDeviceProperties_v2(&mut *props, 0));
+    catch!(cudaGetDeviceProperties_v2(&mut *props, current_device() as i32));
 
     let mut buf = [0u8; 256];
 
@@ -37,6 +40,51 @@ pub fn device_name() -> String {
     my_str.to_string()
 }
 
+pub fn device_count() -> usize {
+    let mut num = 0;
+    catch!(cudaGetDeviceCount(&mut num));
+    num as usize
+}
+
+pub fn current_device() -> usize {
+    let mut device = 0;
+    catch!(cudaGetDevice(&mut device));
+    device as usize
+}
+
+/// Energy used by the current device since the driver was loaded, in joules,
+/// as reported by NVML, which needs a Volta or newer GPU. The CUDA and NVML
+/// indices of a device can differ, so it is looked up by its PCI bus id.
+pub fn device_energy() -> Option<f64> {
+    let mut bus_id = [0; 32];
+    let err = unsafe { cudaDeviceGetPCIBusId(bus_id.as_mut_ptr(), bus_id.len() as i32, current_device() as i32) };
+    if err != cudaError::cudaSuccess {
+        return None;
+    }
+
+    unsafe {
+        if nvmlInit_v2() != nvmlReturn_t::NVML_SUCCESS {
+            return None;
+        }
+
+        let mut device = std::ptr::null_mut();
+        let mut millijoules = 0;
+        let found = nvmlDeviceGetHandleByPciBusId_v2(bus_id.as_ptr(), &mut device) == nvmlReturn_t::NVML_SUCCESS
+            && nvmlDeviceGetTotalEnergyConsumption(device, &mut millijoules) == nvmlReturn_t::NVML_SUCCESS;
+
+        let _ = nvmlShutdown();
+
+        found.then_some(millijoules as f64 / 1000.0)
+    }
+}
+
+/// Buffers are allocated and kernels launched on the current device
+/// of the calling thread, which is device 0 unless set otherwise.
+pub fn set_device(device: usize) {
+    assert!(device < device_count(), "Device {device} does not exist!");
+    catch!(cudaSetDevice(device as i32), "set device");
+}
+
 pub fn device_synchronise() {
     catch!(cudaDeviceSynchronize());
 }
@@ -107,3 +155,72 @@ pub unsafe fn copy_on_device<T>(dest: *mut T, src: *const T, amt: usize) {
     );
     catch!(cudaDeviceSynchronize());
 }
+
+/// # Safety
+/// Pointers need to be valid on their respective devices and `amt` need to be valid.
+pub unsafe fn copy_between_devices<T>(dest: *mut T, dest_device: usize, src: *const T, src_device: usize, amt: usize) {
+    catch!(
+        cudaMemcpyPeer(dest.cast(), dest_device as i32, src.cast(), src_device as i32, amt * std::mem::size_of::<T>()),
+        "memcpy peer"
+    );
+    catch!(cudaDeviceSynchronize());
+}
+
+/// Stream for copies to the device that run alongside the kernels launched
+/// on the default stream, as it is not synchronised with the default stream.
+pub struct CopyStream(cudaStream_t);
+
+impl Default for CopyStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for CopyStream {
+    fn drop(&mut self) {
+        catch!(cudaStreamDestroy(self.0), "stream destroy");
+    }
+}
+
+impl CopyStream {
+    pub fn new() -> Self {
+        let mut stream: cudaStream_t = std::ptr::null_mut();
+        catch!(cudaStreamCreateWithFlags(&mut stream, cudaStreamNonBlocking), "stream create");
+        Self(stream)
+    }
+
+    /// Waits for every copy issued on the stream, without waiting for any kernels.
+    pub fn synchronise(&self) {
+        catch!(cudaStreamSynchronize(self.0), "stream synchronise");
+    }
+}
+
+/// Page-locked host memory, which copies to the device can run asynchronously from.
+pub fn malloc_pinned<T>(num: usize) -> *mut T {
+    let mut ptr = std::ptr::null_mut::<T>();
+    catch!(cudaMallocHost((&mut ptr as *mut *mut T).cast(), num * std::mem::size_of::<T>()), "malloc host");
+    ptr
+}
+
+/// # Safety
+/// Need to make sure not to double free.
+pub unsafe fn free_pinned<T>(ptr: *mut T, _: usize) {
+    catch!(cudaFreeHost(ptr.cast()), "free host");
+}
+
+/// # Safety
+/// Pointers need to be valid and `amt` need to be valid. The copy is only
+/// asynchronous if `src` is pinned, and `src` must not change until `stream`
+/// is synchronised.
+pub unsafe fn copy_to_device_async<T>(dest: *mut T, src: *const T, amt: usize, stream: &CopyStream) {
+    catch!(
+        cudaMemcpyAsync(
+            dest.cast(),
+            src.cast(),
+            amt * std::mem::size_of::<T>(),
+            cudaMemcpyKind::cudaMemcpyHostToDevice,
+            stream.0
+        ),
+        "memcpy async"
+    );
+}
@@ -0,0 +1,310 @@
+/*
+Multi-head self-attention over a sequence of `len` tokens of `dim` features.
+
+Per tensor, `qkv` holds the queries, keys and values of each token in turn,
+and `attn` the attention weights of each head, by query then key. The weights
+of the projections are stored one output row of `dim` at a time.
+*/
+#include <cuda.h>
+#include <cuda_runtime.h>
+
+constexpr size_t threadsPerBlock = static_cast<size_t>(1024);
+
+__global__ void projectQKVKernel(
+    const size_t batchSize,
+    const size_t len,
+    const size_t dim,
+    const size_t rows,
+    const float* weights,
+    const float* biases,
+    const float* inp,
+    float* qkv)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * len * rows)
+        return;
+
+    const size_t token = i / rows;
+    const size_t row = i % rows;
+    const float* x = inp + token * dim;
+
+    float sum = biases[row];
+    for (size_t f = 0; f < dim; f++)
+        sum += weights[row * dim + f] * x[f];
+
+    qkv[i] = sum;
+}
+
+// one thread per query of each head
+__global__ void attendKernel(
+    const size_t batchSize,
+    const size_t len,
+    const size_t heads,
+    const size_t headDim,
+    const float* qkv,
+    float* attn,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * heads * len)
+        return;
+
+    const size_t idx = i / (heads * len);
+    const size_t h = (i / len) % heads;
+    const size_t query = i % len;
+    const size_t inner = heads * headDim;
+    const float scale = rsqrtf(static_cast<float>(headDim));
+
+    const float* thisQKV = qkv + idx * len * 3 * inner;
+    const float* q = thisQKV + query * 3 * inner + h * headDim;
+    float* scores = attn + i * len;
+
+    float max = -INFINITY;
+    for (size_t j = 0; j < len; j++)
+    {
+        const float* k = thisQKV + j * 3 * inner + inner + h * headDim;
+        float score = 0.0F;
+        for (size_t d = 0; d < headDim; d++)
+            score += q[d] * k[d];
+
+        scores[j] = score * scale;
+        max = fmaxf(max, score * scale);
+    }
+
+    float total = 0.0F;
+    for (size_t j = 0; j < len; j++)
+    {
+        scores[j] = expf(scores[j] - max);
+        total += scores[j];
+    }
+
+    for (size_t j = 0; j < len; j++)
+        scores[j] /= total;
+
+    float* thisOut = out + (idx * len + query) * inner + h * headDim;
+    for (size_t d = 0; d < headDim; d++)
+    {
+        float sum = 0.0F;
+        for (size_t j = 0; j < len; j++)
+            sum += scores[j] * thisQKV[j * 3 * inner + 2 * inner + h * headDim + d];
+
+        thisOut[d] = sum;
+    }
+}
+
+// one thread per query of each head, writing the gradient of the
+// pre-softmax scores to `attnGrad` and of the query to `qkvGrad`
+__global__ void backpropAttendQueriesKernel(
+    const size_t batchSize,
+    const size_t len,
+    const size_t heads,
+    const size_t headDim,
+    const float* qkv,
+    const float* attn,
+    const float* errors,
+    float* qkvGrad,
+    float* attnGrad)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * heads * len)
+        return;
+
+    const size_t idx = i / (heads * len);
+    const size_t h = (i / len) % heads;
+    const size_t query = i % len;
+    const size_t inner = heads * headDim;
+    const float scale = rsqrtf(static_cast<float>(headDim));
+
+    const float* thisQKV = qkv + idx * len * 3 * inner;
+    const float* err = errors + (idx * len + query) * inner + h * headDim;
+    const float* weights = attn + i * len;
+    float* grads = attnGrad + i * len;
+
+    float dot = 0.0F;
+    for (size_t j = 0; j < len; j++)
+    {
+        const float* v = thisQKV + j * 3 * inner + 2 * inner + h * headDim;
+        float grad = 0.0F;
+        for (size_t d = 0; d < headDim; d++)
+            grad += err[d] * v[d];
+
+        grads[j] = grad;
+        dot += grad * weights[j];
+    }
+
+    for (size_t j = 0; j < len; j++)
+        grads[j] = weights[j] * (grads[j] - dot) * scale;
+
+    float* qGrad = qkvGrad + idx * len * 3 * inner + query * 3 * inner + h * headDim;
+    for (size_t d = 0; d < headDim; d++)
+    {
+        float sum = 0.0F;
+        for (size_t j = 0; j < len; j++)
+            sum += grads[j] * thisQKV[j * 3 * inner + inner + h * headDim + d];
+
+        qGrad[d] = sum;
+    }
+}
+
+// one thread per key of each head, after the scores' gradients are known
+__global__ void backpropAttendKeysKernel(
+    const size_t batchSize,
+    const size_t len,
+    const size_t heads,
+    const size_t headDim,
+    const float* qkv,
+    const float* attn,
+    const float* attnGrad,
+    const float* errors,
+    float* qkvGrad)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * heads * len)
+        return;
+
+    const size_t idx = i / (heads * len);
+    const size_t h = (i / len) % heads;
+    const size_t key = i % len;
+    const size_t inner = heads * headDim;
+
+    const float* thisQKV = qkv + idx * len * 3 * inner;
+    const float* thisAttn = attn + (idx * heads + h) * len * len;
+    const float* thisAttnGrad = attnGrad + (idx * heads + h) * len * len;
+    const float* thisErrors = errors + idx * len * inner + h * headDim;
+    float* kGrad = qkvGrad + idx * len * 3 * inner + key * 3 * inner + inner + h * headDim;
+    float* vGrad = kGrad + inner;
+
+    for (size_t d = 0; d < headDim; d++)
+    {
+        float k = 0.0F;
+        float v = 0.0F;
+
+        for (size_t query = 0; query < len; query++)
+        {
+            k += thisAttnGrad[query * len + key] * thisQKV[query * 3 * inner + h * headDim + d];
+            v += thisAttn[query * len + key] * thisErrors[query * inner + d];
+        }
+
+        kGrad[d] = k;
+        vGrad[d] = v;
+    }
+}
+
+// each thread accumulates a single weight over the whole batch, so no atomics are needed
+__global__ void backpropProjectQKVWeightsKernel(
+    const size_t batchSize,
+    const size_t len,
+    const size_t dim,
+    const size_t rows,
+    const float* inp,
+    const float* qkvGrad,
+    float* weightsGrad,
+    float* biasesGrad)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= rows * dim)
+        return;
+
+    const size_t row = i / dim;
+    const size_t f = i % dim;
+
+    float grad = 0.0F;
+    float biasGrad = 0.0F;
+
+    for (size_t token = 0; token < batchSize * len; token++)
+    {
+        const float g = qkvGrad[token * rows + row];
+        grad += g * inp[token * dim + f];
+        biasGrad += g;
+    }
+
+    weightsGrad[i] += grad;
+
+    if (f == 0)
+        biasesGrad[row] += biasGrad;
+}
+
+__global__ void backpropProjectQKVInputKernel(
+    const size_t batchSize,
+    const size_t len,
+    const size_t dim,
+    const size_t rows,
+    const float* weights,
+    const float* qkvGrad,
+    float* inp)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * len * dim)
+        return;
+
+    const size_t token = i / dim;
+    const size_t f = i % dim;
+
+    float sum = 0.0F;
+    for (size_t row = 0; row < rows; row++)
+        sum += weights[row * dim + f] * qkvGrad[token * rows + row];
+
+    inp[i] = sum;
+}
+
+extern "C" void attention(
+    const size_t batchSize,
+    const size_t len,
+    const size_t dim,
+    const size_t heads,
+    const size_t headDim,
+    const float* weights,
+    const float* biases,
+    const float* inp,
+    float* qkv,
+    float* attn,
+    float* out)
+{
+    const size_t rows = 3 * heads * headDim;
+    const size_t projectBlocks = (batchSize * len * rows + threadsPerBlock - 1) / threadsPerBlock;
+    projectQKVKernel<<<projectBlocks, threadsPerBlock>>>(batchSize, len, dim, rows, weights, biases, inp, qkv);
+
+    const size_t attendBlocks = (batchSize * heads * len + threadsPerBlock - 1) / threadsPerBlock;
+    attendKernel<<<attendBlocks, threadsPerBlock>>>(batchSize, len, heads, headDim, qkv, attn, out);
+}
+
+extern "C" void backpropAttention(
+    const size_t batchSize,
+    const size_t len,
+    const size_t dim,
+    const size_t heads,
+    const size_t headDim,
+    const float* weights,
+    float* weightsGrad,
+    float* biasesGrad,
+    const float* qkv,
+    const float* attn,
+    float* qkvGrad,
+    float* attnGrad,
+    const float* errors,
+    float* inp)
+{
+    const size_t rows = 3 * heads * headDim;
+    const size_t attendBlocks = (batchSize * heads * len + threadsPerBlock - 1) / threadsPerBlock;
+
+    // the keys' gradients need the scores' gradients of every query
+    backpropAttendQueriesKernel<<<attendBlocks, threadsPerBlock>>>(
+        batchSize, len, heads, headDim, qkv, attn, errors, qkvGrad, attnGrad);
+
+    backpropAttendKeysKernel<<<attendBlocks, threadsPerBlock>>>(
+        batchSize, len, heads, headDim, qkv, attn, attnGrad, errors, qkvGrad);
+
+    const size_t weightBlocks = (rows * dim + threadsPerBlock - 1) / threadsPerBlock;
+    backpropProjectQKVWeightsKernel<<<weightBlocks, threadsPerBlock>>>(
+        batchSize, len, dim, rows, inp, qkvGrad, weightsGrad, biasesGrad);
+
+    // the weight gradients read the inputs, so must finish before they are overwritten
+    const size_t inputBlocks = (batchSize * len * dim + threadsPerBlock - 1) / threadsPerBlock;
+    backpropProjectQKVInputKernel<<<inputBlocks, threadsPerBlock>>>(batchSize, len, dim, rows, weights, qkvGrad, inp);
+}
@@ -45,3 +45,90 @@ extern "C" void backpropSCReLU(const size_t size, const float* in, float* out)
     const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
     bufferBackprop<primeSCReLU><<<numBlocks, threadsPerBlock>>>(size, in, out);
 }
+
+__global__ void backpropBoundedKernel(
+    const size_t size,
+    const float* in,
+    float* out,
+    const float min,
+    const float max,
+    const bool square)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const float thisIn = in[i];
+    const float thisOut = out[i];
+    const float prime = thisOut > min && thisOut < max ? (square ? 2.0F * thisOut : 1.0F) : 0.0F;
+
+    out[i] = thisIn * prime;
+}
+
+extern "C" void backpropBoundedCReLU(const size_t size, const float* in, float* out, const float min, const float max)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    backpropBoundedKernel<<<numBlocks, threadsPerBlock>>>(size, in, out, min, max, false);
+}
+
+extern "C" void backpropBoundedSCReLU(const size_t size, const float* in, float* out, const float min, const float max)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    backpropBoundedKernel<<<numBlocks, threadsPerBlock>>>(size, in, out, min, max, true);
+}
+
+__global__ void backpropLeakyReLUKernel(const size_t size, const float* in, float* out, const float slope)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    out[i] = in[i] * (out[i] > 0.0F ? 1.0F : slope);
+}
+
+extern "C" void backpropLeakyReLU(const size_t size, const float* in, float* out, const float slope)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    backpropLeakyReLUKernel<<<numBlocks, threadsPerBlock>>>(size, in, out, slope);
+}
+
+__global__ void backpropPReLUKernel(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* slopes,
+    float* slopesGrad,
+    const float* in,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const size_t channel = (i % width) % channels;
+    const float x = out[i];
+
+    if (x > 0.0F)
+        out[i] = in[i];
+    else
+    {
+        atomicAdd(&slopesGrad[channel], in[i] * x);
+        out[i] = in[i] * slopes[channel];
+    }
+}
+
+extern "C" void backpropPReLU(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* slopes,
+    float* slopesGrad,
+    const float* in,
+    float* out)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    backpropPReLUKernel<<<numBlocks, threadsPerBlock>>>(size, width, channels, slopes, slopesGrad, in, out);
+}
@@ -38,6 +38,80 @@ extern "C" void activateSCReLU(const size_t size, const float* in, float* out)
     bufferOperation<SCReLU><<<numBlocks, threadsPerBlock>>>(size, in, out);
 }
 
+__global__ void activateBoundedKernel(
+    const size_t size,
+    const float* in,
+    float* out,
+    const float min,
+    const float max,
+    const bool square)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const float clipped = in[i] < min ? min : (in[i] > max ? max : in[i]);
+    out[i] = square ? clipped * clipped : clipped;
+}
+
+extern "C" void activateBoundedCReLU(const size_t size, const float* in, float* out, const float min, const float max)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    activateBoundedKernel<<<numBlocks, threadsPerBlock>>>(size, in, out, min, max, false);
+}
+
+extern "C" void activateBoundedSCReLU(const size_t size, const float* in, float* out, const float min, const float max)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    activateBoundedKernel<<<numBlocks, threadsPerBlock>>>(size, in, out, min, max, true);
+}
+
+__global__ void activateLeakyReLUKernel(const size_t size, const float* in, float* out, const float slope)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    out[i] = in[i] > 0.0F ? in[i] : slope * in[i];
+}
+
+extern "C" void activateLeakyReLU(const size_t size, const float* in, float* out, const float slope)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    activateLeakyReLUKernel<<<numBlocks, threadsPerBlock>>>(size, in, out, slope);
+}
+
+__global__ void activatePReLUKernel(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* in,
+    const float* slopes,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const float slope = slopes[(i % width) % channels];
+    out[i] = in[i] > 0.0F ? in[i] : slope * in[i];
+}
+
+extern "C" void activatePReLU(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* in,
+    const float* slopes,
+    float* out)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    activatePReLUKernel<<<numBlocks, threadsPerBlock>>>(size, width, channels, in, slopes, out);
+}
+
 __global__ void activateDualKernel(
     const size_t batchSize,
     const size_t tensorSize,
@@ -88,3 +162,154 @@ extern "C" void addTo(const size_t size, const float* in, float* out)
     const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
     addToKernel<<<numBlocks, threadsPerBlock>>>(size, in, out);
 }
+
+__global__ void copyStridedKernel(
+    const size_t size,
+    const size_t tensorSize,
+    const size_t inpStride,
+    const size_t inpOffset,
+    const size_t outStride,
+    const size_t outOffset,
+    const float* in,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const size_t tensor = i / tensorSize;
+    const size_t elem = i % tensorSize;
+    out[outStride * tensor + outOffset + elem] = in[inpStride * tensor + inpOffset + elem];
+}
+
+extern "C" void copyStrided(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const size_t inpStride,
+    const size_t inpOffset,
+    const size_t outStride,
+    const size_t outOffset,
+    const float* in,
+    float* out)
+{
+    const size_t size = batchSize * tensorSize;
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    copyStridedKernel<<<numBlocks, threadsPerBlock>>>(size, tensorSize, inpStride, inpOffset, outStride, outOffset, in, out);
+}
+
+__global__ void maskedScaleKernel(const size_t size, const float* mask, const float* in, float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    out[i] = mask[i] * in[i];
+}
+
+extern "C" void maskedScale(const size_t size, const float* mask, const float* in, float* out)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    maskedScaleKernel<<<numBlocks, threadsPerBlock>>>(size, mask, in, out);
+}
+
+__global__ void sumOfSquaresKernel(const size_t size, const float* in, float* out)
+{
+    __shared__ float partial[threadsPerBlock];
+
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+    partial[threadIdx.x] = i < size ? in[i] * in[i] : 0.0F;
+
+    __syncthreads();
+
+    for (size_t stride = blockDim.x / 2; stride > 0; stride /= 2)
+    {
+        if (threadIdx.x < stride)
+            partial[threadIdx.x] += partial[threadIdx.x + stride];
+
+        __syncthreads();
+    }
+
+    if (threadIdx.x == 0)
+        atomicAdd(out, partial[0]);
+}
+
+extern "C" void sumOfSquares(const size_t size, const float* in, float* out)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    sumOfSquaresKernel<<<numBlocks, threadsPerBlock>>>(size, in, out);
+}
+
+/*
+Multiplies the first `stride` elements of each consecutive run of `2 * stride`
+with the rest, after clipping each to `[min, max]`, with one thread per output.
+*/
+__global__ void pairwiseMulKernel(
+    const size_t size,
+    const size_t stride,
+    const float min,
+    const float max,
+    const float* in,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const size_t first = 2 * stride * (i / stride) + i % stride;
+    const float a = fminf(fmaxf(in[first], min), max);
+    const float b = fminf(fmaxf(in[first + stride], min), max);
+
+    out[i] = a * b;
+}
+
+extern "C" void pairwiseMul(
+    const size_t size,
+    const size_t stride,
+    const float min,
+    const float max,
+    const float* in,
+    float* out)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    pairwiseMulKernel<<<numBlocks, threadsPerBlock>>>(size, stride, min, max, in, out);
+}
+
+__global__ void backpropPairwiseMulKernel(
+    const size_t size,
+    const size_t stride,
+    const float min,
+    const float max,
+    const float* in,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const size_t first = 2 * stride * (i / stride) + i % stride;
+    const float err = in[i];
+    const float a = out[first];
+    const float b = out[first + stride];
+
+    const float aPrime = a > min && a < max ? 1.0F : 0.0F;
+    const float bPrime = b > min && b < max ? 1.0F : 0.0F;
+
+    out[first] = err * fminf(fmaxf(b, min), max) * aPrime;
+    out[first + stride] = err * fminf(fmaxf(a, min), max) * bPrime;
+}
+
+extern "C" void backpropPairwiseMul(
+    const size_t size,
+    const size_t stride,
+    const float min,
+    const float max,
+    const float* in,
+    float* out)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    backpropPairwiseMulKernel<<<numBlocks, threadsPerBlock>>>(size, stride, min, max, in, out);
+}
@@ -0,0 +1,240 @@
+/*
+Direct 2D convolution over tensors holding each channel as a contiguous,
+row-major image, with arbitrary stride, padding and dilation, and with
+the channels optionally split into groups, e.g. for depthwise convolutions.
+
+Weights are stored by output channel, then input channel within its group,
+then kernel row.
+*/
+#include <cuda.h>
+#include <cuda_runtime.h>
+
+struct ConvDesc
+{
+    size_t inputChannels;
+    size_t outputChannels;
+    size_t inputHeight;
+    size_t inputWidth;
+    size_t outputHeight;
+    size_t outputWidth;
+    size_t kernelHeight;
+    size_t kernelWidth;
+    size_t strideHeight;
+    size_t strideWidth;
+    size_t paddingHeight;
+    size_t paddingWidth;
+    size_t dilationHeight;
+    size_t dilationWidth;
+    size_t groups;
+};
+
+constexpr size_t threadsPerBlock = static_cast<size_t>(1024);
+
+// input channel that is the `i`th read by output channel `oc`
+__device__ size_t inputChannel(const ConvDesc& d, size_t oc, size_t i)
+{
+    return oc / (d.outputChannels / d.groups) * (d.inputChannels / d.groups) + i;
+}
+
+// input coordinate read by kernel coordinate `k` for output coordinate `o`, or -1 if in the padding
+__device__ long long inputCoord(size_t o, size_t k, size_t stride, size_t padding, size_t dilation, size_t size)
+{
+    const long long pos = static_cast<long long>(o * stride + k * dilation) - static_cast<long long>(padding);
+    return (pos < 0 || pos >= static_cast<long long>(size)) ? -1 : pos;
+}
+
+__global__ void convolutionKernel(
+    const ConvDesc d,
+    const size_t batchSize,
+    const float* weights,
+    const float* biases,
+    const float* inp,
+    float* out)
+{
+    const size_t outSize = d.outputChannels * d.outputHeight * d.outputWidth;
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * outSize)
+        return;
+
+    const size_t idx = i / outSize;
+    const size_t oc = (i % outSize) / (d.outputHeight * d.outputWidth);
+    const size_t oy = (i / d.outputWidth) % d.outputHeight;
+    const size_t ox = i % d.outputWidth;
+    const float* thisInp = inp + d.inputChannels * d.inputHeight * d.inputWidth * idx;
+
+    const size_t groupInputs = d.inputChannels / d.groups;
+    float sum = biases[oc];
+
+    for (size_t j = 0; j < groupInputs; j++)
+        for (size_t ky = 0; ky < d.kernelHeight; ky++)
+        {
+            const long long iy = inputCoord(oy, ky, d.strideHeight, d.paddingHeight, d.dilationHeight, d.inputHeight);
+            if (iy < 0)
+                continue;
+
+            for (size_t kx = 0; kx < d.kernelWidth; kx++)
+            {
+                const long long ix = inputCoord(ox, kx, d.strideWidth, d.paddingWidth, d.dilationWidth, d.inputWidth);
+                if (ix < 0)
+                    continue;
+
+                const size_t ic = inputChannel(d, oc, j);
+                const size_t w = ((oc * groupInputs + j) * d.kernelHeight + ky) * d.kernelWidth + kx;
+                sum += weights[w] * thisInp[(ic * d.inputHeight + iy) * d.inputWidth + ix];
+            }
+        }
+
+    out[i] = sum;
+}
+
+// each thread accumulates a single weight over the whole batch, so no atomics are needed
+__global__ void backpropConvolutionWeightsKernel(
+    const ConvDesc d,
+    const size_t batchSize,
+    const float* errors,
+    const float* inp,
+    float* weightsGrad)
+{
+    const size_t kernelSize = d.kernelHeight * d.kernelWidth;
+    const size_t groupInputs = d.inputChannels / d.groups;
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= d.outputChannels * groupInputs * kernelSize)
+        return;
+
+    const size_t oc = i / (groupInputs * kernelSize);
+    const size_t ic = inputChannel(d, oc, (i / kernelSize) % groupInputs);
+    const size_t ky = (i % kernelSize) / d.kernelWidth;
+    const size_t kx = i % d.kernelWidth;
+
+    const size_t inpSize = d.inputChannels * d.inputHeight * d.inputWidth;
+    const size_t outSize = d.outputChannels * d.outputHeight * d.outputWidth;
+    float grad = 0.0F;
+
+    for (size_t idx = 0; idx < batchSize; idx++)
+        for (size_t oy = 0; oy < d.outputHeight; oy++)
+        {
+            const long long iy = inputCoord(oy, ky, d.strideHeight, d.paddingHeight, d.dilationHeight, d.inputHeight);
+            if (iy < 0)
+                continue;
+
+            for (size_t ox = 0; ox < d.outputWidth; ox++)
+            {
+                const long long ix = inputCoord(ox, kx, d.strideWidth, d.paddingWidth, d.dilationWidth, d.inputWidth);
+                if (ix < 0)
+                    continue;
+
+                const float err = errors[outSize * idx + (oc * d.outputHeight + oy) * d.outputWidth + ox];
+                grad += err * inp[inpSize * idx + (ic * d.inputHeight + iy) * d.inputWidth + ix];
+            }
+        }
+
+    weightsGrad[i] += grad;
+}
+
+__global__ void backpropConvolutionBiasesKernel(
+    const ConvDesc d,
+    const size_t batchSize,
+    const float* errors,
+    float* biasesGrad)
+{
+    const size_t oc = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (oc >= d.outputChannels)
+        return;
+
+    const size_t perChannel = d.outputHeight * d.outputWidth;
+    const size_t outSize = d.outputChannels * perChannel;
+    float grad = 0.0F;
+
+    for (size_t idx = 0; idx < batchSize; idx++)
+        for (size_t j = 0; j < perChannel; j++)
+            grad += errors[outSize * idx + oc * perChannel + j];
+
+    biasesGrad[oc] += grad;
+}
+
+__global__ void backpropConvolutionInputKernel(
+    const ConvDesc d,
+    const size_t batchSize,
+    const float* weights,
+    const float* errors,
+    float* inp)
+{
+    const size_t inpSize = d.inputChannels * d.inputHeight * d.inputWidth;
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * inpSize)
+        return;
+
+    const size_t idx = i / inpSize;
+    const size_t ic = (i % inpSize) / (d.inputHeight * d.inputWidth);
+    const size_t groupInputs = d.inputChannels / d.groups;
+    const size_t groupOutputs = d.outputChannels / d.groups;
+    const size_t group = ic / groupInputs;
+    const long long iy = static_cast<long long>((i / d.inputWidth) % d.inputHeight);
+    const long long ix = static_cast<long long>(i % d.inputWidth);
+    const float* thisErrors = errors + d.outputChannels * d.outputHeight * d.outputWidth * idx;
+
+    float grad = 0.0F;
+
+    for (size_t ky = 0; ky < d.kernelHeight; ky++)
+    {
+        const long long ty = iy + static_cast<long long>(d.paddingHeight) - static_cast<long long>(ky * d.dilationHeight);
+        const long long sh = static_cast<long long>(d.strideHeight);
+        if (ty < 0 || ty % sh != 0 || ty / sh >= static_cast<long long>(d.outputHeight))
+            continue;
+
+        for (size_t kx = 0; kx < d.kernelWidth; kx++)
+        {
+            const long long tx = ix + static_cast<long long>(d.paddingWidth) - static_cast<long long>(kx * d.dilationWidth);
+            const long long sw = static_cast<long long>(d.strideWidth);
+            if (tx < 0 || tx % sw != 0 || tx / sw >= static_cast<long long>(d.outputWidth))
+                continue;
+
+            for (size_t oc = group * groupOutputs; oc < (group + 1) * groupOutputs; oc++)
+            {
+                const size_t w = ((oc * groupInputs + ic % groupInputs) * d.kernelHeight + ky) * d.kernelWidth + kx;
+                grad += weights[w] * thisErrors[(oc * d.outputHeight + ty / sh) * d.outputWidth + tx / sw];
+            }
+        }
+    }
+
+    inp[i] = grad;
+}
+
+extern "C" void convolution(
+    const ConvDesc d,
+    const size_t batchSize,
+    const float* weights,
+    const float* biases,
+    const float* inp,
+    float* out)
+{
+    const size_t total = batchSize * d.outputChannels * d.outputHeight * d.outputWidth;
+    const size_t numBlocks = (total + threadsPerBlock - 1) / threadsPerBlock;
+    convolutionKernel<<<numBlocks, threadsPerBlock>>>(d, batchSize, weights, biases, inp, out);
+}
+
+extern "C" void backpropConvolution(
+    const ConvDesc d,
+    const size_t batchSize,
+    const float* weights,
+    float* weightsGrad,
+    float* biasesGrad,
+    const float* errors,
+    float* inp)
+{
+    const size_t numWeights = d.outputChannels * (d.inputChannels / d.groups) * d.kernelHeight * d.kernelWidth;
+    const size_t weightBlocks = (numWeights + threadsPerBlock - 1) / threadsPerBlock;
+    backpropConvolutionWeightsKernel<<<weightBlocks, threadsPerBlock>>>(d, batchSize, errors, inp, weightsGrad);
+
+    const size_t biasBlocks = (d.outputChannels + threadsPerBlock - 1) / threadsPerBlock;
+    backpropConvolutionBiasesKernel<<<biasBlocks, threadsPerBlock>>>(d, batchSize, errors, biasesGrad);
+
+    // the weight gradients read the inputs, so must finish before they are overwritten
+    const size_t total = batchSize * d.inputChannels * d.inputHeight * d.inputWidth;
+    const size_t inputBlocks = (total + threadsPerBlock - 1) / threadsPerBlock;
+    backpropConvolutionInputKernel<<<inputBlocks, threadsPerBlock>>>(d, batchSize, weights, errors, inp);
+}
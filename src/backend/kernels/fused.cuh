@@ -0,0 +1,21 @@
+#ifndef FUSED
+#define FUSED
+
+/*
+Any of the activations, as fused into the op before them: clipped
+to `[min, max]`, multiplied by `slope` if negative, then squared if
+`square`.
+*/
+__device__ __forceinline__ float fusedActivate(
+    const float in,
+    const float min,
+    const float max,
+    const float slope,
+    const bool square)
+{
+    const float clipped = in < min ? min : (in > max ? max : in);
+    const float sloped = clipped < 0.0F ? slope * clipped : clipped;
+    return square ? sloped * sloped : sloped;
+}
+
+#endif
@@ -1,5 +1,9 @@
 /*
-Computes MSE(sigmoid(outputs), results).
+Computes MPE(sigmoid(outputs), results), optionally weighted
+per tensor, or the Huber loss of the same for `sigmoidHuber`.
+`sigmoidFocal` additionally scales each gradient by |error|^gamma,
+and `sigmoidMSEVariance` trains a second output per position to
+predict the squared error of the first.
 */
 #include <cuda.h>
 #include <cuda_runtime.h>
@@ -38,3 +42,149 @@ extern "C" void sigmoidMPE(
     const size_t numBlocks = (bufferSize + threadsPerBlock - 1) / threadsPerBlock;
     sigmoidMPEKernel<<<numBlocks, threadsPerBlock>>>(bufferSize, outputs, results, error, power);
 }
+
+__global__ void sigmoidMSEVarianceKernel(
+    const size_t batchSize,
+    float* outputs,
+    const float* results,
+    float* error)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize)
+        return;
+
+    float* thisOutput = outputs + 2 * i;
+
+    const float sigmoid = 1.0F / (1.0F + expf(-thisOutput[0]));
+    const float diff = sigmoid - results[2 * i];
+    const float sqr = diff * diff;
+
+    thisOutput[0] = diff * sigmoid * (1.0F - sigmoid);
+    thisOutput[1] = thisOutput[1] - sqr;
+
+    atomicAdd(error, sqr);
+}
+
+extern "C" void sigmoidMSEVariance(
+    const size_t batchSize,
+    float* outputs,
+    const float* results,
+    float* error)
+{
+    const size_t numBlocks = (batchSize + threadsPerBlock - 1) / threadsPerBlock;
+    sigmoidMSEVarianceKernel<<<numBlocks, threadsPerBlock>>>(batchSize, outputs, results, error);
+}
+
+__global__ void sigmoidFocalKernel(
+    const size_t bufferSize,
+    float* outputs,
+    const float* results,
+    float* error,
+    const float power,
+    const float gamma)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= bufferSize)
+        return;
+
+    const float sigmoid = 1.0F / (1.0F + expf(-outputs[i]));
+    const float diff = sigmoid - results[i];
+    const float absd = abs(diff);
+
+    outputs[i] = powf(absd, power + gamma - 1.0F) * sigmoid * (1.0F - sigmoid);
+    outputs[i] = diff > 0.0F ? outputs[i] : -outputs[i];
+
+    atomicAdd(error, powf(absd, power));
+}
+
+extern "C" void sigmoidFocal(
+    const size_t bufferSize,
+    float* outputs,
+    const float* results,
+    float* error,
+    const float power,
+    const float gamma)
+{
+    const size_t numBlocks = (bufferSize + threadsPerBlock - 1) / threadsPerBlock;
+    sigmoidFocalKernel<<<numBlocks, threadsPerBlock>>>(bufferSize, outputs, results, error, power, gamma);
+}
+
+__global__ void sigmoidMPEWeightedKernel(
+    const size_t bufferSize,
+    const size_t tensorSize,
+    float* outputs,
+    const float* results,
+    const float* weights,
+    float* error,
+    const float power)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= bufferSize)
+        return;
+
+    const float weight = weights[i / tensorSize];
+    const float sigmoid = 1.0F / (1.0F + expf(-outputs[i]));
+    const float diff = sigmoid - results[i];
+    const float absd = abs(diff);
+
+    outputs[i] = weight * powf(absd, power - 1.0F) * sigmoid * (1.0F - sigmoid);
+    outputs[i] = diff > 0.0F ? outputs[i] : -outputs[i];
+
+    atomicAdd(error, weight * powf(absd, power));
+}
+
+extern "C" void sigmoidMPEWeighted(
+    const size_t bufferSize,
+    const size_t tensorSize,
+    float* outputs,
+    const float* results,
+    const float* weights,
+    float* error,
+    const float power)
+{
+    const size_t numBlocks = (bufferSize + threadsPerBlock - 1) / threadsPerBlock;
+    sigmoidMPEWeightedKernel<<<numBlocks, threadsPerBlock>>>(bufferSize, tensorSize, outputs, results, weights, error, power);
+}
+
+__global__ void sigmoidHuberKernel(
+    const size_t bufferSize,
+    float* outputs,
+    const float* results,
+    float* error,
+    const float delta)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= bufferSize)
+        return;
+
+    const float sigmoid = 1.0F / (1.0F + expf(-outputs[i]));
+    const float diff = sigmoid - results[i];
+    const float absd = abs(diff);
+
+    if (absd <= delta)
+    {
+        outputs[i] = diff * sigmoid * (1.0F - sigmoid);
+        atomicAdd(error, 0.5F * diff * diff);
+    }
+    else
+    {
+        outputs[i] = delta * sigmoid * (1.0F - sigmoid);
+        outputs[i] = diff > 0.0F ? outputs[i] : -outputs[i];
+        atomicAdd(error, delta * (absd - 0.5F * delta));
+    }
+}
+
+extern "C" void sigmoidHuber(
+    const size_t bufferSize,
+    float* outputs,
+    const float* results,
+    float* error,
+    const float delta)
+{
+    const size_t numBlocks = (bufferSize + threadsPerBlock - 1) / threadsPerBlock;
+    sigmoidHuberKernel<<<numBlocks, threadsPerBlock>>>(bufferSize, outputs, results, error, delta);
+}
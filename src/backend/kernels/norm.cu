@@ -0,0 +1,298 @@
+/*
+Layer and batch normalisation, with learned element-wise scale and
+shift. Backprop recomputes the normalised inputs, as the outputs have
+already been overwritten with errors.
+*/
+#include <cuda.h>
+#include <cuda_runtime.h>
+
+constexpr size_t threadsPerBlock = static_cast<size_t>(1024);
+constexpr float epsilon = 1e-5F;
+
+__device__ void stats(const float* inp, const size_t tensorSize, float* mean, float* rstd)
+{
+    float sum = 0.0F;
+    for (size_t j = 0; j < tensorSize; j++)
+        sum += inp[j];
+
+    const float m = sum / tensorSize;
+
+    float var = 0.0F;
+    for (size_t j = 0; j < tensorSize; j++)
+        var += (inp[j] - m) * (inp[j] - m);
+
+    *mean = m;
+    *rstd = rsqrtf(var / tensorSize + epsilon);
+}
+
+__global__ void layerNormKernel(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    const float* beta,
+    const float* inp,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize)
+        return;
+
+    const float* thisInp = inp + tensorSize * i;
+    float* thisOut = out + tensorSize * i;
+
+    float mean, rstd;
+    stats(thisInp, tensorSize, &mean, &rstd);
+
+    for (size_t j = 0; j < tensorSize; j++)
+        thisOut[j] = gamma[j] * (thisInp[j] - mean) * rstd + beta[j];
+}
+
+extern "C" void layerNorm(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    const float* beta,
+    const float* inp,
+    float* out)
+{
+    const size_t numBlocks = (batchSize + threadsPerBlock - 1) / threadsPerBlock;
+    layerNormKernel<<<numBlocks, threadsPerBlock>>>(batchSize, tensorSize, gamma, beta, inp, out);
+}
+
+__global__ void backpropLayerNormKernel(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    float* gammaGrad,
+    float* betaGrad,
+    const float* inp,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize)
+        return;
+
+    const float* thisInp = inp + tensorSize * i;
+    float* thisOut = out + tensorSize * i;
+
+    float mean, rstd;
+    stats(thisOut, tensorSize, &mean, &rstd);
+
+    float sum = 0.0F;
+    float dot = 0.0F;
+    for (size_t j = 0; j < tensorSize; j++)
+    {
+        const float norm = (thisOut[j] - mean) * rstd;
+        const float grad = thisInp[j] * gamma[j];
+
+        atomicAdd(&gammaGrad[j], thisInp[j] * norm);
+        atomicAdd(&betaGrad[j], thisInp[j]);
+
+        sum += grad;
+        dot += grad * norm;
+    }
+
+    for (size_t j = 0; j < tensorSize; j++)
+    {
+        const float norm = (thisOut[j] - mean) * rstd;
+        const float grad = thisInp[j] * gamma[j];
+        thisOut[j] = rstd * (grad - (sum + norm * dot) / tensorSize);
+    }
+}
+
+extern "C" void backpropLayerNorm(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    float* gammaGrad,
+    float* betaGrad,
+    const float* inp,
+    float* out)
+{
+    const size_t numBlocks = (batchSize + threadsPerBlock - 1) / threadsPerBlock;
+    backpropLayerNormKernel<<<numBlocks, threadsPerBlock>>>(batchSize, tensorSize, gamma, gammaGrad, betaGrad, inp, out);
+}
+
+__global__ void batchNormKernel(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    const float* beta,
+    float* runningMean,
+    float* runningVar,
+    float* batchMean,
+    float* batchRstd,
+    const float* inp,
+    float* out,
+    const float momentum)
+{
+    const size_t j = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (j >= tensorSize)
+        return;
+
+    float sum = 0.0F;
+    for (size_t i = 0; i < batchSize; i++)
+        sum += inp[tensorSize * i + j];
+
+    const float mean = sum / batchSize;
+
+    float var = 0.0F;
+    for (size_t i = 0; i < batchSize; i++)
+    {
+        const float diff = inp[tensorSize * i + j] - mean;
+        var += diff * diff;
+    }
+
+    var /= batchSize;
+    const float rstd = rsqrtf(var + epsilon);
+
+    for (size_t i = 0; i < batchSize; i++)
+        out[tensorSize * i + j] = gamma[j] * (inp[tensorSize * i + j] - mean) * rstd + beta[j];
+
+    batchMean[j] = mean;
+    batchRstd[j] = rstd;
+
+    const float unbiased = batchSize > 1 ? var * batchSize / (batchSize - 1) : var;
+    runningMean[j] += momentum * (mean - runningMean[j]);
+    runningVar[j] += momentum * (unbiased - runningVar[j]);
+}
+
+extern "C" void batchNorm(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    const float* beta,
+    float* runningMean,
+    float* runningVar,
+    float* batchMean,
+    float* batchRstd,
+    const float* inp,
+    float* out,
+    const float momentum)
+{
+    const size_t numBlocks = (tensorSize + threadsPerBlock - 1) / threadsPerBlock;
+    batchNormKernel<<<numBlocks, threadsPerBlock>>>(
+        batchSize,
+        tensorSize,
+        gamma,
+        beta,
+        runningMean,
+        runningVar,
+        batchMean,
+        batchRstd,
+        inp,
+        out,
+        momentum
+    );
+}
+
+__global__ void batchNormInferenceKernel(
+    const size_t size,
+    const size_t tensorSize,
+    const float* gamma,
+    const float* beta,
+    const float* runningMean,
+    const float* runningVar,
+    const float* inp,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const size_t j = i % tensorSize;
+    out[i] = gamma[j] * (inp[i] - runningMean[j]) * rsqrtf(runningVar[j] + epsilon) + beta[j];
+}
+
+extern "C" void batchNormInference(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    const float* beta,
+    const float* runningMean,
+    const float* runningVar,
+    const float* inp,
+    float* out)
+{
+    const size_t size = batchSize * tensorSize;
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    batchNormInferenceKernel<<<numBlocks, threadsPerBlock>>>(
+        size,
+        tensorSize,
+        gamma,
+        beta,
+        runningMean,
+        runningVar,
+        inp,
+        out
+    );
+}
+
+__global__ void backpropBatchNormKernel(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    const float* batchMean,
+    const float* batchRstd,
+    float* gammaGrad,
+    float* betaGrad,
+    const float* inp,
+    float* out)
+{
+    const size_t j = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (j >= tensorSize)
+        return;
+
+    const float mean = batchMean[j];
+    const float rstd = batchRstd[j];
+
+    float dgamma = 0.0F;
+    float dbeta = 0.0F;
+    for (size_t i = 0; i < batchSize; i++)
+    {
+        const float grad = inp[tensorSize * i + j];
+        dgamma += grad * (out[tensorSize * i + j] - mean) * rstd;
+        dbeta += grad;
+    }
+
+    const float n = static_cast<float>(batchSize);
+    const float scale = gamma[j] * rstd / n;
+    for (size_t i = 0; i < batchSize; i++)
+    {
+        const float norm = (out[tensorSize * i + j] - mean) * rstd;
+        out[tensorSize * i + j] = scale * (n * inp[tensorSize * i + j] - dbeta - norm * dgamma);
+    }
+
+    gammaGrad[j] += dgamma;
+    betaGrad[j] += dbeta;
+}
+
+extern "C" void backpropBatchNorm(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* gamma,
+    const float* batchMean,
+    const float* batchRstd,
+    float* gammaGrad,
+    float* betaGrad,
+    const float* inp,
+    float* out)
+{
+    const size_t numBlocks = (tensorSize + threadsPerBlock - 1) / threadsPerBlock;
+    backpropBatchNormKernel<<<numBlocks, threadsPerBlock>>>(
+        batchSize,
+        tensorSize,
+        gamma,
+        batchMean,
+        batchRstd,
+        gammaGrad,
+        betaGrad,
+        inp,
+        out
+    );
+}
@@ -0,0 +1,102 @@
+/*
+Reduces strided runs of each tensor in a batch to single values, taking
+either their maximum or their scaled sum, which covers sum and mean.
+
+Backprop for the maximum recomputes it from the inputs.
+*/
+#include <cuda.h>
+#include <cuda_runtime.h>
+
+constexpr size_t threadsPerBlock = static_cast<size_t>(1024);
+
+__global__ void reduceKernel(
+    const size_t batchSize,
+    const size_t inpSize,
+    const size_t outSize,
+    const size_t len,
+    const size_t outer,
+    const size_t inner,
+    const bool maximum,
+    const float scale,
+    const float* inp,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * outSize)
+        return;
+
+    const size_t idx = i / outSize;
+    const size_t j = i % outSize;
+    const float* thisInp = inp + inpSize * idx + outer * j;
+
+    float result = maximum ? thisInp[0] : 0.0F;
+    for (size_t k = maximum ? 1 : 0; k < len; k++)
+        result = maximum ? max(result, thisInp[inner * k]) : result + thisInp[inner * k];
+
+    out[i] = maximum ? result : scale * result;
+}
+
+extern "C" void reduce(
+    const size_t batchSize,
+    const size_t inpSize,
+    const size_t outSize,
+    const size_t len,
+    const size_t outer,
+    const size_t inner,
+    const bool maximum,
+    const float scale,
+    const float* inp,
+    float* out)
+{
+    const size_t numBlocks = (batchSize * outSize + threadsPerBlock - 1) / threadsPerBlock;
+    reduceKernel<<<numBlocks, threadsPerBlock>>>(batchSize, inpSize, outSize, len, outer, inner, maximum, scale, inp, out);
+}
+
+__global__ void backpropReduceKernel(
+    const size_t batchSize,
+    const size_t inpSize,
+    const size_t outSize,
+    const size_t len,
+    const size_t outer,
+    const size_t inner,
+    const bool maximum,
+    const float scale,
+    const float* inp,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * outSize)
+        return;
+
+    const size_t idx = i / outSize;
+    const size_t j = i % outSize;
+    const float grad = inp[i];
+    float* thisOut = out + inpSize * idx + outer * j;
+
+    size_t best = 0;
+    if (maximum)
+        for (size_t k = 1; k < len; k++)
+            if (thisOut[inner * k] > thisOut[inner * best])
+                best = k;
+
+    for (size_t k = 0; k < len; k++)
+        thisOut[inner * k] = maximum ? (k == best ? grad : 0.0F) : scale * grad;
+}
+
+extern "C" void backpropReduce(
+    const size_t batchSize,
+    const size_t inpSize,
+    const size_t outSize,
+    const size_t len,
+    const size_t outer,
+    const size_t inner,
+    const bool maximum,
+    const float scale,
+    const float* inp,
+    float* out)
+{
+    const size_t numBlocks = (batchSize * outSize + threadsPerBlock - 1) / threadsPerBlock;
+    backpropReduceKernel<<<numBlocks, threadsPerBlock>>>(batchSize, inpSize, outSize, len, outer, inner, maximum, scale, inp, out);
+}
@@ -89,3 +89,57 @@ extern "C" void selectBackprop(
         out
     );
 }
+
+__global__ void gatherKernel(
+    const size_t batchSize,
+    const size_t size,
+    const uint8_t* buckets,
+    const float* weights,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * size)
+        return;
+
+    const size_t thisBucket = static_cast<size_t>(buckets[i / size]);
+    out[i] = weights[size * thisBucket + i % size];
+}
+
+__global__ void backpropGatherKernel(
+    const size_t batchSize,
+    const size_t size,
+    const uint8_t* buckets,
+    const float* errors,
+    float* weightsGrad)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * size)
+        return;
+
+    const size_t thisBucket = static_cast<size_t>(buckets[i / size]);
+    atomicAdd(&weightsGrad[size * thisBucket + i % size], errors[i]);
+}
+
+extern "C" void gather(
+    const size_t batchSize,
+    const size_t size,
+    const uint8_t* buckets,
+    const float* weights,
+    float* out)
+{
+    const size_t numChunks = (batchSize * size + Threads - 1) / Threads;
+    gatherKernel<<<numChunks, Threads>>>(batchSize, size, buckets, weights, out);
+}
+
+extern "C" void backpropGather(
+    const size_t batchSize,
+    const size_t size,
+    const uint8_t* buckets,
+    const float* errors,
+    float* weightsGrad)
+{
+    const size_t numChunks = (batchSize * size + Threads - 1) / Threads;
+    backpropGatherKernel<<<numChunks, Threads>>>(batchSize, size, buckets, errors, weightsGrad);
+}
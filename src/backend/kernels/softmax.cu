@@ -0,0 +1,155 @@
+/*
+Computes CrossEntropy(softmax(outputs), results) for a batch of
+`tensorSize` logits, writing the gradient back into `outputs`.
+
+Also provides softmax and log-softmax as standalone operations,
+with backprop recomputing the softmax from the inputs.
+*/
+#include <cuda.h>
+#include <cuda_runtime.h>
+
+constexpr size_t threadsPerBlock = static_cast<size_t>(1024);
+
+__global__ void softmaxCrossEntropyKernel(
+    const size_t batchSize,
+    const size_t tensorSize,
+    float* outputs,
+    const float* results,
+    float* error)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize)
+        return;
+
+    float* thisOutput = outputs + tensorSize * i;
+    const float* thisResult = results + tensorSize * i;
+
+    float maximum = thisOutput[0];
+    for (size_t j = 1; j < tensorSize; j++)
+        maximum = max(maximum, thisOutput[j]);
+
+    float total = 0.0F;
+    for (size_t j = 0; j < tensorSize; j++)
+    {
+        const float exp = expf(thisOutput[j] - maximum);
+        thisOutput[j] = exp;
+        total += exp;
+    }
+
+    float loss = 0.0F;
+    for (size_t j = 0; j < tensorSize; j++)
+    {
+        const float prob = thisOutput[j] / total;
+        const float target = thisResult[j];
+
+        thisOutput[j] = prob - target;
+
+        if (target > 0.0F)
+            loss -= target * logf(prob);
+    }
+
+    atomicAdd(error, loss);
+}
+
+extern "C" void softmaxCrossEntropy(
+    const size_t batchSize,
+    const size_t tensorSize,
+    float* outputs,
+    const float* results,
+    float* error)
+{
+    const size_t numBlocks = (batchSize + threadsPerBlock - 1) / threadsPerBlock;
+    softmaxCrossEntropyKernel<<<numBlocks, threadsPerBlock>>>(batchSize, tensorSize, outputs, results, error);
+}
+
+__global__ void softmaxKernel(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* inp,
+    float* out,
+    const bool logarithm)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize)
+        return;
+
+    const float* thisInp = inp + tensorSize * i;
+    float* thisOut = out + tensorSize * i;
+
+    float maximum = thisInp[0];
+    for (size_t j = 1; j < tensorSize; j++)
+        maximum = max(maximum, thisInp[j]);
+
+    float total = 0.0F;
+    for (size_t j = 0; j < tensorSize; j++)
+        total += expf(thisInp[j] - maximum);
+
+    const float logTotal = logf(total);
+    for (size_t j = 0; j < tensorSize; j++)
+    {
+        const float shifted = thisInp[j] - maximum;
+        thisOut[j] = logarithm ? shifted - logTotal : expf(shifted) / total;
+    }
+}
+
+extern "C" void softmax(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* inp,
+    float* out,
+    const bool logarithm)
+{
+    const size_t numBlocks = (batchSize + threadsPerBlock - 1) / threadsPerBlock;
+    softmaxKernel<<<numBlocks, threadsPerBlock>>>(batchSize, tensorSize, inp, out, logarithm);
+}
+
+__global__ void backpropSoftmaxKernel(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* inp,
+    float* out,
+    const bool logarithm)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize)
+        return;
+
+    const float* thisInp = inp + tensorSize * i;
+    float* thisOut = out + tensorSize * i;
+
+    float maximum = thisOut[0];
+    for (size_t j = 1; j < tensorSize; j++)
+        maximum = max(maximum, thisOut[j]);
+
+    float total = 0.0F;
+    for (size_t j = 0; j < tensorSize; j++)
+        total += expf(thisOut[j] - maximum);
+
+    float dot = 0.0F;
+    for (size_t j = 0; j < tensorSize; j++)
+    {
+        const float prob = expf(thisOut[j] - maximum) / total;
+        dot += thisInp[j] * (logarithm ? 1.0F : prob);
+    }
+
+    for (size_t j = 0; j < tensorSize; j++)
+    {
+        const float prob = expf(thisOut[j] - maximum) / total;
+        const float grad = thisInp[j];
+        thisOut[j] = logarithm ? grad - prob * dot : prob * (grad - dot);
+    }
+}
+
+extern "C" void backpropSoftmax(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* inp,
+    float* out,
+    const bool logarithm)
+{
+    const size_t numBlocks = (batchSize + threadsPerBlock - 1) / threadsPerBlock;
+    backpropSoftmaxKernel<<<numBlocks, threadsPerBlock>>>(batchSize, tensorSize, inp, out, logarithm);
+}
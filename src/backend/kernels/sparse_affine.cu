@@ -2,6 +2,7 @@
 #include <cuda_runtime.h>
 #include <iostream>
 #include <cstdint>
+#include "fused.cuh"
 
 struct Feat {
     int32_t our;
@@ -14,7 +15,12 @@ __global__ void SingleSparseAffineForwardKernel(
     const float* weights,
     const float* biases,
     const Feat* inputs,
-    float* outputs)
+    float* outputs,
+    float* activated,
+    const float min,
+    const float max,
+    const float slope,
+    const bool square)
 {
     const size_t elem = blockIdx.x * blockDim.x + threadIdx.x;
 
@@ -38,6 +44,9 @@ __global__ void SingleSparseAffineForwardKernel(
     }
 
     thisOutput[0] = ourElementVal;
+
+    if (activated != nullptr)
+        activated[outputSize * blockIdx.y + elem] = fusedActivate(ourElementVal, min, max, slope, square);
 }
 
 __global__ void SingleSparseAffineBackwardKernel(
@@ -84,10 +93,16 @@ __global__ void SingleSparseAffineBackwardKernel(
 __global__ void sparseAffineForwardKernel(
     const size_t inputSize,
     const size_t outputSize,
+    const size_t oppSize,
     const float* weights,
     const float* biases,
     const Feat* inputs,
-    float* outputs)
+    float* outputs,
+    float* activated,
+    const float min,
+    const float max,
+    const float slope,
+    const bool square)
 {
     const size_t elem = blockIdx.x * blockDim.x + threadIdx.x;
 
@@ -96,7 +111,7 @@ __global__ void sparseAffineForwardKernel(
 
     const size_t inputIdx = inputSize * blockIdx.y;
     const Feat* thisInput = inputs + inputSize * blockIdx.y;
-    float* thisOutput = outputs + 2 * outputSize * blockIdx.y + elem;
+    float* thisOutput = outputs + (outputSize + oppSize) * blockIdx.y + elem;
 
     float ourElementVal = biases[elem];
     float oppElementVal = ourElementVal;
@@ -113,13 +128,26 @@ __global__ void sparseAffineForwardKernel(
         oppElementVal += weights[oppIdx];
     }
 
-    thisOutput[         0] = ourElementVal;
-    thisOutput[outputSize] = oppElementVal;
+    thisOutput[0] = ourElementVal;
+
+    // the opposing perspective only uses the first `oppSize` outputs
+    if (elem < oppSize)
+        thisOutput[outputSize] = oppElementVal;
+
+    if (activated != nullptr)
+    {
+        float* thisActivated = activated + (outputSize + oppSize) * blockIdx.y + elem;
+        thisActivated[0] = fusedActivate(ourElementVal, min, max, slope, square);
+
+        if (elem < oppSize)
+            thisActivated[outputSize] = fusedActivate(oppElementVal, min, max, slope, square);
+    }
 }
 
 __global__ void sparseAffineBackwardKernel(
     const size_t inputSize,
     const size_t outputSize,
+    const size_t oppSize,
     float* weightsGrad,
     float* biasesGrad,
     const Feat* inputs,
@@ -133,17 +161,19 @@ __global__ void sparseAffineBackwardKernel(
         return;
 
     const Feat* thisInput = inputs + inputSize * blockIdx.y;
-    const float* thisErrors = errors + 2 * outputSize * blockIdx.y;
+    const float* thisErrors = errors + (outputSize + oppSize) * blockIdx.y;
+    const bool hasOpp = elem < oppSize;
 
     float ourError = thisErrors[elem];
-    float oppError = thisErrors[elem + outputSize];
+    float oppError = hasOpp ? thisErrors[elem + outputSize] : 0.0F;
 
     // Idea from Jay (Beserk author).
     if (ftRegularisation != 0.0F)
     {
-            const float* thisOutput = output + 2 * outputSize * blockIdx.y;
+            const float* thisOutput = output + (outputSize + oppSize) * blockIdx.y;
             ourError += ftRegularisation * (thisOutput[elem] > 0.0F);
-            oppError += ftRegularisation * (thisOutput[elem + outputSize] > 0.0F);
+            if (hasOpp)
+                oppError += ftRegularisation * (thisOutput[elem + outputSize] > 0.0F);
     }
 
     atomicAdd(&biasesGrad[elem], ourError + oppError);
@@ -157,7 +187,9 @@ __global__ void sparseAffineBackwardKernel(
         const size_t ourIdx = static_cast<size_t>(inp.our) * outputSize + elem;
         const size_t oppIdx = static_cast<size_t>(inp.opp) * outputSize + elem;
         atomicAdd(&weightsGrad[ourIdx], ourError);
-        atomicAdd(&weightsGrad[oppIdx], oppError);
+
+        if (hasOpp)
+            atomicAdd(&weightsGrad[oppIdx], oppError);
     }
 }
 
@@ -182,7 +214,47 @@ extern "C" void singleSparseAffineForward(
         weights,
         biases,
         inputs,
-        outputs
+        outputs,
+        nullptr,
+        0.0F,
+        0.0F,
+        0.0F,
+        false
+    );
+}
+
+extern "C" void singleSparseAffineActivateForward(
+    const size_t batchSize,
+    const size_t maxInputSize,
+    const size_t outputSize,
+    const float* weights,
+    const float* biases,
+    const Feat* inputs,
+    float* outputs,
+    float* activated,
+    const float min,
+    const float max,
+    const float slope,
+    const bool square)
+{
+    const size_t numChunks = (outputSize + static_cast<size_t>(1023)) / static_cast<size_t>(1024);
+
+    dim3 grid(numChunks, batchSize);
+
+    const size_t threads = (numChunks == 1) ? outputSize : 1024;
+
+    SingleSparseAffineForwardKernel<<<grid, threads>>>(
+        maxInputSize,
+        outputSize,
+        weights,
+        biases,
+        inputs,
+        outputs,
+        activated,
+        min,
+        max,
+        slope,
+        square
     );
 }
 
@@ -219,6 +291,7 @@ extern "C" void sparseAffineForward(
     const size_t batchSize,
     const size_t maxInputSize,
     const size_t outputSize,
+    const size_t oppSize,
     const float* weights,
     const float* biases,
     const Feat* inputs,
@@ -233,10 +306,53 @@ extern "C" void sparseAffineForward(
     sparseAffineForwardKernel<<<grid, threads>>>(
         maxInputSize,
         outputSize,
+        oppSize,
+        weights,
+        biases,
+        inputs,
+        outputs,
+        nullptr,
+        0.0F,
+        0.0F,
+        0.0F,
+        false
+    );
+}
+
+extern "C" void sparseAffineActivateForward(
+    const size_t batchSize,
+    const size_t maxInputSize,
+    const size_t outputSize,
+    const size_t oppSize,
+    const float* weights,
+    const float* biases,
+    const Feat* inputs,
+    float* outputs,
+    float* activated,
+    const float min,
+    const float max,
+    const float slope,
+    const bool square)
+{
+    const size_t numChunks = (outputSize + static_cast<size_t>(1023)) / static_cast<size_t>(1024);
+
+    dim3 grid(numChunks, batchSize);
+
+    const size_t threads = (numChunks == 1) ? outputSize : 1024;
+
+    sparseAffineForwardKernel<<<grid, threads>>>(
+        maxInputSize,
+        outputSize,
+        oppSize,
         weights,
         biases,
         inputs,
-        outputs
+        outputs,
+        activated,
+        min,
+        max,
+        slope,
+        square
     );
 }
 
@@ -244,6 +360,7 @@ extern "C" void sparseAffineBackward(
     const size_t batchSize,
     const size_t maxInputSize,
     const size_t outputSize,
+    const size_t oppSize,
     float* weightsGrad,
     float* biasesGrad,
     const Feat* inputs,
@@ -260,6 +377,7 @@ extern "C" void sparseAffineBackward(
     sparseAffineBackwardKernel<<<grid, threads>>>(
         maxInputSize,
         outputSize,
+        oppSize,
         weightsGrad,
         biasesGrad,
         inputs,
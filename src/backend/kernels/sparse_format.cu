@@ -0,0 +1,272 @@
+#include <cuda.h>
+#include <cuda_runtime.h>
+#include <cstdint>
+
+struct Feat {
+    int32_t our;
+    int32_t opp;
+};
+
+constexpr size_t threadsPerBlock = static_cast<size_t>(1024);
+
+static size_t numBlocks(const size_t size)
+{
+    return (size + threadsPerBlock - 1) / threadsPerBlock;
+}
+
+// Turns the count of entries in each row, stored at `offsets[row + 1]`, into
+// the offset of the first entry of each row, with the total at the end. Run as
+// a single block, which scans each chunk of rows in turn.
+__global__ void countsToOffsetsKernel(const size_t rows, int32_t* offsets)
+{
+    __shared__ int32_t chunk[1024];
+    __shared__ int32_t carry;
+
+    if (threadIdx.x == 0)
+    {
+        carry = 0;
+        offsets[0] = 0;
+    }
+
+    for (size_t start = 0; start < rows; start += blockDim.x)
+    {
+        const size_t row = start + threadIdx.x;
+        chunk[threadIdx.x] = row < rows ? offsets[row + 1] : 0;
+        __syncthreads();
+
+        for (size_t stride = 1; stride < blockDim.x; stride *= 2)
+        {
+            const int32_t add = threadIdx.x >= stride ? chunk[threadIdx.x - stride] : 0;
+            __syncthreads();
+            chunk[threadIdx.x] += add;
+            __syncthreads();
+        }
+
+        if (row < rows)
+            offsets[row + 1] = carry + chunk[threadIdx.x];
+
+        __syncthreads();
+
+        if (threadIdx.x == blockDim.x - 1)
+            carry += chunk[threadIdx.x];
+
+        __syncthreads();
+    }
+}
+
+__global__ void ellCountKernel(const size_t batchSize, const size_t maxInputSize, const Feat* inputs, int32_t* offsets)
+{
+    const size_t idx = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (idx >= batchSize)
+        return;
+
+    const Feat* thisInput = inputs + maxInputSize * idx;
+    int32_t count = 0;
+
+    while (static_cast<size_t>(count) < maxInputSize && thisInput[count].our != -1)
+        count++;
+
+    offsets[idx + 1] = count;
+}
+
+__global__ void ellFillKernel(
+    const size_t batchSize,
+    const size_t maxInputSize,
+    const bool opp,
+    const Feat* inputs,
+    const int32_t* offsets,
+    int32_t* cols,
+    float* values)
+{
+    const size_t idx = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (idx >= batchSize)
+        return;
+
+    const Feat* thisInput = inputs + maxInputSize * idx;
+    const int32_t start = offsets[idx];
+    const int32_t end = offsets[idx + 1];
+
+    for (int32_t entry = start; entry < end; entry++)
+    {
+        const Feat feat = thisInput[entry - start];
+        cols[entry] = opp ? feat.opp : feat.our;
+        values[entry] = 1.0F;
+    }
+}
+
+__global__ void cooCountKernel(const size_t nnz, const int32_t* rowIndices, int32_t* offsets)
+{
+    const size_t entry = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (entry < nnz)
+        atomicAdd(&offsets[rowIndices[entry] + 1], 1);
+}
+
+__global__ void cooScatterKernel(
+    const size_t nnz,
+    const int32_t* rowIndices,
+    const int32_t* cols,
+    const float* values,
+    int32_t* cursors,
+    int32_t* csrCols,
+    float* csrValues)
+{
+    const size_t entry = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (entry >= nnz)
+        return;
+
+    const int32_t dest = atomicAdd(&cursors[rowIndices[entry]], 1);
+    csrCols[dest] = cols[entry];
+    csrValues[dest] = values[entry];
+}
+
+__global__ void csrToCooKernel(const size_t rows, const int32_t* offsets, int32_t* rowIndices)
+{
+    const size_t row = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (row >= rows)
+        return;
+
+    for (int32_t entry = offsets[row]; entry < offsets[row + 1]; entry++)
+        rowIndices[entry] = static_cast<int32_t>(row);
+}
+
+__global__ void csrAffineForwardKernel(
+    const size_t outputSize,
+    const float* weights,
+    const float* biases,
+    const int32_t* offsets,
+    const int32_t* cols,
+    const float* values,
+    float* outputs)
+{
+    const size_t elem = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (elem >= outputSize)
+        return;
+
+    float val = biases[elem];
+
+    for (int32_t entry = offsets[blockIdx.y]; entry < offsets[blockIdx.y + 1]; entry++)
+        val += values[entry] * weights[static_cast<size_t>(cols[entry]) * outputSize + elem];
+
+    outputs[outputSize * blockIdx.y + elem] = val;
+}
+
+// One thread per output of each entry, as there can be too many entries for the grid's y dimension.
+__global__ void cooAffineBackwardKernel(
+    const size_t nnz,
+    const size_t outputSize,
+    float* weightsGrad,
+    const int32_t* rowIndices,
+    const int32_t* cols,
+    const float* values,
+    const float* errors)
+{
+    const size_t idx = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (idx >= nnz * outputSize)
+        return;
+
+    const size_t entry = idx / outputSize;
+    const size_t elem = idx % outputSize;
+
+    const float error = values[entry] * errors[outputSize * rowIndices[entry] + elem];
+    atomicAdd(&weightsGrad[static_cast<size_t>(cols[entry]) * outputSize + elem], error);
+}
+
+__global__ void rowsBiasBackwardKernel(const size_t outputSize, float* biasesGrad, const float* errors)
+{
+    const size_t elem = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (elem < outputSize)
+        atomicAdd(&biasesGrad[elem], errors[outputSize * blockIdx.y + elem]);
+}
+
+extern "C" void sparseEllToCsr(
+    const size_t batchSize,
+    const size_t maxInputSize,
+    const bool opp,
+    const Feat* inputs,
+    int32_t* offsets,
+    int32_t* cols,
+    float* values)
+{
+    ellCountKernel<<<numBlocks(batchSize), threadsPerBlock>>>(batchSize, maxInputSize, inputs, offsets);
+    countsToOffsetsKernel<<<1, threadsPerBlock>>>(batchSize, offsets);
+    ellFillKernel<<<numBlocks(batchSize), threadsPerBlock>>>(batchSize, maxInputSize, opp, inputs, offsets, cols, values);
+}
+
+extern "C" void sparseCooToCsr(
+    const size_t nnz,
+    const size_t rows,
+    const int32_t* rowIndices,
+    const int32_t* cols,
+    const float* values,
+    int32_t* offsets,
+    int32_t* cursors,
+    int32_t* csrCols,
+    float* csrValues)
+{
+    cudaMemset(offsets, 0, (rows + 1) * sizeof(int32_t));
+
+    if (nnz > 0)
+        cooCountKernel<<<numBlocks(nnz), threadsPerBlock>>>(nnz, rowIndices, offsets);
+
+    countsToOffsetsKernel<<<1, threadsPerBlock>>>(rows, offsets);
+    cudaMemcpy(cursors, offsets, rows * sizeof(int32_t), cudaMemcpyDeviceToDevice);
+
+    if (nnz > 0)
+        cooScatterKernel<<<numBlocks(nnz), threadsPerBlock>>>(nnz, rowIndices, cols, values, cursors, csrCols, csrValues);
+}
+
+extern "C" void sparseCsrToCoo(const size_t rows, const int32_t* offsets, int32_t* rowIndices)
+{
+    if (rows > 0)
+        csrToCooKernel<<<numBlocks(rows), threadsPerBlock>>>(rows, offsets, rowIndices);
+}
+
+extern "C" void sparseCsrAffineForward(
+    const size_t rows,
+    const size_t outputSize,
+    const float* weights,
+    const float* biases,
+    const int32_t* offsets,
+    const int32_t* cols,
+    const float* values,
+    float* outputs)
+{
+    const size_t numChunks = numBlocks(outputSize);
+    const size_t threads = (numChunks == 1) ? outputSize : threadsPerBlock;
+
+    dim3 grid(numChunks, rows);
+
+    csrAffineForwardKernel<<<grid, threads>>>(outputSize, weights, biases, offsets, cols, values, outputs);
+}
+
+extern "C" void sparseCooAffineBackward(
+    const size_t nnz,
+    const size_t rows,
+    const size_t outputSize,
+    float* weightsGrad,
+    float* biasesGrad,
+    const int32_t* rowIndices,
+    const int32_t* cols,
+    const float* values,
+    const float* errors)
+{
+    if (nnz > 0)
+    {
+        const size_t size = nnz * outputSize;
+        cooAffineBackwardKernel<<<numBlocks(size), threadsPerBlock>>>(nnz, outputSize, weightsGrad, rowIndices, cols, values, errors);
+    }
+
+    const size_t numChunks = numBlocks(outputSize);
+    const size_t threads = (numChunks == 1) ? outputSize : threadsPerBlock;
+
+    dim3 grid(numChunks, rows);
+    rowsBiasBackwardKernel<<<grid, threads>>>(outputSize, biasesGrad, errors);
+}
@@ -1,5 +1,6 @@
 #include <cuda.h>
 #include <cuda_runtime.h>
+#include "fused.cuh"
 
 constexpr size_t threads = 1024;
 
@@ -35,3 +36,252 @@ extern "C" void splatAdd(
         out
     );
 }
+
+__global__ void splatAddActivateKernel(
+    const size_t batchSize,
+    const size_t stride,
+    const float* inp,
+    float* pre,
+    float* out,
+    const float min,
+    const float max,
+    const float slope,
+    const bool square)
+{
+    const size_t offset = blockIdx.y;
+    const size_t tid = threadIdx.x;
+    const size_t myId = blockDim.x * blockIdx.x + tid;
+
+    if (myId >= batchSize)
+        return;
+
+    const size_t idx = offset + stride * myId;
+    const float val = pre[idx] + inp[offset];
+    pre[idx] = val;
+    out[idx] = fusedActivate(val, min, max, slope, square);
+}
+
+/*
+Fuses `splatAdd` with an activation, adding `inp` to each tensor of
+`pre` in place and writing the activation of the result to `out`.
+*/
+extern "C" void splatAddActivate(
+    const size_t batchSize,
+    const size_t tensorSize,
+    const float* inp,
+    float* pre,
+    float* out,
+    const float min,
+    const float max,
+    const float slope,
+    const bool square)
+{
+    const size_t grid_x = (batchSize + threads - 1) / threads;
+    const dim3 grid(grid_x, tensorSize);
+
+    splatAddActivateKernel<<<grid, threads>>>(
+        batchSize,
+        tensorSize,
+        inp,
+        pre,
+        out,
+        min,
+        max,
+        slope,
+        square
+    );
+}
+
+/*
+Broadcasts a scalar (`channels == 1`) or a vector (`channels == width`)
+across every tensor in a batch, accumulating its gradient with atomics.
+*/
+__global__ void broadcastAddKernel(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* inp,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    out[i] += inp[(i % width) % channels];
+}
+
+extern "C" void broadcastAdd(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* inp,
+    float* out)
+{
+    const size_t numBlocks = (size + threads - 1) / threads;
+    broadcastAddKernel<<<numBlocks, threads>>>(size, width, channels, inp, out);
+}
+
+__global__ void backpropBroadcastAddKernel(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* inp,
+    float* grad)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    atomicAdd(&grad[(i % width) % channels], inp[i]);
+}
+
+extern "C" void backpropBroadcastAdd(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* inp,
+    float* grad)
+{
+    const size_t numBlocks = (size + threads - 1) / threads;
+    backpropBroadcastAddKernel<<<numBlocks, threads>>>(size, width, channels, inp, grad);
+}
+
+__global__ void broadcastMulKernel(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* inp,
+    const float* x,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    out[i] = inp[(i % width) % channels] * x[i];
+}
+
+extern "C" void broadcastMul(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* inp,
+    const float* x,
+    float* out)
+{
+    const size_t numBlocks = (size + threads - 1) / threads;
+    broadcastMulKernel<<<numBlocks, threads>>>(size, width, channels, inp, x, out);
+}
+
+__global__ void backpropBroadcastMulKernel(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* inp,
+    float* grad,
+    const float* errors,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const size_t channel = (i % width) % channels;
+    const float err = errors[i];
+
+    atomicAdd(&grad[channel], err * out[i]);
+    out[i] = err * inp[channel];
+}
+
+extern "C" void backpropBroadcastMul(
+    const size_t size,
+    const size_t width,
+    const size_t channels,
+    const float* inp,
+    float* grad,
+    const float* errors,
+    float* out)
+{
+    const size_t numBlocks = (size + threads - 1) / threads;
+    backpropBroadcastMulKernel<<<numBlocks, threads>>>(size, width, channels, inp, grad, errors, out);
+}
+
+/*
+Element-wise product of two batches, where each tensor of `a` has
+`channels` elements and is broadcast across its tensor of `x`.
+*/
+__global__ void hadamardKernel(
+    const size_t batchSize,
+    const size_t width,
+    const size_t channels,
+    const float* a,
+    const float* x,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * width)
+        return;
+
+    const size_t idx = i / width;
+    out[i] = a[channels * idx + (i % width) % channels] * x[i];
+}
+
+extern "C" void hadamard(
+    const size_t batchSize,
+    const size_t width,
+    const size_t channels,
+    const float* a,
+    const float* x,
+    float* out)
+{
+    const size_t numBlocks = (batchSize * width + threads - 1) / threads;
+    hadamardKernel<<<numBlocks, threads>>>(batchSize, width, channels, a, x, out);
+}
+
+// each thread handles every element using one entry of `a`, so no atomics are needed
+__global__ void backpropHadamardKernel(
+    const size_t batchSize,
+    const size_t width,
+    const size_t channels,
+    const float* a,
+    float* aGrad,
+    const float* errors,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= batchSize * channels)
+        return;
+
+    const size_t idx = i / channels;
+    const size_t c = i % channels;
+    const float val = a[i];
+    float grad = 0.0F;
+
+    for (size_t j = width * idx + c; j < width * (idx + 1); j += channels)
+    {
+        const float err = errors[j];
+        grad += err * out[j];
+        out[j] = err * val;
+    }
+
+    aGrad[i] = grad;
+}
+
+extern "C" void backpropHadamard(
+    const size_t batchSize,
+    const size_t width,
+    const size_t channels,
+    const float* a,
+    float* aGrad,
+    const float* errors,
+    float* out)
+{
+    const size_t numBlocks = (batchSize * channels + threads - 1) / threads;
+    backpropHadamardKernel<<<numBlocks, threads>>>(batchSize, width, channels, a, aGrad, errors, out);
+}
@@ -2,18 +2,16 @@
 #include <cuda_runtime.h>
 
 constexpr size_t threadsPerBlock = static_cast<size_t>(1024);
-constexpr float B1 = 0.9F;
-constexpr float B2 = 0.999F;
-constexpr float B1P = 1.0F - B1;
-constexpr float B2P = 1.0F - B2;
 constexpr float Epsilon = 0.00000001F;
-constexpr float MaxWeight = 1.98F;
 
 __global__ void updateWeight(
     const size_t networkSize,
     const float decay,
     const float adj,
     const float rate,
+    const float maxWeight,
+    const float beta1,
+    const float beta2,
     float* network,
     float* momentum,
     float* velocity,
@@ -29,11 +27,11 @@ __global__ void updateWeight(
     float param = network[i];
     param *= decay;
 
-    momentum[i] = B1 * momentum[i] + B1P * grad;
-    velocity[i] = B2 * velocity[i] + B2P * grad * grad;
+    momentum[i] = beta1 * momentum[i] + (1.0F - beta1) * grad;
+    velocity[i] = beta2 * velocity[i] + (1.0F - beta2) * grad * grad;
 
     param -= rate * momentum[i] / (sqrt(velocity[i]) + Epsilon);
-    param = min(max(param, -MaxWeight), MaxWeight);
+    param = min(max(param, -maxWeight), maxWeight);
 
     network[i] = param;
 }
@@ -43,6 +41,9 @@ extern "C" void updateWeights(
     const float decay,
     const float adj,
     const float rate,
+    const float maxWeight,
+    const float beta1,
+    const float beta2,
     float* network,
     float* momentum,
     float* velocity,
@@ -54,9 +55,277 @@ extern "C" void updateWeights(
         decay,
         adj,
         rate,
+        maxWeight,
+        beta1,
+        beta2,
         network,
         momentum,
         velocity,
         gradients
     );
-}
\ No newline at end of file
+}
+
+__global__ void updateWeightSGD(
+    const size_t networkSize,
+    const float decay,
+    const float adj,
+    const float rate,
+    const float maxWeight,
+    const float beta,
+    const bool nesterov,
+    float* network,
+    float* momentum,
+    const float* gradients)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= networkSize)
+        return;
+
+    const float grad = adj * gradients[i];
+
+    float param = network[i];
+    param *= decay;
+
+    momentum[i] = beta * momentum[i] + grad;
+    const float step = nesterov ? grad + beta * momentum[i] : momentum[i];
+
+    param -= rate * step;
+    param = min(max(param, -maxWeight), maxWeight);
+
+    network[i] = param;
+}
+
+extern "C" void updateWeightsSGD(
+    const size_t networkSize,
+    const float decay,
+    const float adj,
+    const float rate,
+    const float maxWeight,
+    const float beta,
+    const bool nesterov,
+    float* network,
+    float* momentum,
+    const float* gradients)
+{
+    const size_t numBlocks = (networkSize + threadsPerBlock - 1) / threadsPerBlock;
+    updateWeightSGD<<<numBlocks, threadsPerBlock>>>(
+        networkSize,
+        decay,
+        adj,
+        rate,
+        maxWeight,
+        beta,
+        nesterov,
+        network,
+        momentum,
+        gradients
+    );
+}
+
+__global__ void updateWeightRAdam(
+    const size_t networkSize,
+    const float decay,
+    const float adj,
+    const float rate,
+    const float maxWeight,
+    const float beta1,
+    const float beta2,
+    const float momentumScale,
+    const float rectification,
+    float* network,
+    float* momentum,
+    float* velocity,
+    const float* gradients)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= networkSize)
+        return;
+
+    const float grad = adj * gradients[i];
+
+    float param = network[i];
+    param *= decay;
+
+    momentum[i] = beta1 * momentum[i] + (1.0F - beta1) * grad;
+    velocity[i] = beta2 * velocity[i] + (1.0F - beta2) * grad * grad;
+
+    const float step = momentumScale * momentum[i];
+
+    if (rectification > 0.0F)
+        param -= rate * rectification * step / (sqrt(velocity[i]) + Epsilon);
+    else
+        param -= rate * step;
+
+    param = min(max(param, -maxWeight), maxWeight);
+
+    network[i] = param;
+}
+
+extern "C" void updateWeightsRAdam(
+    const size_t networkSize,
+    const float decay,
+    const float adj,
+    const float rate,
+    const float maxWeight,
+    const float beta1,
+    const float beta2,
+    const float momentumScale,
+    const float rectification,
+    float* network,
+    float* momentum,
+    float* velocity,
+    const float* gradients)
+{
+    const size_t numBlocks = (networkSize + threadsPerBlock - 1) / threadsPerBlock;
+    updateWeightRAdam<<<numBlocks, threadsPerBlock>>>(
+        networkSize,
+        decay,
+        adj,
+        rate,
+        maxWeight,
+        beta1,
+        beta2,
+        momentumScale,
+        rectification,
+        network,
+        momentum,
+        velocity,
+        gradients
+    );
+}
+
+__global__ void lambMomentsKernel(
+    const size_t networkSize,
+    const float decay,
+    const float adj,
+    const float beta1,
+    const float beta2,
+    const float momentumScale,
+    const float velocityScale,
+    const float* network,
+    float* momentum,
+    float* velocity,
+    float* gradients)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= networkSize)
+        return;
+
+    const float grad = adj * gradients[i];
+
+    momentum[i] = beta1 * momentum[i] + (1.0F - beta1) * grad;
+    velocity[i] = beta2 * velocity[i] + (1.0F - beta2) * grad * grad;
+
+    gradients[i] = momentumScale * momentum[i] / (sqrt(velocityScale * velocity[i]) + Epsilon) + decay * network[i];
+}
+
+extern "C" void lambMoments(
+    const size_t networkSize,
+    const float decay,
+    const float adj,
+    const float beta1,
+    const float beta2,
+    const float momentumScale,
+    const float velocityScale,
+    const float* network,
+    float* momentum,
+    float* velocity,
+    float* gradients)
+{
+    const size_t numBlocks = (networkSize + threadsPerBlock - 1) / threadsPerBlock;
+    lambMomentsKernel<<<numBlocks, threadsPerBlock>>>(
+        networkSize,
+        decay,
+        adj,
+        beta1,
+        beta2,
+        momentumScale,
+        velocityScale,
+        network,
+        momentum,
+        velocity,
+        gradients
+    );
+}
+
+__global__ void lambApplyKernel(
+    const size_t size,
+    const float rate,
+    const float maxWeight,
+    float* network,
+    const float* steps)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const float param = network[i] - rate * steps[i];
+    network[i] = min(max(param, -maxWeight), maxWeight);
+}
+
+extern "C" void lambApply(const size_t size, const float rate, const float maxWeight, float* network, const float* steps)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    lambApplyKernel<<<numBlocks, threadsPerBlock>>>(size, rate, maxWeight, network, steps);
+}
+
+__global__ void lookaheadSyncKernel(const size_t size, const float alpha, float* network, float* slow)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const float param = slow[i] + alpha * (network[i] - slow[i]);
+    slow[i] = param;
+    network[i] = param;
+}
+
+extern "C" void lookaheadSync(const size_t size, const float alpha, float* network, float* slow)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    lookaheadSyncKernel<<<numBlocks, threadsPerBlock>>>(size, alpha, network, slow);
+}
+
+__global__ void centraliseGradientsKernel(const size_t fanIn, const size_t fanOut, float* gradients)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= fanOut)
+        return;
+
+    float mean = 0.0F;
+    for (size_t j = 0; j < fanIn; j++)
+        mean += gradients[j * fanOut + i];
+
+    mean /= static_cast<float>(fanIn);
+
+    for (size_t j = 0; j < fanIn; j++)
+        gradients[j * fanOut + i] -= mean;
+}
+
+extern "C" void centraliseGradients(const size_t fanIn, const size_t fanOut, float* gradients)
+{
+    const size_t numBlocks = (fanOut + threadsPerBlock - 1) / threadsPerBlock;
+    centraliseGradientsKernel<<<numBlocks, threadsPerBlock>>>(fanIn, fanOut, gradients);
+}
+
+__global__ void movingAverageKernel(const size_t size, const float alpha, const float* network, float* average)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    average[i] += alpha * (network[i] - average[i]);
+}
+
+extern "C" void movingAverage(const size_t size, const float alpha, const float* network, float* average)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    movingAverageKernel<<<numBlocks, threadsPerBlock>>>(size, alpha, network, average);
+}
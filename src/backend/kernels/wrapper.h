@@ -7,4 +7,5 @@ This file exists to generate CUDA bindings for Rust, via bindgen.
 #include <cuda.h>
 #include <cuda_runtime.h>
 #include <cublas_v2.h>
+#include <nvml.h>
 #endif
\ No newline at end of file
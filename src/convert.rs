@@ -0,0 +1,366 @@
+//! Multi-threaded conversion of position data into shuffled shards of
+//! `ChessBoard`s, optionally filtering and rescoring positions in the
+//! same pass, e.g. to relabel a dataset with evals from an existing net.
+//!
+//! Castling rights are kept in the otherwise unused `extra` bytes of each
+//! `ChessBoard`, as the files of the rooks that each side can castle with,
+//! so that Chess960 positions, with rooks starting on any file, convert
+//! without losing them. The last byte holds the halfmove clock, for the
+//! fifty-move rule. Data converted by other tools has neither, so has no
+//! castling rights and a halfmove clock of zero.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+use bulletformat::{
+    chess::{CudADFormat, MarlinFormat},
+    BulletFormat, ChessBoard,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{util, value_match::Position};
+
+/// Files of the rooks that the side to move and the other side can castle
+/// with, as bitmasks, in that order. Files are the same from either side.
+pub fn castling_rook_files(board: &ChessBoard) -> [u8; 2] {
+    [board.extra[0], board.extra[1]]
+}
+
+pub fn set_castling_rook_files(board: &mut ChessBoard, files: [u8; 2]) {
+    board.extra[0] = files[0];
+    board.extra[1] = files[1];
+}
+
+/// Halfmoves since the last capture or pawn move.
+pub fn halfmove_clock(board: &ChessBoard) -> u8 {
+    board.extra[2]
+}
+
+pub fn set_halfmove_clock(board: &mut ChessBoard, halfmoves: u8) {
+    board.extra[2] = halfmoves;
+}
+
+/// Parses a position in the text format `<fen> | <score> | <result>`,
+/// including its castling rights and halfmove clock if the FEN has them.
+pub fn parse_text_position(line: &str) -> Result<ChessBoard, String> {
+    let mut board = line.parse::<ChessBoard>()?;
+
+    let fen = line.split('|').next().unwrap_or_default();
+    if let Ok(pos) = fen.parse::<Position>() {
+        set_castling_rook_files(&mut board, pos.castling_rook_files());
+        set_halfmove_clock(&mut board, pos.halfmoves());
+    }
+
+    Ok(board)
+}
+
+/// Record formats that can be converted into `ChessBoard`s.
+pub trait IntoChessBoard: BulletFormat {
+    fn into_board(self) -> ChessBoard;
+}
+
+impl IntoChessBoard for ChessBoard {
+    fn into_board(self) -> ChessBoard {
+        self
+    }
+}
+
+impl IntoChessBoard for CudADFormat {
+    fn into_board(self) -> ChessBoard {
+        self.into()
+    }
+}
+
+/// Layout of a marlinformat record, whose fields `MarlinFormat` keeps private.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawMarlinFormat {
+    occ: u64,
+    pcs: [u8; 16],
+    stm_enp: u8,
+    hfm: u8,
+    fmc: u16,
+    score: i16,
+    result: u8,
+    extra: u8,
+}
+
+const _MARLIN_SIZE: () = assert!(std::mem::size_of::<RawMarlinFormat>() == std::mem::size_of::<MarlinFormat>());
+
+impl IntoChessBoard for MarlinFormat {
+    /// Unmoved rooks, which marlinformat uses to mark the rooks that can
+    /// castle, become rooks with castling rights, rather than the bishops
+    /// that the conversion from `bulletformat` turns them into.
+    fn into_board(self) -> ChessBoard {
+        let raw: RawMarlinFormat = unsafe { std::mem::transmute(self) };
+        let stm = usize::from(raw.stm_enp >> 7);
+
+        let mut bbs = [0; 8];
+        let mut castling = [0; 2];
+
+        for (piece, square) in self {
+            let (colour, piece) = (usize::from(piece >> 3), usize::from(piece & 7));
+            let bit = 1 << square;
+
+            // unmoved rook
+            let piece = if piece == 6 {
+                castling[colour ^ stm] |= 1 << (square % 8);
+                3
+            } else {
+                piece
+            };
+
+            bbs[colour] |= bit;
+            bbs[2 + piece] |= bit;
+        }
+
+        let result = f32::from(raw.result) / 2.0;
+        let mut board = ChessBoard::from_raw(bbs, stm, raw.score, result).expect("Invalid marlinformat position!");
+        set_castling_rook_files(&mut board, castling);
+        set_halfmove_clock(&mut board, raw.hfm);
+        board
+    }
+}
+
+/// A stream of positions to be converted.
+pub trait PositionSource {
+    /// Appends up to `max` positions to `buf`, returning
+    /// the number added, which is 0 once exhausted.
+    fn read_chunk(&mut self, buf: &mut Vec<ChessBoard>, max: usize) -> io::Result<usize>;
+}
+
+/// Reads a file of fixed size records, e.g. `MarlinFormat` or `ChessBoard`.
+pub struct BinSource<T> {
+    reader: BufReader<File>,
+    bytes: Vec<u8>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> BinSource<T> {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self { reader: BufReader::new(File::open(path)?), bytes: Vec::new(), phantom: PhantomData })
+    }
+}
+
+impl<T: IntoChessBoard> PositionSource for BinSource<T> {
+    fn read_chunk(&mut self, buf: &mut Vec<ChessBoard>, max: usize) -> io::Result<usize> {
+        let size = std::mem::size_of::<T>();
+        self.bytes.resize(max * size, 0);
+
+        let mut read = 0;
+        while read < self.bytes.len() {
+            match self.reader.read(&mut self.bytes[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+
+        if read % size != 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "File ends partway through a position!"));
+        }
+
+        // the byte buffer is not necessarily aligned for `T`
+        let records = self.bytes[..read].chunks_exact(size);
+        let count = records.len();
+        buf.extend(records.map(|bytes| unsafe { bytes.as_ptr().cast::<T>().read_unaligned() }.into_board()));
+        Ok(count)
+    }
+}
+
+/// Reads positions in the text format `<fen> | <score> | <result>`,
+/// skipping (and reporting) any lines that fail to parse.
+pub struct TextSource {
+    lines: io::Lines<BufReader<File>>,
+    line: usize,
+}
+
+impl TextSource {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self { lines: BufReader::new(File::open(path)?).lines(), line: 0 })
+    }
+}
+
+impl PositionSource for TextSource {
+    fn read_chunk(&mut self, buf: &mut Vec<ChessBoard>, max: usize) -> io::Result<usize> {
+        let mut added = 0;
+
+        while added < max {
+            let Some(line) = self.lines.next() else { break };
+            let line = line?;
+            self.line += 1;
+
+            match parse_text_position(&line) {
+                Ok(pos) => {
+                    buf.push(pos);
+                    added += 1;
+                }
+                Err(error) => println!("Error Parsing Line {}: {error}", self.line),
+            }
+        }
+
+        Ok(added)
+    }
+}
+
+/// Counts of positions seen during a conversion.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConvertSummary {
+    pub read: usize,
+    pub filtered: usize,
+    pub written: usize,
+}
+
+impl std::fmt::Display for ConvertSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Read {} positions, filtered {}, wrote {}", self.read, self.filtered, self.written)
+    }
+}
+
+type Filter<'a> = Box<dyn Fn(&ChessBoard) -> bool + Sync + 'a>;
+type Rescore<'a> = Box<dyn Fn(&ChessBoard) -> i16 + Sync + 'a>;
+
+/// Converts positions into `shards` files named `shard-<i>.bin`, each of
+/// which is shuffled in memory once written, so should be small enough
+/// to fit. Positions are distributed randomly across the shards, so the
+/// shards can be concatenated or interleaved without reshuffling.
+pub struct Converter<'a> {
+    threads: usize,
+    shards: usize,
+    seed: u64,
+    filter: Option<Filter<'a>>,
+    rescore: Option<Rescore<'a>>,
+}
+
+impl<'a> Converter<'a> {
+    const CHUNK_PER_THREAD: usize = 16_384;
+
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "Must use at least one thread!");
+        Self { threads, shards: 1, seed: 0, filter: None, rescore: None }
+    }
+
+    pub fn shards(mut self, shards: usize) -> Self {
+        assert!(shards > 0, "Must output at least one shard!");
+        self.shards = shards;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Only positions for which `filter` returns true are kept.
+    pub fn filter(mut self, filter: impl Fn(&ChessBoard) -> bool + Sync + 'a) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Replaces the score of each kept position, which is
+    /// relative to the side to move, e.g. with a net's eval.
+    pub fn rescore(mut self, rescore: impl Fn(&ChessBoard) -> i16 + Sync + 'a) -> Self {
+        self.rescore = Some(Box::new(rescore));
+        self
+    }
+
+    pub fn run(&self, source: &mut impl PositionSource, out_dir: &str) -> io::Result<ConvertSummary> {
+        std::fs::create_dir_all(out_dir)?;
+
+        let paths: Vec<_> = (0..self.shards).map(|i| format!("{out_dir}/shard-{i}.bin")).collect();
+        let mut outputs = Vec::with_capacity(self.shards);
+        for path in &paths {
+            outputs.push(BufWriter::new(File::create(path)?));
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut summary = ConvertSummary::default();
+        let mut buf = Vec::new();
+        let mut shard_bufs = vec![Vec::new(); self.shards];
+
+        loop {
+            buf.clear();
+            let read = source.read_chunk(&mut buf, self.threads * Self::CHUNK_PER_THREAD)?;
+            if read == 0 {
+                break;
+            }
+
+            summary.read += read;
+            let kept = self.process(&buf);
+            summary.filtered += read - kept.len();
+            summary.written += kept.len();
+
+            for pos in kept {
+                shard_bufs[rng.gen_range(0..self.shards)].push(pos);
+            }
+
+            for (output, shard) in outputs.iter_mut().zip(shard_bufs.iter_mut()) {
+                ChessBoard::write_to_bin(output, shard)?;
+                shard.clear();
+            }
+
+            print!("> Converted {}\r", summary.read);
+            let _ = io::stdout().flush();
+        }
+
+        println!();
+
+        for mut output in outputs {
+            output.flush()?;
+        }
+
+        for (i, path) in paths.iter().enumerate() {
+            println!("> Shuffling Shard {}/{}", i + 1, self.shards);
+            shuffle_file(path, &mut rng)?;
+        }
+
+        Ok(summary)
+    }
+
+    fn process(&self, positions: &[ChessBoard]) -> Vec<ChessBoard> {
+        let chunk_size = positions.len().div_ceil(self.threads);
+
+        std::thread::scope(|s| {
+            let handles: Vec<_> = positions
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || {
+                        let mut kept = Vec::with_capacity(chunk.len());
+
+                        for &pos in chunk {
+                            if self.filter.as_ref().is_some_and(|filter| !filter(&pos)) {
+                                continue;
+                            }
+
+                            let mut pos = pos;
+                            if let Some(rescore) = &self.rescore {
+                                pos.score = rescore(&pos);
+                            }
+
+                            kept.push(pos);
+                        }
+
+                        kept
+                    })
+                })
+                .collect();
+
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+}
+
+fn shuffle_file(path: &str, rng: &mut StdRng) -> io::Result<()> {
+    let size = std::fs::metadata(path)?.len() as usize;
+    let mut data = vec![ChessBoard::default(); size / std::mem::size_of::<ChessBoard>()];
+    File::open(path)?.read_exact(util::to_slice_with_lifetime_mut(&mut data))?;
+
+    for i in (1..data.len()).rev() {
+        data.swap(i, rng.gen_range(0..=i));
+    }
+
+    ChessBoard::write_to_bin(&mut BufWriter::new(File::create(path)?), &data)
+}
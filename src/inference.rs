@@ -0,0 +1,395 @@
+//! Pure CPU evaluation of unquantised networks, for sanity checking
+//! trained nets without a GPU. Build with `TrainerBuilder::build_inference`.
+
+use std::{fs::File, io::Read};
+
+use crate::{
+    inputs::InputType, outputs::OutputBuckets, Activation, AttentionDescription, Axis, ConvolutionDescription,
+    Reduction,
+};
+
+#[derive(Clone)]
+pub(crate) enum Layer {
+    Activate(Activation),
+    Affine { inputs: usize, outputs: usize },
+    Attention { desc: AttentionDescription },
+    BatchNorm { size: usize },
+    Concat { sources: Vec<usize> },
+    Convolution { desc: ConvolutionDescription },
+    Gather { size: usize },
+    GroupedAffine { groups: usize, inputs: usize, outputs: usize },
+    Multiply { source: usize },
+    Norm { size: usize },
+    PairwiseMul { stride: usize, activation: Option<Activation> },
+    PReLU { channels: usize },
+    Reduce { reduction: Reduction, axis: Axis, cols: usize },
+    Select { size: usize },
+    SharedAffine { source: usize },
+    Slice { start: usize, len: usize },
+    Softmax { log: bool },
+}
+
+#[derive(Clone)]
+pub struct InferenceNet<T, U> {
+    input_getter: T,
+    bucket_getter: U,
+    ft_size: usize,
+    /// Outputs used from the accumulator of the opposing perspective,
+    /// 0 if the network only has a single perspective.
+    opp_size: usize,
+    layers: Vec<(Layer, bool)>,
+    params: Vec<f32>,
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> InferenceNet<T, U> {
+    pub(crate) fn new(
+        input_getter: T,
+        bucket_getter: U,
+        ft_size: usize,
+        opp_size: usize,
+        layers: Vec<(Layer, bool)>,
+    ) -> Self {
+        let mut size = (input_getter.size() + 1) * ft_size;
+
+        for (layer, _) in &layers {
+            match layer {
+                Layer::Affine { inputs, outputs } => size += (inputs + 1) * outputs,
+                Layer::Attention { desc } => size += desc.num_weights() + desc.num_biases(),
+                Layer::BatchNorm { size: norm_size } => size += 4 * norm_size,
+                Layer::Convolution { desc } => size += desc.num_weights() + desc.output_channels,
+                Layer::Gather { size: embedding_size } => size += embedding_size * U::BUCKETS,
+                Layer::GroupedAffine { groups, inputs, outputs } => size += (inputs / groups + 1) * outputs,
+                Layer::Norm { size: norm_size } => size += 2 * norm_size,
+                Layer::PReLU { channels } => size += channels,
+                _ => {}
+            }
+        }
+
+        Self { input_getter, bucket_getter, ft_size, opp_size, layers, params: vec![0.0; size] }
+    }
+
+    pub fn net_size(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn load_weights(&mut self, params: &[f32]) {
+        assert_eq!(params.len(), self.net_size(), "Incorrect number of weights!");
+        self.params.copy_from_slice(params);
+    }
+
+    /// Loads weights from a `params.bin` file, as saved by the trainer.
+    pub fn load_weights_from_file(&mut self, path: &str) {
+        let mut file = File::open(path).unwrap_or_else(|_| panic!("Invalid File Path: {path}"));
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap_or_else(|_| panic!("Reading [{path}] failed!"));
+        assert_eq!(bytes.len(), self.net_size() * std::mem::size_of::<f32>(), "Incorrect File Size!");
+
+        for (param, chunk) in self.params.iter_mut().zip(bytes.chunks_exact(4)) {
+            *param = f32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+    }
+
+    pub fn load_from_checkpoint(&mut self, path: &str) {
+        self.load_weights_from_file(&format!("{path}/params.bin"));
+    }
+
+    /// Raw outputs of the network for the given position.
+    pub fn evaluate(&self, pos: &T::RequiredDataType) -> Vec<f32> {
+        let inp_size = self.input_getter.size();
+        let ft_weights = &self.params[..inp_size * self.ft_size];
+        let ft_biases = &self.params[inp_size * self.ft_size..(inp_size + 1) * self.ft_size];
+        let mut offset = (inp_size + 1) * self.ft_size;
+
+        let mut inputs = Vec::with_capacity(self.ft_size + self.opp_size);
+        inputs.extend_from_slice(ft_biases);
+        inputs.extend_from_slice(&ft_biases[..self.opp_size]);
+
+        for (our, opp) in self.input_getter.feature_iter(pos) {
+            let (our_out, opp_out) = inputs.split_at_mut(self.ft_size);
+
+            for (out, feat) in [(our_out, our), (opp_out, opp)] {
+                let weights = &ft_weights[self.ft_size * feat..self.ft_size * (feat + 1)];
+                for (out, weight) in out.iter_mut().zip(weights) {
+                    *out += weight;
+                }
+            }
+        }
+
+        let bucket = usize::from(self.bucket_getter.bucket(pos));
+        let mut res_inputs = Vec::new();
+        let mut in_res_block = false;
+
+        // earlier outputs are only kept if a later layer needs them
+        let keep_history =
+            self.layers.iter().any(|(layer, _)| matches!(layer, Layer::Concat { .. } | Layer::Multiply { .. }));
+        let mut history = Vec::new();
+        // where the weights of each layer start, for shared layers
+        let mut offsets = Vec::with_capacity(self.layers.len());
+
+        for (layer, layer_in_res_block) in &self.layers {
+            offsets.push(offset);
+
+            if !in_res_block && *layer_in_res_block {
+                in_res_block = true;
+                res_inputs = inputs.clone();
+            }
+
+            if in_res_block && !*layer_in_res_block {
+                in_res_block = false;
+                for (x, r) in inputs.iter_mut().zip(&res_inputs) {
+                    *x += r;
+                }
+            }
+
+            if keep_history {
+                history.push(inputs.clone());
+            }
+
+            inputs = match *layer {
+                Layer::Activate(activation) => inputs.iter().map(|&x| activate(activation, x)).collect(),
+                Layer::Affine { inputs: m, outputs: n } => {
+                    let outputs = affine(&self.params[offset..offset + (m + 1) * n], n, &inputs);
+                    offset += (m + 1) * n;
+                    outputs
+                }
+                Layer::Attention { desc } => {
+                    let num_weights = desc.num_weights();
+                    let weights = &self.params[offset..offset + num_weights];
+                    let biases = &self.params[offset + num_weights..offset + num_weights + desc.num_biases()];
+                    offset += num_weights + desc.num_biases();
+
+                    attend(&desc, weights, biases, &inputs)
+                }
+                Layer::BatchNorm { size } => {
+                    let stats = &self.params[offset..offset + 4 * size];
+                    offset += 4 * size;
+
+                    let (gamma, rest) = stats.split_at(size);
+                    let (beta, rest) = rest.split_at(size);
+                    let (mean, var) = rest.split_at(size);
+
+                    (0..size).map(|i| gamma[i] * (inputs[i] - mean[i]) / (var[i] + 1e-5).sqrt() + beta[i]).collect()
+                }
+                // 0 is the feature transformer and `i` is the output of layer `i - 1`
+                Layer::Concat { ref sources } => sources.iter().fold(inputs, |mut outputs, &source| {
+                    outputs.extend_from_slice(&history[source]);
+                    outputs
+                }),
+                Layer::Convolution { desc } => {
+                    let num_weights = desc.num_weights();
+                    let weights = &self.params[offset..offset + num_weights];
+                    let biases = &self.params[offset + num_weights..offset + num_weights + desc.output_channels];
+                    offset += num_weights + desc.output_channels;
+
+                    convolve(&desc, weights, biases, &inputs)
+                }
+                Layer::Gather { size } => {
+                    let weights = &self.params[offset..offset + size * U::BUCKETS];
+                    offset += size * U::BUCKETS;
+
+                    weights[size * bucket..size * (bucket + 1)].to_vec()
+                }
+                Layer::GroupedAffine { groups, inputs: m, outputs: n } => {
+                    let weights = &self.params[offset..offset + m * n / groups];
+                    let mut outputs = self.params[offset + m * n / groups..offset + (m / groups + 1) * n].to_vec();
+                    offset += (m / groups + 1) * n;
+
+                    // each input only feeds the outputs of its own group
+                    let group_outputs = n / groups;
+                    for (i, (x, row)) in inputs.iter().zip(weights.chunks_exact(group_outputs)).enumerate() {
+                        let group = i / (m / groups);
+                        let outputs = &mut outputs[group * group_outputs..(group + 1) * group_outputs];
+                        for (y, w) in outputs.iter_mut().zip(row) {
+                            *y += w * x;
+                        }
+                    }
+
+                    outputs
+                }
+                Layer::Multiply { source } => {
+                    let source = &history[source];
+                    inputs.iter().enumerate().map(|(i, x)| x * source[i % source.len()]).collect()
+                }
+                Layer::Norm { size } => {
+                    let gamma = &self.params[offset..offset + size];
+                    let beta = &self.params[offset + size..offset + 2 * size];
+                    offset += 2 * size;
+
+                    let mean = inputs.iter().sum::<f32>() / size as f32;
+                    let var = inputs.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / size as f32;
+                    let rstd = 1.0 / (var + 1e-5).sqrt();
+
+                    inputs.iter().zip(gamma.iter().zip(beta)).map(|(x, (g, b))| g * (x - mean) * rstd + b).collect()
+                }
+                Layer::PairwiseMul { stride, activation } => {
+                    let act = |x: f32| activation.map_or(x, |activation| activate(activation, x));
+
+                    (0..inputs.len() / 2)
+                        .map(|i| {
+                            let first = 2 * stride * (i / stride) + i % stride;
+                            act(inputs[first]) * act(inputs[first + stride])
+                        })
+                        .collect()
+                }
+                Layer::PReLU { channels } => {
+                    let slopes = &self.params[offset..offset + channels];
+                    offset += channels;
+
+                    let prelu = |(i, &x): (usize, &f32)| x.max(0.0) + slopes[i % channels] * x.min(0.0);
+                    inputs.iter().enumerate().map(prelu).collect()
+                }
+                Layer::Reduce { reduction, axis, cols } => {
+                    let rows = inputs.len() / cols;
+                    let (count, len, outer, inner) = match axis {
+                        Axis::Rows => (cols, rows, rows, 1),
+                        Axis::Cols => (rows, cols, 1, rows),
+                    };
+
+                    (0..count)
+                        .map(|j| {
+                            let elems = (0..len).map(|k| inputs[j * outer + k * inner]);
+                            match reduction {
+                                Reduction::Sum => elems.sum(),
+                                Reduction::Mean => elems.sum::<f32>() / len as f32,
+                                Reduction::Max => elems.fold(f32::NEG_INFINITY, f32::max),
+                            }
+                        })
+                        .collect()
+                }
+                Layer::Select { size } => inputs[size * bucket..size * (bucket + 1)].to_vec(),
+                // applies the weights of the affine layer at index `source`
+                Layer::SharedAffine { source } => {
+                    let Layer::Affine { inputs: m, outputs: n } = self.layers[source].0 else {
+                        unreachable!("Can only share affine weights!")
+                    };
+
+                    affine(&self.params[offsets[source]..offsets[source] + (m + 1) * n], n, &inputs)
+                }
+                Layer::Slice { start, len } => inputs[start..start + len].to_vec(),
+                Layer::Softmax { log } => {
+                    let max = inputs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                    let log_total = inputs.iter().map(|x| (x - max).exp()).sum::<f32>().ln();
+                    let log_probs = inputs.iter().map(|x| x - max - log_total);
+
+                    if log {
+                        log_probs.collect()
+                    } else {
+                        log_probs.map(f32::exp).collect()
+                    }
+                }
+            };
+        }
+
+        inputs
+    }
+
+    pub fn eval(&self, fen: &str) -> f32
+    where
+        T::RequiredDataType: std::str::FromStr<Err = String>,
+    {
+        let board = format!("{fen} | 0 | 0.0").parse::<T::RequiredDataType>().expect("Failed to parse position!");
+        self.evaluate(&board)[0]
+    }
+}
+
+/// Applies an affine layer with `outputs` outputs, whose weights are
+/// stored one column per input, followed by the biases, in `params`.
+fn affine(params: &[f32], outputs: usize, inputs: &[f32]) -> Vec<f32> {
+    let (weights, biases) = params.split_at(params.len() - outputs);
+    let mut outputs = biases.to_vec();
+
+    for (x, row) in inputs.iter().zip(weights.chunks_exact(biases.len())) {
+        for (y, w) in outputs.iter_mut().zip(row) {
+            *y += w * x;
+        }
+    }
+
+    outputs
+}
+
+fn convolve(desc: &ConvolutionDescription, weights: &[f32], biases: &[f32], inputs: &[f32]) -> Vec<f32> {
+    let (oh, ow) = desc.output_shape();
+    let (ih, iw) = desc.input_shape;
+    let (kh, kw) = desc.kernel;
+    let mut outputs = Vec::with_capacity(desc.output_size());
+
+    for (oc, bias) in biases.iter().enumerate() {
+        for oy in 0..oh {
+            for ox in 0..ow {
+                let mut sum = *bias;
+
+                for i in 0..desc.group_inputs() {
+                    let ic = desc.input_channel(oc, i);
+
+                    for ky in 0..kh {
+                        for kx in 0..kw {
+                            if let Some((iy, ix)) = desc.input_position((oy, ox), (ky, kx)) {
+                                let weight = weights[((oc * desc.group_inputs() + i) * kh + ky) * kw + kx];
+                                sum += weight * inputs[(ic * ih + iy) * iw + ix];
+                            }
+                        }
+                    }
+                }
+
+                outputs.push(sum);
+            }
+        }
+    }
+
+    outputs
+}
+
+fn attend(desc: &AttentionDescription, weights: &[f32], biases: &[f32], inputs: &[f32]) -> Vec<f32> {
+    let (inner, head_dim) = (desc.inner(), desc.head_dim);
+    let scale = 1.0 / (head_dim as f32).sqrt();
+
+    // queries, keys and values of each token in turn
+    let qkv: Vec<f32> = inputs
+        .chunks_exact(desc.dim)
+        .flat_map(|token| {
+            weights
+                .chunks_exact(desc.dim)
+                .zip(biases)
+                .map(move |(row, bias)| bias + row.iter().zip(token).map(|(w, x)| w * x).sum::<f32>())
+        })
+        .collect();
+
+    let mut outputs = vec![0.0; desc.output_size()];
+
+    for h in 0..desc.heads {
+        let head = |token: usize, part: usize| {
+            let start = token * 3 * inner + part * inner + h * head_dim;
+            &qkv[start..start + head_dim]
+        };
+
+        for i in 0..desc.len {
+            let scores: Vec<f32> = (0..desc.len)
+                .map(|j| scale * head(i, 0).iter().zip(head(j, 1)).map(|(q, k)| q * k).sum::<f32>())
+                .collect();
+
+            let max = scores.iter().fold(f32::NEG_INFINITY, |max, &x| max.max(x));
+            let exps: Vec<f32> = scores.iter().map(|x| (x - max).exp()).collect();
+            let total: f32 = exps.iter().sum();
+
+            for (j, exp) in exps.iter().enumerate() {
+                for (out, v) in outputs[i * inner + h * head_dim..].iter_mut().zip(head(j, 2)) {
+                    *out += exp / total * v;
+                }
+            }
+        }
+    }
+
+    outputs
+}
+
+fn activate(activation: Activation, x: f32) -> f32 {
+    match activation {
+        Activation::ReLU => x.max(0.0),
+        Activation::CReLU => x.clamp(0.0, 1.0),
+        Activation::SCReLU => x.clamp(0.0, 1.0).powi(2),
+        Activation::BoundedCReLU { min, max } => x.clamp(min, max),
+        Activation::BoundedSCReLU { min, max } => x.clamp(min, max).powi(2),
+        Activation::LeakyReLU(slope) => x.max(0.0) + slope * x.min(0.0),
+    }
+}
@@ -0,0 +1,60 @@
+use bulletformat::ChessBoard;
+
+use super::{chess768::Chess768Iter, Chess768, InputType};
+use crate::convert;
+
+/// `Chess768` with 16 extra features for the files of the rooks that each
+/// side can castle with, which distinguish castling rights in Chess960, where
+/// they can't be inferred from the king and rook squares. Castling rights are
+/// only present in data converted with `bullet::convert`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Chess768Castling;
+impl InputType for Chess768Castling {
+    type RequiredDataType = ChessBoard;
+    type FeatureIter = Chess768CastlingIter;
+
+    fn max_active_inputs(&self) -> usize {
+        36
+    }
+
+    fn inputs(&self) -> usize {
+        768 + 16
+    }
+
+    fn buckets(&self) -> usize {
+        1
+    }
+
+    fn feature_iter(&self, pos: &Self::RequiredDataType) -> Self::FeatureIter {
+        let [ours, theirs] = convert::castling_rook_files(pos);
+
+        Chess768CastlingIter { pieces: Chess768.feature_iter(pos), castling: u16::from(ours) | u16::from(theirs) << 8 }
+    }
+}
+
+pub struct Chess768CastlingIter {
+    pieces: Chess768Iter,
+    /// Rook files of the side to move in the low byte, then of the other side.
+    castling: u16,
+}
+
+impl Iterator for Chess768CastlingIter {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(feats) = self.pieces.next() {
+            return Some(feats);
+        }
+
+        if self.castling == 0 {
+            return None;
+        }
+
+        let idx = self.castling.trailing_zeros() as usize;
+        self.castling &= self.castling - 1;
+
+        // files are the same from either perspective, only the side swaps
+        let (side, file) = (idx / 8, idx % 8);
+        Some((768 + 8 * side + file, 768 + 8 * (side ^ 1) + file))
+    }
+}
@@ -0,0 +1,79 @@
+use bulletformat::ChessBoard;
+
+use super::{chess768::Chess768Iter, Chess768, InputType};
+use crate::convert;
+
+/// Halfmoves covered by each feature of the halfmove clock.
+const HALFMOVES_PER_FEATURE: u8 = 10;
+const CLOCK_FEATURES: usize = 10;
+
+/// `Chess768` with an extra feature for the halfmove clock, in steps of 10
+/// halfmoves, with the last covering 90 or more, so that evaluations can
+/// account for the fifty-move rule. The halfmove clock is only present in
+/// data converted with `bullet::convert`, otherwise it is always zero.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Chess768Rule50;
+impl InputType for Chess768Rule50 {
+    type RequiredDataType = ChessBoard;
+    type FeatureIter = Chess768Rule50Iter;
+
+    fn max_active_inputs(&self) -> usize {
+        33
+    }
+
+    fn inputs(&self) -> usize {
+        768 + CLOCK_FEATURES
+    }
+
+    fn buckets(&self) -> usize {
+        1
+    }
+
+    fn feature_iter(&self, pos: &Self::RequiredDataType) -> Self::FeatureIter {
+        let clock = usize::from(convert::halfmove_clock(pos) / HALFMOVES_PER_FEATURE).min(CLOCK_FEATURES - 1);
+
+        Chess768Rule50Iter { pieces: Chess768.feature_iter(pos), clock: Some(768 + clock) }
+    }
+}
+
+pub struct Chess768Rule50Iter {
+    pieces: Chess768Iter,
+    clock: Option<usize>,
+}
+
+impl Iterator for Chess768Rule50Iter {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(feats) = self.pieces.next() {
+            return Some(feats);
+        }
+
+        // the clock is the same from either perspective
+        self.clock.take().map(|feat| (feat, feat))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock_feature(fen: &str) -> (usize, usize) {
+        let board = convert::parse_text_position(&format!("{fen} | 0 | 0.5")).unwrap();
+        let feats = Chess768Rule50.feature_iter(&board).collect::<Vec<_>>();
+
+        assert_eq!(feats.len(), board.occ().count_ones() as usize + 1);
+        *feats.last().unwrap()
+    }
+
+    #[test]
+    fn halfmove_clock_features() {
+        assert_eq!(clock_feature("8/8/4k3/8/8/3K4/8/8 w - - 0 1"), (768, 768));
+        assert_eq!(clock_feature("8/8/4k3/8/8/3K4/8/8 b - - 37 80"), (771, 771));
+        assert_eq!(clock_feature("8/8/4k3/8/8/3K4/8/8 w - - 99 80"), (777, 777));
+        assert_eq!(clock_feature("8/8/4k3/8/8/3K4/8/8 w - - 150 100"), (777, 777));
+
+        // without a halfmove clock
+        assert_eq!(clock_feature("8/8/4k3/8/8/3K4/8/8 w - -"), (768, 768));
+    }
+}
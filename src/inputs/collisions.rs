@@ -0,0 +1,85 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use super::InputType;
+
+/// Counts of positions whose features under an input type are identical,
+/// despite being distinct under a lossless reference input type.
+#[derive(Clone, Debug, Default)]
+pub struct CollisionReport {
+    pub positions: usize,
+    /// Distinct positions, as determined by the reference input type.
+    pub distinct_positions: usize,
+    /// Distinct sets of features under the input type being checked.
+    pub distinct_feature_sets: usize,
+    /// Feature sets shared by more than one distinct position.
+    pub colliding_feature_sets: usize,
+    /// Distinct positions that share their feature set with another.
+    pub colliding_positions: usize,
+}
+
+impl CollisionReport {
+    /// Fraction of distinct positions that cannot be told apart by the net.
+    pub fn collision_rate(&self) -> f64 {
+        self.colliding_positions as f64 / self.distinct_positions.max(1) as f64
+    }
+}
+
+impl std::fmt::Display for CollisionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Positions              : {}", self.positions)?;
+        writeln!(f, "Distinct Positions     : {}", self.distinct_positions)?;
+        writeln!(f, "Distinct Feature Sets  : {}", self.distinct_feature_sets)?;
+        writeln!(f, "Colliding Feature Sets : {}", self.colliding_feature_sets)?;
+        writeln!(f, "Colliding Positions    : {}", self.colliding_positions)?;
+        write!(f, "Collision Rate         : {:.4}%", 100.0 * self.collision_rate())
+    }
+}
+
+/// Scans `positions` for distinct positions (according to `reference`, e.g.
+/// `Chess768`, which loses no information about the board) that `input` maps
+/// to the same features. Feature sets are compared by 64-bit hash.
+pub fn find_collisions<I, R>(
+    input: &I,
+    reference: &R,
+    positions: impl IntoIterator<Item = I::RequiredDataType>,
+) -> CollisionReport
+where
+    I: InputType,
+    R: InputType<RequiredDataType = I::RequiredDataType>,
+{
+    let mut feature_sets: HashMap<u64, HashSet<u64>> = HashMap::new();
+    let mut report = CollisionReport::default();
+
+    for pos in positions {
+        report.positions += 1;
+
+        let features = feature_hash(input.feature_iter(&pos));
+        let position = feature_hash(reference.feature_iter(&pos));
+        feature_sets.entry(features).or_default().insert(position);
+    }
+
+    report.distinct_feature_sets = feature_sets.len();
+
+    for positions in feature_sets.values() {
+        report.distinct_positions += positions.len();
+
+        if positions.len() > 1 {
+            report.colliding_feature_sets += 1;
+            report.colliding_positions += positions.len();
+        }
+    }
+
+    report
+}
+
+fn feature_hash(features: impl Iterator<Item = (usize, usize)>) -> u64 {
+    let mut features: Vec<_> = features.collect();
+    features.sort_unstable();
+
+    let mut hasher = DefaultHasher::new();
+    features.hash(&mut hasher);
+    hasher.finish()
+}
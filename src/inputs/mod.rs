@@ -4,11 +4,19 @@ mod ataxx147;
 mod chess768;
 mod chess_buckets;
 mod chess_buckets_hm;
+mod chess_castling;
+mod chess_rule50;
+mod collisions;
+mod region_buckets;
 
 pub use ataxx147::{Ataxx147, Ataxx98};
 pub use chess768::Chess768;
 pub use chess_buckets::ChessBuckets;
 pub use chess_buckets_hm::{ChessBucketsMirrored, ChessBucketsMirroredFactorised};
+pub use chess_castling::Chess768Castling;
+pub use chess_rule50::Chess768Rule50;
+pub use collisions::{find_collisions, CollisionReport};
+pub use region_buckets::RegionBuckets;
 
 pub trait InputType: Send + Sync + Copy + Default + 'static {
     type RequiredDataType: BulletFormat + Copy + Send + Sync;
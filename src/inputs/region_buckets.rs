@@ -0,0 +1,71 @@
+use super::{get_num_buckets, InputType};
+
+/// Buckets the features of any `InputType` by the region of the board that
+/// a chosen square lies in, generalising king buckets to other games. The
+/// board has `N` squares, each assigned a region, and `anchor` gives the
+/// chosen square from each perspective, e.g. `(our_ksq, opp_ksq ^ 56)`.
+#[derive(Clone, Copy)]
+pub struct RegionBuckets<I: InputType, const N: usize> {
+    inner: I,
+    regions: [usize; N],
+    num_buckets: usize,
+    anchor: fn(&I::RequiredDataType) -> (usize, usize),
+}
+
+impl<I: InputType, const N: usize> Default for RegionBuckets<I, N> {
+    fn default() -> Self {
+        Self { inner: I::default(), regions: [0; N], num_buckets: 1, anchor: |_| (0, 0) }
+    }
+}
+
+impl<I: InputType, const N: usize> RegionBuckets<I, N> {
+    pub fn new(inner: I, regions: [usize; N], anchor: fn(&I::RequiredDataType) -> (usize, usize)) -> Self {
+        let num_buckets = get_num_buckets(&regions);
+
+        for region in 0..num_buckets {
+            assert!(regions.contains(&region), "Region {region} has no squares!");
+        }
+
+        Self { inner, regions, num_buckets, anchor }
+    }
+}
+
+impl<I: InputType, const N: usize> InputType for RegionBuckets<I, N> {
+    type RequiredDataType = I::RequiredDataType;
+    type FeatureIter = RegionBucketsIter<I::FeatureIter>;
+
+    fn max_active_inputs(&self) -> usize {
+        self.inner.max_active_inputs()
+    }
+
+    fn inputs(&self) -> usize {
+        self.inner.inputs()
+    }
+
+    fn buckets(&self) -> usize {
+        self.inner.buckets() * self.num_buckets
+    }
+
+    fn feature_iter(&self, pos: &Self::RequiredDataType) -> Self::FeatureIter {
+        let (our, opp) = (self.anchor)(pos);
+        let stride = self.inner.size();
+
+        RegionBucketsIter {
+            offsets: [stride * self.regions[our], stride * self.regions[opp]],
+            inner: self.inner.feature_iter(pos),
+        }
+    }
+}
+
+pub struct RegionBucketsIter<T> {
+    offsets: [usize; 2],
+    inner: T,
+}
+
+impl<T: Iterator<Item = (usize, usize)>> Iterator for RegionBucketsIter<T> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(our, opp)| (self.offsets[0] + our, self.offsets[1] + opp))
+    }
+}
@@ -1,10 +1,14 @@
 mod backend;
+pub mod convert;
+pub mod inference;
 pub mod inputs;
 mod loader;
+pub mod moves;
 pub mod outputs;
 pub mod tensor;
 mod trainer;
 pub mod util;
+pub mod value_match;
 
 use std::{
     fs::{self, File},
@@ -15,22 +19,73 @@ use std::{
 use trainer::ansi;
 
 pub use bulletformat as format;
+pub use tensor::{AttentionDescription, ConvolutionDescription, OptimiserType, ParamKind, ParamSettings};
 pub use trainer::{
-    schedule::{LrScheduler, TrainingSchedule, WdlScheduler, Loss},
-    set_cbcs, Trainer, TrainerBuilder,
+    population_based_training,
+    schedule::{
+        BetaScheduler, FreezeScheduler, Loss, LossFunction, LossHead, LrScheduler, RealizedSchedule,
+        RealizedSuperbatch, TrainingRecipe, TrainingSchedule, TrainingStage, WdScheduler, WdlScheduler,
+    },
+    seed_sensitivity, set_cbcs, what_if_finetunes, AccumulationLimits, ActivationRange, ActivationStats, ArchSummary,
+    DistributedSettings, EvalDistribution, ImportFormat, LayerDiff, LayerSummary, MemoryReport, NetDiff, NodeMemory,
+    OutputTransform, PbtMember, PbtResult, PbtSettings, PinnedBatch, RunUsage, SeedSensitivity, Spread, StatsHook,
+    Trainer, TrainerBuilder, WhatIf, WhatIfReport, WhatIfResult,
 };
+pub use value_match::ValueSearch;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Activation {
     ReLU,
     CReLU,
     SCReLU,
+    /// CReLU clipping to `[min, max]` rather than `[0, 1]`.
+    BoundedCReLU {
+        min: f32,
+        max: f32,
+    },
+    /// SCReLU clipping to `[min, max]` rather than `[0, 1]`.
+    BoundedSCReLU {
+        min: f32,
+        max: f32,
+    },
+    /// ReLU with the given slope, rather than 0, for negative inputs.
+    LeakyReLU(f32),
+}
+
+/// Gradient of a clamp node, whose true gradient is zero wherever it clips.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClampGradient {
+    /// Passes the gradient through unchanged, as if the clamp weren't there,
+    /// so clipped outputs can still be pulled back into range.
+    StraightThrough,
+    /// Zeroes the gradient of clipped outputs, as for `BoundedCReLU`.
+    Masked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reduction {
+    Sum,
+    Mean,
+    /// Gradients only flow to the first of the maximal inputs.
+    Max,
+}
+
+/// Axis of a matrix, stored column by column, to reduce along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    /// Reduces each column, giving one output per column.
+    Rows,
+    /// Reduces each row, giving one output per row.
+    Cols,
 }
 
 pub struct LocalSettings<'a> {
     pub threads: usize,
     pub data_file_paths: Vec<&'a str>,
     pub output_directory: &'a str,
+    /// Checkpoint saved during a previous run of the same schedule, to continue
+    /// training from exactly as if the run had not been interrupted.
+    pub resume_from: Option<&'a str>,
 }
 
 impl<'a> LocalSettings<'a> {
@@ -40,6 +95,9 @@ impl<'a> LocalSettings<'a> {
             println!("Data File Path         : {}", ansi(file_path, "32;1"));
         }
         println!("Output Path            : {}", ansi(self.output_directory, "32;1"));
+        if let Some(path) = self.resume_from {
+            println!("Resuming From          : {}", ansi(path, "32;1"));
+        }
     }
 }
 
@@ -67,6 +125,21 @@ pub struct Engine<'a> {
     pub uci_options: Vec<UciOption<'a>>,
 }
 
+/// Settings for matches played on the CPU between the net being trained
+/// and `opponent`, a checkpoint of a net with the same architecture, or the
+/// previously tested net if `None`. See [`value_match`].
+pub struct ValueTestSettings<'a> {
+    pub test_rate: usize,
+    pub out_dir: &'a str,
+    /// File of FENs or EPDs, one per line, which are cycled
+    /// through if there are fewer than `num_game_pairs`.
+    pub book_path: &'a str,
+    pub num_game_pairs: usize,
+    pub threads: usize,
+    pub search: ValueSearch,
+    pub opponent: Option<&'a str>,
+}
+
 pub struct TestSettings<'a> {
     pub test_rate: usize,
     pub out_dir: &'a str,
@@ -79,6 +152,23 @@ pub struct TestSettings<'a> {
     pub dev_engine: Engine<'a>,
 }
 
+fn save_checkpoint<T, U>(
+    superbatch: usize,
+    trainer: &Trainer<T, U>,
+    schedule: &TrainingSchedule,
+    settings: &LocalSettings,
+) where
+    T: inputs::InputType,
+    U: outputs::OutputBuckets<T::RequiredDataType>,
+{
+    if schedule.should_save(superbatch) {
+        let name = format!("{}-{superbatch}", schedule.net_id());
+        let out_dir = settings.output_directory;
+        trainer.save(out_dir, name.clone());
+        println!("Saved [{}]", ansi(name, 31));
+    }
+}
+
 impl<T: inputs::InputType, U: outputs::OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
     pub fn run_custom<F>(&mut self, schedule: &TrainingSchedule, settings: &LocalSettings, callback: F)
     where
@@ -87,15 +177,71 @@ impl<T: inputs::InputType, U: outputs::OutputBuckets<T::RequiredDataType>> Train
         trainer::run::<T, U, F>(self, schedule, settings, callback);
     }
 
+    /// Retrains only `superbatch` of the schedule, on exactly the data it was
+    /// originally trained on, and returns its average loss.
+    pub fn replay_superbatch(
+        &mut self,
+        schedule: &TrainingSchedule,
+        settings: &LocalSettings,
+        superbatch: usize,
+    ) -> f32 {
+        trainer::replay_superbatch(self, schedule, settings, superbatch)
+    }
+
     pub fn run(&mut self, schedule: &TrainingSchedule, settings: &LocalSettings) {
-        self.run_custom(schedule, settings, |superbatch, trainer, schedule, settings| {
-            if schedule.should_save(superbatch) {
-                let name = format!("{}-{superbatch}", schedule.net_id());
-                let out_dir = settings.output_directory;
-                trainer.save(out_dir, name.clone());
-                println!("Saved [{}]", ansi(name, 31));
+        self.run_custom(schedule, settings, save_checkpoint);
+    }
+
+    /// As `run`, but also trains `mini`, a tiny secondary net such as one from
+    /// [`TrainerBuilder::mini_net`], on exactly the same data, saving it and a
+    /// combined export of both nets alongside each checkpoint.
+    pub fn run_with_mini(&mut self, mini: &mut Trainer<T, U>, schedule: &TrainingSchedule, settings: &LocalSettings) {
+        trainer::run_with_mini(self, Some(mini), schedule, settings, save_checkpoint);
+    }
+
+    /// Runs each stage of the recipe in turn, saving checkpoints as in `run`,
+    /// with the recipe and the checkpoint each stage starts from recorded in
+    /// `<output_directory>/<recipe name>-recipe.txt`. Every stage must have a
+    /// different `net_id`, which its checkpoints and logs are named after. The
+    /// loss of every superbatch of every stage is written to `<recipe name>-log.csv`,
+    /// and the schedule and activation CSVs of the stages are merged likewise.
+    pub fn run_recipe(&mut self, recipe: &TrainingRecipe, settings: &LocalSettings) {
+        assert!(!recipe.stages.is_empty(), "Recipe has no stages!");
+        recipe.check_names();
+
+        let out_dir = settings.output_directory;
+        fs::create_dir(out_dir).unwrap_or(());
+        let path = format!("{out_dir}/{}-recipe.txt", recipe.name);
+        recipe.write_metadata(&path, &settings.data_file_paths).expect("Couldn't write recipe to file!");
+
+        let log_path = format!("{out_dir}/{}-log.csv", recipe.name);
+        fs::remove_file(&log_path).unwrap_or(());
+
+        for (i, stage) in recipe.stages.iter().enumerate() {
+            println!("{}", ansi(format!("Recipe [{}] Stage {}/{}", recipe.name, i + 1, recipe.stages.len()), "34;1"));
+            if let Some(checkpoint) = recipe.starting_checkpoint(i) {
+                println!("Starting From          : {}", ansi(checkpoint, "32;1"));
             }
-        });
+
+            let data_file_paths =
+                if stage.data_file_paths.is_empty() { &settings.data_file_paths } else { &stage.data_file_paths };
+
+            let stage_settings = LocalSettings {
+                threads: settings.threads,
+                data_file_paths: data_file_paths.clone(),
+                output_directory: out_dir,
+                resume_from: None,
+            };
+
+            self.run(&stage.schedule, &stage_settings);
+            self.append_stage_log(&log_path, i + 1);
+        }
+
+        for suffix in ["schedule.csv", "activations.csv"] {
+            recipe
+                .merge_stage_csvs(out_dir, suffix)
+                .unwrap_or_else(|_| panic!("Writing to [{out_dir}/{}-{suffix}] failed!", recipe.name));
+        }
     }
 
     pub fn run_and_test(
@@ -266,6 +412,10 @@ impl<T: inputs::InputType, U: outputs::OutputBuckets<T::RequiredDataType>> Train
                             .expect("Couldn't open stats path!");
 
                         writeln!(file, "{superbatch}, {elo}, {err}").expect("Couldn't write to file!");
+
+                        // cutechess writes "inf" and "nan" for one-sided matches, which parse as such
+                        let parse = |x: &str| x.parse::<f32>().unwrap_or(f32::NAN);
+                        (superbatch, parse(elo), parse(err))
                     } else {
                         panic!("Couldn't find elo line!");
                     }
@@ -277,13 +427,79 @@ impl<T: inputs::InputType, U: outputs::OutputBuckets<T::RequiredDataType>> Train
 
         println!("# [Waiting for Tests]");
         for handle in handles {
-            if let Err(err) = handle.join() {
-                println!("{err:?}");
+            match handle.join() {
+                Ok((superbatch, elo, err)) => self.record_elo(superbatch, elo, err),
+                Err(err) => println!("{err:?}"),
             }
         }
     }
 }
 
+impl<T, U> Trainer<T, U>
+where
+    T: inputs::InputType<RequiredDataType = format::ChessBoard>,
+    U: outputs::OutputBuckets<format::ChessBoard>,
+{
+    /// Trains as in `run`, pausing every `test_rate` superbatches to play a
+    /// match using the CPU inference of the current net, with the results
+    /// recorded in `<out_dir>/value-stats.txt` and the report of the run.
+    pub fn run_and_value_test(
+        &mut self,
+        schedule: &TrainingSchedule,
+        settings: &LocalSettings,
+        testing: &ValueTestSettings,
+    ) {
+        let book = value_match::load_openings(testing.book_path);
+        let openings: Vec<_> = book.iter().copied().cycle().take(testing.num_game_pairs).collect();
+
+        let mut base = testing.opponent.map(|path| {
+            let mut net = self.inference_net();
+            net.load_from_checkpoint(path);
+            net
+        });
+
+        fs::create_dir(testing.out_dir).unwrap_or(());
+        let stats_path = format!("{}/value-stats.txt", testing.out_dir);
+        File::create(stats_path.as_str()).expect("Couldn't create stats file!");
+
+        let mut results = Vec::new();
+
+        self.run_custom(schedule, settings, |superbatch, trainer, schedule, settings| {
+            if schedule.should_save(superbatch) {
+                let name = format!("{}-{superbatch}", schedule.net_id());
+                trainer.save(settings.output_directory, name.clone());
+                println!("Saved [{}]", ansi(name, 31));
+            }
+
+            if superbatch % testing.test_rate == 0 || superbatch == schedule.end_superbatch {
+                let dev = trainer.inference_net();
+
+                if let Some(base) = &base {
+                    let result = value_match::play_match(&dev, base, &openings, testing.search, testing.threads);
+                    println!("Value Match [{}]: {result}", ansi(superbatch, 31));
+
+                    let (elo, err) = result.elo();
+                    let mut file = fs::OpenOptions::new()
+                        .append(true)
+                        .open(stats_path.as_str())
+                        .expect("Couldn't open stats path!");
+
+                    writeln!(file, "{superbatch}, {elo:.2}, {err:.2}").expect("Couldn't write to file!");
+                    results.push((superbatch, elo as f32, err as f32));
+                }
+
+                if testing.opponent.is_none() {
+                    base = Some(dev);
+                }
+            }
+        });
+
+        for (superbatch, elo, err) in results {
+            self.record_elo(superbatch, elo, err);
+        }
+    }
+}
+
 fn clone(engine: &Engine, out_dir: &str) {
     println!("# [Cloning {}/{}]", engine.repo, engine.branch);
 
@@ -1,6 +1,7 @@
 use bulletformat::BulletFormat;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::{inputs::InputType, outputs::OutputBuckets};
+use crate::{inputs::InputType, outputs::OutputBuckets, util};
 
 #[repr(C)]
 #[derive(Clone, Copy, Default)]
@@ -26,7 +27,11 @@ impl Feat {
 pub struct GpuDataLoader<I: InputType, O: OutputBuckets<I::RequiredDataType>> {
     inputs: Vec<Feat>,
     results: Vec<f32>,
+    weights: Vec<f32>,
     buckets: Vec<u8>,
+    dropout: f32,
+    seed: u64,
+    source: usize,
     input_getter: I,
     output_getter: O,
 }
@@ -37,7 +42,17 @@ where
     I::RequiredDataType: Send + Sync + Copy,
 {
     pub fn new(input_getter: I, output_getter: O) -> Self {
-        Self { inputs: Vec::new(), results: Vec::new(), buckets: Vec::new(), input_getter, output_getter }
+        Self {
+            inputs: Vec::new(),
+            results: Vec::new(),
+            weights: Vec::new(),
+            buckets: Vec::new(),
+            dropout: 0.0,
+            seed: 0,
+            source: 0,
+            input_getter,
+            output_getter,
+        }
     }
 
     pub fn inputs(&self) -> &Vec<Feat> {
@@ -48,29 +63,76 @@ where
         &self.results
     }
 
+    /// Randomly drops each active feature with probability `rate`, using
+    /// an RNG seeded from `seed` so that the dropped features are reproducible.
+    pub fn set_feature_dropout(&mut self, rate: f32, seed: u64) {
+        assert!((0.0..1.0).contains(&rate), "Invalid dropout rate {rate}!");
+        self.dropout = rate;
+        self.seed = seed;
+    }
+
+    /// Tags the batch with the index of the data file it was read from.
+    pub fn set_source(&mut self, source: usize) {
+        self.source = source;
+    }
+
+    pub fn source(&self) -> usize {
+        self.source
+    }
+
+    /// Per-position loss weights, empty if no weight hook was given.
+    pub fn weights(&self) -> &Vec<f32> {
+        &self.weights
+    }
+
     pub fn buckets(&self) -> &Vec<u8> {
         &self.buckets
     }
 
-    pub fn load(&mut self, data: &[I::RequiredDataType], threads: usize, blend: f32, rscale: f32) {
+    /// Loads the targets of each position, with a head of values per entry of
+    /// `targets`, each the blended (win, draw, loss) distribution if it has
+    /// three values, otherwise the blended result followed by zeroes.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load(
+        &mut self,
+        data: &[I::RequiredDataType],
+        threads: usize,
+        blend: f32,
+        rscale: f32,
+        wdl_hook: fn(&I::RequiredDataType, f32) -> f32,
+        weight_hook: Option<fn(&I::RequiredDataType) -> f32>,
+        targets: &[usize],
+    ) {
         let batch_size = data.len();
         let max_features = self.input_getter.max_active_inputs();
         let chunk_size = (batch_size + threads - 1) / threads;
+        let stride = targets.iter().sum::<usize>();
 
         self.inputs = vec![Feat { our: 0, opp: 0 }; max_features * batch_size];
-        self.results = vec![0.0; batch_size];
+        self.results = vec![0.0; stride * batch_size];
         self.buckets = vec![0; batch_size];
 
+        if let Some(hook) = weight_hook {
+            self.weights = data.iter().map(hook).collect();
+        } else {
+            self.weights.clear();
+        }
+
+        let dropout = self.dropout;
+        let seed = self.seed;
+
         std::thread::scope(move |s| {
             data.chunks(chunk_size)
                 .zip(self.inputs.chunks_mut(max_features * chunk_size))
-                .zip(self.results.chunks_mut(chunk_size))
+                .zip(self.results.chunks_mut(stride * chunk_size))
                 .zip(self.buckets.chunks_mut(chunk_size))
-                .for_each(|(((data_chunk, input_chunk), results_chunk), buckets_chunk)| {
+                .enumerate()
+                .for_each(|(chunk, (((data_chunk, input_chunk), results_chunk), buckets_chunk))| {
                     let inp = &self.input_getter;
                     let out = &self.output_getter;
                     s.spawn(move || {
                         let chunk_len = data_chunk.len();
+                        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(chunk as u64));
 
                         for i in 0..chunk_len {
                             let pos = &data_chunk[i];
@@ -78,6 +140,10 @@ where
                             let offset = max_features * i;
 
                             for (our, opp) in inp.feature_iter(pos) {
+                                if dropout > 0.0 && rng.gen::<f32>() < dropout {
+                                    continue;
+                                }
+
                                 input_chunk[offset + j] = Feat::new(our as i32, opp as i32);
                                 j += 1;
                             }
@@ -86,7 +152,23 @@ where
                                 input_chunk[offset + j] = Feat::new(-1, -1);
                             }
 
-                            results_chunk[i] = pos.blended_result(blend, rscale);
+                            let blend = wdl_hook(pos, blend);
+
+                            let mut start = stride * i;
+                            for &size in targets {
+                                let target = &mut results_chunk[start..start + size];
+                                start += size;
+
+                                if size == 3 {
+                                    let score = util::sigmoid(f32::from(pos.score()), rscale);
+                                    target[0] = (1.0 - blend) * score;
+                                    target[2] = (1.0 - blend) * (1.0 - score);
+                                    target[2 - pos.result_idx()] += blend;
+                                } else {
+                                    target[0] = pos.blended_result(blend, rscale);
+                                }
+                            }
+
                             buckets_chunk[i] = out.bucket(pos);
                         }
                     });
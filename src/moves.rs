@@ -0,0 +1,280 @@
+/// Promotion piece of a chess move, if any.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Promotion {
+    #[default]
+    None,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+}
+
+/// A chess move, with squares indexed from `a1 = 0` to `h8 = 63`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub from: u8,
+    pub to: u8,
+    pub promo: Promotion,
+}
+
+impl Move {
+    pub fn new(from: u8, to: u8, promo: Promotion) -> Self {
+        assert!(from < 64 && to < 64, "Invalid move {from} -> {to}!");
+        Self { from, to, promo }
+    }
+
+    /// Parses a move in UCI notation, e.g. `e2e4` or `e7e8q`.
+    pub fn from_uci(uci: &str) -> Option<Self> {
+        let bytes = uci.as_bytes();
+        if !(4..=5).contains(&bytes.len()) {
+            return None;
+        }
+
+        let square = |file: u8, rank: u8| {
+            let valid = (b'a'..=b'h').contains(&file) && (b'1'..=b'8').contains(&rank);
+            valid.then(|| 8 * (rank - b'1') + file - b'a')
+        };
+
+        let promo = match bytes.get(4) {
+            None => Promotion::None,
+            Some(b'n') => Promotion::Knight,
+            Some(b'b') => Promotion::Bishop,
+            Some(b'r') => Promotion::Rook,
+            Some(b'q') => Promotion::Queen,
+            Some(_) => return None,
+        };
+
+        Some(Self { from: square(bytes[0], bytes[1])?, to: square(bytes[2], bytes[3])?, promo })
+    }
+
+    /// Mirrors the move vertically, so that moves for black
+    /// can be indexed from black's perspective.
+    pub fn flipped(self) -> Self {
+        Self { from: self.from ^ 56, to: self.to ^ 56, promo: self.promo }
+    }
+}
+
+/// Maps moves to the outputs of a policy head.
+pub trait MoveIndexer {
+    const MOVES: usize;
+
+    fn index(&self, mov: Move) -> usize;
+}
+
+/// Indexes moves by `64 * from + to`, ignoring promotions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FromTo;
+impl MoveIndexer for FromTo {
+    const MOVES: usize = 4096;
+
+    fn index(&self, mov: Move) -> usize {
+        64 * usize::from(mov.from) + usize::from(mov.to)
+    }
+}
+
+/// Indexes only the 1792 from-to pairs reachable by a queen or a knight,
+/// followed by the 88 promotions (22 pawn moves onto the eighth rank, with
+/// 4 promotion pieces each), for 1880 moves in total. Moves should be
+/// given from the perspective of the side to move.
+#[derive(Clone, Debug)]
+pub struct Move1880 {
+    destinations: [u64; 64],
+    offsets: [usize; 64],
+}
+
+impl Default for Move1880 {
+    fn default() -> Self {
+        let mut destinations = [0u64; 64];
+        let mut offsets = [0; 64];
+        let mut total = 0;
+
+        for from in 0..64 {
+            let (rank, file) = (from / 8, from % 8);
+
+            for to in 0..64 {
+                let (dr, df) = ((to / 8) as i32 - rank as i32, (to % 8) as i32 - file as i32);
+                let queen = (dr == 0 || df == 0 || dr.abs() == df.abs()) && to != from;
+                let knight = dr.abs() * df.abs() == 2;
+
+                if queen || knight {
+                    destinations[from] |= 1 << to;
+                }
+            }
+
+            offsets[from] = total;
+            total += destinations[from].count_ones() as usize;
+        }
+
+        assert_eq!(total, Self::PROMOTIONS_START);
+
+        Self { destinations, offsets }
+    }
+}
+
+impl Move1880 {
+    const PROMOTIONS_START: usize = 1792;
+
+    /// The move at `index`, such that `self.index(self.mov(index)) == index`,
+    /// for reading moves back out of the outputs of a policy head.
+    pub fn mov(&self, index: usize) -> Move {
+        assert!(index < Self::MOVES, "Invalid move index {index}!");
+
+        if index < Self::PROMOTIONS_START {
+            let from = self.offsets.partition_point(|&offset| offset <= index) - 1;
+
+            let mut destinations = self.destinations[from];
+            for _ in 0..index - self.offsets[from] {
+                destinations &= destinations - 1;
+            }
+
+            return Move::new(from as u8, destinations.trailing_zeros() as u8, Promotion::None);
+        }
+
+        let (idx, piece) = ((index - Self::PROMOTIONS_START) / 4, (index - Self::PROMOTIONS_START) % 4);
+        let from_file = (idx + 1) / 3;
+        let to_file = idx - 2 * from_file;
+        let promo = [Promotion::Knight, Promotion::Bishop, Promotion::Rook, Promotion::Queen][piece];
+
+        Move::new(48 + from_file as u8, 56 + to_file as u8, promo)
+    }
+}
+
+impl MoveIndexer for Move1880 {
+    const MOVES: usize = 1880;
+
+    fn index(&self, mov: Move) -> usize {
+        let from = usize::from(mov.from);
+        let to = usize::from(mov.to);
+
+        let piece = match mov.promo {
+            Promotion::None => {
+                let destinations = self.destinations[from];
+                assert!(destinations & (1 << to) > 0, "Move {from} -> {to} is not a queen or knight move!");
+                return self.offsets[from] + (destinations & ((1 << to) - 1)).count_ones() as usize;
+            }
+            Promotion::Knight => 0,
+            Promotion::Bishop => 1,
+            Promotion::Rook => 2,
+            Promotion::Queen => 3,
+        };
+
+        let (from_file, to_file) = (from % 8, to % 8);
+        assert!(from / 8 == 6 && to / 8 == 7 && from_file.abs_diff(to_file) <= 1, "Invalid promotion {from} -> {to}!");
+
+        // the a-file pawn has 2 promotion moves and the rest have 3, so the
+        // pawn on file f has its first move at 3f - 1, and to file t at 2f + t
+        let idx = 2 * from_file + to_file;
+        Self::PROMOTIONS_START + 4 * idx + piece
+    }
+}
+
+/// Sparse policy target for a single position, as pairs of move index and
+/// probability, normalised from the given move weights (e.g. visit counts).
+/// Moves are flipped before indexing if `flip` is set, as when black is to move.
+pub fn sparse_policy_target<M: MoveIndexer>(indexer: &M, moves: &[(Move, f32)], flip: bool) -> Vec<(usize, f32)> {
+    let total: f32 = moves.iter().map(|(_, weight)| weight).sum();
+    assert!(total > 0.0, "Policy target has no weight!");
+
+    moves
+        .iter()
+        .map(|&(mov, weight)| {
+            let mov = if flip { mov.flipped() } else { mov };
+            (indexer.index(mov), weight / total)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every move that `Move1880` indexes, from the side to move's perspective.
+    fn all_moves() -> Vec<Move> {
+        let mut moves = Vec::new();
+
+        for from in 0..64i32 {
+            let (rank, file) = (from / 8, from % 8);
+
+            for to in 0..64 {
+                let (dr, df) = (to / 8 - rank, to % 8 - file);
+                if (to != from && (dr == 0 || df == 0 || dr.abs() == df.abs())) || dr.abs() * df.abs() == 2 {
+                    moves.push(Move::new(from as u8, to as u8, Promotion::None));
+
+                    if rank == 6 && dr == 1 && df.abs() <= 1 {
+                        for promo in [Promotion::Knight, Promotion::Bishop, Promotion::Rook, Promotion::Queen] {
+                            moves.push(Move::new(from as u8, to as u8, promo));
+                        }
+                    }
+                }
+            }
+        }
+
+        moves
+    }
+
+    #[test]
+    fn move1880_indices_are_unique() {
+        let indexer = Move1880::default();
+        let moves = all_moves();
+        assert_eq!(moves.len(), Move1880::MOVES);
+
+        let mut seen = vec![false; Move1880::MOVES];
+        for mov in moves {
+            let index = indexer.index(mov);
+            assert!(!seen[index], "{mov:?} has the same index {index} as another move!");
+            seen[index] = true;
+        }
+    }
+
+    #[test]
+    fn move1880_round_trips() {
+        let indexer = Move1880::default();
+
+        for index in 0..Move1880::MOVES {
+            assert_eq!(indexer.index(indexer.mov(index)), index);
+        }
+
+        for mov in all_moves() {
+            assert_eq!(indexer.mov(indexer.index(mov)), mov);
+        }
+    }
+
+    #[test]
+    fn move1880_promotions() {
+        let indexer = Move1880::default();
+        let uci = |uci| Move::from_uci(uci).unwrap();
+
+        assert_eq!(indexer.index(uci("a7a8n")), 1792);
+        assert_eq!(indexer.index(uci("a7b8q")), 1799);
+        assert_eq!(indexer.index(uci("b7a8n")), 1800);
+        assert_eq!(indexer.index(uci("h7h8q")), 1879);
+    }
+
+    /// A move by black, flipped, has the index of the mirrored move by white.
+    #[test]
+    fn flipped_moves_share_indices() {
+        let indexer = Move1880::default();
+        let uci = |uci| Move::from_uci(uci).unwrap();
+
+        for (white, black) in [("e2e4", "e7e5"), ("g1f3", "g8f6"), ("a1h8", "a8h1"), ("b7c8q", "b2c1q")] {
+            assert_eq!(uci(black).flipped(), uci(white));
+            assert_eq!(indexer.index(uci(black).flipped()), indexer.index(uci(white)));
+        }
+
+        for mov in all_moves().into_iter().filter(|mov| mov.promo == Promotion::None) {
+            assert_eq!(mov.flipped().flipped(), mov);
+            assert_eq!(indexer.mov(indexer.index(mov.flipped())), mov.flipped());
+        }
+
+        let moves = [(uci("e7e5"), 3.0), (uci("g8f6"), 1.0)];
+        let target = sparse_policy_target(&indexer, &moves, true);
+        assert_eq!(target, [(indexer.index(uci("e2e4")), 0.75), (indexer.index(uci("g1f3")), 0.25)]);
+    }
+
+    #[test]
+    fn from_to_indices() {
+        let mov = Move::from_uci("e2e4").unwrap();
+        assert_eq!(FromTo.index(mov), 64 * 12 + 28);
+        assert_eq!(FromTo.index(mov.flipped()), 64 * 52 + 36);
+    }
+}
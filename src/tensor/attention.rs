@@ -0,0 +1,46 @@
+/// Multi-head self-attention over a sequence of `len` tokens, each stored
+/// contiguously as `dim` features, e.g. one token per square or piece.
+/// The queries, keys and values are separate affine projections of each
+/// token to `heads * head_dim` features, shared across tokens, and the
+/// output holds the concatenated outputs of the heads for each token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttentionDescription {
+    pub len: usize,
+    pub dim: usize,
+    pub heads: usize,
+    pub head_dim: usize,
+}
+
+impl AttentionDescription {
+    pub fn new(len: usize, dim: usize, heads: usize, head_dim: usize) -> Self {
+        assert!(
+            len > 0 && dim > 0 && heads > 0 && head_dim > 0,
+            "Invalid attention of {heads}x{head_dim} over {len}x{dim}!"
+        );
+        Self { len, dim, heads, head_dim }
+    }
+
+    /// Features of each token in the output, and of each projection.
+    pub fn inner(&self) -> usize {
+        self.heads * self.head_dim
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.len * self.dim
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.len * self.inner()
+    }
+
+    /// Weights of the query, key and value projections in turn, each
+    /// stored one output at a time, as `inner` contiguous rows of `dim`.
+    pub fn num_weights(&self) -> usize {
+        3 * self.inner() * self.dim
+    }
+
+    /// Biases of the query, key and value projections in turn.
+    pub fn num_biases(&self) -> usize {
+        3 * self.inner()
+    }
+}
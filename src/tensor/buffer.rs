@@ -10,13 +10,17 @@ pub struct DeviceBuffer {
     size: usize,
     ptr: *mut f32,
     id: usize,
+    device: usize,
+    owned: bool,
 }
 
 impl Drop for DeviceBuffer {
     fn drop(&mut self) {
-        self.report("Freed");
-        unsafe {
-            util::free(self.ptr, self.size);
+        if self.owned {
+            self.report("Freed");
+            unsafe {
+                util::free(self.ptr, self.size);
+            }
         }
     }
 }
@@ -26,13 +30,22 @@ impl DeviceBuffer {
         ALLOC_ID.fetch_add(1, Ordering::SeqCst);
         let id = ALLOC_ID.load(Ordering::SeqCst);
 
-        let res = Self { size, ptr: util::calloc(size), id };
+        let res = Self { size, ptr: util::calloc(size), id, device: util::current_device(), owned: true };
 
         res.report("Allocated");
 
         res
     }
 
+    /// View of `size` floats starting at `ptr`, in memory owned
+    /// by another buffer, which is not freed when it is dropped.
+    ///
+    /// # Safety
+    /// The memory must outlive the view.
+    pub unsafe fn view(ptr: *mut f32, size: usize) -> Self {
+        Self { size, ptr, id: 0, device: util::current_device(), owned: false }
+    }
+
     pub fn set_tracking(tracking: bool) {
         TRACKING.store(tracking, Ordering::SeqCst);
     }
@@ -45,6 +58,11 @@ impl DeviceBuffer {
         self.ptr
     }
 
+    /// The device the buffer was allocated on.
+    pub fn device(&self) -> usize {
+        self.device
+    }
+
     pub fn set_zero(&self) {
         util::set_zero(self.ptr, self.size)
     }
@@ -52,7 +70,11 @@ impl DeviceBuffer {
     pub fn load_from_device(&self, buf: &Self) {
         assert!(buf.size <= self.size, "Overflow: {} > {}!", buf.size, self.size);
         unsafe {
-            util::copy_on_device(self.ptr, buf.ptr, buf.size);
+            if self.device == buf.device {
+                util::copy_on_device(self.ptr, buf.ptr, buf.size);
+            } else {
+                util::copy_between_devices(self.ptr, self.device, buf.ptr, buf.device, buf.size);
+            }
         }
         util::device_synchronise();
     }
@@ -65,6 +87,15 @@ impl DeviceBuffer {
         util::device_synchronise();
     }
 
+    /// As `load_from_host`, but returns once the copy is issued on `stream`.
+    ///
+    /// # Safety
+    /// `buf` should be pinned, and must not change until `stream` is synchronised.
+    pub unsafe fn load_from_host_async(&self, buf: &[f32], stream: &util::CopyStream) {
+        assert!(buf.len() <= self.size, "Overflow!");
+        util::copy_to_device_async(self.ptr, buf.as_ptr(), buf.len(), stream);
+    }
+
     pub fn write_to_host(&self, buf: &mut [f32]) {
         assert!(buf.len() <= self.size, "Overflow!");
         unsafe {
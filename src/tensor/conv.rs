@@ -0,0 +1,129 @@
+/// A 2D convolution over tensors that hold each channel as a contiguous,
+/// row-major image, as is the output. Pairs are given as (height, width).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConvolutionDescription {
+    pub input_shape: (usize, usize),
+    pub input_channels: usize,
+    pub output_channels: usize,
+    pub kernel: (usize, usize),
+    pub stride: (usize, usize),
+    /// Zeroes added on both sides of each input image.
+    pub padding: (usize, usize),
+    /// Spacing between the inputs each kernel is applied to.
+    pub dilation: (usize, usize),
+    /// Number of equal, contiguous groups the channels are split into,
+    /// with each output channel only reading the inputs of its own group.
+    pub groups: usize,
+}
+
+impl ConvolutionDescription {
+    /// A convolution with unit stride and dilation, and no padding.
+    pub fn new(
+        input_shape: (usize, usize),
+        input_channels: usize,
+        output_channels: usize,
+        kernel: (usize, usize),
+    ) -> Self {
+        Self {
+            input_shape,
+            input_channels,
+            output_channels,
+            kernel,
+            stride: (1, 1),
+            padding: (0, 0),
+            dilation: (1, 1),
+            groups: 1,
+        }
+    }
+
+    /// A convolution applying a separate kernel to each channel, e.g.
+    /// followed by a pointwise convolution to mix the channels.
+    pub fn depthwise(input_shape: (usize, usize), channels: usize, kernel: (usize, usize)) -> Self {
+        Self::new(input_shape, channels, channels, kernel).with_groups(channels)
+    }
+
+    /// A 1x1 convolution, i.e. the same affine map applied to every square.
+    pub fn pointwise(input_shape: (usize, usize), input_channels: usize, output_channels: usize) -> Self {
+        Self::new(input_shape, input_channels, output_channels, (1, 1))
+    }
+
+    pub fn with_stride(mut self, stride: (usize, usize)) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    pub fn with_padding(mut self, padding: (usize, usize)) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: (usize, usize)) -> Self {
+        self.dilation = dilation;
+        self
+    }
+
+    pub fn with_groups(mut self, groups: usize) -> Self {
+        let divides = |channels: usize| groups > 0 && channels.is_multiple_of(groups);
+        assert!(
+            divides(self.input_channels) && divides(self.output_channels),
+            "Can't split channels into {groups} groups!"
+        );
+        self.groups = groups;
+        self
+    }
+
+    /// Number of input channels read by each output channel.
+    pub fn group_inputs(&self) -> usize {
+        self.input_channels / self.groups
+    }
+
+    /// Input channel that is the `i`th read by output channel `output_channel`.
+    pub fn input_channel(&self, output_channel: usize, i: usize) -> usize {
+        output_channel / (self.output_channels / self.groups) * self.group_inputs() + i
+    }
+
+    /// Panics if the description is invalid, e.g. if the kernel doesn't fit in the padded input.
+    pub fn output_shape(&self) -> (usize, usize) {
+        let dim = |inp: usize, kernel: usize, stride: usize, padding: usize, dilation: usize| {
+            assert!(kernel > 0 && stride > 0 && dilation > 0, "Invalid convolution {self:?}!");
+            let span = dilation * (kernel - 1) + 1;
+            assert!(inp + 2 * padding >= span, "Kernel doesn't fit in the input of {self:?}!");
+            (inp + 2 * padding - span) / stride + 1
+        };
+
+        (
+            dim(self.input_shape.0, self.kernel.0, self.stride.0, self.padding.0, self.dilation.0),
+            dim(self.input_shape.1, self.kernel.1, self.stride.1, self.padding.1, self.dilation.1),
+        )
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.input_channels * self.input_shape.0 * self.input_shape.1
+    }
+
+    pub fn output_size(&self) -> usize {
+        let (height, width) = self.output_shape();
+        self.output_channels * height * width
+    }
+
+    /// Number of weights, stored by output channel, then input channel
+    /// within its group, then kernel row.
+    pub fn num_weights(&self) -> usize {
+        self.output_channels * self.group_inputs() * self.kernel.0 * self.kernel.1
+    }
+
+    /// Position in the input image that `kernel_pos` of the kernel applied for
+    /// the output at `output_pos` reads, or `None` if it lies in the padding.
+    pub fn input_position(&self, output_pos: (usize, usize), kernel_pos: (usize, usize)) -> Option<(usize, usize)> {
+        let coord = |out: usize, kernel: usize, stride: usize, padding: usize, dilation: usize, size: usize| {
+            (out * stride + kernel * dilation).checked_sub(padding).filter(|&pos| pos < size)
+        };
+
+        let (sh, sw) = self.stride;
+        let (ph, pw) = self.padding;
+        let (dh, dw) = self.dilation;
+        let y = coord(output_pos.0, kernel_pos.0, sh, ph, dh, self.input_shape.0)?;
+        let x = coord(output_pos.1, kernel_pos.1, sw, pw, dw, self.input_shape.1)?;
+        Some((y, x))
+    }
+}
@@ -1,7 +1,10 @@
+mod attention;
 mod buffer;
+mod conv;
 mod optimiser;
 mod shape;
 mod sparse;
+mod sparse_matrix;
 mod tensor_batch;
 mod tensor_single;
 
@@ -9,13 +12,22 @@ mod tensor_single;
 #[rustfmt::skip]
 mod tests;
 
-pub use crate::backend::{
-    util::{self, device_name, device_synchronise, panic_if_device_error},
-    DeviceHandles,
+pub use crate::{
+    backend::{
+        util::{
+            self, current_device, device_count, device_energy, device_name, device_synchronise, panic_if_device_error,
+            set_device,
+        },
+        DeviceHandles,
+    },
+    loader::Feat,
 };
+pub use attention::AttentionDescription;
 pub use buffer::DeviceBuffer;
-pub use optimiser::Optimiser;
+pub use conv::ConvolutionDescription;
+pub use optimiser::{Optimiser, OptimiserType, ParamKind, ParamSettings};
 pub use shape::Shape;
 pub use sparse::SparseTensor;
+pub use sparse_matrix::{SparseLayout, SparseMatrix};
 pub use tensor_batch::TensorBatch;
 pub use tensor_single::Tensor;
@@ -1,10 +1,100 @@
 use super::DeviceBuffer;
 use crate::backend::{ops, util, DeviceHandles};
 
+/// The update rule applied to the network weights.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum OptimiserType {
+    #[default]
+    AdamW,
+    /// SGD with (optionally Nesterov) momentum, which is
+    /// stored in the same buffer as Adam's first moment.
+    SGD { momentum: f32, nesterov: bool },
+    /// Rectified Adam, which falls back to SGD with momentum for the
+    /// first few steps while the adaptive learning rate has too much
+    /// variance, removing the need for a learning rate warmup.
+    RAdam,
+    /// Layer-wise adaptive moments, which scales the Adam step of each
+    /// parameter tensor by the ratio of the tensor's norm to the norm of
+    /// its step, keeping very large batch sizes stable.
+    LAMB,
+}
+
+const B1: f32 = 0.9;
+const B2: f32 = 0.999;
+
+/// Kind of parameters held by a segment, so that each can be optimised differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamKind {
+    /// Weight matrices and other multiplicative parameters.
+    Weights,
+    /// Bias vectors and other additive parameters, e.g. the shift of a
+    /// normalisation layer, which typically need no decay and wider clipping.
+    Biases,
+}
+
+/// How the parameters of one `ParamKind` are optimised.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParamSettings {
+    /// Parameters are clipped to `[-max_weight, max_weight]` after every update.
+    pub max_weight: f32,
+    /// Scales the weight decay.
+    pub decay_mult: f32,
+    /// Scales the learning rate, on top of any per-layer multiplier.
+    pub lr_mult: f32,
+}
+
+impl Default for ParamSettings {
+    fn default() -> Self {
+        Self { max_weight: 1.98, decay_mult: 1.0, lr_mult: 1.0 }
+    }
+}
+
+/// Bias correction of the first moment and variance rectification
+/// term (zero if it is not yet tractable) for RAdam at step `t`.
+pub(super) fn radam_scales(t: usize, (beta1, beta2): (f32, f32)) -> (f32, f32) {
+    let t = t as i32;
+    let b1t = beta1.powi(t);
+    let b2t = beta2.powi(t);
+
+    let rho_inf = 2.0 / (1.0 - beta2) - 1.0;
+    let rho_t = rho_inf - 2.0 * t as f32 * b2t / (1.0 - b2t);
+
+    let rectification = if rho_t > 4.0 {
+        let r = (rho_t - 4.0) * (rho_t - 2.0) * rho_inf / ((rho_inf - 4.0) * (rho_inf - 2.0) * rho_t);
+        (r * (1.0 - b2t)).sqrt()
+    } else {
+        0.0
+    };
+
+    (1.0 / (1.0 - b1t), rectification)
+}
+
+/// Slow weights for the Lookahead wrapper, which are moved a fraction
+/// `alpha` of the way towards the weights every `steps` updates, after
+/// which the weights are reset to them.
+struct Lookahead {
+    steps: usize,
+    alpha: f32,
+    slow: DeviceBuffer,
+    synced: bool,
+}
+
 /// A struct intended to hold all network weights and biases
 /// needed for training.
 pub struct Optimiser {
+    kind: OptimiserType,
     size: usize,
+    step: usize,
+    betas: (f32, f32),
+    segments: Vec<(usize, usize, f32, ParamKind)>,
+    segment_scales: Vec<f32>,
+    weight_settings: ParamSettings,
+    bias_settings: ParamSettings,
+    matrices: Vec<(usize, usize, usize)>,
+    centralise: bool,
+    norms: DeviceBuffer,
+    lookahead: Option<Lookahead>,
+    accumulator: Option<DeviceBuffer>,
     network: DeviceBuffer,
     momentum: DeviceBuffer,
     velocity: DeviceBuffer,
@@ -12,9 +102,21 @@ pub struct Optimiser {
 }
 
 impl Optimiser {
-    pub fn new(size: usize) -> Self {
+    pub fn new(size: usize, kind: OptimiserType) -> Self {
         Self {
+            kind,
             size,
+            step: 0,
+            betas: (B1, B2),
+            segments: Vec::new(),
+            segment_scales: Vec::new(),
+            weight_settings: ParamSettings::default(),
+            bias_settings: ParamSettings::default(),
+            matrices: Vec::new(),
+            centralise: false,
+            norms: DeviceBuffer::new(1),
+            lookahead: None,
+            accumulator: None,
             network: DeviceBuffer::new(size),
             momentum: DeviceBuffer::new(size),
             velocity: DeviceBuffer::new(size),
@@ -26,10 +128,166 @@ impl Optimiser {
         self.size
     }
 
+    /// Floats allocated for the moments and any slow weights
+    /// or accumulated gradients, besides the weights and gradients.
+    pub fn state_size(&self) -> usize {
+        let slow = self.lookahead.as_ref().map_or(0, |lookahead| lookahead.slow.size());
+        self.momentum.size() + self.velocity.size() + slow + self.accumulator.as_ref().map_or(0, DeviceBuffer::size)
+    }
+
+    pub fn kind(&self) -> OptimiserType {
+        self.kind
+    }
+
+    /// Number of updates applied so far.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    pub fn set_step(&mut self, step: usize) {
+        self.step = step;
+    }
+
+    pub fn betas(&self) -> (f32, f32) {
+        self.betas
+    }
+
+    /// Sets the decay rates of the first and second moments used by Adam,
+    /// RAdam and LAMB from the next update onwards. Bias corrections treat
+    /// the current betas as if they had been used for every step so far.
+    pub fn set_betas(&mut self, beta1: f32, beta2: f32) {
+        assert!((0.0..1.0).contains(&beta1) && (0.0..1.0).contains(&beta2), "Invalid betas ({beta1}, {beta2})!");
+        self.betas = (beta1, beta2);
+    }
+
+    /// Marks the `size` weights starting at `start` as a single parameter
+    /// tensor of the given `kind`, for optimisers that work per layer, with
+    /// its learning rate scaled by `lr_mult`.
+    pub fn add_segment(&mut self, start: usize, size: usize, lr_mult: f32, kind: ParamKind) {
+        assert!(start + size <= self.size, "Segment out of bounds!");
+        self.segments.push((start, size, lr_mult, kind));
+        self.segment_scales.push(1.0);
+    }
+
+    pub fn param_settings(&self, kind: ParamKind) -> ParamSettings {
+        match kind {
+            ParamKind::Weights => self.weight_settings,
+            ParamKind::Biases => self.bias_settings,
+        }
+    }
+
+    /// Sets how every segment of the given `kind` is optimised, by default
+    /// identically for weights and biases.
+    pub fn set_param_settings(&mut self, kind: ParamKind, settings: ParamSettings) {
+        assert!(settings.max_weight > 0.0, "Invalid clipping bound {}!", settings.max_weight);
+        assert!(settings.decay_mult >= 0.0 && settings.lr_mult >= 0.0, "Invalid multipliers in {settings:?}!");
+
+        match kind {
+            ParamKind::Weights => self.weight_settings = settings,
+            ParamKind::Biases => self.bias_settings = settings,
+        }
+    }
+
+    /// Further scales the learning rate of every segment within the `size`
+    /// weights starting at `start` by `scale`, with zero freezing them.
+    pub fn set_lr_scale(&mut self, start: usize, size: usize, scale: f32) {
+        for (&(seg_start, seg_size, ..), seg_scale) in self.segments.iter().zip(self.segment_scales.iter_mut()) {
+            if seg_start >= start && seg_start + seg_size <= start + size {
+                *seg_scale = scale;
+            }
+        }
+    }
+
+    /// Marks the weights starting at `start` as a matrix stored as `fan_in`
+    /// contiguous blocks of `fan_out`, for gradient centralisation.
+    pub fn add_matrix(&mut self, start: usize, fan_in: usize, fan_out: usize) {
+        assert!(start + fan_in * fan_out <= self.size, "Matrix out of bounds!");
+        self.matrices.push((start, fan_in, fan_out));
+    }
+
+    /// Before each update, subtract from the gradients of each output
+    /// neuron of every weight matrix their mean.
+    pub fn set_gradient_centralisation(&mut self, centralise: bool) {
+        self.centralise = centralise;
+    }
+
+    /// Start, size, learning rate multiplier and settings of each segment.
+    fn segments(&self) -> Vec<(usize, usize, f32, ParamSettings)> {
+        if self.segments.is_empty() {
+            vec![(0, self.size, 1.0, self.weight_settings)]
+        } else {
+            self.segments
+                .iter()
+                .zip(&self.segment_scales)
+                .map(|(&(start, size, mult, kind), scale)| (start, size, mult * scale, self.param_settings(kind)))
+                .collect()
+        }
+    }
+
+    /// Wraps the optimiser in Lookahead, with the slow weights updated every
+    /// `steps` updates by moving them a fraction `alpha` towards the weights.
+    pub fn set_lookahead(&mut self, steps: usize, alpha: f32) {
+        assert!(steps > 0, "Lookahead must sync at least every step!");
+        assert!(alpha > 0.0 && alpha <= 1.0, "Lookahead alpha must be in (0, 1]!");
+        self.lookahead = Some(Lookahead { steps, alpha, slow: DeviceBuffer::new(self.size), synced: false });
+    }
+
+    pub fn has_lookahead(&self) -> bool {
+        self.lookahead.is_some()
+    }
+
+    /// Returns false if Lookahead is not enabled.
+    pub fn write_slow_weights_to_host(&self, buf: &mut [f32]) -> bool {
+        if let Some(lookahead) = &self.lookahead {
+            lookahead.slow.write_to_host(buf);
+        }
+
+        self.lookahead.is_some()
+    }
+
+    pub fn load_slow_weights_from_host(&mut self, buf: &[f32]) {
+        let lookahead = self.lookahead.as_mut().expect("Lookahead is not enabled!");
+        lookahead.slow.load_from_host(buf);
+        lookahead.synced = true;
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.norms = DeviceBuffer::new(threads);
+    }
+
     pub fn zero_gradient(&self) {
         util::set_zero(self.gradients.ptr(), self.gradients.size());
     }
 
+    /// Adds the current gradients to a running sum, which is
+    /// moved back into the gradients by `take_accumulated_gradient`.
+    pub fn accumulate_gradient(&mut self, handle: DeviceHandles) {
+        let accumulator = self.accumulator.get_or_insert_with(|| {
+            let buf = DeviceBuffer::new(self.size);
+            util::set_zero(buf.ptr(), self.size);
+            buf
+        });
+
+        unsafe {
+            ops::add_to(handle, self.size, self.gradients.ptr(), accumulator.ptr());
+        }
+    }
+
+    pub fn take_accumulated_gradient(&self) {
+        let accumulator = self.accumulator.as_ref().expect("No gradients have been accumulated!");
+        self.gradients.load_from_device(accumulator);
+        util::set_zero(accumulator.ptr(), self.size);
+    }
+
+    /// Moves `average` a fraction `alpha` of the way towards the weights.
+    pub fn update_average(&self, handle: DeviceHandles, alpha: f32, average: &DeviceBuffer) {
+        assert_eq!(average.size(), self.size, "Average is the wrong size!");
+
+        unsafe {
+            ops::moving_average(handle, self.size, alpha, self.network.ptr(), average.ptr());
+        }
+    }
+
     /// Pointer to network buffer starting at `network.ptr() + index`.
     pub fn weights_offset(&self, index: usize) -> *mut f32 {
         assert!(index < self.size, "Index out of bounds: {index} >= {}!", self.size);
@@ -42,20 +300,202 @@ impl Optimiser {
         unsafe { self.gradients.ptr().add(index) }
     }
 
-    pub fn update(&self, handle: DeviceHandles, decay: f32, adj: f32, rate: f32) {
+    fn sum_of_squares(&self, handle: DeviceHandles, buf: *const f32, size: usize) -> f32 {
+        self.norms.set_zero();
+
+        unsafe {
+            ops::sum_of_squares(handle, size, buf, self.norms.ptr());
+        }
+
+        let mut sums = vec![0.0; self.norms.size()];
+        self.norms.write_to_host(&mut sums);
+        sums.iter().sum()
+    }
+
+    /// L2 norm of the gradients.
+    pub fn gradient_norm(&self, handle: DeviceHandles) -> f32 {
+        self.sum_of_squares(handle, self.gradients.ptr(), self.size).sqrt()
+    }
+
+    fn lamb_update(&self, handle: DeviceHandles, decay: f32, adj: f32, rate: f32) {
+        let t = self.step as i32;
+        let (beta1, beta2) = self.betas;
+        let momentum_scale = 1.0 / (1.0 - beta1.powi(t));
+        let velocity_scale = 1.0 / (1.0 - beta2.powi(t));
+
+        for (start, size, lr_mult, settings) in self.segments() {
+            let weights = self.weights_offset(start);
+            let steps = self.gradients_offset(start);
+
+            // frozen weights keep their momentum as well
+            let rate = rate * lr_mult * settings.lr_mult;
+            if rate == 0.0 {
+                continue;
+            }
+
+            // the gradient buffer is overwritten with the step for each weight
+            unsafe {
+                ops::lamb_moments(
+                    handle,
+                    size,
+                    decay * settings.decay_mult,
+                    adj,
+                    beta1,
+                    beta2,
+                    momentum_scale,
+                    velocity_scale,
+                    weights,
+                    self.momentum.ptr().add(start),
+                    self.velocity.ptr().add(start),
+                    steps,
+                );
+            }
+
+            let weights_norm = self.sum_of_squares(handle, weights, size).sqrt();
+            let steps_norm = self.sum_of_squares(handle, steps, size).sqrt();
+
+            let trust = if weights_norm > 0.0 && steps_norm > 0.0 { weights_norm / steps_norm } else { 1.0 };
+
+            unsafe {
+                ops::lamb_apply(handle, size, rate * trust, settings.max_weight, weights, steps);
+            }
+        }
+    }
+
+    fn update_segment(
+        &self,
+        handle: DeviceHandles,
+        segment: (usize, usize, ParamSettings),
+        decay: f32,
+        adj: f32,
+        rate: f32,
+    ) {
+        let (start, size, settings) = segment;
+        let (decay, max_weight) = (decay * settings.decay_mult, settings.max_weight);
         let decay_gamma = 1.0 - decay * rate;
+        let network = self.weights_offset(start);
+        let gradients = self.gradients_offset(start);
+        let momentum = unsafe { self.momentum.ptr().add(start) };
+        let velocity = unsafe { self.velocity.ptr().add(start) };
+        let (beta1, beta2) = self.betas;
+
         unsafe {
-            ops::update_weights(
-                handle,
-                self.size,
-                decay_gamma,
-                adj,
-                rate,
-                self.network.ptr(),
-                self.momentum.ptr(),
-                self.velocity.ptr(),
-                self.gradients.ptr(),
-            );
+            match self.kind {
+                OptimiserType::AdamW => ops::update_weights(
+                    handle,
+                    size,
+                    decay_gamma,
+                    adj,
+                    rate,
+                    max_weight,
+                    beta1,
+                    beta2,
+                    network,
+                    momentum,
+                    velocity,
+                    gradients,
+                ),
+                OptimiserType::SGD { momentum: beta, nesterov } => ops::update_weights_sgd(
+                    handle,
+                    size,
+                    decay_gamma,
+                    adj,
+                    rate,
+                    max_weight,
+                    beta,
+                    nesterov,
+                    network,
+                    momentum,
+                    gradients,
+                ),
+                OptimiserType::RAdam => {
+                    let (momentum_scale, rectification) = radam_scales(self.step, self.betas);
+                    ops::update_weights_radam(
+                        handle,
+                        size,
+                        decay_gamma,
+                        adj,
+                        rate,
+                        max_weight,
+                        beta1,
+                        beta2,
+                        momentum_scale,
+                        rectification,
+                        network,
+                        momentum,
+                        velocity,
+                        gradients,
+                    )
+                }
+                OptimiserType::LAMB => unreachable!(),
+            }
+        }
+    }
+
+    pub fn update(&mut self, handle: DeviceHandles, decay: f32, adj: f32, rate: f32) {
+        self.step += 1;
+
+        if let Some(lookahead) = &mut self.lookahead {
+            if !lookahead.synced {
+                lookahead.slow.load_from_device(&self.network);
+                lookahead.synced = true;
+            }
+        }
+
+        if self.centralise {
+            for &(start, fan_in, fan_out) in &self.matrices {
+                unsafe {
+                    ops::centralise_gradients(handle, fan_in, fan_out, self.gradients_offset(start));
+                }
+            }
+        }
+
+        if let OptimiserType::LAMB = self.kind {
+            self.lamb_update(handle, decay, adj, rate);
+        } else {
+            for (start, size, lr_mult, settings) in self.segments() {
+                let rate = rate * lr_mult * settings.lr_mult;
+
+                // frozen weights keep their momentum as well
+                if rate == 0.0 {
+                    continue;
+                }
+
+                self.update_segment(handle, (start, size, settings), decay, adj, rate);
+            }
+        }
+
+        if let Some(Lookahead { steps, alpha, slow, .. }) = &self.lookahead {
+            if self.step.is_multiple_of(*steps) {
+                unsafe {
+                    ops::lookahead_sync(handle, self.size, *alpha, self.network.ptr(), slow.ptr());
+                }
+            }
+        }
+    }
+
+    /// Copies the weights, moments, step, Lookahead slow weights and any
+    /// accumulated gradients of `other` directly between device buffers,
+    /// which may be on different devices.
+    pub fn load_state_from(&mut self, other: &Optimiser) {
+        assert_eq!(self.size, other.size, "Optimisers are different sizes!");
+
+        self.network.load_from_device(&other.network);
+        self.momentum.load_from_device(&other.momentum);
+        self.velocity.load_from_device(&other.velocity);
+        self.step = other.step;
+
+        if let (Some(lookahead), Some(theirs)) = (&mut self.lookahead, &other.lookahead) {
+            lookahead.slow.load_from_device(&theirs.slow);
+            lookahead.synced = theirs.synced;
+        }
+
+        match &other.accumulator {
+            Some(theirs) => {
+                let accumulator = self.accumulator.get_or_insert_with(|| DeviceBuffer::new(self.size));
+                accumulator.load_from_device(theirs);
+            }
+            None => self.accumulator = None,
         }
     }
 
@@ -1,7 +1,8 @@
-use super::{Shape, Tensor, TensorBatch};
+use super::{tensor_batch::fused_params, Shape, Tensor, TensorBatch};
 use crate::{
     backend::{ops, util, DeviceHandles},
     loader::Feat,
+    Activation,
 };
 
 /// A sparse representation of a tensor with dimensions `(1, input_dim)`.
@@ -43,6 +44,18 @@ impl SparseTensor {
         self.used
     }
 
+    pub fn input_dim(&self) -> usize {
+        self.input_dim
+    }
+
+    pub fn max_num_inputs(&self) -> usize {
+        self.max_num_inputs
+    }
+
+    pub(super) fn ptr(&self) -> *mut Feat {
+        self.ptr
+    }
+
     pub fn append(&mut self, inputs: &[Feat]) {
         let num_inputs = inputs.len() / self.max_num_inputs;
         assert!(self.used + num_inputs <= self.cap);
@@ -56,10 +69,28 @@ impl SparseTensor {
         self.used += num_inputs;
     }
 
+    /// As `append`, but returns once the copy is issued on `stream`.
+    ///
+    /// # Safety
+    /// `inputs` should be pinned, and must not change until `stream` is synchronised.
+    pub unsafe fn append_async(&mut self, inputs: &[Feat], stream: &util::CopyStream) {
+        let num_inputs = inputs.len() / self.max_num_inputs;
+        assert!(self.used + num_inputs <= self.cap);
+
+        let used_space = self.used * self.max_num_inputs;
+        util::copy_to_device_async(self.ptr.add(used_space), inputs.as_ptr(), inputs.len(), stream);
+
+        self.used += num_inputs;
+    }
+
     /// Sparse Affine Transformation:
     ///
     /// Computes outputs[i] = weights * inputs[i] + biases.
     ///
+    /// The opposing perspective fills the rest of each output, and
+    /// may be narrower, in which case it is the first outputs of its
+    /// accumulator.
+    ///
     /// # Safety
     /// `weights`, `biases` and `inputs` must be initialised properly.
     pub unsafe fn affine(
@@ -71,20 +102,67 @@ impl SparseTensor {
     ) {
         assert!(inputs.used > 0);
         let input_dim = inputs.input_dim;
-        let output_dim = outputs.element_size() / 2;
+        let output_dim = biases.num_elements();
+        let opp_dim = outputs.element_size() - output_dim;
 
         assert_eq!(weights.shape(), Shape::new(output_dim, input_dim));
         assert_eq!(biases.shape(), Shape::new(1, output_dim));
+        assert!(opp_dim <= output_dim);
 
         ops::sparse_affine_forward(
             handle,
             inputs.used,
             inputs.max_num_inputs,
             output_dim,
+            opp_dim,
+            weights.ptr(),
+            biases.ptr(),
+            inputs.ptr,
+            outputs.ptr(),
+        );
+    }
+
+    /// Fuses `affine` and `TensorBatch::activate`, writing `op` of the outputs to `activated`.
+    ///
+    /// # Safety
+    /// `weights`, `biases` and `inputs` must be initialised properly.
+    pub unsafe fn affine_activate(
+        handle: DeviceHandles,
+        op: Activation,
+        weights: &Tensor,
+        inputs: &SparseTensor,
+        biases: &Tensor,
+        outputs: &TensorBatch,
+        activated: &TensorBatch,
+    ) {
+        assert!(inputs.used > 0);
+        let input_dim = inputs.input_dim;
+        let output_dim = biases.num_elements();
+        let opp_dim = outputs.element_size() - output_dim;
+
+        assert_eq!(weights.shape(), Shape::new(output_dim, input_dim));
+        assert_eq!(biases.shape(), Shape::new(1, output_dim));
+        assert_eq!(outputs.shape(), activated.shape());
+        assert!(inputs.used <= activated.cap(), "Overflow!");
+        assert!(opp_dim <= output_dim);
+
+        let (min, max, slope, square) = fused_params(op);
+
+        ops::sparse_affine_activate_forward(
+            handle,
+            inputs.used,
+            inputs.max_num_inputs,
+            output_dim,
+            opp_dim,
             weights.ptr(),
             biases.ptr(),
             inputs.ptr,
             outputs.ptr(),
+            activated.ptr(),
+            min,
+            max,
+            slope,
+            square,
         );
     }
 
@@ -92,6 +170,10 @@ impl SparseTensor {
     ///
     /// Computes backprop for outputs[i] = weights * inputs[i] + biases.
     ///
+    /// `output` is a copy of the outputs taken before they were replaced by
+    /// `errors`, which is only needed with FT regularisation, as `ft_reg`
+    /// is ignored without it.
+    ///
     /// # Safety
     /// `weights`, `biases` and `errors` must be initialised properly.
     pub unsafe fn affine_backprop(
@@ -100,15 +182,19 @@ impl SparseTensor {
         inputs: &SparseTensor,
         biases_grad: &Tensor,
         errors: &TensorBatch,
-        output: &TensorBatch,
+        output: Option<&TensorBatch>,
         ft_reg: f32,
     ) {
+        let (output, ft_reg) = regularised_output(output, ft_reg);
+
         assert!(inputs.used > 0);
         let input_dim = inputs.input_dim;
-        let output_dim = errors.element_size() / 2;
+        let output_dim = biases_grad.num_elements();
+        let opp_dim = errors.element_size() - output_dim;
 
         assert_eq!(weights_grad.shape(), Shape::new(output_dim, input_dim));
         assert_eq!(biases_grad.shape(), Shape::new(1, output_dim));
+        assert!(opp_dim <= output_dim);
 
         ops::sparse_affine_backward(
             handle,
@@ -116,11 +202,12 @@ impl SparseTensor {
             inputs.max_num_inputs,
             input_dim,
             output_dim,
+            opp_dim,
             weights_grad.ptr(),
             biases_grad.ptr(),
             inputs.ptr,
             errors.ptr(),
-            output.ptr(),
+            output,
             ft_reg,
         );
     }
@@ -153,6 +240,47 @@ impl SparseTensor {
         );
     }
 
+    /// As `affine_activate`, for `single_affine`.
+    ///
+    /// # Safety
+    /// `weights`, `biases` and `inputs` must be initialised properly.
+    pub unsafe fn single_affine_activate(
+        handle: DeviceHandles,
+        op: Activation,
+        weights: &Tensor,
+        inputs: &SparseTensor,
+        biases: &Tensor,
+        outputs: &TensorBatch,
+        activated: &TensorBatch,
+    ) {
+        assert!(inputs.used > 0);
+        let input_dim = inputs.input_dim;
+        let output_dim = outputs.element_size();
+
+        assert_eq!(weights.shape(), Shape::new(output_dim, input_dim));
+        assert_eq!(biases.shape(), Shape::new(1, output_dim));
+        assert_eq!(outputs.shape(), activated.shape());
+        assert!(inputs.used <= activated.cap(), "Overflow!");
+
+        let (min, max, slope, square) = fused_params(op);
+
+        ops::single_sparse_affine_activate_forward(
+            handle,
+            inputs.used,
+            inputs.max_num_inputs,
+            output_dim,
+            weights.ptr(),
+            biases.ptr(),
+            inputs.ptr,
+            outputs.ptr(),
+            activated.ptr(),
+            min,
+            max,
+            slope,
+            square,
+        );
+    }
+
     /// # Safety
     /// `weights`, `biases` and `errors` must be initialised properly.
     pub unsafe fn single_affine_backprop(
@@ -161,9 +289,11 @@ impl SparseTensor {
         inputs: &SparseTensor,
         biases_grad: &Tensor,
         errors: &TensorBatch,
-        output: &TensorBatch,
+        output: Option<&TensorBatch>,
         ft_reg: f32,
     ) {
+        let (output, ft_reg) = regularised_output(output, ft_reg);
+
         assert!(inputs.used > 0);
         let input_dim = inputs.input_dim;
         let output_dim = errors.element_size();
@@ -181,8 +311,17 @@ impl SparseTensor {
             biases_grad.ptr(),
             inputs.ptr,
             errors.ptr(),
-            output.ptr(),
+            output,
             ft_reg,
         );
     }
 }
+
+/// Pointer to the output copy, if regularising, with the regularisation to
+/// apply, so that nothing is read from the outputs when there isn't one.
+fn regularised_output(output: Option<&TensorBatch>, ft_reg: f32) -> (*const f32, f32) {
+    match output {
+        Some(output) if ft_reg != 0.0 => (output.ptr(), ft_reg),
+        _ => (std::ptr::null(), 0.0),
+    }
+}
@@ -0,0 +1,312 @@
+use super::{DeviceBuffer, Shape, SparseTensor, Tensor, TensorBatch};
+use crate::backend::{ops, util, DeviceHandles};
+
+/// Layout of the indices of a [`SparseMatrix`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SparseLayout {
+    /// The row of each entry, which suits ops that scatter each entry.
+    Coo,
+    /// The offset of the first entry of each row, with the number of entries
+    /// at the end, which suits ops that work on each row in turn.
+    Csr,
+}
+
+/// A sparse matrix of `rows` by `cols` on the device, with `i32` column
+/// indices and `f32` values, for sparse ops that need more than the fixed
+/// number of features per row of a [`SparseTensor`].
+///
+/// Each row of the matrix is a single sample of a batch, as in a
+/// [`TensorBatch`], so `cols` is the input dimension of an affine.
+pub struct SparseMatrix {
+    layout: SparseLayout,
+    rows: usize,
+    cols: usize,
+    nnz: usize,
+    cap: usize,
+    row_indices: *mut i32,
+    col_indices: *mut i32,
+    values: DeviceBuffer,
+}
+
+impl Drop for SparseMatrix {
+    fn drop(&mut self) {
+        unsafe {
+            util::free(self.row_indices, self.row_indices_len());
+            util::free(self.col_indices, self.cap);
+        }
+    }
+}
+
+fn row_indices_len(layout: SparseLayout, rows: usize, cap: usize) -> usize {
+    match layout {
+        SparseLayout::Coo => cap,
+        SparseLayout::Csr => rows + 1,
+    }
+}
+
+impl SparseMatrix {
+    /// Space for `cap` entries, with the indices uninitialised.
+    fn alloc(layout: SparseLayout, rows: usize, cols: usize, cap: usize) -> Self {
+        assert!(rows < 2_147_483_647 && cols < 2_147_483_647, "Unsupported dimensions {rows}x{cols}!");
+
+        // zero sized allocations are not allowed
+        let cap = cap.max(1);
+
+        Self {
+            layout,
+            rows,
+            cols,
+            nnz: 0,
+            cap,
+            row_indices: util::malloc(row_indices_len(layout, rows, cap)),
+            col_indices: util::malloc(cap),
+            values: DeviceBuffer::new(cap),
+        }
+    }
+
+    fn row_indices_len(&self) -> usize {
+        row_indices_len(self.layout, self.rows, self.cap)
+    }
+
+    /// Loads a matrix from the host, where `row_indices` holds the row of
+    /// each entry for [`SparseLayout::Coo`], or the `rows + 1` offsets of
+    /// each row for [`SparseLayout::Csr`].
+    pub fn from_host(
+        layout: SparseLayout,
+        rows: usize,
+        cols: usize,
+        row_indices: &[i32],
+        col_indices: &[i32],
+        values: &[f32],
+    ) -> Self {
+        let nnz = values.len();
+        assert_eq!(col_indices.len(), nnz);
+        assert!(col_indices.iter().all(|&col| (0..cols as i32).contains(&col)), "Column out of bounds!");
+
+        match layout {
+            SparseLayout::Coo => {
+                assert_eq!(row_indices.len(), nnz);
+                assert!(row_indices.iter().all(|&row| (0..rows as i32).contains(&row)), "Row out of bounds!");
+            }
+            SparseLayout::Csr => {
+                assert_eq!(row_indices.len(), rows + 1);
+                assert_eq!(row_indices[0], 0);
+                assert_eq!(row_indices[rows] as usize, nnz);
+                assert!(row_indices.windows(2).all(|w| w[0] <= w[1]), "Offsets must be ascending!");
+            }
+        }
+
+        let mut matrix = Self::alloc(layout, rows, cols, nnz);
+        matrix.nnz = nnz;
+
+        unsafe {
+            util::copy_to_device(matrix.row_indices, row_indices.as_ptr(), row_indices.len());
+            util::copy_to_device(matrix.col_indices, col_indices.as_ptr(), nnz);
+        }
+
+        matrix.values.load_from_host(values);
+
+        matrix
+    }
+
+    /// CSR matrix of the features of each sample of `inputs`, from the
+    /// perspective of the side to move, or of the opposing side if `opp`,
+    /// with each feature having a value of one.
+    pub fn from_sparse_tensor(handle: DeviceHandles, inputs: &SparseTensor, opp: bool) -> Self {
+        let rows = inputs.used();
+        assert!(rows > 0);
+        let mut matrix = Self::alloc(SparseLayout::Csr, rows, inputs.input_dim(), rows * inputs.max_num_inputs());
+
+        unsafe {
+            ops::sparse_ell_to_csr(
+                handle,
+                rows,
+                inputs.max_num_inputs(),
+                opp,
+                inputs.ptr(),
+                matrix.row_indices,
+                matrix.col_indices,
+                matrix.values.ptr(),
+            );
+        }
+
+        matrix.nnz = matrix.row_offset(rows);
+
+        matrix
+    }
+
+    fn row_offset(&self, row: usize) -> usize {
+        let mut offset = 0;
+
+        unsafe {
+            util::copy_from_device(&mut offset, self.row_indices.add(row), 1);
+        }
+
+        util::device_synchronise();
+
+        offset as usize
+    }
+
+    pub fn layout(&self) -> SparseLayout {
+        self.layout
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Number of stored entries.
+    pub fn nnz(&self) -> usize {
+        self.nnz
+    }
+
+    /// Copy of the matrix in `layout`. Converting to CSR keeps the entries of
+    /// each row in order on the CPU, but their order is unspecified on CUDA.
+    pub fn to_layout(&self, handle: DeviceHandles, layout: SparseLayout) -> Self {
+        let mut matrix = Self::alloc(layout, self.rows, self.cols, self.nnz);
+        matrix.nnz = self.nnz;
+
+        unsafe {
+            match (self.layout, layout) {
+                (SparseLayout::Coo, SparseLayout::Csr) => {
+                    let cursors = util::malloc::<i32>(self.rows.max(1));
+
+                    ops::sparse_coo_to_csr(
+                        handle,
+                        self.nnz,
+                        self.rows,
+                        self.row_indices,
+                        self.col_indices,
+                        self.values.ptr(),
+                        matrix.row_indices,
+                        cursors,
+                        matrix.col_indices,
+                        matrix.values.ptr(),
+                    );
+
+                    util::device_synchronise();
+                    util::free(cursors, self.rows.max(1));
+                }
+                (SparseLayout::Csr, SparseLayout::Coo) => {
+                    ops::sparse_csr_to_coo(handle, self.rows, self.row_indices, matrix.row_indices);
+                    util::copy_on_device(matrix.col_indices, self.col_indices, self.nnz);
+                    util::copy_on_device(matrix.values.ptr(), self.values.ptr(), self.nnz);
+                }
+                _ => {
+                    let len = self.row_indices_len().min(matrix.row_indices_len());
+                    util::copy_on_device(matrix.row_indices, self.row_indices, len);
+                    util::copy_on_device(matrix.col_indices, self.col_indices, self.nnz);
+                    util::copy_on_device(matrix.values.ptr(), self.values.ptr(), self.nnz);
+                }
+            }
+        }
+
+        util::device_synchronise();
+
+        matrix
+    }
+
+    pub fn to_csr(&self, handle: DeviceHandles) -> Self {
+        self.to_layout(handle, SparseLayout::Csr)
+    }
+
+    pub fn to_coo(&self, handle: DeviceHandles) -> Self {
+        self.to_layout(handle, SparseLayout::Coo)
+    }
+
+    /// Row indices, column indices and values, with the
+    /// row indices as they are stored in the layout.
+    pub fn to_host(&self) -> (Vec<i32>, Vec<i32>, Vec<f32>) {
+        let len = match self.layout {
+            SparseLayout::Coo => self.nnz,
+            SparseLayout::Csr => self.rows + 1,
+        };
+
+        let mut row_indices = vec![0; len];
+        let mut col_indices = vec![0; self.nnz];
+        let mut values = vec![0.0; self.nnz];
+
+        unsafe {
+            util::copy_from_device(row_indices.as_mut_ptr(), self.row_indices, len);
+            util::copy_from_device(col_indices.as_mut_ptr(), self.col_indices, self.nnz);
+        }
+
+        self.values.write_to_host(&mut values);
+
+        (row_indices, col_indices, values)
+    }
+
+    /// Sparse Affine Transformation:
+    ///
+    /// Computes outputs[i] = weights * inputs[i] + biases, from a CSR matrix.
+    ///
+    /// # Safety
+    /// `weights`, `biases` and `inputs` must be initialised properly.
+    pub unsafe fn affine(
+        handle: DeviceHandles,
+        weights: &Tensor,
+        inputs: &SparseMatrix,
+        biases: &Tensor,
+        outputs: &TensorBatch,
+    ) {
+        assert_eq!(inputs.layout, SparseLayout::Csr, "Sparse affine needs a CSR matrix!");
+        assert!(inputs.rows > 0);
+        assert!(inputs.rows <= outputs.cap(), "Overflow!");
+        let output_dim = outputs.element_size();
+
+        assert_eq!(weights.shape(), Shape::new(output_dim, inputs.cols));
+        assert_eq!(biases.shape(), Shape::new(1, output_dim));
+
+        ops::sparse_csr_affine_forward(
+            handle,
+            inputs.rows,
+            output_dim,
+            weights.ptr(),
+            biases.ptr(),
+            inputs.row_indices,
+            inputs.col_indices,
+            inputs.values.ptr(),
+            outputs.ptr(),
+        );
+    }
+
+    /// Sparse Affine Transformation:
+    ///
+    /// Computes backprop for outputs[i] = weights * inputs[i] + biases,
+    /// from a COO matrix, adding to the gradients.
+    ///
+    /// # Safety
+    /// `weights`, `biases` and `errors` must be initialised properly.
+    pub unsafe fn affine_backprop(
+        handle: DeviceHandles,
+        weights_grad: &Tensor,
+        inputs: &SparseMatrix,
+        biases_grad: &Tensor,
+        errors: &TensorBatch,
+    ) {
+        assert_eq!(inputs.layout, SparseLayout::Coo, "Sparse affine backprop needs a COO matrix!");
+        assert!(inputs.rows > 0);
+        assert!(inputs.rows <= errors.cap(), "Overflow!");
+        let output_dim = errors.element_size();
+
+        assert_eq!(weights_grad.shape(), Shape::new(output_dim, inputs.cols));
+        assert_eq!(biases_grad.shape(), Shape::new(1, output_dim));
+
+        ops::sparse_coo_affine_backward(
+            handle,
+            inputs.nnz,
+            inputs.rows,
+            output_dim,
+            weights_grad.ptr(),
+            biases_grad.ptr(),
+            inputs.row_indices,
+            inputs.col_indices,
+            inputs.values.ptr(),
+            errors.ptr(),
+        );
+    }
+}
@@ -1,7 +1,7 @@
-use super::{DeviceBuffer, Shape, Tensor};
+use super::{AttentionDescription, ConvolutionDescription, DeviceBuffer, Shape, Tensor};
 use crate::{
-    backend::{ops, DeviceHandles},
-    Activation,
+    backend::{ops, util, DeviceHandles},
+    Activation, Axis, ClampGradient, Reduction,
 };
 
 pub struct TensorBatch {
@@ -19,6 +19,18 @@ impl TensorBatch {
         Self { shape, cap, buf: DeviceBuffer::new(cap * shape.size()) }
     }
 
+    /// Creates a tensor with given `shape` and `cap` in memory
+    /// starting at `ptr`, which is owned elsewhere.
+    ///
+    /// # Safety
+    /// The memory must hold at least `cap * shape.size()`
+    /// floats, and outlive the tensor.
+    pub unsafe fn view(shape: Shape, cap: usize, ptr: *mut f32) -> Self {
+        assert!(cap > 0, "Cannot have a 0 sized batch!");
+
+        Self { shape, cap, buf: DeviceBuffer::view(ptr, cap * shape.size()) }
+    }
+
     pub fn shape(&self) -> Shape {
         self.shape
     }
@@ -31,7 +43,8 @@ impl TensorBatch {
         false
     }
 
-    pub(crate) fn ptr(&self) -> *mut f32 {
+    /// Raw device pointer to the start of the batch.
+    pub fn ptr(&self) -> *mut f32 {
         self.buf.ptr()
     }
 
@@ -47,6 +60,12 @@ impl TensorBatch {
         self.buf.load_from_host(buf);
     }
 
+    /// # Safety
+    /// See `DeviceBuffer::load_from_host_async`.
+    pub unsafe fn load_from_host_async(&self, buf: &[f32], stream: &util::CopyStream) {
+        self.buf.load_from_host_async(buf, stream);
+    }
+
     pub fn write_to_host(&self, buf: &mut [f32]) {
         self.buf.write_to_host(buf);
     }
@@ -57,6 +76,10 @@ impl TensorBatch {
         self.buf.load_from_device(&other.buf);
     }
 
+    pub fn set_zero(&self) {
+        self.buf.set_zero();
+    }
+
     /// # Safety
     /// `a` must be initialised, all other sources of unsafety
     /// should trip an assert.
@@ -87,6 +110,7 @@ impl TensorBatch {
         ops::splat_mul_matrixt_vector(handle, m, n, a.ptr(), y.ptr(), x.ptr(), batch_size);
     }
 
+    /// Adds the sum over the batch of `y[i] * x[i]^T` to `a`.
     pub fn reduce_add_mul_vector_vectort(
         handle: DeviceHandles,
         batch_size: usize,
@@ -113,6 +137,8 @@ impl TensorBatch {
         }
     }
 
+    /// Adds the sum over the batch of `inp` to `out`.
+    ///
     /// # Safety
     /// `out` must be pointing to valid allocated memory.
     pub unsafe fn reduce_add(
@@ -133,12 +159,239 @@ impl TensorBatch {
         ops::splat_add(handle, batch_size, out.element_size(), inp.ptr(), out.ptr());
     }
 
+    /// Fuses `splat_add` and `activate`, adding `inp` to `pre` in place
+    /// and writing `op` of the result to `out`.
+    ///
+    /// # Safety
+    /// `inp` must be initialised.
+    pub unsafe fn splat_add_activate(
+        handle: DeviceHandles,
+        batch_size: usize,
+        op: Activation,
+        inp: &Tensor,
+        pre: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(inp.shape(), pre.shape());
+        assert_eq!(pre.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert!(batch_size <= pre.cap() && batch_size <= out.cap(), "Overflow!");
+        let (min, max, slope, square) = fused_params(op);
+        let size = out.element_size();
+        ops::splat_add_activate(handle, batch_size, size, inp.ptr(), pre.ptr(), out.ptr(), min, max, slope, square);
+    }
+
+    /// Adds `inp`, a scalar or a vector of the same size as each
+    /// tensor, to every tensor in the batch.
+    ///
+    /// # Safety
+    /// `inp` must be initialised.
+    pub unsafe fn broadcast_add(handle: DeviceHandles, batch_size: usize, inp: &Tensor, out: &TensorBatch) {
+        let (width, channels) = broadcast_dims(inp, out);
+        assert!(batch_size <= out.cap(), "Overflow!");
+        ops::broadcast_add(handle, batch_size * width, width, channels, inp.ptr(), out.ptr());
+    }
+
+    /// Accumulates the gradient of the input to `broadcast_add` into `grad`,
+    /// given the gradients `errors` of its outputs, which are also the
+    /// gradients of the batch it was added to.
+    ///
+    /// # Safety
+    /// `grad` must be initialised.
+    pub unsafe fn backprop_broadcast_add(
+        handle: DeviceHandles,
+        batch_size: usize,
+        errors: &TensorBatch,
+        grad: &Tensor,
+    ) {
+        let (width, channels) = broadcast_dims(grad, errors);
+        assert!(batch_size <= errors.cap(), "Overflow!");
+        ops::backprop_broadcast_add(handle, batch_size * width, width, channels, errors.ptr(), grad.ptr());
+    }
+
+    /// Multiplies every tensor of `x` element-wise by `inp`, a scalar
+    /// or a vector of the same size as each tensor.
+    ///
+    /// # Safety
+    /// `inp` must be initialised.
+    pub unsafe fn broadcast_mul(
+        handle: DeviceHandles,
+        batch_size: usize,
+        inp: &Tensor,
+        x: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(x.shape(), out.shape(), "Mismatched tensor shapes!");
+        let (width, channels) = broadcast_dims(inp, x);
+        assert!(batch_size <= x.cap() && batch_size <= out.cap(), "Overflow!");
+        ops::broadcast_mul(handle, batch_size * width, width, channels, inp.ptr(), x.ptr(), out.ptr());
+    }
+
+    /// This calculates `out[i] = errors[i] * inp[i]`, with `out` holding the
+    /// inputs `x` to `broadcast_mul`, accumulating the gradient of `inp` into `grad`.
+    ///
+    /// # Safety
+    /// `inp` and `grad` must be initialised.
+    pub unsafe fn backprop_broadcast_mul(
+        handle: DeviceHandles,
+        batch_size: usize,
+        inp: &Tensor,
+        grad: &Tensor,
+        errors: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(errors.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert_eq!(inp.num_elements(), grad.num_elements(), "Mismatched tensor shapes!");
+        let (width, channels) = broadcast_dims(inp, out);
+        assert!(batch_size <= errors.cap() && batch_size <= out.cap(), "Overflow!");
+
+        ops::backprop_broadcast_mul(
+            handle,
+            batch_size * width,
+            width,
+            channels,
+            inp.ptr(),
+            grad.ptr(),
+            errors.ptr(),
+            out.ptr(),
+        );
+    }
+
+    /// Multiplies every tensor of `x` element-wise by the corresponding
+    /// tensor of `a`, which is repeated across it if `a` is smaller.
+    pub fn hadamard(handle: DeviceHandles, batch_size: usize, a: &TensorBatch, x: &TensorBatch, out: &TensorBatch) {
+        assert_eq!(x.shape(), out.shape(), "Mismatched tensor shapes!");
+        let (width, channels) = (x.element_size(), a.element_size());
+        assert_eq!(width % channels, 0, "Cannot broadcast {channels} elements across {width}!");
+        assert!(batch_size <= a.cap() && batch_size <= x.cap() && batch_size <= out.cap(), "Overflow!");
+
+        unsafe {
+            ops::hadamard(handle, batch_size, width, channels, a.ptr(), x.ptr(), out.ptr());
+        }
+    }
+
+    /// This calculates `out = errors * a`, with `out` holding the inputs `x`
+    /// to `hadamard`, and writes the gradients of `a` to `a_grad`.
+    pub fn backprop_hadamard(
+        handle: DeviceHandles,
+        batch_size: usize,
+        a: &TensorBatch,
+        a_grad: &TensorBatch,
+        errors: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(errors.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert_eq!(a.shape(), a_grad.shape(), "Mismatched tensor shapes!");
+        let (width, channels) = (out.element_size(), a.element_size());
+        assert_eq!(width % channels, 0, "Cannot broadcast {channels} elements across {width}!");
+        assert!(batch_size <= a_grad.cap() && batch_size <= out.cap(), "Overflow!");
+
+        unsafe {
+            ops::backprop_hadamard(handle, batch_size, width, channels, a.ptr(), a_grad.ptr(), errors.ptr(), out.ptr());
+        }
+    }
+
     /// # Safety
     /// `inp` must be pointing to valid allocated memory.
     pub unsafe fn add_to(handle: DeviceHandles, batch_size: usize, inp: &TensorBatch, out: &TensorBatch) {
         Self::map(ops::add_to, handle, batch_size, inp, out);
     }
 
+    /// Copies each tensor of `inp` into its tensor of `out`, starting at
+    /// `offset`, for concatenating tensors.
+    pub fn concat_into(handle: DeviceHandles, batch_size: usize, inp: &TensorBatch, out: &TensorBatch, offset: usize) {
+        let (size, stride) = (inp.element_size(), out.element_size());
+        assert!(offset + size <= stride, "Overflow!");
+        assert!(batch_size <= inp.cap() && batch_size <= out.cap(), "Overflow!");
+
+        unsafe {
+            ops::copy_strided(handle, batch_size, size, size, 0, stride, offset, inp.ptr(), out.ptr());
+        }
+    }
+
+    /// Reverse of `concat_into`, copying the part of each tensor of
+    /// `inp` starting at `offset` into its tensor of `out`.
+    pub fn split_from(handle: DeviceHandles, batch_size: usize, inp: &TensorBatch, offset: usize, out: &TensorBatch) {
+        let (stride, size) = (inp.element_size(), out.element_size());
+        assert!(offset + size <= stride, "Overflow!");
+        assert!(batch_size <= inp.cap() && batch_size <= out.cap(), "Overflow!");
+
+        unsafe {
+            ops::copy_strided(handle, batch_size, size, stride, offset, size, 0, inp.ptr(), out.ptr());
+        }
+    }
+
+    /// Gradients of the inputs to `split_from`, given the gradients `inp`
+    /// of its outputs, which are zero outside of the copied part.
+    pub fn backprop_split(
+        handle: DeviceHandles,
+        batch_size: usize,
+        inp: &TensorBatch,
+        offset: usize,
+        out: &TensorBatch,
+    ) {
+        out.buf.set_zero();
+        Self::concat_into(handle, batch_size, inp, out, offset);
+    }
+
+    /// Multiplies the first `stride` elements of each consecutive run of
+    /// `2 * stride` in each tensor with the rest, after clipping each to
+    /// `[min, max]`, so that `out` has half as many elements as `inp`.
+    pub fn pairwise_mul(
+        handle: DeviceHandles,
+        batch_size: usize,
+        stride: usize,
+        (min, max): (f32, f32),
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(inp.element_size(), 2 * out.element_size(), "Mismatched tensor shapes!");
+        assert_eq!(out.element_size() % stride, 0, "Invalid stride {stride}!");
+        assert!(batch_size <= inp.cap() && batch_size <= out.cap(), "Overflow!");
+
+        unsafe {
+            ops::pairwise_mul(handle, batch_size * out.element_size(), stride, min, max, inp.ptr(), out.ptr());
+        }
+    }
+
+    /// This calculates the gradients of the inputs to `pairwise_mul` given
+    /// the gradients `inp` of its outputs, overwriting the inputs in `out`.
+    pub fn backprop_pairwise_mul(
+        handle: DeviceHandles,
+        batch_size: usize,
+        stride: usize,
+        (min, max): (f32, f32),
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(out.element_size(), 2 * inp.element_size(), "Mismatched tensor shapes!");
+        assert_eq!(inp.element_size() % stride, 0, "Invalid stride {stride}!");
+        assert!(batch_size <= inp.cap() && batch_size <= out.cap(), "Overflow!");
+
+        unsafe {
+            ops::backprop_pairwise_mul(handle, batch_size * inp.element_size(), stride, min, max, inp.ptr(), out.ptr());
+        }
+    }
+
+    /// This calculates `out[i] = mask[i] * inp[i]` elementwise, for a mask
+    /// covering the whole batch, as used by dropout in both directions.
+    pub fn masked_scale(
+        handle: DeviceHandles,
+        batch_size: usize,
+        mask: &DeviceBuffer,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert!(batch_size <= inp.cap() && batch_size <= out.cap(), "Overflow!");
+
+        let size = batch_size * inp.element_size();
+        assert!(size <= mask.size(), "Mask is too small!");
+
+        unsafe {
+            ops::masked_scale(handle, size, mask.ptr(), inp.ptr(), out.ptr());
+        }
+    }
+
     /// Modifies a batch of tensors.
     fn map(
         f: unsafe fn(DeviceHandles, usize, *const f32, *mut f32),
@@ -155,12 +408,58 @@ impl TensorBatch {
         }
     }
 
+    /// Modifies a batch of tensors with an operation clipped to `[min, max]`.
+    #[allow(clippy::too_many_arguments)]
+    fn map_bounded(
+        f: unsafe fn(DeviceHandles, usize, *const f32, *mut f32, f32, f32),
+        handle: DeviceHandles,
+        batch_size: usize,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+        min: f32,
+        max: f32,
+    ) {
+        assert!(min < max, "Invalid activation bounds!");
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert_eq!(inp.cap(), out.cap(), "Mismatched cap sizes!");
+        assert!(batch_size <= inp.cap(), "Overflow!");
+        unsafe {
+            f(handle, batch_size * inp.element_size(), inp.ptr(), out.ptr(), min, max);
+        }
+    }
+
+    /// Modifies a batch of tensors with an operation taking a single parameter.
+    fn map_param(
+        f: unsafe fn(DeviceHandles, usize, *const f32, *mut f32, f32),
+        handle: DeviceHandles,
+        batch_size: usize,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+        param: f32,
+    ) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert_eq!(inp.cap(), out.cap(), "Mismatched cap sizes!");
+        assert!(batch_size <= inp.cap(), "Overflow!");
+        unsafe {
+            f(handle, batch_size * inp.element_size(), inp.ptr(), out.ptr(), param);
+        }
+    }
+
     /// This calulates `out[i] = op(inp[i])` for a batch of input.
     pub fn activate(handle: DeviceHandles, batch_size: usize, op: Activation, inp: &TensorBatch, out: &TensorBatch) {
         match op {
             Activation::ReLU => Self::map(ops::activate_relu, handle, batch_size, inp, out),
             Activation::CReLU => Self::map(ops::activate_crelu, handle, batch_size, inp, out),
             Activation::SCReLU => Self::map(ops::activate_screlu, handle, batch_size, inp, out),
+            Activation::BoundedCReLU { min, max } => {
+                Self::map_bounded(ops::activate_bounded_crelu, handle, batch_size, inp, out, min, max)
+            }
+            Activation::BoundedSCReLU { min, max } => {
+                Self::map_bounded(ops::activate_bounded_screlu, handle, batch_size, inp, out, min, max)
+            }
+            Activation::LeakyReLU(slope) => {
+                Self::map_param(ops::activate_leaky_relu, handle, batch_size, inp, out, slope)
+            }
         }
     }
 
@@ -176,9 +475,337 @@ impl TensorBatch {
             Activation::ReLU => Self::map(ops::backprop_relu, handle, batch_size, inp, out),
             Activation::CReLU => Self::map(ops::backprop_crelu, handle, batch_size, inp, out),
             Activation::SCReLU => Self::map(ops::backprop_screlu, handle, batch_size, inp, out),
+            Activation::BoundedCReLU { min, max } => {
+                Self::map_bounded(ops::backprop_bounded_crelu, handle, batch_size, inp, out, min, max)
+            }
+            Activation::BoundedSCReLU { min, max } => {
+                Self::map_bounded(ops::backprop_bounded_screlu, handle, batch_size, inp, out, min, max)
+            }
+            Activation::LeakyReLU(slope) => {
+                Self::map_param(ops::backprop_leaky_relu, handle, batch_size, inp, out, slope)
+            }
         }
     }
 
+    /// This calculates `out[i] = clamp(inp[i], min, max)` for a batch of input.
+    pub fn clamp(handle: DeviceHandles, batch_size: usize, min: f32, max: f32, inp: &TensorBatch, out: &TensorBatch) {
+        Self::map_bounded(ops::activate_bounded_crelu, handle, batch_size, inp, out, min, max);
+    }
+
+    /// Overwrites `out`, the inputs of the clamp, with their gradient, given the
+    /// gradient of the outputs `inp`, zeroing it where clipped if `Masked`.
+    pub fn backprop_clamp(
+        handle: DeviceHandles,
+        batch_size: usize,
+        min: f32,
+        max: f32,
+        gradient: ClampGradient,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        match gradient {
+            ClampGradient::StraightThrough => out.copy_from(inp),
+            ClampGradient::Masked => {
+                Self::map_bounded(ops::backprop_bounded_crelu, handle, batch_size, inp, out, min, max);
+            }
+        }
+    }
+
+    /// Parametric ReLU, with `slopes` holding either one
+    /// slope per element of the tensor or a single slope.
+    ///
+    /// # Safety
+    /// `slopes` must be initialised.
+    pub unsafe fn prelu(
+        handle: DeviceHandles,
+        batch_size: usize,
+        slopes: &Tensor,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert!(batch_size <= inp.cap(), "Overflow!");
+
+        let width = inp.element_size();
+        let channels = slopes.num_elements();
+        assert!(channels == 1 || channels == width, "Invalid number of slopes!");
+
+        ops::activate_prelu(handle, batch_size * width, width, channels, inp.ptr(), slopes.ptr(), out.ptr());
+    }
+
+    /// This calculates `out[i] = inp[i] * prelu'(out[i])`,
+    /// accumulating the gradients of the slopes into `slopes_grad`.
+    ///
+    /// # Safety
+    /// `slopes` and `slopes_grad` must be initialised.
+    pub unsafe fn backprop_prelu(
+        handle: DeviceHandles,
+        batch_size: usize,
+        slopes: &Tensor,
+        slopes_grad: &Tensor,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert_eq!(slopes.shape(), slopes_grad.shape(), "Mismatched tensor shapes!");
+        assert!(batch_size <= inp.cap(), "Overflow!");
+
+        let width = inp.element_size();
+        let channels = slopes.num_elements();
+        assert!(channels == 1 || channels == width, "Invalid number of slopes!");
+
+        ops::backprop_prelu(
+            handle,
+            batch_size * width,
+            width,
+            channels,
+            slopes.ptr(),
+            slopes_grad.ptr(),
+            inp.ptr(),
+            out.ptr(),
+        );
+    }
+
+    /// Softmax, or log-softmax if `log`, of each tensor in the batch.
+    pub fn softmax(handle: DeviceHandles, batch_size: usize, log: bool, inp: &TensorBatch, out: &TensorBatch) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert!(batch_size <= inp.cap(), "Overflow!");
+        unsafe {
+            ops::softmax(handle, batch_size, inp.element_size(), inp.ptr(), out.ptr(), log);
+        }
+    }
+
+    /// This calculates `out = inp * softmax'(out)`, with `out`
+    /// holding the inputs to the softmax, as with activations.
+    pub fn backprop_softmax(handle: DeviceHandles, batch_size: usize, log: bool, inp: &TensorBatch, out: &TensorBatch) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert!(batch_size <= inp.cap(), "Overflow!");
+        unsafe {
+            ops::backprop_softmax(handle, batch_size, inp.element_size(), inp.ptr(), out.ptr(), log);
+        }
+    }
+
+    /// Reduces each tensor, viewed as a matrix with `cols` columns, along `axis`.
+    pub fn reduce(
+        handle: DeviceHandles,
+        batch_size: usize,
+        reduction: Reduction,
+        axis: Axis,
+        cols: usize,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        let (inp_size, out_size) = (inp.element_size(), out.element_size());
+        let (len, outer, inner) = reduce_layout(axis, inp_size, cols, out_size);
+        let scale = if reduction == Reduction::Mean { 1.0 / len as f32 } else { 1.0 };
+        assert!(batch_size <= inp.cap() && batch_size <= out.cap(), "Overflow!");
+
+        unsafe {
+            let max = reduction == Reduction::Max;
+            ops::reduce(handle, batch_size, inp_size, out_size, len, outer, inner, max, scale, inp.ptr(), out.ptr());
+        }
+    }
+
+    /// This calculates the gradients of the inputs to `reduce` given the
+    /// gradients `inp` of its outputs, overwriting the inputs in `out`.
+    pub fn backprop_reduce(
+        handle: DeviceHandles,
+        batch_size: usize,
+        reduction: Reduction,
+        axis: Axis,
+        cols: usize,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        let (inp_size, out_size) = (out.element_size(), inp.element_size());
+        let (len, outer, inner) = reduce_layout(axis, inp_size, cols, out_size);
+        let scale = if reduction == Reduction::Mean { 1.0 / len as f32 } else { 1.0 };
+        assert!(batch_size <= inp.cap() && batch_size <= out.cap(), "Overflow!");
+
+        unsafe {
+            let max = reduction == Reduction::Max;
+            ops::backprop_reduce(
+                handle,
+                batch_size,
+                inp_size,
+                out_size,
+                len,
+                outer,
+                inner,
+                max,
+                scale,
+                inp.ptr(),
+                out.ptr(),
+            );
+        }
+    }
+
+    /// Normalises each tensor to zero mean and unit variance, then
+    /// scales by `gamma` and shifts by `beta` element-wise.
+    ///
+    /// # Safety
+    /// `gamma` and `beta` must be initialised.
+    pub unsafe fn layer_norm(
+        handle: DeviceHandles,
+        batch_size: usize,
+        gamma: &Tensor,
+        beta: &Tensor,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert_eq!(gamma.num_elements(), inp.element_size(), "Mismatched tensor shapes!");
+        assert_eq!(beta.num_elements(), inp.element_size(), "Mismatched tensor shapes!");
+        assert!(batch_size <= inp.cap(), "Overflow!");
+
+        ops::layer_norm(handle, batch_size, inp.element_size(), gamma.ptr(), beta.ptr(), inp.ptr(), out.ptr());
+    }
+
+    /// This calculates `out = inp * layer_norm'(out)`, accumulating
+    /// the gradients of `gamma` and `beta` into `gamma_grad` and `beta_grad`.
+    ///
+    /// # Safety
+    /// `gamma`, `gamma_grad` and `beta_grad` must be initialised.
+    pub unsafe fn backprop_layer_norm(
+        handle: DeviceHandles,
+        batch_size: usize,
+        gamma: &Tensor,
+        gamma_grad: &Tensor,
+        beta_grad: &Tensor,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert_eq!(gamma.shape(), gamma_grad.shape(), "Mismatched tensor shapes!");
+        assert_eq!(gamma.shape(), beta_grad.shape(), "Mismatched tensor shapes!");
+        assert_eq!(gamma.num_elements(), inp.element_size(), "Mismatched tensor shapes!");
+        assert!(batch_size <= inp.cap(), "Overflow!");
+
+        ops::backprop_layer_norm(
+            handle,
+            batch_size,
+            inp.element_size(),
+            gamma.ptr(),
+            gamma_grad.ptr(),
+            beta_grad.ptr(),
+            inp.ptr(),
+            out.ptr(),
+        );
+    }
+
+    /// Normalises each element by its mean and variance across the batch,
+    /// then scales by `gamma` and shifts by `beta` element-wise. The batch
+    /// statistics are kept for backprop, and the running statistics are
+    /// moved towards them by `momentum`.
+    ///
+    /// # Safety
+    /// All tensors must be initialised.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn batch_norm(
+        handle: DeviceHandles,
+        batch_size: usize,
+        gamma: &Tensor,
+        beta: &Tensor,
+        running_mean: &Tensor,
+        running_var: &Tensor,
+        batch_mean: &DeviceBuffer,
+        batch_rstd: &DeviceBuffer,
+        momentum: f32,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        let size = inp.element_size();
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert!([gamma, beta, running_mean, running_var].iter().all(|x| x.num_elements() == size));
+        assert!(batch_mean.size() == size && batch_rstd.size() == size);
+        assert!(batch_size <= inp.cap(), "Overflow!");
+
+        ops::batch_norm(
+            handle,
+            batch_size,
+            size,
+            gamma.ptr(),
+            beta.ptr(),
+            running_mean.ptr(),
+            running_var.ptr(),
+            batch_mean.ptr(),
+            batch_rstd.ptr(),
+            inp.ptr(),
+            out.ptr(),
+            momentum,
+        );
+    }
+
+    /// As `batch_norm`, but normalising by the running statistics, for evaluation.
+    ///
+    /// # Safety
+    /// All tensors must be initialised.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn batch_norm_inference(
+        handle: DeviceHandles,
+        batch_size: usize,
+        gamma: &Tensor,
+        beta: &Tensor,
+        running_mean: &Tensor,
+        running_var: &Tensor,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        let size = inp.element_size();
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert!([gamma, beta, running_mean, running_var].iter().all(|x| x.num_elements() == size));
+        assert!(batch_size <= inp.cap(), "Overflow!");
+
+        ops::batch_norm_inference(
+            handle,
+            batch_size,
+            size,
+            gamma.ptr(),
+            beta.ptr(),
+            running_mean.ptr(),
+            running_var.ptr(),
+            inp.ptr(),
+            out.ptr(),
+        );
+    }
+
+    /// This calculates `out = inp * batch_norm'(out)`, accumulating the
+    /// gradients of `gamma` and `beta` into `gamma_grad` and `beta_grad`.
+    ///
+    /// # Safety
+    /// All tensors must be initialised, and `batch_mean` and
+    /// `batch_rstd` must be from the forward pass of this batch.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn backprop_batch_norm(
+        handle: DeviceHandles,
+        batch_size: usize,
+        gamma: &Tensor,
+        batch_mean: &DeviceBuffer,
+        batch_rstd: &DeviceBuffer,
+        gamma_grad: &Tensor,
+        beta_grad: &Tensor,
+        inp: &TensorBatch,
+        out: &TensorBatch,
+    ) {
+        let size = inp.element_size();
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        assert!([gamma, gamma_grad, beta_grad].iter().all(|x| x.num_elements() == size));
+        assert!(batch_size <= inp.cap(), "Overflow!");
+
+        ops::backprop_batch_norm(
+            handle,
+            batch_size,
+            size,
+            gamma.ptr(),
+            batch_mean.ptr(),
+            batch_rstd.ptr(),
+            gamma_grad.ptr(),
+            beta_grad.ptr(),
+            inp.ptr(),
+            out.ptr(),
+        );
+    }
+
     /// # Safety
     /// `weights` and `biases` must be initialised.
     pub unsafe fn affine(
@@ -193,6 +820,29 @@ impl TensorBatch {
         TensorBatch::splat_add(handle, batch_size, biases, outputs);
     }
 
+    /// Fuses `affine` and `activate`, writing the outputs of the affine
+    /// transformation to `pre` and `op` of them to `outputs`.
+    ///
+    /// # Safety
+    /// `weights` and `biases` must be initialised.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn affine_activate(
+        handle: DeviceHandles,
+        batch_size: usize,
+        op: Activation,
+        weights: &Tensor,
+        inputs: &TensorBatch,
+        biases: &Tensor,
+        pre: &TensorBatch,
+        outputs: &TensorBatch,
+    ) {
+        TensorBatch::splat_mul_matrix_vector(handle, batch_size, weights, inputs, pre);
+        TensorBatch::splat_add_activate(handle, batch_size, op, biases, pre, outputs);
+    }
+
+    /// Adds to the gradients, so that layers sharing the same weights can
+    /// backprop into the same gradients.
+    ///
     /// # Safety
     /// `weights` must be initialised.
     #[allow(clippy::too_many_arguments)]
@@ -211,6 +861,52 @@ impl TensorBatch {
         TensorBatch::splat_mul_matrixt_vector(handle, batch_size, weights, errors, inputs);
     }
 
+    /// As `affine`, but with the inputs and outputs split into `groups` equal
+    /// chunks, each chunk of outputs depending only on the same chunk of inputs
+    /// through its own matrix. The matrices of `weights` are stored one after
+    /// another, each with a column per input, and are all multiplied at once.
+    ///
+    /// # Safety
+    /// `weights` and `biases` must be initialised.
+    pub unsafe fn grouped_affine(
+        handle: DeviceHandles,
+        batch_size: usize,
+        groups: usize,
+        weights: &Tensor,
+        inputs: &TensorBatch,
+        biases: &Tensor,
+        outputs: &TensorBatch,
+    ) {
+        let (m, n) = grouped_dims(groups, weights.shape(), inputs, outputs);
+        assert!(batch_size <= inputs.cap(), "Overflow!");
+
+        ops::grouped_mul_matrix_vector(handle, groups, m, n, weights.ptr(), inputs.ptr(), outputs.ptr(), batch_size);
+        TensorBatch::splat_add(handle, batch_size, biases, outputs);
+    }
+
+    /// # Safety
+    /// `weights` must be initialised.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn backprop_grouped_affine(
+        handle: DeviceHandles,
+        ones: &DeviceBuffer,
+        batch_size: usize,
+        groups: usize,
+        weights: &Tensor,
+        errors: &TensorBatch,
+        inputs: &TensorBatch,
+        weights_grad: &Tensor,
+        biases_grad: &Tensor,
+    ) {
+        let (m, n) = grouped_dims(groups, weights.shape(), inputs, errors);
+        assert!(batch_size <= inputs.cap(), "Overflow!");
+
+        let (x, y) = (inputs.ptr(), errors.ptr());
+        ops::grouped_reduce_add_mul_vector_vectort(handle, groups, m, n, y, x, weights_grad.ptr(), batch_size);
+        TensorBatch::reduce_add(handle, ones, batch_size, errors, biases_grad);
+        ops::grouped_mul_matrixt_vector(handle, groups, m, n, weights.ptr(), y, x, batch_size);
+    }
+
     pub fn sigmoid_mpe(&self, handle: DeviceHandles, batch_size: usize, results: &TensorBatch, error: &DeviceBuffer, power: f32) {
         assert_eq!(self.shape(), results.shape());
         assert_eq!(self.element_size(), results.element_size());
@@ -220,6 +916,106 @@ impl TensorBatch {
         }
     }
 
+    /// MSE of the sigmoid of the first of each pair of outputs, with
+    /// the second output regressed onto the squared error of the first.
+    pub fn sigmoid_mse_variance(
+        &self,
+        handle: DeviceHandles,
+        batch_size: usize,
+        results: &TensorBatch,
+        error: &DeviceBuffer,
+    ) {
+        assert_eq!(self.shape(), results.shape());
+        assert_eq!(self.element_size(), 2);
+        assert!(batch_size <= self.cap(), "Overflow!");
+
+        unsafe {
+            ops::sigmoid_mse_variance(handle, batch_size, self.ptr(), results.ptr(), error.ptr());
+        }
+    }
+
+    /// As `sigmoid_mpe`, with each gradient additionally scaled by `|error|^gamma`.
+    pub fn sigmoid_focal(
+        &self,
+        handle: DeviceHandles,
+        batch_size: usize,
+        results: &TensorBatch,
+        error: &DeviceBuffer,
+        power: f32,
+        gamma: f32,
+    ) {
+        assert_eq!(self.shape(), results.shape());
+        assert_eq!(self.element_size(), results.element_size());
+
+        unsafe {
+            ops::sigmoid_focal(handle, batch_size, self.ptr(), results.ptr(), error.ptr(), power, gamma);
+        }
+    }
+
+    /// As `sigmoid_mpe`, with the error of each tensor in the batch
+    /// scaled by the corresponding entry of `weights`.
+    pub fn sigmoid_mpe_weighted(
+        &self,
+        handle: DeviceHandles,
+        batch_size: usize,
+        results: &TensorBatch,
+        weights: &TensorBatch,
+        error: &DeviceBuffer,
+        power: f32,
+    ) {
+        assert_eq!(self.shape(), results.shape());
+        assert_eq!(weights.element_size(), 1);
+        assert!(batch_size <= weights.cap(), "Overflow!");
+
+        let tensor_size = self.element_size();
+
+        unsafe {
+            ops::sigmoid_mpe_weighted(
+                handle,
+                batch_size * tensor_size,
+                tensor_size,
+                self.ptr(),
+                results.ptr(),
+                weights.ptr(),
+                error.ptr(),
+                power,
+            );
+        }
+    }
+
+    pub fn sigmoid_huber(
+        &self,
+        handle: DeviceHandles,
+        batch_size: usize,
+        results: &TensorBatch,
+        error: &DeviceBuffer,
+        delta: f32,
+    ) {
+        assert_eq!(self.shape(), results.shape());
+        assert_eq!(self.element_size(), results.element_size());
+
+        unsafe {
+            ops::sigmoid_huber(handle, batch_size, self.ptr(), results.ptr(), error.ptr(), delta);
+        }
+    }
+
+    /// Replaces each tensor of logits with the gradient of the
+    /// cross-entropy between its softmax and the matching `results`.
+    pub fn softmax_crossentropy(
+        &self,
+        handle: DeviceHandles,
+        batch_size: usize,
+        results: &TensorBatch,
+        error: &DeviceBuffer,
+    ) {
+        assert_eq!(self.shape(), results.shape());
+        assert!(batch_size <= self.cap(), "Overflow!");
+
+        unsafe {
+            ops::softmax_crossentropy(handle, batch_size, self.element_size(), self.ptr(), results.ptr(), error.ptr());
+        }
+    }
+
     /// # Safety
     /// `buckets` must be valid.
     pub unsafe fn select(
@@ -249,6 +1045,181 @@ impl TensorBatch {
 
         ops::select_backprop(handle, batch_size, inp.element_size(), out.element_size(), buckets, inp.ptr(), out.ptr());
     }
+
+    /// Sets each tensor of `out` to the column of `weights` given by its bucket.
+    ///
+    /// # Safety
+    /// `buckets` must be valid and index columns of `weights`, which must be initialised.
+    pub unsafe fn gather(
+        handle: DeviceHandles,
+        batch_size: usize,
+        buckets: *const u8,
+        weights: &Tensor,
+        out: &TensorBatch,
+    ) {
+        assert_eq!(weights.shape().rows(), out.element_size());
+        assert!(batch_size <= out.cap(), "Overflow!");
+        ops::gather(handle, batch_size, out.element_size(), buckets, weights.ptr(), out.ptr());
+    }
+
+    /// Accumulates the gradient of `gather` into the columns of `weights_grad`.
+    ///
+    /// # Safety
+    /// `buckets` must be valid and index columns of `weights_grad`, which must be initialised.
+    pub unsafe fn backprop_gather(
+        handle: DeviceHandles,
+        batch_size: usize,
+        buckets: *const u8,
+        errors: &TensorBatch,
+        weights_grad: &Tensor,
+    ) {
+        assert_eq!(weights_grad.shape().rows(), errors.element_size());
+        assert!(batch_size <= errors.cap(), "Overflow!");
+        ops::backprop_gather(handle, batch_size, errors.element_size(), buckets, errors.ptr(), weights_grad.ptr());
+    }
+
+    /// Applies the convolution described by `desc` to each tensor of `inputs`, with
+    /// `weights` of shape `(desc.num_weights(), 1)` and one bias per output channel.
+    ///
+    /// # Safety
+    /// All tensors must be initialised.
+    pub unsafe fn convolution(
+        handle: DeviceHandles,
+        desc: &ConvolutionDescription,
+        batch_size: usize,
+        weights: &Tensor,
+        biases: &Tensor,
+        inputs: &TensorBatch,
+        outputs: &TensorBatch,
+    ) {
+        assert_eq!(weights.num_elements(), desc.num_weights());
+        assert_eq!(biases.num_elements(), desc.output_channels);
+        assert_eq!(inputs.element_size(), desc.input_size());
+        assert_eq!(outputs.element_size(), desc.output_size());
+        assert!(batch_size <= inputs.cap() && batch_size <= outputs.cap(), "Overflow!");
+
+        ops::convolution(handle, desc, batch_size, weights.ptr(), biases.ptr(), inputs.ptr(), outputs.ptr());
+    }
+
+    /// Accumulates the gradients of `weights` and `biases`, then overwrites
+    /// `inputs` with the gradient of the convolution with respect to it.
+    ///
+    /// # Safety
+    /// All tensors must be initialised.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn backprop_convolution(
+        handle: DeviceHandles,
+        desc: &ConvolutionDescription,
+        batch_size: usize,
+        weights: &Tensor,
+        errors: &TensorBatch,
+        inputs: &TensorBatch,
+        weights_grad: &Tensor,
+        biases_grad: &Tensor,
+    ) {
+        assert_eq!(weights.num_elements(), desc.num_weights());
+        assert_eq!(weights_grad.num_elements(), desc.num_weights());
+        assert_eq!(biases_grad.num_elements(), desc.output_channels);
+        assert_eq!(inputs.element_size(), desc.input_size());
+        assert_eq!(errors.element_size(), desc.output_size());
+        assert!(batch_size <= inputs.cap() && batch_size <= errors.cap(), "Overflow!");
+
+        ops::backprop_convolution(
+            handle,
+            desc,
+            batch_size,
+            weights.ptr(),
+            weights_grad.ptr(),
+            biases_grad.ptr(),
+            errors.ptr(),
+            inputs.ptr(),
+        );
+    }
+
+    /// Applies the self-attention described by `desc` to each tensor of `inputs`, with
+    /// `weights` of shape `(desc.num_weights(), 1)` and `desc.num_biases()` biases,
+    /// keeping the projections in `qkv` and the attention weights in `attn` for backprop.
+    ///
+    /// # Safety
+    /// All tensors must be initialised.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn attention(
+        handle: DeviceHandles,
+        desc: &AttentionDescription,
+        batch_size: usize,
+        weights: &Tensor,
+        biases: &Tensor,
+        inputs: &TensorBatch,
+        qkv: &DeviceBuffer,
+        attn: &DeviceBuffer,
+        outputs: &TensorBatch,
+    ) {
+        assert_eq!(weights.num_elements(), desc.num_weights());
+        assert_eq!(biases.num_elements(), desc.num_biases());
+        assert_eq!(inputs.element_size(), desc.input_size());
+        assert_eq!(outputs.element_size(), desc.output_size());
+        assert!(batch_size <= inputs.cap() && batch_size <= outputs.cap(), "Overflow!");
+        assert!(batch_size * desc.len * desc.num_biases() <= qkv.size(), "Overflow!");
+        assert!(batch_size * desc.heads * desc.len * desc.len <= attn.size(), "Overflow!");
+
+        ops::attention(
+            handle,
+            desc,
+            batch_size,
+            weights.ptr(),
+            biases.ptr(),
+            inputs.ptr(),
+            qkv.ptr(),
+            attn.ptr(),
+            outputs.ptr(),
+        );
+    }
+
+    /// Accumulates the gradients of `weights` and `biases`, then overwrites
+    /// `inputs` with the gradient of the attention with respect to it, given
+    /// the `qkv` and `attn` of the forward pass, and scratch buffers of equal size.
+    ///
+    /// # Safety
+    /// All tensors must be initialised.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn backprop_attention(
+        handle: DeviceHandles,
+        desc: &AttentionDescription,
+        batch_size: usize,
+        weights: &Tensor,
+        errors: &TensorBatch,
+        inputs: &TensorBatch,
+        weights_grad: &Tensor,
+        biases_grad: &Tensor,
+        qkv: &DeviceBuffer,
+        attn: &DeviceBuffer,
+        qkv_grad: &DeviceBuffer,
+        attn_grad: &DeviceBuffer,
+    ) {
+        assert_eq!(weights.num_elements(), desc.num_weights());
+        assert_eq!(weights_grad.num_elements(), desc.num_weights());
+        assert_eq!(biases_grad.num_elements(), desc.num_biases());
+        assert_eq!(inputs.element_size(), desc.input_size());
+        assert_eq!(errors.element_size(), desc.output_size());
+        assert!(batch_size <= inputs.cap() && batch_size <= errors.cap(), "Overflow!");
+        assert!(batch_size * desc.len * desc.num_biases() <= qkv.size().min(qkv_grad.size()), "Overflow!");
+        assert!(batch_size * desc.heads * desc.len * desc.len <= attn.size().min(attn_grad.size()), "Overflow!");
+
+        ops::backprop_attention(
+            handle,
+            desc,
+            batch_size,
+            weights.ptr(),
+            weights_grad.ptr(),
+            biases_grad.ptr(),
+            qkv.ptr(),
+            attn.ptr(),
+            qkv_grad.ptr(),
+            attn_grad.ptr(),
+            errors.ptr(),
+            inputs.ptr(),
+        );
+    }
 }
 
 fn validate_dims(a_shape: Shape, x: &TensorBatch, y: &TensorBatch) -> (usize, usize) {
@@ -258,3 +1229,49 @@ fn validate_dims(a_shape: Shape, x: &TensorBatch, y: &TensorBatch) -> (usize, us
 
     (a_shape.cols(), a_shape.rows())
 }
+
+/// Inputs and outputs of each group.
+fn grouped_dims(groups: usize, a_shape: Shape, x: &TensorBatch, y: &TensorBatch) -> (usize, usize) {
+    assert_eq!(a_shape.cols() % groups, 0, "Cannot split {} inputs into {groups} groups!", a_shape.cols());
+    assert_eq!(x.shape(), Shape::new(1, a_shape.cols()));
+    assert_eq!(y.shape(), Shape::new(1, groups * a_shape.rows()));
+    assert_eq!(x.cap(), y.cap(), "Not all tensor caps are the same length!");
+
+    (a_shape.cols() / groups, a_shape.rows())
+}
+
+/// Parameters of `op` for the fused kernels, as `(min, max, slope, square)`.
+pub(super) fn fused_params(op: Activation) -> (f32, f32, f32, bool) {
+    match op {
+        Activation::ReLU => (0.0, f32::INFINITY, 1.0, false),
+        Activation::CReLU => (0.0, 1.0, 1.0, false),
+        Activation::SCReLU => (0.0, 1.0, 1.0, true),
+        Activation::BoundedCReLU { min, max } | Activation::BoundedSCReLU { min, max } => {
+            assert!(min < max, "Invalid activation bounds!");
+            (min, max, 1.0, matches!(op, Activation::BoundedSCReLU { .. }))
+        }
+        Activation::LeakyReLU(slope) => (f32::NEG_INFINITY, f32::INFINITY, slope, false),
+    }
+}
+
+/// Size of each tensor and the number of elements broadcast across it.
+fn broadcast_dims(inp: &Tensor, out: &TensorBatch) -> (usize, usize) {
+    let (width, channels) = (out.element_size(), inp.num_elements());
+    assert!(channels == 1 || channels == width, "Can only broadcast a scalar or a vector of the same size!");
+    (width, channels)
+}
+
+/// Number of elements reduced to each output, and the strides between
+/// the first element of consecutive outputs and between reduced elements.
+fn reduce_layout(axis: Axis, inp_size: usize, cols: usize, out_size: usize) -> (usize, usize, usize) {
+    assert_eq!(inp_size % cols, 0, "Cannot view tensor as a matrix with {cols} columns!");
+    let rows = inp_size / cols;
+
+    let layout = match axis {
+        Axis::Rows => (rows, rows, 1),
+        Axis::Cols => (cols, 1, rows),
+    };
+
+    assert_eq!(layout.0 * out_size, inp_size, "Mismatched tensor shapes!");
+    layout
+}
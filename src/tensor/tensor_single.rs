@@ -1,5 +1,5 @@
 use super::Shape;
-use crate::backend::util;
+use crate::backend::{ops, util, DeviceHandles};
 
 /// Single Rank-2 Tensor on the GPU.
 /// This data type does not own the memory it points to,
@@ -50,6 +50,15 @@ impl Tensor {
         self.shape.size()
     }
 
+    /// Adds `inp` to `out` elementwise.
+    ///
+    /// # Safety
+    /// Both tensors must be pointing to valid allocated memory.
+    pub unsafe fn add_to(handle: DeviceHandles, inp: &Tensor, out: &Tensor) {
+        assert_eq!(inp.shape(), out.shape(), "Mismatched tensor shapes!");
+        ops::add_to(handle, inp.num_elements(), inp.ptr(), out.ptr());
+    }
+
     pub fn load_from_host(&self, buf: &[f32]) {
         assert!(!self.ptr.is_null(), "Attempting to dereference null pointer!");
 
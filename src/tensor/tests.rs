@@ -1,5 +1,8 @@
-use crate::{backend::{DeviceHandles, util}, Activation, loader::Feat};
-use super::{Shape, SparseTensor, Tensor, TensorBatch, DeviceBuffer};
+use crate::{backend::{DeviceHandles, util}, Activation, Axis, ClampGradient, Reduction, loader::Feat};
+use super::{
+    AttentionDescription, ConvolutionDescription, Optimiser, OptimiserType, ParamKind, ParamSettings, Shape,
+    SparseLayout, SparseMatrix, SparseTensor, Tensor, TensorBatch, DeviceBuffer,
+};
 
 #[test]
 fn tensor_activate() {
@@ -21,6 +24,122 @@ fn tensor_activate() {
     assert_eq!(xs, [0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0]);
 }
 
+#[test]
+fn bounded_activate() {
+    let handle = DeviceHandles::default();
+    let mut xs = [2.5, -1.0, -0.5, 0.5, 1.0, 1.5, 0.0, 3.0, 1.0];
+    let bounds = Activation::BoundedSCReLU { min: -0.75, max: 2.0 };
+
+    let x = TensorBatch::new(Shape::new(1, 3), 3);
+    let y = TensorBatch::new(Shape::new(1, 3), 3);
+
+    x.load_from_host(&xs);
+    TensorBatch::activate(handle, 3, bounds, &x, &y);
+    y.write_to_host(&mut xs);
+
+    assert_eq!(xs, [4.0, 0.5625, 0.25, 0.25, 1.0, 2.25, 0.0, 4.0, 1.0]);
+
+    y.load_from_host(&[1.0; 9]);
+    TensorBatch::backprop_activation(handle, 3, bounds, &y, &x);
+    x.write_to_host(&mut xs);
+
+    assert_eq!(xs, [0.0, 0.0, -1.0, 1.0, 2.0, 3.0, 0.0, 0.0, 2.0]);
+}
+
+#[test]
+fn clamp() {
+    let handle = DeviceHandles::default();
+    let inputs = [2.5, -1.0, 0.5, 1.0];
+    let mut xs = [0.0; 4];
+
+    let x = TensorBatch::new(Shape::new(1, 2), 2);
+    let y = TensorBatch::new(Shape::new(1, 2), 2);
+
+    x.load_from_host(&inputs);
+    TensorBatch::clamp(handle, 2, -0.75, 2.0, &x, &y);
+    y.write_to_host(&mut xs);
+
+    assert_eq!(xs, [2.0, -0.75, 0.5, 1.0]);
+
+    TensorBatch::clamp(handle, 2, 0.0, f32::INFINITY, &x, &y);
+    y.write_to_host(&mut xs);
+
+    assert_eq!(xs, [2.5, 0.0, 0.5, 1.0]);
+
+    y.load_from_host(&[1.0, 2.0, 3.0, 4.0]);
+    TensorBatch::backprop_clamp(handle, 2, -0.75, 2.0, ClampGradient::Masked, &y, &x);
+    x.write_to_host(&mut xs);
+
+    assert_eq!(xs, [0.0, 0.0, 3.0, 4.0]);
+
+    x.load_from_host(&inputs);
+    TensorBatch::backprop_clamp(handle, 2, -0.75, 2.0, ClampGradient::StraightThrough, &y, &x);
+    x.write_to_host(&mut xs);
+
+    assert_eq!(xs, [1.0, 2.0, 3.0, 4.0]);
+}
+
+#[test]
+fn leaky_relu() {
+    let handle = DeviceHandles::default();
+    let mut xs = [2.0, -1.0, -0.5, 0.5, 1.0, -4.0];
+    let leaky = Activation::LeakyReLU(0.25);
+
+    let x = TensorBatch::new(Shape::new(1, 3), 2);
+    let y = TensorBatch::new(Shape::new(1, 3), 2);
+
+    x.load_from_host(&xs);
+    TensorBatch::activate(handle, 2, leaky, &x, &y);
+    y.write_to_host(&mut xs);
+
+    assert_eq!(xs, [2.0, -0.25, -0.125, 0.5, 1.0, -1.0]);
+
+    y.load_from_host(&[1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+    TensorBatch::backprop_activation(handle, 2, leaky, &y, &x);
+    x.write_to_host(&mut xs);
+
+    assert_eq!(xs, [1.0, 0.25, 0.5, 2.0, 3.0, 0.75]);
+}
+
+#[test]
+fn prelu() {
+    let handle = DeviceHandles::default();
+    let mut xs = [2.0, -1.0, -0.5, 0.5, 1.0, -4.0];
+    let mut grads = [0.0; 3];
+
+    let mut slopes = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    let mut slopes_grad = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    slopes.calloc();
+    slopes_grad.calloc();
+    slopes.load_from_host(&[0.25, 0.5, 0.125]);
+
+    let x = TensorBatch::new(Shape::new(1, 3), 2);
+    let y = TensorBatch::new(Shape::new(1, 3), 2);
+
+    x.load_from_host(&xs);
+    unsafe {
+        TensorBatch::prelu(handle, 2, &slopes, &x, &y);
+    }
+    y.write_to_host(&mut xs);
+
+    assert_eq!(xs, [2.0, -0.5, -0.0625, 0.5, 1.0, -0.5]);
+
+    y.load_from_host(&[1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+    unsafe {
+        TensorBatch::backprop_prelu(handle, 2, &slopes, &slopes_grad, &y, &x);
+    }
+    x.write_to_host(&mut xs);
+    slopes_grad.write_to_host(&mut grads);
+
+    assert_eq!(xs, [1.0, 0.5, 0.25, 2.0, 3.0, 0.375]);
+    assert_eq!(grads, [0.0, -1.0, -13.0]);
+
+    unsafe {
+        slopes.free();
+        slopes_grad.free();
+    }
+}
+
 #[test]
 fn tensor_lt() {
     let handle = DeviceHandles::default();
@@ -112,9 +231,6 @@ fn tensor_sparse_affine() {
         let mut biases = Tensor::uninit(Shape::new(1, N));
         let mut inputs = SparseTensor::uninit(B, M, 1);
         let outputs = TensorBatch::new(Shape::new(1, 2 * N), B);
-        let zeros = TensorBatch::new(Shape::new(1, 2 * N), B);
-
-        zeros.load_from_host(&[0.0; 2 * N * B]);
 
         weights.calloc();
         biases.calloc();
@@ -138,7 +254,7 @@ fn tensor_sparse_affine() {
         wg.calloc();
         bg.calloc();
 
-        SparseTensor::affine_backprop(handle, &wg, &inputs, &bg, &outputs, &zeros, 0.0);
+        SparseTensor::affine_backprop(handle, &wg, &inputs, &bg, &outputs, None, 0.0);
 
         let mut wbuf = [0.0; 6];
         wg.write_to_host(&mut wbuf);
@@ -156,6 +272,196 @@ fn tensor_sparse_affine() {
     }
 }
 
+#[test]
+fn tensor_sparse_affine_backprop_regularisation() {
+    let handle = DeviceHandles::default();
+
+    const M: usize = 2;
+    const N: usize = 2;
+
+    unsafe {
+        let mut inputs = SparseTensor::uninit(1, M, 1);
+        let errors = TensorBatch::new(Shape::new(1, 2 * N), 1);
+        let copy = TensorBatch::new(Shape::new(1, 2 * N), 1);
+
+        inputs.append(&[Feat::new(0, 1)]);
+        errors.load_from_host(&[1.0; 2 * N]);
+        copy.load_from_host(&[1.0, -1.0, 1.0, -1.0]);
+
+        for (output, expected) in [(Some(&copy), [3.0, 2.0]), (None, [2.0, 2.0])] {
+            let mut wg = Tensor::uninit(Shape::new(N, M));
+            let mut bg = Tensor::uninit(Shape::new(1, N));
+
+            wg.calloc();
+            bg.calloc();
+
+            SparseTensor::affine_backprop(handle, &wg, &inputs, &bg, &errors, output, 0.5);
+
+            let mut bbuf = [0.0; N];
+            bg.write_to_host(&mut bbuf);
+            assert_eq!(bbuf, expected);
+
+            wg.free();
+            bg.free();
+        }
+    }
+}
+
+#[test]
+fn tensor_sparse_affine_asymmetric() {
+    let handle = DeviceHandles::default();
+
+    const M: usize = 3;
+    const N: usize = 2;
+    const B: usize = 3;
+
+    let a_t = [
+        1.0, 0.0,
+        1.0, 1.0,
+        0.0, 1.0,
+    ];
+
+    let b = [0.5, -0.5];
+
+    let xs = [Feat::new(0, 1), Feat::new(1, 2), Feat::new(2, 0)];
+
+    unsafe {
+        let mut weights = Tensor::uninit(Shape::new(N, M));
+        let mut biases = Tensor::uninit(Shape::new(1, N));
+        let mut inputs = SparseTensor::uninit(B, M, 1);
+        let outputs = TensorBatch::new(Shape::new(1, N + 1), B);
+
+        weights.calloc();
+        biases.calloc();
+
+        weights.load_from_host(&a_t);
+        biases.load_from_host(&b);
+
+        inputs.append(&xs);
+
+        SparseTensor::affine(handle, &weights, &inputs, &biases, &outputs);
+
+        // the opposing perspective only has the first output
+        let mut ys = [0.0; (N + 1) * B];
+        outputs.write_to_host(&mut ys);
+
+        let expected = [1.5, -0.5, 1.5, 1.5, 0.5, 0.5, 0.5, 0.5, 1.5];
+        assert_eq!(expected, ys);
+
+        let mut wg = Tensor::uninit(Shape::new(N, M));
+        let mut bg = Tensor::uninit(Shape::new(1, N));
+
+        wg.calloc();
+        bg.calloc();
+
+        SparseTensor::affine_backprop(handle, &wg, &inputs, &bg, &outputs, None, 0.0);
+
+        let mut wbuf = [0.0; 6];
+        wg.write_to_host(&mut wbuf);
+        let expected = [3.0, -0.5, 3.0, 0.5, 1.0, 0.5];
+        assert_eq!(wbuf, expected);
+
+        let mut bbuf = [0.0; 2];
+        bg.write_to_host(&mut bbuf);
+        assert_eq!(bbuf, [7.0, 0.5]);
+
+        weights.free();
+        biases.free();
+        wg.free();
+        bg.free();
+    }
+}
+
+#[test]
+fn sparse_matrix() {
+    let handle = DeviceHandles::default();
+
+    const M: usize = 3;
+    const N: usize = 2;
+    const B: usize = 3;
+
+    let a_t = [
+        1.0, 0.0,
+        1.0, 1.0,
+        0.0, 1.0,
+    ];
+
+    let b = [0.5, -0.5];
+
+    let xs = [
+        Feat::new(0, 1), Feat::new(2, 0),
+        Feat::new(1, 2), Feat::new(-1, -1),
+        Feat::new(2, 2), Feat::new(1, 0),
+    ];
+
+    unsafe {
+        let mut weights = Tensor::uninit(Shape::new(N, M));
+        let mut biases = Tensor::uninit(Shape::new(1, N));
+        let mut inputs = SparseTensor::uninit(B, M, 2);
+        let outputs = TensorBatch::new(Shape::new(1, N), B);
+
+        weights.calloc();
+        biases.calloc();
+
+        weights.load_from_host(&a_t);
+        biases.load_from_host(&b);
+
+        inputs.append(&xs);
+
+        let csr = SparseMatrix::from_sparse_tensor(handle, &inputs, false);
+        assert_eq!(csr.nnz(), 5);
+        assert_eq!(csr.to_host(), (vec![0, 2, 3, 5], vec![0, 2, 1, 2, 1], vec![1.0; 5]));
+
+        let coo = csr.to_coo(handle);
+        assert_eq!(coo.to_host(), (vec![0, 0, 1, 2, 2], vec![0, 2, 1, 2, 1], vec![1.0; 5]));
+        assert_eq!(coo.to_csr(handle).to_host().0, vec![0, 2, 3, 5]);
+
+        SparseMatrix::affine(handle, &weights, &csr, &biases, &outputs);
+
+        let mut ys = [0.0; N * B];
+        outputs.write_to_host(&mut ys);
+        assert_eq!(ys, [1.5, 0.5, 1.5, 0.5, 1.5, 1.5]);
+
+        let opp = SparseMatrix::from_sparse_tensor(handle, &inputs, true);
+        SparseMatrix::affine(handle, &weights, &opp, &biases, &outputs);
+
+        outputs.write_to_host(&mut ys);
+        assert_eq!(ys, [2.5, 0.5, 0.5, 0.5, 1.5, 0.5]);
+
+        // entries out of order, with values other than one
+        let unsorted = SparseMatrix::from_host(SparseLayout::Coo, B, M, &[2, 0, 1, 0], &[1, 0, 1, 2], &[2.0, 1.0, -1.0, 0.5]);
+        let sorted = unsorted.to_csr(handle);
+        assert_eq!(sorted.to_host().0, vec![0, 2, 3, 4]);
+
+        SparseMatrix::affine(handle, &weights, &sorted, &biases, &outputs);
+
+        outputs.write_to_host(&mut ys);
+        assert_eq!(ys, [1.5, 0.0, -0.5, -1.5, 2.5, 1.5]);
+
+        let mut wg = Tensor::uninit(Shape::new(N, M));
+        let mut bg = Tensor::uninit(Shape::new(1, N));
+
+        wg.calloc();
+        bg.calloc();
+
+        SparseMatrix::affine(handle, &weights, &csr, &biases, &outputs);
+        SparseMatrix::affine_backprop(handle, &wg, &coo, &bg, &outputs);
+
+        let mut wbuf = [0.0; 6];
+        wg.write_to_host(&mut wbuf);
+        assert_eq!(wbuf, [1.5, 0.5, 3.0, 2.0, 3.0, 2.0]);
+
+        let mut bbuf = [0.0; 2];
+        bg.write_to_host(&mut bbuf);
+        assert_eq!(bbuf, [4.5, 2.5]);
+
+        weights.free();
+        biases.free();
+        wg.free();
+        bg.free();
+    }
+}
+
 #[test]
 fn reduce_add_mul_vector_vectort() {
     let handle = DeviceHandles::default();
@@ -234,6 +540,14 @@ fn tensor_reduce_add() {
     out.write_to_host(&mut buf);
     assert_eq!(buf, [4.0, 3.0, 7.0]);
 
+    // adds to the output
+    unsafe {
+        TensorBatch::reduce_add(handle, &ones, 2, &inp, &out);
+    }
+
+    out.write_to_host(&mut buf);
+    assert_eq!(buf, [6.0, 4.0, 10.0]);
+
     unsafe {
         out.free();
     }
@@ -265,58 +579,340 @@ fn tensor_splat_add() {
     }
 }
 
+const FUSED_ACTIVATIONS: [Activation; 6] = [
+    Activation::ReLU,
+    Activation::CReLU,
+    Activation::SCReLU,
+    Activation::BoundedCReLU { min: -0.75, max: 1.25 },
+    Activation::BoundedSCReLU { min: -0.75, max: 1.25 },
+    Activation::LeakyReLU(0.125),
+];
+
 #[test]
-fn affine() {
+fn splat_add_activate() {
     let handle = DeviceHandles::default();
-    let inps = [1.0, 2.0, -0.5];
-    let ws = [
-        1.0, 0.0, 1.0,
-        0.0, 1.0, 0.0,
-        1.0, 0.0, 1.0,
-    ];
-    let bs = [0.1, 0.2, 0.3];
+    let splat = [0.5, -1.0, 1.0];
+    let vecs = [1.0, 1.0, 0.0, -1.0, 1.0, 0.0, 1.0, -0.5, 1.0, -1.5, 1.0, 0.25];
 
-    unsafe {
-        let mut w = Tensor::uninit(Shape::new(3, 3));
-        let mut b = Tensor::uninit(Shape::new(1, 3));
-        let x = TensorBatch::new(Shape::new(1, 3), 1);
-        let y = TensorBatch::new(Shape::new(1, 3), 1);
-        let ones = DeviceBuffer::new(1);
-        ones.load_from_host(&[1.0]);
+    let mut inp = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    inp.calloc();
+    inp.load_from_host(&splat);
 
-        w.calloc();
-        b.calloc();
+    let pre = TensorBatch::new(Shape::new(1, 3), 4);
+    let out = TensorBatch::new(Shape::new(1, 3), 4);
+    let expected = TensorBatch::new(Shape::new(1, 3), 4);
 
-        w.load_from_host(&ws);
-        b.load_from_host(&bs);
+    for op in FUSED_ACTIVATIONS {
+        pre.load_from_host(&vecs);
 
-        x.load_from_host(&inps);
+        unsafe {
+            TensorBatch::splat_add_activate(handle, 4, op, &inp, &pre, &out);
+        }
 
-        TensorBatch::affine(handle, 1, &w, &x, &b, &y);
+        let mut buf = [0.0; 12];
+        pre.write_to_host(&mut buf);
+        assert_eq!(buf, [1.5, 0.0, 1.0, -0.5, 0.0, 1.0, 1.5, -1.5, 2.0, -1.0, 0.0, 1.25]);
 
-        let mut buf = [0.0; 3];
-        y.write_to_host(&mut buf);
-        assert_eq!(buf, [0.6, 2.2, 0.8]);
+        TensorBatch::activate(handle, 4, op, &pre, &expected);
 
-        let mut wg = Tensor::uninit(Shape::new(3, 3));
-        let mut bg = Tensor::uninit(Shape::new(1, 3));
+        let mut ys = [0.0; 12];
+        out.write_to_host(&mut ys);
+        expected.write_to_host(&mut buf);
+        assert_eq!(ys, buf, "{op:?}");
+    }
 
-        wg.calloc();
-        bg.calloc();
+    unsafe {
+        inp.free();
+    }
+}
 
-        TensorBatch::backprop_affine(handle, &ones, 1, &w, &y, &x, &wg, &bg);
+#[test]
+fn sparse_affine_activate() {
+    let handle = DeviceHandles::default();
 
-        x.write_to_host(&mut buf);
-        assert_eq!(buf, [1.4000001, 2.2, 1.4000001]);
+    const M: usize = 3;
+    const N: usize = 2;
+    const B: usize = 3;
 
-        let mut wbuf = [0.0; 9];
-        wg.write_to_host(&mut wbuf);
-        assert_eq!(wbuf, [
-            0.6, 2.2, 0.8,
-            1.2, 4.4, 1.6,
-            -0.3, -1.1, -0.4,
-            //0.6, 1.2, -0.3,
-            //2.2, 4.4, -1.1,
+    let a_t = [
+        1.0, -1.5,
+        1.0, 1.0,
+        -2.0, 1.0,
+    ];
+
+    let b = [0.5, -0.5];
+
+    let xs = [Feat::new(0, 1), Feat::new(1, 2), Feat::new(2, 0)];
+
+    unsafe {
+        let mut weights = Tensor::uninit(Shape::new(N, M));
+        let mut biases = Tensor::uninit(Shape::new(1, N));
+        let mut inputs = SparseTensor::uninit(B, M, 1);
+
+        weights.calloc();
+        biases.calloc();
+
+        weights.load_from_host(&a_t);
+        biases.load_from_host(&b);
+
+        inputs.append(&xs);
+
+        // both perspectives, and the opposing perspective with only the first output
+        for size in [2 * N, N + 1] {
+            let outputs = TensorBatch::new(Shape::new(1, size), B);
+            let activated = TensorBatch::new(Shape::new(1, size), B);
+            let expected = TensorBatch::new(Shape::new(1, size), B);
+
+            SparseTensor::affine(handle, &weights, &inputs, &biases, &expected);
+
+            let mut pre = vec![0.0; size * B];
+            expected.write_to_host(&mut pre);
+
+            for op in FUSED_ACTIVATIONS {
+                SparseTensor::affine_activate(handle, op, &weights, &inputs, &biases, &outputs, &activated);
+                TensorBatch::activate(handle, B, op, &outputs, &expected);
+
+                let mut ys = vec![0.0; size * B];
+                outputs.write_to_host(&mut ys);
+                assert_eq!(ys, pre);
+
+                let mut buf = vec![0.0; size * B];
+                activated.write_to_host(&mut ys);
+                expected.write_to_host(&mut buf);
+                assert_eq!(ys, buf, "{op:?}");
+            }
+        }
+
+        weights.free();
+        biases.free();
+    }
+}
+
+#[test]
+fn concat_split() {
+    let handle = DeviceHandles::default();
+
+    let a = TensorBatch::new(Shape::new(1, 2), 2);
+    let b = TensorBatch::new(Shape::new(1, 1), 2);
+    let out = TensorBatch::new(Shape::new(1, 3), 2);
+
+    a.load_from_host(&[1.0, 2.0, 3.0, 4.0]);
+    b.load_from_host(&[5.0, 6.0]);
+
+    TensorBatch::concat_into(handle, 2, &a, &out, 0);
+    TensorBatch::concat_into(handle, 2, &b, &out, 2);
+
+    let mut buf = [0.0; 6];
+    out.write_to_host(&mut buf);
+    assert_eq!(buf, [1.0, 2.0, 5.0, 3.0, 4.0, 6.0]);
+
+    out.load_from_host(&[-1.0, -2.0, -3.0, -4.0, -5.0, -6.0]);
+    TensorBatch::split_from(handle, 2, &out, 0, &a);
+    TensorBatch::split_from(handle, 2, &out, 2, &b);
+
+    let mut buf = [0.0; 4];
+    a.write_to_host(&mut buf);
+    assert_eq!(buf, [-1.0, -2.0, -4.0, -5.0]);
+
+    let mut buf = [0.0; 2];
+    b.write_to_host(&mut buf);
+    assert_eq!(buf, [-3.0, -6.0]);
+
+    TensorBatch::backprop_split(handle, 2, &b, 2, &out);
+
+    let mut buf = [0.0; 6];
+    out.write_to_host(&mut buf);
+    assert_eq!(buf, [0.0, 0.0, -3.0, 0.0, 0.0, -6.0]);
+}
+
+#[test]
+fn reduce() {
+    let handle = DeviceHandles::default();
+
+    // a 2 x 3 matrix, stored column by column
+    let xs = [1.0, 4.0, 2.0, -5.0, 3.0, 6.0];
+    let inp = TensorBatch::new(Shape::new(1, 6), 1);
+    let rows = TensorBatch::new(Shape::new(1, 2), 1);
+    let cols = TensorBatch::new(Shape::new(1, 3), 1);
+
+    inp.load_from_host(&xs);
+    TensorBatch::reduce(handle, 1, Reduction::Sum, Axis::Cols, 3, &inp, &rows);
+    TensorBatch::reduce(handle, 1, Reduction::Max, Axis::Rows, 3, &inp, &cols);
+
+    let mut buf = [0.0; 2];
+    rows.write_to_host(&mut buf);
+    assert_eq!(buf, [6.0, 5.0]);
+
+    let mut buf = [0.0; 3];
+    cols.write_to_host(&mut buf);
+    assert_eq!(buf, [4.0, 2.0, 6.0]);
+
+    cols.load_from_host(&[1.0, 2.0, 3.0]);
+    TensorBatch::backprop_reduce(handle, 1, Reduction::Max, Axis::Rows, 3, &cols, &inp);
+
+    let mut buf = [0.0; 6];
+    inp.write_to_host(&mut buf);
+    assert_eq!(buf, [0.0, 1.0, 2.0, 0.0, 0.0, 3.0]);
+
+    rows.load_from_host(&[3.0, 6.0]);
+    TensorBatch::backprop_reduce(handle, 1, Reduction::Mean, Axis::Cols, 3, &rows, &inp);
+
+    inp.write_to_host(&mut buf);
+    assert_eq!(buf, [1.0, 2.0, 1.0, 2.0, 1.0, 2.0]);
+}
+
+#[test]
+fn broadcast() {
+    let handle = DeviceHandles::default();
+
+    let mut vector = unsafe { Tensor::uninit(Shape::new(1, 2)) };
+    let mut scalar = unsafe { Tensor::uninit(Shape::new(1, 1)) };
+    let mut grad = unsafe { Tensor::uninit(Shape::new(1, 2)) };
+    vector.calloc();
+    scalar.calloc();
+    grad.calloc();
+    vector.load_from_host(&[2.0, -1.0]);
+    scalar.load_from_host(&[0.5]);
+
+    let x = TensorBatch::new(Shape::new(1, 2), 3);
+    let y = TensorBatch::new(Shape::new(1, 2), 3);
+    x.load_from_host(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+    let mut buf = [0.0; 6];
+
+    unsafe {
+        TensorBatch::broadcast_mul(handle, 3, &vector, &x, &y);
+        y.write_to_host(&mut buf);
+        assert_eq!(buf, [2.0, -2.0, 6.0, -4.0, 10.0, -6.0]);
+
+        TensorBatch::broadcast_add(handle, 3, &scalar, &y);
+        y.write_to_host(&mut buf);
+        assert_eq!(buf, [2.5, -1.5, 6.5, -3.5, 10.5, -5.5]);
+
+        y.load_from_host(&[1.0, 1.0, 0.0, 1.0, 1.0, 0.0]);
+        TensorBatch::backprop_broadcast_mul(handle, 3, &vector, &grad, &y, &x);
+        x.write_to_host(&mut buf);
+        assert_eq!(buf, [2.0, -1.0, 0.0, -1.0, 2.0, 0.0]);
+
+        let mut grad_buf = [0.0; 2];
+        grad.write_to_host(&mut grad_buf);
+        assert_eq!(grad_buf, [6.0, 6.0]);
+
+        let mut scalar_buf = [0.0; 1];
+        scalar.load_from_host(&[0.0]);
+        TensorBatch::backprop_broadcast_add(handle, 3, &y, &scalar);
+        scalar.write_to_host(&mut scalar_buf);
+        assert_eq!(scalar_buf, [4.0]);
+
+        vector.free();
+        scalar.free();
+        grad.free();
+    }
+}
+
+#[test]
+fn hadamard() {
+    let handle = DeviceHandles::default();
+
+    let a = TensorBatch::new(Shape::new(1, 2), 2);
+    let a_grad = TensorBatch::new(Shape::new(1, 2), 2);
+    let x = TensorBatch::new(Shape::new(1, 4), 2);
+    let y = TensorBatch::new(Shape::new(1, 4), 2);
+
+    a.load_from_host(&[2.0, -1.0, 0.5, 3.0]);
+    x.load_from_host(&[1.0, 2.0, 3.0, 4.0, 2.0, 2.0, -2.0, 1.0]);
+
+    TensorBatch::hadamard(handle, 2, &a, &x, &y);
+
+    let mut buf = [0.0; 8];
+    y.write_to_host(&mut buf);
+    assert_eq!(buf, [2.0, -2.0, 6.0, -4.0, 1.0, 6.0, -1.0, 3.0]);
+
+    y.load_from_host(&[1.0, 1.0, 1.0, 0.0, 2.0, 0.0, 0.0, 1.0]);
+    TensorBatch::backprop_hadamard(handle, 2, &a, &a_grad, &y, &x);
+
+    x.write_to_host(&mut buf);
+    assert_eq!(buf, [2.0, -1.0, 2.0, 0.0, 1.0, 0.0, 0.0, 3.0]);
+
+    let mut buf = [0.0; 4];
+    a_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [4.0, 2.0, 4.0, 1.0]);
+}
+
+#[test]
+fn pairwise_mul() {
+    let handle = DeviceHandles::default();
+
+    let inp = TensorBatch::new(Shape::new(1, 4), 2);
+    let out = TensorBatch::new(Shape::new(1, 2), 2);
+
+    inp.load_from_host(&[0.5, 2.0, 0.5, 0.25, -1.0, 0.5, 0.5, 0.5]);
+    TensorBatch::pairwise_mul(handle, 2, 2, (0.0, 1.0), &inp, &out);
+
+    let mut buf = [0.0; 4];
+    out.write_to_host(&mut buf);
+    assert_eq!(buf, [0.25, 0.25, 0.0, 0.25]);
+
+    out.load_from_host(&[1.0, 2.0, 1.0, 1.0]);
+    TensorBatch::backprop_pairwise_mul(handle, 2, 1, (f32::NEG_INFINITY, f32::INFINITY), &out, &inp);
+
+    let mut buf = [0.0; 8];
+    inp.write_to_host(&mut buf);
+    assert_eq!(buf, [2.0, 0.5, 0.5, 1.0, 0.5, -1.0, 0.5, 0.5]);
+}
+
+#[test]
+fn affine() {
+    let handle = DeviceHandles::default();
+    let inps = [1.0, 2.0, -0.5];
+    let ws = [
+        1.0, 0.0, 1.0,
+        0.0, 1.0, 0.0,
+        1.0, 0.0, 1.0,
+    ];
+    let bs = [0.1, 0.2, 0.3];
+
+    unsafe {
+        let mut w = Tensor::uninit(Shape::new(3, 3));
+        let mut b = Tensor::uninit(Shape::new(1, 3));
+        let x = TensorBatch::new(Shape::new(1, 3), 1);
+        let y = TensorBatch::new(Shape::new(1, 3), 1);
+        let ones = DeviceBuffer::new(1);
+        ones.load_from_host(&[1.0]);
+
+        w.calloc();
+        b.calloc();
+
+        w.load_from_host(&ws);
+        b.load_from_host(&bs);
+
+        x.load_from_host(&inps);
+
+        TensorBatch::affine(handle, 1, &w, &x, &b, &y);
+
+        let mut buf = [0.0; 3];
+        y.write_to_host(&mut buf);
+        assert_eq!(buf, [0.6, 2.2, 0.8]);
+
+        let mut wg = Tensor::uninit(Shape::new(3, 3));
+        let mut bg = Tensor::uninit(Shape::new(1, 3));
+
+        wg.calloc();
+        bg.calloc();
+
+        TensorBatch::backprop_affine(handle, &ones, 1, &w, &y, &x, &wg, &bg);
+
+        x.write_to_host(&mut buf);
+        assert_eq!(buf, [1.4000001, 2.2, 1.4000001]);
+
+        let mut wbuf = [0.0; 9];
+        wg.write_to_host(&mut wbuf);
+        assert_eq!(wbuf, [
+            0.6, 2.2, 0.8,
+            1.2, 4.4, 1.6,
+            -0.3, -1.1, -0.4,
+            //0.6, 1.2, -0.3,
+            //2.2, 4.4, -1.1,
             //0.8, 1.6, -0.4,
             ]);
 
@@ -339,22 +935,317 @@ fn mse() {
 
     let error = DeviceBuffer::new(1);
 
-    let x = TensorBatch::new(Shape::new(1, 1), 9);
-    x.load_from_host(&out);
+    let x = TensorBatch::new(Shape::new(1, 1), 9);
+    x.load_from_host(&out);
+
+    let r = TensorBatch::new(Shape::new(1, 1), 9);
+    r.load_from_host(&res);
+
+    x.sigmoid_mpe(handle, 3, &r, &error, 2.0);
+
+    let mut buf = [0.0; 3];
+    x.write_to_host(&mut buf);
+
+    for (e, (&o, &r)) in buf.iter().zip(out.iter().zip(res.iter())) {
+        let sig = 1.0 / (1.0 + (-o).exp());
+
+        let diff = e - (sig - r) * sig * (1.0 - sig);
+        assert!(diff.abs() < 0.00001);
+    }
+}
+
+#[test]
+fn weighted_mse() {
+    let handle = DeviceHandles::default();
+    let out = [1.5, 0.0, 1.0];
+    let res = [0.5, 0.5, 0.5];
+    let weights = [2.0, 1.0, 0.5];
+
+    let error = DeviceBuffer::new(1);
+
+    let x = TensorBatch::new(Shape::new(1, 1), 9);
+    x.load_from_host(&out);
+
+    let r = TensorBatch::new(Shape::new(1, 1), 9);
+    r.load_from_host(&res);
+
+    let w = TensorBatch::new(Shape::new(1, 1), 9);
+    w.load_from_host(&weights);
+
+    x.sigmoid_mpe_weighted(handle, 3, &r, &w, &error, 2.0);
+
+    let mut buf = [0.0; 3];
+    x.write_to_host(&mut buf);
+
+    for ((e, &w), (&o, &r)) in buf.iter().zip(weights.iter()).zip(out.iter().zip(res.iter())) {
+        let sig = 1.0 / (1.0 + (-o).exp());
+
+        let diff = e - w * (sig - r) * sig * (1.0 - sig);
+        assert!(diff.abs() < 0.00001);
+    }
+}
+
+#[test]
+fn focal() {
+    let handle = DeviceHandles::default();
+    let out = [1.5, 0.0, -1.0];
+    let res = [0.5, 0.5, 1.0];
+    let gamma = 1.5;
+
+    let error = DeviceBuffer::new(1);
+
+    let x = TensorBatch::new(Shape::new(1, 1), 9);
+    x.load_from_host(&out);
+
+    let r = TensorBatch::new(Shape::new(1, 1), 9);
+    r.load_from_host(&res);
+
+    x.sigmoid_focal(handle, 3, &r, &error, 2.0, gamma);
+
+    let mut buf = [0.0; 3];
+    x.write_to_host(&mut buf);
+
+    for (e, (&o, &r)) in buf.iter().zip(out.iter().zip(res.iter())) {
+        let sig = 1.0 / (1.0 + (-o).exp());
+        let grad = (sig - r) * (sig - r).abs().powf(gamma);
+
+        let diff = e - grad * sig * (1.0 - sig);
+        assert!(diff.abs() < 0.00001);
+    }
+}
+
+#[test]
+fn mse_variance() {
+    let handle = DeviceHandles::default();
+    let out = [1.5, 0.2, 0.0, 0.0, -1.0, 0.5];
+    let res = [0.5, 0.0, 0.5, 0.0, 1.0, 0.0];
+
+    let error = DeviceBuffer::new(1);
+
+    let x = TensorBatch::new(Shape::new(1, 2), 3);
+    x.load_from_host(&out);
+
+    let r = TensorBatch::new(Shape::new(1, 2), 3);
+    r.load_from_host(&res);
+
+    x.sigmoid_mse_variance(handle, 3, &r, &error);
+
+    let mut buf = [0.0; 6];
+    x.write_to_host(&mut buf);
+
+    for (e, (o, r)) in buf.chunks(2).zip(out.chunks(2).zip(res.chunks(2))) {
+        let sig = 1.0 / (1.0 + (-o[0]).exp());
+        let sqr = (sig - r[0]).powi(2);
+
+        assert!((e[0] - (sig - r[0]) * sig * (1.0 - sig)).abs() < 0.00001);
+        assert!((e[1] - (o[1] - sqr)).abs() < 0.00001);
+    }
+}
+
+#[test]
+fn centralise_gradients() {
+    let handle = DeviceHandles::default();
+    let mut xs = [1.0, 2.0, 3.0, 4.0, 5.0, 0.0];
+
+    let grads = DeviceBuffer::new(6);
+    grads.load_from_host(&xs);
+
+    unsafe {
+        crate::backend::ops::centralise_gradients(handle, 3, 2, grads.ptr());
+    }
+
+    grads.write_to_host(&mut xs);
+
+    assert_eq!(xs, [-2.0, 0.0, 0.0, 2.0, 2.0, -2.0]);
+}
+
+#[test]
+fn param_settings() {
+    let handle = DeviceHandles::default();
+    let mut xs = [0.0; 4];
+
+    let mut opt = Optimiser::new(4, OptimiserType::SGD { momentum: 0.0, nesterov: false });
+    opt.add_segment(0, 2, 1.0, ParamKind::Weights);
+    opt.add_segment(2, 2, 1.0, ParamKind::Biases);
+    opt.set_param_settings(ParamKind::Biases, ParamSettings { max_weight: 4.0, decay_mult: 0.0, lr_mult: 1.0 });
+
+    opt.load_weights_from_host(&[1.0, 5.0, 1.0, 5.0]);
+    opt.zero_gradient();
+    opt.update(handle, 0.5, 1.0, 1.0);
+    opt.write_weights_to_host(&mut xs);
+
+    assert_eq!(xs, [0.5, 1.98, 1.0, 4.0]);
+}
+
+#[test]
+fn optimiser_state_copy() {
+    let handle = DeviceHandles::default();
+    let mut xs = [0.0; 3];
+    let mut ys = [0.0; 3];
+
+    let mut opt = Optimiser::new(3, OptimiserType::AdamW);
+    opt.load_from_cpu(&[0.1, -0.2, 0.3], &[0.5, 0.5, 0.5], &[1.0, 1.0, 1.0]);
+    opt.zero_gradient();
+    opt.update(handle, 1.0, 1.0, 0.1);
+
+    let mut fork = Optimiser::new(3, OptimiserType::AdamW);
+    fork.load_state_from(&opt);
+    assert_eq!(fork.step(), opt.step());
+
+    opt.update(handle, 1.0, 1.0, 0.1);
+    fork.update(handle, 1.0, 1.0, 0.1);
+
+    opt.write_weights_to_host(&mut xs);
+    fork.write_weights_to_host(&mut ys);
+    assert_eq!(xs, ys);
+}
+
+/// Single element buffers holding each of `values`.
+fn scalars(values: &[f32]) -> Vec<DeviceBuffer> {
+    values.iter().map(|&x| {
+        let buf = DeviceBuffer::new(1);
+        buf.load_from_host(&[x]);
+        buf
+    }).collect()
+}
+
+fn scalar(buf: &DeviceBuffer) -> f32 {
+    let mut x = [0.0];
+    buf.write_to_host(&mut x);
+    x[0]
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() <= 1e-4 * expected.abs().max(1.0), "{actual} != {expected}");
+}
+
+#[test]
+fn sgd_update() {
+    let handle = DeviceHandles::default();
+
+    // momentum 0.9, learning rate 0.1 and a gradient of 0.5, so without nesterov
+    // m = 0.5 then 0.95, and with it each step is also the gradient plus 0.9 m
+    for (nesterov, expected) in [(false, [0.95, 0.855]), (true, [0.905, 0.7695])] {
+        let [p, m, g] = &scalars(&[1.0, 0.0, 0.5])[..] else { unreachable!() };
+
+        for (step, expected) in expected.into_iter().enumerate() {
+            unsafe {
+                crate::backend::ops::update_weights_sgd(handle, 1, 1.0, 1.0, 0.1, 10.0, 0.9, nesterov, p.ptr(), m.ptr(), g.ptr());
+            }
+
+            assert_close(scalar(p), expected);
+            assert_close(scalar(m), [0.5, 0.95][step]);
+        }
+    }
+
+    // decayed, then clipped
+    let [p, m, g] = &scalars(&[1.0, 0.0, -2.0])[..] else { unreachable!() };
+    unsafe {
+        crate::backend::ops::update_weights_sgd(handle, 1, 0.9, 1.0, 0.1, 1.0, 0.9, false, p.ptr(), m.ptr(), g.ptr());
+    }
+    assert_eq!(scalar(p), 1.0);
+}
+
+#[test]
+fn radam_update() {
+    let handle = DeviceHandles::default();
+
+    // with betas of 0.9 and 0.999 and a gradient of 2, m = 0.2 then 0.38, and
+    // v = 0.004 then 0.007996, with bias corrections of 10 then 1 / 0.19
+    let scales = [10.0, 1.0 / 0.19];
+
+    // rectified by 0.5 at both steps, and unrectified, as SGD with momentum
+    let cases = [(0.5, [-0.58114, -1.6995]), (0.0, [0.8, 0.6])];
+
+    for (rectification, expected) in cases {
+        let [p, m, v, g] = &scalars(&[1.0, 0.0, 0.0, 2.0])[..] else { unreachable!() };
+
+        for step in 0..2 {
+            unsafe {
+                crate::backend::ops::update_weights_radam(
+                    handle, 1, 1.0, 1.0, 0.1, 10.0, 0.9, 0.999, scales[step], rectification,
+                    p.ptr(), m.ptr(), v.ptr(), g.ptr(),
+                );
+            }
+
+            assert_close(scalar(p), expected[step]);
+            assert_close(scalar(m), [0.2, 0.38][step]);
+            assert_close(scalar(v), [0.004, 0.007996][step]);
+        }
+    }
+}
+
+#[test]
+fn radam_rectification() {
+    let betas = (0.9, 0.999);
+
+    // the variance of the adaptive learning rate is intractable until step 5
+    for step in 1..=4 {
+        assert_eq!(super::optimiser::radam_scales(step, betas).1, 0.0);
+    }
+
+    let (momentum_scale, rectification) = super::optimiser::radam_scales(5, betas);
+    assert_close(momentum_scale, 2.4419428);
+    assert_close(rectification, 0.0012228846);
+}
+
+#[test]
+fn lamb_update() {
+    let handle = DeviceHandles::default();
+    let [p, m, v, g] = &scalars(&[0.5, 0.0, 0.0, 2.0])[..] else { unreachable!() };
+
+    // m = 0.2 and v = 0.004, so with bias corrections of 10 and 1000 the
+    // Adam step is 1, plus 0.01 * 0.5 of weight decay
+    unsafe {
+        crate::backend::ops::lamb_moments(handle, 1, 0.01, 1.0, 0.9, 0.999, 10.0, 1000.0, p.ptr(), m.ptr(), v.ptr(), g.ptr());
+    }
+
+    assert_close(scalar(m), 0.2);
+    assert_close(scalar(v), 0.004);
+    assert_close(scalar(g), 1.005);
+    assert_eq!(scalar(p), 0.5);
+
+    unsafe {
+        crate::backend::ops::lamb_apply(handle, 1, 0.1, 10.0, p.ptr(), g.ptr());
+    }
+
+    assert_close(scalar(p), 0.3995);
+
+    // clipped
+    unsafe {
+        crate::backend::ops::lamb_apply(handle, 1, 10.0, 1.0, p.ptr(), g.ptr());
+    }
+
+    assert_eq!(scalar(p), -1.0);
+}
 
-    let r = TensorBatch::new(Shape::new(1, 1), 9);
-    r.load_from_host(&res);
+#[test]
+fn frozen_segments_keep_their_moments() {
+    let handle = DeviceHandles::default();
+    let kinds = [
+        OptimiserType::AdamW,
+        OptimiserType::SGD { momentum: 0.9, nesterov: false },
+        OptimiserType::RAdam,
+        OptimiserType::LAMB,
+    ];
 
-    x.sigmoid_mpe(handle, 3, &r, &error, 2.0);
+    for kind in kinds {
+        let mut opt = Optimiser::new(4, kind);
+        opt.add_segment(0, 2, 1.0, ParamKind::Weights);
+        opt.add_segment(2, 2, 1.0, ParamKind::Weights);
+        opt.set_lr_scale(0, 2, 0.0);
 
-    let mut buf = [0.0; 3];
-    x.write_to_host(&mut buf);
+        opt.load_from_cpu(&[0.1, -0.2, 0.3, -0.4], &[0.5; 4], &[1.0; 4]);
+        opt.zero_gradient();
+        opt.update(handle, 0.01, 1.0, 0.1);
 
-    for (e, (&o, &r)) in buf.iter().zip(out.iter().zip(res.iter())) {
-        let sig = 1.0 / (1.0 + (-o).exp());
+        let (mut weights, mut momentum, mut velocity) = ([0.0; 4], [0.0; 4], [0.0; 4]);
+        opt.write_to_host(&mut weights, &mut momentum, &mut velocity);
 
-        let diff = e - (sig - r) * sig * (1.0 - sig);
-        assert!(diff.abs() < 0.00001);
+        assert_eq!(weights[..2], [0.1, -0.2], "{kind:?}");
+        assert_eq!(momentum[..2], [0.5; 2], "{kind:?}");
+        assert_eq!(velocity[..2], [1.0; 2], "{kind:?}");
+        assert_ne!(momentum[2..], [0.5; 2], "{kind:?}");
     }
 }
 
@@ -406,3 +1297,583 @@ fn select() {
     input_gpu.write_to_host(&mut buf);
     assert_eq!(buf, expected);
 }
+
+#[test]
+fn grouped_affine() {
+    let handle = DeviceHandles::default();
+
+    let mut weights = unsafe { Tensor::uninit(Shape::new(4, 1)) };
+    let mut biases = unsafe { Tensor::uninit(Shape::new(1, 2)) };
+    let mut weights_grad = unsafe { Tensor::uninit(Shape::new(4, 1)) };
+    let mut biases_grad = unsafe { Tensor::uninit(Shape::new(1, 2)) };
+    for tensor in [&mut weights, &mut biases, &mut weights_grad, &mut biases_grad] {
+        tensor.calloc();
+    }
+
+    weights.load_from_host(&[1.0, 2.0, 3.0, 4.0]);
+    biases.load_from_host(&[0.5, -0.5]);
+
+    let ones = DeviceBuffer::new(2);
+    ones.load_from_host(&[1.0, 1.0]);
+
+    let inputs = TensorBatch::new(Shape::new(1, 4), 2);
+    let outputs = TensorBatch::new(Shape::new(1, 2), 2);
+    inputs.load_from_host(&[1.0, 2.0, 3.0, 4.0, 0.0, 1.0, 0.0, -1.0]);
+
+    unsafe {
+        TensorBatch::grouped_affine(handle, 2, 2, &weights, &inputs, &biases, &outputs);
+    }
+
+    let mut buf = [0.0; 4];
+    outputs.write_to_host(&mut buf);
+    assert_eq!(buf, [5.5, 24.5, 2.5, -4.5]);
+
+    outputs.load_from_host(&[1.0, 2.0, 1.0, -1.0]);
+    unsafe {
+        TensorBatch::backprop_grouped_affine(
+            handle,
+            &ones,
+            2,
+            2,
+            &weights,
+            &outputs,
+            &inputs,
+            &weights_grad,
+            &biases_grad,
+        );
+    }
+
+    weights_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [1.0, 3.0, 6.0, 9.0]);
+
+    let mut buf = [0.0; 2];
+    biases_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [2.0, 1.0]);
+
+    let mut buf = [0.0; 8];
+    inputs.write_to_host(&mut buf);
+    assert_eq!(buf, [1.0, 2.0, 6.0, 8.0, 1.0, 2.0, -3.0, -4.0]);
+
+    unsafe {
+        weights.free();
+        biases.free();
+        weights_grad.free();
+        biases_grad.free();
+    }
+}
+
+#[test]
+fn convolution() {
+    let handle = DeviceHandles::default();
+
+    let desc = ConvolutionDescription::new((3, 3), 1, 1, (2, 2))
+        .with_stride((2, 1))
+        .with_padding((1, 0))
+        .with_dilation((1, 2));
+
+    assert_eq!(desc.output_shape(), (2, 1));
+
+    let mut weights = unsafe { Tensor::uninit(Shape::new(1, 4)) };
+    let mut biases = unsafe { Tensor::uninit(Shape::new(1, 1)) };
+    let mut weights_grad = unsafe { Tensor::uninit(Shape::new(1, 4)) };
+    let mut biases_grad = unsafe { Tensor::uninit(Shape::new(1, 1)) };
+    for tensor in [&mut weights, &mut biases, &mut weights_grad, &mut biases_grad] {
+        tensor.calloc();
+    }
+
+    weights.load_from_host(&[1.0, 2.0, 3.0, 4.0]);
+    biases.load_from_host(&[0.5]);
+
+    let inputs = TensorBatch::new(Shape::new(1, 9), 2);
+    let outputs = TensorBatch::new(Shape::new(1, 2), 2);
+    inputs.load_from_host(&[
+        1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0,
+        1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0,
+    ]);
+
+    unsafe {
+        TensorBatch::convolution(handle, &desc, 2, &weights, &biases, &inputs, &outputs);
+    }
+
+    let mut buf = [0.0; 4];
+    outputs.write_to_host(&mut buf);
+    assert_eq!(buf, [15.5, 73.5, 3.5, -3.5]);
+
+    outputs.load_from_host(&[1.0, 2.0, 1.0, -1.0]);
+    unsafe {
+        TensorBatch::backprop_convolution(handle, &desc, 2, &weights, &outputs, &inputs, &weights_grad, &biases_grad);
+    }
+
+    weights_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [8.0, 12.0, 16.0, 22.0]);
+
+    let mut buf = [0.0; 1];
+    biases_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [3.0]);
+
+    let mut buf = [0.0; 18];
+    inputs.write_to_host(&mut buf);
+    assert_eq!(buf, [
+        3.0, 0.0, 4.0, 2.0, 0.0, 4.0, 6.0, 0.0, 8.0,
+        3.0, 0.0, 4.0, -1.0, 0.0, -2.0, -3.0, 0.0, -4.0,
+    ]);
+
+    unsafe {
+        weights.free();
+        biases.free();
+        weights_grad.free();
+        biases_grad.free();
+    }
+}
+
+#[test]
+fn depthwise_convolution() {
+    let handle = DeviceHandles::default();
+
+    let desc = ConvolutionDescription::depthwise((2, 2), 2, (2, 2));
+    assert_eq!(desc.num_weights(), 8);
+
+    let mut weights = unsafe { Tensor::uninit(Shape::new(1, 8)) };
+    let mut biases = unsafe { Tensor::uninit(Shape::new(1, 2)) };
+    let mut weights_grad = unsafe { Tensor::uninit(Shape::new(1, 8)) };
+    let mut biases_grad = unsafe { Tensor::uninit(Shape::new(1, 2)) };
+    for tensor in [&mut weights, &mut biases, &mut weights_grad, &mut biases_grad] {
+        tensor.calloc();
+    }
+
+    weights.load_from_host(&[1.0, 2.0, 3.0, 4.0, -1.0, 0.0, 0.0, 1.0]);
+    biases.load_from_host(&[0.0, 1.0]);
+
+    let inputs = TensorBatch::new(Shape::new(1, 8), 1);
+    let outputs = TensorBatch::new(Shape::new(1, 2), 1);
+    inputs.load_from_host(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+
+    unsafe {
+        TensorBatch::convolution(handle, &desc, 1, &weights, &biases, &inputs, &outputs);
+    }
+
+    let mut buf = [0.0; 2];
+    outputs.write_to_host(&mut buf);
+    assert_eq!(buf, [30.0, 4.0]);
+
+    outputs.load_from_host(&[1.0, 2.0]);
+    unsafe {
+        TensorBatch::backprop_convolution(handle, &desc, 1, &weights, &outputs, &inputs, &weights_grad, &biases_grad);
+    }
+
+    biases_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [1.0, 2.0]);
+
+    let mut buf = [0.0; 8];
+    weights_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [1.0, 2.0, 3.0, 4.0, 10.0, 12.0, 14.0, 16.0]);
+
+    inputs.write_to_host(&mut buf);
+    assert_eq!(buf, [1.0, 2.0, 3.0, 4.0, -2.0, 0.0, 0.0, 2.0]);
+
+    unsafe {
+        weights.free();
+        biases.free();
+        weights_grad.free();
+        biases_grad.free();
+    }
+}
+
+#[test]
+fn attention() {
+    let handle = DeviceHandles::default();
+
+    let desc = AttentionDescription::new(2, 1, 1, 1);
+    assert_eq!((desc.num_weights(), desc.num_biases()), (3, 3));
+
+    let mut weights = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    let mut biases = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    let mut weights_grad = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    let mut biases_grad = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    for tensor in [&mut weights, &mut biases, &mut weights_grad, &mut biases_grad] {
+        tensor.calloc();
+    }
+
+    let (qkv, qkv_grad) = (DeviceBuffer::new(12), DeviceBuffer::new(12));
+    let (attn, attn_grad) = (DeviceBuffer::new(8), DeviceBuffer::new(8));
+
+    let inputs = TensorBatch::new(Shape::new(1, 2), 2);
+    let outputs = TensorBatch::new(Shape::new(1, 2), 2);
+
+    // queries and keys of [1, 3], so the tokens attend mostly to the second
+    weights.load_from_host(&[1.0, 1.0, 1.0]);
+    inputs.load_from_host(&[1.0, 3.0, 2.0, 2.0]);
+
+    unsafe {
+        TensorBatch::attention(handle, &desc, 2, &weights, &biases, &inputs, &qkv, &attn, &outputs);
+    }
+
+    let weighted = |a: f32, b: f32| (a.exp() + 3.0 * b.exp()) / (a.exp() + b.exp());
+    let mut buf = [0.0; 4];
+    outputs.write_to_host(&mut buf);
+    for (x, y) in buf.iter().zip([weighted(1.0, 3.0), weighted(3.0, 9.0), 2.0, 2.0]) {
+        assert!((x - y).abs() < 0.0001);
+    }
+
+    // zero queries attend equally to every token
+    weights.load_from_host(&[0.0, 1.0, 1.0]);
+    unsafe {
+        TensorBatch::attention(handle, &desc, 2, &weights, &biases, &inputs, &qkv, &attn, &outputs);
+    }
+
+    outputs.write_to_host(&mut buf);
+    assert_eq!(buf, [2.0, 2.0, 2.0, 2.0]);
+
+    outputs.load_from_host(&[1.0, 1.0, 1.0, 1.0]);
+    unsafe {
+        TensorBatch::backprop_attention(
+            handle, &desc, 2, &weights, &outputs, &inputs, &weights_grad, &biases_grad, &qkv, &attn, &qkv_grad, &attn_grad,
+        );
+    }
+
+    let mut buf = [0.0; 3];
+    weights_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [4.0, 0.0, 8.0]);
+
+    biases_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [2.0, 0.0, 4.0]);
+
+    let mut buf = [0.0; 4];
+    inputs.write_to_host(&mut buf);
+    assert_eq!(buf, [1.0, 1.0, 1.0, 1.0]);
+
+    unsafe {
+        weights.free();
+        biases.free();
+        weights_grad.free();
+        biases_grad.free();
+    }
+}
+
+#[test]
+fn gather() {
+    let handle = DeviceHandles::default();
+    let buckets = [2, 0, 2];
+
+    let mut weights = unsafe { Tensor::uninit(Shape::new(3, 2)) };
+    let mut weights_grad = unsafe { Tensor::uninit(Shape::new(3, 2)) };
+    weights.calloc();
+    weights_grad.calloc();
+    weights.load_from_host(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+    let out = TensorBatch::new(Shape::new(1, 2), 3);
+    let buckets_gpu = util::calloc::<u8>(3);
+
+    unsafe {
+        util::copy_to_device(buckets_gpu, buckets.as_ptr(), 3);
+        TensorBatch::gather(handle, 3, buckets_gpu, &weights, &out);
+    }
+
+    let mut buf = [0.0; 6];
+    out.write_to_host(&mut buf);
+    assert_eq!(buf, [5.0, 6.0, 1.0, 2.0, 5.0, 6.0]);
+
+    out.load_from_host(&[1.0, 2.0, 3.0, 4.0, 0.5, -1.0]);
+    unsafe {
+        TensorBatch::backprop_gather(handle, 3, buckets_gpu, &out, &weights_grad);
+    }
+
+    weights_grad.write_to_host(&mut buf);
+    assert_eq!(buf, [3.0, 4.0, 0.0, 0.0, 1.5, 1.0]);
+
+    unsafe {
+        weights.free();
+        weights_grad.free();
+    }
+}
+
+#[test]
+fn softmax() {
+    let handle = DeviceHandles::default();
+    let inp = [1.0, 0.0, -1.0, 0.5, 2.0, 0.5];
+    let grads = [1.0, -1.0, 0.5, 0.0, 2.0, 1.0];
+
+    let x = TensorBatch::new(Shape::new(1, 3), 2);
+    let y = TensorBatch::new(Shape::new(1, 3), 2);
+
+    for log in [false, true] {
+        let mut buf = [0.0; 6];
+        x.load_from_host(&inp);
+        TensorBatch::softmax(handle, 2, log, &x, &y);
+        y.write_to_host(&mut buf);
+
+        y.load_from_host(&grads);
+        TensorBatch::backprop_softmax(handle, 2, log, &y, &x);
+        let mut back = [0.0; 6];
+        x.write_to_host(&mut back);
+
+        for ((o, g), (b, e)) in inp.chunks(3).zip(grads.chunks(3)).zip(buf.chunks(3).zip(back.chunks(3))) {
+            let total = o.iter().map(|x| x.exp()).sum::<f32>();
+            let probs: Vec<f32> = o.iter().map(|x| x.exp() / total).collect();
+            let dot: f32 = if log { g.iter().sum() } else { g.iter().zip(&probs).map(|(g, p)| g * p).sum() };
+
+            for i in 0..3 {
+                let (expected, grad) =
+                    if log { (probs[i].ln(), g[i] - probs[i] * dot) } else { (probs[i], probs[i] * (g[i] - dot)) };
+
+                assert!((b[i] - expected).abs() < 0.00001);
+                assert!((e[i] - grad).abs() < 0.00001);
+            }
+        }
+    }
+}
+
+#[test]
+fn layer_norm() {
+    let handle = DeviceHandles::default();
+    let inp = [1.0, 2.0, 4.0, -1.0, 0.5, 0.0];
+    let grads = [1.0, -1.0, 0.5, 0.25, 2.0, -1.0];
+    let gammas = [1.0, 2.0, 0.5];
+    let betas = [0.0, 1.0, -1.0];
+
+    let norm = |x: &[f32], gammas: &[f32]| {
+        let mean = x.iter().sum::<f32>() / 3.0;
+        let var = x.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / 3.0;
+        let rstd = 1.0 / (var + 1e-5).sqrt();
+        x.iter().zip(gammas).map(|(x, g)| g * (x - mean) * rstd).collect::<Vec<f32>>()
+    };
+
+    let mut gamma = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    let mut beta = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    let mut gamma_grad = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    let mut beta_grad = unsafe { Tensor::uninit(Shape::new(1, 3)) };
+    for tensor in [&mut gamma, &mut beta, &mut gamma_grad, &mut beta_grad] {
+        tensor.calloc();
+    }
+    gamma.load_from_host(&gammas);
+    beta.load_from_host(&betas);
+
+    let x = TensorBatch::new(Shape::new(1, 3), 2);
+    let y = TensorBatch::new(Shape::new(1, 3), 2);
+    x.load_from_host(&inp);
+
+    let mut buf = [0.0; 6];
+    unsafe {
+        TensorBatch::layer_norm(handle, 2, &gamma, &beta, &x, &y);
+    }
+    y.write_to_host(&mut buf);
+
+    for (x, y) in inp.chunks(3).zip(buf.chunks(3)) {
+        for ((n, y), b) in norm(x, &gammas).iter().zip(y).zip(betas) {
+            assert!((n + b - y).abs() < 0.0001);
+        }
+    }
+
+    y.load_from_host(&grads);
+    unsafe {
+        TensorBatch::backprop_layer_norm(handle, 2, &gamma, &gamma_grad, &beta_grad, &y, &x);
+    }
+    x.write_to_host(&mut buf);
+
+    let mut expected_gamma_grad = [0.0; 3];
+    let mut expected_beta_grad = [0.0; 3];
+
+    // compare against finite differences of the sum of outputs weighted by `grads`
+    for ((x, g), dx) in inp.chunks(3).zip(grads.chunks(3)).zip(buf.chunks(3)) {
+        let loss = |x: &[f32]| norm(x, &gammas).iter().zip(g).map(|(y, g)| y * g).sum::<f32>();
+
+        for i in 0..3 {
+            let mut shifted = x.to_vec();
+            shifted[i] += 0.001;
+            let numeric = (loss(&shifted) - loss(x)) / 0.001;
+            assert!((numeric - dx[i]).abs() < 0.01, "{numeric} vs {}", dx[i]);
+
+            expected_gamma_grad[i] += g[i] * norm(x, &[1.0; 3])[i];
+            expected_beta_grad[i] += g[i];
+        }
+    }
+
+    let mut gamma_grads = [0.0; 3];
+    let mut beta_grads = [0.0; 3];
+    gamma_grad.write_to_host(&mut gamma_grads);
+    beta_grad.write_to_host(&mut beta_grads);
+
+    for i in 0..3 {
+        assert!((gamma_grads[i] - expected_gamma_grad[i]).abs() < 0.0001);
+        assert!((beta_grads[i] - expected_beta_grad[i]).abs() < 0.0001);
+    }
+
+    unsafe {
+        for tensor in [&mut gamma, &mut beta, &mut gamma_grad, &mut beta_grad] {
+            tensor.free();
+        }
+    }
+}
+
+#[test]
+fn batch_norm() {
+    let handle = DeviceHandles::default();
+    let inp = [1.0, 2.0, 4.0, -1.0, 0.5, 0.0];
+    let grads = [1.0, -1.0, 0.5, 0.25, 2.0, -1.0];
+    let gammas = [1.0, 2.0, 0.5];
+    let betas = [0.0, 1.0, -1.0];
+
+    // normalises each of the 3 outputs over a batch of 2
+    let norm = |x: &[f32], gammas: &[f32]| {
+        let mut out = vec![0.0; 6];
+        for i in 0..3 {
+            let mean = (x[i] + x[i + 3]) / 2.0;
+            let var = ((x[i] - mean).powi(2) + (x[i + 3] - mean).powi(2)) / 2.0;
+            let rstd = 1.0 / (var + 1e-5).sqrt();
+            out[i] = gammas[i] * (x[i] - mean) * rstd;
+            out[i + 3] = gammas[i] * (x[i + 3] - mean) * rstd;
+        }
+        out
+    };
+
+    let mut tensors: Vec<_> = (0..6).map(|_| unsafe { Tensor::uninit(Shape::new(1, 3)) }).collect();
+    for tensor in &mut tensors {
+        tensor.calloc();
+    }
+    let [gamma, beta, mean, var, gamma_grad, beta_grad] = &tensors[..] else { unreachable!() };
+    gamma.load_from_host(&gammas);
+    beta.load_from_host(&betas);
+    var.load_from_host(&[1.0; 3]);
+
+    let batch_mean = DeviceBuffer::new(3);
+    let batch_rstd = DeviceBuffer::new(3);
+
+    let x = TensorBatch::new(Shape::new(1, 3), 2);
+    let y = TensorBatch::new(Shape::new(1, 3), 2);
+    x.load_from_host(&inp);
+
+    let mut buf = [0.0; 6];
+    unsafe {
+        TensorBatch::batch_norm(handle, 2, gamma, beta, mean, var, &batch_mean, &batch_rstd, 0.5, &x, &y);
+    }
+    y.write_to_host(&mut buf);
+
+    for (i, (n, y)) in norm(&inp, &gammas).iter().zip(buf).enumerate() {
+        assert!((n + betas[i % 3] - y).abs() < 0.0001);
+    }
+
+    // running statistics move halfway towards the (unbiased) batch statistics
+    let mut means = [0.0; 3];
+    let mut vars = [0.0; 3];
+    mean.write_to_host(&mut means);
+    var.write_to_host(&mut vars);
+
+    for i in 0..3 {
+        let batch_mean = (inp[i] + inp[i + 3]) / 2.0;
+        let batch_var = (inp[i] - inp[i + 3]).powi(2) / 2.0;
+        assert!((means[i] - 0.5 * batch_mean).abs() < 0.0001);
+        assert!((vars[i] - 0.5 * (1.0 + batch_var)).abs() < 0.0001);
+    }
+
+    unsafe {
+        TensorBatch::batch_norm_inference(handle, 2, gamma, beta, mean, var, &x, &y);
+    }
+    y.write_to_host(&mut buf);
+
+    for (i, (x, y)) in inp.iter().zip(buf).enumerate() {
+        let j = i % 3;
+        let expected = gammas[j] * (x - means[j]) / (vars[j] + 1e-5).sqrt() + betas[j];
+        assert!((expected - y).abs() < 0.0001);
+    }
+
+    y.load_from_host(&grads);
+    unsafe {
+        TensorBatch::backprop_batch_norm(handle, 2, gamma, &batch_mean, &batch_rstd, gamma_grad, beta_grad, &y, &x);
+    }
+    x.write_to_host(&mut buf);
+
+    // compare against finite differences of the sum of outputs weighted by `grads`
+    let loss = |x: &[f32]| norm(x, &gammas).iter().zip(grads).map(|(y, g)| y * g).sum::<f32>();
+
+    for i in 0..6 {
+        let mut shifted = inp.to_vec();
+        shifted[i] += 0.001;
+        let numeric = (loss(&shifted) - loss(&inp)) / 0.001;
+        assert!((numeric - buf[i]).abs() < 0.01, "{numeric} vs {}", buf[i]);
+    }
+
+    let mut gamma_grads = [0.0; 3];
+    let mut beta_grads = [0.0; 3];
+    gamma_grad.write_to_host(&mut gamma_grads);
+    beta_grad.write_to_host(&mut beta_grads);
+
+    let normed = norm(&inp, &[1.0; 3]);
+    for i in 0..3 {
+        let expected_gamma_grad = grads[i] * normed[i] + grads[i + 3] * normed[i + 3];
+        assert!((gamma_grads[i] - expected_gamma_grad).abs() < 0.0001);
+        assert!((beta_grads[i] - grads[i] - grads[i + 3]).abs() < 0.0001);
+    }
+
+    unsafe {
+        for tensor in &mut tensors {
+            tensor.free();
+        }
+    }
+}
+
+#[test]
+fn softmax_crossentropy() {
+    let handle = DeviceHandles::default();
+    let out = [1.0, 0.0, -1.0, 0.5, 0.5, 0.5];
+    let res = [1.0, 0.0, 0.0, 0.25, 0.5, 0.25];
+
+    let error = DeviceBuffer::new(1);
+
+    let x = TensorBatch::new(Shape::new(1, 3), 2);
+    x.load_from_host(&out);
+
+    let r = TensorBatch::new(Shape::new(1, 3), 2);
+    r.load_from_host(&res);
+
+    x.softmax_crossentropy(handle, 2, &r, &error);
+
+    let mut buf = [0.0; 6];
+    x.write_to_host(&mut buf);
+
+    let mut expected_error = 0.0;
+    for (o, (e, r)) in out.chunks(3).zip(buf.chunks(3).zip(res.chunks(3))) {
+        let total = o.iter().map(|x| x.exp()).sum::<f32>();
+
+        for i in 0..3 {
+            let prob = o[i].exp() / total;
+            assert!((e[i] - (prob - r[i])).abs() < 0.00001);
+
+            if r[i] > 0.0 {
+                expected_error -= r[i] * prob.ln();
+            }
+        }
+    }
+
+    let mut err = [0.0];
+    error.write_to_host(&mut err);
+    assert!((err[0] - expected_error).abs() < 0.0001);
+}
+
+#[test]
+fn huber() {
+    let handle = DeviceHandles::default();
+    let out = [1.5, 0.0, -3.0];
+    let res = [0.5, 0.5, 1.0];
+    let delta = 0.25;
+
+    let error = DeviceBuffer::new(1);
+
+    let x = TensorBatch::new(Shape::new(1, 1), 9);
+    x.load_from_host(&out);
+
+    let r = TensorBatch::new(Shape::new(1, 1), 9);
+    r.load_from_host(&res);
+
+    x.sigmoid_huber(handle, 3, &r, &error, delta);
+
+    let mut buf = [0.0; 3];
+    x.write_to_host(&mut buf);
+
+    for (e, (&o, &r)) in buf.iter().zip(out.iter().zip(res.iter())) {
+        let sig = 1.0 / (1.0 + (-o).exp());
+        let grad = (sig - r).clamp(-delta, delta);
+
+        let diff = e - grad * sig * (1.0 - sig);
+        assert!(diff.abs() < 0.00001);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{inputs::InputType, outputs::OutputBuckets};
+
+use super::Trainer;
+
+/// Custom statistics of the outputs of a node on a sampled batch, given the
+/// outputs of every position in turn and the number of outputs per position.
+/// Each returned value is averaged over the sampled batches.
+pub type StatsHook = fn(&[f32], usize) -> Vec<(&'static str, f32)>;
+
+/// Statistics of the outputs of a node, over the batches sampled since they
+/// were last taken.
+#[derive(Clone, Debug)]
+pub struct ActivationStats {
+    /// Numbered as in `TrainerBuilder::concat`, with 0 the feature transformer.
+    pub node: usize,
+    pub name: String,
+    pub mean: f32,
+    pub std: f32,
+    /// Fraction of outputs that are exactly zero, e.g. dead ReLUs.
+    pub sparsity: f32,
+    pub min: f32,
+    pub max: f32,
+    /// Averages of the values returned by the node's `StatsHook`, if it has one.
+    pub custom: Vec<(&'static str, f32)>,
+}
+
+pub(super) struct StatsCollector {
+    node: usize,
+    name: String,
+    every: usize,
+    hook: Option<StatsHook>,
+    sum: f64,
+    sum_sq: f64,
+    zeros: usize,
+    count: usize,
+    min: f32,
+    max: f32,
+    samples: usize,
+    custom: Vec<(&'static str, f64)>,
+}
+
+impl StatsCollector {
+    fn record(&mut self, outputs: &[f32], element_size: usize) {
+        for &x in outputs {
+            self.sum += f64::from(x);
+            self.sum_sq += f64::from(x) * f64::from(x);
+            self.zeros += usize::from(x == 0.0);
+            self.min = self.min.min(x);
+            self.max = self.max.max(x);
+        }
+
+        self.count += outputs.len();
+        self.samples += 1;
+
+        if let Some(hook) = self.hook {
+            for (name, value) in hook(outputs, element_size) {
+                match self.custom.iter_mut().find(|(custom, _)| *custom == name) {
+                    Some((_, sum)) => *sum += f64::from(value),
+                    None => self.custom.push((name, f64::from(value))),
+                }
+            }
+        }
+    }
+
+    fn take(&mut self) -> Option<ActivationStats> {
+        if self.samples == 0 {
+            return None;
+        }
+
+        let count = self.count as f64;
+        let mean = self.sum / count;
+        let var = (self.sum_sq / count - mean * mean).max(0.0);
+        let custom = self.custom.iter().map(|&(name, sum)| (name, (sum / self.samples as f64) as f32)).collect();
+
+        let stats = ActivationStats {
+            node: self.node,
+            name: self.name.clone(),
+            mean: mean as f32,
+            std: var.sqrt() as f32,
+            sparsity: (self.zeros as f64 / count) as f32,
+            min: self.min,
+            max: self.max,
+            custom,
+        };
+
+        *self = Self::new(self.node, std::mem::take(&mut self.name), self.every, self.hook);
+
+        Some(stats)
+    }
+
+    fn new(node: usize, name: String, every: usize, hook: Option<StatsHook>) -> Self {
+        Self {
+            node,
+            name,
+            every,
+            hook,
+            sum: 0.0,
+            sum_sq: 0.0,
+            zeros: 0,
+            count: 0,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            samples: 0,
+            custom: Vec::new(),
+        }
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Collects statistics of the outputs of `node`, numbered as in
+    /// `TrainerBuilder::concat`, on every `every`th training batch, along with
+    /// any custom statistics returned by `hook`. During `run` the statistics
+    /// are printed and appended to `<net>-activations.csv` after each
+    /// superbatch, otherwise they can be read with `take_activation_stats`.
+    pub fn add_activation_stats(&mut self, node: usize, every: usize, hook: Option<StatsHook>) {
+        assert!(node <= self.nodes.len(), "Node {node} doesn't exist!");
+        assert!(every > 0, "Must sample at least every batch!");
+
+        let name = self.summary().layers[node].name.clone();
+        self.activation_stats.push(StatsCollector::new(node, name, every, hook));
+    }
+
+    /// Statistics of each node registered with `add_activation_stats`
+    /// that has been sampled since they were last taken.
+    pub fn take_activation_stats(&mut self) -> Vec<ActivationStats> {
+        self.activation_stats.iter_mut().filter_map(StatsCollector::take).collect()
+    }
+
+    /// Records the outputs of each registered node due to be sampled this
+    /// batch, which must be called after the forward pass and before backprop
+    /// overwrites them with their gradients.
+    pub(super) fn collect_activation_stats(&mut self) {
+        if self.activation_stats.is_empty() {
+            return;
+        }
+
+        self.stats_batches += 1;
+        let batch_size = self.inputs.used();
+
+        // taken so that outputs can be recomputed while recording
+        let mut collectors = std::mem::take(&mut self.activation_stats);
+
+        for collector in &mut collectors {
+            if !self.stats_batches.is_multiple_of(collector.every) {
+                continue;
+            }
+
+            let outputs = match collector.node {
+                0 => &self.ft.outputs,
+                node => {
+                    unsafe {
+                        self.recompute_outputs(batch_size, node - 1, true);
+                    }
+
+                    &self.nodes[node - 1].outputs
+                }
+            };
+
+            let mut buf = vec![0.0; batch_size * outputs.element_size()];
+            outputs.write_to_host(&mut buf);
+            collector.record(&buf, outputs.element_size());
+        }
+
+        self.activation_stats = collectors;
+    }
+}
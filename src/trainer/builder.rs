@@ -1,29 +1,86 @@
+use rand::{rngs::StdRng, SeedableRng};
+
 use crate::{
+    inference::{InferenceNet, Layer},
     inputs::InputType,
     outputs::OutputBuckets,
-    tensor::{self, DeviceBuffer, DeviceHandles, Optimiser, Shape, SparseTensor, Tensor, TensorBatch},
-    Activation,
+    tensor::{
+        self, AttentionDescription, ConvolutionDescription, DeviceBuffer, DeviceHandles, Optimiser, OptimiserType,
+        ParamKind, ParamSettings, Shape, SparseTensor, Tensor, TensorBatch,
+    },
+    Activation, Axis, ClampGradient, Reduction,
 };
 
-use super::{Affine, FeatureTransformer, Node, Operation, QuantiseInfo, Trainer};
+use super::{
+    export, simplify, Affine, Attention, BatchNorm, Concat, Convolution, Dropout, FeatureTransformer, Fusion, Gather,
+    LayerNorm, Multiply, Node, Operation, OutputTransform, PReLU, PairwiseMul, QuantiseInfo, Recompute, SharedAffine,
+    SharedBuffers, Trainer,
+};
 
 enum OpType {
     Activate(Activation),
     Affine,
+    Attention(AttentionDescription),
+    BatchNorm,
+    Clamp { min: f32, max: f32, gradient: ClampGradient },
+    Concat { sources: Vec<usize> },
+    Convolution(ConvolutionDescription),
+    Dropout { rate: f32 },
+    Gather,
+    GroupedAffine { groups: usize },
+    LayerNorm,
+    Multiply { source: usize },
+    PairwiseMul { stride: usize, activation: Option<Activation> },
+    PReLU { channels: usize },
+    Reduce { reduction: Reduction, axis: Axis, cols: usize },
+    Select,
+    SharedAffine { source: usize },
+    Slice { start: usize },
+    Softmax { log: bool },
+}
+
+impl OpType {
+    fn sources(&self) -> &[usize] {
+        match self {
+            OpType::Concat { sources } => sources,
+            OpType::Multiply { source } | OpType::SharedAffine { source } => std::slice::from_ref(source),
+            _ => &[],
+        }
+    }
+
+    fn sources_mut(&mut self) -> &mut [usize] {
+        match self {
+            OpType::Concat { sources } => sources,
+            OpType::Multiply { source } | OpType::SharedAffine { source } => std::slice::from_mut(source),
+            _ => &mut [],
+        }
+    }
 }
 
 struct NodeType {
     size: usize,
     op: OpType,
     in_res_block: bool,
+    lr_mult: f32,
+    recompute: bool,
 }
 
 pub struct TrainerBuilder<T, U> {
     input_getter: T,
     bucket_getter: U,
     ft_out_size: usize,
+    ft_opp_size: Option<usize>,
+    ft_lr_mult: f32,
     nodes: Vec<NodeType>,
     quantisations: Vec<i32>,
+    per_row_quantisation: bool,
+    output_transform: Option<OutputTransform>,
+    optimiser: OptimiserType,
+    lookahead: Option<(usize, f32)>,
+    gradient_centralisation: bool,
+    fusion: bool,
+    buffer_sharing: bool,
+    param_settings: Vec<(ParamKind, ParamSettings)>,
     single_perspective: bool,
     in_res_block: bool,
     size: usize,
@@ -35,8 +92,18 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Default for TrainerBui
             input_getter: T::default(),
             bucket_getter: U::default(),
             ft_out_size: 0,
+            ft_opp_size: None,
+            ft_lr_mult: 1.0,
             nodes: Vec::new(),
             quantisations: Vec::new(),
+            per_row_quantisation: false,
+            output_transform: None,
+            optimiser: OptimiserType::AdamW,
+            lookahead: None,
+            gradient_centralisation: false,
+            fusion: true,
+            buffer_sharing: true,
+            param_settings: Vec::new(),
             single_perspective: false,
             in_res_block: false,
             size: 0,
@@ -45,18 +112,83 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Default for TrainerBui
 }
 
 impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
+    /// Number of outputs of the feature transformer, across both perspectives.
+    fn ft_outputs(&self) -> usize {
+        if self.single_perspective {
+            self.ft_out_size
+        } else {
+            self.ft_out_size + self.ft_opp_size.unwrap_or(self.ft_out_size)
+        }
+    }
+
     fn get_last_layer_size(&self) -> usize {
         if let Some(node) = self.nodes.last() {
             node.size
         } else {
-            self.ft_out_size * if self.single_perspective { 1 } else { 2 }
+            self.ft_outputs()
+        }
+    }
+
+    /// Removes activations that have no effect and merges consecutive
+    /// activations into one where possible. Activations have no weights,
+    /// so this doesn't change the layout of the saved network.
+    fn simplify(&mut self) {
+        // outputs read by later nodes can't be merged away
+        let pinned: Vec<usize> = self.nodes.iter().flat_map(|node| node.op.sources().iter().copied()).collect();
+
+        let mut nodes: Vec<NodeType> = Vec::with_capacity(self.nodes.len());
+        let mut outputs = vec![0];
+
+        for (i, node) in std::mem::take(&mut self.nodes).into_iter().enumerate() {
+            if !Self::merge_activation(&mut nodes, &node, pinned.contains(&i)) {
+                nodes.push(node);
+            }
+
+            outputs.push(nodes.len());
+        }
+
+        for node in &mut nodes {
+            for source in node.op.sources_mut() {
+                *source = outputs[*source];
+            }
+        }
+
+        self.nodes = nodes;
+    }
+
+    /// Returns true if `node` was removed as an identity or fused into the
+    /// previous node, which is only allowed if that isn't `prev_pinned`.
+    fn merge_activation(nodes: &mut [NodeType], node: &NodeType, prev_pinned: bool) -> bool {
+        let OpType::Activate(activation) = node.op else { return false };
+
+        // residual blocks save their input and add it back at the nodes
+        // on their boundaries, so identities are only removed outside them
+        let outside_blocks = |prev: &NodeType| !prev.in_res_block && !node.in_res_block;
+        if simplify::is_identity(activation) && nodes.last().is_some_and(outside_blocks) {
+            return true;
         }
+
+        if prev_pinned {
+            return false;
+        }
+
+        if let Some(prev) = nodes.last_mut().filter(|prev| prev.in_res_block == node.in_res_block) {
+            if let OpType::Activate(prev_activation) = prev.op {
+                if let Some(fused) = simplify::fuse(prev_activation, activation) {
+                    prev.op = OpType::Activate(fused);
+                    return true;
+                }
+            }
+        }
+
+        false
     }
 
     pub fn single_perspective(mut self) -> Self {
         if !self.nodes.is_empty() {
             panic!("You need to set 'single_perspective' before adding any layers!");
         }
+        assert!(self.ft_opp_size.is_none(), "An asymmetric feature transformer needs both perspectives!");
         self.single_perspective = true;
         self
     }
@@ -76,14 +208,114 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
         self
     }
 
+    /// Quantisations constrained to powers of two, `1 << shift`, so that engines
+    /// can dequantise with a right shift rather than a division. The largest
+    /// shifts that fit a trained net are given by `Trainer::max_quantisation_shifts`.
+    pub fn quantisation_shifts(mut self, shifts: &[u32]) -> Self {
+        for &shift in shifts {
+            assert!(shift < 31, "Quantisation shift {shift} is too large!");
+        }
+
+        self.quantisations = shifts.iter().map(|&shift| 1 << shift).collect();
+        self
+    }
+
+    /// Quantise each output neuron of an affine layer with its own scale,
+    /// chosen as large as possible while still fitting in an `i16`. The
+    /// scales are saved alongside the quantised net, in `<net>-scales.bin`,
+    /// and the engine should rescale each output by `quantisation / scale`.
+    pub fn per_row_quantisation(mut self) -> Self {
+        self.per_row_quantisation = true;
+        self
+    }
+
+    /// Transform applied to the output layer whenever the net is quantised,
+    /// which needs the net to end in an affine layer, or one followed by a
+    /// batch norm. The transformed layer is what gets quantised, so its
+    /// quantisation usually has to be reduced to keep it in `i16` range.
+    pub fn output_transform(mut self, transform: OutputTransform) -> Self {
+        self.output_transform = Some(transform);
+        self
+    }
+
+    /// Defaults to `OptimiserType::AdamW`.
+    pub fn optimiser(mut self, optimiser: OptimiserType) -> Self {
+        self.optimiser = optimiser;
+        self
+    }
+
+    /// Wraps the optimiser in Lookahead, syncing the slow weights every
+    /// `steps` updates with interpolation factor `alpha`. The slow weights
+    /// are saved to `slow.bin` in each checkpoint.
+    pub fn lookahead(mut self, steps: usize, alpha: f32) -> Self {
+        self.lookahead = Some((steps, alpha));
+        self
+    }
+
+    /// Centralise the gradients of each weight matrix, subtracting from
+    /// the gradients of each output neuron their mean, before every update.
+    pub fn gradient_centralisation(mut self) -> Self {
+        self.gradient_centralisation = true;
+        self
+    }
+
+    /// Computes every activation in its own kernel, rather than fusing those
+    /// after the feature transformer or an affine layer into it, which should
+    /// give the same results, so is only useful for debugging.
+    pub fn disable_fusion(mut self) -> Self {
+        self.fusion = false;
+        self
+    }
+
+    /// Gives every buffer that is only used during part of backprop its own
+    /// memory, rather than sharing it with those whose lifetimes don't
+    /// overlap, which should give the same results, so is only useful for
+    /// debugging or comparing the memory used.
+    pub fn disable_buffer_sharing(mut self) -> Self {
+        self.buffer_sharing = false;
+        self
+    }
+
+    /// Sets how every parameter of the given `kind` is optimised, e.g. to exempt
+    /// biases, including those of the feature transformer, from weight decay or
+    /// give them wider clipping bounds. By default both kinds are clipped to
+    /// `[-1.98, 1.98]`, with the full weight decay and learning rate.
+    pub fn param_settings(mut self, kind: ParamKind, settings: ParamSettings) -> Self {
+        self.param_settings.push((kind, settings));
+        self
+    }
+
     pub fn feature_transformer(mut self, size: usize) -> Self {
         assert!(self.nodes.is_empty());
         self.ft_out_size = size;
         self
     }
 
+    /// Feature transformer where the side not to move uses only the first
+    /// `nstm_size` outputs of its accumulator, rather than all `stm_size`.
+    /// The weights are shared as usual, so engines keep a full accumulator
+    /// for each side, and the first layer takes `stm_size + nstm_size` inputs.
+    pub fn asymmetric_feature_transformer(mut self, stm_size: usize, nstm_size: usize) -> Self {
+        assert!(self.nodes.is_empty());
+        assert!(!self.single_perspective, "An asymmetric feature transformer needs both perspectives!");
+        assert!(0 < nstm_size && nstm_size <= stm_size, "Invalid asymmetric sizes {stm_size} and {nstm_size}!");
+        self.ft_out_size = stm_size;
+        self.ft_opp_size = Some(nstm_size);
+        self
+    }
+
+    /// Preset for a tiny secondary net, a `hidden` neuron feature transformer
+    /// followed directly by the output layer, which engines can use to cheaply
+    /// evaluate positions for pruning before (or instead of) the main net. Train
+    /// it alongside the main net with `Trainer::run_with_mini`, or separately,
+    /// and export both together with `Trainer::save_quantised_combined`.
+    pub fn mini_net(self, hidden: usize) -> Self {
+        assert!(self.nodes.is_empty(), "A mini net can't have any other layers!");
+        self.feature_transformer(hidden).activate(Activation::CReLU).add_layer(1)
+    }
+
     fn add(mut self, size: usize, op: OpType) -> Self {
-        self.nodes.push(NodeType { size, op, in_res_block: self.in_res_block });
+        self.nodes.push(NodeType { size, op, in_res_block: self.in_res_block, lr_mult: 1.0, recompute: false });
 
         self
     }
@@ -93,11 +325,344 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
         self.add(size, OpType::Affine)
     }
 
+    /// Applies the weights of an earlier layer added with `add_layer`, numbered
+    /// as in `concat`, to the outputs of the previous layer, e.g. to run the same
+    /// layer on two branches. Gradients from every use are summed. In quantised
+    /// nets the inputs must be on the same scale as those of the earlier layer.
+    pub fn add_shared_layer(self, layer: usize) -> Self {
+        assert!(layer > 0, "Can't share the weights of the feature transformer!");
+
+        let source = self.nodes.get(layer - 1).unwrap_or_else(|| panic!("Layer {layer} doesn't exist!"));
+        assert!(matches!(source.op, OpType::Affine), "Layer {layer} isn't an affine layer!");
+
+        let source_inputs = match layer {
+            1 => self.ft_outputs(),
+            _ => self.nodes[layer - 2].size,
+        };
+
+        let inputs = self.get_last_layer_size();
+        assert_eq!(inputs, source_inputs, "Layer {layer} takes {source_inputs} inputs, not {inputs}!");
+
+        let size = source.size;
+        self.add(size, OpType::SharedAffine { source: layer })
+    }
+
+    /// Splits the outputs of the previous layer into `groups` equal chunks and
+    /// applies a separate affine layer with `size` outputs to each, giving
+    /// `groups * size` outputs, e.g. for per-head weights. Unlike `add_layer`,
+    /// this is not bucketed.
+    pub fn add_grouped_layer(mut self, groups: usize, size: usize) -> Self {
+        let inputs = self.get_last_layer_size();
+        assert!(groups > 0 && inputs.is_multiple_of(groups), "Cannot split {inputs} outputs into {groups} groups!");
+        assert!(!self.in_res_block || groups * size == inputs, "Cannot change size in a residual block!");
+
+        self.size += (inputs + groups) * size;
+        self.add(groups * size, OpType::GroupedAffine { groups })
+    }
+
+    /// Scales the learning rate of the most recently added layer, or
+    /// of the feature transformer if no layers have been added yet.
+    pub fn lr_multiplier(mut self, mult: f32) -> Self {
+        assert!(mult >= 0.0, "Invalid learning rate multiplier {mult}!");
+
+        let is_affine = |node: &&mut NodeType| {
+            matches!(
+                node.op,
+                OpType::Affine | OpType::Attention(_) | OpType::Convolution(_) | OpType::GroupedAffine { .. }
+            )
+        };
+        if let Some(node) = self.nodes.iter_mut().rev().find(is_affine) {
+            node.lr_mult = mult;
+        } else {
+            self.ft_lr_mult = mult;
+        }
+
+        self
+    }
+
+    /// Doesn't keep the outputs of the most recently added layer after the
+    /// forward pass, instead sharing their memory with every other recomputable
+    /// layer and recomputing them from the nearest kept outputs when backprop
+    /// needs them, trading extra compute for memory on deep or wide networks.
+    /// Layers read by `concat` or `multiply`, batch norms, the output layer,
+    /// and layers in or entering residual blocks can't be recomputed.
+    pub fn recompute(mut self) -> Self {
+        let node = self.nodes.last_mut().expect("Can't recompute the feature transformer!");
+        node.recompute = true;
+        self
+    }
+
     pub fn activate(self, activation: Activation) -> Self {
         let size = self.get_last_layer_size();
         self.add(size, OpType::Activate(activation))
     }
 
+    /// Clips each output of the previous layer to `[min, max]`, e.g. to keep
+    /// values mid-network within range of their quantisation. Unlike the
+    /// bounded activations, it is never fused with neighbouring activations,
+    /// and its gradient can pass straight through clipped outputs.
+    pub fn clamp(self, min: f32, max: f32, gradient: ClampGradient) -> Self {
+        assert!(min < max, "Invalid clamp bounds [{min}, {max}]!");
+        let size = self.get_last_layer_size();
+        self.add(size, OpType::Clamp { min, max, gradient })
+    }
+
+    /// Elementwise minimum of each output of the previous layer and `value`.
+    pub fn min(self, value: f32, gradient: ClampGradient) -> Self {
+        self.clamp(f32::NEG_INFINITY, value, gradient)
+    }
+
+    /// Elementwise maximum of each output of the previous layer and `value`.
+    pub fn max(self, value: f32, gradient: ClampGradient) -> Self {
+        self.clamp(value, f32::INFINITY, gradient)
+    }
+
+    /// Parametric ReLU with learned negative slopes, one per
+    /// neuron if `per_channel`, otherwise one for the whole layer.
+    /// The slopes are quantised by the first quantisation factor.
+    pub fn prelu(mut self, per_channel: bool) -> Self {
+        let size = self.get_last_layer_size();
+        let channels = if per_channel { size } else { 1 };
+        self.size += channels;
+        self.add(size, OpType::PReLU { channels })
+    }
+
+    /// Concatenates the output of the previous layer with the outputs of the
+    /// given earlier layers, in order, e.g. to feed the feature transformer
+    /// outputs to a later layer as well. Layers are numbered in the order they
+    /// were added, starting from 1, including activations, with 0 being the
+    /// feature transformer. Each output keeps its own quantisation, so engines
+    /// must bring them to a common scale. Not supported in residual blocks.
+    pub fn concat(self, layers: &[usize]) -> Self {
+        assert!(!self.in_res_block, "Can't concatenate in a residual block!");
+        assert!(!layers.is_empty(), "Nothing to concatenate!");
+
+        let mut size = self.get_last_layer_size();
+        for &layer in layers {
+            size += match layer {
+                0 => self.ft_outputs(),
+                _ => {
+                    let node = self.nodes.get(layer - 1).unwrap_or_else(|| panic!("Layer {layer} doesn't exist!"));
+                    assert!(!node.in_res_block, "Can't concatenate layer {layer} from a residual block!");
+                    node.size
+                }
+            };
+        }
+
+        self.add(size, OpType::Concat { sources: layers.to_vec() })
+    }
+
+    /// Multiplies the output of the previous layer element-wise by the output
+    /// of an earlier layer, numbered as in `concat`, e.g. for gating. If the
+    /// earlier output is smaller, the previous output is viewed as a matrix
+    /// with that many rows, each of which is multiplied by the same value.
+    pub fn multiply(self, layer: usize) -> Self {
+        assert!(!self.in_res_block, "Can't multiply in a residual block!");
+
+        let size = self.get_last_layer_size();
+        let source_size = match layer {
+            0 => self.ft_outputs(),
+            _ => {
+                let node = self.nodes.get(layer - 1).unwrap_or_else(|| panic!("Layer {layer} doesn't exist!"));
+                assert!(!node.in_res_block, "Can't multiply by layer {layer} from a residual block!");
+                node.size
+            }
+        };
+
+        assert_eq!(size % source_size, 0, "Can't broadcast {source_size} outputs across {size}!");
+        self.add(size, OpType::Multiply { source: layer })
+    }
+
+    /// Multiplies each of the first `stride` outputs of every consecutive run of
+    /// `2 * stride` outputs of the previous layer with the one `stride` after it,
+    /// halving the number of outputs, after applying `activation` to both, which
+    /// must clip its input, e.g. CReLU. With `stride` as half the feature
+    /// transformer size, this pairs up the outputs of each perspective.
+    pub fn pairwise_mul(self, stride: usize, activation: Option<Activation>) -> Self {
+        assert!(!self.in_res_block, "Cannot change size in a residual block!");
+
+        if let Some(activation) = activation {
+            assert!(simplify::clamp_bounds(activation).is_some(), "Can't fuse {activation:?} with pairwise multiply!");
+        }
+
+        let size = self.get_last_layer_size();
+        assert!(stride > 0 && size.is_multiple_of(2 * stride), "Can't pair {size} outputs with stride {stride}!");
+
+        self.add(size / 2, OpType::PairwiseMul { stride, activation })
+    }
+
+    /// Zeroes each output of the previous layer with probability `rate`
+    /// while training, scaling the rest to keep their expected value the
+    /// same. Has no effect when evaluating, and isn't part of the saved net.
+    pub fn dropout(self, rate: f32) -> Self {
+        assert!((0.0..1.0).contains(&rate), "Invalid dropout rate {rate}!");
+        let size = self.get_last_layer_size();
+        self.add(size, OpType::Dropout { rate })
+    }
+
+    /// Normalises each output of the previous affine layer by its mean and
+    /// variance over the batch, followed by a learned scale and shift. When
+    /// evaluating, running statistics are used instead, and it is folded into
+    /// the affine layer when quantising, so engines never see it.
+    pub fn batch_norm(mut self) -> Self {
+        let last = self.nodes.last();
+        assert!(
+            last.is_some_and(|node| matches!(node.op, OpType::Affine) && node.in_res_block == self.in_res_block),
+            "BatchNorm must directly follow an affine layer, to be folded into it!"
+        );
+
+        let size = self.get_last_layer_size();
+        self.size += 4 * size;
+        self.add(size, OpType::BatchNorm)
+    }
+
+    /// Normalises the outputs of the previous layer to zero mean and unit
+    /// variance, followed by a learned scale and shift for each output.
+    /// The scale and shift are quantised by the first quantisation factor.
+    pub fn layer_norm(mut self) -> Self {
+        let size = self.get_last_layer_size();
+        self.size += 2 * size;
+        self.add(size, OpType::LayerNorm)
+    }
+
+    /// Softmax over the outputs of the previous layer, e.g. for a policy
+    /// head or a distribution over game results.
+    pub fn softmax(self) -> Self {
+        let size = self.get_last_layer_size();
+        self.add(size, OpType::Softmax { log: false })
+    }
+
+    /// As `softmax`, but outputs log-probabilities, which is more numerically
+    /// stable than taking the logarithm of the softmax afterwards.
+    pub fn log_softmax(self) -> Self {
+        let size = self.get_last_layer_size();
+        self.add(size, OpType::Softmax { log: true })
+    }
+
+    /// Views the outputs of the previous layer as a matrix with `cols` columns,
+    /// each stored contiguously, and reduces it along `axis`, giving one output
+    /// per column for `Axis::Rows` and one per row for `Axis::Cols`.
+    pub fn reduce(self, reduction: Reduction, axis: Axis, cols: usize) -> Self {
+        assert!(!self.in_res_block, "Cannot change size in a residual block!");
+
+        let size = self.get_last_layer_size();
+        assert!(cols > 0 && size.is_multiple_of(cols), "Cannot view {size} outputs as a matrix with {cols} columns!");
+
+        let out_size = match axis {
+            Axis::Rows => cols,
+            Axis::Cols => size / cols,
+        };
+
+        self.add(out_size, OpType::Reduce { reduction, axis, cols })
+    }
+
+    /// Maximum over each of `channels` equal, contiguous chunks of the outputs
+    /// of the previous layer, e.g. over the squares of each channel of a
+    /// convolution. With a single channel, this pools every output.
+    pub fn global_max_pool(self, channels: usize) -> Self {
+        self.reduce(Reduction::Max, Axis::Rows, channels)
+    }
+
+    /// As `global_max_pool`, but averages each chunk.
+    pub fn global_avg_pool(self, channels: usize) -> Self {
+        self.reduce(Reduction::Mean, Axis::Rows, channels)
+    }
+
+    /// Outputs `start..start + len` of the previous layer, with the gradients
+    /// of the rest being zero. Together with `concat` this allows e.g. applying
+    /// different activations to each half of a layer.
+    pub fn slice(self, start: usize, len: usize) -> Self {
+        assert!(!self.in_res_block, "Cannot change size in a residual block!");
+
+        let size = self.get_last_layer_size();
+        assert!(len > 0 && start + len <= size, "Slice {start}..{} out of range of {size} outputs!", start + len);
+
+        self.add(len, OpType::Slice { start })
+    }
+
+    /// Number of the most recently added layer, as in `concat`, to refer back
+    /// to it later, e.g. with `branch`.
+    pub fn last_layer(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Continues from the outputs of an earlier layer, numbered as in `concat`,
+    /// e.g. to start a second head from a shared hidden layer.
+    pub fn branch(self, layer: usize) -> Self {
+        let size = self.get_last_layer_size();
+        let branch_size = match layer {
+            0 => self.ft_outputs(),
+            _ => self.nodes.get(layer - 1).unwrap_or_else(|| panic!("Layer {layer} doesn't exist!")).size,
+        };
+
+        self.concat(&[layer]).slice(size, branch_size)
+    }
+
+    /// Concatenates the outputs of the given layers, numbered as in `concat`,
+    /// as the outputs of the net, one head per layer for `Loss::MultiHead`.
+    /// Only the first head is used by `eval`.
+    pub fn output_heads(self, layers: &[usize]) -> Self {
+        let (first, rest) = layers.split_first().expect("No output heads!");
+        let builder = self.branch(*first);
+
+        if rest.is_empty() {
+            builder
+        } else {
+            builder.concat(rest)
+        }
+    }
+
+    /// Views the outputs of the previous layer as one branch per output bucket,
+    /// and outputs only the branch of each position's bucket, so gradients only
+    /// reach the chosen branch. This makes layer stacks explicit, e.g. a shared
+    /// `add_grouped_layer(1, buckets * size)` followed by further grouped layers
+    /// with one group per bucket, and then `select`.
+    pub fn select(self) -> Self {
+        assert!(!self.in_res_block, "Cannot change size in a residual block!");
+
+        let size = self.get_last_layer_size();
+        assert_eq!(size % U::BUCKETS, 0, "Cannot split {size} outputs into {} buckets!", U::BUCKETS);
+
+        self.add(size / U::BUCKETS, OpType::Select)
+    }
+
+    /// Replaces the outputs of the previous layer with a learned embedding of
+    /// `size` outputs for the output bucket of each position, e.g. to be
+    /// concatenated with earlier layers, or, alone in a residual block, added to
+    /// the previous outputs as per-bucket biases. The embeddings start at zero
+    /// and are quantised by the first quantisation factor.
+    pub fn gather(mut self, size: usize) -> Self {
+        assert!(!self.in_res_block || size == self.get_last_layer_size(), "Cannot change size in a residual block!");
+
+        self.size += size * U::BUCKETS;
+        self.add(size, OpType::Gather)
+    }
+
+    /// 2D convolution described by `desc`, viewing the outputs of the previous
+    /// layer as `desc.input_channels` contiguous, row-major images, and giving
+    /// `desc.output_channels` images in the same layout. There is one bias per
+    /// output channel, and it is quantised like an affine layer.
+    pub fn convolution(mut self, desc: ConvolutionDescription) -> Self {
+        let size = self.get_last_layer_size();
+        assert_eq!(size, desc.input_size(), "Convolution expects {} inputs, not {size}!", desc.input_size());
+        assert!(!self.in_res_block || desc.output_size() == size, "Cannot change size in a residual block!");
+
+        self.size += desc.num_weights() + desc.output_channels;
+        self.add(desc.output_size(), OpType::Convolution(desc))
+    }
+
+    /// Multi-head self-attention described by `desc`, viewing the outputs of the
+    /// previous layer as `desc.len` tokens of `desc.dim` contiguous features. The
+    /// query, key and value projections are quantised like an affine layer.
+    pub fn self_attention(mut self, desc: AttentionDescription) -> Self {
+        let size = self.get_last_layer_size();
+        assert_eq!(size, desc.input_size(), "Attention expects {} inputs, not {size}!", desc.input_size());
+        assert!(!self.in_res_block || desc.output_size() == size, "Cannot change size in a residual block!");
+
+        self.size += desc.num_weights() + desc.num_biases();
+        self.add(desc.output_size(), OpType::Attention(desc))
+    }
+
     pub fn start_residual_block(mut self) -> Self {
         assert!(!self.in_res_block, "Already in residual block!");
         self.in_res_block = true;
@@ -110,7 +675,338 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
         self
     }
 
-    pub fn build(self) -> Trainer<T, U> {
+    /// Rebuilds the network saved by `Trainer::save_net_file` and loads its
+    /// weights, without needing the builder calls that originally made it.
+    /// Training settings such as the optimiser are not saved, so they can be
+    /// set on this builder beforehand, but it must not have any layers yet.
+    /// Returns an error if the file can't be read, or isn't a valid net file
+    /// for these inputs and output buckets.
+    pub fn load_net_file(self, path: &str) -> Result<Trainer<T, U>, String> {
+        assert!(self.nodes.is_empty() && self.ft_out_size == 0, "Net file must be loaded into an empty builder!");
+
+        let bytes = std::fs::read(path).map_err(|err| format!("Reading [{path}] failed: {err}"))?;
+        let mut builder = self;
+        let mut rest = bytes.as_slice();
+
+        let mut next_line = || {
+            let end = rest.iter().position(|&byte| byte == b'\n').ok_or("Net file has no weights!")?;
+            let line = std::str::from_utf8(&rest[..end]).map_err(|_| "Invalid net file header!");
+            rest = &rest[end + 1..];
+            line
+        };
+
+        match next_line()? {
+            NET_FILE_HEADER => {}
+            line if line.starts_with("bullet-net") => return Err(format!("Unsupported net file version [{line}]!")),
+            _ => return Err(String::from("Not a net file!")),
+        }
+
+        let num = loop {
+            let line = next_line()?;
+
+            if let Some(num) = line.strip_prefix("weights ") {
+                break num.parse::<usize>().map_err(|_| format!("Invalid number of weights [{num}]!"))?;
+            }
+
+            builder = builder.replay(line)?;
+        };
+
+        if builder.ft_out_size == 0 {
+            return Err(String::from("Net file has no feature transformer!"));
+        }
+
+        if rest.len() != 4 * num {
+            return Err(String::from("Net file has the wrong number of weights!"));
+        }
+
+        let weights: Vec<f32> =
+            rest.chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect();
+
+        let trainer = builder.build();
+        if trainer.net_size() != weights.len() {
+            return Err(String::from("Net file has the wrong number of weights!"));
+        }
+
+        trainer.optimiser.load_weights_from_host(&weights);
+
+        Ok(trainer)
+    }
+
+    /// Describes the network as one builder call per line, with its arguments
+    /// separated by spaces, which `replay` turns back into the same network.
+    fn describe(&self) -> String {
+        let mut lines = vec![
+            // type names are only for reference, and may contain spaces
+            format!("input {} {}", self.input_getter.size(), std::any::type_name::<T>()),
+            format!("output_buckets {} {}", U::BUCKETS, std::any::type_name::<U>()),
+        ];
+
+        if self.single_perspective {
+            lines.push(String::from("single_perspective"));
+        }
+
+        lines.push(match self.ft_opp_size {
+            Some(nstm_size) => format!("asymmetric_feature_transformer {} {nstm_size}", self.ft_out_size),
+            None => format!("feature_transformer {}", self.ft_out_size),
+        });
+
+        if !self.quantisations.is_empty() {
+            let quants: Vec<_> = self.quantisations.iter().map(i32::to_string).collect();
+            lines.push(format!("quantisations {}", quants.join(" ")));
+        }
+
+        if self.per_row_quantisation {
+            lines.push(String::from("per_row_quantisation"));
+        }
+
+        match self.output_transform {
+            Some(OutputTransform::Centipawns { eval_scale }) => {
+                lines.push(format!("output_transform Centipawns {eval_scale}"));
+            }
+            Some(OutputTransform::EngineUnits { eval_scale, units_per_pawn }) => {
+                lines.push(format!("output_transform EngineUnits {eval_scale} {units_per_pawn}"));
+            }
+            None => {}
+        }
+
+        let mut in_res_block = false;
+        let mut inputs = self.ft_outputs();
+
+        for NodeType { size, op, in_res_block: node_in_res_block, .. } in &self.nodes {
+            if *node_in_res_block != in_res_block {
+                in_res_block = *node_in_res_block;
+                lines.push(String::from(if in_res_block { "start_residual_block" } else { "end_residual_block" }));
+            }
+
+            lines.push(match op {
+                OpType::Activate(activation) => format!("activate {}", describe_activation(*activation)),
+                OpType::Affine => format!("add_layer {size}"),
+                OpType::Attention(desc) => {
+                    format!("self_attention {} {} {} {}", desc.len, desc.dim, desc.heads, desc.head_dim)
+                }
+                OpType::BatchNorm => String::from("batch_norm"),
+                OpType::Clamp { min, max, gradient } => format!("clamp {min} {max} {gradient:?}"),
+                OpType::Concat { sources } => {
+                    let sources: Vec<_> = sources.iter().map(usize::to_string).collect();
+                    format!("concat {}", sources.join(" "))
+                }
+                OpType::Convolution(desc) => format!(
+                    "convolution {} {} {} {} {} {} {} {} {} {} {} {} {}",
+                    desc.input_shape.0,
+                    desc.input_shape.1,
+                    desc.input_channels,
+                    desc.output_channels,
+                    desc.kernel.0,
+                    desc.kernel.1,
+                    desc.stride.0,
+                    desc.stride.1,
+                    desc.padding.0,
+                    desc.padding.1,
+                    desc.dilation.0,
+                    desc.dilation.1,
+                    desc.groups,
+                ),
+                OpType::Dropout { rate } => format!("dropout {rate}"),
+                OpType::Gather => format!("gather {size}"),
+                OpType::GroupedAffine { groups } => format!("add_grouped_layer {groups} {}", size / groups),
+                OpType::LayerNorm => String::from("layer_norm"),
+                OpType::Multiply { source } => format!("multiply {source}"),
+                OpType::PairwiseMul { stride, activation: Some(activation) } => {
+                    format!("pairwise_mul {stride} {}", describe_activation(*activation))
+                }
+                OpType::PairwiseMul { stride, activation: None } => format!("pairwise_mul {stride}"),
+                OpType::PReLU { channels } => format!("prelu {}", *channels == inputs),
+                OpType::Reduce { reduction, axis, cols } => format!("reduce {reduction:?} {axis:?} {cols}"),
+                OpType::Select => String::from("select"),
+                OpType::SharedAffine { source } => format!("add_shared_layer {source}"),
+                OpType::Slice { start } => format!("slice {start} {size}"),
+                OpType::Softmax { log: false } => String::from("softmax"),
+                OpType::Softmax { log: true } => String::from("log_softmax"),
+            });
+
+            inputs = *size;
+        }
+
+        if in_res_block {
+            lines.push(String::from("end_residual_block"));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Applies a single line written by `describe`.
+    fn replay(self, line: &str) -> Result<Self, String> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let arg = |i: usize| tokens.get(i).copied().ok_or_else(|| format!("Missing argument {i} in [{line}]!"));
+        let invalid = |i: usize| format!("Invalid argument {i} in [{line}]!");
+        let num = |i: usize| arg(i)?.parse::<usize>().map_err(|_| invalid(i));
+        let float = |i: usize| arg(i)?.parse::<f32>().map_err(|_| invalid(i));
+
+        let builder = match arg(0)? {
+            "input" => {
+                let name = tokens[2..].join(" ");
+                if num(1)? != self.input_getter.size() {
+                    return Err(format!("Net file was saved with input {name}!"));
+                }
+                self
+            }
+            "output_buckets" => {
+                let name = tokens[2..].join(" ");
+                if num(1)? != U::BUCKETS {
+                    return Err(format!("Net file was saved with output buckets {name}!"));
+                }
+                self
+            }
+            "single_perspective" => self.single_perspective(),
+            "feature_transformer" => self.feature_transformer(num(1)?),
+            "asymmetric_feature_transformer" => self.asymmetric_feature_transformer(num(1)?, num(2)?),
+            "quantisations" => {
+                let quants = tokens[1..].iter().map(|q| q.parse().map_err(|_| format!("Invalid [{line}]!")));
+                self.quantisations(&quants.collect::<Result<Vec<i32>, _>>()?)
+            }
+            "per_row_quantisation" => self.per_row_quantisation(),
+            "output_transform" => {
+                let transform = match arg(1)? {
+                    "Centipawns" => OutputTransform::Centipawns { eval_scale: float(2)? },
+                    "EngineUnits" => OutputTransform::EngineUnits { eval_scale: float(2)?, units_per_pawn: float(3)? },
+                    other => return Err(format!("Unknown output transform [{other}]!")),
+                };
+                self.output_transform(transform)
+            }
+            "start_residual_block" => self.start_residual_block(),
+            "end_residual_block" => self.end_residual_block(),
+            "activate" => self.activate(parse_activation(&tokens[1..])?),
+            "add_layer" => self.add_layer(num(1)?),
+            "add_grouped_layer" => self.add_grouped_layer(num(1)?, num(2)?),
+            "self_attention" => self.self_attention(AttentionDescription::new(num(1)?, num(2)?, num(3)?, num(4)?)),
+            "batch_norm" => self.batch_norm(),
+            "clamp" => {
+                let gradient = match arg(3)? {
+                    "StraightThrough" => ClampGradient::StraightThrough,
+                    "Masked" => ClampGradient::Masked,
+                    other => return Err(format!("Unknown clamp gradient [{other}]!")),
+                };
+                self.clamp(float(1)?, float(2)?, gradient)
+            }
+            "concat" => {
+                let sources = (1..tokens.len()).map(num).collect::<Result<Vec<_>, _>>()?;
+                self.concat(&sources)
+            }
+            "convolution" => self.convolution(ConvolutionDescription {
+                input_shape: (num(1)?, num(2)?),
+                input_channels: num(3)?,
+                output_channels: num(4)?,
+                kernel: (num(5)?, num(6)?),
+                stride: (num(7)?, num(8)?),
+                padding: (num(9)?, num(10)?),
+                dilation: (num(11)?, num(12)?),
+                groups: num(13)?,
+            }),
+            "dropout" => self.dropout(float(1)?),
+            "gather" => self.gather(num(1)?),
+            "layer_norm" => self.layer_norm(),
+            "multiply" => self.multiply(num(1)?),
+            "pairwise_mul" => {
+                let activation = if tokens.len() > 2 { Some(parse_activation(&tokens[2..])?) } else { None };
+                self.pairwise_mul(num(1)?, activation)
+            }
+            "prelu" => self.prelu(arg(1)? == "true"),
+            "reduce" => {
+                let reduction = match arg(1)? {
+                    "Sum" => Reduction::Sum,
+                    "Mean" => Reduction::Mean,
+                    "Max" => Reduction::Max,
+                    other => return Err(format!("Unknown reduction [{other}]!")),
+                };
+                let axis = match arg(2)? {
+                    "Rows" => Axis::Rows,
+                    "Cols" => Axis::Cols,
+                    other => return Err(format!("Unknown axis [{other}]!")),
+                };
+                self.reduce(reduction, axis, num(3)?)
+            }
+            "select" => self.select(),
+            "add_shared_layer" => self.add_shared_layer(num(1)?),
+            "slice" => self.slice(num(1)?, num(2)?),
+            "softmax" => self.softmax(),
+            "log_softmax" => self.log_softmax(),
+            other => return Err(format!("Unknown net file entry [{other}]!")),
+        };
+
+        Ok(builder)
+    }
+
+    /// Builds a pure CPU copy of the network, for evaluation only.
+    pub fn build_inference(mut self) -> InferenceNet<T, U> {
+        self.simplify();
+
+        let mut inp_size = self.ft_outputs();
+        let mut layers = Vec::new();
+        // index of the output of each node in `layers`
+        let mut outputs = vec![0];
+
+        for NodeType { size, op, in_res_block, .. } in &self.nodes {
+            match op {
+                OpType::Affine => {
+                    layers.push((Layer::Affine { inputs: inp_size, outputs: size * U::BUCKETS }, *in_res_block));
+
+                    if U::BUCKETS > 1 {
+                        layers.push((Layer::Select { size: *size }, *in_res_block));
+                    }
+                }
+                OpType::Activate(activation) => layers.push((Layer::Activate(*activation), *in_res_block)),
+                OpType::Attention(desc) => layers.push((Layer::Attention { desc: *desc }, *in_res_block)),
+                OpType::BatchNorm => layers.push((Layer::BatchNorm { size: *size }, *in_res_block)),
+                OpType::Clamp { min, max, .. } => {
+                    let activation = Activation::BoundedCReLU { min: *min, max: *max };
+                    layers.push((Layer::Activate(activation), *in_res_block));
+                }
+                OpType::Concat { sources } => {
+                    let sources = sources.iter().map(|&source| outputs[source]).collect();
+                    layers.push((Layer::Concat { sources }, *in_res_block));
+                }
+                OpType::Convolution(desc) => layers.push((Layer::Convolution { desc: *desc }, *in_res_block)),
+                OpType::Dropout { .. } => {}
+                OpType::Gather => layers.push((Layer::Gather { size: *size }, *in_res_block)),
+                OpType::GroupedAffine { groups } => {
+                    let layer = Layer::GroupedAffine { groups: *groups, inputs: inp_size, outputs: *size };
+                    layers.push((layer, *in_res_block));
+                }
+                OpType::LayerNorm => layers.push((Layer::Norm { size: *size }, *in_res_block)),
+                OpType::PReLU { channels } => layers.push((Layer::PReLU { channels: *channels }, *in_res_block)),
+                OpType::Multiply { source } => {
+                    layers.push((Layer::Multiply { source: outputs[*source] }, *in_res_block));
+                }
+                OpType::PairwiseMul { stride, activation } => {
+                    layers.push((Layer::PairwiseMul { stride: *stride, activation: *activation }, *in_res_block));
+                }
+                OpType::Reduce { reduction, axis, cols } => {
+                    layers.push((Layer::Reduce { reduction: *reduction, axis: *axis, cols: *cols }, *in_res_block));
+                }
+                OpType::Select => layers.push((Layer::Select { size: *size }, *in_res_block)),
+                OpType::SharedAffine { source } => {
+                    layers.push((Layer::SharedAffine { source: outputs[*source - 1] }, *in_res_block));
+
+                    if U::BUCKETS > 1 {
+                        layers.push((Layer::Select { size: *size }, *in_res_block));
+                    }
+                }
+                OpType::Slice { start } => layers.push((Layer::Slice { start: *start, len: *size }, *in_res_block)),
+                OpType::Softmax { log } => layers.push((Layer::Softmax { log: *log }, *in_res_block)),
+            }
+
+            outputs.push(layers.len());
+            inp_size = *size;
+        }
+
+        let opp_size = self.ft_outputs() - self.ft_out_size;
+        InferenceNet::new(self.input_getter, self.bucket_getter, self.ft_out_size, opp_size, layers)
+    }
+
+    pub fn build(mut self) -> Trainer<T, U> {
+        self.simplify();
+        let description = self.describe();
+
         let inp_getter_size = self.input_getter.size();
         let max_active_inputs = self.input_getter.max_active_inputs();
 
@@ -119,14 +1015,20 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
         let ft_size = (inp_getter_size + 1) * self.ft_out_size;
         let net_size = self.size + ft_size;
 
-        let opt = Optimiser::new(net_size);
+        let mut opt = Optimiser::new(net_size, self.optimiser);
+        if let Some((steps, alpha)) = self.lookahead {
+            opt.set_lookahead(steps, alpha);
+        }
+        opt.set_gradient_centralisation(self.gradient_centralisation);
+        for &(kind, settings) in &self.param_settings {
+            opt.set_param_settings(kind, settings);
+        }
         let batch_size = 1;
-        let mul = if self.single_perspective { 1 } else { 2 };
 
         unsafe {
             let ftw_shape = Shape::new(self.ft_out_size, inp_getter_size);
             let ftb_shape = Shape::new(1, self.ft_out_size);
-            let fto_shape = Shape::new(1, mul * self.ft_out_size);
+            let fto_shape = Shape::new(1, self.ft_outputs());
 
             let mut ft = FeatureTransformer {
                 weights: Tensor::uninit(ftw_shape),
@@ -135,31 +1037,37 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
                 biases_grad: Tensor::uninit(ftb_shape),
                 single_perspective: self.single_perspective,
                 outputs: TensorBatch::new(fto_shape, batch_size),
-                copy: TensorBatch::new(fto_shape, batch_size),
+                copy: None,
             };
 
             let mut offset = 0;
             ft.weights.set_ptr(opt.weights_offset(offset));
             ft.weights_grad.set_ptr(opt.gradients_offset(offset));
+            opt.add_segment(offset, self.ft_out_size * inp_getter_size, self.ft_lr_mult, ParamKind::Weights);
+            opt.add_matrix(offset, inp_getter_size, self.ft_out_size);
             offset += self.ft_out_size * inp_getter_size;
 
             ft.biases.set_ptr(opt.weights_offset(offset));
             ft.biases_grad.set_ptr(opt.gradients_offset(offset));
+            opt.add_segment(offset, self.ft_out_size, self.ft_lr_mult, ParamKind::Biases);
             offset += self.ft_out_size;
 
-            let mut nodes = Vec::new();
-            let mut inp_size = mul * self.ft_out_size;
+            let mut nodes: Vec<Node> = Vec::new();
+            let mut recomputed = Vec::new();
+            let mut inp_size = self.ft_outputs();
+            // index of the output of each node in `nodes`
+            let mut outputs = vec![0];
 
             let mut quantiser = Vec::new();
             let mut qi = 0;
             let mut accq = 1;
             if !self.quantisations.is_empty() {
-                quantiser.push(QuantiseInfo { val: self.quantisations[qi], start: 0 });
+                quantiser.push(QuantiseInfo { val: self.quantisations[qi], start: 0, rows: None, factors: (0, 1) });
                 accq *= self.quantisations[qi];
                 qi += 1;
             }
 
-            for NodeType { size, op, in_res_block } in &self.nodes {
+            for NodeType { size, op, in_res_block, lr_mult, recompute } in &self.nodes {
                 let size = *size;
                 let in_res_block = *in_res_block;
 
@@ -183,9 +1091,13 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
                         affine.weights_grad.set_ptr(opt.gradients_offset(offset));
 
                         if !self.quantisations.is_empty() {
-                            quantiser.push(QuantiseInfo { val: self.quantisations[qi], start: offset });
+                            let val = self.quantisations[qi];
+                            let factors = (qi, qi + 1);
+                            quantiser.push(QuantiseInfo { val, start: offset, rows: Some(raw_size), factors });
                         }
 
+                        opt.add_segment(offset, inp_size * raw_size, *lr_mult, ParamKind::Weights);
+                        opt.add_matrix(offset, inp_size, raw_size);
                         offset += inp_size * raw_size;
 
                         affine.biases.set_ptr(opt.weights_offset(offset));
@@ -193,10 +1105,11 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
 
                         if !self.quantisations.is_empty() {
                             accq *= self.quantisations[qi];
-                            quantiser.push(QuantiseInfo { val: accq, start: offset });
+                            quantiser.push(QuantiseInfo { val: accq, start: offset, rows: None, factors: (0, qi + 1) });
                             qi += 1;
                         }
 
+                        opt.add_segment(offset, raw_size, *lr_mult, ParamKind::Biases);
                         offset += raw_size;
 
                         let outputs = TensorBatch::new(bsh, batch_size);
@@ -215,17 +1128,362 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
                         let outputs = TensorBatch::new(bsh, batch_size);
                         nodes.push(Node { outputs, op: Operation::Activate(*activation), in_res_block });
                     }
+                    OpType::Clamp { min, max, gradient } => {
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        let op = Operation::Clamp { min: *min, max: *max, gradient: *gradient };
+                        nodes.push(Node { outputs, op, in_res_block });
+                    }
+                    OpType::BatchNorm => {
+                        let sh = Shape::new(1, size);
+                        let mut norm = BatchNorm {
+                            gamma: Tensor::uninit(sh),
+                            beta: Tensor::uninit(sh),
+                            running_mean: Tensor::uninit(sh),
+                            running_var: Tensor::uninit(sh),
+                            gamma_grad: Tensor::uninit(sh),
+                            beta_grad: Tensor::uninit(sh),
+                            batch_mean: DeviceBuffer::new(size),
+                            batch_rstd: DeviceBuffer::new(size),
+                        };
+
+                        norm.gamma.set_ptr(opt.weights_offset(offset));
+                        norm.gamma_grad.set_ptr(opt.gradients_offset(offset));
+                        norm.beta.set_ptr(opt.weights_offset(offset + size));
+                        norm.beta_grad.set_ptr(opt.gradients_offset(offset + size));
+                        norm.running_mean.set_ptr(opt.weights_offset(offset + 2 * size));
+                        norm.running_var.set_ptr(opt.weights_offset(offset + 3 * size));
+
+                        // folded into the previous layer when quantising, so has no quantisation
+                        opt.add_segment(offset, size, 1.0, ParamKind::Weights);
+                        opt.add_segment(offset + size, size, 1.0, ParamKind::Biases);
+                        opt.add_segment(offset + 2 * size, 2 * size, 0.0, ParamKind::Weights);
+                        offset += 4 * size;
+
+                        let outputs = TensorBatch::new(sh, batch_size);
+                        nodes.push(Node { outputs, op: Operation::BatchNorm(norm), in_res_block });
+                    }
+                    OpType::LayerNorm => {
+                        let sh = Shape::new(1, size);
+                        let mut norm = LayerNorm {
+                            gamma: Tensor::uninit(sh),
+                            beta: Tensor::uninit(sh),
+                            gamma_grad: Tensor::uninit(sh),
+                            beta_grad: Tensor::uninit(sh),
+                        };
+
+                        norm.gamma.set_ptr(opt.weights_offset(offset));
+                        norm.gamma_grad.set_ptr(opt.gradients_offset(offset));
+                        norm.beta.set_ptr(opt.weights_offset(offset + size));
+                        norm.beta_grad.set_ptr(opt.gradients_offset(offset + size));
+
+                        if !self.quantisations.is_empty() {
+                            quantiser.push(QuantiseInfo {
+                                val: self.quantisations[0],
+                                start: offset,
+                                rows: None,
+                                factors: (0, 1),
+                            });
+                        }
+
+                        opt.add_segment(offset, size, 1.0, ParamKind::Weights);
+                        opt.add_segment(offset + size, size, 1.0, ParamKind::Biases);
+                        offset += 2 * size;
+
+                        let outputs = TensorBatch::new(sh, batch_size);
+                        nodes.push(Node { outputs, op: Operation::LayerNorm(norm), in_res_block });
+                    }
+                    OpType::PReLU { channels } => {
+                        let ssh = Shape::new(1, *channels);
+                        let mut prelu = PReLU { slopes: Tensor::uninit(ssh), slopes_grad: Tensor::uninit(ssh) };
+
+                        prelu.slopes.set_ptr(opt.weights_offset(offset));
+                        prelu.slopes_grad.set_ptr(opt.gradients_offset(offset));
+
+                        if !self.quantisations.is_empty() {
+                            quantiser.push(QuantiseInfo {
+                                val: self.quantisations[0],
+                                start: offset,
+                                rows: None,
+                                factors: (0, 1),
+                            });
+                        }
+
+                        opt.add_segment(offset, *channels, 1.0, ParamKind::Weights);
+                        offset += channels;
+
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::PReLU(prelu), in_res_block });
+                    }
+                    OpType::Dropout { rate } => {
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+
+                        // seeded by position, so that runs are reproducible
+                        let dropout = Dropout {
+                            rate: *rate,
+                            mask: DeviceBuffer::new(outputs.num_elements()),
+                            rng: StdRng::seed_from_u64(nodes.len() as u64),
+                        };
+
+                        nodes.push(Node { outputs, op: Operation::Dropout(dropout), in_res_block });
+                    }
+                    OpType::GroupedAffine { groups } => {
+                        let groups = *groups;
+                        let (group_inputs, group_outputs) = (inp_size / groups, size / groups);
+                        let wsh = Shape::new(inp_size, group_outputs);
+                        let bsh = Shape::new(1, size);
+
+                        let ones = DeviceBuffer::new(1);
+                        ones.load_from_host(&[1.0]);
+                        let mut affine = Affine {
+                            weights: Tensor::uninit(wsh),
+                            biases: Tensor::uninit(bsh),
+                            weights_grad: Tensor::uninit(wsh),
+                            biases_grad: Tensor::uninit(bsh),
+                            ones,
+                        };
+
+                        affine.weights.set_ptr(opt.weights_offset(offset));
+                        affine.weights_grad.set_ptr(opt.gradients_offset(offset));
+
+                        // the matrices of different groups have different rows,
+                        // so can't share per-row quantisation
+                        if !self.quantisations.is_empty() {
+                            let val = self.quantisations[qi];
+                            quantiser.push(QuantiseInfo { val, start: offset, rows: None, factors: (qi, qi + 1) });
+                        }
+
+                        let group_size = group_inputs * group_outputs;
+                        opt.add_segment(offset, groups * group_size, *lr_mult, ParamKind::Weights);
+                        for group in 0..groups {
+                            opt.add_matrix(offset + group * group_size, group_inputs, group_outputs);
+                        }
+                        offset += groups * group_size;
+
+                        affine.biases.set_ptr(opt.weights_offset(offset));
+                        affine.biases_grad.set_ptr(opt.gradients_offset(offset));
+
+                        if !self.quantisations.is_empty() {
+                            accq *= self.quantisations[qi];
+                            quantiser.push(QuantiseInfo { val: accq, start: offset, rows: None, factors: (0, qi + 1) });
+                            qi += 1;
+                        }
+
+                        opt.add_segment(offset, size, *lr_mult, ParamKind::Biases);
+                        offset += size;
+
+                        let outputs = TensorBatch::new(bsh, batch_size);
+                        nodes.push(Node { outputs, op: Operation::GroupedAffine { groups, affine }, in_res_block });
+                    }
+                    OpType::Attention(desc) => {
+                        let (num_weights, num_biases) = (desc.num_weights(), desc.num_biases());
+                        let wsh = Shape::new(1, num_weights);
+                        let bsh = Shape::new(1, num_biases);
+
+                        let mut attention = Attention {
+                            desc: *desc,
+                            weights: Tensor::uninit(wsh),
+                            biases: Tensor::uninit(bsh),
+                            weights_grad: Tensor::uninit(wsh),
+                            biases_grad: Tensor::uninit(bsh),
+                            qkv: DeviceBuffer::new(batch_size * desc.len * num_biases),
+                            attn: DeviceBuffer::new(batch_size * desc.heads * desc.len * desc.len),
+                            qkv_grad: DeviceBuffer::new(batch_size * desc.len * num_biases),
+                            attn_grad: DeviceBuffer::new(batch_size * desc.heads * desc.len * desc.len),
+                        };
+
+                        attention.weights.set_ptr(opt.weights_offset(offset));
+                        attention.weights_grad.set_ptr(opt.gradients_offset(offset));
+
+                        if !self.quantisations.is_empty() {
+                            let val = self.quantisations[qi];
+                            quantiser.push(QuantiseInfo { val, start: offset, rows: None, factors: (qi, qi + 1) });
+                        }
+
+                        // stored one output at a time, so not marked as a matrix for gradient centralisation
+                        opt.add_segment(offset, num_weights, *lr_mult, ParamKind::Weights);
+                        offset += num_weights;
+
+                        attention.biases.set_ptr(opt.weights_offset(offset));
+                        attention.biases_grad.set_ptr(opt.gradients_offset(offset));
+
+                        if !self.quantisations.is_empty() {
+                            accq *= self.quantisations[qi];
+                            quantiser.push(QuantiseInfo { val: accq, start: offset, rows: None, factors: (0, qi + 1) });
+                            qi += 1;
+                        }
+
+                        opt.add_segment(offset, num_biases, *lr_mult, ParamKind::Biases);
+                        offset += num_biases;
+
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::Attention(attention), in_res_block });
+                    }
+                    OpType::Convolution(desc) => {
+                        let (num_weights, channels) = (desc.num_weights(), desc.output_channels);
+                        let wsh = Shape::new(1, num_weights);
+                        let bsh = Shape::new(1, channels);
+
+                        let mut conv = Convolution {
+                            desc: *desc,
+                            weights: Tensor::uninit(wsh),
+                            biases: Tensor::uninit(bsh),
+                            weights_grad: Tensor::uninit(wsh),
+                            biases_grad: Tensor::uninit(bsh),
+                        };
+
+                        conv.weights.set_ptr(opt.weights_offset(offset));
+                        conv.weights_grad.set_ptr(opt.gradients_offset(offset));
+
+                        if !self.quantisations.is_empty() {
+                            let val = self.quantisations[qi];
+                            quantiser.push(QuantiseInfo { val, start: offset, rows: None, factors: (qi, qi + 1) });
+                        }
+
+                        // stored by output channel, so not marked as a matrix for gradient centralisation
+                        opt.add_segment(offset, num_weights, *lr_mult, ParamKind::Weights);
+                        offset += num_weights;
+
+                        conv.biases.set_ptr(opt.weights_offset(offset));
+                        conv.biases_grad.set_ptr(opt.gradients_offset(offset));
+
+                        if !self.quantisations.is_empty() {
+                            accq *= self.quantisations[qi];
+                            quantiser.push(QuantiseInfo { val: accq, start: offset, rows: None, factors: (0, qi + 1) });
+                            qi += 1;
+                        }
+
+                        opt.add_segment(offset, channels, *lr_mult, ParamKind::Biases);
+                        offset += channels;
+
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::Convolution(conv), in_res_block });
+                    }
+                    OpType::Gather => {
+                        let wsh = Shape::new(buckets, size);
+                        let mut gather = Gather { weights: Tensor::uninit(wsh), weights_grad: Tensor::uninit(wsh) };
+
+                        gather.weights.set_ptr(opt.weights_offset(offset));
+                        gather.weights_grad.set_ptr(opt.gradients_offset(offset));
+
+                        if !self.quantisations.is_empty() {
+                            quantiser.push(QuantiseInfo {
+                                val: self.quantisations[0],
+                                start: offset,
+                                rows: None,
+                                factors: (0, 1),
+                            });
+                        }
+
+                        // an output vector per bucket, so optimised like a bias
+                        opt.add_segment(offset, size * buckets, *lr_mult, ParamKind::Biases);
+                        offset += size * buckets;
+
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::Gather(gather), in_res_block });
+                    }
+                    OpType::Concat { sources } => {
+                        let sources: Vec<usize> = sources.iter().map(|&source| outputs[source]).collect();
+                        let grads = sources
+                            .iter()
+                            .map(|&source| match source {
+                                0 => fto_shape,
+                                _ => nodes[source - 1].outputs.shape(),
+                            })
+                            .map(|shape| TensorBatch::new(shape, batch_size))
+                            .collect();
+
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::Concat(Concat { sources, grads }), in_res_block });
+                    }
+                    OpType::Multiply { source } => {
+                        let source = outputs[*source];
+                        let shape = match source {
+                            0 => fto_shape,
+                            _ => nodes[source - 1].outputs.shape(),
+                        };
+
+                        let grad = TensorBatch::new(shape, batch_size);
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::Multiply(Multiply { source, grad }), in_res_block });
+                    }
+                    OpType::PairwiseMul { stride, activation } => {
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        let op = Operation::PairwiseMul(PairwiseMul { stride: *stride, activation: *activation });
+                        nodes.push(Node { outputs, op, in_res_block });
+                    }
+                    OpType::Reduce { reduction, axis, cols } => {
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        let op = Operation::Reduce { reduction: *reduction, axis: *axis, cols: *cols };
+                        nodes.push(Node { outputs, op, in_res_block });
+                    }
+                    OpType::Select => {
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::Select, in_res_block });
+                    }
+                    OpType::SharedAffine { source } => {
+                        // the affine node is the first made for the earlier layer
+                        let source = outputs[*source - 1];
+                        let Operation::Affine(shared) = &nodes[source].op else { unreachable!() };
+                        let (wsh, bsh) = (shared.weights.shape(), shared.biases.shape());
+
+                        let ones = DeviceBuffer::new(1);
+                        ones.load_from_host(&[1.0]);
+                        let mut affine = Affine {
+                            weights: Tensor::uninit(wsh),
+                            biases: Tensor::uninit(bsh),
+                            weights_grad: Tensor::uninit(wsh),
+                            biases_grad: Tensor::uninit(bsh),
+                            ones,
+                        };
+
+                        affine.weights.set_ptr(shared.weights.ptr());
+                        affine.biases.set_ptr(shared.biases.ptr());
+                        affine.weights_grad.set_ptr(shared.weights_grad.ptr());
+                        affine.biases_grad.set_ptr(shared.biases_grad.ptr());
+
+                        let outputs = TensorBatch::new(bsh, batch_size);
+                        let op = Operation::SharedAffine(SharedAffine { source, affine });
+                        nodes.push(Node { outputs, op, in_res_block });
+
+                        if buckets > 1 {
+                            nodes.push(Node {
+                                outputs: TensorBatch::new(Shape::new(1, size), batch_size),
+                                op: Operation::Select,
+                                in_res_block,
+                            });
+                        }
+                    }
+                    OpType::Slice { start } => {
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::Slice { start: *start }, in_res_block });
+                    }
+                    OpType::Softmax { log } => {
+                        let outputs = TensorBatch::new(Shape::new(1, size), batch_size);
+                        nodes.push(Node { outputs, op: Operation::Softmax { log: *log }, in_res_block });
+                    }
                 };
 
+                recomputed.resize(nodes.len(), *recompute);
+                outputs.push(nodes.len());
                 inp_size = size;
             }
 
+            let recompute = Recompute::new(&mut nodes, recomputed, batch_size);
+            let fusion = if self.fusion { Fusion::new(&nodes) } else { None };
+            let shared = self.buffer_sharing.then(|| SharedBuffers::new(&mut ft, &mut nodes, &mut [], batch_size));
+
+            if self.output_transform.is_some() {
+                assert!(export::output_layer(&nodes).is_some(), "Output transforms need an affine output layer!");
+            }
+
             assert_eq!(qi, self.quantisations.len(), "Incorrectly specified number of quantisations!");
             assert_eq!(offset, net_size);
 
             let inputs = SparseTensor::uninit(batch_size, inp_getter_size, max_active_inputs);
 
-            let results = TensorBatch::new(Shape::new(1, 1), batch_size);
+            let output_size = nodes.last().expect("Nodes is empty!").outputs.shape();
+            let results = TensorBatch::new(output_size, batch_size);
+            let weights = TensorBatch::new(Shape::new(1, 1), batch_size);
             let error_device = DeviceBuffer::new(1);
 
             let trainer = Trainer {
@@ -237,12 +1495,46 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
                 nodes,
                 inputs,
                 results,
+                weights,
                 error_device,
+                spike_filter: None,
                 error: 0.0,
+                position_error: 0.0,
+                error_positions: 0,
                 ft_reg: 0.0,
                 used: 0,
                 quantiser,
+                per_row_quantisation: self.per_row_quantisation,
                 buckets: tensor::util::calloc(batch_size),
+                wdl_hook: |_, blend| blend,
+                weight_hook: None,
+                validation_sample: Vec::new(),
+                game_holdout: None,
+                input_dropout: None,
+                hard_mining: None,
+                ft_freeze: None,
+                beta_scheduler: None,
+                schedule_replay: None,
+                swa: None,
+                ema: None,
+                net_version: None,
+                output_transform: self.output_transform,
+                accumulation_steps: 1,
+                accumulated_batches: 0,
+                accumulated_positions: 0,
+                accumulation_limits: None,
+                description,
+                activation_stats: Vec::new(),
+                stats_batches: 0,
+                heads: Vec::new(),
+                recompute,
+                shared,
+                fusion,
+                show_memory_report: false,
+                device_power: None,
+                distributed: None,
+                prefetch: None,
+                report: None,
             };
 
             trainer.randomise_weights(true, true);
@@ -251,3 +1543,32 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> TrainerBuilder<T, U> {
         }
     }
 }
+
+/// First line of a file written by `Trainer::save_net_file`.
+pub(super) const NET_FILE_HEADER: &str = "bullet-net 1";
+
+fn describe_activation(activation: Activation) -> String {
+    match activation {
+        Activation::BoundedCReLU { min, max } => format!("BoundedCReLU {min} {max}"),
+        Activation::BoundedSCReLU { min, max } => format!("BoundedSCReLU {min} {max}"),
+        Activation::LeakyReLU(slope) => format!("LeakyReLU {slope}"),
+        _ => format!("{activation:?}"),
+    }
+}
+
+fn parse_activation(tokens: &[&str]) -> Result<Activation, String> {
+    let float = |i: usize| -> Result<f32, String> {
+        let token = tokens.get(i).ok_or_else(|| format!("Missing parameter of activation {tokens:?}!"))?;
+        token.parse().map_err(|_| format!("Invalid parameter of activation {tokens:?}!"))
+    };
+
+    Ok(match tokens.first().copied() {
+        Some("ReLU") => Activation::ReLU,
+        Some("CReLU") => Activation::CReLU,
+        Some("SCReLU") => Activation::SCReLU,
+        Some("BoundedCReLU") => Activation::BoundedCReLU { min: float(1)?, max: float(2)? },
+        Some("BoundedSCReLU") => Activation::BoundedSCReLU { min: float(1)?, max: float(2)? },
+        Some("LeakyReLU") => Activation::LeakyReLU(float(1)?),
+        _ => return Err(format!("Unknown activation {tokens:?}!")),
+    })
+}
@@ -0,0 +1,344 @@
+use crate::{inputs::InputType, outputs::OutputBuckets, tensor::TensorBatch, Activation};
+
+use super::{ansi, Operation, Quantised, SharedAffine, Trainer};
+
+/// Range of values observed at the output of a layer during calibration.
+#[derive(Clone, Copy, Debug)]
+pub struct ActivationRange {
+    pub min: f32,
+    pub max: f32,
+    /// Fraction of values greater than 1.0, the upper clip of CReLU/SCReLU.
+    pub above_one: f32,
+}
+
+impl ActivationRange {
+    pub fn max_abs(&self) -> f32 {
+        self.min.abs().max(self.max.abs())
+    }
+
+    /// Largest quantisation that keeps every observed value in `i16` range.
+    pub fn max_i16_quantisation(&self) -> i32 {
+        (f32::from(i16::MAX) / self.max_abs()).floor() as i32
+    }
+}
+
+/// Largest shift that keeps `max << shift` in `i16` range once truncated.
+pub(super) fn max_i16_shift(max: f32) -> i64 {
+    let limit = f64::from(i16::MAX) + 1.0;
+    let shift = (limit / f64::from(max)).log2().floor() as i64;
+
+    if f64::from(max) * 2f64.powi(shift as i32) < limit {
+        shift
+    } else {
+        shift - 1
+    }
+}
+
+/// Widths, in bits, of the signed integers an engine accumulates in, which
+/// quantised exports can be checked against with `set_accumulation_limits`.
+#[derive(Clone, Copy, Debug)]
+pub struct AccumulationLimits {
+    /// Feature transformer accumulators.
+    pub ft_bits: u32,
+    /// Dot products of later affine and convolution layers.
+    pub layer_bits: u32,
+}
+
+impl Default for AccumulationLimits {
+    fn default() -> Self {
+        Self { ft_bits: 16, layer_bits: 32 }
+    }
+}
+
+fn max_signed(bits: u32) -> f64 {
+    2f64.powi(bits as i32 - 1) - 1.0
+}
+
+/// Worst-case magnitude of the accumulators of a layer.
+struct Headroom {
+    name: String,
+    bound: f64,
+    limit: f64,
+}
+
+impl Headroom {
+    fn bits(&self) -> f64 {
+        (self.limit / self.bound).log2()
+    }
+}
+
+struct RangeTracker {
+    min: f32,
+    max: f32,
+    above_one: usize,
+    count: usize,
+}
+
+impl Default for RangeTracker {
+    fn default() -> Self {
+        Self { min: f32::INFINITY, max: f32::NEG_INFINITY, above_one: 0, count: 0 }
+    }
+}
+
+impl RangeTracker {
+    fn record(&mut self, tensor: &TensorBatch, batch_size: usize) {
+        let mut buf = vec![0.0; batch_size * tensor.element_size()];
+        tensor.write_to_host(&mut buf);
+
+        for &x in &buf {
+            self.min = self.min.min(x);
+            self.max = self.max.max(x);
+            self.above_one += usize::from(x > 1.0);
+        }
+
+        self.count += buf.len();
+    }
+
+    fn range(&self) -> ActivationRange {
+        ActivationRange { min: self.min, max: self.max, above_one: self.above_one as f32 / self.count as f32 }
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Runs the network over `data`, recording the range of the feature
+    /// transformer outputs (first entry) and then of every node's outputs,
+    /// and prints the ranges along with the largest `i16`-safe quantisation
+    /// of the feature transformer and clipping statistics for each activation.
+    pub fn calibrate(&mut self, data: &[T::RequiredDataType]) -> Vec<ActivationRange> {
+        let mut trackers: Vec<RangeTracker> = (0..=self.nodes.len()).map(|_| RangeTracker::default()).collect();
+
+        for batch in data.chunks(self.batch_size()) {
+            self.forward_batch(batch);
+
+            trackers[0].record(&self.ft.outputs, batch.len());
+            for (i, (tracker, node)) in trackers.iter_mut().skip(1).zip(self.nodes.iter()).enumerate() {
+                unsafe {
+                    self.recompute_outputs(batch.len(), i, false);
+                }
+
+                tracker.record(&node.outputs, batch.len());
+            }
+        }
+
+        self.clear_data();
+
+        let ranges: Vec<_> = trackers.iter().map(RangeTracker::range).collect();
+
+        println!("{}", ansi("Activation Ranges", "34;1"));
+        println!("Feature Transformer    : [{}, {}]", ansi(ranges[0].min, 31), ansi(ranges[0].max, 31));
+        println!("Max i16 FT Quantisation: {}", ansi(ranges[0].max_i16_quantisation(), 31));
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let range = ranges[i + 1];
+            let name = match node.op {
+                Operation::Activate(activation) => format!("{activation:?}"),
+                Operation::Affine(_) => "Affine".to_string(),
+                Operation::Attention(_) => "Attention".to_string(),
+                Operation::BatchNorm(_) => "BatchNorm".to_string(),
+                Operation::Clamp { .. } => "Clamp".to_string(),
+                Operation::Concat(_) => "Concat".to_string(),
+                Operation::Convolution(_) => "Convolution".to_string(),
+                Operation::Dropout(_) => "Dropout".to_string(),
+                Operation::Gather(_) => "Gather".to_string(),
+                Operation::GroupedAffine { .. } => "GroupedAffine".to_string(),
+                Operation::LayerNorm(_) => "LayerNorm".to_string(),
+                Operation::Multiply(_) => "Multiply".to_string(),
+                Operation::PairwiseMul(_) => "PairwiseMul".to_string(),
+                Operation::PReLU(_) => "PReLU".to_string(),
+                Operation::Reduce { reduction, .. } => format!("{reduction:?}"),
+                Operation::Select => "Select".to_string(),
+                Operation::SharedAffine(_) => "SharedAffine".to_string(),
+                Operation::Slice { .. } => "Slice".to_string(),
+                Operation::Softmax { log: false } => "Softmax".to_string(),
+                Operation::Softmax { log: true } => "LogSoftmax".to_string(),
+            };
+
+            println!("Node {i:>2} {name:<14}: [{}, {}]", ansi(range.min, 31), ansi(range.max, 31));
+
+            if let Operation::Activate(Activation::CReLU | Activation::SCReLU) = node.op {
+                let input = ranges[i];
+                println!("  Inputs Clipped Above 1: {}", ansi(format!("{:.2}%", 100.0 * input.above_one), 31));
+            }
+        }
+
+        ranges
+    }
+
+    /// Largest shifts for `TrainerBuilder::quantisation_shifts` that keep every
+    /// quantised weight of the current net in `i16` range. Each shift is chosen
+    /// in turn, as large as possible given the shifts before it, as the biases
+    /// of a layer are scaled by every quantisation up to and including its own.
+    pub fn max_quantisation_shifts(&self) -> Vec<u32> {
+        assert!(!self.quantiser.is_empty(), "Net has no quantisations!");
+        assert!(!self.per_row_quantisation, "Per-row quantisation picks its own scales!");
+
+        let mut buf = vec![0.0; self.optimiser.size()];
+        self.optimiser.write_weights_to_host(&mut buf);
+        let (buf, quantiser) = self.export_weights(&buf);
+
+        let count = quantiser.iter().map(|info| info.factors.1).max().unwrap();
+        let mut shifts: Vec<u32> = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let mut shift = 30;
+
+            for (j, info) in quantiser.iter().enumerate().filter(|(_, info)| info.factors.1 == i + 1) {
+                let end = quantiser.get(j + 1).map_or(buf.len(), |next| next.start);
+                let max = buf[info.start..end].iter().fold(0.0f32, |max, x| max.max(x.abs()));
+
+                if max > 0.0 {
+                    let before: i64 = shifts[info.factors.0..i].iter().map(|&s| i64::from(s)).sum();
+                    let limit = max_i16_shift(max) - before;
+                    shift = shift.min(limit.max(0) as u32);
+                }
+            }
+
+            shifts.push(shift);
+        }
+
+        shifts
+    }
+
+    /// Before writing any quantised net, checks that no accumulator of the
+    /// engine can overflow `limits` for inputs within `ranges`, as returned by
+    /// `calibrate`, and refuses to write the net otherwise.
+    pub fn set_accumulation_limits(&mut self, limits: AccumulationLimits, ranges: Vec<ActivationRange>) {
+        assert_eq!(ranges.len(), self.nodes.len() + 1, "Ranges must be from calibrating this net!");
+        self.accumulation_limits = Some((limits, ranges));
+    }
+
+    /// Prints the worst-case accumulator of each layer with the quantised
+    /// weights, given inputs within `ranges`, and its headroom below `limits`
+    /// in bits, returning whether every layer fits.
+    pub fn report_accumulation_headroom(&self, limits: AccumulationLimits, ranges: &[ActivationRange]) -> bool {
+        let mut buf = vec![0.0; self.optimiser.size()];
+        self.optimiser.write_weights_to_host(&mut buf);
+
+        let (buf, quantiser) = self.export_weights(&buf);
+        let Some(quantised) = self.quantise(&buf, &quantiser) else { return false };
+
+        println!("{}", ansi("Accumulation Headroom", "34;1"));
+        let headroom = self.accumulation_headroom(&quantised, limits, ranges);
+        for layer in &headroom {
+            let bits = ansi(format!("{:.2} bits", layer.bits()), if layer.bound > layer.limit { 31 } else { 32 });
+            println!("{:<22}: {:>14.0} of {:>12.0}, {bits}", layer.name, layer.bound, layer.limit);
+        }
+
+        headroom.iter().all(|layer| layer.bound <= layer.limit)
+    }
+
+    /// Warns and returns false if the limits set by `set_accumulation_limits` could be exceeded.
+    pub(super) fn check_accumulation(&self, quantised: &Quantised) -> bool {
+        let Some((limits, ranges)) = &self.accumulation_limits else { return true };
+        let headroom = self.accumulation_headroom(quantised, *limits, ranges);
+        let unsafe_layers: Vec<_> = headroom.iter().filter(|layer| layer.bound > layer.limit).collect();
+
+        if unsafe_layers.is_empty() {
+            return true;
+        }
+
+        println!("================= WARNING ================");
+        println!("   Quantised accumulators could overflow: ");
+        for layer in unsafe_layers {
+            println!("     > {} reaches {:.0}, limit {:.0}", layer.name, layer.bound, layer.limit);
+        }
+        println!("   Reduce the quantisations to export.    ");
+        println!("==========================================");
+
+        false
+    }
+
+    /// Bounds each accumulator by the sum of the magnitudes of its quantised
+    /// weights times the largest quantised input, plus its bias. Inputs added
+    /// back at the end of a residual block aren't included in the ranges.
+    fn accumulation_headroom(
+        &self,
+        quantised: &Quantised,
+        limits: AccumulationLimits,
+        ranges: &[ActivationRange],
+    ) -> Vec<Headroom> {
+        let (weights, scales) = (&quantised.weights, &quantised.scales);
+        let ft_weights = self.ft.weights.num_elements();
+
+        // the feature transformer outputs are its accumulators, so are bounded exactly
+        let mut headroom = vec![Headroom {
+            name: String::from("Feature Transformer"),
+            bound: f64::from(ranges[0].max_abs()) * scales[ft_weights],
+            limit: max_signed(limits.ft_bits),
+        }];
+
+        let mut offset = ft_weights + self.ft.biases.num_elements();
+        // where the weights of each node start, for shared affine layers
+        let mut starts = vec![0; self.nodes.len()];
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            starts[i] = offset;
+
+            // size of the weights, number of outputs and the output each weight feeds
+            let (name, wsize, outputs, output_of): (_, _, _, Box<dyn Fn(usize) -> usize>) = match &node.op {
+                Operation::Affine(affine) | Operation::SharedAffine(SharedAffine { affine, .. }) => {
+                    let rows = affine.biases.num_elements();
+                    ("Affine", affine.weights.num_elements(), rows, Box::new(move |k| k % rows))
+                }
+                Operation::GroupedAffine { groups, affine } => {
+                    let (wsize, outputs) = (affine.weights.num_elements(), affine.biases.num_elements());
+                    let (group_inputs, group_outputs) = (affine.weights.shape().cols() / groups, outputs / groups);
+                    let output_of = move |k| (k / group_outputs / group_inputs) * group_outputs + k % group_outputs;
+                    ("GroupedAffine", wsize, outputs, Box::new(output_of))
+                }
+                Operation::Attention(attention) => {
+                    let (wsize, dim) = (attention.weights.num_elements(), attention.desc.dim);
+                    // the query, key and value projections, before any attention is applied
+                    ("Attention", wsize, attention.biases.num_elements(), Box::new(move |k| k / dim))
+                }
+                Operation::Convolution(conv) => {
+                    let (wsize, channels) = (conv.weights.num_elements(), conv.biases.num_elements());
+                    // every weight of a channel, including those that only read padding
+                    ("Convolution", wsize, channels, Box::new(move |k| k / (wsize / channels)))
+                }
+                Operation::Gather(gather) => {
+                    offset += gather.weights.num_elements();
+                    continue;
+                }
+                Operation::LayerNorm(norm) => {
+                    offset += norm.gamma.num_elements() + norm.beta.num_elements();
+                    continue;
+                }
+                Operation::PReLU(prelu) => {
+                    offset += prelu.slopes.num_elements();
+                    continue;
+                }
+                // batch norms are folded away before quantising
+                _ => continue,
+            };
+
+            // shared layers apply the weights of an earlier layer to their own inputs
+            let (start, name) = match &node.op {
+                Operation::SharedAffine(SharedAffine { source, .. }) => (starts[*source], "SharedAffine"),
+                _ => (offset, name),
+            };
+
+            let biases = start + wsize;
+            let max_input = f64::from(ranges[i].max_abs());
+            let mut bounds: Vec<f64> = (biases..biases + outputs).map(|b| f64::from(weights[b]).abs()).collect();
+
+            for k in 0..wsize {
+                let output = output_of(k);
+                let input_scale = scales[biases + output] / scales[start + k];
+                bounds[output] += f64::from(weights[start + k]).abs() * max_input * input_scale;
+            }
+
+            headroom.push(Headroom {
+                name: format!("Node {i:>2} {name}"),
+                bound: bounds.into_iter().fold(0.0, f64::max),
+                limit: max_signed(limits.layer_bits),
+            });
+
+            if !matches!(node.op, Operation::SharedAffine(_)) {
+                offset = biases + outputs;
+            }
+        }
+
+        headroom
+    }
+}
@@ -1,8 +1,12 @@
+use rand::rngs::StdRng;
+
 use crate::{
-    tensor::{DeviceBuffer, Tensor, TensorBatch},
-    Activation,
+    tensor::{AttentionDescription, ConvolutionDescription, DeviceBuffer, Tensor, TensorBatch},
+    Activation, Axis, ClampGradient, Reduction,
 };
 
+use super::simplify;
+
 pub(super) struct FeatureTransformer {
     pub weights: Tensor,
     pub biases: Tensor,
@@ -10,7 +14,9 @@ pub(super) struct FeatureTransformer {
     pub biases_grad: Tensor,
     pub single_perspective: bool,
     pub outputs: TensorBatch,
-    pub copy: TensorBatch,
+    /// Copy of the outputs taken before backprop overwrites them with errors,
+    /// which is only read with FT regularisation, so is otherwise not allocated.
+    pub copy: Option<TensorBatch>,
 }
 
 pub(super) struct Affine {
@@ -21,10 +27,161 @@ pub(super) struct Affine {
     pub ones: DeviceBuffer,
 }
 
+/// Affine layer applying the weights of an earlier one, with its weights and
+/// gradients pointing into those of the earlier layer, so that backprop adds
+/// straight to the gradients of the earlier layer.
+pub(super) struct SharedAffine {
+    /// Index in `nodes` of the affine layer whose weights are applied.
+    pub source: usize,
+    pub affine: Affine,
+}
+
+/// Multi-head self-attention, keeping the projections and attention
+/// weights of the forward pass, and scratch space for their gradients.
+pub(super) struct Attention {
+    pub desc: AttentionDescription,
+    pub weights: Tensor,
+    pub biases: Tensor,
+    pub weights_grad: Tensor,
+    pub biases_grad: Tensor,
+    pub qkv: DeviceBuffer,
+    pub attn: DeviceBuffer,
+    pub qkv_grad: DeviceBuffer,
+    pub attn_grad: DeviceBuffer,
+}
+
+/// 2D convolution, with one bias per output channel.
+pub(super) struct Convolution {
+    pub desc: ConvolutionDescription,
+    pub weights: Tensor,
+    pub biases: Tensor,
+    pub weights_grad: Tensor,
+    pub biases_grad: Tensor,
+}
+
+/// Learned embedding of the output bucket of each position, which
+/// replaces the previous output, stored as one column per bucket.
+pub(super) struct Gather {
+    pub weights: Tensor,
+    pub weights_grad: Tensor,
+}
+
+/// Parametric ReLU, with either one learned negative slope per
+/// output or a single slope shared by the whole layer.
+pub(super) struct PReLU {
+    pub slopes: Tensor,
+    pub slopes_grad: Tensor,
+}
+
+/// Concatenation of the previous output with the outputs of earlier nodes.
+pub(super) struct Concat {
+    /// Outputs appended, in order, where 0 is the feature
+    /// transformer and `i` is the output of node `i - 1`.
+    pub sources: Vec<usize>,
+    /// Gradients of each source, which are added to its
+    /// errors once backprop reaches it.
+    pub grads: Vec<TensorBatch>,
+}
+
+/// Element-wise product of the previous output with the output of an
+/// earlier node, which is broadcast across it if smaller.
+pub(super) struct Multiply {
+    /// 0 is the feature transformer and `i` is the output of node `i - 1`.
+    pub source: usize,
+    /// Gradients of the source, added once backprop reaches it.
+    pub grad: TensorBatch,
+}
+
+/// Products of pairs of inputs `stride` apart, after an optional clipping activation.
+pub(super) struct PairwiseMul {
+    pub stride: usize,
+    pub activation: Option<Activation>,
+}
+
+impl PairwiseMul {
+    pub fn bounds(&self) -> (f32, f32) {
+        self.activation.map_or((f32::NEG_INFINITY, f32::INFINITY), |activation| {
+            simplify::clamp_bounds(activation).expect("Only clipping activations can be fused!")
+        })
+    }
+}
+
+/// Zeroes each input with probability `rate` when training, scaling the
+/// rest by `1 / (1 - rate)`, and does nothing when evaluating.
+pub(super) struct Dropout {
+    pub rate: f32,
+    /// Scale applied to each input of the current batch.
+    pub mask: DeviceBuffer,
+    pub rng: StdRng,
+}
+
+/// Batch normalisation, with a learned scale and shift for each output.
+/// The running statistics used for evaluation are stored alongside the
+/// weights, so are saved with them, but are never updated by the optimiser.
+pub(super) struct BatchNorm {
+    pub gamma: Tensor,
+    pub beta: Tensor,
+    pub running_mean: Tensor,
+    pub running_var: Tensor,
+    pub gamma_grad: Tensor,
+    pub beta_grad: Tensor,
+    /// Statistics of the current batch, kept for backprop.
+    pub batch_mean: DeviceBuffer,
+    pub batch_rstd: DeviceBuffer,
+}
+
+impl BatchNorm {
+    pub const MOMENTUM: f32 = 0.1;
+}
+
+/// Layer normalisation, with a learned scale and shift for each output.
+pub(super) struct LayerNorm {
+    pub gamma: Tensor,
+    pub beta: Tensor,
+    pub gamma_grad: Tensor,
+    pub beta_grad: Tensor,
+}
+
 pub(super) enum Operation {
     Activate(Activation),
     Affine(Affine),
+    Attention(Attention),
+    BatchNorm(BatchNorm),
+    /// Clips each output to `[min, max]`.
+    Clamp {
+        min: f32,
+        max: f32,
+        gradient: ClampGradient,
+    },
+    Concat(Concat),
+    Convolution(Convolution),
+    Dropout(Dropout),
+    Gather(Gather),
+    /// Separate affine layer applied to each of `groups` chunks of the inputs.
+    GroupedAffine {
+        groups: usize,
+        affine: Affine,
+    },
+    LayerNorm(LayerNorm),
+    Multiply(Multiply),
+    PairwiseMul(PairwiseMul),
+    PReLU(PReLU),
+    /// Reduces each tensor, viewed as a matrix with `cols` columns, along `axis`.
+    Reduce {
+        reduction: Reduction,
+        axis: Axis,
+        cols: usize,
+    },
     Select,
+    SharedAffine(SharedAffine),
+    /// Outputs starting from `start` of each tensor.
+    Slice {
+        start: usize,
+    },
+    /// Softmax over each tensor, or log-softmax if `log`.
+    Softmax {
+        log: bool,
+    },
 }
 
 pub(super) struct Node {
@@ -33,7 +190,119 @@ pub(super) struct Node {
     pub in_res_block: bool,
 }
 
+/// Tracks recent gradient norms, flagging batches whose norm
+/// exceeds `factor` times the median of the recent history.
+pub(super) struct SpikeFilter {
+    pub factor: f32,
+    pub history: std::collections::VecDeque<f32>,
+    pub last_skipped: Option<(f32, f32)>,
+}
+
+impl SpikeFilter {
+    const HISTORY: usize = 256;
+    const WARMUP: usize = 16;
+
+    pub fn new(factor: f32) -> Self {
+        Self { factor, history: std::collections::VecDeque::with_capacity(Self::HISTORY), last_skipped: None }
+    }
+
+    /// Returns whether the batch should be applied, only
+    /// adding the norms of applied batches to the history.
+    pub fn accept(&mut self, norm: f32) -> bool {
+        self.last_skipped = None;
+
+        if self.history.len() >= Self::WARMUP {
+            let mut sorted: Vec<f32> = self.history.iter().copied().collect();
+            let mid = sorted.len() / 2;
+            let median = *sorted.select_nth_unstable_by(mid, f32::total_cmp).1;
+
+            if norm > self.factor * median {
+                self.last_skipped = Some((norm, median));
+                return false;
+            }
+        }
+
+        if self.history.len() == Self::HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(norm);
+        true
+    }
+}
+
+/// Every position of a deterministic `fraction` of games, identified by
+/// `game`, is kept out of training, with the first `sample_size` found
+/// becoming the validation sample.
+pub(super) struct GameHoldout<D> {
+    pub fraction: f32,
+    pub sample_size: usize,
+    pub game: fn(&D) -> u64,
+}
+
+impl<D> Clone for GameHoldout<D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D> Copy for GameHoldout<D> {}
+
+impl<D> GameHoldout<D> {
+    pub fn is_held_out(&self, pos: &D) -> bool {
+        // splitmix64, so that consecutive game indices are spread uniformly
+        let mut x = (self.game)(pos).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^= x >> 31;
+
+        ((x >> 11) as f64 / (1u64 << 53) as f64) < f64::from(self.fraction)
+    }
+}
+
+/// Running average of the weights at the end of
+/// each superbatch from `start` onwards.
+pub(super) struct Swa {
+    pub start: usize,
+    pub count: usize,
+    pub weights: DeviceBuffer,
+}
+
+/// Exponential moving average of the weights, updated after every optimiser step.
+pub(super) struct Ema {
+    pub decay: f32,
+    pub steps: usize,
+    pub ema_only: bool,
+    pub weights: DeviceBuffer,
+}
+
 pub(super) struct QuantiseInfo {
     pub val: i32,
     pub start: usize,
+    /// Number of output rows, if this block holds affine weights.
+    pub rows: Option<usize>,
+    /// `val` is the product of the quantisations in `factors.0..factors.1`,
+    /// as the biases of a layer are scaled by every quantisation before them.
+    pub factors: (usize, usize),
+}
+
+pub(super) struct Quantised {
+    pub weights: Vec<i16>,
+    pub scales: Vec<f64>,
+    pub row_scales: Vec<i32>,
+}
+
+impl Quantised {
+    pub fn set(&mut self, buf: &[f32], idx: usize, scale: f64) -> Result<(), f64> {
+        let qf = (scale * f64::from(buf[idx])).trunc();
+        let q = qf as i16;
+
+        if f64::from(q) != qf {
+            return Err(qf);
+        }
+
+        self.weights[idx] = q;
+        self.scales[idx] = scale;
+        Ok(())
+    }
 }
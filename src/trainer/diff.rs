@@ -0,0 +1,153 @@
+use crate::{inputs::InputType, outputs::OutputBuckets};
+
+use super::{ImportFormat, Trainer};
+
+/// Differences between the weights of a single layer of two nets.
+#[derive(Clone, Debug)]
+pub struct LayerDiff {
+    pub name: String,
+    pub params: usize,
+    /// Number of weights that differ at all.
+    pub changed: usize,
+    pub max_abs_delta: f32,
+    pub mean_abs_delta: f32,
+    /// Cosine similarity of the weights of the layer in each net.
+    pub cosine: f32,
+}
+
+/// Results of [`Trainer::diff_quantised`], comparing net `a` with net `b`.
+#[derive(Clone, Debug)]
+pub struct NetDiff {
+    /// Layers with weights, in the order of [`Trainer::summary`].
+    pub layers: Vec<LayerDiff>,
+    pub probes: Vec<String>,
+    /// Raw output of each net on each probe, multiply by the
+    /// eval scale for an eval in centipawns.
+    pub evals_a: Vec<f32>,
+    pub evals_b: Vec<f32>,
+}
+
+impl NetDiff {
+    /// Index of the probe with the largest difference between the evals.
+    pub fn worst_probe(&self) -> Option<usize> {
+        (0..self.probes.len()).max_by(|&i, &j| self.eval_delta(i).total_cmp(&self.eval_delta(j)))
+    }
+
+    pub fn mean_eval_delta(&self) -> f32 {
+        (0..self.probes.len()).map(|i| self.eval_delta(i)).sum::<f32>() / self.probes.len().max(1) as f32
+    }
+
+    fn eval_delta(&self, probe: usize) -> f32 {
+        (self.evals_a[probe] - self.evals_b[probe]).abs()
+    }
+}
+
+impl std::fmt::Display for NetDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rule = "-".repeat(92);
+        writeln!(
+            f,
+            "{:<24} {:>10} {:>10} {:>14} {:>14} {:>14}",
+            "Layer", "Params", "Changed", "Max |Delta|", "Mean |Delta|", "Cosine"
+        )?;
+        writeln!(f, "{rule}")?;
+
+        for layer in &self.layers {
+            writeln!(
+                f,
+                "{:<24} {:>10} {:>10} {:>14.6} {:>14.6} {:>14.8}",
+                layer.name, layer.params, layer.changed, layer.max_abs_delta, layer.mean_abs_delta, layer.cosine
+            )?;
+        }
+
+        writeln!(f, "{rule}")?;
+        write!(f, "Changed Layers         : {}", self.layers.iter().filter(|layer| layer.changed > 0).count())?;
+
+        if let Some(worst) = self.worst_probe() {
+            write!(f, "\nMean |Eval Delta|      : {:.6}", self.mean_eval_delta())?;
+            write!(f, "\nMax |Eval Delta|       : {:.6} ({})", self.eval_delta(worst), self.probes[worst])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Compares two nets with this architecture, saved by `save_quantised`,
+    /// layer by layer and by their evals on each of `probes`, e.g. to check
+    /// that a finetune only changed the layers it was meant to. Both nets are
+    /// dequantised with this net's quantisations, and its weights are restored
+    /// afterwards.
+    pub fn diff_quantised(&self, a: &str, b: &str, probes: &[&str]) -> NetDiff
+    where
+        T::RequiredDataType: std::str::FromStr<Err = String>,
+    {
+        let size = self.net_size();
+        let mut original = vec![0.0; size];
+        self.write_weights_to_cpu(&mut original);
+
+        let load = |path: &str| {
+            self.import_weights(path, ImportFormat::Quantised);
+            let mut weights = vec![0.0; size];
+            self.write_weights_to_cpu(&mut weights);
+            (weights, self.eval_fens(probes))
+        };
+
+        let (weights_a, evals_a) = load(a);
+        let (weights_b, evals_b) = load(b);
+
+        self.optimiser.load_weights_from_host(&original);
+
+        let summary = self.summary();
+        assert_eq!(summary.params(), size, "Layers don't cover the weights of the net!");
+
+        let mut layers = Vec::new();
+        let mut start = 0;
+
+        for layer in summary.layers {
+            if layer.params == 0 {
+                continue;
+            }
+
+            let end = start + layer.params;
+            layers.push(diff_layer(layer.name, &weights_a[start..end], &weights_b[start..end]));
+            start = end;
+        }
+
+        NetDiff { layers, probes: probes.iter().map(|probe| probe.to_string()).collect(), evals_a, evals_b }
+    }
+}
+
+fn diff_layer(name: String, a: &[f32], b: &[f32]) -> LayerDiff {
+    let (mut dot, mut norm_a, mut norm_b, mut sum_delta) = (0.0, 0.0, 0.0, 0.0);
+    let mut max_abs_delta = 0.0f32;
+    let mut changed = 0;
+
+    for (&x, &y) in a.iter().zip(b) {
+        let delta = (x - y).abs();
+        changed += usize::from(x != y);
+        max_abs_delta = max_abs_delta.max(delta);
+        sum_delta += f64::from(delta);
+        dot += f64::from(x) * f64::from(y);
+        norm_a += f64::from(x) * f64::from(x);
+        norm_b += f64::from(y) * f64::from(y);
+    }
+
+    // identical layers point the same way even if they are all zero
+    let cosine = if changed == 0 {
+        1.0
+    } else if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        (dot / (norm_a * norm_b).sqrt()) as f32
+    };
+
+    LayerDiff {
+        name,
+        params: a.len(),
+        changed,
+        max_abs_delta,
+        mean_abs_delta: (sum_delta / a.len() as f64) as f32,
+        cosine,
+    }
+}
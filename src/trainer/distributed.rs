@@ -0,0 +1,301 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use crate::{inputs::InputType, outputs::OutputBuckets};
+
+use super::Trainer;
+
+/// Settings for training a single net on several machines at once, by
+/// periodically averaging the weights of a trainer on each of them.
+///
+/// Every machine runs the same schedule, with its own data loader, so each
+/// should be given a different shard of the data. The machine with rank 0
+/// listens on `address` and the rest connect to it, so it must be started
+/// first or within a couple of minutes of the others.
+#[derive(Clone, Copy, Debug)]
+pub struct DistributedSettings<'a> {
+    /// Address of the machine with rank 0, e.g. `"10.0.0.1:7878"`.
+    pub address: &'a str,
+    pub rank: usize,
+    pub world_size: usize,
+    /// Batches trained between each averaging of the weights, which
+    /// also happens at the end of every superbatch. An interval of one
+    /// keeps the weights in sync at every step, at the cost of sending
+    /// the whole net over the network after each batch.
+    pub interval: usize,
+}
+
+pub(super) struct Distributed {
+    pub rank: usize,
+    pub world_size: usize,
+    pub interval: usize,
+    /// Ranks of and connections to every other machine for rank 0, or to rank 0 for the rest.
+    peers: Vec<(usize, TcpStream)>,
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Longest that a machine waits on a read or write before giving up on its
+/// peers, which must cover the slowest machine catching up to the others,
+/// including the time taken to save a checkpoint at the end of a superbatch.
+const PEER_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Sent by rank 0 in reply to each machine before the averaged buffer.
+const STATUS_OK: usize = 0;
+const STATUS_ABORT: usize = 1;
+
+fn write_u64(stream: &mut TcpStream, x: usize) -> io::Result<()> {
+    stream.write_all(&(x as u64).to_le_bytes())
+}
+
+fn read_u64(stream: &mut TcpStream) -> io::Result<usize> {
+    let mut bytes = [0; 8];
+    stream.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes) as usize)
+}
+
+fn write_floats(stream: &mut TcpStream, buf: &[f32]) -> io::Result<()> {
+    let bytes: Vec<u8> = buf.iter().flat_map(|x| x.to_le_bytes()).collect();
+    stream.write_all(&bytes)
+}
+
+fn read_floats(stream: &mut TcpStream, buf: &mut [f32]) -> io::Result<()> {
+    let mut bytes = vec![0; 4 * buf.len()];
+    stream.read_exact(&mut bytes)?;
+
+    for (x, chunk) in buf.iter_mut().zip(bytes.chunks_exact(4)) {
+        *x = f32::from_le_bytes(chunk.try_into().unwrap());
+    }
+
+    Ok(())
+}
+
+/// Sent by every other machine to rank 0 before its buffer: the superbatch,
+/// batch and length of the buffer, all of which must match on every machine.
+type Header = (usize, usize, usize);
+
+fn write_header(stream: &mut TcpStream, (superbatch, batch, len): Header) -> io::Result<()> {
+    write_u64(stream, superbatch)?;
+    write_u64(stream, batch)?;
+    write_u64(stream, len)
+}
+
+fn read_header(stream: &mut TcpStream) -> io::Result<Header> {
+    Ok((read_u64(stream)?, read_u64(stream)?, read_u64(stream)?))
+}
+
+/// Describes a failed read or write, distinguishing a peer that went away.
+fn peer_error(rank: usize, err: io::Error) -> String {
+    match err.kind() {
+        ErrorKind::UnexpectedEof
+        | ErrorKind::ConnectionReset
+        | ErrorKind::BrokenPipe
+        | ErrorKind::ConnectionAborted => {
+            format!("Rank {rank} disconnected!")
+        }
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => format!("Timed out waiting for rank {rank}!"),
+        _ => format!("Communicating with rank {rank} failed: {err}"),
+    }
+}
+
+impl Distributed {
+    fn connect(settings: DistributedSettings, net_size: usize) -> Self {
+        let DistributedSettings { address, rank, world_size, interval } = settings;
+        let mut peers = Vec::new();
+
+        if rank == 0 {
+            let listener = TcpListener::bind(address).unwrap_or_else(|_| panic!("Binding to [{address}] failed!"));
+            listener.set_nonblocking(true).expect("Setting listener to non-blocking failed!");
+
+            let start = Instant::now();
+
+            while peers.len() < world_size - 1 {
+                let mut stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(err) if err.kind() == ErrorKind::WouldBlock && start.elapsed() < CONNECT_TIMEOUT => {
+                        std::thread::sleep(Duration::from_millis(100));
+                        continue;
+                    }
+                    Err(_) => panic!("Only {} of {} machines connected!", peers.len() + 1, world_size),
+                };
+
+                stream.set_nonblocking(false).expect("Setting peer to blocking failed!");
+                set_timeouts(&stream);
+
+                let read = |stream: &mut TcpStream| read_u64(stream).expect("Reading from peer failed!");
+                let (peer, size) = (read(&mut stream), read(&mut stream));
+
+                assert!((1..world_size).contains(&peer), "Invalid rank {peer}!");
+                assert!(peers.iter().all(|&(rank, _)| rank != peer), "Rank {peer} connected twice!");
+                assert_eq!(size, net_size, "Rank {peer} has a different architecture!");
+
+                peers.push((peer, stream));
+            }
+        } else {
+            let start = Instant::now();
+
+            let mut stream = loop {
+                match TcpStream::connect(address) {
+                    Ok(stream) => break stream,
+                    Err(_) if start.elapsed() < CONNECT_TIMEOUT => std::thread::sleep(Duration::from_secs(1)),
+                    Err(_) => panic!("Connecting to [{address}] failed!"),
+                }
+            };
+
+            set_timeouts(&stream);
+            write_u64(&mut stream, rank).expect("Writing to peer failed!");
+            write_u64(&mut stream, net_size).expect("Writing to peer failed!");
+            peers.push((0, stream));
+        }
+
+        for (_, stream) in &peers {
+            stream.set_nodelay(true).expect("Setting TCP_NODELAY failed!");
+        }
+
+        Self { rank, world_size, interval, peers }
+    }
+
+    /// Replaces `buf` with its mean over every machine, after checking that
+    /// they are all at the same `step`. On failure every connection is
+    /// closed, so that the other machines stop at their next exchange rather
+    /// than waiting on this one until they time out.
+    fn average(&mut self, step: (usize, usize), buf: &mut [f32]) -> Result<(), String> {
+        let res = if self.rank == 0 { self.gather(step, buf) } else { self.exchange(step, buf) };
+
+        if res.is_err() {
+            for (_, stream) in &self.peers {
+                stream.shutdown(std::net::Shutdown::Both).unwrap_or(());
+            }
+        }
+
+        res
+    }
+
+    fn gather(&mut self, (superbatch, batch): (usize, usize), buf: &mut [f32]) -> Result<(), String> {
+        let mut failure = None;
+
+        for (rank, stream) in &mut self.peers {
+            match read_header(stream) {
+                Ok(theirs) if theirs == (superbatch, batch, buf.len()) => {}
+                Ok((sb, b, _)) => {
+                    failure = Some(format!(
+                        "Rank {rank} is at superbatch {sb} batch {b}, not superbatch {superbatch} batch {batch}!"
+                    ));
+                }
+                Err(err) => failure = Some(peer_error(*rank, err)),
+            }
+
+            if failure.is_some() {
+                break;
+            }
+        }
+
+        if let Some(failure) = failure {
+            for (_, stream) in &mut self.peers {
+                write_u64(stream, STATUS_ABORT).unwrap_or(());
+            }
+
+            return Err(failure);
+        }
+
+        let mut theirs = vec![0.0; buf.len()];
+
+        for (rank, stream) in &mut self.peers {
+            read_floats(stream, &mut theirs).map_err(|err| peer_error(*rank, err))?;
+
+            for (x, y) in buf.iter_mut().zip(&theirs) {
+                *x += y;
+            }
+        }
+
+        for x in buf.iter_mut() {
+            *x /= self.world_size as f32;
+        }
+
+        for (rank, stream) in &mut self.peers {
+            write_u64(stream, STATUS_OK)
+                .and_then(|_| write_floats(stream, buf))
+                .map_err(|err| peer_error(*rank, err))?;
+        }
+
+        Ok(())
+    }
+
+    fn exchange(&mut self, (superbatch, batch): (usize, usize), buf: &mut [f32]) -> Result<(), String> {
+        let stream = &mut self.peers[0].1;
+
+        let sent = write_header(stream, (superbatch, batch, buf.len())).and_then(|_| write_floats(stream, buf));
+
+        // rank 0 may have aborted before reading the buffer, so see if it said why
+        let status = read_u64(stream);
+
+        match (sent, status) {
+            (_, Ok(STATUS_OK)) => read_floats(stream, buf).map_err(|err| peer_error(0, err)),
+            (_, Ok(_)) => Err(String::from("Rank 0 aborted, as the machines are out of sync!")),
+            (Err(err), _) | (_, Err(err)) => Err(peer_error(0, err)),
+        }
+    }
+}
+
+fn set_timeouts(stream: &TcpStream) {
+    stream.set_read_timeout(Some(PEER_TIMEOUT)).expect("Setting read timeout failed!");
+    stream.set_write_timeout(Some(PEER_TIMEOUT)).expect("Setting write timeout failed!");
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Trains as one of several machines, connecting to the others before
+    /// returning. The weights are averaged along with the moments of the
+    /// optimiser, as leaving the moments local lets each machine keep
+    /// stepping in the direction of its own shard just after averaging,
+    /// which undoes much of it when the interval is short. Lookahead slow
+    /// weights and any averaged copies of the weights stay local, so the
+    /// checkpoints saved by rank 0 are the ones to keep.
+    pub fn set_distributed(&mut self, settings: DistributedSettings) {
+        assert!(settings.world_size > 1, "Need at least two machines!");
+        assert!(settings.rank < settings.world_size, "Invalid rank {}!", settings.rank);
+        assert!(settings.interval > 0, "Must train for at least one batch between averaging!");
+
+        self.distributed = Some(Distributed::connect(settings, self.net_size()));
+    }
+
+    /// Rank and number of machines, if training is distributed.
+    pub fn distributed_rank(&self) -> Option<(usize, usize)> {
+        self.distributed.as_ref().map(|distributed| (distributed.rank, distributed.world_size))
+    }
+
+    /// Averages the weights and moments over every machine if `batch`, counted
+    /// from one within `superbatch`, is due to be averaged. Returns false if
+    /// the machines are out of sync or a peer has gone, after printing why and
+    /// disconnecting from the rest, so that the run can be stopped.
+    pub(super) fn apply_weight_averaging(
+        &mut self,
+        superbatch: usize,
+        batch: usize,
+        batches_per_superbatch: usize,
+    ) -> bool {
+        let size = self.net_size();
+        let Some(distributed) = &mut self.distributed else { return true };
+
+        if !batch.is_multiple_of(distributed.interval) && !batch.is_multiple_of(batches_per_superbatch) {
+            return true;
+        }
+
+        let mut buf = vec![0.0; 3 * size];
+        let (weights, rest) = buf.split_at_mut(size);
+        let (momentum, velocity) = rest.split_at_mut(size);
+        self.optimiser.write_to_host(weights, momentum, velocity);
+
+        if let Err(err) = distributed.average((superbatch, batch), &mut buf) {
+            println!("Distributed training failed: {err}");
+            self.distributed = None;
+            return false;
+        }
+
+        self.optimiser.load_from_cpu(&buf[..size], &buf[size..2 * size], &buf[2 * size..]);
+
+        true
+    }
+}
@@ -0,0 +1,115 @@
+use std::{fs::File, io::Write};
+
+use bulletformat::BulletFormat;
+
+use crate::{inputs::InputType, outputs::OutputBuckets};
+
+use super::Trainer;
+
+/// Histogram of the net's predicted score over a sample of positions,
+/// split by the game result of each position.
+#[derive(Clone, Debug)]
+pub struct EvalDistribution {
+    /// Number of (loss, draw, win) positions in each of a
+    /// set of equal width bins covering scores in `[0, 1]`.
+    pub bins: Vec<[usize; 3]>,
+    /// Mean squared error of the predicted score against the game result.
+    pub result_error: f32,
+}
+
+impl EvalDistribution {
+    fn new(bins: usize) -> Self {
+        Self { bins: vec![[0; 3]; bins], result_error: 0.0 }
+    }
+
+    fn add(&mut self, score: f32, result_idx: usize) {
+        let bins = self.bins.len();
+        let bin = ((score * bins as f32) as usize).min(bins - 1);
+        self.bins[bin][result_idx] += 1;
+    }
+
+    pub fn write_csv(&self, path: &str) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        let width = 1.0 / self.bins.len() as f32;
+
+        writeln!(file, "start,end,loss,draw,win")?;
+        for (i, [loss, draw, win]) in self.bins.iter().enumerate() {
+            let start = i as f32 * width;
+            writeln!(file, "{start:.3},{:.3},{loss},{draw},{win}", start + width)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Sets the positions used to export the eval distribution on every save.
+    pub fn set_validation_sample(&mut self, data: &[T::RequiredDataType]) {
+        self.validation_sample = data.to_vec();
+    }
+
+    /// Runs the network over `data`, and bins the predicted score, as
+    /// the sigmoid of the output or the expected score of a WDL output.
+    pub fn eval_distribution(&mut self, data: &[T::RequiredDataType], bins: usize) -> EvalDistribution {
+        assert!(bins > 0, "Need at least one bin!");
+
+        let mut distribution = EvalDistribution::new(bins);
+        let output_size = self.results.element_size();
+        let mut error = 0.0;
+
+        for batch in data.chunks(self.batch_size()) {
+            self.forward_batch(batch);
+
+            let mut outputs = vec![0.0; batch.len() * output_size];
+            self.nodes.last().expect("Nodes is empty!").outputs.write_to_host(&mut outputs);
+
+            for (pos, output) in batch.iter().zip(outputs.chunks(output_size)) {
+                let score = if self.wdl_outputs() {
+                    let max = output.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                    let exps: Vec<f32> = output.iter().map(|x| (x - max).exp()).collect();
+                    (exps[0] + 0.5 * exps[1]) / exps.iter().sum::<f32>()
+                } else {
+                    1.0 / (1.0 + (-output[0]).exp())
+                };
+
+                let result = pos.result_idx();
+                distribution.add(score, result);
+                error += f64::from(score - result as f32 / 2.0).powi(2);
+            }
+        }
+
+        self.clear_data();
+        distribution.result_error = (error / data.len().max(1) as f64) as f32;
+
+        distribution
+    }
+
+    /// Error of the predictions on the validation sample against
+    /// the results, if one is set.
+    pub fn validation_error(&mut self) -> Option<f32> {
+        if self.validation_sample.is_empty() {
+            return None;
+        }
+
+        let sample = std::mem::take(&mut self.validation_sample);
+        let error = self.eval_distribution(&sample, 1).result_error;
+        self.validation_sample = sample;
+
+        Some(error)
+    }
+
+    /// Writes the eval distribution of the validation sample, if one is
+    /// set, returning the error of the predictions against the results.
+    pub(super) fn export_eval_distribution(&mut self, path: &str) -> Option<f32> {
+        if self.validation_sample.is_empty() {
+            return None;
+        }
+
+        let sample = std::mem::take(&mut self.validation_sample);
+        let distribution = self.eval_distribution(&sample, 20);
+        self.validation_sample = sample;
+
+        distribution.write_csv(path).unwrap_or_else(|_| panic!("Writing to [{path}] failed!"));
+        Some(distribution.result_error)
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt::Write;
+
+use crate::{inputs::InputType, outputs::OutputBuckets};
+
+use super::{
+    components::{Concat, Multiply, Operation, SharedAffine},
+    Trainer,
+};
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Graphviz DOT description of the network, with a node per layer named
+    /// as in [`Trainer::summary`] and each edge labelled with the number of
+    /// values passed along it. Render with e.g. `dot -Tsvg net.dot -o net.svg`.
+    pub fn to_dot(&self) -> String {
+        let summary = self.summary();
+        let inputs = self.input_getter.inputs();
+        let buckets = self.input_getter.buckets();
+
+        let mut dot = String::from("digraph net {\n    rankdir=TB;\n    node [shape=box, fontname=\"monospace\"];\n\n");
+
+        let input_name = if buckets > 1 { format!("Inputs {inputs}x{buckets}") } else { format!("Inputs {inputs}") };
+        writeln!(dot, "    inputs [label=\"{input_name}\", shape=ellipse];").unwrap();
+
+        // layer 0 is the feature transformer, and layer `i` is node `i - 1`
+        for (i, layer) in summary.layers.iter().enumerate() {
+            let params = if layer.params > 0 { format!("\\n{} params", layer.params) } else { String::new() };
+            writeln!(dot, "    l{i} [label=\"{}{params}\"];", layer.name).unwrap();
+        }
+
+        writeln!(dot, "    outputs [label=\"Outputs\", shape=ellipse];\n").unwrap();
+
+        let perspectives = if self.ft.single_perspective { "" } else { " x2" };
+        writeln!(dot, "    inputs -> l0 [label=\"sparse{perspectives}\"];").unwrap();
+
+        let mut res_inputs = 0;
+        let mut in_res_block = false;
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let prev = &summary.layers[i];
+
+            if !in_res_block && node.in_res_block {
+                in_res_block = true;
+                res_inputs = i;
+            }
+
+            if in_res_block && !node.in_res_block {
+                in_res_block = false;
+                writeln!(dot, "    l{res_inputs} -> l{} [label=\"residual\", style=dashed];", i + 1).unwrap();
+            }
+
+            // gathers read only the output bucket of each position
+            if let Operation::Gather(_) = node.op {
+                writeln!(dot, "    inputs -> l{} [label=\"bucket\", style=dotted];", i + 1).unwrap();
+                continue;
+            }
+
+            writeln!(dot, "    l{i} -> l{} [label=\"{}\"];", i + 1, prev.outputs).unwrap();
+
+            let sources = match &node.op {
+                Operation::Concat(Concat { sources, .. }) => sources.clone(),
+                Operation::Multiply(Multiply { source, .. }) => vec![*source],
+                Operation::SharedAffine(SharedAffine { source, .. }) => {
+                    let edge = "label=\"shared weights\", style=dotted, arrowhead=none";
+                    writeln!(dot, "    l{} -> l{} [{edge}];", source + 1, i + 1).unwrap();
+                    continue;
+                }
+                _ => continue,
+            };
+
+            for source in sources {
+                let size = summary.layers[source].outputs;
+                writeln!(dot, "    l{source} -> l{} [label=\"{size}\", style=dotted];", i + 1).unwrap();
+            }
+        }
+
+        let last = summary.layers.len() - 1;
+        writeln!(dot, "    l{last} -> outputs [label=\"{}\"];", summary.layers[last].outputs).unwrap();
+        dot.push_str("}\n");
+
+        dot
+    }
+}
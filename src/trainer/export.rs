@@ -0,0 +1,84 @@
+use crate::{inputs::InputType, outputs::OutputBuckets};
+
+use super::{Affine, Node, Operation, QuantiseInfo, Trainer};
+
+/// Transform applied to the output of the net when it is quantised, and
+/// recorded in its manifest, so that engines can use the exported net as
+/// it is rather than having its weights edited by hand. The net is trained
+/// to output the logit that the loss applies its sigmoid to, so the sigmoid
+/// is dropped from every export.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputTransform {
+    /// Multiplies the output by `eval_scale`, which should be the eval
+    /// scale of the schedule, so that the net outputs centipawns.
+    Centipawns { eval_scale: f32 },
+    /// Multiplies the output by `eval_scale * units_per_pawn / 100`, so that
+    /// the net outputs the internal units of an engine, with the biases of
+    /// the output layer rounded to a whole number of units.
+    EngineUnits { eval_scale: f32, units_per_pawn: f32 },
+}
+
+impl OutputTransform {
+    fn scale(self) -> f32 {
+        match self {
+            Self::Centipawns { eval_scale } => eval_scale,
+            Self::EngineUnits { eval_scale, units_per_pawn } => eval_scale * units_per_pawn / 100.0,
+        }
+    }
+}
+
+impl std::fmt::Display for OutputTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Centipawns { eval_scale } => write!(f, "centipawns, sigmoid dropped, scaled by {eval_scale}"),
+            Self::EngineUnits { eval_scale, units_per_pawn } => write!(
+                f,
+                "engine units ({units_per_pawn} per pawn), sigmoid dropped, scaled by {}, biases rounded",
+                eval_scale * units_per_pawn / 100.0
+            ),
+        }
+    }
+}
+
+/// Affine layer whose outputs, possibly after a batch norm folded
+/// into it, are the outputs of the net.
+pub(super) fn output_layer(nodes: &[Node]) -> Option<&Affine> {
+    let mut nodes = nodes.iter().rev();
+    let mut last = nodes.next()?;
+
+    if matches!(last.op, Operation::BatchNorm(_)) {
+        last = nodes.next()?;
+    }
+
+    match &last.op {
+        Operation::Affine(affine) => Some(affine),
+        _ => None,
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Weights as they are quantised, with batch norms folded and the output
+    /// transform applied, along with the quantisation blocks moved to match.
+    pub(super) fn export_weights(&self, buf: &[f32]) -> (Vec<f32>, Vec<QuantiseInfo>) {
+        let (mut weights, quantiser) = self.fold_batch_norms(buf);
+
+        if let Some(transform) = self.output_transform {
+            let layer = output_layer(&self.nodes).expect("Output transforms need an affine output layer!");
+            let biases = layer.biases.num_elements();
+            let start = weights.len() - layer.weights.num_elements() - biases;
+
+            for weight in &mut weights[start..] {
+                *weight *= transform.scale();
+            }
+
+            if let OutputTransform::EngineUnits { .. } = transform {
+                let start = weights.len() - biases;
+                for bias in &mut weights[start..] {
+                    *bias = bias.round();
+                }
+            }
+        }
+
+        (weights, quantiser)
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    inputs::InputType,
+    outputs::OutputBuckets,
+    tensor::{SparseTensor, TensorBatch},
+    Activation,
+};
+
+use super::{Affine, Node, Operation, SharedAffine, Trainer};
+
+/// Activations computed in the same kernel as the feature transformer or
+/// affine layer before them, which still writes its own outputs for backprop,
+/// as the per-op launch overhead dominates the forward pass of small nets.
+pub(super) struct Fusion {
+    /// Whether each node in `nodes` is fused into the node before it,
+    /// or the feature transformer for the first node.
+    pub nodes: Vec<bool>,
+}
+
+impl Fusion {
+    /// Finds the activations that can be fused, returning `None` if there are none.
+    pub fn new(nodes: &[Node]) -> Option<Self> {
+        let fused: Vec<_> = (0..nodes.len()).map(|i| Self::fusable(nodes, i)).collect();
+        fused.contains(&true).then_some(Self { nodes: fused })
+    }
+
+    fn fusable(nodes: &[Node], node: usize) -> bool {
+        if !matches!(nodes[node].op, Operation::Activate(_)) {
+            return false;
+        }
+
+        if node == 0 {
+            return true;
+        }
+
+        // the residual is added to the outputs of the node before
+        // an activation that exits a block, before it is applied
+        let prev = &nodes[node - 1];
+        let exits_res_block = prev.in_res_block && !nodes[node].in_res_block;
+
+        matches!(prev.op, Operation::Affine(_) | Operation::SharedAffine(_)) && !exits_res_block
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Activation of `node`, if it is computed along with the node before it.
+    pub(super) fn fused_activation(&self, node: usize) -> Option<Activation> {
+        let fusion = self.fusion.as_ref()?;
+
+        match self.nodes.get(node)?.op {
+            Operation::Activate(activation) if fusion.nodes[node] => Some(activation),
+            _ => None,
+        }
+    }
+
+    /// Applies the feature transformer and the activation fused into it.
+    ///
+    /// # Safety
+    /// It is undefined behaviour to call this if `our_inputs` is not
+    /// properly initialised.
+    pub(super) unsafe fn forward_ft_fused(&self, activation: Activation) {
+        let (w, b, out, activated) = (&self.ft.weights, &self.ft.biases, &self.ft.outputs, &self.nodes[0].outputs);
+
+        if self.ft.single_perspective {
+            SparseTensor::single_affine_activate(self.handle, activation, w, &self.inputs, b, out, activated);
+        } else {
+            SparseTensor::affine_activate(self.handle, activation, w, &self.inputs, b, out, activated);
+        }
+    }
+
+    /// Applies `node`, an affine layer, to `inputs`, along with
+    /// `activation` of the next node, writing to both of their outputs.
+    ///
+    /// # Safety
+    /// It is undefined behaviour to call this if `our_inputs` is not
+    /// properly initialised.
+    pub(super) unsafe fn forward_fused(
+        &self,
+        batch_size: usize,
+        node: usize,
+        activation: Activation,
+        inputs: &TensorBatch,
+    ) {
+        let (pre, out) = (&self.nodes[node].outputs, &self.nodes[node + 1].outputs);
+
+        match &self.nodes[node].op {
+            Operation::Affine(Affine { weights, biases, .. })
+            | Operation::SharedAffine(SharedAffine { affine: Affine { weights, biases, .. }, .. }) => {
+                TensorBatch::affine_activate(self.handle, batch_size, activation, weights, inputs, biases, pre, out);
+            }
+            _ => unreachable!("Only affine layers have activations fused into them!"),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{
+    inputs::InputType,
+    outputs::OutputBuckets,
+    tensor::{DeviceBuffer, TensorBatch},
+};
+
+use super::{
+    schedule::{Loss, WdlScheduler},
+    Trainer,
+};
+
+/// Online hard example mining, which skips the positions of each batch with
+/// the lowest loss from backprop, so that the gradient is spent on the
+/// positions the net is still getting wrong.
+pub(super) struct HardMining {
+    pub skip: WdlScheduler,
+    /// Fraction of the positions of each batch skipped in the current superbatch.
+    pub fraction: f32,
+    /// Scale applied to the errors of each output of the current batch.
+    pub mask: DeviceBuffer,
+    /// Whether `mask` has been loaded for the current batch.
+    pub active: bool,
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Loss of a single position, matching the loss added to the error
+/// by the kernels of each loss, without any per-position weight.
+fn position_loss(loss: Loss, outputs: &[f32], results: &[f32]) -> f32 {
+    let diff = || (sigmoid(outputs[0]) - results[0]).abs();
+
+    match loss {
+        Loss::SigmoidMSE | Loss::SigmoidMPE(_) | Loss::SigmoidFocal { .. } | Loss::SigmoidMSEVariance => {
+            diff().powf(loss.power())
+        }
+        Loss::SigmoidHuber { delta } => {
+            let diff = diff();
+            if diff <= delta {
+                0.5 * diff * diff
+            } else {
+                delta * (diff - 0.5 * delta)
+            }
+        }
+        Loss::SoftmaxWDL => {
+            let max = outputs.iter().fold(f32::NEG_INFINITY, |max, &x| max.max(x));
+            let log_sum = outputs.iter().map(|x| (x - max).exp()).sum::<f32>().ln() + max;
+            outputs.iter().zip(results).map(|(x, r)| r * (log_sum - x)).sum()
+        }
+        Loss::MultiHead(heads) => {
+            let mut offset = 0;
+            let mut total = 0.0;
+
+            for head in heads {
+                let size = head.loss.outputs();
+                let range = offset..offset + size;
+                total += head.weight * position_loss(head.loss, &outputs[range.clone()], &results[range]);
+                offset += size;
+            }
+
+            total
+        }
+        Loss::Custom(_) => panic!("Hard example mining does not support custom losses!"),
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Skips the fraction of the positions of each batch with the lowest loss
+    /// from backprop, scheduled like the WDL blend, and scales the gradients
+    /// of the rest so that the gradient is the mean over the positions kept.
+    /// The forward pass is still run on every position, and the reported
+    /// loss is that of the whole batch. Custom losses are not supported.
+    pub fn set_hard_example_mining(&mut self, skip: WdlScheduler) {
+        let mask = DeviceBuffer::new(self.batch_size() * self.results.element_size());
+        self.hard_mining = Some(HardMining { skip, fraction: 0.0, mask, active: false });
+    }
+
+    pub fn hard_example_mining(&self) -> Option<WdlScheduler> {
+        self.hard_mining.as_ref().map(|mining| mining.skip)
+    }
+
+    /// Sets the fraction of positions skipped for `superbatch`.
+    pub(super) fn apply_hard_mining_schedule(&mut self, superbatch: usize, end_superbatch: usize) {
+        if let Some(mining) = &mut self.hard_mining {
+            let fraction = mining.skip.blend(superbatch, end_superbatch);
+            assert!((0.0..1.0).contains(&fraction), "Invalid fraction of positions to skip {fraction}!");
+            mining.fraction = fraction;
+        }
+    }
+
+    /// Scores each position of the current batch by its loss, from the outputs
+    /// of the forward pass, and loads the mask that skips the easiest of them.
+    pub(super) fn select_hard_positions(&mut self, loss: Loss) {
+        let batch_size = self.inputs.used();
+        let size = self.results.element_size();
+
+        let Some(mining) = &mut self.hard_mining else { return };
+        let skipped = (mining.fraction * batch_size as f32) as usize;
+        mining.active = skipped > 0;
+
+        if !mining.active {
+            return;
+        }
+
+        let mut outputs = vec![0.0; self.results.num_elements()];
+        let mut results = vec![0.0; self.results.num_elements()];
+        self.nodes.last().expect("Nodes is empty!").outputs.write_to_host(&mut outputs);
+        self.results.write_to_host(&mut results);
+
+        let mut weights = vec![1.0; self.weights.num_elements()];
+        if self.weight_hook.is_some() {
+            self.weights.write_to_host(&mut weights);
+        }
+
+        let losses: Vec<f32> = (0..batch_size)
+            .map(|i| {
+                let range = i * size..(i + 1) * size;
+                weights[i] * position_loss(loss, &outputs[range.clone()], &results[range])
+            })
+            .collect();
+
+        let mut order: Vec<usize> = (0..batch_size).collect();
+        order.sort_unstable_by(|&a, &b| losses[a].total_cmp(&losses[b]));
+
+        let scale = batch_size as f32 / (batch_size - skipped) as f32;
+        let mut mask = vec![scale; batch_size * size];
+        for &i in &order[..skipped] {
+            mask[i * size..(i + 1) * size].fill(0.0);
+        }
+
+        if mining.mask.size() < mask.len() {
+            mining.mask = DeviceBuffer::new(self.results.num_elements());
+        }
+
+        mining.mask.load_from_host(&mask);
+    }
+
+    /// Applies the mask loaded by `select_hard_positions` to the errors
+    /// of the output layer, before they are backpropagated.
+    pub(super) fn skip_easy_positions(&self) {
+        let Some(HardMining { mask, active: true, .. }) = &self.hard_mining else { return };
+        let errors = &self.nodes.last().expect("Nodes is empty!").outputs;
+        TensorBatch::masked_scale(self.handle, self.inputs.used(), mask, errors, errors);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{
+    inputs::InputType,
+    outputs::OutputBuckets,
+    tensor::{DeviceBuffer, Shape, TensorBatch},
+};
+
+use super::{calc_loss, schedule::LossHead, Trainer};
+
+/// Copies of the outputs and results of one head of a `Loss::MultiHead`, so
+/// that its loss can be calculated on its own, with the error it adds and the
+/// factor its gradients are scaled by.
+pub(super) struct HeadBuffers {
+    outputs: TensorBatch,
+    results: TensorBatch,
+    error: DeviceBuffer,
+    scale: DeviceBuffer,
+    factor: f32,
+}
+
+impl HeadBuffers {
+    fn new(head: &LossHead, batch_size: usize, threads: usize) -> Self {
+        let size = head.loss.outputs();
+        let factor = head.weight * head.loss.power();
+
+        let scale = DeviceBuffer::new(batch_size * size);
+        scale.load_from_host(&vec![factor; batch_size * size]);
+
+        Self {
+            outputs: TensorBatch::new(Shape::new(1, size), batch_size),
+            results: TensorBatch::new(Shape::new(1, size), batch_size),
+            error: DeviceBuffer::new(threads),
+            scale,
+            factor,
+        }
+    }
+
+    fn matches(&self, head: &LossHead, batch_size: usize, threads: usize) -> bool {
+        self.outputs.element_size() == head.loss.outputs()
+            && self.outputs.cap() == batch_size
+            && self.error.size() == threads
+            && self.factor == head.weight * head.loss.power()
+    }
+
+    /// Buffers that are overwritten for each batch, so can share memory.
+    pub(super) fn scratch(&mut self) -> [&mut TensorBatch; 2] {
+        [&mut self.outputs, &mut self.results]
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Calculates the loss of each head on its slice of the outputs, writing the
+    /// weighted total to the error and the gradients of each head, scaled by its
+    /// weight and the power of its loss, in place of its outputs.
+    pub(super) fn calc_head_errors(&mut self, heads: &[LossHead]) {
+        assert!(!heads.is_empty(), "Multi-head loss has no heads!");
+
+        let batch_size = self.inputs.used();
+        let cap = self.batch_size();
+        let threads = self.error_device.size();
+
+        let stale = self.heads.len() != heads.len()
+            || self.heads.iter().zip(heads).any(|(buffers, head)| !buffers.matches(head, cap, threads));
+
+        if stale {
+            self.heads = heads.iter().map(|head| HeadBuffers::new(head, cap, threads)).collect();
+            self.share_buffers();
+        }
+
+        let outputs = &self.nodes.last().expect("Nodes is empty!").outputs;
+        let weights = self.weight_hook.map(|_| &self.weights);
+        let mut offset = 0;
+        let mut error = 0.0;
+
+        for (head, buffers) in heads.iter().zip(&self.heads) {
+            TensorBatch::split_from(self.handle, batch_size, outputs, offset, &buffers.outputs);
+            TensorBatch::split_from(self.handle, batch_size, &self.results, offset, &buffers.results);
+
+            buffers.error.set_zero();
+            calc_loss(self.handle, batch_size, head.loss, &buffers.outputs, &buffers.results, weights, &buffers.error);
+
+            TensorBatch::masked_scale(self.handle, batch_size, &buffers.scale, &buffers.outputs, &buffers.outputs);
+            TensorBatch::concat_into(self.handle, batch_size, &buffers.outputs, outputs, offset);
+
+            let mut errors = vec![0.0; threads];
+            buffers.error.write_to_host(&mut errors);
+            error += head.weight * errors.iter().sum::<f32>();
+
+            offset += buffers.outputs.element_size();
+        }
+
+        let mut errors = vec![0.0; threads];
+        self.error_device.write_to_host(&mut errors);
+        errors[0] += error;
+        self.error_device.load_from_host(&errors);
+    }
+}
@@ -0,0 +1,129 @@
+//! Importing networks saved in formats other than this crate's checkpoints,
+//! so that existing nets can be used as a starting point for training.
+
+use std::io::Read;
+
+use crate::{inputs::InputType, outputs::OutputBuckets};
+
+use super::{Affine, Operation, QuantiseInfo, Trainer};
+
+/// Layout of a file of weights to import with `Trainer::import_weights`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// A quantised net, as written by `save_quantised`, which is often all
+    /// that is kept of older nets, dequantised using this net's quantisations.
+    /// Any trailing padding or version string is ignored. If the net was
+    /// quantised per row, the `-scales.bin` written alongside it is also read.
+    Quantised,
+    /// Raw `f32` weights in the order used here, but with the matrix of each
+    /// (non-grouped) affine layer stored one output at a time, as written by
+    /// many other trainers, rather than one input at a time.
+    OutputMajor,
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Loads weights from `path`, saved in `format`, leaving the optimiser state
+    /// untouched, so the net can be fine-tuned rather than trained from scratch.
+    pub fn import_weights(&self, path: &str, format: ImportFormat) {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .unwrap_or_else(|_| panic!("Reading [{path}] failed!"));
+
+        let size = self.net_size();
+        let weights = match format {
+            ImportFormat::Quantised => {
+                let has_batch_norm = self.nodes.iter().any(|node| matches!(node.op, Operation::BatchNorm(_)));
+                assert!(!has_batch_norm, "Quantised nets have batch norms folded away, so can't be imported!");
+                assert!(!self.quantiser.is_empty(), "Importing a quantised net needs quantisations!");
+                assert!(bytes.len() >= 2 * size, "[{path}] is too small to hold a quantised net of {size} weights!");
+
+                let row_scales = self.per_row_quantisation.then(|| {
+                    let scales_path = format!("{}-scales.bin", path.trim_end_matches(".bin"));
+                    let bytes =
+                        std::fs::read(&scales_path).unwrap_or_else(|_| panic!("Reading [{scales_path}] failed!"));
+                    bytes.chunks_exact(4).map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect::<Vec<_>>()
+                });
+
+                let scales = self.quantisation_scales(row_scales.as_deref());
+                let quantised = bytes.chunks_exact(2).take(size).map(|b| i16::from_ne_bytes([b[0], b[1]]));
+                quantised.zip(scales).map(|(q, scale)| (f64::from(q) / scale) as f32).collect()
+            }
+            ImportFormat::OutputMajor => {
+                assert_eq!(bytes.len(), 4 * size, "[{path}] doesn't hold exactly {size} weights!");
+                let raw: Vec<f32> =
+                    bytes.chunks_exact(4).map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect();
+                self.transpose_affine_weights(&raw)
+            }
+        };
+
+        self.optimiser.load_weights_from_host(&weights);
+    }
+
+    /// The scale each weight is multiplied by when quantising, as in `quantise`.
+    fn quantisation_scales(&self, row_scales: Option<&[i32]>) -> Vec<f64> {
+        let size = self.net_size();
+        let mut scales = vec![1.0; size];
+        let mut row_scales = row_scales.unwrap_or_default().iter();
+
+        let mut qiter = self.quantiser.iter().peekable();
+        while let Some(&QuantiseInfo { val, start, rows, .. }) = qiter.next() {
+            let end = qiter.peek().map_or(size, |next| next.start);
+
+            match rows {
+                Some(rows) if self.per_row_quantisation => {
+                    let biases = qiter.next().expect("Affine weights must be followed by biases!");
+                    let input_scale = f64::from(biases.val / val);
+
+                    for row in 0..rows {
+                        let scale = f64::from(*row_scales.next().expect("Not enough row scales!"));
+
+                        for i in (start + row..end).step_by(rows) {
+                            scales[i] = scale;
+                        }
+
+                        scales[biases.start + row] = input_scale * scale;
+                    }
+                }
+                _ => scales[start..end].fill(f64::from(val)),
+            }
+        }
+
+        scales
+    }
+
+    fn transpose_affine_weights(&self, raw: &[f32]) -> Vec<f32> {
+        let mut weights = raw.to_vec();
+        let mut offset = self.ft.weights.num_elements() + self.ft.biases.num_elements();
+
+        for node in &self.nodes {
+            match &node.op {
+                Operation::Affine(Affine { weights: w, biases, .. }) => {
+                    let (rows, cols) = (biases.num_elements(), w.num_elements() / biases.num_elements());
+
+                    for row in 0..rows {
+                        for col in 0..cols {
+                            weights[offset + col * rows + row] = raw[offset + row * cols + col];
+                        }
+                    }
+
+                    offset += w.num_elements() + rows;
+                }
+                Operation::Attention(attention) => {
+                    offset += attention.weights.num_elements() + attention.biases.num_elements();
+                }
+                Operation::BatchNorm(norm) => offset += 4 * norm.gamma.num_elements(),
+                Operation::Convolution(conv) => offset += conv.weights.num_elements() + conv.biases.num_elements(),
+                Operation::Gather(gather) => offset += gather.weights.num_elements(),
+                Operation::GroupedAffine { affine, .. } => {
+                    offset += affine.weights.num_elements() + affine.biases.num_elements();
+                }
+                Operation::LayerNorm(norm) => offset += norm.gamma.num_elements() + norm.beta.num_elements(),
+                Operation::PReLU(prelu) => offset += prelu.slopes.num_elements(),
+                _ => {}
+            }
+        }
+
+        weights
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{inputs::InputType, loader::Feat, outputs::OutputBuckets, tensor::TensorBatch};
+
+use super::{
+    ansi,
+    components::{Attention, BatchNorm, Concat, Dropout, Ema, Multiply, Operation, SharedAffine, Swa},
+    Trainer,
+};
+
+/// A single row of a [`MemoryReport`], in bytes of device memory.
+#[derive(Clone, Debug)]
+pub struct NodeMemory {
+    pub name: String,
+    /// Outputs kept for backprop, which it overwrites with their errors,
+    /// or the weights of the network for the optimiser.
+    pub values: usize,
+    /// Gradients of the parameters, and of any inputs that are
+    /// accumulated separately from the errors of their node.
+    pub gradients: usize,
+    /// Scratch space, or the state of the optimiser and any
+    /// averaged copies of the weights.
+    pub workspace: usize,
+}
+
+impl NodeMemory {
+    pub fn total(&self) -> usize {
+        self.values + self.gradients + self.workspace
+    }
+}
+
+/// Device memory allocated by a trainer at its current batch size,
+/// for estimating how large a network or batch fits on a device.
+/// Buffers allocated once training starts, such as those of each
+/// head of a `Loss::MultiHead`, are not counted unless they are shared.
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    pub batch_size: usize,
+    pub nodes: Vec<NodeMemory>,
+    /// Bytes saved by buffers with disjoint lifetimes sharing memory,
+    /// which are counted in their own row rather than by each node.
+    pub shared_savings: usize,
+}
+
+impl MemoryReport {
+    pub fn total(&self) -> usize {
+        self.nodes.iter().map(NodeMemory::total).sum()
+    }
+}
+
+fn mib(bytes: usize) -> String {
+    format!("{:.2}", bytes as f64 / (1024.0 * 1024.0))
+}
+
+impl std::fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rule = "-".repeat(82);
+        writeln!(f, "{:<24} {:>14} {:>14} {:>14} {:>12}", "Node (MiB)", "Values", "Gradients", "Workspace", "Total")?;
+        writeln!(f, "{rule}")?;
+
+        for node in &self.nodes {
+            let (values, gradients, workspace) = (mib(node.values), mib(node.gradients), mib(node.workspace));
+            writeln!(f, "{:<24} {values:>14} {gradients:>14} {workspace:>14} {:>12}", node.name, mib(node.total()))?;
+        }
+
+        writeln!(f, "{rule}")?;
+        write!(f, "Device Memory          : {} MiB (batch size {})", mib(self.total()), self.batch_size)?;
+
+        if self.shared_savings > 0 {
+            write!(f, "\nSaved by Sharing       : {} MiB", mib(self.shared_savings))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn batch_bytes(tensor: &TensorBatch) -> usize {
+    4 * tensor.num_elements()
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Lists the device memory allocated for each node of the network, and
+    /// for the loss and optimiser, at the current batch size.
+    pub fn memory_report(&self) -> MemoryReport {
+        let summary = self.summary();
+        let ft = &self.ft;
+        let shared = self.shared.is_some();
+        let unshared = |bytes: usize| if shared { 0 } else { bytes };
+
+        let mut nodes = vec![NodeMemory {
+            name: summary.layers[0].name.clone(),
+            values: batch_bytes(&ft.outputs) + unshared(ft.copy.as_ref().map_or(0, batch_bytes)),
+            gradients: 4 * summary.layers[0].params,
+            workspace: std::mem::size_of::<Feat>() * self.inputs.num_elements(),
+        }];
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let recomputed = self.recompute.as_ref().is_some_and(|recompute| recompute.nodes[i]);
+            let mut gradients = 4 * summary.layers[i + 1].params;
+
+            let workspace = match &node.op {
+                Operation::Affine(affine) | Operation::GroupedAffine { affine, .. } => 4 * affine.ones.size(),
+                Operation::Attention(Attention { qkv, attn, qkv_grad, attn_grad, .. }) => {
+                    4 * (qkv.size() + attn.size()) + unshared(4 * (qkv_grad.size() + attn_grad.size()))
+                }
+                Operation::BatchNorm(BatchNorm { batch_mean, batch_rstd, .. }) => {
+                    4 * (batch_mean.size() + batch_rstd.size())
+                }
+                Operation::Concat(Concat { grads, .. }) => {
+                    gradients += unshared(grads.iter().map(batch_bytes).sum());
+                    0
+                }
+                Operation::Dropout(Dropout { mask, .. }) => 4 * mask.size(),
+                Operation::Multiply(Multiply { grad, .. }) => {
+                    gradients += unshared(batch_bytes(grad));
+                    0
+                }
+                Operation::SharedAffine(SharedAffine { affine, .. }) => 4 * affine.ones.size(),
+                _ => 0,
+            };
+
+            // outputs of recomputed nodes are in the shared slots
+            let values = if recomputed { 0 } else { batch_bytes(&node.outputs) };
+
+            nodes.push(NodeMemory { name: summary.layers[i + 1].name.clone(), values, gradients, workspace });
+        }
+
+        if let Some(recompute) = &self.recompute {
+            let workspace = 4 * recompute.size();
+            nodes.push(NodeMemory { name: String::from("Recompute Slots"), values: 0, gradients: 0, workspace });
+        }
+
+        if let Some(shared) = self.shared.as_ref().filter(|shared| shared.size() > 0) {
+            let workspace = 4 * shared.size();
+            nodes.push(NodeMemory { name: String::from("Shared Buffers"), values: 0, gradients: 0, workspace });
+        }
+
+        nodes.push(NodeMemory {
+            name: String::from("Loss"),
+            values: batch_bytes(&self.results),
+            gradients: 0,
+            workspace: batch_bytes(&self.weights)
+                + 4 * self.error_device.size()
+                + self.hard_mining.as_ref().map_or(0, |mining| 4 * mining.mask.size()),
+        });
+
+        let averages = self.ema.as_ref().map_or(0, |Ema { weights, .. }| weights.size())
+            + self.swa.as_ref().map_or(0, |Swa { weights, .. }| weights.size());
+
+        nodes.push(NodeMemory {
+            name: String::from("Optimiser"),
+            values: 4 * self.optimiser.size(),
+            gradients: 0,
+            workspace: 4 * (self.optimiser.state_size() + averages),
+        });
+
+        let shared_savings = self.shared.as_ref().map_or(0, |shared| 4 * shared.saved());
+
+        MemoryReport { batch_size: self.batch_size(), nodes, shared_savings }
+    }
+
+    /// Prints the memory report at the start of each run, to check
+    /// that the network fits on the device before it is trained.
+    pub fn set_memory_report(&mut self, print: bool) {
+        self.show_memory_report = print;
+    }
+
+    pub(super) fn print_memory_report(&self) {
+        if self.show_memory_report {
+            println!("{}", ansi("Memory Usage", "34;1"));
+            println!("{}", self.memory_report());
+        }
+    }
+}
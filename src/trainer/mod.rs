@@ -1,22 +1,78 @@
+mod activations;
 mod builder;
+mod calibrate;
 mod components;
+mod diff;
+mod distributed;
+mod distribution;
+mod dot;
+mod export;
+mod fusion;
+mod hard_mining;
+mod heads;
+mod import;
+mod memory;
+mod pbt;
+mod prefetch;
+mod recompute;
+mod report;
 mod run;
 pub mod schedule;
-
+mod sensitivity;
+mod sharing;
+mod simplify;
+mod summary;
+mod usage;
+mod what_if;
+
+#[cfg(test)]
+#[rustfmt::skip]
+mod tests;
+
+use activations::StatsCollector;
+pub use activations::{ActivationStats, StatsHook};
 pub use builder::TrainerBuilder;
-use components::{Affine, FeatureTransformer, Node, Operation, QuantiseInfo};
+pub use calibrate::{AccumulationLimits, ActivationRange};
+use components::{
+    Affine, Attention, BatchNorm, Concat, Convolution, Dropout, Ema, FeatureTransformer, GameHoldout, Gather,
+    LayerNorm, Multiply, Node, Operation, PReLU, PairwiseMul, QuantiseInfo, Quantised, SharedAffine, SpikeFilter, Swa,
+};
+pub use diff::{LayerDiff, NetDiff};
+use distributed::Distributed;
+pub use distributed::DistributedSettings;
+pub use distribution::EvalDistribution;
+pub use export::OutputTransform;
+use fusion::Fusion;
+use hard_mining::HardMining;
+use heads::HeadBuffers;
+pub use import::ImportFormat;
+pub use memory::{MemoryReport, NodeMemory};
+pub use pbt::{population_based_training, PbtMember, PbtResult, PbtSettings};
+pub use prefetch::PinnedBatch;
+use prefetch::Prefetch;
 use rand_distr::Distribution;
-pub use run::{ansi, run, set_cbcs};
+use recompute::Recompute;
+use report::RunReport;
+pub use run::{ansi, replay_superbatch, run, run_with_mini, set_cbcs};
+use schedule::{BetaScheduler, FreezeScheduler, Loss, RealizedSchedule, WdlScheduler};
+pub use sensitivity::{seed_sensitivity, SeedSensitivity, Spread};
+use sharing::SharedBuffers;
+pub use summary::{ArchSummary, LayerSummary};
+pub use usage::RunUsage;
+pub use what_if::{what_if_finetunes, WhatIf, WhatIfReport, WhatIfResult};
+
+use std::io::Write;
 
 use crate::{
+    inference::{InferenceNet, Layer},
     inputs::InputType,
     loader::GpuDataLoader,
     outputs::OutputBuckets,
     tensor::{self, device_synchronise, DeviceBuffer, DeviceHandles, Optimiser, SparseTensor, TensorBatch},
-    util,
+    util, Activation,
 };
 
-pub struct Trainer<T, U> {
+pub struct Trainer<T: InputType, U> {
     input_getter: T,
     bucket_getter: U,
     handle: DeviceHandles,
@@ -26,11 +82,51 @@ pub struct Trainer<T, U> {
     nodes: Vec<Node>,
     inputs: SparseTensor,
     results: TensorBatch,
+    weights: TensorBatch,
     error_device: DeviceBuffer,
+    spike_filter: Option<SpikeFilter>,
     error: f32,
+    /// Total loss and number of positions trained since the error was last zeroed.
+    position_error: f32,
+    error_positions: usize,
     used: usize,
     quantiser: Vec<QuantiseInfo>,
+    per_row_quantisation: bool,
     buckets: *mut u8,
+    wdl_hook: fn(&T::RequiredDataType, f32) -> f32,
+    weight_hook: Option<fn(&T::RequiredDataType) -> f32>,
+    validation_sample: Vec<T::RequiredDataType>,
+    game_holdout: Option<GameHoldout<T::RequiredDataType>>,
+    input_dropout: Option<WdlScheduler>,
+    hard_mining: Option<HardMining>,
+    ft_freeze: Option<FreezeScheduler>,
+    beta_scheduler: Option<BetaScheduler>,
+    schedule_replay: Option<RealizedSchedule>,
+    swa: Option<Swa>,
+    ema: Option<Ema>,
+    net_version: Option<String>,
+    output_transform: Option<OutputTransform>,
+    accumulation_steps: usize,
+    accumulated_batches: usize,
+    accumulated_positions: usize,
+    accumulation_limits: Option<(AccumulationLimits, Vec<ActivationRange>)>,
+    /// Builder calls that make the network, written at the start of net files.
+    description: String,
+    activation_stats: Vec<StatsCollector>,
+    stats_batches: usize,
+    /// Scratch space for each head of a `Loss::MultiHead`.
+    heads: Vec<HeadBuffers>,
+    recompute: Option<Recompute>,
+    /// Memory shared by buffers with disjoint lifetimes, unless disabled.
+    shared: Option<SharedBuffers>,
+    fusion: Option<Fusion>,
+    show_memory_report: bool,
+    device_power: Option<f32>,
+    distributed: Option<Distributed>,
+    prefetch: Option<Prefetch>,
+    /// Report of the current or last run, kept so that the results of
+    /// matches finishing after the run can still be added to it.
+    report: Option<RunReport>,
 }
 
 impl<T: InputType, U> std::fmt::Display for Trainer<T, U> {
@@ -55,7 +151,7 @@ impl<T: InputType, U> std::fmt::Display for Trainer<T, U> {
 
         for (i, node) in self.nodes.iter().enumerate() {
             match node.op {
-                Operation::Affine(_) => {}
+                Operation::Affine(_) | Operation::GroupedAffine { .. } | Operation::SharedAffine(_) => {}
                 _ => continue,
             }
 
@@ -74,6 +170,8 @@ impl<T: InputType, U> std::fmt::Display for Trainer<T, U> {
 impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
     pub fn set_error_zero(&mut self) {
         self.error = 0.0;
+        self.position_error = 0.0;
+        self.error_positions = 0;
     }
 
     pub fn save(&self, out_dir: &str, name: String) {
@@ -96,9 +194,61 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         util::write_to_bin(&buf3, size, &format!("{path}/velocity.bin"), false)
             .unwrap_or_else(|_| panic!("Writing to [{path}/velocity.bin] failed!"));
 
-        if !self.quantiser.is_empty() {
+        self.save_net_file(&format!("{path}/net.bnet"))
+            .unwrap_or_else(|_| panic!("Writing to [{path}/net.bnet] failed!"));
+
+        if self.optimiser.write_slow_weights_to_host(&mut buf1) {
+            util::write_to_bin(&buf1, size, &format!("{path}/slow.bin"), false)
+                .unwrap_or_else(|_| panic!("Writing to [{path}/slow.bin] failed!"));
+        }
+
+        let ema_only = matches!(self.ema, Some(Ema { ema_only: true, .. }));
+        if !self.quantiser.is_empty() && !ema_only {
             self.save_quantised(&format!("{path}/{name}.bin"));
         }
+
+        if let Some(Ema { steps: 1.., weights, ema_only, .. }) = &self.ema {
+            weights.write_to_host(&mut buf1);
+
+            util::write_to_bin(&buf1, size, &format!("{path}/ema.bin"), false)
+                .unwrap_or_else(|_| panic!("Writing to [{path}/ema.bin] failed!"));
+
+            if !self.quantiser.is_empty() {
+                let out_path = if *ema_only { format!("{path}/{name}.bin") } else { format!("{path}/{name}-ema.bin") };
+                self.write_quantised(&buf1, &out_path);
+            }
+        }
+
+        if let Some(Swa { count: 1.., weights, .. }) = &self.swa {
+            weights.write_to_host(&mut buf1);
+
+            util::write_to_bin(&buf1, size, &format!("{path}/swa.bin"), false)
+                .unwrap_or_else(|_| panic!("Writing to [{path}/swa.bin] failed!"));
+
+            if !self.quantiser.is_empty() {
+                self.write_quantised(&buf1, &format!("{path}/{name}-swa.bin"));
+            }
+        }
+    }
+
+    /// Writes the architecture and weights to a single self-describing file,
+    /// which `TrainerBuilder::load_net_file` rebuilds the trainer from. The file
+    /// starts with lines of text, the first being `bullet-net 1` and each other
+    /// a builder call with its arguments separated by spaces, e.g. `add_layer 16`,
+    /// followed by `weights <n>` and then the `n` weights as little-endian `f32`s,
+    /// laid out as in `params.bin`.
+    pub fn save_net_file(&self, path: &str) -> std::io::Result<()> {
+        let mut weights = vec![0.0; self.net_size()];
+        self.optimiser.write_weights_to_host(&mut weights);
+
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "{}\n{}\nweights {}", builder::NET_FILE_HEADER, self.description, weights.len())?;
+
+        for weight in weights {
+            file.write_all(&weight.to_le_bytes())?;
+        }
+
+        file.flush()
     }
 
     pub fn save_quantised(&self, out_path: &str) {
@@ -106,28 +256,253 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         let mut buf = vec![0.0; size];
 
         self.optimiser.write_weights_to_host(&mut buf);
+        self.write_quantised(&buf, out_path);
+    }
+
+    /// Writes the quantised weights of this net followed by those of
+    /// `mini`, e.g. a tiny net used by the engine for lazy evaluation
+    /// or pruning, each padded to a multiple of 64 bytes, so that the
+    /// mini net starts at the first multiple of 64 after this one.
+    pub fn save_quantised_combined<T2, U2>(&self, mini: &Trainer<T2, U2>, out_path: &str)
+    where
+        T2: InputType,
+        U2: OutputBuckets<T2::RequiredDataType>,
+    {
+        assert!(!self.quantiser.is_empty() && !mini.quantiser.is_empty(), "Both nets need quantisations!");
+        let (Some(main), Some(mini)) = (self.quantised_weights(), mini.quantised_weights()) else { return };
+
+        util::write_to_bin(&main, main.len(), out_path, true)
+            .and_then(|_| util::append_to_bin(&mini, mini.len(), out_path, true))
+            .unwrap_or_else(|_| panic!("Writing to [{out_path}] failed!"));
+    }
+
+    fn quantised_weights(&self) -> Option<Vec<i16>> {
+        let mut buf = vec![0.0; self.optimiser.size()];
+        self.optimiser.write_weights_to_host(&mut buf);
+
+        let (buf, quantiser) = self.export_weights(&buf);
+        let quantised = self.quantise(&buf, &quantiser)?;
+        self.check_accumulation(&quantised).then_some(quantised.weights)
+    }
+
+    fn write_quantised(&self, buf: &[f32], out_path: &str) {
+        let (buf, quantiser) = self.export_weights(buf);
+        let size = buf.len();
+        let Some(quantised) = self.quantise(&buf, &quantiser) else { return };
+        if !self.check_accumulation(&quantised) {
+            return;
+        }
+
+        util::write_to_bin(&quantised.weights, size, out_path, true)
+            .unwrap_or_else(|_| panic!("Writing to [{out_path}] failed!"));
+
+        if let Some(version) = &self.net_version {
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(out_path)
+                .unwrap_or_else(|_| panic!("Opening [{out_path}] failed!"));
+
+            let mut trailer = version.as_bytes().to_vec();
+            trailer.resize(trailer.len().next_multiple_of(64), 0);
+            file.write_all(&trailer).unwrap_or_else(|_| panic!("Writing to [{out_path}] failed!"));
+        }
+
+        self.write_manifest(out_path).unwrap_or_else(|_| panic!("Writing manifest for [{out_path}] failed!"));
+
+        if self.per_row_quantisation {
+            let scales_path = format!("{}-scales.bin", out_path.trim_end_matches(".bin"));
+            let scales = &quantised.row_scales;
+            util::write_to_bin(scales, scales.len(), &scales_path, true)
+                .unwrap_or_else(|_| panic!("Writing to [{scales_path}] failed!"));
+        }
+    }
+
+    /// Sets a version string that is appended to each quantised net, zero
+    /// padded to a multiple of 64 bytes, and recorded in its manifest.
+    pub fn set_net_version(&mut self, version: &str) {
+        self.net_version = Some(version.to_string());
+    }
+
+    /// Writes `<net>.manifest` alongside the quantised net at `net_path`, with its
+    /// SHA-256, size in bytes, architecture, version and output transform, so that
+    /// engines can verify that they embed the intended net.
+    fn write_manifest(&self, net_path: &str) -> std::io::Result<()> {
+        let bytes = std::fs::read(net_path)?;
+        let hash: String = util::sha256(&bytes).iter().map(|byte| format!("{byte:02x}")).collect();
+
+        let path = format!("{}.manifest", net_path.trim_end_matches(".bin"));
+        let mut file = std::fs::File::create(path)?;
+
+        writeln!(file, "sha256: {hash}")?;
+        writeln!(file, "size: {}", bytes.len())?;
+        writeln!(file, "arch: {self}")?;
+        if let Some(version) = &self.net_version {
+            writeln!(file, "version: {version}")?;
+        }
+        if let Some(transform) = &self.output_transform {
+            writeln!(file, "output: {transform}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Prints the maximum and mean absolute error introduced
+    /// by quantisation, for each quantised block of the network.
+    pub fn report_quantisation_error(&self) {
+        let mut buf = vec![0.0; self.optimiser.size()];
+
+        self.optimiser.write_weights_to_host(&mut buf);
+
+        let (buf, quantiser) = self.export_weights(&buf);
+        let size = buf.len();
+        let Some(quantised) = self.quantise(&buf, &quantiser) else { return };
+
+        let mut qiter = quantiser.iter().enumerate().peekable();
+        while let Some((i, &QuantiseInfo { start, .. })) = qiter.next() {
+            let end = qiter.peek().map_or(size, |(_, next)| next.start);
+
+            let mut max = 0.0f64;
+            let mut total = 0.0;
+            for ((&q, &scale), &weight) in
+                quantised.weights.iter().zip(&quantised.scales).zip(&buf).take(end).skip(start)
+            {
+                let error = (f64::from(q) / scale - f64::from(weight)).abs();
+                max = max.max(error);
+                total += error;
+            }
+
+            let mean = total / (end - start) as f64;
+            println!("Quantisation Block {i:>2}  : max error {}, mean error {}", ansi(max, 31), ansi(mean, 31));
+        }
+    }
+
+    /// Folds each batch norm into the affine layer before it, which is exact
+    /// using the running statistics, returning the weights with the batch norm
+    /// parameters removed and the quantisation blocks moved to match.
+    fn fold_batch_norms(&self, buf: &[f32]) -> (Vec<f32>, Vec<QuantiseInfo>) {
+        let mut folded = buf.to_vec();
+        let mut removed = Vec::new();
+        let mut offset = self.ft.weights.num_elements() + self.ft.biases.num_elements();
+        let mut prev_affine = None;
+
+        for Node { op, .. } in &self.nodes {
+            match op {
+                Operation::Affine(Affine { weights, biases, .. }) => {
+                    let rows = biases.num_elements();
+                    prev_affine = Some((offset, offset + weights.num_elements(), rows));
+                    offset += weights.num_elements() + rows;
+                }
+                Operation::BatchNorm(BatchNorm { gamma, .. }) => {
+                    let size = gamma.num_elements();
+                    let (weights_start, biases_start, rows) = prev_affine.expect("BatchNorm must follow an affine!");
+
+                    // rows of a bucketed layer share the statistics of their output
+                    for row in 0..rows {
+                        let [gamma, beta, mean, var] = [0, 1, 2, 3].map(|i| buf[offset + i * size + row % size]);
+                        let scale = gamma / (var + 1e-5).sqrt();
+
+                        for i in (weights_start + row..biases_start).step_by(rows) {
+                            folded[i] *= scale;
+                        }
+
+                        let bias = &mut folded[biases_start + row];
+                        *bias = (*bias - mean) * scale + beta;
+                    }
+
+                    removed.push(offset..offset + 4 * size);
+                    offset += 4 * size;
+                }
+                Operation::LayerNorm(LayerNorm { gamma, beta, .. }) => {
+                    offset += gamma.num_elements() + beta.num_elements();
+                }
+                Operation::Attention(Attention { weights, biases, .. })
+                | Operation::Convolution(Convolution { weights, biases, .. }) => {
+                    prev_affine = None;
+                    offset += weights.num_elements() + biases.num_elements();
+                }
+                Operation::Gather(Gather { weights, .. }) => offset += weights.num_elements(),
+                Operation::GroupedAffine { affine, .. } => {
+                    prev_affine = None;
+                    offset += affine.weights.num_elements() + affine.biases.num_elements();
+                }
+                Operation::PReLU(PReLU { slopes, .. }) => offset += slopes.num_elements(),
+                _ => {}
+            }
+        }
+
+        let mut weights = Vec::with_capacity(buf.len());
+        let mut start = 0;
+        for range in &removed {
+            weights.extend_from_slice(&folded[start..range.start]);
+            start = range.end;
+        }
+        weights.extend_from_slice(&folded[start..]);
+
+        let quantiser = self
+            .quantiser
+            .iter()
+            .map(|&QuantiseInfo { val, start, rows, factors }| {
+                let shift: usize = removed.iter().filter(|range| range.start < start).map(ExactSizeIterator::len).sum();
+                QuantiseInfo { val, start: start - shift, rows, factors }
+            })
+            .collect();
+
+        (weights, quantiser)
+    }
+
+    fn quantise(&self, buf: &[f32], quantiser: &[QuantiseInfo]) -> Option<Quantised> {
+        let size = buf.len();
+        let mut quantised = Quantised { weights: vec![0; size], scales: vec![0.0; size], row_scales: Vec::new() };
+
+        let mut qiter = quantiser.iter().peekable();
+        while let Some(&QuantiseInfo { val, start, rows, factors }) = qiter.next() {
+            let end = qiter.peek().map_or(size, |next| next.start);
+
+            match rows {
+                Some(rows) if self.per_row_quantisation => {
+                    let biases = qiter.next().expect("Affine weights must be followed by biases!");
+                    let input_scale = f64::from(biases.val / val);
+                    let limit = f64::from(i16::MAX);
+
+                    for row in 0..rows {
+                        let weights = (start + row..end).step_by(rows);
+                        let max = weights.clone().fold(0.0f64, |max, i| max.max(f64::from(buf[i]).abs()));
+                        let bias = input_scale * f64::from(buf[biases.start + row]).abs();
+                        let scale = (limit / max.max(bias)).floor().clamp(1.0, f64::from(i32::MAX));
 
-        let mut qbuf = vec![0i16; size];
-        let mut qiter = self.quantiser.iter().peekable();
-        while let Some(&QuantiseInfo { val, start }) = qiter.next() {
-            let end = if let Some(QuantiseInfo { start: next_start, .. }) = qiter.peek() { *next_start } else { size };
-
-            for i in start..end {
-                let qf = (f64::from(val) * f64::from(buf[i])).trunc();
-                let q = qf as i16;
-                if f64::from(q) != qf {
-                    println!("================= WARNING ================");
-                    println!("   An error occured during quantisation:  ");
-                    println!("     > Cannot convert \"{qf:.0}\"");
-                    println!("   You will need to quantise manually.    ");
-                    println!("==========================================");
-                    return;
+                        quantised.row_scales.push(scale as i32);
+
+                        for i in weights.chain(std::iter::once(biases.start + row)) {
+                            let scale = if i < end { scale } else { input_scale * scale };
+
+                            if let Err(qf) = quantised.set(buf, i, scale) {
+                                quantisation_warning(qf, None);
+                                return None;
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    for i in start..end {
+                        if let Err(qf) = quantised.set(buf, i, f64::from(val)) {
+                            // the shift of the last quantisation of the block, given the rest
+                            let shift = (val.count_ones() == 1).then(|| {
+                                let last = factors.1 - 1;
+                                let own = quantiser.iter().find(|info| info.factors == (last, last + 1)).unwrap().val;
+                                let max = buf[start..end].iter().fold(0.0f32, |max, x| max.max(x.abs()));
+                                let before = i64::from(val.trailing_zeros() - own.trailing_zeros());
+                                (last, calibrate::max_i16_shift(max) - before)
+                            });
+
+                            quantisation_warning(qf, shift);
+                            return None;
+                        }
+                    }
                 }
-                qbuf[i] = q;
             }
         }
 
-        util::write_to_bin(&qbuf, size, out_path, true).unwrap_or_else(|_| panic!("Writing to [{out_path}] failed!"));
+        Some(quantised)
     }
 
     fn load_from_bin(&self, path: &str) -> Vec<f32> {
@@ -162,6 +537,7 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
     pub fn set_threads(&mut self, threads: usize) {
         self.handle.set_threads(threads);
         self.error_device = DeviceBuffer::new(threads);
+        self.optimiser.set_threads(threads);
     }
 
     pub fn load_weights_from_file(&self, path: &str) {
@@ -169,15 +545,123 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         self.optimiser.load_weights_from_host(&network);
     }
 
-    pub fn load_from_checkpoint(&self, path: &str) {
+    pub fn load_from_checkpoint(&mut self, path: &str) {
         let network = self.load_from_bin(format!("{path}/params.bin").as_str());
         let momentum = self.load_from_bin(format!("{path}/momentum.bin").as_str());
         let velocity = self.load_from_bin(format!("{path}/velocity.bin").as_str());
 
-        self.optimiser.load_from_cpu(&network, &momentum, &velocity)
+        self.optimiser.load_from_cpu(&network, &momentum, &velocity);
+
+        let slow_path = format!("{path}/slow.bin");
+        if self.optimiser.has_lookahead() && std::path::Path::new(&slow_path).exists() {
+            let slow = self.load_from_bin(&slow_path);
+            self.optimiser.load_slow_weights_from_host(&slow);
+        }
+    }
+
+    /// Writes the training state not held in the weight buffers, so
+    /// that a run resumed from this checkpoint matches an uninterrupted one.
+    pub(super) fn save_state(&self, path: &str, superbatch: usize) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(format!("{path}/state.txt"))?;
+
+        writeln!(file, "superbatch: {superbatch}")?;
+        writeln!(file, "step: {}", self.optimiser.step())?;
+
+        if let Some(swa) = &self.swa {
+            writeln!(file, "swa_count: {}", swa.count)?;
+        }
+
+        if let Some(ema) = &self.ema {
+            writeln!(file, "ema_steps: {}", ema.steps)?;
+        }
+
+        if let Some(filter) = &self.spike_filter {
+            let history: Vec<_> = filter.history.iter().map(f32::to_string).collect();
+            writeln!(file, "spike_history: {}", history.join(" "))?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads a checkpoint saved during `run`, returning the
+    /// superbatch it was saved at.
+    pub(super) fn resume_from_checkpoint(&mut self, path: &str) -> usize {
+        self.load_from_checkpoint(path);
+
+        let state_path = format!("{path}/state.txt");
+        let state = std::fs::read_to_string(&state_path).unwrap_or_else(|_| panic!("Reading [{state_path}] failed!"));
+
+        let mut superbatch = None;
+        for line in state.lines() {
+            let (key, value) = line.split_once(": ").unwrap_or((line.trim_end_matches(':'), ""));
+
+            match key {
+                "superbatch" => superbatch = Some(value.parse().expect("Invalid superbatch!")),
+                "step" => self.optimiser.set_step(value.parse().expect("Invalid step!")),
+                "swa_count" => {
+                    let swa = self.swa.as_mut().expect("Checkpoint uses SWA!");
+                    swa.count = value.parse().expect("Invalid SWA count!");
+
+                    if swa.count > 0 {
+                        let weights = self.load_from_bin(&format!("{path}/swa.bin"));
+                        self.swa.as_ref().unwrap().weights.load_from_host(&weights);
+                    }
+                }
+                "ema_steps" => {
+                    let ema = self.ema.as_mut().expect("Checkpoint uses EMA!");
+                    ema.steps = value.parse().expect("Invalid EMA steps!");
+
+                    if ema.steps > 0 {
+                        let weights = self.load_from_bin(&format!("{path}/ema.bin"));
+                        self.ema.as_ref().unwrap().weights.load_from_host(&weights);
+                    }
+                }
+                "spike_history" => {
+                    if let Some(filter) = &mut self.spike_filter {
+                        filter.history = value.split_whitespace().map(|x| x.parse().expect("Invalid norm!")).collect();
+                    }
+                }
+                _ => panic!("Unknown key [{key}] in [{state_path}]!"),
+            }
+        }
+
+        superbatch.expect("No superbatch in state file!")
+    }
+
+    /// Copies the full training state of `other`, which must have the same
+    /// architecture, straight between device buffers without going through
+    /// files on the host, so that forked candidates continue exactly where
+    /// `other` left off. To fork onto another GPU, call `tensor::set_device`
+    /// before building this trainer, and whenever switching between them.
+    pub fn load_state_from(&mut self, other: &Self) {
+        assert_eq!(self.net_size(), other.net_size(), "Trainers have different architectures!");
+
+        self.optimiser.load_state_from(&other.optimiser);
+
+        if let (Some(swa), Some(theirs)) = (&mut self.swa, &other.swa) {
+            swa.count = theirs.count;
+            swa.weights.load_from_device(&theirs.weights);
+        }
+
+        if let (Some(ema), Some(theirs)) = (&mut self.ema, &other.ema) {
+            ema.steps = theirs.steps;
+            ema.weights.load_from_device(&theirs.weights);
+        }
+
+        if let (Some(filter), Some(theirs)) = (&mut self.spike_filter, &other.spike_filter) {
+            filter.history = theirs.history.clone();
+        }
+
+        self.accumulated_batches = other.accumulated_batches;
+        self.accumulated_positions = other.accumulated_positions;
+        self.error = other.error;
+        self.position_error = other.position_error;
+        self.error_positions = other.error_positions;
     }
 
     pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.prefetch = None;
+
         if !self.buckets.is_null() {
             unsafe { tensor::util::free(self.buckets, self.batch_size()) }
         }
@@ -191,16 +675,69 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         }
 
         self.results = TensorBatch::new(self.results.shape(), batch_size);
+        self.weights = TensorBatch::new(self.weights.shape(), batch_size);
         self.ft.outputs = TensorBatch::new(self.ft.outputs.shape(), batch_size);
-        self.ft.copy = TensorBatch::new(self.ft.copy.shape(), batch_size);
 
-        for node in &mut self.nodes {
-            node.outputs = TensorBatch::new(node.outputs.shape(), batch_size);
+        // shared buffers are pointed into new slots afterwards
+        let shared = self.shared.is_some();
+        if self.ft.copy.is_some() && !shared {
+            self.ft.copy = Some(TensorBatch::new(self.ft.outputs.shape(), batch_size));
+        }
+
+        // the scales of each head are only allocated for one batch size
+        self.heads.clear();
+
+        let recomputed = self.recompute.take().map(|recompute| recompute.nodes);
+
+        for (i, node) in self.nodes.iter_mut().enumerate() {
+            // recomputable nodes are pointed into shared memory afterwards
+            if !recomputed.as_ref().is_some_and(|recomputed| recomputed[i]) {
+                node.outputs = TensorBatch::new(node.outputs.shape(), batch_size);
+            }
+
+            match &mut node.op {
+                Operation::Concat(Concat { grads, .. }) if !shared => {
+                    for grad in grads {
+                        *grad = TensorBatch::new(grad.shape(), batch_size);
+                    }
+                }
+                Operation::Attention(attention) => {
+                    let Attention { desc, qkv, attn, qkv_grad, attn_grad, .. } = attention;
+                    *qkv = DeviceBuffer::new(batch_size * desc.len * desc.num_biases());
+                    *attn = DeviceBuffer::new(batch_size * desc.heads * desc.len * desc.len);
+                    if !shared {
+                        *qkv_grad = DeviceBuffer::new(qkv.size());
+                        *attn_grad = DeviceBuffer::new(attn.size());
+                    }
+                }
+                Operation::Dropout(dropout) => dropout.mask = DeviceBuffer::new(node.outputs.num_elements()),
+                Operation::Multiply(Multiply { grad, .. }) if !shared => {
+                    *grad = TensorBatch::new(grad.shape(), batch_size);
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(recomputed) = recomputed {
+            self.recompute = Recompute::new(&mut self.nodes, recomputed, batch_size);
         }
+
+        self.share_buffers();
     }
 
     pub fn randomise_weights(&self, init_biases: bool, use_gaussian: bool) {
-        use rand::{rngs::ThreadRng, thread_rng};
+        self.randomise_weights_with(&mut rand::thread_rng(), init_biases, use_gaussian);
+    }
+
+    /// Re-initialises the weights as when the trainer is built, but
+    /// reproducibly, from an RNG seeded with `seed`.
+    pub fn randomise_weights_seeded(&self, seed: u64) {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        self.randomise_weights_with(&mut StdRng::seed_from_u64(seed), true, true);
+    }
+
+    fn randomise_weights_with(&self, rng: &mut impl rand::Rng, init_biases: bool, use_gaussian: bool) {
         use rand_distr::{Normal, Uniform};
 
         enum Dist {
@@ -217,7 +754,7 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
                 }
             }
 
-            fn sample(&self, rng: &mut ThreadRng) -> f32 {
+            fn sample(&self, rng: &mut impl rand::Rng) -> f32 {
                 match self {
                     Dist::Normal(x) => x.sample(rng),
                     Dist::Uniform(x) => x.sample(rng),
@@ -227,8 +764,6 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
 
         let mut network = vec![0.0; self.net_size()];
 
-        let mut rng = thread_rng();
-
         let ft_wsize = self.ft.weights.num_elements();
         let ft_bsize = self.ft.biases.num_elements();
         let input_size = self.ft.weights.shape().cols();
@@ -237,39 +772,109 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         let dist = Dist::new(stdev, use_gaussian);
 
         for weight in network.iter_mut().take(ft_wsize) {
-            *weight = dist.sample(&mut rng);
+            *weight = dist.sample(rng);
         }
 
         let mut offset = ft_wsize;
 
         if init_biases {
             for weight in network.iter_mut().skip(offset).take(ft_bsize) {
-                *weight = dist.sample(&mut rng);
+                *weight = dist.sample(rng);
             }
         }
 
         offset += ft_bsize;
 
         for Node { op, .. } in &self.nodes {
-            if let Operation::Affine(
-                Affine { weights, biases, .. }
-            ) = op {
+            if let Operation::PReLU(PReLU { slopes, .. }) = op {
+                // the initial slope used in the original paper
+                let channels = slopes.num_elements();
+                network[offset..offset + channels].fill(0.25);
+                offset += channels;
+            }
+
+            if let Operation::Gather(Gather { weights, .. }) = op {
+                // starts at zero, so adds nothing in a residual block
+                offset += weights.num_elements();
+            }
+
+            if let Operation::BatchNorm(BatchNorm { gamma, .. }) = op {
+                // gamma, beta, running mean and running variance
+                let size = gamma.num_elements();
+                network[offset..offset + size].fill(1.0);
+                network[offset + 3 * size..offset + 4 * size].fill(1.0);
+                offset += 4 * size;
+            }
+
+            if let Operation::LayerNorm(LayerNorm { gamma, beta, .. }) = op {
+                // starts as plain normalisation
+                network[offset..offset + gamma.num_elements()].fill(1.0);
+                offset += gamma.num_elements() + beta.num_elements();
+            }
+
+            if let Operation::Convolution(Convolution { desc, weights, biases, .. }) = op {
+                let (kernel_height, kernel_width) = desc.kernel;
+                let fan_in = desc.group_inputs() * kernel_height * kernel_width;
+                let dist = Dist::new((1.0 / fan_in as f32).sqrt(), use_gaussian);
+
+                let wsize = weights.num_elements();
+                for weight in network.iter_mut().skip(offset).take(wsize) {
+                    *weight = dist.sample(rng);
+                }
+
+                offset += wsize;
+
+                if init_biases {
+                    for weight in network.iter_mut().skip(offset).take(biases.num_elements()) {
+                        *weight = dist.sample(rng);
+                    }
+                }
+
+                offset += biases.num_elements();
+            }
+
+            if let Operation::Attention(Attention { desc, weights, biases, .. }) = op {
+                let dist = Dist::new((1.0 / desc.dim as f32).sqrt(), use_gaussian);
+
+                let wsize = weights.num_elements();
+                for weight in network.iter_mut().skip(offset).take(wsize) {
+                    *weight = dist.sample(rng);
+                }
+
+                offset += wsize;
+
+                if init_biases {
+                    for weight in network.iter_mut().skip(offset).take(biases.num_elements()) {
+                        *weight = dist.sample(rng);
+                    }
+                }
+
+                offset += biases.num_elements();
+            }
+
+            let affine = match op {
+                Operation::Affine(affine) => Some((affine, 1)),
+                Operation::GroupedAffine { groups, affine } => Some((affine, *groups)),
+                _ => None,
+            };
+
+            if let Some((Affine { weights, biases, .. }, groups)) = affine {
                 let wsize = weights.num_elements();
                 let bsize = biases.num_elements();
-                let input_size = weights.shape().cols();
+                let input_size = weights.shape().cols() / groups;
 
                 let stdev = (1.0 / input_size as f32).sqrt();
                 let dist = Dist::new(stdev, use_gaussian);
 
                 for weight in network.iter_mut().skip(offset).take(wsize) {
-                    *weight = dist.sample(&mut rng);
+                    *weight = dist.sample(rng);
                 }
 
                 offset += wsize;
 
                 if init_biases {
                     for weight in network.iter_mut().skip(offset).take(bsize) {
-                        *weight = dist.sample(&mut rng);
+                        *weight = dist.sample(rng);
                     }
                 }
 
@@ -282,12 +887,178 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
 
     pub fn set_ft_reg(&mut self, val: f32) {
         self.ft_reg = val;
+
+        // the copy of the FT outputs is only read when regularising
+        let needs_copy = val != 0.0;
+        if needs_copy != self.ft.copy.is_some() {
+            self.ft.copy = needs_copy.then(|| TensorBatch::new(self.ft.outputs.shape(), self.ft.outputs.cap()));
+            self.share_buffers();
+        }
+    }
+
+    /// Sets a hook that receives each position and the scheduled WDL blend,
+    /// and returns the blend actually used for that position's target.
+    pub fn set_wdl_hook(&mut self, hook: fn(&T::RequiredDataType, f32) -> f32) {
+        self.wdl_hook = hook;
+    }
+
+    pub fn wdl_hook(&self) -> fn(&T::RequiredDataType, f32) -> f32 {
+        self.wdl_hook
     }
 
+    /// Randomly drops active input features during training, with the
+    /// probability of dropping each feature scheduled like the WDL blend.
+    pub fn set_input_dropout(&mut self, rate: WdlScheduler) {
+        self.input_dropout = Some(rate);
+    }
+
+    pub fn input_dropout(&self) -> Option<WdlScheduler> {
+        self.input_dropout
+    }
+
+    /// Stochastic Weight Averaging: keeps an average of the weights at
+    /// the end of each superbatch from `start` onwards, which is saved
+    /// to `swa.bin` in each checkpoint, and quantised to `<net>-swa.bin`.
+    pub fn set_swa(&mut self, start: usize) {
+        self.swa = Some(Swa { start, count: 0, weights: DeviceBuffer::new(self.net_size()) });
+    }
+
+    fn update_swa(&mut self, superbatch: usize) {
+        if let Some(swa) = &mut self.swa {
+            if superbatch >= swa.start {
+                swa.count += 1;
+                self.optimiser.update_average(self.handle, 1.0 / swa.count as f32, &swa.weights);
+            }
+        }
+    }
+
+    /// Keeps an exponential moving average of the weights, updated after every
+    /// optimiser step, which is saved to `ema.bin` in each checkpoint. If
+    /// `ema_only` is set the quantised net is made from the EMA weights,
+    /// otherwise they are quantised separately to `<net>-ema.bin`.
+    pub fn set_ema(&mut self, decay: f32, ema_only: bool) {
+        assert!((0.0..1.0).contains(&decay), "EMA decay must be in [0, 1)!");
+        self.ema = Some(Ema { decay, steps: 0, ema_only, weights: DeviceBuffer::new(self.net_size()) });
+    }
+
+    fn update_ema(&mut self) {
+        if let Some(ema) = &mut self.ema {
+            // average uniformly over the first steps, so
+            // that the initial weights don't linger
+            ema.steps += 1;
+            let alpha = (1.0 / ema.steps as f32).max(1.0 - ema.decay);
+            self.optimiser.update_average(self.handle, alpha, &ema.weights);
+        }
+    }
+
+    /// Freezes the feature transformer partway through training, leaving
+    /// only the later layers to be trained.
+    pub fn set_ft_freeze(&mut self, freeze: FreezeScheduler) {
+        self.ft_freeze = Some(freeze);
+    }
+
+    pub fn ft_freeze(&self) -> Option<FreezeScheduler> {
+        self.ft_freeze
+    }
+
+    /// Schedules the betas of the optimiser over the run, which is
+    /// ignored by optimisers without them, such as SGD.
+    pub fn set_beta_scheduler(&mut self, scheduler: BetaScheduler) {
+        self.beta_scheduler = Some(scheduler);
+    }
+
+    pub fn beta_scheduler(&self) -> Option<BetaScheduler> {
+        self.beta_scheduler
+    }
+
+    /// Trains with the learning rate, weight decay and WDL blend recorded by a
+    /// previous run in `<net>-schedule.csv`, in place of those of the schedule,
+    /// which must have the same batch size and batches per superbatch.
+    pub fn set_schedule_replay(&mut self, realized: RealizedSchedule) {
+        self.schedule_replay = Some(realized);
+    }
+
+    pub fn schedule_replay(&self) -> Option<&RealizedSchedule> {
+        self.schedule_replay.as_ref()
+    }
+
+    /// Sets the betas of the optimiser for `superbatch` of a run ending at `end_superbatch`.
+    fn apply_beta_schedule(&mut self, superbatch: usize, end_superbatch: usize) {
+        if let Some(scheduler) = self.beta_scheduler {
+            let (beta1, beta2) = scheduler.betas(superbatch, end_superbatch);
+            self.optimiser.set_betas(beta1, beta2);
+        }
+    }
+
+    /// Scales the feature transformer's learning rate for `superbatch`.
+    fn apply_ft_freeze(&mut self, superbatch: usize) {
+        if let Some(freeze) = self.ft_freeze {
+            let ft_size = self.ft.weights.num_elements() + self.ft.biases.num_elements();
+            self.optimiser.set_lr_scale(0, ft_size, freeze.lr_scale(superbatch));
+        }
+    }
+
+    /// Accumulates gradients over `steps` batches before each optimiser
+    /// step, emulating a batch size `steps` times larger. Any batches left
+    /// over at the end of training do not contribute to an update.
+    pub fn set_gradient_accumulation(&mut self, steps: usize) {
+        assert!(steps > 0, "Must accumulate over at least one batch!");
+        self.accumulation_steps = steps;
+        self.accumulated_batches = 0;
+        self.accumulated_positions = 0;
+    }
+
+    /// Sets a hook that returns the weight of each position's contribution
+    /// to the loss, e.g. to down-weight early-game positions.
+    /// Only supported by `Loss::SigmoidMSE` and `Loss::SigmoidMPE`.
+    pub fn set_weight_hook(&mut self, hook: fn(&T::RequiredDataType) -> f32) {
+        self.weight_hook = Some(hook);
+    }
+
+    pub fn weight_hook(&self) -> Option<fn(&T::RequiredDataType) -> f32> {
+        self.weight_hook
+    }
+
+    /// Holds out every position of a deterministic `fraction` of games from
+    /// training, so that validation positions never share a game with training
+    /// positions. Games are identified by `game`, e.g. from an index stored with
+    /// each position when the data was generated. When running, the first
+    /// `sample_size` held out positions in the data become the validation sample.
+    pub fn set_game_holdout(&mut self, fraction: f32, sample_size: usize, game: fn(&T::RequiredDataType) -> u64) {
+        assert!((0.0..1.0).contains(&fraction), "Invalid holdout fraction {fraction}!");
+        self.game_holdout = Some(GameHoldout { fraction, sample_size, game });
+    }
+
+    /// Skips the update from any batch whose gradient norm exceeds `factor`
+    /// times the median norm of recent batches, to guard against corrupted data.
+    pub fn set_gradient_spike_skip(&mut self, factor: f32) {
+        assert!(factor > 1.0, "Spike factor must be greater than 1!");
+        self.spike_filter = Some(SpikeFilter::new(factor));
+    }
+
+    /// If the previous batch was skipped due to a gradient spike,
+    /// returns its gradient norm and the median it was compared to.
+    pub fn last_skipped_batch(&self) -> Option<(f32, f32)> {
+        self.spike_filter.as_ref().and_then(|filter| filter.last_skipped)
+    }
+
+    /// L2 norm of the current gradients, scaled by `adj`.
+    fn gradient_norm(&self, adj: f32) -> f32 {
+        adj * self.optimiser.gradient_norm(self.handle)
+    }
+
+    /// Sum of the mean loss of each batch trained since the error was last zeroed.
     pub fn error(&self) -> f32 {
         self.error
     }
 
+    /// Mean loss of every position trained since the error was last zeroed,
+    /// which unlike dividing `error` by the number of batches, doesn't give
+    /// the positions of a partial batch more weight than those of a full one.
+    pub fn mean_error(&self) -> f32 {
+        self.position_error / self.error_positions.max(1) as f32
+    }
+
     pub fn input_getter(&self) -> T {
         self.input_getter
     }
@@ -304,6 +1075,68 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         self.optimiser.write_weights_to_host(buf);
     }
 
+    /// Pure CPU copy of the network with its current weights.
+    pub fn inference_net(&self) -> InferenceNet<T, U> {
+        let mut layers = Vec::new();
+        // index of the output of each node in `layers`
+        let mut outputs = vec![0];
+
+        for node in &self.nodes {
+            let layer = match &node.op {
+                Operation::Activate(activation) => Layer::Activate(*activation),
+                Operation::Affine(Affine { weights, biases, .. }) => {
+                    let outputs = biases.num_elements();
+                    Layer::Affine { inputs: weights.num_elements() / outputs, outputs }
+                }
+                Operation::Attention(Attention { desc, .. }) => Layer::Attention { desc: *desc },
+                Operation::BatchNorm(BatchNorm { gamma, .. }) => Layer::BatchNorm { size: gamma.num_elements() },
+                Operation::Clamp { min, max, .. } => Layer::Activate(Activation::BoundedCReLU { min: *min, max: *max }),
+                Operation::Concat(Concat { sources, .. }) => {
+                    Layer::Concat { sources: sources.iter().map(|&source| outputs[source]).collect() }
+                }
+                Operation::Convolution(Convolution { desc, .. }) => Layer::Convolution { desc: *desc },
+                Operation::Dropout(_) => {
+                    outputs.push(layers.len());
+                    continue;
+                }
+                Operation::Gather(Gather { weights, .. }) => Layer::Gather { size: weights.shape().rows() },
+                Operation::GroupedAffine { groups, affine } => Layer::GroupedAffine {
+                    groups: *groups,
+                    inputs: affine.weights.shape().cols(),
+                    outputs: affine.biases.num_elements(),
+                },
+                Operation::LayerNorm(LayerNorm { gamma, .. }) => Layer::Norm { size: gamma.num_elements() },
+                Operation::PReLU(PReLU { slopes, .. }) => Layer::PReLU { channels: slopes.num_elements() },
+                Operation::Select => Layer::Select { size: node.outputs.shape().rows() },
+                Operation::SharedAffine(SharedAffine { source, .. }) => {
+                    Layer::SharedAffine { source: outputs[*source] }
+                }
+                Operation::Multiply(Multiply { source, .. }) => Layer::Multiply { source: outputs[*source] },
+                Operation::PairwiseMul(PairwiseMul { stride, activation }) => {
+                    Layer::PairwiseMul { stride: *stride, activation: *activation }
+                }
+                Operation::Reduce { reduction, axis, cols } => {
+                    Layer::Reduce { reduction: *reduction, axis: *axis, cols: *cols }
+                }
+                Operation::Slice { start } => Layer::Slice { start: *start, len: node.outputs.element_size() },
+                Operation::Softmax { log } => Layer::Softmax { log: *log },
+            };
+
+            layers.push((layer, node.in_res_block));
+            outputs.push(layers.len());
+        }
+
+        let ft_size = self.ft.biases.num_elements();
+        let opp_size = self.ft.outputs.shape().rows() - ft_size;
+        let mut net = InferenceNet::new(self.input_getter, self.bucket_getter, ft_size, opp_size, layers);
+
+        let mut weights = vec![0.0; self.net_size()];
+        self.write_weights_to_cpu(&mut weights);
+        net.load_weights(&weights);
+
+        net
+    }
+
     pub fn clear_data(&mut self) {
         self.used = 0;
         self.inputs.clear();
@@ -312,6 +1145,7 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
     pub fn load_data(&mut self, loader: &GpuDataLoader<T, U>) {
         let inputs = loader.inputs();
         let results = loader.results();
+        let weights = loader.weights();
         let buckets = loader.buckets();
 
         unsafe {
@@ -319,6 +1153,10 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
             self.inputs.append(our);
             self.results.load_from_host(results);
 
+            if !weights.is_empty() {
+                self.weights.load_from_host(weights);
+            }
+
             if U::BUCKETS > 1 {
                 let ptr = buckets.as_ptr();
                 let amt = buckets.len();
@@ -329,10 +1167,29 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         }
     }
 
+    /// Largest batch that can be loaded at once. Smaller batches, such as the
+    /// positions left over at the end of a data file, are trained on as they
+    /// are, with the gradients averaged over the positions they hold.
     pub fn batch_size(&self) -> usize {
         self.ft.outputs.cap()
     }
 
+    fn wdl_outputs(&self) -> bool {
+        self.results.element_size() == 3
+    }
+
+    /// Loads `batch` and runs the forward pass on it.
+    fn forward_batch(&mut self, batch: &[T::RequiredDataType]) {
+        self.clear_data();
+        let mut loader = GpuDataLoader::new(self.input_getter, self.bucket_getter);
+        loader.load(batch, 1, 0.0, 1.0, self.wdl_hook, None, &[self.results.element_size()]);
+        self.load_data(&loader);
+
+        unsafe {
+            self.forward(false);
+        }
+    }
+
     pub fn eval(&mut self, fen: &str) -> f32
     where
         T::RequiredDataType: std::str::FromStr<Err = String>,
@@ -340,35 +1197,68 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         self.clear_data();
         let board = format!("{fen} | 0 | 0.0").parse::<T::RequiredDataType>().expect("Failed to parse position!");
         let mut loader = GpuDataLoader::new(self.input_getter, self.bucket_getter);
-        loader.load(&[board], 1, 0.0, 1.0);
+        loader.load(&[board], 1, 0.0, 1.0, self.wdl_hook, None, &[self.results.element_size()]);
         self.load_data(&loader);
 
         unsafe {
-            self.forward();
+            self.forward(false);
         }
 
         tensor::panic_if_device_error("Something went wrong!");
 
-        let mut eval = vec![0.0; self.batch_size()];
+        let mut eval = vec![0.0; self.results.num_elements()];
         self.nodes.last().expect("Nodes is empty!").outputs.write_to_host(&mut eval);
 
         self.clear_data();
         eval[0]
     }
 
-    pub fn train_on_batch(&mut self, decay: f32, rate: f32, power: f32) -> bool {
+    /// Raw output of the net with its current weights on `fen`, evaluated on
+    /// the CPU, so it can be called from the callbacks passed to `run`
+    /// without disturbing training. Multiply by the eval scale of the
+    /// schedule for an eval in centipawns.
+    pub fn eval_fen(&self, fen: &str) -> f32
+    where
+        T::RequiredDataType: std::str::FromStr<Err = String>,
+    {
+        self.eval_fens(&[fen])[0]
+    }
+
+    /// As `eval_fen`, but only copies the weights off the device once.
+    pub fn eval_fens(&self, fens: &[&str]) -> Vec<f32>
+    where
+        T::RequiredDataType: std::str::FromStr<Err = String>,
+    {
+        let net = self.inference_net();
+        fens.iter().map(|fen| net.eval(fen)).collect()
+    }
+
+    pub fn train_on_batch(&mut self, decay: f32, rate: f32, loss: Loss) -> bool {
         self.optimiser.zero_gradient();
         self.error_device.set_zero();
 
+        self.sample_dropout_masks();
+
         unsafe {
-            self.forward();
-            self.calc_errors(power);
+            self.forward(true);
+        }
+
+        self.collect_activation_stats();
+
+        self.select_hard_positions(loss);
+
+        unsafe {
+            self.calc_errors(loss);
+            self.skip_easy_positions();
             self.backprop();
         }
 
         let mut errors = vec![0.0; self.error_device.size()];
         self.error_device.write_to_host(&mut errors);
-        self.error += errors.iter().sum::<f32>() / self.inputs.used() as f32;
+        let error = errors.iter().sum::<f32>();
+        self.error += error / self.inputs.used() as f32;
+        self.position_error += error;
+        self.error_positions += self.inputs.used();
 
         tensor::panic_if_device_error("Something went wrong!");
 
@@ -376,20 +1266,103 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
             return false;
         }
 
-        let adj = power / self.inputs.used() as f32;
+        self.accumulated_positions += self.inputs.used();
+
+        if self.accumulation_steps > 1 {
+            self.optimiser.accumulate_gradient(self.handle);
+            self.accumulated_batches += 1;
+
+            if self.accumulated_batches < self.accumulation_steps {
+                return true;
+            }
+
+            self.optimiser.take_accumulated_gradient();
+            self.accumulated_batches = 0;
+        }
+
+        let adj = loss.power() / self.accumulated_positions as f32;
+        self.accumulated_positions = 0;
+
+        let norm = self.spike_filter.is_some().then(|| self.gradient_norm(adj));
+
+        if let (Some(norm), Some(filter)) = (norm, &mut self.spike_filter) {
+            if !filter.accept(norm) {
+                return true;
+            }
+        }
+
         self.optimiser.update(self.handle, decay, adj, rate);
+        self.update_ema();
 
         device_synchronise();
         true
     }
 
+    /// Output of the feature transformer if `idx` is 0, otherwise of node `idx - 1`.
+    fn output(&self, idx: usize) -> &TensorBatch {
+        match idx {
+            0 => &self.ft.outputs,
+            _ => &self.nodes[idx - 1].outputs,
+        }
+    }
+
+    /// Adds the gradients of output `idx` from each later node that reads it,
+    /// once the output has been overwritten with its errors.
+    fn add_source_grads(&self, batch_size: usize, idx: usize) {
+        for node in &self.nodes {
+            let grads: Vec<_> = match &node.op {
+                Operation::Concat(Concat { sources, grads }) => sources.iter().zip(grads).collect(),
+                Operation::Multiply(Multiply { source, grad }) => vec![(source, grad)],
+                _ => continue,
+            };
+
+            for (_, grad) in grads.into_iter().filter(|(&source, _)| source == idx) {
+                unsafe {
+                    TensorBatch::add_to(self.handle, batch_size, grad, self.output(idx));
+                }
+            }
+        }
+    }
+
+    /// Output of an earlier node that `node` reads during backprop.
+    fn source(&self, node: &Node) -> Option<&TensorBatch> {
+        match &node.op {
+            Operation::Multiply(Multiply { source, .. }) => Some(self.output(*source)),
+            _ => None,
+        }
+    }
+
+    /// Draws a new mask for each dropout node, for the current batch.
+    fn sample_dropout_masks(&mut self) {
+        use rand::Rng;
+
+        let batch_size = self.inputs.used();
+
+        for node in &mut self.nodes {
+            if let Operation::Dropout(Dropout { rate, mask, rng }) = &mut node.op {
+                let keep = 1.0 / (1.0 - *rate);
+                let values: Vec<f32> = (0..batch_size * node.outputs.element_size())
+                    .map(|_| if rng.gen::<f32>() < *rate { 0.0 } else { keep })
+                    .collect();
+
+                mask.load_from_host(&values);
+            }
+        }
+    }
+
+    /// Batch norms use the statistics of the batch if `training`,
+    /// and update their running statistics, otherwise they use the
+    /// running statistics. Dropout is only applied if `training`.
+    ///
     /// # Safety
     /// It is undefined behaviour to call this if `our_inputs` is not
     /// properly initialised.
-    unsafe fn forward(&self) {
+    unsafe fn forward(&self, training: bool) {
         let batch_size = self.inputs.used();
 
-        if self.ft.single_perspective {
+        if let Some(activation) = self.fused_activation(0) {
+            self.forward_ft_fused(activation);
+        } else if self.ft.single_perspective {
             SparseTensor::single_affine(self.handle, &self.ft.weights, &self.inputs, &self.ft.biases, &self.ft.outputs);
         } else {
             SparseTensor::affine(self.handle, &self.ft.weights, &self.inputs, &self.ft.biases, &self.ft.outputs);
@@ -399,7 +1372,7 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         let mut res_inputs = inputs;
         let mut in_res_block = false;
 
-        for node in &self.nodes {
+        for (i, node) in self.nodes.iter().enumerate() {
             // entering residual block
             if !in_res_block && node.in_res_block {
                 in_res_block = true;
@@ -412,30 +1385,130 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
                 TensorBatch::add_to(self.handle, batch_size, res_inputs, inputs);
             }
 
-            match &node.op {
-                Operation::Activate(activation) => {
-                    TensorBatch::activate(self.handle, batch_size, *activation, inputs, &node.outputs);
-                }
-                Operation::Affine(Affine { weights, biases, .. }) => {
-                    TensorBatch::affine(self.handle, batch_size, weights, inputs, biases, &node.outputs);
-                }
-                Operation::Select => TensorBatch::select(self.handle, batch_size, self.buckets, inputs, &node.outputs),
+            if let Some(activation) = self.fused_activation(i + 1) {
+                self.forward_fused(batch_size, i, activation, inputs);
+            } else if self.fused_activation(i).is_none() {
+                self.forward_single(batch_size, node, inputs, training);
             }
 
             inputs = &node.outputs;
         }
     }
 
+    /// Applies `node` to `inputs`, writing to its outputs.
+    ///
+    /// # Safety
+    /// It is undefined behaviour to call this if `our_inputs` is not
+    /// properly initialised.
+    unsafe fn forward_single(&self, batch_size: usize, node: &Node, inputs: &TensorBatch, training: bool) {
+        match &node.op {
+            Operation::Activate(activation) => {
+                TensorBatch::activate(self.handle, batch_size, *activation, inputs, &node.outputs);
+            }
+            Operation::Affine(Affine { weights, biases, .. })
+            | Operation::SharedAffine(SharedAffine { affine: Affine { weights, biases, .. }, .. }) => {
+                TensorBatch::affine(self.handle, batch_size, weights, inputs, biases, &node.outputs);
+            }
+            Operation::BatchNorm(norm) if training => TensorBatch::batch_norm(
+                self.handle,
+                batch_size,
+                &norm.gamma,
+                &norm.beta,
+                &norm.running_mean,
+                &norm.running_var,
+                &norm.batch_mean,
+                &norm.batch_rstd,
+                BatchNorm::MOMENTUM,
+                inputs,
+                &node.outputs,
+            ),
+            Operation::BatchNorm(norm) => TensorBatch::batch_norm_inference(
+                self.handle,
+                batch_size,
+                &norm.gamma,
+                &norm.beta,
+                &norm.running_mean,
+                &norm.running_var,
+                inputs,
+                &node.outputs,
+            ),
+            Operation::Attention(Attention { desc, weights, biases, qkv, attn, .. }) => {
+                let (w, b, out) = (weights, biases, &node.outputs);
+                TensorBatch::attention(self.handle, desc, batch_size, w, b, inputs, qkv, attn, out);
+            }
+            Operation::Clamp { min, max, .. } => {
+                TensorBatch::clamp(self.handle, batch_size, *min, *max, inputs, &node.outputs);
+            }
+            Operation::Concat(Concat { sources, .. }) => {
+                TensorBatch::concat_into(self.handle, batch_size, inputs, &node.outputs, 0);
+                let mut offset = inputs.element_size();
+
+                for &source in sources {
+                    let source = self.output(source);
+                    TensorBatch::concat_into(self.handle, batch_size, source, &node.outputs, offset);
+                    offset += source.element_size();
+                }
+            }
+            Operation::Convolution(Convolution { desc, weights, biases, .. }) => {
+                TensorBatch::convolution(self.handle, desc, batch_size, weights, biases, inputs, &node.outputs);
+            }
+            Operation::Dropout(Dropout { mask, .. }) if training => {
+                TensorBatch::masked_scale(self.handle, batch_size, mask, inputs, &node.outputs);
+            }
+            Operation::Dropout(_) => node.outputs.copy_from(inputs),
+            Operation::GroupedAffine { groups, affine: Affine { weights: w, biases: b, .. } } => {
+                TensorBatch::grouped_affine(self.handle, batch_size, *groups, w, inputs, b, &node.outputs);
+            }
+            Operation::Gather(Gather { weights, .. }) => {
+                TensorBatch::gather(self.handle, batch_size, self.buckets, weights, &node.outputs);
+            }
+            Operation::LayerNorm(LayerNorm { gamma, beta, .. }) => {
+                TensorBatch::layer_norm(self.handle, batch_size, gamma, beta, inputs, &node.outputs);
+            }
+            Operation::PReLU(PReLU { slopes, .. }) => {
+                TensorBatch::prelu(self.handle, batch_size, slopes, inputs, &node.outputs);
+            }
+            Operation::PairwiseMul(pairwise) => {
+                let (stride, bounds) = (pairwise.stride, pairwise.bounds());
+                TensorBatch::pairwise_mul(self.handle, batch_size, stride, bounds, inputs, &node.outputs);
+            }
+            Operation::Multiply(Multiply { source, .. }) => {
+                TensorBatch::hadamard(self.handle, batch_size, self.output(*source), inputs, &node.outputs);
+            }
+            Operation::Reduce { reduction, axis, cols } => {
+                TensorBatch::reduce(self.handle, batch_size, *reduction, *axis, *cols, inputs, &node.outputs);
+            }
+            Operation::Select => TensorBatch::select(self.handle, batch_size, self.buckets, inputs, &node.outputs),
+            Operation::Slice { start } => {
+                TensorBatch::split_from(self.handle, batch_size, inputs, *start, &node.outputs);
+            }
+            Operation::Softmax { log } => {
+                TensorBatch::softmax(self.handle, batch_size, *log, inputs, &node.outputs);
+            }
+        }
+    }
+
     /// # Safety
     /// It is undefined behaviour to call this without previously calling
     /// `self.forward`.
-    unsafe fn calc_errors(&self, power: f32) {
+    unsafe fn calc_errors(&mut self, loss: Loss) {
         let batch_size = self.inputs.used();
         let output_layer = self.nodes.last().expect("Nodes is empty!");
 
         assert_eq!(self.results.shape(), output_layer.outputs.shape());
+        assert_eq!(
+            loss.outputs(),
+            self.results.element_size(),
+            "{loss:?} does not match the size of the output layer!"
+        );
+
+        if let Loss::MultiHead(heads) = loss {
+            self.calc_head_errors(heads);
+            return;
+        }
 
-        output_layer.outputs.sigmoid_mpe(self.handle, batch_size, &self.results, &self.error_device, power);
+        let weights = self.weight_hook.map(|_| &self.weights);
+        calc_loss(self.handle, batch_size, loss, &output_layer.outputs, &self.results, weights, &self.error_device);
     }
 
     /// # Safety
@@ -451,20 +1524,26 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
         let mut in_res_block = false;
 
         for node in (1..num_nodes).rev() {
+            self.recompute_inputs(batch_size, node);
+
             backprop_single(
                 self.handle,
                 batch_size,
                 &self.nodes[node],
                 &self.nodes[node - 1].outputs,
+                self.source(&self.nodes[node]),
                 self.nodes[node - 1].in_res_block,
                 self.buckets,
                 &mut res_errors,
                 &mut in_res_block,
             );
+
+            self.add_source_grads(batch_size, node);
         }
 
-        if self.ft_reg != 0.0 {
-            self.ft.copy.copy_from(&self.ft.outputs);
+        // the outputs are about to be overwritten by their errors
+        if let Some(copy) = &self.ft.copy {
+            copy.copy_from(&self.ft.outputs);
         }
 
         backprop_single(
@@ -472,12 +1551,15 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
             batch_size,
             &self.nodes[0],
             &self.ft.outputs,
+            self.source(&self.nodes[0]),
             false,
             self.buckets,
             &mut res_errors,
             &mut in_res_block,
         );
 
+        self.add_source_grads(batch_size, 0);
+
         if self.ft.single_perspective {
             SparseTensor::single_affine_backprop(
                 self.handle,
@@ -485,7 +1567,7 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
                 &self.inputs,
                 &self.ft.biases_grad,
                 &self.ft.outputs,
-                &self.ft.copy,
+                self.ft.copy.as_ref(),
                 self.ft_reg,
             );
         } else {
@@ -495,19 +1577,50 @@ impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
                 &self.inputs,
                 &self.ft.biases_grad,
                 &self.ft.outputs,
-                &self.ft.copy,
+                self.ft.copy.as_ref(),
                 self.ft_reg,
             );
         }
     }
 }
 
+/// Adds the loss of `outputs` against `results` to `error`, replacing
+/// each output with the gradient of the loss with respect to it.
+fn calc_loss(
+    handle: DeviceHandles,
+    batch_size: usize,
+    loss: Loss,
+    outputs: &TensorBatch,
+    results: &TensorBatch,
+    weights: Option<&TensorBatch>,
+    error: &DeviceBuffer,
+) {
+    match (loss, weights) {
+        (Loss::MultiHead(_), _) => panic!("Multi-head losses can't be nested!"),
+        (Loss::SigmoidMSE | Loss::SigmoidMPE(_), Some(weights)) => {
+            outputs.sigmoid_mpe_weighted(handle, batch_size, results, weights, error, loss.power())
+        }
+        (_, Some(_)) => panic!("{loss:?} does not support per-position weights!"),
+        (Loss::SigmoidMSE | Loss::SigmoidMPE(_), None) => {
+            outputs.sigmoid_mpe(handle, batch_size, results, error, loss.power())
+        }
+        (Loss::SigmoidFocal { power, gamma }, None) => {
+            outputs.sigmoid_focal(handle, batch_size, results, error, power, gamma)
+        }
+        (Loss::SigmoidMSEVariance, None) => outputs.sigmoid_mse_variance(handle, batch_size, results, error),
+        (Loss::SigmoidHuber { delta }, None) => outputs.sigmoid_huber(handle, batch_size, results, error, delta),
+        (Loss::SoftmaxWDL, None) => outputs.softmax_crossentropy(handle, batch_size, results, error),
+        (Loss::Custom(loss), None) => loss.calc_errors(handle, batch_size, outputs, results, error),
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 unsafe fn backprop_single<'a>(
     handle: DeviceHandles,
     batch_size: usize,
     this_node: &Node,
     inputs: &'a TensorBatch,
+    source: Option<&TensorBatch>,
     in_res: bool,
     buckets: *const u8,
     res_errors: &mut &'a TensorBatch,
@@ -519,10 +1632,70 @@ unsafe fn backprop_single<'a>(
         Operation::Activate(activation) => {
             TensorBatch::backprop_activation(handle, batch_size, *activation, errors, inputs);
         }
-        Operation::Affine(Affine { weights: w, weights_grad: wg, biases_grad: bg, ones, .. }) => {
+        // shared layers add to the gradients of the layer they share
+        Operation::Affine(Affine { weights: w, weights_grad: wg, biases_grad: bg, ones, .. })
+        | Operation::SharedAffine(SharedAffine {
+            affine: Affine { weights: w, weights_grad: wg, biases_grad: bg, ones, .. },
+            ..
+        }) => {
             TensorBatch::backprop_affine(handle, ones, batch_size, w, errors, inputs, wg, bg);
         }
+        Operation::Attention(attention) => {
+            let Attention { desc, weights: w, weights_grad: wg, biases_grad: bg, qkv, attn, .. } = attention;
+            let (qkv_grad, attn_grad) = (&attention.qkv_grad, &attention.attn_grad);
+            TensorBatch::backprop_attention(
+                handle, desc, batch_size, w, errors, inputs, wg, bg, qkv, attn, qkv_grad, attn_grad,
+            );
+        }
+        Operation::BatchNorm(BatchNorm { gamma, gamma_grad, beta_grad, batch_mean, batch_rstd, .. }) => {
+            TensorBatch::backprop_batch_norm(
+                handle, batch_size, gamma, batch_mean, batch_rstd, gamma_grad, beta_grad, errors, inputs,
+            );
+        }
+        Operation::Clamp { min, max, gradient } => {
+            TensorBatch::backprop_clamp(handle, batch_size, *min, *max, *gradient, errors, inputs);
+        }
+        Operation::Concat(Concat { grads, .. }) => {
+            TensorBatch::split_from(handle, batch_size, errors, 0, inputs);
+            let mut offset = inputs.element_size();
+
+            for grad in grads {
+                TensorBatch::split_from(handle, batch_size, errors, offset, grad);
+                offset += grad.element_size();
+            }
+        }
+        Operation::Convolution(Convolution { desc, weights: w, weights_grad: wg, biases_grad: bg, .. }) => {
+            TensorBatch::backprop_convolution(handle, desc, batch_size, w, errors, inputs, wg, bg);
+        }
+        Operation::Dropout(Dropout { mask, .. }) => TensorBatch::masked_scale(handle, batch_size, mask, errors, inputs),
+        Operation::GroupedAffine { groups, affine } => {
+            let Affine { weights: w, weights_grad: wg, biases_grad: bg, ones, .. } = affine;
+            TensorBatch::backprop_grouped_affine(handle, ones, batch_size, *groups, w, errors, inputs, wg, bg);
+        }
+        Operation::Gather(Gather { weights_grad, .. }) => {
+            TensorBatch::backprop_gather(handle, batch_size, buckets, errors, weights_grad);
+            inputs.set_zero();
+        }
+        Operation::LayerNorm(LayerNorm { gamma, gamma_grad, beta_grad, .. }) => {
+            TensorBatch::backprop_layer_norm(handle, batch_size, gamma, gamma_grad, beta_grad, errors, inputs);
+        }
+        Operation::PReLU(PReLU { slopes, slopes_grad }) => {
+            TensorBatch::backprop_prelu(handle, batch_size, slopes, slopes_grad, errors, inputs);
+        }
         Operation::Select => TensorBatch::select_backprop(handle, batch_size, buckets, errors, inputs),
+        Operation::PairwiseMul(pairwise) => {
+            let (stride, bounds) = (pairwise.stride, pairwise.bounds());
+            TensorBatch::backprop_pairwise_mul(handle, batch_size, stride, bounds, errors, inputs);
+        }
+        Operation::Multiply(Multiply { grad, .. }) => {
+            let source = source.expect("Multiply must be given its source!");
+            TensorBatch::backprop_hadamard(handle, batch_size, source, grad, errors, inputs);
+        }
+        Operation::Reduce { reduction, axis, cols } => {
+            TensorBatch::backprop_reduce(handle, batch_size, *reduction, *axis, *cols, errors, inputs);
+        }
+        Operation::Slice { start } => TensorBatch::backprop_split(handle, batch_size, errors, *start, inputs),
+        Operation::Softmax { log } => TensorBatch::backprop_softmax(handle, batch_size, *log, errors, inputs),
     }
 
     // entering residual block
@@ -537,3 +1710,17 @@ unsafe fn backprop_single<'a>(
         TensorBatch::add_to(handle, batch_size, res_errors, inputs);
     }
 }
+
+/// `largest_shift` is the index of the quantisation that overflowed and
+/// its largest safe shift, if the quantisations are powers of two.
+fn quantisation_warning(qf: f64, largest_shift: Option<(usize, i64)>) {
+    println!("================= WARNING ================");
+    println!("   An error occured during quantisation:  ");
+    println!("     > Cannot convert \"{qf:.0}\"");
+    if let Some((index, shift)) = largest_shift {
+        println!("     > Shift {index} can be at most {shift}");
+        println!("     > See Trainer::max_quantisation_shifts");
+    }
+    println!("   You will need to quantise manually.    ");
+    println!("==========================================");
+}
@@ -0,0 +1,324 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    inputs::InputType,
+    loader::GpuDataLoader,
+    outputs::OutputBuckets,
+    tensor::{device_name, device_synchronise},
+    LocalSettings, TrainingSchedule,
+};
+
+use super::{
+    ansi,
+    run::{batch_seed, for_each_batch, holdout_sample},
+    usage::EnergyMeter,
+    RunUsage, Trainer,
+};
+
+use std::{sync::mpsc::sync_channel, time::Instant};
+
+/// Hyperparameters of one member of a population, as
+/// multipliers of those given by the training schedule.
+#[derive(Clone, Copy, Debug)]
+pub struct PbtMember {
+    pub lr_mult: f32,
+    pub wd_mult: f32,
+}
+
+impl Default for PbtMember {
+    fn default() -> Self {
+        Self { lr_mult: 1.0, wd_mult: 1.0 }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PbtSettings {
+    /// Superbatches trained between each round of exploitation.
+    pub interval: usize,
+    /// Fraction of the population, rounded down but at least one member,
+    /// that is replaced by copies of the best members each round.
+    pub truncation: f32,
+    /// Each hyperparameter of a copied member is multiplied or
+    /// divided by this, at random, to explore around the winner.
+    pub perturb: f32,
+    /// Seeds the initial weights of each member and the choices made when exploiting.
+    pub seed: u64,
+}
+
+impl Default for PbtSettings {
+    fn default() -> Self {
+        Self { interval: 10, truncation: 0.25, perturb: 1.2, seed: 0 }
+    }
+}
+
+/// Results of [`population_based_training`].
+#[derive(Clone, Debug)]
+pub struct PbtResult {
+    /// Final hyperparameters of each member.
+    pub members: Vec<PbtMember>,
+    /// Score of each member at the end of training, lower being better.
+    pub scores: Vec<f32>,
+    /// Index of the member with the best final score.
+    pub best: usize,
+}
+
+impl std::fmt::Display for PbtResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:>8} {:>12} {:>12} {:>14}", "Member", "LR Mult", "WD Mult", "Score")?;
+
+        for (i, (member, score)) in self.members.iter().zip(&self.scores).enumerate() {
+            let best = if i == self.best { " *" } else { "" };
+            writeln!(f, "{i:>8} {:>12.4} {:>12.4} {score:>14.6}{best}", member.lr_mult, member.wd_mult)?;
+        }
+
+        write!(f, "Best Member            : {}", self.best)
+    }
+}
+
+type Score<T, U> = fn(&mut Trainer<T, U>) -> f32;
+
+/// Trains a population of nets from `build`, one per entry of `members`, on
+/// the same stream of batches. Every `interval` superbatches each member is
+/// scored, lower being better, and the worst members are overwritten with the
+/// full training state of randomly chosen members from the best, continuing
+/// with perturbed copies of their hyperparameters.
+///
+/// Members are scored by `score` if given, e.g. from quick matches, otherwise
+/// by the error on the validation sample if games are held out, otherwise by
+/// their running loss over the interval. Members whose loss becomes NaN stop
+/// training until they are replaced.
+///
+/// Each member is saved to `<output_directory>/member-<i>` whenever the
+/// schedule saves, the best at the end to `<output_directory>/<net>-best`,
+/// and the results to `<output_directory>/pbt.txt`.
+pub fn population_based_training<T, U, B>(
+    mut build: B,
+    members: &[PbtMember],
+    schedule: &TrainingSchedule,
+    settings: &LocalSettings,
+    pbt: PbtSettings,
+    score: Option<Score<T, U>>,
+) -> PbtResult
+where
+    T: InputType,
+    U: OutputBuckets<T::RequiredDataType>,
+    B: FnMut() -> Trainer<T, U>,
+{
+    assert!(members.len() > 1, "Need at least two members in the population!");
+    assert!(pbt.interval > 0, "Must train for at least one superbatch between rounds!");
+    assert!((0.0..=0.5).contains(&pbt.truncation), "Can't replace more than half the population!");
+    assert!(pbt.perturb >= 1.0, "Perturbation factor must be at least 1!");
+    assert!(settings.resume_from.is_none(), "Resuming is not supported for population based training!");
+
+    let out_dir = settings.output_directory;
+    let data_file_paths: Vec<_> = settings.data_file_paths.iter().map(|s| s.to_string()).collect();
+    let mut rng = StdRng::seed_from_u64(pbt.seed);
+    let mut members = members.to_vec();
+    let mut log = String::new();
+
+    let mut trainers: Vec<_> = (0..members.len())
+        .map(|i| {
+            let member_dir = format!("{out_dir}/member-{i}");
+            std::fs::create_dir_all(&member_dir).unwrap_or_else(|_| panic!("Creating [{member_dir}] failed!"));
+
+            let mut trainer = build();
+            trainer.randomise_weights_seeded(pbt.seed.wrapping_add(i as u64));
+            trainer.set_batch_size(schedule.batch_size);
+            trainer.set_ft_reg(schedule.ft_regularisation);
+            trainer.set_threads(settings.threads);
+            trainer.set_error_zero();
+            trainer
+        })
+        .collect();
+
+    let batch_size = trainers[0].batch_size();
+
+    if let Some(holdout) = trainers[0].game_holdout {
+        let sample = holdout_sample(&data_file_paths, batch_size, holdout);
+        for trainer in &mut trainers {
+            trainer.set_validation_sample(&sample);
+        }
+    }
+
+    device_synchronise();
+
+    let x = trainers[0].input_getter();
+    let y = trainers[0].bucket_getter();
+    let wdl_hook = trainers[0].wdl_hook();
+    let weight_hook = trainers[0].weight_hook();
+    let input_dropout = trainers[0].input_dropout();
+    let holdout = trainers[0].game_holdout;
+    let targets = schedule.loss_function.targets();
+    let rscale = 1.0 / schedule.eval_scale;
+    let threads = settings.threads;
+    let sch = schedule.clone();
+    let timer = Instant::now();
+    let energy = EnergyMeter::start();
+    let (sender, reciever) = sync_channel::<GpuDataLoader<T, U>>(512);
+
+    let dataloader = std::thread::spawn(move || {
+        for_each_batch(&data_file_paths, batch_size, &sch, holdout, |sb, cb, _, batch: &[T::RequiredDataType]| {
+            let mut gpu_loader = GpuDataLoader::<T, U>::new(x, y);
+            if let Some(dropout) = input_dropout {
+                gpu_loader.set_feature_dropout(dropout.blend(sb, sch.end_superbatch), batch_seed(sb, cb));
+            }
+            gpu_loader.load(batch, threads, sch.wdl(sb), rscale, wdl_hook, weight_hook, &targets);
+            sender.send(gpu_loader).unwrap();
+            true
+        });
+    });
+
+    let mut superbatch = schedule.start_superbatch;
+    let mut curr_batch = 0;
+    let mut diverged = vec![false; members.len()];
+    let mut scores = vec![0.0; members.len()];
+    let mut positions = 0;
+    let mut checkpoints = 0;
+
+    while let Ok(gpu_loader) = reciever.recv() {
+        let (lrate, decay) = (schedule.lr(superbatch), schedule.wd(superbatch));
+
+        for ((trainer, member), diverged) in trainers.iter_mut().zip(&members).zip(&mut diverged) {
+            if *diverged {
+                continue;
+            }
+
+            trainer.clear_data();
+            trainer.load_data(&gpu_loader);
+            device_synchronise();
+
+            trainer.apply_ft_freeze(superbatch);
+            trainer.apply_beta_schedule(superbatch, schedule.end_superbatch);
+            trainer.apply_hard_mining_schedule(superbatch, schedule.end_superbatch);
+            let rate = lrate * member.lr_mult;
+            *diverged = !trainer.train_on_batch(decay * member.wd_mult, rate, schedule.loss_function);
+            device_synchronise();
+
+            positions += trainer.inputs.used();
+        }
+
+        curr_batch += 1;
+
+        if curr_batch % schedule.batches_per_superbatch != 0 {
+            continue;
+        }
+
+        let finished = superbatch == schedule.end_superbatch;
+
+        if (superbatch - schedule.start_superbatch + 1).is_multiple_of(pbt.interval) || finished {
+            for (i, trainer) in trainers.iter_mut().enumerate() {
+                scores[i] = if diverged[i] {
+                    f32::INFINITY
+                } else if let Some(score) = score {
+                    score(trainer)
+                } else {
+                    trainer.validation_error().unwrap_or(trainer.mean_error())
+                };
+
+                trainer.set_error_zero();
+            }
+
+            let round = format!("superbatch {superbatch} | scores {}", format_scores(&scores));
+            println!("{}", ansi(&round, "34;1"));
+            log += &format!("{round}\n");
+
+            if !finished {
+                for (loser, winner) in exploit(&scores, pbt.truncation, &mut rng) {
+                    replace_member(&mut trainers, &mut members, loser, winner, pbt.perturb, &mut rng);
+                    diverged[loser] = false;
+
+                    let line = format!(
+                        "  member {loser} <- member {winner} | lr mult {:.4} | wd mult {:.4}",
+                        members[loser].lr_mult, members[loser].wd_mult
+                    );
+                    println!("{line}");
+                    log += &format!("{line}\n");
+                }
+            }
+        }
+
+        if schedule.should_save(superbatch) {
+            for (i, trainer) in trainers.iter().enumerate() {
+                trainer.save(&format!("{out_dir}/member-{i}"), format!("{}-{superbatch}", schedule.net_id()));
+            }
+
+            checkpoints += trainers.len();
+        }
+
+        superbatch += 1;
+        curr_batch = 0;
+    }
+
+    dataloader.join().unwrap();
+
+    let best = (0..scores.len()).min_by(|&a, &b| scores[a].total_cmp(&scores[b])).unwrap();
+    trainers[best].save(out_dir, format!("{}-best", schedule.net_id()));
+    checkpoints += 1;
+
+    let results = PbtResult { members, scores, best };
+
+    let path = format!("{out_dir}/pbt.txt");
+    std::fs::write(&path, format!("{}\n\n{log}\n{results}\n", schedule.net_id()))
+        .unwrap_or_else(|_| panic!("Writing to [{path}] failed!"));
+    println!("{results}");
+
+    let usage = RunUsage {
+        net_id: schedule.net_id(),
+        arch: format!("{}", trainers[best]),
+        device: device_name(),
+        positions,
+        seconds: timer.elapsed().as_secs_f64(),
+        checkpoints,
+        measured_energy: energy.read(),
+        device_power: trainers[best].device_power(),
+    };
+    usage.report(out_dir);
+
+    results
+}
+
+/// Pairs each of the worst members with a random member from the best.
+pub(super) fn exploit(scores: &[f32], truncation: f32, rng: &mut StdRng) -> Vec<(usize, usize)> {
+    let mut ranked: Vec<_> = (0..scores.len()).collect();
+    ranked.sort_by(|&a, &b| scores[a].total_cmp(&scores[b]));
+
+    let replaced = ((truncation * scores.len() as f32) as usize).max(1);
+    let (best, worst) = (&ranked[..replaced], &ranked[scores.len() - replaced..]);
+
+    worst.iter().map(|&loser| (loser, best[rng.gen_range(0..replaced)])).collect()
+}
+
+/// Overwrites `loser` with the full training state of `winner`, and
+/// perturbs a copy of the hyperparameters of `winner` to continue with.
+pub(super) fn replace_member<T: InputType, U: OutputBuckets<T::RequiredDataType>>(
+    trainers: &mut [Trainer<T, U>],
+    members: &mut [PbtMember],
+    loser: usize,
+    winner: usize,
+    perturb: f32,
+    rng: &mut StdRng,
+) {
+    let (dst, src) = pair_mut(trainers, loser, winner);
+    dst.load_state_from(src);
+    dst.set_error_zero();
+
+    let mut perturb = || if rng.gen() { perturb } else { 1.0 / perturb };
+    members[loser] =
+        PbtMember { lr_mult: members[winner].lr_mult * perturb(), wd_mult: members[winner].wd_mult * perturb() };
+}
+
+fn pair_mut<X>(xs: &mut [X], dst: usize, src: usize) -> (&mut X, &X) {
+    assert_ne!(dst, src);
+
+    if dst < src {
+        let (left, right) = xs.split_at_mut(src);
+        (&mut left[dst], &right[0])
+    } else {
+        let (left, right) = xs.split_at_mut(dst);
+        (&mut right[0], &left[src])
+    }
+}
+
+fn format_scores(scores: &[f32]) -> String {
+    scores.iter().map(|score| format!("{score:.6}")).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use crate::{
+    inputs::InputType,
+    loader::{Feat, GpuDataLoader},
+    outputs::OutputBuckets,
+    tensor::{util, SparseTensor, TensorBatch},
+};
+
+use super::Trainer;
+
+/// Page-locked host buffer that a batch is copied into before it is uploaded.
+struct Pinned<T> {
+    ptr: *mut T,
+    cap: usize,
+    len: usize,
+}
+
+impl<T> Drop for Pinned<T> {
+    fn drop(&mut self) {
+        unsafe {
+            util::free_pinned(self.ptr, self.cap);
+        }
+    }
+}
+
+impl<T: Copy> Pinned<T> {
+    fn new(cap: usize) -> Self {
+        // zero sized allocations are not allowed
+        let cap = cap.max(1);
+        Self { ptr: util::malloc_pinned(cap), cap, len: 0 }
+    }
+
+    /// Replaces the contents of the buffer with `buf`.
+    fn fill(&mut self, buf: &[T]) {
+        assert!(buf.len() <= self.cap, "Overflow!");
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(buf.as_ptr(), self.ptr, buf.len());
+        }
+
+        self.len = buf.len();
+    }
+
+    fn as_slice(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+/// Copy of a batch in page-locked host memory, filled on the dataloader
+/// thread, so that the training thread only has to issue its upload with
+/// `Trainer::prefetch_data`. Batches are allocated up front by
+/// `Trainer::pinned_batch` and reused, as page-locking memory is slow.
+pub struct PinnedBatch {
+    inputs: Pinned<Feat>,
+    results: Pinned<f32>,
+    weights: Pinned<f32>,
+    buckets: Pinned<u8>,
+}
+
+// the buffers are only ever accessed through the batch that owns them
+unsafe impl Send for PinnedBatch {}
+unsafe impl Sync for PinnedBatch {}
+
+impl PinnedBatch {
+    /// Copies the batch in `loader`.
+    pub fn fill<T: InputType, U: OutputBuckets<T::RequiredDataType>>(&mut self, loader: &GpuDataLoader<T, U>) {
+        self.inputs.fill(loader.inputs());
+        self.results.fill(loader.results());
+        self.weights.fill(loader.weights());
+        self.buckets.fill(loader.buckets());
+    }
+}
+
+/// Second set of the device buffers that a batch is loaded into, so that
+/// the next batch can be uploaded on a copy stream while the current one
+/// is trained on, then swapped in without waiting on the host.
+pub(super) struct Prefetch {
+    stream: util::CopyStream,
+    inputs: SparseTensor,
+    results: TensorBatch,
+    weights: TensorBatch,
+    buckets: *mut u8,
+    /// The batch being uploaded, which must outlive the copies reading from it.
+    staged: Option<Arc<PinnedBatch>>,
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        // copies may still be reading from the staged batch
+        self.stream.synchronise();
+
+        unsafe {
+            util::free(self.buckets, self.results.cap());
+        }
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    fn new_prefetch(&self) -> Prefetch {
+        let batch_size = self.batch_size();
+        let max_active_inputs = self.input_getter.max_active_inputs();
+
+        Prefetch {
+            stream: util::CopyStream::new(),
+            inputs: unsafe { SparseTensor::uninit(batch_size, self.input_getter.size(), max_active_inputs) },
+            results: TensorBatch::new(self.results.shape(), batch_size),
+            weights: TensorBatch::new(self.weights.shape(), batch_size),
+            buckets: util::calloc(batch_size),
+            staged: None,
+        }
+    }
+
+    /// Page-locked space for a batch of the current batch size, for the
+    /// dataloader to copy batches into before they are passed to `prefetch_data`.
+    pub fn pinned_batch(&self) -> PinnedBatch {
+        let batch_size = self.batch_size();
+        let max_active_inputs = self.input_getter.max_active_inputs();
+
+        PinnedBatch {
+            inputs: Pinned::new(batch_size * max_active_inputs),
+            results: Pinned::new(batch_size * self.results.element_size()),
+            weights: Pinned::new(batch_size * self.weights.element_size()),
+            buckets: Pinned::new(batch_size),
+        }
+    }
+
+    /// Starts uploading `batch`, returning once the copies are issued, so
+    /// that the host can go on to launch the kernels of the batch already
+    /// loaded while they run. The batch is trained on after it is swapped in
+    /// by `load_prefetched`, and is not written to until then, as this holds
+    /// on to a reference to it. Any number of trainers can prefetch the same
+    /// batch at once.
+    pub fn prefetch_data(&mut self, batch: &Arc<PinnedBatch>) {
+        let batch_size = self.batch_size();
+        if self.prefetch.as_ref().is_none_or(|prefetch| prefetch.results.cap() != batch_size) {
+            self.prefetch = Some(self.new_prefetch());
+        }
+
+        let prefetch = self.prefetch.as_mut().unwrap();
+        assert!(prefetch.staged.is_none(), "A batch is already being prefetched!");
+
+        let results = batch.results.as_slice();
+        let weights = batch.weights.as_slice();
+
+        unsafe {
+            prefetch.inputs.clear();
+            prefetch.inputs.append_async(batch.inputs.as_slice(), &prefetch.stream);
+            prefetch.results.load_from_host_async(results, &prefetch.stream);
+
+            if !weights.is_empty() {
+                prefetch.weights.load_from_host_async(weights, &prefetch.stream);
+            }
+
+            if U::BUCKETS > 1 {
+                let buckets = batch.buckets.as_slice();
+                util::copy_to_device_async(prefetch.buckets, buckets.as_ptr(), buckets.len(), &prefetch.stream);
+            }
+        }
+
+        prefetch.staged = Some(batch.clone());
+    }
+
+    /// Waits for the batch passed to `prefetch_data` to finish uploading, then
+    /// swaps it in for the batch currently loaded, in place of `clear_data`
+    /// and `load_data`. The kernels of the batch it replaces must have finished.
+    pub fn load_prefetched(&mut self) {
+        let prefetch = self.prefetch.as_mut().expect("No batch has been prefetched!");
+        let batch = prefetch.staged.take().expect("No batch has been prefetched!");
+
+        prefetch.stream.synchronise();
+
+        std::mem::swap(&mut self.inputs, &mut prefetch.inputs);
+        std::mem::swap(&mut self.results, &mut prefetch.results);
+
+        if !batch.weights.as_slice().is_empty() {
+            std::mem::swap(&mut self.weights, &mut prefetch.weights);
+        }
+
+        if U::BUCKETS > 1 {
+            std::mem::swap(&mut self.buckets, &mut prefetch.buckets);
+        }
+
+        self.used = batch.results.len;
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    inputs::InputType,
+    outputs::OutputBuckets,
+    tensor::{DeviceBuffer, TensorBatch},
+};
+
+use super::{Concat, Multiply, Node, Operation, Trainer};
+
+/// Memory shared by the outputs of recomputable nodes, which is overwritten
+/// during the forward pass, so their outputs are recomputed from the nearest
+/// kept output whenever they are needed again.
+pub(super) struct Recompute {
+    /// Whether each node in `nodes` is recomputable.
+    pub nodes: Vec<bool>,
+    /// Node `i` writes to slot `i % 3`, so never overwrites its inputs, nor
+    /// the errors of the node after it while its own outputs are recomputed.
+    /// Only accessed through the outputs of the nodes.
+    _slots: [DeviceBuffer; 3],
+    /// Holds the errors of a recomputable node while a run of three or
+    /// more recomputable nodes before it is recomputed.
+    spare: DeviceBuffer,
+}
+
+impl Recompute {
+    /// Points the outputs of each recomputable node into the shared slots,
+    /// returning `None`, with all outputs left as they are, if there are none.
+    pub fn new(nodes: &mut [Node], recomputed: Vec<bool>, batch_size: usize) -> Option<Self> {
+        assert_eq!(nodes.len(), recomputed.len());
+
+        if !recomputed.contains(&true) {
+            return None;
+        }
+
+        assert!(!recomputed[nodes.len() - 1], "Can't recompute the output layer!");
+
+        for (i, node) in nodes.iter().enumerate() {
+            let sources = match &node.op {
+                Operation::Concat(Concat { sources, .. }) => sources.clone(),
+                Operation::Multiply(Multiply { source, .. }) => vec![*source],
+                _ => continue,
+            };
+
+            for source in sources.into_iter().filter(|&source| source > 0) {
+                assert!(!recomputed[source - 1], "Can't recompute node {}, as node {i} reads it!", source - 1);
+            }
+        }
+
+        for (i, node) in nodes.iter().enumerate() {
+            let is_norm = matches!(node.op, Operation::BatchNorm(_));
+            assert!(!(recomputed[i] && is_norm), "Can't recompute node {i}, as batch norms update their statistics!");
+        }
+
+        for (i, window) in nodes.windows(2).enumerate() {
+            let in_res_block = window[0].in_res_block || window[1].in_res_block;
+            assert!(
+                !(recomputed[i] && in_res_block),
+                "Can't recompute node {i}, as it is in or enters a residual block!"
+            );
+        }
+
+        let size = nodes
+            .iter()
+            .zip(&recomputed)
+            .filter(|(_, &recompute)| recompute)
+            .map(|(node, _)| node.outputs.element_size())
+            .max()
+            .unwrap_or(0)
+            * batch_size;
+
+        let slots = [DeviceBuffer::new(size), DeviceBuffer::new(size), DeviceBuffer::new(size)];
+
+        for (i, node) in nodes.iter_mut().enumerate().filter(|(i, _)| recomputed[*i]) {
+            unsafe {
+                node.outputs = TensorBatch::view(node.outputs.shape(), batch_size, slots[i % 3].ptr());
+            }
+        }
+
+        Some(Self { nodes: recomputed, _slots: slots, spare: DeviceBuffer::new(size) })
+    }
+
+    /// Floats allocated for the slots and spare.
+    pub fn size(&self) -> usize {
+        self._slots.iter().map(DeviceBuffer::size).sum::<usize>() + self.spare.size()
+    }
+
+    /// First node of the run of recomputable nodes ending at `node`.
+    fn run_start(&self, node: usize) -> usize {
+        (0..node).rev().find(|&i| !self.nodes[i]).map_or(0, |i| i + 1)
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Recomputes the outputs of `node`, if it is recomputable, along with
+    /// those of the recomputable nodes leading up to it.
+    ///
+    /// # Safety
+    /// It is undefined behaviour to call this without previously calling
+    /// `self.forward`.
+    pub(super) unsafe fn recompute_outputs(&self, batch_size: usize, node: usize, training: bool) {
+        let Some(recompute) = &self.recompute else { return };
+
+        if !recompute.nodes[node] {
+            return;
+        }
+
+        for i in recompute.run_start(node)..=node {
+            self.forward_single(batch_size, &self.nodes[i], self.output(i), training);
+        }
+    }
+
+    /// Recomputes the inputs of `node` before backprop reaches it,
+    /// keeping the errors already written to its outputs.
+    ///
+    /// # Safety
+    /// It is undefined behaviour to call this without previously calling
+    /// `self.forward`.
+    pub(super) unsafe fn recompute_inputs(&self, batch_size: usize, node: usize) {
+        let Some(recompute) = &self.recompute else { return };
+
+        if node == 0 || !recompute.nodes[node - 1] {
+            return;
+        }
+
+        // the errors share a slot with every third node before them
+        let errors = &self.nodes[node].outputs;
+        let spare = (recompute.nodes[node] && recompute.run_start(node - 1) + 3 <= node).then(|| {
+            let spare = TensorBatch::view(errors.shape(), errors.cap(), recompute.spare.ptr());
+            spare.copy_from(errors);
+            spare
+        });
+
+        self.recompute_outputs(batch_size, node - 1, true);
+
+        if let Some(spare) = spare {
+            errors.copy_from(&spare);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! A single self-contained HTML file summarising a run, written to its
+//! output directory at the end, with the training curves plotted by a
+//! small embedded script so that it can be shared without the checkpoints.
+
+use std::{fmt::Write as _, io::Write as _};
+
+use crate::{inputs::InputType, outputs::OutputBuckets, TrainingSchedule};
+
+use super::Trainer;
+
+/// Metrics at the end of a superbatch.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Record {
+    pub superbatch: usize,
+    pub loss: f32,
+    pub lr: f32,
+    pub mini_loss: Option<f32>,
+    /// Error of the validation sample against the game results, on saves.
+    pub validation: Option<f32>,
+    /// Seconds since the start of the run.
+    pub time: f32,
+}
+
+/// Result of a match played by the net saved at the end of a superbatch.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct EloRecord {
+    pub superbatch: usize,
+    pub elo: f32,
+    pub err: f32,
+}
+
+pub(super) struct RunReport {
+    path: String,
+    net_id: String,
+    arch: String,
+    summary: String,
+    schedule: String,
+    device: String,
+    datasets: Vec<(String, usize)>,
+    records: Vec<Record>,
+    elo: Vec<EloRecord>,
+}
+
+impl RunReport {
+    /// Report to be written to `path`.
+    pub fn new(path: String, schedule: &TrainingSchedule, arch: String, summary: String, device: String) -> Self {
+        Self {
+            path,
+            net_id: schedule.net_id(),
+            arch,
+            summary,
+            schedule: format!("{schedule:#?}"),
+            device,
+            datasets: Vec::new(),
+            records: Vec::new(),
+            elo: Vec::new(),
+        }
+    }
+
+    pub fn add_dataset(&mut self, path: &str, positions: usize) {
+        self.datasets.push((path.to_string(), positions));
+    }
+
+    pub fn push(&mut self, record: Record) {
+        self.records.push(record);
+    }
+
+    /// Matches are played after the superbatch they test has been recorded,
+    /// and possibly after the end of the run, so are kept separately.
+    pub fn push_elo(&mut self, record: EloRecord) {
+        self.elo.push(record);
+        self.elo.sort_by_key(|r| r.superbatch);
+    }
+
+    /// Appends the records to the CSV at `path`, writing its header if it is new.
+    pub fn append_records(&self, path: &str, stage: usize) -> std::io::Result<()> {
+        let exists = std::path::Path::new(path).exists();
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+
+        if !exists {
+            writeln!(file, "stage,superbatch,loss,mini_loss,validation,lr,time")?;
+        }
+
+        let optional = |x: Option<f32>| x.map(|x| x.to_string()).unwrap_or_default();
+
+        for r in &self.records {
+            let (mini, validation) = (optional(r.mini_loss), optional(r.validation));
+            writeln!(file, "{stage},{},{},{mini},{validation},{},{:.1}", r.superbatch, r.loss, r.lr, r.time)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn write(&self) -> std::io::Result<()> {
+        std::fs::write(&self.path, self.html())
+    }
+
+    fn html(&self) -> String {
+        let mut html = String::new();
+        let title = escape(&self.net_id);
+
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{STYLE}</style>\n\
+            </head>\n<body>\n<h1>{title}</h1>\n"
+        );
+
+        html += "<h2>Training</h2>\n";
+        if self.records.is_empty() {
+            html += "<p>No superbatches were completed.</p>\n";
+        } else {
+            html += "<canvas id=\"loss\" width=\"900\" height=\"360\"></canvas>\n";
+            html += "<canvas id=\"lr\" width=\"900\" height=\"200\"></canvas>\n";
+            if !self.elo.is_empty() {
+                html += "<canvas id=\"elo\" width=\"900\" height=\"300\"></canvas>\n";
+            }
+            html += &self.results_table();
+        }
+
+        if !self.elo.is_empty() {
+            html += &self.elo_table();
+        }
+
+        let _ = writeln!(html, "<h2>Architecture</h2>\n<pre>{}\n\n{}</pre>", escape(&self.arch), escape(&self.summary));
+        let _ = writeln!(html, "<h2>Schedule</h2>\n<pre>{}</pre>", escape(&self.schedule));
+        let _ = writeln!(html, "<p>Device: {}</p>", escape(&self.device));
+        html += &self.datasets_table();
+
+        let _ = writeln!(html, "<script>\nconst data = {};\n{SCRIPT}</script>\n</body>\n</html>", self.json());
+
+        html
+    }
+
+    fn results_table(&self) -> String {
+        let last = self.records.last().unwrap();
+        let total = self.records.iter().map(|r| r.time).fold(0.0, f32::max);
+        let best_validation = self.records.iter().filter_map(|r| r.validation).reduce(f32::min);
+
+        let mut table = String::from("<table>\n");
+        let _ = writeln!(table, "<tr><td>Superbatches</td><td>{}</td></tr>", last.superbatch);
+        let _ = writeln!(table, "<tr><td>Final Loss</td><td>{:.6}</td></tr>", last.loss);
+        if let Some(loss) = last.mini_loss {
+            let _ = writeln!(table, "<tr><td>Final Mini Loss</td><td>{loss:.6}</td></tr>");
+        }
+        if let Some(validation) = best_validation {
+            let _ = writeln!(table, "<tr><td>Best Validation Error</td><td>{validation:.6}</td></tr>");
+        }
+        if let Some(last) = self.elo.last() {
+            let _ = writeln!(table, "<tr><td>Final Elo</td><td>{:.2} +/- {:.2}</td></tr>", last.elo, last.err);
+        }
+        let _ = writeln!(table, "<tr><td>Time</td><td>{total:.1}s</td></tr>");
+        table += "</table>\n";
+
+        table
+    }
+
+    fn elo_table(&self) -> String {
+        let mut table = String::from("<h2>Testing</h2>\n<table>\n<tr><th>Superbatch</th><th>Elo</th></tr>\n");
+        for r in &self.elo {
+            let _ = writeln!(table, "<tr><td>{}</td><td>{:.2} +/- {:.2}</td></tr>", r.superbatch, r.elo, r.err);
+        }
+        table += "</table>\n";
+
+        table
+    }
+
+    fn datasets_table(&self) -> String {
+        let total = self.datasets.iter().map(|(_, positions)| positions).sum::<usize>();
+
+        let mut table =
+            String::from("<h2>Data</h2>\n<table>\n<tr><th>File</th><th>Positions</th><th>Share</th></tr>\n");
+        for (path, positions) in &self.datasets {
+            let share = 100.0 * *positions as f64 / total.max(1) as f64;
+            let _ = writeln!(table, "<tr><td>{}</td><td>{positions}</td><td>{share:.2}%</td></tr>", escape(path));
+        }
+        let _ = writeln!(table, "<tr><th>Total</th><th>{total}</th><th></th></tr>\n</table>");
+
+        table
+    }
+
+    /// The records as columns of a JSON object, with `null` for missing values
+    /// and for non-finite ones, such as the Elo of a match that was all wins,
+    /// as JSON has no way of writing them.
+    fn json(&self) -> String {
+        let column = |f: &dyn Fn(&Record) -> Option<f32>| {
+            let values: Vec<_> = self
+                .records
+                .iter()
+                .map(|r| f(r).filter(|x| x.is_finite()).map_or(String::from("null"), |x| x.to_string()))
+                .collect();
+            format!("[{}]", values.join(","))
+        };
+
+        let elo = |r: &Record| self.elo.iter().rev().find(|e| e.superbatch == r.superbatch);
+
+        let superbatches: Vec<_> = self.records.iter().map(|r| r.superbatch.to_string()).collect();
+
+        format!(
+            "{{\"superbatch\":[{}],\"loss\":{},\"mini\":{},\"validation\":{},\"lr\":{},\"elo\":{},\"elo_low\":{},\
+            \"elo_high\":{}}}",
+            superbatches.join(","),
+            column(&|r| Some(r.loss)),
+            column(&|r| r.mini_loss),
+            column(&|r| r.validation),
+            column(&|r| Some(r.lr)),
+            column(&|r| elo(r).map(|e| e.elo)),
+            column(&|r| elo(r).map(|e| e.elo - e.err)),
+            column(&|r| elo(r).map(|e| e.elo + e.err)),
+        )
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Adds the result of a match played by the net saved at the end of
+    /// `superbatch` to the report of the current or last run, rewriting it.
+    pub(crate) fn record_elo(&mut self, superbatch: usize, elo: f32, err: f32) {
+        let Some(report) = &mut self.report else { return };
+
+        report.push_elo(EloRecord { superbatch, elo, err });
+        report.write().unwrap_or_else(|_| panic!("Writing to [{}] failed!", report.path()));
+    }
+
+    /// Appends the loss and learning rate of each superbatch of the last run
+    /// to the CSV at `path`, as stage `stage` of a recipe.
+    pub(crate) fn append_stage_log(&self, path: &str, stage: usize) {
+        let report = self.report.as_ref().expect("No run has finished!");
+        report.append_records(path, stage).unwrap_or_else(|_| panic!("Writing to [{path}] failed!"));
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;max-width:960px}\
+canvas{display:block;margin-bottom:1em;border:1px solid #ccc}\
+pre{background:#f4f4f4;padding:1em;overflow-x:auto}\
+table{border-collapse:collapse}td,th{padding:0.2em 1em;text-align:left}";
+
+const SCRIPT: &str = r##"function plot(id, series) {
+  const canvas = document.getElementById(id);
+  if (!canvas) return;
+  const ctx = canvas.getContext("2d");
+  const pad = 60, w = canvas.width - 2 * pad, h = canvas.height - 2 * pad;
+  const xs = data.superbatch;
+  const present = series.filter(s => s.values.some(v => v !== null));
+  const all = present.flatMap(s => s.values.filter(v => v !== null));
+  if (all.length === 0) return;
+  let lo = Math.min(...all), hi = Math.max(...all);
+  if (lo === hi) { lo -= 1; hi += 1; }
+  const x0 = xs[0], x1 = Math.max(xs[xs.length - 1], x0 + 1);
+  const px = x => pad + (x - x0) / (x1 - x0) * w;
+  const py = y => pad + h - (y - lo) / (hi - lo) * h;
+  ctx.font = "12px sans-serif";
+  ctx.strokeStyle = "#999";
+  ctx.strokeRect(pad, pad, w, h);
+  ctx.fillStyle = "#000";
+  for (let i = 0; i <= 4; i++) {
+    const y = lo + (hi - lo) * i / 4;
+    ctx.fillText(y.toPrecision(4), 4, py(y) + 4);
+    const x = Math.round(x0 + (x1 - x0) * i / 4);
+    ctx.fillText(x, px(x) - 8, pad + h + 16);
+  }
+  present.forEach((s, i) => {
+    ctx.strokeStyle = ctx.fillStyle = s.colour;
+    ctx.beginPath();
+    let started = false;
+    s.values.forEach((v, j) => {
+      if (v === null) return;
+      if (started) ctx.lineTo(px(xs[j]), py(v)); else ctx.moveTo(px(xs[j]), py(v));
+      started = true;
+      if (s.points) ctx.fillRect(px(xs[j]) - 2, py(v) - 2, 4, 4);
+    });
+    ctx.stroke();
+    ctx.fillText(s.name, pad + 10 + 110 * i, pad - 10);
+  });
+}
+plot("loss", [
+  { name: "loss", values: data.loss, colour: "#1f77b4" },
+  { name: "mini loss", values: data.mini, colour: "#ff7f0e" },
+  { name: "validation", values: data.validation, colour: "#2ca02c", points: true },
+]);
+plot("lr", [{ name: "learning rate", values: data.lr, colour: "#d62728" }]);
+plot("elo", [
+  { name: "elo", values: data.elo, colour: "#9467bd", points: true },
+  { name: "elo - err", values: data.elo_low, colour: "#c5b0d5" },
+  { name: "elo + err", values: data.elo_high, colour: "#c5b0d5" },
+]);
+"##;
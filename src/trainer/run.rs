@@ -6,13 +6,23 @@ use crate::{
     util, LocalSettings, Trainer, TrainingSchedule,
 };
 
+use super::{
+    report::{Record, RunReport},
+    schedule::{RealizedSchedule, RealizedSuperbatch},
+    usage::EnergyMeter,
+    ActivationStats, GameHoldout, PinnedBatch, RunUsage,
+};
+
 use std::{
     fs::File,
     io::{stdout, BufRead, BufReader, Write},
+    ops::RangeInclusive,
     sync::{
         atomic::{AtomicBool, Ordering::SeqCst},
-        mpsc::sync_channel,
+        mpsc::{channel, sync_channel, Receiver, Sender},
+        Arc,
     },
+    thread::JoinHandle,
     time::Instant,
 };
 
@@ -21,10 +31,28 @@ pub fn run<T: InputType, U: OutputBuckets<T::RequiredDataType>, F>(
     trainer: &mut Trainer<T, U>,
     schedule: &TrainingSchedule,
     settings: &LocalSettings,
+    callback: F,
+) where
+    F: FnMut(usize, &Trainer<T, U>, &TrainingSchedule, &LocalSettings),
+{
+    run_with_mini(trainer, None, schedule, settings, callback);
+}
+
+/// As `run`, but also trains `mini` on exactly the same batches, with the same
+/// schedule. Whenever a checkpoint is saved, the mini net is saved alongside it
+/// in `<net>-<superbatch>-mini`, and both are exported together to
+/// `<net>-<superbatch>/<net>-<superbatch>-combined.bin` if quantised.
+pub fn run_with_mini<T: InputType, U: OutputBuckets<T::RequiredDataType>, F>(
+    trainer: &mut Trainer<T, U>,
+    mut mini: Option<&mut Trainer<T, U>>,
+    schedule: &TrainingSchedule,
+    settings: &LocalSettings,
     mut callback: F,
 ) where
     F: FnMut(usize, &Trainer<T, U>, &TrainingSchedule, &LocalSettings),
 {
+    assert!(mini.is_none() || settings.resume_from.is_none(), "Resuming is not supported when training a mini net!");
+
     let threads = settings.threads;
     let data_file_paths: Vec<_> = settings.data_file_paths.iter().map(|s| s.to_string()).collect();
     let out_dir = settings.output_directory.to_string();
@@ -37,11 +65,29 @@ pub fn run<T: InputType, U: OutputBuckets<T::RequiredDataType>, F>(
     trainer.set_batch_size(schedule.batch_size);
     trainer.set_ft_reg(schedule.ft_regularisation);
 
+    if let Some(mini) = mini.as_deref_mut() {
+        mini.set_batch_size(schedule.batch_size);
+        mini.set_ft_reg(schedule.ft_regularisation);
+    }
+
+    // superbatches up to and including this one were completed before the checkpoint
+    let resumed = settings.resume_from.map(|path| trainer.resume_from_checkpoint(path));
+
+    let replay = trainer.schedule_replay().cloned();
+    if let Some(replay) = &replay {
+        check_replay(replay, schedule);
+    }
+
+    let schedule_path = format!("{out_dir}/{}-schedule.csv", schedule.net_id());
+    if resumed.is_none() {
+        std::fs::remove_file(&schedule_path).unwrap_or(());
+    }
+
     let data_size = std::mem::size_of::<T::RequiredDataType>() as u64;
     let esc = esc();
-    let rscale = 1.0 / schedule.eval_scale;
     let mut file_size = 0;
-    for file in data_file_paths.iter() {
+    let mut datasets = Vec::new();
+    for &file in settings.data_file_paths.iter() {
         let this_size = std::fs::metadata(file).unwrap_or_else(|_| panic!("Invalid File Metadata: {file}")).len();
 
         if this_size % data_size != 0 {
@@ -49,6 +95,7 @@ pub fn run<T: InputType, U: OutputBuckets<T::RequiredDataType>, F>(
         }
 
         file_size += this_size;
+        datasets.push((file, (this_size / data_size) as usize));
     }
 
     let num = (file_size / data_size) as usize;
@@ -69,128 +116,695 @@ pub fn run<T: InputType, U: OutputBuckets<T::RequiredDataType>, F>(
     println!("Net Name               : {}", ansi(schedule.net_id.clone(), "32;1"));
     println!("Arch                   : {}", ansi(format!("{trainer}"), 31));
     schedule.display();
+    if let Some(freeze) = trainer.ft_freeze() {
+        println!("FT Freeze              : {}", freeze.colourful());
+    }
+    if let Some(scheduler) = trainer.beta_scheduler() {
+        println!("Beta Scheduler         : {}", scheduler.colourful());
+    }
+    if let Some(skip) = trainer.hard_example_mining() {
+        println!("Hard Example Mining    : {}", skip.colourful());
+    }
+    if let Some(replay) = &replay {
+        println!("Replaying Schedule     : {} superbatches", ansi(replay.superbatches.len(), 31));
+    }
     println!("Device                 : {}", ansi(device_name(), 31));
+    if let Some((rank, world_size)) = trainer.distributed_rank() {
+        println!("Distributed            : {}", ansi(format!("rank {rank} of {world_size}"), 31));
+    }
     settings.display();
     println!("Positions              : {}", ansi(num, 31));
 
-    let pos_per_sb = schedule.batch_size * schedule.batches_per_superbatch;
+    if let Some(holdout) = trainer.game_holdout {
+        let sample = holdout_sample(&data_file_paths, batch_size, holdout);
+        println!("Held Out Games         : {}", ansi(format!("{:.2}%", 100.0 * holdout.fraction), 31));
+        println!("Validation Positions   : {}", ansi(sample.len(), 31));
+        trainer.set_validation_sample(&sample);
+    }
+
+    let pos_per_sb = schedule.positions_per_superbatch();
     let total_pos = pos_per_sb * (schedule.end_superbatch - schedule.start_superbatch + 1);
     let iters = total_pos as f64 / num as f64;
     println!("Total Epochs           : {}", ansi(format!("{iters:.2}"), 31));
 
-    let timer = Instant::now();
+    let summary = trainer.summary();
+    println!("{summary}");
+    trainer.print_memory_report();
 
-    trainer.set_threads(threads);
-    device_synchronise();
+    if let Some(mini) = mini.as_deref() {
+        println!("Mini Net               : {}", ansi(format!("{mini}"), 31));
+    }
 
-    let x = trainer.input_getter();
-    let y = trainer.bucket_getter();
-    let sch = schedule.clone();
-    let (sender, reciever) = sync_channel::<GpuDataLoader<T, U>>(512);
+    let summary_path = format!("{out_dir}/{}-summary.txt", schedule.net_id());
+    std::fs::write(&summary_path, format!("{trainer}\n\n{summary}\n"))
+        .unwrap_or_else(|_| panic!("Writing to [{summary_path}] failed!"));
 
-    let buffer_size_mb = 256;
-    let buffer_size = buffer_size_mb * 1024 * 1024;
-    let data_size: usize = std::mem::size_of::<T::RequiredDataType>();
-    let batches_per_load = buffer_size / data_size / batch_size;
-    let cap = data_size * batch_size * batches_per_load;
+    let dot_path = format!("{out_dir}/{}-arch.dot", schedule.net_id());
+    std::fs::write(&dot_path, trainer.to_dot()).unwrap_or_else(|_| panic!("Writing to [{dot_path}] failed!"));
 
-    let dataloader = std::thread::spawn(move || {
-        let mut sb = sch.start_superbatch;
-        let mut cb = 0;
-        let mut blend = sch.wdl_scheduler.blend(sb, sch.end_superbatch);
-
-        'dataloading: loop {
-            let mut loader_files = vec![];
-            for file in data_file_paths.iter() {
-                loader_files.push(File::open(file).unwrap_or_else(|_| panic!("Invalid File Path: {file}")));
-            }
+    let report_path = format!("{out_dir}/{}-report.html", schedule.net_id());
+    let mut report = RunReport::new(report_path, schedule, format!("{trainer}"), format!("{summary}"), device_name());
+    for &(path, positions) in datasets.iter() {
+        report.add_dataset(path, positions);
+    }
+    trainer.report = Some(report);
 
-            for loader_file in loader_files.iter() {
-                let mut file = BufReader::with_capacity(cap, loader_file);
-                while let Ok(buf) = file.fill_buf() {
-                    if buf.is_empty() {
-                        break;
-                    }
+    let timer = Instant::now();
+    let energy = EnergyMeter::start();
 
-                    let data: &[T::RequiredDataType] = util::to_slice_with_lifetime(buf);
-
-                    for batch in data.chunks(batch_size) {
-                        let mut gpu_loader = GpuDataLoader::<T, U>::new(x, y);
-                        gpu_loader.load(batch, threads, blend, rscale);
-                        sender.send(gpu_loader).unwrap();
-                        cb += 1;
-                        if cb % sch.batches_per_superbatch == 0 {
-                            if sb == sch.end_superbatch {
-                                break 'dataloading;
-                            }
-
-                            cb = 0;
-                            sb += 1;
-                            blend = sch.wdl_scheduler.blend(sb, sch.end_superbatch);
-                        }
-                    }
+    trainer.set_threads(threads);
+    if let Some(mini) = mini.as_deref_mut() {
+        mini.set_threads(threads);
+        mini.set_error_zero();
+    }
+    device_synchronise();
 
-                    let consumed = buf.len();
-                    file.consume(consumed);
-                }
-            }
-        }
-    });
+    let first = resumed.map_or(schedule.start_superbatch, |resumed| resumed + 1);
+    let superbatches = first..=schedule.end_superbatch;
+    let (batches, recycle, dataloader) =
+        spawn_dataloader(trainer, schedule, replay.clone(), data_file_paths, superbatches, threads);
 
-    let mut prev_lr = schedule.lr(1);
-    let mut superbatch = schedule.start_superbatch;
+    let mut prev_lr = realized(replay.as_ref(), schedule, 1).lr;
+    let mut superbatch = first;
     let mut curr_batch = 0;
     let mut superbatch_timer = Instant::now();
+    let mut source_losses = vec![SourceLoss::default(); settings.data_file_paths.len()];
+    let mut coverage = vec![SourceCoverage::default(); settings.data_file_paths.len()];
+    let mut positions = 0;
+    let mut superbatch_positions = 0;
+    let mut checkpoints = 0;
     trainer.set_error_zero();
 
-    while let Ok(gpu_loader) = reciever.recv() {
-        let lrate = schedule.lr(superbatch);
+    // each batch is uploaded while the one before it is trained on
+    let mut next = batches.recv().ok();
+    if let Some((_, batch)) = &next {
+        prefetch_data(trainer, mini.as_deref_mut(), batch);
+    }
+
+    while let Some((gpu_loader, batch)) = next.take() {
+        let values = realized(replay.as_ref(), schedule, superbatch);
+        let lrate = values.lr;
         if lrate != prev_lr {
             println!("LR Dropped to {}", ansi(lrate, num_cs()));
         }
         prev_lr = lrate;
 
-        trainer.clear_data();
-        device_synchronise();
+        trainer.load_prefetched();
+        if let Some(mini) = mini.as_deref_mut() {
+            mini.load_prefetched();
+        }
 
-        trainer.load_data(&gpu_loader);
-        device_synchronise();
+        // every trainer has loaded the batch, so it can be refilled
+        recycle.send(Arc::into_inner(batch).expect("Batch is still being uploaded!")).unwrap_or(());
 
-        let valid = trainer.train_on_batch(0.01, lrate, schedule.power());
+        next = batches.recv().ok();
+        if let Some((_, batch)) = &next {
+            prefetch_data(trainer, mini.as_deref_mut(), batch);
+        }
+
+        trainer.apply_ft_freeze(superbatch);
+        trainer.apply_beta_schedule(superbatch, schedule.end_superbatch);
+        trainer.apply_hard_mining_schedule(superbatch, schedule.end_superbatch);
+        let prev_error = trainer.position_error;
+        let valid = trainer.train_on_batch(values.wd, lrate, schedule.loss_function);
         device_synchronise();
 
+        let source = &mut source_losses[gpu_loader.source()];
+        source.error += trainer.position_error - prev_error;
+        source.positions += trainer.inputs.used();
+        source.batches += 1;
+        coverage[gpu_loader.source()].record_batch(trainer.inputs.used());
+        positions += trainer.inputs.used();
+        superbatch_positions += trainer.inputs.used();
+
         if !valid {
             trainer.save(out_dir, format!("error-nan-batch-{curr_batch}"));
             panic!("Batch {curr_batch} NaN!");
         }
 
+        if let Some(mini) = mini.as_deref_mut() {
+            mini.apply_ft_freeze(superbatch);
+            mini.apply_beta_schedule(superbatch, schedule.end_superbatch);
+            mini.apply_hard_mining_schedule(superbatch, schedule.end_superbatch);
+            let valid = mini.train_on_batch(values.wd, lrate, schedule.loss_function);
+            device_synchronise();
+
+            if !valid {
+                mini.save(out_dir, format!("error-nan-batch-{curr_batch}-mini"));
+                panic!("Batch {curr_batch} NaN in mini net!");
+            }
+
+            positions += mini.inputs.used();
+        }
+
+        if let Some((norm, median)) = trainer.last_skipped_batch() {
+            println!(
+                "Skipped superbatch {} batch {}: gradient norm {} vs median {}",
+                ansi(superbatch, num_cs()),
+                ansi(curr_batch, num_cs()),
+                ansi(format!("{norm:.4}"), 31),
+                ansi(format!("{median:.4}"), 31),
+            );
+        }
+
         if curr_batch % 128 == 0 {
             report_superbatch_progress(
                 superbatch,
-                batch_size,
                 schedule.batches_per_superbatch,
                 curr_batch,
+                superbatch_positions,
                 &superbatch_timer,
             );
         }
 
         curr_batch += 1;
 
+        if !trainer.apply_weight_averaging(superbatch, curr_batch, schedule.batches_per_superbatch) {
+            trainer.save(out_dir, format!("error-distributed-superbatch-{superbatch}-batch-{curr_batch}"));
+            panic!("Lost the other machines at superbatch {superbatch} batch {curr_batch}!");
+        }
+
         if curr_batch % schedule.batches_per_superbatch == 0 {
-            let error = trainer.error() / schedule.batches_per_superbatch as f32;
+            let error = trainer.mean_error();
+            trainer.update_swa(superbatch);
+
+            report_superbatch_finished(schedule, superbatch, error, &superbatch_timer, &timer, superbatch_positions);
+            report_source_losses(&settings.data_file_paths, &mut source_losses);
+
+            RealizedSchedule::append(&schedule_path, &values)
+                .unwrap_or_else(|_| panic!("Writing to [{schedule_path}] failed!"));
+
+            let stats = trainer.take_activation_stats();
+            if !stats.is_empty() {
+                report_activation_stats(&stats);
+                let stats_path = format!("{out_dir}/{}-activations.csv", schedule.net_id());
+                append_activation_stats(&stats_path, superbatch, &stats)
+                    .unwrap_or_else(|_| panic!("Writing to [{stats_path}] failed!"));
+            }
+
+            let mut mini_loss = None;
+            if let Some(mini) = mini.as_deref_mut() {
+                let error = mini.mean_error();
+                println!("mini net running loss {}", ansi(format!("{error:.6}"), num_cs()));
+                mini.update_swa(superbatch);
+                mini.set_error_zero();
+                mini_loss = Some(error);
+            }
+
+            let mut validation = None;
+
+            if schedule.should_save(superbatch) {
+                let name = format!("{}-{superbatch}", schedule.net_id());
+                let path = format!("{out_dir}/{name}");
+                std::fs::create_dir(path.as_str()).unwrap_or(());
+                validation = trainer.export_eval_distribution(&format!("{path}/eval-distribution.csv"));
+                trainer
+                    .save_state(&path, superbatch)
+                    .unwrap_or_else(|_| panic!("Writing to [{path}/state.txt] failed!"));
+                checkpoints += 1;
+
+                let coverage_path = format!("{path}/data-coverage.csv");
+                write_data_coverage(&coverage_path, &datasets, &coverage)
+                    .unwrap_or_else(|_| panic!("Writing to [{coverage_path}] failed!"));
+                warn_unread_sources(&datasets, &coverage);
+
+                if let Some(mini) = mini.as_deref() {
+                    mini.save(out_dir, format!("{name}-mini"));
+                    checkpoints += 1;
+
+                    if !trainer.quantiser.is_empty() && !mini.quantiser.is_empty() {
+                        trainer.save_quantised_combined(mini, &format!("{path}/{name}-combined.bin"));
+                    }
+                }
+            }
 
-            report_superbatch_finished(schedule, superbatch, error, &superbatch_timer, &timer, pos_per_sb);
+            let time = timer.elapsed().as_secs_f32();
+            let record = Record { superbatch, loss: error, lr: lrate, mini_loss, validation, time };
+            trainer.report.as_mut().unwrap().push(record);
 
             callback(superbatch, trainer, schedule, settings);
 
             superbatch += 1;
             curr_batch = 0;
+            superbatch_positions = 0;
             superbatch_timer = Instant::now();
             trainer.set_error_zero();
         }
     }
 
     dataloader.join().unwrap();
+
+    warn_unread_sources(&datasets, &coverage);
+
+    let usage = RunUsage {
+        net_id: schedule.net_id(),
+        arch: format!("{trainer}"),
+        device: device_name(),
+        positions,
+        seconds: timer.elapsed().as_secs_f64(),
+        checkpoints,
+        measured_energy: energy.read(),
+        device_power: trainer.device_power(),
+    };
+    usage.report(out_dir);
+
+    let report = trainer.report.as_ref().unwrap();
+    report.write().unwrap_or_else(|_| panic!("Writing to [{}] failed!", report.path()));
+}
+
+/// Retrains a single superbatch of `schedule` on exactly the batches it was
+/// originally trained on, returning its average loss. The order of the data
+/// depends only on the files and the schedule, and any random augmentation is
+/// seeded by superbatch and batch, so loading the checkpoint saved at the end
+/// of the previous superbatch and replaying reproduces the run.
+pub fn replay_superbatch<T: InputType, U: OutputBuckets<T::RequiredDataType>>(
+    trainer: &mut Trainer<T, U>,
+    schedule: &TrainingSchedule,
+    settings: &LocalSettings,
+    superbatch: usize,
+) -> f32 {
+    assert!(
+        (schedule.start_superbatch..=schedule.end_superbatch).contains(&superbatch),
+        "Superbatch {superbatch} is not in the schedule!"
+    );
+
+    let data_file_paths: Vec<_> = settings.data_file_paths.iter().map(|s| s.to_string()).collect();
+
+    trainer.set_batch_size(schedule.batch_size);
+    trainer.set_ft_reg(schedule.ft_regularisation);
+    trainer.set_threads(settings.threads);
+    trainer.set_error_zero();
+    device_synchronise();
+
+    if let Some(replay) = trainer.schedule_replay() {
+        check_replay(replay, schedule);
+    }
+
+    let values = realized(trainer.schedule_replay(), schedule, superbatch);
+    let lrate = values.lr;
+    let timer = Instant::now();
+    let mut positions = 0;
+
+    let replay = trainer.schedule_replay().cloned();
+    let (batches, recycle, dataloader) =
+        spawn_dataloader(trainer, schedule, replay, data_file_paths, superbatch..=superbatch, settings.threads);
+
+    let mut next = batches.recv().ok();
+    if let Some((_, batch)) = &next {
+        trainer.prefetch_data(batch);
+    }
+
+    while let Some((_, batch)) = next.take() {
+        trainer.load_prefetched();
+        recycle.send(Arc::into_inner(batch).expect("Batch is still being uploaded!")).unwrap_or(());
+
+        next = batches.recv().ok();
+        if let Some((_, batch)) = &next {
+            trainer.prefetch_data(batch);
+        }
+
+        trainer.apply_ft_freeze(superbatch);
+        trainer.apply_beta_schedule(superbatch, schedule.end_superbatch);
+        trainer.apply_hard_mining_schedule(superbatch, schedule.end_superbatch);
+        let valid = trainer.train_on_batch(values.wd, lrate, schedule.loss_function);
+        device_synchronise();
+
+        assert!(valid, "Superbatch {superbatch} NaN!");
+        positions += trainer.inputs.used();
+    }
+
+    dataloader.join().unwrap();
+
+    let error = trainer.mean_error();
+    report_superbatch_finished(schedule, superbatch, error, &timer, &timer, positions);
+
+    error
+}
+
+/// Page-locked batches in flight between the dataloader thread and the
+/// trainer, which limits how far ahead of training the dataloader gets.
+const PINNED_BATCHES: usize = 16;
+
+/// A batch from the dataloader thread, with its copy in page-locked memory.
+type LoadedBatch<T, U> = (GpuDataLoader<T, U>, Arc<PinnedBatch>);
+
+/// Loads the batches of `superbatches` on a new thread, copying each into a
+/// page-locked batch from a pool of `PINNED_BATCHES`. Each batch must be sent
+/// back once every trainer has loaded it, to be refilled with a later one.
+fn spawn_dataloader<T: InputType, U: OutputBuckets<T::RequiredDataType>>(
+    trainer: &Trainer<T, U>,
+    schedule: &TrainingSchedule,
+    replay: Option<RealizedSchedule>,
+    data_file_paths: Vec<String>,
+    superbatches: RangeInclusive<usize>,
+    threads: usize,
+) -> (Receiver<LoadedBatch<T, U>>, Sender<PinnedBatch>, JoinHandle<()>) {
+    let (sender, reciever) = sync_channel(PINNED_BATCHES);
+    let (recycle, pool) = channel();
+
+    for _ in 0..PINNED_BATCHES {
+        recycle.send(trainer.pinned_batch()).unwrap();
+    }
+
+    let x = trainer.input_getter();
+    let y = trainer.bucket_getter();
+    let wdl_hook = trainer.wdl_hook();
+    let weight_hook = trainer.weight_hook();
+    let input_dropout = trainer.input_dropout();
+    let targets = schedule.loss_function.targets();
+    let holdout = trainer.game_holdout;
+    let sch = schedule.clone();
+    let batch_size = trainer.batch_size();
+    let rscale = 1.0 / schedule.eval_scale;
+
+    let dataloader = std::thread::spawn(move || {
+        for_each_batch(&data_file_paths, batch_size, &sch, holdout, |sb, cb, source, batch: &[T::RequiredDataType]| {
+            if sb < *superbatches.start() {
+                return true;
+            }
+
+            if sb > *superbatches.end() {
+                return false;
+            }
+
+            let blend = realized(replay.as_ref(), &sch, sb).wdl;
+            let mut gpu_loader = GpuDataLoader::<T, U>::new(x, y);
+            gpu_loader.set_source(source);
+            if let Some(dropout) = input_dropout {
+                gpu_loader.set_feature_dropout(dropout.blend(sb, sch.end_superbatch), batch_seed(sb, cb));
+            }
+            gpu_loader.load(batch, threads, blend, rscale, wdl_hook, weight_hook, &targets);
+
+            // waits until the trainer is done with one of the pinned batches
+            let Ok(mut pinned) = pool.recv() else { return false };
+            pinned.fill(&gpu_loader);
+            sender.send((gpu_loader, Arc::new(pinned))).is_ok()
+        });
+    });
+
+    (reciever, recycle, dataloader)
+}
+
+/// Starts uploading `batch` to `trainer`, and to `mini` if training one.
+fn prefetch_data<T: InputType, U: OutputBuckets<T::RequiredDataType>>(
+    trainer: &mut Trainer<T, U>,
+    mini: Option<&mut Trainer<T, U>>,
+    batch: &Arc<PinnedBatch>,
+) {
+    trainer.prefetch_data(batch);
+
+    if let Some(mini) = mini {
+        mini.prefetch_data(batch);
+    }
+}
+
+/// Values that `superbatch` is trained with, as recorded in `replay` if given.
+fn realized(replay: Option<&RealizedSchedule>, schedule: &TrainingSchedule, superbatch: usize) -> RealizedSuperbatch {
+    match replay {
+        Some(replay) => replay.get(schedule, superbatch),
+        None => RealizedSuperbatch::from_schedule(schedule, superbatch),
+    }
+}
+
+/// Checks that the recorded superbatches in `schedule` were made of the same
+/// batches, as the order of the data depends on both.
+fn check_replay(replay: &RealizedSchedule, schedule: &TrainingSchedule) {
+    let range = schedule.start_superbatch..=schedule.end_superbatch;
+
+    for record in replay.superbatches.iter().filter(|record| range.contains(&record.superbatch)) {
+        assert!(
+            record.batch_size == schedule.batch_size && record.batches == schedule.batches_per_superbatch,
+            "Superbatch {} was trained with {} batches of {} positions, but the schedule has {} batches of {}!",
+            record.superbatch,
+            record.batches,
+            record.batch_size,
+            schedule.batches_per_superbatch,
+            schedule.batch_size,
+        );
+    }
+}
+
+/// Seed for the randomness used in loading a given batch of a superbatch.
+pub(super) fn batch_seed(superbatch: usize, batch: usize) -> u64 {
+    ((superbatch as u64) << 32) | batch as u64
+}
+
+/// Calls `f` with each batch of data in training order, along with the superbatch
+/// it belongs to, its index within it and the index of the file it was read from,
+/// until the end of the schedule or `f` returns false. Positions of games held out
+/// by `holdout` are skipped, with batches filled from the positions after them.
+/// The last batch of each file holds whatever positions are left over, so may
+/// be smaller than `batch_size`, rather than being dropped.
+pub(super) fn for_each_batch<D: Copy, F>(
+    data_file_paths: &[String],
+    batch_size: usize,
+    schedule: &TrainingSchedule,
+    holdout: Option<GameHoldout<D>>,
+    mut f: F,
+) where
+    F: FnMut(usize, usize, usize, &[D]) -> bool,
+{
+    let mut sb = schedule.start_superbatch;
+    let mut cb = 0;
+
+    // returns false once training should stop
+    let mut step = |source: usize, batch: &[D]| {
+        if !f(sb, cb, source, batch) {
+            return false;
+        }
+
+        cb += 1;
+        if cb % schedule.batches_per_superbatch == 0 {
+            if sb == schedule.end_superbatch {
+                return false;
+            }
+
+            cb = 0;
+            sb += 1;
+        }
+
+        true
+    };
+
+    let mut kept = Vec::with_capacity(batch_size);
+    let mut kept_source = 0;
+
+    loop {
+        let finished = for_each_chunk(data_file_paths, batch_size, |source, data: &[D]| {
+            let Some(holdout) = holdout else {
+                return data.chunks(batch_size).all(|batch| step(source, batch));
+            };
+
+            // positions left over from the end of the previous file
+            if source != kept_source && !kept.is_empty() {
+                if !step(kept_source, &kept) {
+                    return false;
+                }
+
+                kept.clear();
+            }
+
+            kept_source = source;
+
+            for pos in data.iter().filter(|pos| !holdout.is_held_out(pos)) {
+                kept.push(*pos);
+
+                if kept.len() == batch_size {
+                    if !step(source, &kept) {
+                        return false;
+                    }
+
+                    kept.clear();
+                }
+            }
+
+            true
+        });
+
+        if finished {
+            return;
+        }
+
+        // positions left over from the end of the last file
+        if !kept.is_empty() {
+            if !step(kept_source, &kept) {
+                return;
+            }
+
+            kept.clear();
+        }
+    }
+}
+
+/// Calls `f` with each chunk of data read from the files in turn, with the
+/// index of its file, returning true if `f` returned false to stop early.
+fn for_each_chunk<D, F>(data_file_paths: &[String], batch_size: usize, mut f: F) -> bool
+where
+    F: FnMut(usize, &[D]) -> bool,
+{
+    let buffer_size_mb = 256;
+    let buffer_size = buffer_size_mb * 1024 * 1024;
+    let data_size: usize = std::mem::size_of::<D>();
+    let batches_per_load = buffer_size / data_size / batch_size;
+    let cap = data_size * batch_size * batches_per_load;
+
+    let mut loader_files = vec![];
+    for file in data_file_paths.iter() {
+        loader_files.push(File::open(file).unwrap_or_else(|_| panic!("Invalid File Path: {file}")));
+    }
+
+    for (source, loader_file) in loader_files.iter().enumerate() {
+        let mut file = BufReader::with_capacity(cap, loader_file);
+        while let Ok(buf) = file.fill_buf() {
+            if buf.is_empty() {
+                break;
+            }
+
+            let data: &[D] = util::to_slice_with_lifetime(buf);
+
+            if !f(source, data) {
+                return true;
+            }
+
+            let consumed = buf.len();
+            file.consume(consumed);
+        }
+    }
+
+    false
+}
+
+/// The first `sample_size` positions in the data of games held out by `holdout`.
+pub(super) fn holdout_sample<D: Copy>(
+    data_file_paths: &[String],
+    batch_size: usize,
+    holdout: GameHoldout<D>,
+) -> Vec<D> {
+    let mut sample = Vec::with_capacity(holdout.sample_size);
+
+    for_each_chunk(data_file_paths, batch_size, |_, data: &[D]| {
+        let held_out = data.iter().filter(|pos| holdout.is_held_out(pos));
+        sample.extend(held_out.take(holdout.sample_size - sample.len()));
+        sample.len() < holdout.sample_size
+    });
+
+    sample
+}
+
+/// Running loss of the batches read from one data file during a superbatch.
+#[derive(Clone, Copy, Default)]
+struct SourceLoss {
+    /// Summed over every position, so that a partial final batch
+    /// counts for no more than the positions in it.
+    error: f32,
+    positions: usize,
+    batches: usize,
+}
+
+/// Reports the loss on each data file, if there are several, so that
+/// it is clear which the net fits well and which it struggles with.
+fn report_source_losses(data_file_paths: &[&str], source_losses: &mut [SourceLoss]) {
+    if source_losses.len() > 1 {
+        for (path, source) in data_file_paths.iter().zip(source_losses.iter()) {
+            let loss = match source.positions {
+                0 => String::from("-"),
+                positions => format!("{:.6}", source.error / positions as f32),
+            };
+
+            println!("  {} | loss {} | {} batches", ansi(path, "32;1"), ansi(loss, num_cs()), source.batches);
+        }
+    }
+
+    source_losses.fill(SourceLoss::default());
+}
+
+fn report_activation_stats(stats: &[ActivationStats]) {
+    for node in stats {
+        print!(
+            "  node {} {} | mean {} | std {} | sparsity {}",
+            node.node,
+            ansi(&node.name, "32;1"),
+            ansi(format!("{:.4}", node.mean), num_cs()),
+            ansi(format!("{:.4}", node.std), num_cs()),
+            ansi(format!("{:.2}%", 100.0 * node.sparsity), num_cs()),
+        );
+
+        for (name, value) in &node.custom {
+            print!(" | {name} {}", ansi(format!("{value:.4}"), num_cs()));
+        }
+
+        println!();
+    }
+}
+
+/// Appends the activation statistics of a superbatch to a CSV file
+/// of `superbatch,node,name,metric,value`, one row per metric.
+fn append_activation_stats(path: &str, superbatch: usize, stats: &[ActivationStats]) -> std::io::Result<()> {
+    let exists = std::path::Path::new(path).exists();
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+
+    if !exists {
+        writeln!(file, "superbatch,node,name,metric,value")?;
+    }
+
+    for node in stats {
+        let builtin = [("mean", node.mean), ("std", node.std), ("sparsity", node.sparsity)];
+        let extremes = [("min", node.min), ("max", node.max)];
+
+        for (metric, value) in builtin.iter().chain(&extremes).chain(&node.custom) {
+            writeln!(file, "{superbatch},{},\"{}\",{metric},{value}", node.node, node.name)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Batches and positions read from one data file over the whole
+/// run, and when it was last read.
+#[derive(Clone, Copy, Default)]
+struct SourceCoverage {
+    batches: usize,
+    positions: usize,
+    last_read: Option<Instant>,
+}
+
+impl SourceCoverage {
+    fn record_batch(&mut self, positions: usize) {
+        self.batches += 1;
+        self.positions += positions;
+        self.last_read = Some(Instant::now());
+    }
+}
+
+/// Writes how much of each data file has been trained on so far, as
+/// `file,positions,batches,positions_read,epochs,secs_since_read`, with
+/// an empty last column for files that have never been read.
+fn write_data_coverage(path: &str, datasets: &[(&str, usize)], coverage: &[SourceCoverage]) -> std::io::Result<()> {
+    let mut csv = String::from("file,positions,batches,positions_read,epochs,secs_since_read\n");
+
+    for (&(file, positions), source) in datasets.iter().zip(coverage.iter()) {
+        let read = source.positions;
+        let epochs = read as f64 / positions.max(1) as f64;
+        let since = source.last_read.map(|time| format!("{:.1}", time.elapsed().as_secs_f32())).unwrap_or_default();
+        csv += &format!("{file},{positions},{},{read},{epochs:.4},{since}\n", source.batches);
+    }
+
+    std::fs::write(path, csv)
+}
+
+/// Warns about configured data files that no batch has been read from, which
+/// usually means a mistyped path has quietly shrunk the dataset.
+fn warn_unread_sources(datasets: &[(&str, usize)], coverage: &[SourceCoverage]) {
+    for (&(file, positions), source) in datasets.iter().zip(coverage.iter()) {
+        if source.batches == 0 {
+            println!(
+                "{} data file {} ({} positions) has not been read from",
+                ansi("Warning:", 31),
+                ansi(file, "32;1"),
+                ansi(positions, num_cs()),
+            );
+        }
+    }
 }
 
 static CBCS: AtomicBool = AtomicBool::new(false);
@@ -225,15 +839,14 @@ fn esc() -> &'static str {
 
 fn report_superbatch_progress(
     superbatch: usize,
-    batch_size: usize,
     batches: usize,
     finished_batches: usize,
+    positions: usize,
     superbatch_timer: &Instant,
 ) {
     let num_cs = num_cs();
     let superbatch_time = superbatch_timer.elapsed().as_secs_f32();
     let pct = finished_batches as f32 / batches as f32;
-    let positions = finished_batches * batch_size;
     let pos_per_sec = positions as f32 / superbatch_time;
 
     let seconds = superbatch_time / pct - superbatch_time;
@@ -1,4 +1,9 @@
-use crate::ansi;
+use std::io::Write;
+
+use crate::{
+    ansi,
+    tensor::{DeviceBuffer, DeviceHandles, TensorBatch},
+};
 
 #[derive(Clone, Debug)]
 pub struct TrainingSchedule {
@@ -11,6 +16,7 @@ pub struct TrainingSchedule {
     pub end_superbatch: usize,
     pub wdl_scheduler: WdlScheduler,
     pub lr_scheduler: LrScheduler,
+    pub wd_scheduler: WdScheduler,
     pub loss_function: Loss,
     pub save_rate: usize,
 }
@@ -20,6 +26,19 @@ impl TrainingSchedule {
         self.net_id.clone()
     }
 
+    /// Sets the number of batches per superbatch to cover `positions`, rounded
+    /// up to a whole batch, so that superbatches stay comparable between runs
+    /// with different batch sizes, e.g. `with_superbatch_positions(100_000_000)`.
+    pub fn with_superbatch_positions(mut self, positions: usize) -> Self {
+        assert!(positions > 0, "Superbatches must contain at least one position!");
+        self.batches_per_superbatch = positions.div_ceil(self.batch_size);
+        self
+    }
+
+    pub fn positions_per_superbatch(&self) -> usize {
+        self.batch_size * self.batches_per_superbatch
+    }
+
     pub fn should_save(&self, superbatch: usize) -> bool {
         superbatch % self.save_rate == 0 || superbatch == self.end_superbatch
     }
@@ -28,6 +47,10 @@ impl TrainingSchedule {
         self.lr_scheduler.lr(superbatch)
     }
 
+    pub fn wd(&self, superbatch: usize) -> f32 {
+        self.wd_scheduler.wd(superbatch, self.end_superbatch)
+    }
+
     pub fn wdl(&self, superbatch: usize) -> f32 {
         self.wdl_scheduler.blend(superbatch, self.end_superbatch)
     }
@@ -37,19 +60,189 @@ impl TrainingSchedule {
         println!("1 / FT Regularisation  : {}", ansi(format!("{:.0}", 1.0 / self.ft_regularisation), 31));
         println!("Batch Size             : {}", ansi(self.batch_size, 31));
         println!("Batches / Superbatch   : {}", ansi(self.batches_per_superbatch, 31));
-        println!("Positions / Superbatch : {}", ansi(self.batches_per_superbatch * self.batch_size, 31));
+        println!("Positions / Superbatch : {}", ansi(self.positions_per_superbatch(), 31));
         println!("Start Superbatch       : {}", ansi(self.start_superbatch, 31));
         println!("End Superbatch         : {}", ansi(self.end_superbatch, 31));
         println!("Save Rate              : {}", ansi(self.save_rate, 31));
         println!("WDL Scheduler          : {}", self.wdl_scheduler.colourful());
         println!("LR Scheduler           : {}", self.lr_scheduler.colourful());
+        println!("WD Scheduler           : {}", self.wd_scheduler.colourful());
     }
 
     pub fn power(&self) -> f32 {
-        match self.loss_function {
-            Loss::SigmoidMSE => 2.0,
-            Loss::SigmoidMPE(x) => x,
+        self.loss_function.power()
+    }
+}
+
+/// Values of the schedule that a single superbatch was trained with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RealizedSuperbatch {
+    pub superbatch: usize,
+    pub batch_size: usize,
+    pub batches: usize,
+    pub lr: f32,
+    pub wd: f32,
+    pub wdl: f32,
+}
+
+impl RealizedSuperbatch {
+    pub fn from_schedule(schedule: &TrainingSchedule, superbatch: usize) -> Self {
+        Self {
+            superbatch,
+            batch_size: schedule.batch_size,
+            batches: schedule.batches_per_superbatch,
+            lr: schedule.lr(superbatch),
+            wd: schedule.wd(superbatch),
+            wdl: schedule.wdl(superbatch),
+        }
+    }
+}
+
+/// Schedule that a run was actually trained with, recorded superbatch by
+/// superbatch to `<net>-schedule.csv` in the output directory, so that runs
+/// tweaked by hand can be reproduced exactly with `Trainer::set_schedule_replay`.
+#[derive(Clone, Debug, Default)]
+pub struct RealizedSchedule {
+    pub superbatches: Vec<RealizedSuperbatch>,
+}
+
+impl RealizedSchedule {
+    const HEADER: &'static str = "superbatch,batch_size,batches,lr,wd,wdl";
+
+    /// Loads a schedule recorded by a previous run.
+    pub fn load(path: &str) -> Self {
+        let csv = std::fs::read_to_string(path).unwrap_or_else(|_| panic!("Reading [{path}] failed!"));
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(Self::HEADER), "[{path}] is not a recorded schedule!");
+
+        let superbatches = lines
+            .map(|line| {
+                let fields: Vec<_> = line.split(',').collect();
+                assert_eq!(fields.len(), 6, "Invalid line [{line}] in [{path}]!");
+
+                let invalid = || -> ! { panic!("Invalid line [{line}] in [{path}]!") };
+                let int = |i: usize| fields[i].parse().unwrap_or_else(|_| invalid());
+                let float = |i: usize| fields[i].parse().unwrap_or_else(|_| invalid());
+
+                RealizedSuperbatch {
+                    superbatch: int(0),
+                    batch_size: int(1),
+                    batches: int(2),
+                    lr: float(3),
+                    wd: float(4),
+                    wdl: float(5),
+                }
+            })
+            .collect();
+
+        Self { superbatches }
+    }
+
+    /// Values for `superbatch` as recorded, or from `schedule` for
+    /// superbatches that weren't, e.g. when extending a run.
+    pub fn get(&self, schedule: &TrainingSchedule, superbatch: usize) -> RealizedSuperbatch {
+        // later records win, as a resumed run records superbatches again
+        let recorded = self.superbatches.iter().rev().find(|record| record.superbatch == superbatch);
+        recorded.copied().unwrap_or_else(|| RealizedSuperbatch::from_schedule(schedule, superbatch))
+    }
+
+    /// Appends the values of one superbatch to the schedule recorded at `path`.
+    pub(super) fn append(path: &str, record: &RealizedSuperbatch) -> std::io::Result<()> {
+        let exists = std::path::Path::new(path).exists();
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+
+        if !exists {
+            writeln!(file, "{}", Self::HEADER)?;
+        }
+
+        let RealizedSuperbatch { superbatch, batch_size, batches, lr, wd, wdl } = record;
+        writeln!(file, "{superbatch},{batch_size},{batches},{lr},{wd},{wdl}")
+    }
+}
+
+/// A single schedule within a `TrainingRecipe`, trained on its own data.
+#[derive(Clone, Debug)]
+pub struct TrainingStage<'a> {
+    pub schedule: TrainingSchedule,
+    /// If empty, the data file paths from the `LocalSettings` are used.
+    pub data_file_paths: Vec<&'a str>,
+}
+
+/// A sequence of schedules trained one after another, each starting from
+/// the weights and optimiser state at the end of the previous one, e.g.
+/// pretraining on a large dataset before finetuning on a curated one.
+#[derive(Clone, Debug)]
+pub struct TrainingRecipe<'a> {
+    pub name: String,
+    pub stages: Vec<TrainingStage<'a>>,
+}
+
+impl<'a> TrainingRecipe<'a> {
+    /// Name of the checkpoint that stage `idx` starts from, if any.
+    pub fn starting_checkpoint(&self, idx: usize) -> Option<String> {
+        let prev = &self.stages[idx.checked_sub(1)?].schedule;
+        Some(format!("{}-{}", prev.net_id(), prev.end_superbatch))
+    }
+
+    /// Panics if two stages have the same `net_id`, or one has the name of the
+    /// recipe, as they would write checkpoints and logs over each other.
+    pub fn check_names(&self) {
+        for (i, stage) in self.stages.iter().enumerate() {
+            let id = stage.schedule.net_id();
+            assert_ne!(id, self.name, "Stage {} has the same name as the recipe!", i + 1);
+
+            if let Some(j) = self.stages[..i].iter().position(|prev| prev.schedule.net_id() == id) {
+                panic!("Stages {} and {} both have the net_id [{id}]!", j + 1, i + 1);
+            }
+        }
+    }
+
+    /// Concatenates the `<net_id>-<suffix>` CSV written by each stage into
+    /// `<name>-<suffix>`, with the number of the stage in a leading column.
+    pub(crate) fn merge_stage_csvs(&self, out_dir: &str, suffix: &str) -> std::io::Result<()> {
+        let mut merged = String::new();
+
+        for (i, stage) in self.stages.iter().enumerate() {
+            let Ok(contents) = std::fs::read_to_string(format!("{out_dir}/{}-{suffix}", stage.schedule.net_id()))
+            else {
+                continue;
+            };
+
+            let mut lines = contents.lines();
+            let header = lines.next().unwrap_or_default();
+            if merged.is_empty() {
+                merged += &format!("stage,{header}\n");
+            }
+
+            for line in lines {
+                merged += &format!("{},{line}\n", i + 1);
+            }
+        }
+
+        if merged.is_empty() {
+            return Ok(());
         }
+
+        std::fs::write(format!("{out_dir}/{}-{suffix}", self.name), merged)
+    }
+
+    pub fn write_metadata(&self, path: &str, default_data: &[&str]) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+
+        writeln!(file, "Recipe: {}", self.name)?;
+        for (i, stage) in self.stages.iter().enumerate() {
+            let data = if stage.data_file_paths.is_empty() { default_data } else { &stage.data_file_paths };
+
+            writeln!(file)?;
+            writeln!(file, "Stage {}/{}", i + 1, self.stages.len())?;
+            writeln!(file, "Data: {data:?}")?;
+            if let Some(checkpoint) = self.starting_checkpoint(i) {
+                writeln!(file, "Starting From: {checkpoint}")?;
+            }
+            writeln!(file, "{:#?}", stage.schedule)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -57,6 +250,103 @@ impl TrainingSchedule {
 pub enum Loss {
     SigmoidMSE,
     SigmoidMPE(f32),
+    /// `SigmoidMPE(power)` with each position's gradient scaled by
+    /// `|error|^gamma`, focusing training on the hardest positions.
+    /// The reported loss is unscaled.
+    SigmoidFocal {
+        power: f32,
+        gamma: f32,
+    },
+    /// `SigmoidMSE` on the first of two outputs, with the second trained to
+    /// predict the squared error of the first, as a measure of uncertainty.
+    /// The reported loss is that of the first output only.
+    SigmoidMSEVariance,
+    /// Huber loss on the sigmoid of the output, quadratic for
+    /// errors up to `delta` and linear beyond that.
+    SigmoidHuber {
+        delta: f32,
+    },
+    /// Cross-entropy of the softmax of three output logits (win, draw, loss)
+    /// against the blended game result distribution.
+    SoftmaxWDL,
+    /// A user-defined loss function.
+    Custom(&'static dyn LossFunction),
+    /// One loss per output head, e.g. a value head and a WDL head built with
+    /// `TrainerBuilder::output_heads`. The outputs of the net are split into
+    /// consecutive heads of the sizes each loss expects, and the reported
+    /// loss and gradients of each head are scaled by its weight.
+    MultiHead(&'static [LossHead]),
+}
+
+/// A head of a `Loss::MultiHead`.
+#[derive(Clone, Copy, Debug)]
+pub struct LossHead {
+    pub loss: Loss,
+    pub weight: f32,
+}
+
+/// A loss function provided from outside the crate, used via `Loss::Custom`.
+pub trait LossFunction: std::fmt::Debug + Sync {
+    /// Computes the loss of the first `batch_size` tensors in `outputs` against
+    /// `results`, adding the total to `error`, and replaces each output with
+    /// the gradient of the loss with respect to it.
+    fn calc_errors(
+        &self,
+        handle: DeviceHandles,
+        batch_size: usize,
+        outputs: &TensorBatch,
+        results: &TensorBatch,
+        error: &DeviceBuffer,
+    );
+
+    /// Scale applied to the gradients before the optimiser step.
+    fn power(&self) -> f32 {
+        1.0
+    }
+
+    /// Whether the loss expects (win, draw, loss) targets.
+    fn is_wdl(&self) -> bool {
+        false
+    }
+}
+
+impl Loss {
+    pub fn power(&self) -> f32 {
+        match *self {
+            Self::SigmoidMSE | Self::SigmoidMSEVariance => 2.0,
+            Self::SigmoidMPE(x) | Self::SigmoidFocal { power: x, .. } => x,
+            Self::SigmoidHuber { .. } | Self::SoftmaxWDL => 1.0,
+            Self::Custom(loss) => loss.power(),
+            // each head is scaled by its own power when calculating its errors
+            Self::MultiHead(_) => 1.0,
+        }
+    }
+
+    pub fn is_wdl(&self) -> bool {
+        match self {
+            Self::SoftmaxWDL => true,
+            Self::Custom(loss) => loss.is_wdl(),
+            _ => false,
+        }
+    }
+
+    /// Number of outputs per position the loss expects.
+    pub fn outputs(&self) -> usize {
+        match self {
+            Self::SigmoidMSEVariance => 2,
+            Self::MultiHead(heads) => heads.iter().map(|head| head.loss.outputs()).sum(),
+            _ if self.is_wdl() => 3,
+            _ => 1,
+        }
+    }
+
+    /// Number of outputs per position of each head, as loaded by `GpuDataLoader::load`.
+    pub fn targets(&self) -> Vec<usize> {
+        match self {
+            Self::MultiHead(heads) => heads.iter().map(|head| head.loss.outputs()).collect(),
+            _ => vec![self.outputs()],
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -105,6 +395,124 @@ impl LrScheduler {
     }
 }
 
+/// Decoupled weight decay, as used by AdamW.
+#[derive(Clone, Copy, Debug)]
+pub enum WdScheduler {
+    /// Constant Decay
+    Constant { value: f32 },
+    /// Linearly interpolate from `start` to `end` over the run.
+    Linear { start: f32, end: f32 },
+    /// Cosine anneal from `start` to `end` over the run.
+    Cosine { start: f32, end: f32 },
+}
+
+impl WdScheduler {
+    pub fn wd(&self, superbatch: usize, max: usize) -> f32 {
+        let progress = (superbatch - 1) as f32 / (max - 1).max(1) as f32;
+
+        match *self {
+            Self::Constant { value } => value,
+            Self::Linear { start, end } => start + (end - start) * progress,
+            Self::Cosine { start, end } => end + (start - end) * 0.5 * (1.0 + (std::f32::consts::PI * progress).cos()),
+        }
+    }
+
+    pub fn colourful(&self) -> String {
+        match *self {
+            Self::Constant { value } => format!("constant {}", ansi(value, 31)),
+            Self::Linear { start, end } => {
+                format!("linear taper start {} end {}", ansi(start, 31), ansi(end, 31))
+            }
+            Self::Cosine { start, end } => {
+                format!("cosine anneal start {} end {}", ansi(start, 31), ansi(end, 31))
+            }
+        }
+    }
+}
+
+/// Decay rates of the first and second moments of Adam-style optimisers,
+/// as `(beta1, beta2)`, e.g. raising beta2 late in training.
+#[derive(Clone, Copy, Debug)]
+pub enum BetaScheduler {
+    Constant {
+        beta1: f32,
+        beta2: f32,
+    },
+    /// Linearly interpolate each beta from `start` to `end` over the run.
+    Linear {
+        start: (f32, f32),
+        end: (f32, f32),
+    },
+    /// Cosine anneal each beta from `start` to `end` over the run.
+    Cosine {
+        start: (f32, f32),
+        end: (f32, f32),
+    },
+}
+
+impl BetaScheduler {
+    pub fn betas(&self, superbatch: usize, max: usize) -> (f32, f32) {
+        let progress = (superbatch - 1) as f32 / (max - 1).max(1) as f32;
+        let lerp = |start: f32, end: f32, t: f32| start + (end - start) * t;
+
+        match *self {
+            Self::Constant { beta1, beta2 } => (beta1, beta2),
+            Self::Linear { start, end } => (lerp(start.0, end.0, progress), lerp(start.1, end.1, progress)),
+            Self::Cosine { start, end } => {
+                let t = 0.5 * (1.0 - (std::f32::consts::PI * progress).cos());
+                (lerp(start.0, end.0, t), lerp(start.1, end.1, t))
+            }
+        }
+    }
+
+    pub fn colourful(&self) -> String {
+        let pair = |(beta1, beta2): (f32, f32)| format!("({}, {})", ansi(beta1, 31), ansi(beta2, 31));
+
+        match *self {
+            Self::Constant { beta1, beta2 } => format!("constant {}", pair((beta1, beta2))),
+            Self::Linear { start, end } => format!("linear start {} end {}", pair(start), pair(end)),
+            Self::Cosine { start, end } => format!("cosine anneal start {} end {}", pair(start), pair(end)),
+        }
+    }
+}
+
+/// Freezes a part of the network partway through training,
+/// by scaling its learning rate down to zero.
+#[derive(Clone, Copy, Debug)]
+pub enum FreezeScheduler {
+    /// Freeze from superbatch `start` onwards.
+    Freeze { start: usize },
+    /// Linearly reduce the learning rate to zero between superbatch `start` and `end`.
+    Taper { start: usize, end: usize },
+}
+
+impl FreezeScheduler {
+    pub fn lr_scale(&self, superbatch: usize) -> f32 {
+        match *self {
+            Self::Freeze { start } => {
+                if superbatch >= start {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Self::Taper { start, end } => {
+                let progress = (superbatch.saturating_sub(start) as f32 / (end - start).max(1) as f32).min(1.0);
+                1.0 - progress
+            }
+        }
+    }
+
+    pub fn colourful(&self) -> String {
+        match *self {
+            Self::Freeze { start } => format!("freeze at superbatch {}", ansi(start, 31)),
+            Self::Taper { start, end } => {
+                format!("taper to zero from superbatch {} to {}", ansi(start, 31), ansi(end, 31))
+            }
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum WdlScheduler {
     Constant { value: f32 },
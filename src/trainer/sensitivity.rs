@@ -0,0 +1,135 @@
+use crate::{inputs::InputType, outputs::OutputBuckets, LocalSettings, TrainingSchedule};
+
+use super::{ansi, run, Trainer};
+
+/// Mean and sample standard deviation of a metric across replicate runs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Spread {
+    pub mean: f64,
+    pub stdev: f64,
+}
+
+impl Spread {
+    fn new(values: &[f32]) -> Self {
+        let n = values.len() as f64;
+        let mean = values.iter().map(|&x| f64::from(x)).sum::<f64>() / n;
+        let var = values.iter().map(|&x| (f64::from(x) - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+        Self { mean, stdev: var.sqrt() }
+    }
+
+    /// Standard deviation as a fraction of the mean, for comparing
+    /// against relative differences between experiments.
+    pub fn relative(&self) -> f64 {
+        self.stdev / self.mean.abs()
+    }
+}
+
+impl std::fmt::Display for Spread {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.6} +/- {:.6} ({:.3}%)", self.mean, self.stdev, 100.0 * self.relative())
+    }
+}
+
+/// Results of [`seed_sensitivity`], with entries in the order of `seeds`.
+#[derive(Clone, Debug)]
+pub struct SeedSensitivity {
+    pub seeds: Vec<u64>,
+    /// Average loss of the final superbatch of each run.
+    pub losses: Vec<f32>,
+    /// Value of the probe at the end of each run, if one was given.
+    pub probes: Vec<f32>,
+}
+
+impl SeedSensitivity {
+    pub fn loss(&self) -> Spread {
+        Spread::new(&self.losses)
+    }
+
+    pub fn probe(&self) -> Option<Spread> {
+        (!self.probes.is_empty()).then(|| Spread::new(&self.probes))
+    }
+}
+
+impl std::fmt::Display for SeedSensitivity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:>20} {:>14} {:>14}", "Seed", "Loss", "Probe")?;
+
+        for (i, (seed, loss)) in self.seeds.iter().zip(&self.losses).enumerate() {
+            let probe = self.probes.get(i).map_or(String::from("-"), |probe| format!("{probe:.6}"));
+            writeln!(f, "{seed:>20} {loss:>14.6} {probe:>14}")?;
+        }
+
+        write!(f, "Loss                   : {}", self.loss())?;
+
+        if let Some(probe) = self.probe() {
+            write!(f, "\nProbe                  : {probe}")?;
+        }
+
+        Ok(())
+    }
+}
+
+type Probe<T, U> = fn(&mut Trainer<T, U>) -> f32;
+
+/// Trains a fresh net from `build` for each seed, differing only in the seed
+/// used to initialise the weights, and reports the spread of the final loss
+/// and of `probe`, evaluated on each trained net. Differences between two
+/// experiments that are within a couple of standard deviations are noise.
+///
+/// Each run writes its checkpoints to `<output_directory>/seed-<seed>`, and
+/// the results are written to `<output_directory>/seed-sensitivity.txt`.
+pub fn seed_sensitivity<T, U, B>(
+    mut build: B,
+    seeds: &[u64],
+    schedule: &TrainingSchedule,
+    settings: &LocalSettings,
+    probe: Option<Probe<T, U>>,
+) -> SeedSensitivity
+where
+    T: InputType,
+    U: OutputBuckets<T::RequiredDataType>,
+    B: FnMut() -> Trainer<T, U>,
+{
+    assert!(seeds.len() > 1, "Need at least two seeds to measure their spread!");
+
+    let mut results = SeedSensitivity { seeds: seeds.to_vec(), losses: Vec::new(), probes: Vec::new() };
+
+    for (i, &seed) in seeds.iter().enumerate() {
+        println!("{}", ansi(format!("Seed {seed} ({}/{})", i + 1, seeds.len()), "34;1"));
+
+        let out_dir = format!("{}/seed-{seed}", settings.output_directory);
+        std::fs::create_dir_all(&out_dir).unwrap_or_else(|_| panic!("Creating [{out_dir}] failed!"));
+
+        let run_settings = LocalSettings {
+            threads: settings.threads,
+            data_file_paths: settings.data_file_paths.clone(),
+            output_directory: &out_dir,
+            resume_from: None,
+        };
+
+        let mut trainer = build();
+        trainer.randomise_weights_seeded(seed);
+
+        let mut loss = 0.0;
+        run(&mut trainer, schedule, &run_settings, |superbatch, trainer, schedule, settings| {
+            loss = trainer.mean_error();
+
+            if schedule.should_save(superbatch) {
+                trainer.save(settings.output_directory, format!("{}-{superbatch}", schedule.net_id()));
+            }
+        });
+
+        results.losses.push(loss);
+
+        if let Some(probe) = probe {
+            results.probes.push(probe(&mut trainer));
+        }
+    }
+
+    let path = format!("{}/seed-sensitivity.txt", settings.output_directory);
+    std::fs::write(&path, format!("{}\n\n{results}\n", schedule.net_id()))
+        .unwrap_or_else(|_| panic!("Writing to [{path}] failed!"));
+    println!("{results}");
+
+    results
+}
@@ -0,0 +1,158 @@
+use crate::{
+    inputs::InputType,
+    outputs::OutputBuckets,
+    tensor::{DeviceBuffer, TensorBatch},
+};
+
+use super::{heads::HeadBuffers, Attention, Concat, FeatureTransformer, Multiply, Node, Operation, Trainer};
+
+/// Memory shared by the buffers only used while calculating the loss or during
+/// part of backprop, each of which is overwritten before it is read, so those
+/// whose lifetimes don't overlap are pointed into the same slot.
+pub(super) struct SharedBuffers {
+    /// Only accessed through the buffers pointed into them.
+    _slots: Vec<DeviceBuffer>,
+    /// Floats the buffers would take if each had its own memory.
+    unshared: usize,
+}
+
+enum Buffer<'a> {
+    Batch(&'a mut TensorBatch),
+    Raw(&'a mut DeviceBuffer),
+}
+
+/// A buffer of `size` floats that is live from when backprop reaches stage `last`
+/// until it leaves stage `first`, where the loss is calculated in stage
+/// `nodes.len() + 1`, node `i` is backpropagated in stage `i + 1` and the
+/// feature transformer in stage 0.
+struct Lifetime<'a> {
+    buffer: Buffer<'a>,
+    size: usize,
+    first: usize,
+    last: usize,
+}
+
+impl<'a> Lifetime<'a> {
+    fn batch(tensor: &'a mut TensorBatch, batch_size: usize, first: usize, last: usize) -> Self {
+        let size = batch_size * tensor.element_size();
+        Self { buffer: Buffer::Batch(tensor), size, first, last }
+    }
+
+    fn raw(buffer: &'a mut DeviceBuffer, size: usize, stage: usize) -> Self {
+        Self { buffer: Buffer::Raw(buffer), size, first: stage, last: stage }
+    }
+}
+
+fn lifetimes<'a>(
+    ft: &'a mut FeatureTransformer,
+    nodes: &'a mut [Node],
+    heads: &'a mut [HeadBuffers],
+    batch_size: usize,
+) -> Vec<Lifetime<'a>> {
+    let loss = nodes.len() + 1;
+    let mut lifetimes = Vec::new();
+
+    for head in heads {
+        let [outputs, results] = head.scratch();
+        lifetimes.push(Lifetime::batch(outputs, batch_size, loss, loss));
+        lifetimes.push(Lifetime::batch(results, batch_size, loss, loss));
+    }
+
+    // written before node 0 is backpropagated, and read by the feature transformer
+    if let Some(copy) = &mut ft.copy {
+        lifetimes.push(Lifetime::batch(copy, batch_size, 0, 1));
+    }
+
+    for (i, node) in nodes.iter_mut().enumerate() {
+        match &mut node.op {
+            // written by the node, and added to the errors of each source once they are known
+            Operation::Concat(Concat { sources, grads }) => {
+                for (source, grad) in sources.iter().zip(grads) {
+                    lifetimes.push(Lifetime::batch(grad, batch_size, source + 1, i + 1));
+                }
+            }
+            Operation::Multiply(Multiply { source, grad }) => {
+                lifetimes.push(Lifetime::batch(grad, batch_size, *source + 1, i + 1));
+            }
+            Operation::Attention(Attention { qkv, attn, qkv_grad, attn_grad, .. }) => {
+                lifetimes.push(Lifetime::raw(qkv_grad, qkv.size(), i + 1));
+                lifetimes.push(Lifetime::raw(attn_grad, attn.size(), i + 1));
+            }
+            _ => {}
+        }
+    }
+
+    lifetimes
+}
+
+/// Assigns each lifetime to the first slot with no overlapping lifetimes, from
+/// the largest buffer down, returning the size of each slot and the slot of each buffer.
+fn pack(lifetimes: &[Lifetime]) -> (Vec<usize>, Vec<usize>) {
+    let mut order = (0..lifetimes.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(lifetimes[i].size));
+
+    let mut slots: Vec<(usize, Vec<(usize, usize)>)> = Vec::new();
+    let mut assigned = vec![0; lifetimes.len()];
+
+    for i in order {
+        let Lifetime { size, first, last, .. } = lifetimes[i];
+        let free = |used: &Vec<(usize, usize)>| used.iter().all(|&(f, l)| last < f || l < first);
+
+        assigned[i] = match slots.iter().position(|(_, used)| free(used)) {
+            Some(slot) => slot,
+            None => {
+                slots.push((size, Vec::new()));
+                slots.len() - 1
+            }
+        };
+
+        slots[assigned[i]].1.push((first, last));
+    }
+
+    (slots.into_iter().map(|(size, _)| size).collect(), assigned)
+}
+
+impl SharedBuffers {
+    /// Points each buffer only used during backprop or the loss into the shared slots,
+    /// at the given batch size, regardless of the batch size it was allocated for.
+    pub fn new(ft: &mut FeatureTransformer, nodes: &mut [Node], heads: &mut [HeadBuffers], batch_size: usize) -> Self {
+        let lifetimes = lifetimes(ft, nodes, heads, batch_size);
+        let (sizes, assigned) = pack(&lifetimes);
+        let slots = sizes.into_iter().map(DeviceBuffer::new).collect::<Vec<_>>();
+        let unshared = lifetimes.iter().map(|lifetime| lifetime.size).sum();
+
+        for (lifetime, slot) in lifetimes.into_iter().zip(assigned) {
+            let ptr = slots[slot].ptr();
+
+            unsafe {
+                match lifetime.buffer {
+                    Buffer::Batch(tensor) => *tensor = TensorBatch::view(tensor.shape(), batch_size, ptr),
+                    Buffer::Raw(buffer) => *buffer = DeviceBuffer::view(ptr, lifetime.size),
+                }
+            }
+        }
+
+        Self { _slots: slots, unshared }
+    }
+
+    /// Floats allocated for the slots.
+    pub fn size(&self) -> usize {
+        self._slots.iter().map(DeviceBuffer::size).sum()
+    }
+
+    /// Floats saved by sharing the slots.
+    pub fn saved(&self) -> usize {
+        self.unshared - self.size()
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Shares the memory of the buffers with disjoint lifetimes, if enabled,
+    /// which has to be repeated whenever any of them are reallocated.
+    pub(super) fn share_buffers(&mut self) {
+        if self.shared.is_some() {
+            let batch_size = self.batch_size();
+            self.shared = Some(SharedBuffers::new(&mut self.ft, &mut self.nodes, &mut self.heads, batch_size));
+        }
+    }
+}
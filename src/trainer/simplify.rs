@@ -0,0 +1,108 @@
+//! Rewrites of consecutive activations into a single equivalent
+//! activation, so that each batch needs fewer kernel launches.
+
+use crate::Activation;
+
+/// Bounds of activations which clip their input to `[min, max]`.
+pub(super) fn clamp_bounds(activation: Activation) -> Option<(f32, f32)> {
+    match activation {
+        Activation::ReLU => Some((0.0, f32::INFINITY)),
+        Activation::CReLU => Some((0.0, 1.0)),
+        Activation::BoundedCReLU { min, max } => Some((min, max)),
+        _ => None,
+    }
+}
+
+/// Bounds of activations which clip their input to `[min, max]` and then square it.
+fn squared_clamp_bounds(activation: Activation) -> Option<(f32, f32)> {
+    match activation {
+        Activation::SCReLU => Some((0.0, 1.0)),
+        Activation::BoundedSCReLU { min, max } => Some((min, max)),
+        _ => None,
+    }
+}
+
+fn from_clamp_bounds(min: f32, max: f32) -> Activation {
+    match (min, max) {
+        (0.0, f32::INFINITY) => Activation::ReLU,
+        (0.0, 1.0) => Activation::CReLU,
+        _ => Activation::BoundedCReLU { min, max },
+    }
+}
+
+fn from_squared_clamp_bounds(min: f32, max: f32) -> Activation {
+    match (min, max) {
+        (0.0, 1.0) => Activation::SCReLU,
+        _ => Activation::BoundedSCReLU { min, max },
+    }
+}
+
+/// Smallest interval containing every output of the activation.
+fn output_range(activation: Activation) -> (f32, f32) {
+    if let Some(bounds) = clamp_bounds(activation) {
+        return bounds;
+    }
+
+    if let Some((min, max)) = squared_clamp_bounds(activation) {
+        let (a, b) = (min * min, max * max);
+        let lowest = if min <= 0.0 && max >= 0.0 { 0.0 } else { a.min(b) };
+        return (lowest, a.max(b));
+    }
+
+    match activation {
+        Activation::LeakyReLU(slope) if slope <= 0.0 => (0.0, f32::INFINITY),
+        _ => (f32::NEG_INFINITY, f32::INFINITY),
+    }
+}
+
+/// Bounds of the intersection of two intervals, if it is non-empty.
+fn intersect((a, b): (f32, f32), (c, d): (f32, f32)) -> Option<(f32, f32)> {
+    let (min, max) = (a.max(c), b.min(d));
+    (min <= max).then_some((min, max))
+}
+
+pub(super) fn is_identity(activation: Activation) -> bool {
+    match activation {
+        Activation::LeakyReLU(slope) => slope == 1.0,
+        _ => clamp_bounds(activation) == Some((f32::NEG_INFINITY, f32::INFINITY)),
+    }
+}
+
+/// A single activation equivalent to applying `first` and then `second`,
+/// if there is one. The gradients of the two agree everywhere except
+/// possibly at the finitely many points where either is not differentiable.
+pub(super) fn fuse(first: Activation, second: Activation) -> Option<Activation> {
+    let (lowest, highest) = output_range(first);
+
+    // `second` doesn't affect any output of `first`
+    let leaves_unchanged = match second {
+        Activation::LeakyReLU(_) => lowest >= 0.0,
+        _ => clamp_bounds(second).is_some_and(|(min, max)| min <= lowest && highest <= max),
+    };
+
+    if leaves_unchanged {
+        return Some(first);
+    }
+
+    if let Some(bounds) = clamp_bounds(first) {
+        if let Some(second_bounds) = clamp_bounds(second) {
+            return intersect(bounds, second_bounds).map(|(min, max)| from_clamp_bounds(min, max));
+        }
+
+        if let Some(second_bounds) = squared_clamp_bounds(second) {
+            return intersect(bounds, second_bounds).map(|(min, max)| from_squared_clamp_bounds(min, max));
+        }
+    }
+
+    match (first, second) {
+        (Activation::LeakyReLU(a), Activation::LeakyReLU(b)) if a >= 0.0 && b >= 0.0 => {
+            Some(Activation::LeakyReLU(a * b))
+        }
+        // negative inputs stay non-positive, so are clipped to `min` regardless
+        (Activation::LeakyReLU(slope), _) if slope >= 0.0 => {
+            let min = clamp_bounds(second).or(squared_clamp_bounds(second))?.0;
+            (min >= 0.0).then_some(second)
+        }
+        _ => None,
+    }
+}
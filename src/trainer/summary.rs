@@ -0,0 +1,149 @@
+use crate::{inputs::InputType, outputs::OutputBuckets};
+
+use super::{
+    components::{Operation, SharedAffine},
+    Trainer,
+};
+
+/// A single row of an [`ArchSummary`].
+#[derive(Clone, Debug)]
+pub struct LayerSummary {
+    pub name: String,
+    /// Number of outputs per position.
+    pub outputs: usize,
+    pub params: usize,
+    /// Approximate forward pass FLOPs per position, counting a
+    /// multiply-add as 2 and a sparse accumulation as 1.
+    pub flops: usize,
+    /// Bytes of activations stored per batch.
+    pub bytes: usize,
+}
+
+/// Per-layer summary of a built network, for comparing
+/// the cost of candidate architectures before training.
+#[derive(Clone, Debug)]
+pub struct ArchSummary {
+    pub batch_size: usize,
+    pub layers: Vec<LayerSummary>,
+}
+
+impl ArchSummary {
+    pub fn params(&self) -> usize {
+        self.layers.iter().map(|layer| layer.params).sum()
+    }
+
+    pub fn flops(&self) -> usize {
+        self.layers.iter().map(|layer| layer.flops).sum()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.layers.iter().map(|layer| layer.bytes).sum()
+    }
+}
+
+impl std::fmt::Display for ArchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rule = "-".repeat(82);
+        writeln!(f, "{:<24} {:>10} {:>14} {:>14} {:>16}", "Layer", "Outputs", "Params", "FLOPs/Pos", "Bytes/Batch")?;
+        writeln!(f, "{rule}")?;
+
+        for layer in &self.layers {
+            writeln!(
+                f,
+                "{:<24} {:>10} {:>14} {:>14} {:>16}",
+                layer.name, layer.outputs, layer.params, layer.flops, layer.bytes
+            )?;
+        }
+
+        writeln!(f, "{rule}")?;
+        writeln!(f, "Total Params           : {}", self.params())?;
+        writeln!(f, "FLOPs per Position     : {}", self.flops())?;
+        writeln!(f, "FLOPs per Batch        : {}", self.flops() * self.batch_size)?;
+        write!(f, "Bytes per Batch        : {} (batch size {})", self.bytes(), self.batch_size)
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Summarises the layers of the network at the current batch size.
+    pub fn summary(&self) -> ArchSummary {
+        let batch_size = self.batch_size();
+        let perspectives = if self.ft.single_perspective { 1 } else { 2 };
+        let ft_size = self.ft.biases.num_elements();
+        let ft_outputs = self.ft.outputs.shape().rows();
+        let active = self.input_getter.max_active_inputs();
+
+        let ft_outputs_bytes = 4 * batch_size * ft_outputs;
+        let ft_copy_bytes = if self.ft.copy.is_some() { ft_outputs_bytes } else { 0 };
+
+        let mut layers = vec![LayerSummary {
+            name: String::from("Feature Transformer"),
+            outputs: ft_outputs,
+            params: self.ft.weights.num_elements() + ft_size,
+            flops: (active + 1) * ft_outputs,
+            bytes: 4 * batch_size * perspectives * active + ft_outputs_bytes + ft_copy_bytes,
+        }];
+
+        let mut inputs = ft_outputs;
+
+        for node in &self.nodes {
+            let outputs = node.outputs.shape().rows();
+
+            let (name, params, flops) = match &node.op {
+                Operation::Affine(affine) => {
+                    let weights = affine.weights.num_elements();
+                    (format!("Affine {inputs} -> {outputs}"), weights + outputs, 2 * weights + outputs)
+                }
+                Operation::Activate(activation) => (format!("{activation:?}"), 0, outputs),
+                Operation::Attention(attention) => {
+                    let desc = &attention.desc;
+                    let params = desc.num_weights() + desc.num_biases();
+                    let (len, dim, heads, head_dim) = (desc.len, desc.dim, desc.heads, desc.head_dim);
+                    // projections, then the scores and weighted sum of the values of each head
+                    let macs = len * desc.num_weights() + 2 * heads * len * len * head_dim;
+                    let name = format!("Attention {len}x{dim} -> {len}x{heads}x{head_dim}");
+                    (name, params, 2 * macs + 5 * heads * len * len)
+                }
+                Operation::BatchNorm(_) => (String::from("BatchNorm"), 4 * outputs, 4 * outputs),
+                Operation::Clamp { min, max, .. } => (format!("Clamp [{min}, {max}]"), 0, outputs),
+                Operation::Concat(_) => (String::from("Concat"), 0, 0),
+                Operation::Convolution(conv) => {
+                    let (desc, weights) = (&conv.desc, conv.weights.num_elements());
+                    let ((ih, iw), (oh, ow)) = (desc.input_shape, desc.output_shape());
+                    let (ic, oc) = (desc.input_channels, desc.output_channels);
+                    // ignores the taps that fall in the padding
+                    let macs = outputs * weights / oc;
+                    let mut name = format!("Conv {ic}x{ih}x{iw} -> {oc}x{oh}x{ow}");
+                    if desc.groups > 1 {
+                        name += &format!(" in {} groups", desc.groups);
+                    }
+                    (name, weights + oc, 2 * macs + outputs)
+                }
+                Operation::Dropout(_) => (String::from("Dropout"), 0, outputs),
+                Operation::GroupedAffine { groups, affine } => {
+                    let weights = affine.weights.num_elements();
+                    let name = format!("Grouped Affine {groups}x({} -> {})", inputs / groups, outputs / groups);
+                    (name, weights + outputs, 2 * weights + outputs)
+                }
+                Operation::Gather(gather) => (String::from("Gather Bucket"), gather.weights.num_elements(), 0),
+                Operation::LayerNorm(_) => (String::from("LayerNorm"), 2 * outputs, 6 * outputs),
+                Operation::Multiply(_) => (String::from("Multiply"), 0, outputs),
+                Operation::PairwiseMul(_) => (String::from("Pairwise Multiply"), 0, 3 * outputs),
+                Operation::PReLU(prelu) => (String::from("PReLU"), prelu.slopes.num_elements(), 2 * outputs),
+                Operation::Reduce { reduction, axis, .. } => (format!("{reduction:?} over {axis:?}"), 0, inputs),
+                Operation::Select => (String::from("Select Bucket"), 0, 0),
+                Operation::SharedAffine(SharedAffine { affine, .. }) => {
+                    let weights = affine.weights.num_elements();
+                    (format!("Shared Affine {inputs} -> {outputs}"), 0, 2 * weights + outputs)
+                }
+                Operation::Slice { start } => (format!("Slice {start}..{}", start + outputs), 0, 0),
+                Operation::Softmax { log: false } => (String::from("Softmax"), 0, 3 * outputs),
+                Operation::Softmax { log: true } => (String::from("LogSoftmax"), 0, 3 * outputs),
+            };
+
+            layers.push(LayerSummary { name, outputs, params, flops, bytes: 4 * batch_size * outputs });
+            inputs = outputs;
+        }
+
+        ArchSummary { batch_size, layers }
+    }
+}
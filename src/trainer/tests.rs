@@ -0,0 +1,858 @@
+use std::{sync::Arc, thread::JoinHandle};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    format::{BulletFormat, ChessBoard}, inputs, loader::GpuDataLoader, outputs,
+    population_based_training, tensor::{DeviceHandles, Shape, Tensor, TensorBatch}, Activation, AttentionDescription,
+    LocalSettings, Loss, LossHead, LrScheduler, PbtMember, PbtSettings, TrainerBuilder, TrainingRecipe,
+    TrainingSchedule, TrainingStage, WdScheduler, WdlScheduler,
+};
+use super::{
+    components::{Affine, GameHoldout, Operation, SharedAffine},
+    pbt::{exploit, replace_member},
+    report::{EloRecord, Record, RunReport},
+    run::for_each_batch,
+    simplify::{fuse, is_identity},
+    DistributedSettings, Trainer,
+};
+
+type TestTrainer = Trainer<inputs::Chess768, outputs::Single>;
+
+fn small_trainer() -> TestTrainer {
+    TrainerBuilder::default()
+        .input(inputs::Chess768)
+        .output_buckets(outputs::Single)
+        .feature_transformer(8)
+        .activate(Activation::SCReLU)
+        .add_layer(1)
+        .build()
+}
+
+/// Random positions with both kings and up to six other pieces, scored by material.
+fn positions(count: usize, seed: u64) -> Vec<ChessBoard> {
+    const VALUES: [i16; 5] = [100, 300, 300, 500, 900];
+
+    let mut state = seed;
+    let mut below = |n: u64| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) % n
+    };
+
+    (0..count).map(|_| {
+        let mut bbs = [0u64; 8];
+        let mut score = 0;
+
+        for i in 0..2 + below(7) {
+            let (colour, piece) = if i < 2 { (i as usize, 5) } else { (below(2) as usize, below(5) as usize) };
+
+            let sq = loop {
+                let sq = below(64);
+                if (bbs[0] | bbs[1]) & (1 << sq) == 0 && (piece != 0 || (8..56).contains(&sq)) {
+                    break sq;
+                }
+            };
+
+            bbs[colour] |= 1 << sq;
+            bbs[2 + piece] |= 1 << sq;
+
+            if piece < 5 {
+                score += if colour == 0 { VALUES[piece] } else { -VALUES[piece] };
+            }
+        }
+
+        let result = [0.0, 0.5, 1.0][usize::from(score > -100) + usize::from(score > 100)];
+        ChessBoard::from_raw(bbs, below(2) as usize, score, result).unwrap()
+    }).collect()
+}
+
+/// Empty directory for the files written by a test.
+fn test_dir(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("bullet-test-{name}"));
+    std::fs::remove_dir_all(&dir).unwrap_or(());
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.to_str().unwrap().to_string()
+}
+
+fn write_positions(path: &str, data: &[ChessBoard]) {
+    std::fs::write(path, ChessBoard::as_bytes_slice(data)).unwrap();
+}
+
+fn schedule(batch_size: usize, batches_per_superbatch: usize, superbatches: usize) -> TrainingSchedule {
+    TrainingSchedule {
+        net_id: "test".to_string(),
+        eval_scale: 400.0,
+        ft_regularisation: 0.0,
+        batch_size,
+        batches_per_superbatch,
+        start_superbatch: 1,
+        end_superbatch: superbatches,
+        wdl_scheduler: WdlScheduler::Constant { value: 0.5 },
+        lr_scheduler: LrScheduler::Constant { value: 0.001 },
+        wd_scheduler: WdScheduler::Constant { value: 0.01 },
+        loss_function: Loss::SigmoidMSE,
+        save_rate: superbatches,
+    }
+}
+
+fn load(trainer: &TestTrainer, data: &[ChessBoard]) -> GpuDataLoader<inputs::Chess768, outputs::Single> {
+    let mut loader = GpuDataLoader::new(inputs::Chess768, outputs::Single);
+    loader.load(data, 1, 0.5, 1.0 / 400.0, trainer.wdl_hook(), None, &[1]);
+    loader
+}
+
+/// Weights, momentum and velocity of the optimiser.
+fn optimiser_state(trainer: &TestTrainer) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    let size = trainer.net_size();
+    let (mut weights, mut momentum, mut velocity) = (vec![0.0; size], vec![0.0; size], vec![0.0; size]);
+    trainer.optimiser.write_to_host(&mut weights, &mut momentum, &mut velocity);
+    (weights, momentum, velocity)
+}
+
+/// Runs `f` on a trainer connected as `rank` of two machines, whose
+/// optimiser state is filled with `rank`, `2 * rank` and `4 * rank`.
+fn spawn_rank<R, F>(address: &'static str, rank: usize, f: F) -> JoinHandle<R>
+where
+    R: Send + 'static,
+    F: FnOnce(&mut TestTrainer) -> R + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut trainer = small_trainer();
+        let size = trainer.net_size();
+        let fill = |x: usize| vec![x as f32; size];
+        trainer.optimiser.load_from_cpu(&fill(rank), &fill(2 * rank), &fill(4 * rank));
+
+        trainer.set_distributed(DistributedSettings { address, rank, world_size: 2, interval: 2 });
+        f(&mut trainer)
+    })
+}
+
+#[test]
+fn distributed_averaging() {
+    let address = "127.0.0.1:47391";
+
+    let run = |trainer: &mut TestTrainer| {
+        // not due to be averaged
+        assert!(trainer.apply_weight_averaging(1, 1, 3));
+        assert!(trainer.apply_weight_averaging(1, 2, 3));
+        optimiser_state(trainer)
+    };
+
+    let ranks = [spawn_rank(address, 0, run), spawn_rank(address, 1, run)];
+
+    for rank in ranks {
+        let (weights, momentum, velocity) = rank.join().unwrap();
+        assert!(weights.iter().all(|&x| x == 0.5));
+        assert!(momentum.iter().all(|&x| x == 1.0));
+        assert!(velocity.iter().all(|&x| x == 2.0));
+    }
+}
+
+#[test]
+fn distributed_out_of_sync() {
+    let address = "127.0.0.1:47392";
+
+    let ranks = [
+        spawn_rank(address, 0, |trainer| (trainer.apply_weight_averaging(1, 2, 3), optimiser_state(trainer).0)),
+        spawn_rank(address, 1, |trainer| (trainer.apply_weight_averaging(1, 3, 3), optimiser_state(trainer).0)),
+    ];
+
+    for (rank, handle) in ranks.into_iter().enumerate() {
+        let (synced, weights) = handle.join().unwrap();
+        assert!(!synced);
+        assert!(weights.iter().all(|&x| x == rank as f32));
+    }
+}
+
+#[test]
+fn distributed_disconnect() {
+    let address = "127.0.0.1:47393";
+
+    let ranks = [
+        spawn_rank(address, 0, |trainer| trainer.apply_weight_averaging(1, 2, 3)),
+        // leaves without averaging
+        spawn_rank(address, 1, |_| true),
+    ];
+
+    let [first, second] = ranks;
+    assert!(second.join().unwrap());
+    assert!(!first.join().unwrap());
+}
+
+#[test]
+fn prefetch_matches_load_data() {
+    let data = positions(100, 1);
+    let mut direct = small_trainer();
+    let mut prefetched = small_trainer();
+
+    for trainer in [&mut direct, &mut prefetched] {
+        trainer.set_batch_size(64);
+        trainer.randomise_weights_seeded(7);
+    }
+
+    // the second batch is partial, and swaps back into the first set of buffers
+    let batches: Vec<_> = data.chunks(64).map(|batch| load(&direct, batch)).collect();
+
+    let mut pinned: Vec<_> = batches.iter().map(|loader| {
+        let mut batch = prefetched.pinned_batch();
+        batch.fill(loader);
+        Arc::new(batch)
+    }).collect();
+
+    prefetched.prefetch_data(&pinned[0]);
+
+    for (i, loader) in batches.iter().enumerate() {
+        direct.clear_data();
+        direct.load_data(loader);
+
+        prefetched.load_prefetched();
+        if let Some(next) = pinned.get(i + 1) {
+            prefetched.prefetch_data(next);
+        }
+
+        assert_eq!(direct.inputs.used(), prefetched.inputs.used());
+        assert_eq!(direct.used, prefetched.used);
+
+        let mut results = [vec![0.0; 64], vec![0.0; 64]];
+        direct.results.write_to_host(&mut results[0]);
+        prefetched.results.write_to_host(&mut results[1]);
+        assert_eq!(results[0][..direct.used], results[1][..direct.used]);
+
+        assert!(direct.train_on_batch(0.01, 0.001, Loss::SigmoidMSE));
+        assert!(prefetched.train_on_batch(0.01, 0.001, Loss::SigmoidMSE));
+        assert_eq!(direct.error().to_bits(), prefetched.error().to_bits());
+        assert_eq!(optimiser_state(&direct), optimiser_state(&prefetched));
+    }
+
+    // every batch has been released by the trainer
+    assert!(pinned.drain(..).all(|batch| Arc::into_inner(batch).is_some()));
+}
+
+/// Checks that one pass over `files` gives every position kept by `holdout`
+/// exactly once and in order, in batches of at most `batch_size` that never
+/// span two files, with only the last batch of each file being partial.
+fn check_single_epoch(files: &[Vec<ChessBoard>], batch_size: usize, holdout: Option<GameHoldout<ChessBoard>>) {
+    let dir = test_dir(&format!("epoch-{}", holdout.is_some()));
+    let paths: Vec<_> = (0..files.len()).map(|i| format!("{dir}/{i}.data")).collect();
+
+    for (path, data) in paths.iter().zip(files) {
+        write_positions(path, data);
+    }
+
+    let kept: Vec<Vec<ChessBoard>> = files.iter().map(|data| {
+        data.iter().filter(|pos| holdout.is_none_or(|holdout| !holdout.is_held_out(pos))).copied().collect()
+    }).collect();
+
+    let total = kept.iter().map(Vec::len).sum::<usize>();
+    let mut batches = vec![Vec::new(); files.len()];
+    let mut seen = 0;
+
+    for_each_batch(&paths, batch_size, &schedule(batch_size, 4, 100), holdout, |_, _, source, batch: &[ChessBoard]| {
+        batches[source].push(batch.to_vec());
+        seen += batch.len();
+        seen < total
+    });
+
+    assert_eq!(seen, total);
+
+    for (batches, kept) in batches.iter().zip(&kept) {
+        let sizes: Vec<_> = batches.iter().map(Vec::len).collect();
+        let expected: Vec<_> = kept.chunks(batch_size).map(<[ChessBoard]>::len).collect();
+        assert_eq!(sizes, expected);
+
+        let trained: Vec<_> = batches.concat();
+        assert_eq!(ChessBoard::as_bytes_slice(&trained), ChessBoard::as_bytes_slice(kept));
+    }
+}
+
+#[test]
+fn partial_batches_cover_every_position_once() {
+    let files = [positions(100, 2), positions(50, 3)];
+    check_single_epoch(&files, 32, None);
+
+    let game = |pos: &ChessBoard| pos.occ();
+    check_single_epoch(&files, 32, Some(GameHoldout { fraction: 0.3, sample_size: 0, game }));
+}
+
+#[test]
+fn partial_batches_are_trained() {
+    let dir = test_dir("partial-batches");
+    let path = format!("{dir}/train.data");
+    write_positions(&path, &positions(100, 4));
+
+    let mut trainer = small_trainer();
+    let settings = LocalSettings { threads: 1, data_file_paths: vec![&path], output_directory: &dir, resume_from: None };
+
+    // batches of 32, 32, 32 and 4 positions
+    let mut trained = None;
+    trainer.run_custom(&schedule(32, 4, 1), &settings, |_, trainer, _, _| {
+        trained = Some((trainer.error_positions, trainer.mean_error(), trainer.position_error));
+    });
+
+    let (positions, mean_error, total_error) = trained.expect("No superbatch finished!");
+    assert_eq!(positions, 100);
+    assert_eq!(mean_error, total_error / 100.0);
+}
+
+#[test]
+fn report_writes_non_finite_values_as_null() {
+    let dir = test_dir("report");
+    let path = format!("{dir}/report.html");
+    let schedule = schedule(32, 4, 3);
+    let mut report = RunReport::new(path.clone(), &schedule, String::new(), String::new(), String::new());
+
+    for superbatch in 1..=3 {
+        let loss = if superbatch == 2 { f32::NAN } else { 0.5 };
+        report.push(Record { superbatch, loss, lr: 0.001, mini_loss: None, validation: None, time: 1.0 });
+    }
+
+    // matches can finish out of order
+    report.push_elo(EloRecord { superbatch: 3, elo: 12.5, err: 4.0 });
+    report.push_elo(EloRecord { superbatch: 1, elo: f32::INFINITY, err: f32::NAN });
+    report.write().unwrap();
+
+    let html = std::fs::read_to_string(&path).unwrap();
+    assert!(html.contains("<tr><td>Final Elo</td><td>12.50 +/- 4.00</td></tr>"));
+
+    let data = html.lines().find(|line| line.starts_with("const data = ")).unwrap();
+    assert!(data.contains("\"loss\":[0.5,null,0.5]"));
+    assert!(data.contains("\"elo\":[null,null,12.5]"));
+    assert!(data.contains("\"elo_low\":[null,null,8.5]"));
+    assert!(data.contains("\"elo_high\":[null,null,16.5]"));
+    assert!(!data.contains("NaN") && !data.contains("inf"));
+}
+
+/// Net applying a hidden layer of 8 twice in a row, either as the same
+/// layer shared by `add_shared_layer`, or as two separate layers.
+fn twice_applied(shared: bool) -> TestTrainer {
+    let builder = TrainerBuilder::default()
+        .input(inputs::Chess768)
+        .output_buckets(outputs::Single)
+        .feature_transformer(8)
+        .activate(Activation::SCReLU)
+        .add_layer(8)
+        .activate(Activation::CReLU)
+        .add_layer(8)
+        .activate(Activation::CReLU);
+
+    let builder = if shared { builder.add_shared_layer(4) } else { builder.add_layer(8) };
+    builder.add_layer(1).build()
+}
+
+fn affine(trainer: &TestTrainer, node: usize) -> &Affine {
+    match &trainer.nodes[node].op {
+        Operation::Affine(affine) | Operation::SharedAffine(SharedAffine { affine, .. }) => affine,
+        _ => panic!("Node {node} isn't an affine layer!"),
+    }
+}
+
+fn to_host(tensor: &Tensor) -> Vec<f32> {
+    let mut buf = vec![0.0; tensor.num_elements()];
+    tensor.write_to_host(&mut buf);
+    buf
+}
+
+#[test]
+fn shared_layer_gradients_are_summed() {
+    let mut separate = twice_applied(false);
+    let mut shared = twice_applied(true);
+    separate.set_batch_size(64);
+    shared.set_batch_size(64);
+    separate.randomise_weights_seeded(11);
+
+    // both uses of the layer have the same weights in the separate net too
+    for (from, to) in [(&separate.ft.weights, &shared.ft.weights), (&separate.ft.biases, &shared.ft.biases)] {
+        to.load_from_host(&to_host(from));
+    }
+
+    for (from, to) in [(3, 5), (1, 1), (3, 3), (6, 6)] {
+        let weights = to_host(&affine(&separate, from).weights);
+        let biases = to_host(&affine(&separate, from).biases);
+        affine(&separate, to).weights.load_from_host(&weights);
+        affine(&separate, to).biases.load_from_host(&biases);
+        affine(&shared, to).weights.load_from_host(&weights);
+        affine(&shared, to).biases.load_from_host(&biases);
+    }
+
+    let data = positions(64, 5);
+
+    for trainer in [&mut separate, &mut shared] {
+        let loader = load(trainer, &data);
+        trainer.clear_data();
+        trainer.load_data(&loader);
+        trainer.optimiser.zero_gradient();
+        trainer.error_device.set_zero();
+
+        unsafe {
+            trainer.forward(true);
+            trainer.calc_errors(Loss::SigmoidMSE);
+            trainer.backprop();
+        }
+    }
+
+    let mut errors = [vec![0.0; 1], vec![0.0; 1]];
+    separate.error_device.write_to_host(&mut errors[0]);
+    shared.error_device.write_to_host(&mut errors[1]);
+    assert_eq!(errors[0], errors[1]);
+
+    let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(x, y)| (x - y).abs() <= 1e-5 * x.abs().max(1.0));
+
+    let first = affine(&separate, 3);
+    let second = affine(&separate, 5);
+    let sum = |a: &Tensor, b: &Tensor| to_host(a).iter().zip(to_host(b)).map(|(x, y)| x + y).collect::<Vec<_>>();
+    let weights_grad = sum(&first.weights_grad, &second.weights_grad);
+    let biases_grad = sum(&first.biases_grad, &second.biases_grad);
+
+    // the second use reached the gradients of the layer, rather than being dropped
+    assert!(to_host(&second.weights_grad).iter().any(|&x| x != 0.0));
+
+    // the shared layer has no gradients of its own
+    for node in [3, 5] {
+        assert!(close(&to_host(&affine(&shared, node).weights_grad), &weights_grad));
+        assert!(close(&to_host(&affine(&shared, node).biases_grad), &biases_grad));
+    }
+
+    // and every other gradient is unchanged
+    for node in [1, 6] {
+        assert!(close(&to_host(&affine(&shared, node).weights_grad), &to_host(&affine(&separate, node).weights_grad)));
+    }
+
+    assert!(close(&to_host(&shared.ft.weights_grad), &to_host(&separate.ft.weights_grad)));
+}
+
+/// Net with most of its hidden layers recomputed if `recompute`, including
+/// a run of five, which recomputes its inputs into the spare buffer.
+fn deep_trainer(recompute: bool) -> TestTrainer {
+    type Builder = TrainerBuilder<inputs::Chess768, outputs::Single>;
+    let maybe = |builder: Builder| if recompute { builder.recompute() } else { builder };
+
+    let builder = Builder::default().input(inputs::Chess768).output_buckets(outputs::Single).feature_transformer(16);
+    let builder = maybe(builder.activate(Activation::SCReLU));
+    let builder = maybe(builder.add_layer(16));
+    let builder = maybe(builder.activate(Activation::CReLU));
+    let builder = maybe(builder.add_layer(16));
+    let builder = maybe(builder.activate(Activation::SCReLU));
+    let builder = builder.add_layer(8);
+    let builder = maybe(builder.activate(Activation::CReLU));
+    builder.add_layer(1).build()
+}
+
+#[test]
+fn recompute_matches_keeping_outputs() {
+    let data = positions(256, 6);
+    let mut kept = deep_trainer(false);
+    let mut recomputed = deep_trainer(true);
+
+    assert!(kept.recompute.is_none());
+    assert!(recomputed.recompute.is_some());
+
+    for trainer in [&mut kept, &mut recomputed] {
+        trainer.set_batch_size(64);
+        trainer.randomise_weights_seeded(13);
+    }
+
+    for batch in data.chunks(64) {
+        for trainer in [&mut kept, &mut recomputed] {
+            let loader = load(trainer, batch);
+            trainer.clear_data();
+            trainer.load_data(&loader);
+            assert!(trainer.train_on_batch(0.01, 0.001, Loss::SigmoidMSE));
+        }
+
+        assert_eq!(kept.error().to_bits(), recomputed.error().to_bits());
+        assert_eq!(optimiser_state(&kept), optimiser_state(&recomputed));
+    }
+}
+
+/// Net with every kind of buffer that can share memory, including gradients
+/// of the feature transformer outputs that are kept until the end of backprop.
+fn sharing_trainer(share: bool) -> TestTrainer {
+    let builder = TrainerBuilder::default()
+        .input(inputs::Chess768)
+        .output_buckets(outputs::Single)
+        .feature_transformer(16)
+        .activate(Activation::SCReLU)
+        .self_attention(AttentionDescription::new(4, 8, 2, 4))
+        .add_layer(8)
+        .activate(Activation::CReLU)
+        .multiply(3)
+        .concat(&[0, 2])
+        .add_layer(2);
+
+    if share { builder.build() } else { builder.disable_buffer_sharing().build() }
+}
+
+#[test]
+fn shared_buffers_match_separate_buffers() {
+    const HEADS: [LossHead; 2] = [
+        LossHead { loss: Loss::SigmoidMSE, weight: 1.0 },
+        LossHead { loss: Loss::SigmoidMSE, weight: 0.5 },
+    ];
+
+    let data = positions(256, 8);
+    let mut separate = sharing_trainer(false);
+    let mut shared = sharing_trainer(true);
+
+    for trainer in [&mut separate, &mut shared] {
+        trainer.set_batch_size(64);
+        trainer.set_ft_reg(0.01);
+        trainer.randomise_weights_seeded(17);
+    }
+
+    assert!(separate.shared.is_none());
+
+    let (separate_memory, shared_memory) = (separate.memory_report(), shared.memory_report());
+    assert_eq!(separate_memory.shared_savings, 0);
+    assert!(shared_memory.shared_savings > 0);
+    assert_eq!(shared_memory.total() + shared_memory.shared_savings, separate_memory.total());
+
+    for batch in data.chunks(64) {
+        for trainer in [&mut separate, &mut shared] {
+            let mut loader = GpuDataLoader::new(inputs::Chess768, outputs::Single);
+            loader.load(batch, 1, 0.5, 1.0 / 400.0, trainer.wdl_hook(), None, &[1, 1]);
+            trainer.clear_data();
+            trainer.load_data(&loader);
+            assert!(trainer.train_on_batch(0.01, 0.001, Loss::MultiHead(&HEADS)));
+        }
+
+        assert_eq!(separate.error().to_bits(), shared.error().to_bits());
+        assert_eq!(optimiser_state(&separate), optimiser_state(&shared));
+    }
+}
+
+const SAMPLES: [f32; 15] = [-10.0, -3.0, -1.5, -1.0, -0.5, -0.1, 0.0, 0.1, 0.5, 0.9, 1.0, 1.5, 2.0, 3.0, 10.0];
+
+/// Applies each activation in turn to every sample, with the CPU kernels.
+fn activate(activations: &[Activation]) -> Vec<f32> {
+    let handle = DeviceHandles::default();
+    let x = TensorBatch::new(Shape::new(1, SAMPLES.len()), 1);
+    let y = TensorBatch::new(Shape::new(1, SAMPLES.len()), 1);
+    x.load_from_host(&SAMPLES);
+
+    for &activation in activations {
+        TensorBatch::activate(handle, 1, activation, &x, &y);
+        x.copy_from(&y);
+    }
+
+    let mut buf = vec![0.0; SAMPLES.len()];
+    x.write_to_host(&mut buf);
+    buf
+}
+
+#[test]
+fn fused_activations_match_applying_both() {
+    use Activation::*;
+
+    let bounded = |min, max| BoundedCReLU { min, max };
+    let squared = |min, max| BoundedSCReLU { min, max };
+
+    let cases = [
+        (ReLU, ReLU, Some(ReLU)),
+        (ReLU, CReLU, Some(CReLU)),
+        (CReLU, ReLU, Some(CReLU)),
+        (CReLU, SCReLU, Some(SCReLU)),
+        (SCReLU, CReLU, Some(SCReLU)),
+        (ReLU, SCReLU, Some(SCReLU)),
+        (bounded(-1.0, 2.0), bounded(0.5, 3.0), Some(bounded(0.5, 2.0))),
+        (bounded(-1.0, 1.0), SCReLU, Some(SCReLU)),
+        (bounded(-1.0, 2.0), squared(-0.5, 3.0), Some(squared(-0.5, 2.0))),
+        (bounded(-0.5, 0.5), bounded(-1.0, 1.0), Some(bounded(-0.5, 0.5))),
+        (squared(-2.0, 1.0), CReLU, None),
+        (SCReLU, bounded(0.25, 2.0), None),
+        // disjoint bounds clip every input to the same value
+        (bounded(-2.0, -1.0), bounded(1.0, 2.0), None),
+        (bounded(1.0, 2.0), bounded(-2.0, -1.0), None),
+        (bounded(-2.0, -1.0), SCReLU, None),
+        (LeakyReLU(0.1), LeakyReLU(0.5), Some(LeakyReLU(0.05))),
+        (LeakyReLU(0.1), ReLU, Some(ReLU)),
+        (LeakyReLU(2.0), CReLU, Some(CReLU)),
+        (LeakyReLU(0.1), SCReLU, Some(SCReLU)),
+        (LeakyReLU(0.1), bounded(-1.0, 1.0), None),
+        (LeakyReLU(-0.5), LeakyReLU(0.1), Some(LeakyReLU(-0.5))),
+        (LeakyReLU(-0.5), CReLU, None),
+        (LeakyReLU(0.5), LeakyReLU(-0.5), None),
+        (ReLU, LeakyReLU(0.1), Some(ReLU)),
+        (SCReLU, LeakyReLU(0.3), Some(SCReLU)),
+        (CReLU, LeakyReLU(-1.0), Some(CReLU)),
+        (bounded(-1.0, 1.0), LeakyReLU(0.5), None),
+    ];
+
+    for (first, second, expected) in cases {
+        // `Activation` isn't `PartialEq`, as it holds floats
+        let fused = fuse(first, second);
+        assert_eq!(format!("{fused:?}"), format!("{expected:?}"), "fuse({first:?}, {second:?})");
+
+        if let Some(fused) = fused {
+            let both = activate(&[first, second]);
+            let single = activate(&[fused]);
+            let close = both.iter().zip(&single).all(|(x, y)| (x - y).abs() <= 1e-6 * x.abs().max(1.0));
+            assert!(close, "fuse({first:?}, {second:?}): {both:?} != {single:?}");
+        }
+    }
+}
+
+#[test]
+fn identity_activations() {
+    use Activation::*;
+
+    let cases = [
+        (LeakyReLU(1.0), true),
+        (BoundedCReLU { min: f32::NEG_INFINITY, max: f32::INFINITY }, true),
+        (LeakyReLU(0.5), false),
+        (LeakyReLU(-1.0), false),
+        (BoundedCReLU { min: f32::NEG_INFINITY, max: 5.0 }, false),
+        (BoundedCReLU { min: -5.0, max: f32::INFINITY }, false),
+        (BoundedSCReLU { min: f32::NEG_INFINITY, max: f32::INFINITY }, false),
+        (ReLU, false),
+        (CReLU, false),
+        (SCReLU, false),
+    ];
+
+    for (activation, identity) in cases {
+        assert_eq!(is_identity(activation), identity, "{activation:?}");
+        assert_eq!(activate(&[activation]) == SAMPLES, identity, "{activation:?}");
+    }
+}
+
+fn recipe(names: &[&str]) -> TrainingRecipe<'static> {
+    let stage = |name: &str| {
+        let schedule = TrainingSchedule { net_id: name.to_string(), ..schedule(32, 2, 2) };
+        TrainingStage { schedule, data_file_paths: Vec::new() }
+    };
+
+    TrainingRecipe { name: "recipe".to_string(), stages: names.iter().map(|name| stage(name)).collect() }
+}
+
+#[test]
+fn recipe_logs_are_merged() {
+    let dir = test_dir("recipe");
+    let path = format!("{dir}/train.data");
+    write_positions(&path, &positions(200, 7));
+
+    let mut trainer = small_trainer();
+    let settings = LocalSettings { threads: 1, data_file_paths: vec![&path], output_directory: &dir, resume_from: None };
+    trainer.run_recipe(&recipe(&["pretrain", "finetune"]), &settings);
+
+    // each stage keeps its own checkpoints
+    for name in ["pretrain-2", "finetune-2"] {
+        assert!(std::path::Path::new(&format!("{dir}/{name}")).exists(), "{name} wasn't saved!");
+    }
+
+    let log = std::fs::read_to_string(format!("{dir}/recipe-log.csv")).unwrap();
+    let stages: Vec<_> = log.lines().skip(1).map(|line| line.split(',').take(2).collect::<Vec<_>>().join(",")).collect();
+    assert!(log.starts_with("stage,superbatch,loss,"));
+    assert_eq!(stages, ["1,1", "1,2", "2,1", "2,2"]);
+
+    let schedule = std::fs::read_to_string(format!("{dir}/recipe-schedule.csv")).unwrap();
+    let mut lines = schedule.lines();
+    assert!(lines.next().unwrap().starts_with("stage,superbatch,"));
+    let stages: Vec<_> = lines.map(|line| line.split(',').next().unwrap()).collect();
+    assert_eq!(stages, ["1", "1", "2", "2"]);
+}
+
+#[test]
+#[should_panic(expected = "Stages 1 and 3 both have the net_id [pretrain]!")]
+fn recipe_stages_need_distinct_names() {
+    recipe(&["pretrain", "finetune", "pretrain"]).check_names();
+}
+
+#[test]
+#[should_panic(expected = "Stage 2 has the same name as the recipe!")]
+fn recipe_stages_need_names_other_than_the_recipe() {
+    recipe(&["pretrain", "recipe"]).check_names();
+}
+
+/// Net quantised with `shifts`, with weights of 0.1 other than the first
+/// feature transformer weight and the output bias.
+fn shifted_trainer(shifts: &[u32]) -> TestTrainer {
+    let trainer = TrainerBuilder::default()
+        .input(inputs::Chess768)
+        .output_buckets(outputs::Single)
+        .quantisation_shifts(shifts)
+        .feature_transformer(8)
+        .activate(Activation::CReLU)
+        .add_layer(1)
+        .build();
+
+    let size = trainer.net_size();
+    let mut weights = vec![0.1; size];
+    weights[0] = 3.0;
+    weights[size - 1] = 1.5;
+    trainer.optimiser.load_from_cpu(&weights, &vec![0.0; size], &vec![0.0; size]);
+
+    trainer
+}
+
+fn quantises(trainer: &TestTrainer) -> bool {
+    let mut buf = vec![0.0; trainer.net_size()];
+    trainer.optimiser.write_weights_to_host(&mut buf);
+    let (buf, quantiser) = trainer.export_weights(&buf);
+    trainer.quantise(&buf, &quantiser).is_some()
+}
+
+#[test]
+fn max_quantisation_shifts_are_the_largest_that_fit() {
+    let shifts = shifted_trainer(&[0, 0]).max_quantisation_shifts();
+
+    // 3 << 13 fits, then the output bias leaves a single shift for the layer
+    assert_eq!(shifts, [13, 1]);
+    assert!(quantises(&shifted_trainer(&shifts)));
+    assert!(!quantises(&shifted_trainer(&[14, 1])));
+    assert!(!quantises(&shifted_trainer(&[13, 2])));
+}
+
+#[test]
+fn exploit_replaces_the_worst_with_the_best() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let scores = [0.4, 0.1, f32::INFINITY, 0.3, 0.2, 0.9, 0.5, f32::NAN];
+
+    // a quarter of eight is two, so the two worst are replaced by the two best
+    for _ in 0..16 {
+        let pairs = exploit(&scores, 0.25, &mut rng);
+        assert_eq!(pairs.iter().map(|&(loser, _)| loser).collect::<Vec<_>>(), [2, 7]);
+        assert!(pairs.iter().all(|&(_, winner)| winner == 1 || winner == 4));
+    }
+
+    // at least one member is always replaced
+    assert_eq!(exploit(&[0.2, 0.1], 0.0, &mut rng), [(0, 1)]);
+}
+
+#[test]
+fn replaced_members_copy_the_winner() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut trainers = vec![small_trainer(), small_trainer()];
+    let mut members = [PbtMember { lr_mult: 2.0, wd_mult: 0.5 }, PbtMember::default()];
+
+    trainers[0].randomise_weights_seeded(1);
+    trainers[1].randomise_weights_seeded(2);
+    replace_member(&mut trainers, &mut members, 1, 0, 1.5, &mut rng);
+
+    assert_eq!(optimiser_state(&trainers[0]), optimiser_state(&trainers[1]));
+    assert!([3.0, 2.0 / 1.5].contains(&members[1].lr_mult));
+    assert!([0.75, 0.5 / 1.5].contains(&members[1].wd_mult));
+
+    // the winner is untouched
+    assert_eq!((members[0].lr_mult, members[0].wd_mult), (2.0, 0.5));
+}
+
+#[test]
+fn diverged_members_are_replaced() {
+    let dir = test_dir("pbt-diverged");
+    let path = format!("{dir}/data.bin");
+    write_positions(&path, &positions(256, 7));
+
+    let settings = LocalSettings { threads: 1, data_file_paths: vec![&path], output_directory: &dir, resume_from: None };
+    let pbt = PbtSettings { interval: 1, ..Default::default() };
+
+    // an infinite learning rate makes the weights, and so the loss, NaN
+    let members = [PbtMember { lr_mult: f32::INFINITY, wd_mult: 1.0 }, PbtMember::default()];
+    let result = population_based_training(small_trainer, &members, &schedule(32, 4, 2), &settings, pbt, None);
+
+    let log = std::fs::read_to_string(format!("{dir}/pbt.txt")).unwrap();
+    assert!(log.contains("superbatch 1 | scores inf"), "{log}");
+    assert!(log.contains("member 0 <- member 1"), "{log}");
+
+    assert!([1.2, 1.0 / 1.2].contains(&result.members[0].lr_mult));
+    assert!(result.scores.iter().all(|score| score.is_finite()), "{:?}", result.scores);
+}
+
+const FENS: [&str; 4] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "8/5k2/3p4/1p1Pp2p/pP2Pp1P/P4P1K/8/8 b - - 99 50",
+    "4k3/8/8/8/8/8/8/4K2R w K - 0 1",
+];
+
+#[test]
+fn inference_matches_trainer() {
+    type Builder = TrainerBuilder<inputs::Chess768, outputs::MaterialCount<4>>;
+
+    let mut trainer = Builder::default()
+        .input(inputs::Chess768)
+        .output_buckets(outputs::MaterialCount::<4>)
+        .feature_transformer(16)
+        .activate(Activation::SCReLU)
+        .pairwise_mul(8, None)
+        .add_layer(8)
+        .activate(Activation::CReLU)
+        .layer_norm()
+        .add_layer(1)
+        .build();
+
+    trainer.randomise_weights_seeded(3);
+    let net = trainer.inference_net();
+
+    let evals = FENS.map(|fen| trainer.eval(fen));
+    assert!(evals.windows(2).all(|pair| pair[0] != pair[1]), "{evals:?}");
+
+    for (fen, expected) in FENS.into_iter().zip(evals) {
+        let actual = net.eval(fen);
+        assert!((expected - actual).abs() <= 1e-4 * expected.abs().max(1.0), "{fen}: {expected} vs {actual}");
+    }
+}
+
+fn net_file_builder() -> TrainerBuilder<inputs::Chess768, outputs::Single> {
+    TrainerBuilder::default().input(inputs::Chess768).output_buckets(outputs::Single)
+}
+
+#[test]
+fn net_file_round_trips() {
+    let dir = test_dir("net-file");
+    let path = format!("{dir}/net.bin");
+
+    let trainer = net_file_builder()
+        .feature_transformer(16)
+        .activate(Activation::SCReLU)
+        .add_layer(8)
+        .activate(Activation::BoundedCReLU { min: -0.5, max: 1.5 })
+        .concat(&[0, 2])
+        .add_layer(1)
+        .build();
+
+    trainer.randomise_weights_seeded(5);
+    trainer.save_net_file(&path).unwrap();
+
+    let loaded = net_file_builder().load_net_file(&path).unwrap();
+    assert_eq!(loaded.description, trainer.description);
+    assert_eq!(format!("{loaded}"), format!("{trainer}"));
+
+    let weights = |trainer: &TestTrainer| optimiser_state(trainer).0.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+    assert_eq!(weights(&loaded), weights(&trainer));
+}
+
+#[test]
+fn invalid_net_files_are_rejected() {
+    let dir = test_dir("net-file-invalid");
+    let path = format!("{dir}/net.bin");
+
+    small_trainer().save_net_file(&path).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    let header_len = bytes.windows(8).position(|w| w == b"weights ").unwrap();
+    let header = std::str::from_utf8(&bytes[..header_len]).unwrap();
+
+    let with_header = |header: &str| [header.as_bytes(), &bytes[header_len..]].concat();
+
+    let invalid = [
+        ("truncated header", bytes[..header_len / 2].to_vec()),
+        ("truncated weights", bytes[..bytes.len() - 3].to_vec()),
+        ("extra weights", [&bytes[..], &[0; 4]].concat()),
+        ("empty file", Vec::new()),
+        ("no header", with_header(&header.replacen("bullet-net 1\n", "", 1))),
+        ("newer version", with_header(&header.replacen("bullet-net 1", "bullet-net 2", 1))),
+        ("unknown entry", with_header(&header.replacen("add_layer", "add_layr", 1))),
+        ("invalid argument", with_header(&header.replacen("add_layer 1", "add_layer x", 1))),
+        ("missing argument", with_header(&header.replacen("feature_transformer 8", "feature_transformer", 1))),
+        ("unknown activation", with_header(&header.replacen("SCReLU", "SCReLV", 1))),
+        ("other inputs", with_header(&header.replacen("input 768", "input 769", 1))),
+        ("no feature transformer", with_header(&header.replacen("feature_transformer 8\n", "", 1))),
+        ("wrong size", with_header(&header.replacen("add_layer 1", "add_layer 2", 1))),
+        ("invalid utf-8", [&[0xff, b'\n'], &bytes[..]].concat()),
+    ];
+
+    for (name, bytes) in invalid {
+        std::fs::write(&path, bytes).unwrap();
+        assert!(net_file_builder().load_net_file(&path).is_err(), "{name}");
+    }
+
+    assert!(net_file_builder().load_net_file(&format!("{dir}/missing.bin")).is_err());
+}
@@ -0,0 +1,120 @@
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use crate::{inputs::InputType, outputs::OutputBuckets, tensor::device_energy};
+
+use super::{ansi, Trainer};
+
+/// Resources used by a single run, printed at its end and appended to
+/// `<output_directory>/usage.csv`, so that the cost of the architectures
+/// and schedules trained in the same directory can be compared.
+#[derive(Clone, Debug)]
+pub struct RunUsage {
+    pub net_id: String,
+    pub arch: String,
+    pub device: String,
+    /// Positions trained on during the run, counted once for each net
+    /// trained on them, so excluding any superbatches skipped on resuming.
+    pub positions: usize,
+    pub seconds: f64,
+    pub checkpoints: usize,
+    /// Energy used by the device during the run in kWh, from its telemetry.
+    pub measured_energy: Option<f64>,
+    /// Average power drawn by the device, from `Trainer::set_device_power`,
+    /// for estimating the energy used where it can't be measured.
+    pub device_power: Option<f32>,
+}
+
+impl RunUsage {
+    /// Runs only ever train on the current device.
+    pub fn device_hours(&self) -> f64 {
+        self.seconds / 3600.0
+    }
+
+    /// Energy used by the device in kWh, as measured, or otherwise
+    /// estimated from the average power drawn by the device.
+    pub fn energy(&self) -> Option<f64> {
+        self.measured_energy.or_else(|| self.device_power.map(|watts| f64::from(watts) * self.device_hours() / 1000.0))
+    }
+
+    pub fn positions_per_second(&self) -> f64 {
+        self.positions as f64 / self.seconds
+    }
+
+    pub(super) fn print(&self) {
+        println!("{}", ansi("Run Usage", "34;1"));
+        println!("Positions              : {}", ansi(self.positions, 31));
+        println!("Wall Time              : {}", ansi(format!("{:.1}s", self.seconds), 31));
+        println!("Device Hours           : {}", ansi(format!("{:.3}", self.device_hours()), 31));
+        if let Some(energy) = self.measured_energy {
+            println!("Energy                 : {}", ansi(format!("{energy:.3} kWh"), 31));
+        } else if let Some(energy) = self.energy() {
+            println!("Energy Estimate        : {}", ansi(format!("{energy:.3} kWh"), 31));
+        }
+        println!("Checkpoints            : {}", ansi(self.checkpoints, 31));
+        println!("Average Speed          : {}", ansi(format!("{:.0} pos/sec", self.positions_per_second()), 31));
+    }
+
+    /// Appends a row to the CSV at `path`, writing its header if it is new.
+    pub(super) fn append(&self, path: &str) -> std::io::Result<()> {
+        let exists = Path::new(path).exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        if !exists {
+            writeln!(file, "net,arch,device,positions,seconds,device_hours,energy_kwh,checkpoints,pos_per_sec")?;
+        }
+
+        let energy = self.energy().map(|energy| format!("{energy:.4}")).unwrap_or_default();
+
+        writeln!(
+            file,
+            "{},\"{}\",\"{}\",{},{:.1},{:.4},{energy},{},{:.0}",
+            self.net_id,
+            self.arch.replace('"', "\"\""),
+            self.device.replace('"', "\"\""),
+            self.positions,
+            self.seconds,
+            self.device_hours(),
+            self.checkpoints,
+            self.positions_per_second(),
+        )
+    }
+
+    pub(super) fn report(&self, out_dir: &str) {
+        self.print();
+
+        let path = format!("{out_dir}/usage.csv");
+        self.append(&path).unwrap_or_else(|_| panic!("Writing to [{path}] failed!"));
+    }
+}
+
+/// Reading of the energy counter of the device at the start of a run, if
+/// the device has one, which is only the case for recent NVIDIA GPUs.
+pub(super) struct EnergyMeter {
+    start: Option<f64>,
+}
+
+impl EnergyMeter {
+    pub fn start() -> Self {
+        Self { start: device_energy() }
+    }
+
+    /// Energy used since the meter was started, in kWh.
+    pub fn read(&self) -> Option<f64> {
+        let (start, end) = (self.start?, device_energy()?);
+        Some((end - start) / 3.6e6)
+    }
+}
+
+impl<T: InputType, U: OutputBuckets<T::RequiredDataType>> Trainer<T, U> {
+    /// Average power drawn by the device while training, in watts, from which
+    /// the energy used by each run is estimated when the device can't report
+    /// the energy it used, as on the CPU and on GPUs older than Volta.
+    pub fn set_device_power(&mut self, watts: f32) {
+        assert!(watts > 0.0, "Invalid device power {watts}W!");
+        self.device_power = Some(watts);
+    }
+
+    pub fn device_power(&self) -> Option<f32> {
+        self.device_power
+    }
+}
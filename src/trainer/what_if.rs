@@ -0,0 +1,161 @@
+use crate::{inputs::InputType, outputs::OutputBuckets, LocalSettings, TrainingSchedule};
+
+use super::{ansi, run, run::holdout_sample, Trainer};
+
+/// One of the finetunes tried by [`what_if_finetunes`].
+#[derive(Clone, Debug)]
+pub struct WhatIf<'a> {
+    /// Label in the results, and the directory its checkpoints are saved to.
+    pub name: &'a str,
+    pub schedule: TrainingSchedule,
+    /// Data to finetune on, if not the data of the settings.
+    pub data_file_paths: Option<Vec<&'a str>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WhatIfResult {
+    pub name: String,
+    /// Average loss of the final superbatch, which is only comparable
+    /// between finetunes on the same data.
+    pub loss: f32,
+    /// Error on the validation sample at the end of the finetune.
+    pub validation: Option<f32>,
+}
+
+/// Results of [`what_if_finetunes`], with finetunes in the order they were given.
+#[derive(Clone, Debug)]
+pub struct WhatIfReport {
+    /// Error of the checkpoint itself on the validation sample.
+    pub baseline: Option<f32>,
+    pub finetunes: Vec<WhatIfResult>,
+}
+
+impl WhatIfReport {
+    /// Index of the finetune with the lowest validation error, or the
+    /// lowest loss if there is no validation sample.
+    pub fn best(&self) -> Option<usize> {
+        let score = |result: &WhatIfResult| result.validation.unwrap_or(result.loss);
+        (0..self.finetunes.len()).min_by(|&a, &b| score(&self.finetunes[a]).total_cmp(&score(&self.finetunes[b])))
+    }
+}
+
+impl std::fmt::Display for WhatIfReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = |x: Option<f32>| x.map_or(String::from("-"), |x| format!("{x:.6}"));
+
+        writeln!(f, "{:<24} {:>14} {:>14} {:>14}", "Finetune", "Loss", "Validation", "Delta")?;
+        writeln!(f, "{:<24} {:>14} {:>14} {:>14}", "(checkpoint)", "-", format(self.baseline), "-")?;
+
+        let best = self.best();
+
+        for (i, result) in self.finetunes.iter().enumerate() {
+            let delta = result.validation.zip(self.baseline).map(|(validation, baseline)| validation - baseline);
+            let best = if best == Some(i) { " *" } else { "" };
+            writeln!(
+                f,
+                "{:<24} {:>14.6} {:>14} {:>14}{best}",
+                result.name,
+                result.loss,
+                format(result.validation),
+                delta.map_or(String::from("-"), |delta| format!("{delta:+.6}")),
+            )?;
+        }
+
+        match best {
+            Some(best) => write!(f, "Best Finetune          : {}", self.finetunes[best].name),
+            None => write!(f, "Best Finetune          : -"),
+        }
+    }
+}
+
+/// Loads the checkpoint at `checkpoint` into a fresh net from `build` for each
+/// of `finetunes`, and trains it with the schedule and data of that finetune,
+/// to compare options such as continuing training, dropping the learning rate
+/// or switching data, without having to keep track of separate runs by hand.
+/// Finetunes are run one after another, starting from the weights and
+/// optimiser state of the checkpoint at the start superbatch of their schedule.
+///
+/// Every finetune, and the checkpoint itself, is scored on the same validation
+/// sample, which is `validation` if not empty, otherwise the held out games of
+/// the data of the settings, if the nets from `build` hold games out. Without
+/// a validation sample, only the losses of finetunes on the same data can be
+/// compared.
+///
+/// Each finetune writes its checkpoints to `<output_directory>/what-if-<name>`,
+/// and the results are written to `<output_directory>/what-if.txt`.
+pub fn what_if_finetunes<T, U, B>(
+    mut build: B,
+    checkpoint: &str,
+    finetunes: &[WhatIf],
+    settings: &LocalSettings,
+    validation: &[T::RequiredDataType],
+) -> WhatIfReport
+where
+    T: InputType,
+    U: OutputBuckets<T::RequiredDataType>,
+    B: FnMut() -> Trainer<T, U>,
+{
+    assert!(!finetunes.is_empty(), "Need at least one finetune!");
+    assert!(settings.resume_from.is_none(), "Finetunes start from the checkpoint, so can't be resumed!");
+
+    for (i, finetune) in finetunes.iter().enumerate() {
+        let name = finetune.name;
+        assert!(!finetunes[..i].iter().any(|other| other.name == name), "Duplicate finetune name [{name}]!");
+    }
+
+    let mut checkpointed = build();
+    checkpointed.load_from_checkpoint(checkpoint);
+
+    let sample = match checkpointed.game_holdout {
+        Some(holdout) if validation.is_empty() => {
+            let data_file_paths: Vec<_> = settings.data_file_paths.iter().map(|s| s.to_string()).collect();
+            holdout_sample(&data_file_paths, finetunes[0].schedule.batch_size, holdout)
+        }
+        _ => validation.to_vec(),
+    };
+
+    checkpointed.set_validation_sample(&sample);
+    let baseline = checkpointed.validation_error();
+    drop(checkpointed);
+
+    let mut results = WhatIfReport { baseline, finetunes: Vec::new() };
+
+    for (i, finetune) in finetunes.iter().enumerate() {
+        println!("{}", ansi(format!("Finetune {} ({}/{})", finetune.name, i + 1, finetunes.len()), "34;1"));
+
+        let out_dir = format!("{}/what-if-{}", settings.output_directory, finetune.name);
+        std::fs::create_dir_all(&out_dir).unwrap_or_else(|_| panic!("Creating [{out_dir}] failed!"));
+
+        let run_settings = LocalSettings {
+            threads: settings.threads,
+            data_file_paths: finetune.data_file_paths.clone().unwrap_or_else(|| settings.data_file_paths.clone()),
+            output_directory: &out_dir,
+            resume_from: None,
+        };
+
+        let mut trainer = build();
+        trainer.load_from_checkpoint(checkpoint);
+
+        let mut loss = 0.0;
+        run(&mut trainer, &finetune.schedule, &run_settings, |superbatch, trainer, schedule, settings| {
+            loss = trainer.mean_error();
+
+            if schedule.should_save(superbatch) {
+                trainer.save(settings.output_directory, format!("{}-{superbatch}", schedule.net_id()));
+            }
+        });
+
+        // a net holding games out validates on those of its own data during the run
+        trainer.set_validation_sample(&sample);
+        let validation = trainer.validation_error();
+
+        results.finetunes.push(WhatIfResult { name: finetune.name.to_string(), loss, validation });
+    }
+
+    let path = format!("{}/what-if.txt", settings.output_directory);
+    std::fs::write(&path, format!("{checkpoint}\n\n{results}\n"))
+        .unwrap_or_else(|_| panic!("Writing to [{path}] failed!"));
+    println!("{results}");
+
+    results
+}
@@ -0,0 +1,252 @@
+//! Ultra-fast games between two nets of the same architecture, using the
+//! CPU inference of each net with a tiny search, as a cheap proxy for the
+//! strength of a net that doesn't need an engine to be rebuilt.
+
+mod position;
+
+#[cfg(test)]
+#[rustfmt::skip]
+mod tests;
+
+use std::{collections::HashMap, str::FromStr};
+
+use bulletformat::ChessBoard;
+
+pub use position::Position;
+
+use crate::{inference::InferenceNet, inputs::InputType, moves::Move, outputs::OutputBuckets};
+
+/// Games still going after this many plies are adjudicated as draws.
+const MAX_PLIES: usize = 400;
+const MAX_DEPTH: usize = 64;
+const MATE: f32 = 1_000_000.0;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ValueSearch {
+    /// Plays the move leading to the best evaluated position.
+    OnePly,
+    /// Iteratively deepened alpha-beta search, which stops partway through
+    /// a depth once the given number of positions have been visited.
+    Nodes(usize),
+}
+
+/// Results from the perspective of the first net.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MatchResult {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl MatchResult {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.games() as f64
+    }
+
+    /// Elo difference and the half-width of its 95% confidence interval.
+    pub fn elo(&self) -> (f64, f64) {
+        let elo = |score: f64| -400.0 * (1.0 / score.clamp(1e-6, 1.0 - 1e-6) - 1.0).log10();
+
+        let games = self.games() as f64;
+        let score = self.score();
+        let variance = (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / games;
+        let margin = 1.96 * (variance / games).sqrt();
+
+        (elo(score), (elo(score + margin) - elo(score - margin)) / 2.0)
+    }
+
+    fn add(&mut self, result: i32) {
+        match result {
+            1 => self.wins += 1,
+            0 => self.draws += 1,
+            _ => self.losses += 1,
+        }
+    }
+}
+
+impl std::fmt::Display for MatchResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (elo, err) = self.elo();
+        write!(f, "W: {}, D: {}, L: {}, Elo: {elo:.2} +/- {err:.2}", self.wins, self.draws, self.losses)
+    }
+}
+
+/// Reads opening positions from a file of FENs or EPDs, one per line.
+pub fn load_openings(path: &str) -> Vec<Position> {
+    let book = std::fs::read_to_string(path).unwrap_or_else(|_| panic!("Invalid File Path: {path}"));
+
+    book.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Position::from_str(line).unwrap_or_else(|err| panic!("{err}")))
+        .collect()
+}
+
+/// Plays a pair of games from each opening, with each net playing both colours.
+pub fn play_match<T, U>(
+    dev: &InferenceNet<T, U>,
+    base: &InferenceNet<T, U>,
+    openings: &[Position],
+    search: ValueSearch,
+    threads: usize,
+) -> MatchResult
+where
+    T: InputType<RequiredDataType = ChessBoard>,
+    U: OutputBuckets<ChessBoard>,
+{
+    assert!(!openings.is_empty(), "No openings provided!");
+    let chunk_size = openings.len().div_ceil(threads.max(1));
+
+    std::thread::scope(|s| {
+        let handles: Vec<_> = openings
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    let mut result = MatchResult::default();
+
+                    for &opening in chunk {
+                        result.add(play_game([dev, base], opening, search));
+                        result.add(-play_game([base, dev], opening, search));
+                    }
+
+                    result
+                })
+            })
+            .collect();
+
+        let mut result = MatchResult::default();
+        for handle in handles {
+            let res = handle.join().unwrap();
+            result.wins += res.wins;
+            result.draws += res.draws;
+            result.losses += res.losses;
+        }
+
+        result
+    })
+}
+
+/// Plays a game with `nets[0]` to move first, returning the result
+/// from its perspective as 1 for a win, 0 for a draw and -1 for a loss.
+fn play_game<T, U>(nets: [&InferenceNet<T, U>; 2], mut pos: Position, search: ValueSearch) -> i32
+where
+    T: InputType<RequiredDataType = ChessBoard>,
+    U: OutputBuckets<ChessBoard>,
+{
+    let mut seen = HashMap::new();
+
+    for ply in 0..MAX_PLIES {
+        let moves = pos.legal_moves();
+        let result_for_stm = if moves.is_empty() {
+            Some(if pos.in_check() { -1 } else { 0 })
+        } else if pos.halfmoves() >= 100 || pos.insufficient_material() {
+            Some(0)
+        } else {
+            let count = seen.entry(pos.repetition_key()).or_insert(0);
+            *count += 1;
+            (*count >= 3).then_some(0)
+        };
+
+        if let Some(result) = result_for_stm {
+            return if ply % 2 == 0 { result } else { -result };
+        }
+
+        let mut searcher = Searcher { net: nets[ply % 2], nodes: 0, limit: 0 };
+        pos.make_move(searcher.best_move(&pos, moves, search));
+    }
+
+    0
+}
+
+struct Searcher<'a, T, U> {
+    net: &'a InferenceNet<T, U>,
+    nodes: usize,
+    limit: usize,
+}
+
+impl<T, U> Searcher<'_, T, U>
+where
+    T: InputType<RequiredDataType = ChessBoard>,
+    U: OutputBuckets<ChessBoard>,
+{
+    fn best_move(&mut self, pos: &Position, moves: Vec<Move>, search: ValueSearch) -> Move {
+        let max_depth = match search {
+            ValueSearch::OnePly => 1,
+            ValueSearch::Nodes(limit) => {
+                self.limit = limit;
+                MAX_DEPTH
+            }
+        };
+
+        let mut best = moves[0];
+
+        for depth in 1..=max_depth {
+            let mut alpha = -MATE;
+            let mut depth_best = best;
+
+            // search the previous best move first, for better pruning
+            let ordered = std::iter::once(best).chain(moves.iter().copied().filter(|&mov| mov != best));
+
+            for mov in ordered {
+                let mut child = *pos;
+                child.make_move(mov);
+
+                let Some(score) = self.negamax(&child, depth - 1, -MATE, -alpha).map(|score| -score) else {
+                    return best;
+                };
+
+                if score > alpha {
+                    alpha = score;
+                    depth_best = mov;
+                }
+            }
+
+            best = depth_best;
+
+            if self.nodes >= self.limit {
+                break;
+            }
+        }
+
+        best
+    }
+
+    /// Returns `None` if the node limit was hit partway through the search.
+    fn negamax(&mut self, pos: &Position, depth: usize, mut alpha: f32, beta: f32) -> Option<f32> {
+        self.nodes += 1;
+
+        let moves = pos.legal_moves();
+        if moves.is_empty() {
+            return Some(if pos.in_check() { -MATE } else { 0.0 });
+        }
+
+        if depth == 0 {
+            return Some(self.net.evaluate(&pos.to_board())[0]);
+        }
+
+        if self.nodes >= self.limit {
+            return None;
+        }
+
+        for mov in moves {
+            let mut child = *pos;
+            child.make_move(mov);
+
+            let score = -self.negamax(&child, depth - 1, -beta, -alpha)?;
+
+            if score >= beta {
+                return Some(score);
+            }
+
+            alpha = alpha.max(score);
+        }
+
+        Some(alpha)
+    }
+}
@@ -0,0 +1,391 @@
+use bulletformat::ChessBoard;
+
+use crate::{
+    convert,
+    moves::{Move, Promotion},
+};
+
+const PAWN: usize = 0;
+const KNIGHT: usize = 1;
+const BISHOP: usize = 2;
+const ROOK: usize = 3;
+const QUEEN: usize = 4;
+const KING: usize = 5;
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_STEPS: [(i8, i8); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+const DIAGONALS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+const ORTHOGONALS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+
+/// Chess or Chess960 position, with bitboards in the order
+/// White, Black, Pawn, Knight, Bishop, Rook, Queen, King.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Position {
+    bbs: [u64; 8],
+    stm: usize,
+    /// Files of the rooks that White and Black can castle with, as bitmasks.
+    castling: [u8; 2],
+    enp: Option<u8>,
+    halfmoves: u8,
+}
+
+fn offset(sq: usize, (df, dr): (i8, i8)) -> Option<usize> {
+    let file = (sq % 8) as i8 + df;
+    let rank = (sq / 8) as i8 + dr;
+    ((0..8).contains(&file) && (0..8).contains(&rank)).then(|| 8 * rank as usize + file as usize)
+}
+
+fn steps(sq: usize, deltas: &[(i8, i8)]) -> u64 {
+    deltas.iter().filter_map(|&delta| offset(sq, delta)).fold(0, |bb, to| bb | (1 << to))
+}
+
+fn rays(sq: usize, occ: u64, dirs: &[(i8, i8)]) -> u64 {
+    let mut bb = 0;
+
+    for &dir in dirs {
+        let mut curr = sq;
+        while let Some(to) = offset(curr, dir) {
+            bb |= 1 << to;
+            if occ & (1 << to) > 0 {
+                break;
+            }
+            curr = to;
+        }
+    }
+
+    bb
+}
+
+fn pawn_attacks(side: usize, sq: usize) -> u64 {
+    let dr = if side == 0 { 1 } else { -1 };
+    steps(sq, &[(-1, dr), (1, dr)])
+}
+
+fn squares(mut bb: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        (bb > 0).then(|| {
+            let sq = bb.trailing_zeros() as usize;
+            bb &= bb - 1;
+            sq
+        })
+    })
+}
+
+/// Squares from `a` to `b` on the same rank, including `b` but not `a`.
+fn between(a: usize, b: usize) -> u64 {
+    let (lo, hi) = (a.min(b), a.max(b));
+    let span = ((1u64 << (hi - lo + 1)) - 1) << lo;
+    span & !(1 << a)
+}
+
+/// Squares the king and rook move to when the king on `ksq` castles with
+/// the rook on `rsq`, which are the same as in standard chess for Chess960.
+fn castling_destinations(ksq: usize, rsq: usize) -> (usize, usize) {
+    let rank = ksq - ksq % 8;
+
+    if rsq > ksq {
+        (rank + 6, rank + 5)
+    } else {
+        (rank + 2, rank + 3)
+    }
+}
+
+fn parse_square(sq: &str) -> Result<u8, String> {
+    match sq.as_bytes() {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Ok(8 * (rank - b'1') + file - b'a'),
+        _ => Err(format!("Invalid square: {sq}")),
+    }
+}
+
+/// Parses castling rights in FEN, X-FEN or Shredder-FEN, where `K` and `Q`
+/// refer to the outermost rook on each side of the king, and a file letter
+/// to the rook on that file, so that Chess960 positions are unambiguous.
+fn parse_castling(field: &str, bbs: &[u64; 8]) -> Result<[u8; 2], String> {
+    let mut castling = [0; 2];
+
+    for ch in field.chars().filter(|&ch| ch != '-') {
+        let side = usize::from(ch.is_ascii_lowercase());
+        let back_rank = 0xFF << (56 * side);
+        let rooks = (bbs[side] & bbs[2 + ROOK] & back_rank) >> (56 * side);
+        let king = (bbs[side] & bbs[2 + KING] & back_rank) >> (56 * side);
+        let invalid = || format!("Invalid castling rights: {field}");
+
+        if king == 0 {
+            return Err(invalid());
+        }
+
+        let file = match ch.to_ascii_uppercase() {
+            'K' => (64 - (rooks & !(2 * king - 1)).leading_zeros()).wrapping_sub(1),
+            'Q' => (rooks & (king - 1)).trailing_zeros(),
+            file @ 'A'..='H' => u32::from(file) - u32::from('A'),
+            _ => return Err(invalid()),
+        };
+
+        if file >= 8 || rooks & (1 << file) == 0 {
+            return Err(invalid());
+        }
+
+        castling[side] |= 1 << file;
+    }
+
+    Ok(castling)
+}
+
+impl std::str::FromStr for Position {
+    type Err = String;
+
+    /// Parses a FEN or EPD, with the move counters being optional. Castling
+    /// rights may be given as in X-FEN or Shredder-FEN for Chess960.
+    fn from_str(fen: &str) -> Result<Self, String> {
+        let fields: Vec<_> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(format!("Invalid FEN: {fen}"));
+        }
+
+        let mut bbs = [0; 8];
+        for (i, row) in fields[0].split('/').enumerate() {
+            let mut file = 0;
+
+            for ch in row.chars() {
+                if let Some(skip) = ch.to_digit(10) {
+                    file += skip as usize;
+                    continue;
+                }
+
+                let piece = "pnbrqk".find(ch.to_ascii_lowercase()).ok_or(format!("Invalid piece: {ch}"))?;
+                if i >= 8 || file >= 8 {
+                    return Err(format!("Invalid board: {}", fields[0]));
+                }
+
+                let bit = 1 << (8 * (7 - i) + file);
+                bbs[usize::from(ch.is_ascii_lowercase())] |= bit;
+                bbs[2 + piece] |= bit;
+                file += 1;
+            }
+        }
+
+        let stm = match fields[1] {
+            "w" => 0,
+            "b" => 1,
+            _ => return Err(format!("Invalid side to move: {}", fields[1])),
+        };
+
+        let castling = parse_castling(fields[2], &bbs)?;
+        let enp = if fields[3] == "-" { None } else { Some(parse_square(fields[3])?) };
+        let halfmoves = fields.get(4).and_then(|hm| hm.parse().ok()).unwrap_or(0);
+
+        let pos = Self { bbs, stm, castling, enp, halfmoves };
+        for side in 0..2 {
+            if (pos.bbs[side] & pos.bbs[2 + KING]).count_ones() != 1 {
+                return Err(format!("Invalid FEN: {fen}"));
+            }
+        }
+
+        Ok(pos)
+    }
+}
+
+impl Position {
+    pub fn stm(&self) -> usize {
+        self.stm
+    }
+
+    pub fn halfmoves(&self) -> u8 {
+        self.halfmoves
+    }
+
+    /// The position with the halfmove clock zeroed, for detecting repetitions.
+    pub fn repetition_key(&self) -> Self {
+        Self { halfmoves: 0, ..*self }
+    }
+
+    /// Files of the rooks that the side to move and the other side
+    /// can castle with, as bitmasks, in that order.
+    pub fn castling_rook_files(&self) -> [u8; 2] {
+        [self.castling[self.stm], self.castling[self.stm ^ 1]]
+    }
+
+    pub fn to_board(&self) -> ChessBoard {
+        let mut board = ChessBoard::from_raw(self.bbs, self.stm, 0, 0.5).expect("Position is valid!");
+        convert::set_castling_rook_files(&mut board, self.castling_rook_files());
+        convert::set_halfmove_clock(&mut board, self.halfmoves);
+        board
+    }
+
+    fn occ(&self) -> u64 {
+        self.bbs[0] | self.bbs[1]
+    }
+
+    fn piece_on(&self, sq: usize) -> Option<usize> {
+        (0..6).find(|&piece| self.bbs[2 + piece] & (1 << sq) > 0)
+    }
+
+    fn is_attacked(&self, sq: usize, side: usize, occ: u64) -> bool {
+        let them = self.bbs[side];
+        let queens = self.bbs[2 + QUEEN];
+
+        pawn_attacks(side ^ 1, sq) & them & self.bbs[2 + PAWN] > 0
+            || steps(sq, &KNIGHT_STEPS) & them & self.bbs[2 + KNIGHT] > 0
+            || steps(sq, &KING_STEPS) & them & self.bbs[2 + KING] > 0
+            || rays(sq, occ, &DIAGONALS) & them & (self.bbs[2 + BISHOP] | queens) > 0
+            || rays(sq, occ, &ORTHOGONALS) & them & (self.bbs[2 + ROOK] | queens) > 0
+    }
+
+    pub fn in_check(&self) -> bool {
+        let ksq = (self.bbs[self.stm] & self.bbs[2 + KING]).trailing_zeros() as usize;
+        self.is_attacked(ksq, self.stm ^ 1, self.occ())
+    }
+
+    /// Neither side has enough material to checkmate.
+    pub fn insufficient_material(&self) -> bool {
+        let heavy = self.bbs[2 + PAWN] | self.bbs[2 + ROOK] | self.bbs[2 + QUEEN];
+        let minors = self.bbs[2 + KNIGHT] | self.bbs[2 + BISHOP];
+        heavy == 0 && minors.count_ones() <= 1
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(64);
+        let us = self.bbs[self.stm];
+        let occ = self.occ();
+        let enemies = self.bbs[self.stm ^ 1];
+
+        let mut add = |from: usize, targets: u64, promote: bool| {
+            for to in squares(targets) {
+                if promote {
+                    for promo in [Promotion::Queen, Promotion::Rook, Promotion::Bishop, Promotion::Knight] {
+                        moves.push(Move::new(from as u8, to as u8, promo));
+                    }
+                } else {
+                    moves.push(Move::new(from as u8, to as u8, Promotion::None));
+                }
+            }
+        };
+
+        for from in squares(us) {
+            match self.piece_on(from) {
+                Some(PAWN) => {
+                    let (forward, start_rank, last_rank) = if self.stm == 0 { (8, 1, 7) } else { (-8, 6, 0) };
+                    let one = (from as i32 + forward) as usize;
+                    let promote = one / 8 == last_rank;
+
+                    let mut targets = pawn_attacks(self.stm, from) & enemies;
+                    if let Some(enp) = self.enp {
+                        targets |= pawn_attacks(self.stm, from) & (1 << enp);
+                    }
+
+                    if occ & (1 << one) == 0 {
+                        targets |= 1 << one;
+
+                        let two = (one as i32 + forward) as usize;
+                        if from / 8 == start_rank && occ & (1 << two) == 0 {
+                            targets |= 1 << two;
+                        }
+                    }
+
+                    add(from, targets, promote);
+                }
+                Some(KNIGHT) => add(from, steps(from, &KNIGHT_STEPS) & !us, false),
+                Some(BISHOP) => add(from, rays(from, occ, &DIAGONALS) & !us, false),
+                Some(ROOK) => add(from, rays(from, occ, &ORTHOGONALS) & !us, false),
+                Some(QUEEN) => add(from, (rays(from, occ, &DIAGONALS) | rays(from, occ, &ORTHOGONALS)) & !us, false),
+                Some(KING) => add(from, steps(from, &KING_STEPS) & !us, false),
+                _ => unreachable!(),
+            }
+        }
+
+        let ksq = (us & self.bbs[2 + KING]).trailing_zeros() as usize;
+
+        for rook_file in 0..8 {
+            if self.castling[self.stm] & (1 << rook_file) == 0 {
+                continue;
+            }
+
+            let rsq = 56 * self.stm + rook_file;
+            let (king_to, rook_to) = castling_destinations(ksq, rsq);
+
+            // the king and rook are the only pieces allowed in the way
+            let others = occ & !(1 << ksq) & !(1 << rsq);
+            let path = between(ksq, king_to) | between(rsq, rook_to);
+            let king_path = between(ksq, king_to) | (1 << ksq);
+            let safe = squares(king_path).all(|sq| !self.is_attacked(sq, self.stm ^ 1, others));
+
+            if others & path == 0 && safe {
+                moves.push(Move::new(ksq as u8, rsq as u8, Promotion::None));
+            }
+        }
+
+        moves.retain(|&mov| {
+            let mut pos = *self;
+            pos.make_move(mov);
+            pos.stm ^= 1;
+            !pos.in_check()
+        });
+
+        moves
+    }
+
+    /// Plays a move, which must be legal. Castling is given as the
+    /// king moving to the square of the rook it castles with.
+    pub fn make_move(&mut self, mov: Move) {
+        let (from, to) = (usize::from(mov.from), usize::from(mov.to));
+        let (us, them) = (self.stm, self.stm ^ 1);
+        let piece = self.piece_on(from).expect("No piece to move!");
+
+        if piece == KING && self.bbs[us] & (1 << to) > 0 {
+            let (king_to, rook_to) = castling_destinations(from, to);
+            self.bbs[us] ^= (1 << from) ^ (1 << to) ^ (1 << king_to) ^ (1 << rook_to);
+            self.bbs[2 + KING] ^= (1 << from) ^ (1 << king_to);
+            self.bbs[2 + ROOK] ^= (1 << to) ^ (1 << rook_to);
+            self.castling[us] = 0;
+            self.enp = None;
+            self.halfmoves = self.halfmoves.saturating_add(1);
+            self.stm = them;
+            return;
+        }
+
+        let captured = self.piece_on(to);
+        let move_bb = (1 << from) | (1 << to);
+
+        if let Some(captured) = captured {
+            self.bbs[them] ^= 1 << to;
+            self.bbs[2 + captured] ^= 1 << to;
+        }
+
+        if piece == PAWN && self.enp == Some(mov.to) {
+            let sq = if us == 0 { to - 8 } else { to + 8 };
+            self.bbs[them] ^= 1 << sq;
+            self.bbs[2 + PAWN] ^= 1 << sq;
+        }
+
+        self.bbs[us] ^= move_bb;
+        self.bbs[2 + piece] ^= move_bb;
+
+        let promo = match mov.promo {
+            Promotion::None => None,
+            Promotion::Knight => Some(KNIGHT),
+            Promotion::Bishop => Some(BISHOP),
+            Promotion::Rook => Some(ROOK),
+            Promotion::Queen => Some(QUEEN),
+        };
+
+        if let Some(promo) = promo {
+            self.bbs[2 + PAWN] ^= 1 << to;
+            self.bbs[2 + promo] ^= 1 << to;
+        }
+
+        if piece == KING {
+            self.castling[us] = 0;
+        }
+
+        // a rook that moves or is captured can no longer castle
+        for sq in [from, to] {
+            if sq / 8 == 0 || sq / 8 == 7 {
+                self.castling[sq / 56] &= !(1 << (sq % 8));
+            }
+        }
+
+        self.enp = (piece == PAWN && from.abs_diff(to) == 16).then(|| ((from + to) / 2) as u8);
+        self.halfmoves = if piece == PAWN || captured.is_some() { 0 } else { self.halfmoves.saturating_add(1) };
+        self.stm = them;
+    }
+}
@@ -0,0 +1,57 @@
+use super::Position;
+
+fn perft(pos: &Position, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = pos.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    moves.into_iter().map(|mov| {
+        let mut pos = *pos;
+        pos.make_move(mov);
+        perft(&pos, depth - 1)
+    }).sum()
+}
+
+/// Checks the number of leaf nodes at each depth from one.
+fn check(fen: &str, counts: &[u64]) {
+    let pos: Position = fen.parse().unwrap();
+
+    for (depth, &count) in counts.iter().enumerate() {
+        assert_eq!(perft(&pos, depth + 1), count, "{fen} at depth {}", depth + 1);
+    }
+}
+
+#[test]
+fn perft_startpos() {
+    check("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &[20, 400, 8902, 197281]);
+}
+
+#[test]
+fn perft_kiwipete() {
+    check("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97862]);
+}
+
+#[test]
+fn perft_en_passant_pin() {
+    // the pawn on b5 can't take en passant on c6 after c7c5, as it would expose the king to the rook on h5
+    check("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]);
+}
+
+#[test]
+fn perft_promotions() {
+    check("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467]);
+}
+
+#[test]
+fn perft_chess960() {
+    // Shredder-FEN castling rights, with castling rooks on both sides of the king
+    check("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", &[21, 528, 12189]);
+    check("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", &[21, 807, 18002]);
+    check("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", &[20, 479, 10471]);
+}
+